@@ -22,11 +22,32 @@ use bincode::{deserialize, serialize, Infinite};
 
 pub const DATA_PATH: &str = "DATA_PATH";
 pub const LOG_TYPE_AUTHORITIES: u8 = 1;
+/// Same as `LOG_TYPE_AUTHORITIES`, followed by the node list.
+pub const LOG_TYPE_AUTHORITIES_WITH_NODES: u8 = 2;
+
+/// Which lists were changed by `receive_authorities_list`.
+///
+/// The network layer only cares about `nodes`, consensus about `validators`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct AuthorityChanges {
+    pub authorities: bool,
+    pub validators: bool,
+    pub nodes: bool,
+}
+
+impl AuthorityChanges {
+    pub fn is_empty(&self) -> bool {
+        !(self.authorities || self.validators || self.nodes)
+    }
+}
 
 #[derive(Debug)]
 pub struct AuthorityManage {
     pub authorities: Vec<Address>,
     pub validators: Vec<Address>,
+    /// Every node allowed to join the network, observers included.
+    /// Always a superset of `validators`.
+    pub nodes: Vec<Address>,
     pub authorities_log: Wal,
     pub authorities_old: Vec<Address>,
     pub validators_old: Vec<Address>,
@@ -39,16 +60,44 @@ impl Default for AuthorityManage {
     }
 }
 
+// (authority_h_old, authorities, validators_old, validators, nodes)
+type AuthorityRecord = (
+    usize,
+    Vec<Address>,
+    Vec<Address>,
+    Vec<Address>,
+    Vec<Address>,
+);
+
+// Validators which are missing from `nodes` are appended, keeping `validators ⊆ nodes`.
+fn merge_nodes(nodes: &[Address], validators: &[Address]) -> Vec<Address> {
+    let mut merged = nodes.to_vec();
+    for validator in validators {
+        if !merged.contains(validator) {
+            merged.push(*validator);
+        }
+    }
+    merged
+}
+
 impl AuthorityManage {
     pub fn new() -> Self {
         let logpath = ::std::env::var(DATA_PATH)
             .unwrap_or_else(|_| panic!("{} must be set", DATA_PATH))
             + "/authorities";
+        Self::load(&logpath)
+    }
 
+    /// Load the lists from the wal in `logpath`.
+    ///
+    /// Logs written before observer nodes existed have no node list,
+    /// every authority and validator is taken as a node for them.
+    pub fn load(logpath: &str) -> Self {
         let mut authority_manage = AuthorityManage {
             authorities: Vec::new(),
             validators: Vec::new(),
-            authorities_log: Wal::create(&*logpath).unwrap(),
+            nodes: Vec::new(),
+            authorities_log: Wal::create(logpath).unwrap(),
             authorities_old: Vec::new(),
             validators_old: Vec::new(),
             authority_h_old: 0,
@@ -56,17 +105,30 @@ impl AuthorityManage {
 
         let vec_out = authority_manage.authorities_log.load();
         if !vec_out.is_empty() {
-            if let Ok((h, authorities, validators_old, validators)) = deserialize(&(vec_out[0].1)) {
-                let authorities: Vec<Address> = authorities;
-                let validators_old: Vec<Address> = validators_old;
-                let validators: Vec<Address> = validators;
-
+            let (mtype, ref body) = vec_out[0];
+            let decoded: Option<AuthorityRecord> = match mtype {
+                LOG_TYPE_AUTHORITIES_WITH_NODES => deserialize(body).ok(),
+                LOG_TYPE_AUTHORITIES => deserialize(body).ok().map(
+                    |(h, authorities, validators_old, validators): (
+                        usize,
+                        Vec<Address>,
+                        Vec<Address>,
+                        Vec<Address>,
+                    )| {
+                        let nodes = merge_nodes(&authorities, &validators);
+                        (h, authorities, validators_old, validators, nodes)
+                    },
+                ),
+                _ => None,
+            };
+            if let Some((h, authorities, validators_old, validators, nodes)) = decoded {
                 authority_manage.authorities.extend_from_slice(&authorities);
                 authority_manage
                     .validators_old
                     .extend_from_slice(&validators_old);
                 authority_manage.authority_h_old = h;
                 authority_manage.validators.extend_from_slice(&validators);
+                authority_manage.nodes = merge_nodes(&nodes, &validators);
             }
         }
 
@@ -77,21 +139,41 @@ impl AuthorityManage {
         self.validators.len()
     }
 
+    pub fn is_validator(&self, address: &Address) -> bool {
+        self.validators.contains(address)
+    }
+
+    pub fn is_node(&self, address: &Address) -> bool {
+        self.nodes.contains(address) || self.is_validator(address)
+    }
+
+    /// Update authorities and validators, leaving the node list alone
+    /// apart from adding new validators to it.
     pub fn receive_authorities_list(
         &mut self,
         height: usize,
         authorities: &[Address],
         validators: &[Address],
-    ) {
-        let flag = if self.validators != validators {
-            2
-        } else if self.authorities != authorities {
-            1
-        } else {
-            0
+    ) -> AuthorityChanges {
+        let nodes = self.nodes.clone();
+        self.receive_lists(height, authorities, validators, &nodes)
+    }
+
+    pub fn receive_lists(
+        &mut self,
+        height: usize,
+        authorities: &[Address],
+        validators: &[Address],
+        nodes: &[Address],
+    ) -> AuthorityChanges {
+        let nodes = merge_nodes(nodes, validators);
+        let changes = AuthorityChanges {
+            authorities: self.authorities != authorities,
+            validators: self.validators != validators,
+            nodes: self.nodes != nodes,
         };
 
-        if flag > 0 {
+        if changes.authorities || changes.validators {
             self.authorities_old.clear();
             self.authorities_old.extend_from_slice(&self.authorities);
             self.validators_old.clear();
@@ -102,11 +184,13 @@ impl AuthorityManage {
             self.authorities.extend_from_slice(&authorities);
             self.validators.clear();
             self.validators.extend_from_slice(&validators);
+        }
+        self.nodes = nodes;
 
-            if flag == 2 {
-                self.save();
-            }
+        if changes.validators || changes.nodes {
+            self.save();
         }
+        changes
     }
 
     pub fn save(&mut self) {
@@ -116,10 +200,126 @@ impl AuthorityManage {
                 self.authorities.clone(),
                 self.validators_old.clone(),
                 self.validators.clone(),
+                self.nodes.clone(),
             ),
             Infinite,
         )
         .unwrap();
-        let _ = self.authorities_log.save(LOG_TYPE_AUTHORITIES, &bmsg);
+        let _ = self
+            .authorities_log
+            .save(LOG_TYPE_AUTHORITIES_WITH_NODES, &bmsg);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::remove_dir_all;
+
+    fn log_path(name: &str) -> String {
+        let path = ::std::env::temp_dir().join(format!(
+            "authority_manage_{}_{}",
+            name,
+            ::std::process::id()
+        ));
+        let _ = remove_dir_all(&path);
+        path.to_str().unwrap().to_owned()
+    }
+
+    fn addresses(range: ::std::ops::Range<u64>) -> Vec<Address> {
+        range.map(Address::from).collect()
+    }
+
+    #[test]
+    fn wal_round_trip() {
+        let path = log_path("round_trip");
+        let mut am = AuthorityManage::load(&path);
+        let changes = am.receive_lists(5, &addresses(1..4), &addresses(1..3), &addresses(1..6));
+        assert!(changes.authorities && changes.validators && changes.nodes);
+
+        let loaded = AuthorityManage::load(&path);
+        assert_eq!(loaded.authorities, addresses(1..4));
+        assert_eq!(loaded.validators, addresses(1..3));
+        assert_eq!(loaded.nodes, addresses(1..6));
+        assert_eq!(loaded.authority_h_old, 5);
+        let _ = remove_dir_all(&path);
+    }
+
+    #[test]
+    fn membership() {
+        let path = log_path("membership");
+        let mut am = AuthorityManage::load(&path);
+        // Validator 7 is not in the node list given, it is added anyway.
+        am.receive_lists(
+            1,
+            &addresses(1..3),
+            &[Address::from(1), Address::from(7)],
+            &addresses(1..5),
+        );
+        assert!(am.is_validator(&Address::from(1)));
+        assert!(!am.is_validator(&Address::from(3)));
+        assert!(am.is_node(&Address::from(3)));
+        assert!(am.is_node(&Address::from(7)));
+        assert!(!am.is_node(&Address::from(9)));
+        assert_eq!(am.validator_n(), 2);
+        let _ = remove_dir_all(&path);
+    }
+
+    #[test]
+    fn changes_distinguish_lists() {
+        let path = log_path("changes");
+        let mut am = AuthorityManage::load(&path);
+        am.receive_lists(1, &addresses(1..3), &addresses(1..3), &addresses(1..3));
+
+        let changes = am.receive_lists(2, &addresses(1..3), &addresses(1..3), &addresses(1..5));
+        assert_eq!(
+            changes,
+            AuthorityChanges {
+                authorities: false,
+                validators: false,
+                nodes: true,
+            }
+        );
+        // Observers joining must not move the consensus history.
+        assert_eq!(am.authority_h_old, 1);
+
+        let changes = am.receive_authorities_list(3, &addresses(1..3), &addresses(1..2));
+        assert!(changes.validators && !changes.nodes && !changes.authorities);
+        assert_eq!(am.nodes, addresses(1..5));
+        assert!(am
+            .receive_authorities_list(4, &addresses(1..3), &addresses(1..2))
+            .is_empty());
+        let _ = remove_dir_all(&path);
+    }
+
+    #[test]
+    fn legacy_log_upgrade() {
+        let path = log_path("legacy");
+        {
+            let mut wal = Wal::create(&path).unwrap();
+            let legacy = serialize(
+                &(7usize, addresses(1..4), addresses(1..2), addresses(2..5)),
+                Infinite,
+            )
+            .unwrap();
+            wal.save(LOG_TYPE_AUTHORITIES, &legacy).unwrap();
+        }
+
+        let mut am = AuthorityManage::load(&path);
+        assert_eq!(am.authorities, addresses(1..4));
+        assert_eq!(am.validators_old, addresses(1..2));
+        assert_eq!(am.validators, addresses(2..5));
+        assert_eq!(am.nodes, addresses(1..5));
+        assert_eq!(am.authority_h_old, 7);
+
+        // The next save writes the new format.
+        am.save();
+        let mut am = AuthorityManage::load(&path);
+        assert_eq!(
+            am.authorities_log.load()[0].0,
+            LOG_TYPE_AUTHORITIES_WITH_NODES
+        );
+        assert_eq!(am.nodes, addresses(1..5));
+        let _ = remove_dir_all(&path);
     }
 }
@@ -51,3 +51,41 @@ where
     fn pubkey(&self) -> &Self::PubKey;
     fn address(&self) -> Address;
 }
+
+/// How an address is derived from a public key.
+///
+/// The address is the last 20 bytes of the hash of some bytes of the key,
+/// with the hash of the build. Which bytes is what the modes disagree on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressDerivation {
+    /// What existing chains use: the `PubKey` bytes as the backend stores
+    /// them, which leaves out the `0x04` tag of an uncompressed point.
+    Legacy,
+    /// The standard encoding of the key: the 32 bytes of RFC 8032 for
+    /// ed25519, and the 65 bytes `0x04 || x || y` of SEC 1 for secp256k1
+    /// and of GB/T 32918 for sm2.
+    Canonical,
+}
+
+impl Default for AddressDerivation {
+    fn default() -> Self {
+        AddressDerivation::Legacy
+    }
+}
+
+/// The derivation of `pubkey_to_address`, and so of `CreateKey::address`
+/// and `Sign::verify_address`. Only a new chain may build with the
+/// `canonical-address` feature, it changes every address.
+#[cfg(not(feature = "canonical-address"))]
+pub const ADDRESS_DERIVATION: AddressDerivation = AddressDerivation::Legacy;
+#[cfg(feature = "canonical-address")]
+pub const ADDRESS_DERIVATION: AddressDerivation = AddressDerivation::Canonical;
+
+/// `address_from_pubkey` for whichever derivation a chain uses, at runtime.
+pub trait AddressFromPubKey {
+    type PubKey;
+
+    /// The bytes hashed for the address in `derivation` mode.
+    fn address_preimage(pubkey: &Self::PubKey, derivation: AddressDerivation) -> Vec<u8>;
+    fn address_from_pubkey(pubkey: &Self::PubKey, derivation: AddressDerivation) -> Address;
+}
@@ -19,7 +19,12 @@ extern crate cita_secp256k1;
 #[cfg(feature = "sm2")]
 extern crate cita_sm2;
 
-pub use cita_crypto_trait::{CreateKey, Sign};
+#[cfg(feature = "test-utils")]
+pub mod test_utils;
+
+pub use cita_crypto_trait::{
+    AddressDerivation, AddressFromPubKey, CreateKey, Sign, ADDRESS_DERIVATION,
+};
 #[cfg(feature = "ed25519")]
 pub use cita_ed25519::*;
 #[cfg(feature = "secp256k1")]
@@ -33,3 +38,40 @@ pub const SIGNATURE_NAME: &str = "ed25519";
 pub const SIGNATURE_NAME: &str = "secp256k1";
 #[cfg(feature = "sm2")]
 pub const SIGNATURE_NAME: &str = "sm2";
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        address_from_pubkey, pubkey_to_address, AddressDerivation, CreateKey, KeyPair, Message,
+        Sign, Signature, ADDRESS_DERIVATION,
+    };
+
+    #[test]
+    fn facade_address_matches_backend() {
+        let keypair = KeyPair::gen_keypair();
+        #[cfg(feature = "ed25519")]
+        let backend = |derivation| cita_ed25519::address_from_pubkey(keypair.pubkey(), derivation);
+        #[cfg(feature = "secp256k1")]
+        let backend =
+            |derivation| cita_secp256k1::address_from_pubkey(keypair.pubkey(), derivation);
+        #[cfg(feature = "sm2")]
+        let backend = |derivation| cita_sm2::address_from_pubkey(keypair.pubkey(), derivation);
+
+        for &derivation in &[AddressDerivation::Legacy, AddressDerivation::Canonical] {
+            assert_eq!(
+                address_from_pubkey(keypair.pubkey(), derivation),
+                backend(derivation)
+            );
+        }
+        assert_eq!(keypair.address(), backend(ADDRESS_DERIVATION));
+
+        // The signer recovered from a signature has the same address.
+        let message = Message::from([7u8; 32]);
+        let signature = Signature::sign(keypair.privkey(), &message).unwrap();
+        let recovered = signature.recover(&message).unwrap();
+        assert_eq!(pubkey_to_address(&recovered), keypair.address());
+        assert!(signature
+            .verify_address(&keypair.address(), &message)
+            .unwrap());
+    }
+}
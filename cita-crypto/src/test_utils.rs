@@ -0,0 +1,163 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Validators for consensus tests, with the `test-utils` feature.
+//!
+//! The keys of a `ValidatorSet` only depend on its seed, so a failing
+//! test has the same authorities when run again, and the set is written
+//! against the facade: it is the same code whichever backend is built.
+//! Votes and proofs are in the `proof` crate, on top of it.
+
+#[cfg(not(feature = "sm2"))]
+use crate::Sign;
+use crate::{CreateKey, KeyPair, Message, Signature};
+use cita_types::Address;
+
+/// The seed of `ValidatorSet::generate`.
+pub const DEFAULT_SEED: u64 = 0x00c1_7a00;
+
+pub struct ValidatorSet {
+    keypairs: Vec<KeyPair>,
+}
+
+impl ValidatorSet {
+    /// `n` validators from `DEFAULT_SEED`.
+    pub fn generate(n: usize) -> Self {
+        ValidatorSet::from_seed(DEFAULT_SEED, n)
+    }
+
+    /// `n` validators, the first ones of any larger set from `seed` too.
+    pub fn from_seed(seed: u64, n: usize) -> Self {
+        let mut seeds = SeedStream::new(seed);
+        let keypairs = (0..n)
+            .map(|_| loop {
+                // A backend may refuse a few secrets, e.g. above the order
+                // of its curve. Draw again, it is still deterministic.
+                if let Ok(keypair) = KeyPair::from_seed(seeds.next_seed()) {
+                    break keypair;
+                }
+            })
+            .collect();
+        ValidatorSet { keypairs }
+    }
+
+    pub fn len(&self) -> usize {
+        self.keypairs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keypairs.is_empty()
+    }
+
+    pub fn keypair(&self, validator: usize) -> &KeyPair {
+        &self.keypairs[validator]
+    }
+
+    pub fn address(&self, validator: usize) -> Address {
+        self.keypairs[validator].address()
+    }
+
+    /// The addresses of the validators, in their order.
+    pub fn authorities(&self) -> Vec<Address> {
+        self.keypairs.iter().map(CreateKey::address).collect()
+    }
+
+    /// Which validator has `address`.
+    pub fn position(&self, address: &Address) -> Option<usize> {
+        self.keypairs
+            .iter()
+            .position(|keypair| keypair.address() == *address)
+    }
+
+    /// The same signature for the same message every time, so are the
+    /// proofs and blocks signed with it.
+    pub fn sign(&self, validator: usize, message: &Message) -> Signature {
+        let privkey = self.keypairs[validator].privkey();
+        // SM2 draws its nonce from the RNG otherwise.
+        #[cfg(feature = "sm2")]
+        let signature = Signature::sign_deterministic(privkey, message);
+        #[cfg(not(feature = "sm2"))]
+        let signature = Signature::sign(privkey, message);
+        signature.expect("a validator signs any message")
+    }
+}
+
+/// SplitMix64, 32 bytes at a time.
+struct SeedStream {
+    state: u64,
+}
+
+impl SeedStream {
+    fn new(seed: u64) -> Self {
+        SeedStream { state: seed }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn next_seed(&mut self) -> [u8; 32] {
+        let mut seed = [0u8; 32];
+        for chunk in seed.chunks_mut(8) {
+            chunk.copy_from_slice(&self.next_u64().to_be_bytes());
+        }
+        seed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ValidatorSet;
+    use crate::{pubkey_to_address, Message, Sign};
+
+    #[test]
+    fn same_seed_same_validators() {
+        let validators = ValidatorSet::generate(4);
+        assert_eq!(validators.len(), 4);
+        assert_eq!(
+            validators.authorities(),
+            ValidatorSet::generate(4).authorities()
+        );
+        assert_eq!(
+            validators.authorities()[..2],
+            ValidatorSet::generate(2).authorities()[..]
+        );
+        assert_ne!(
+            validators.authorities(),
+            ValidatorSet::from_seed(1, 4).authorities()
+        );
+
+        let mut authorities = validators.authorities();
+        authorities.sort();
+        authorities.dedup();
+        assert_eq!(authorities.len(), 4);
+        assert_eq!(validators.position(&validators.address(2)), Some(2));
+    }
+
+    #[test]
+    fn validators_sign() {
+        let validators = ValidatorSet::generate(3);
+        let message = Message::from([9u8; 32]);
+        for validator in 0..3 {
+            let signature = validators.sign(validator, &message);
+            let signer = pubkey_to_address(&signature.recover(&message).unwrap());
+            assert_eq!(signer, validators.address(validator));
+            assert_eq!(validators.sign(validator, &message), signature);
+        }
+    }
+}
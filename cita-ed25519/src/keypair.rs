@@ -15,14 +15,18 @@
 use super::{Address, PrivKey, PubKey};
 use crate::error::Error;
 use crate::types::H160;
-use cita_crypto_trait::CreateKey;
+use cita_crypto_trait::{AddressDerivation, AddressFromPubKey, CreateKey, ADDRESS_DERIVATION};
 use hashable::Hashable;
 use rustc_serialize::hex::ToHex;
-use sodiumoxide::crypto::sign::gen_keypair;
+use sodiumoxide::crypto::sign::{gen_keypair, keypair_from_seed, Seed};
 use std::fmt;
 
 pub fn pubkey_to_address(pubkey: &PubKey) -> Address {
-    H160::from(pubkey.crypt_hash())
+    address_from_pubkey(pubkey, ADDRESS_DERIVATION)
+}
+
+pub fn address_from_pubkey(pubkey: &PubKey, derivation: AddressDerivation) -> Address {
+    KeyPair::address_from_pubkey(pubkey, derivation)
 }
 
 #[derive(Default)]
@@ -39,13 +43,32 @@ impl fmt::Display for KeyPair {
     }
 }
 
+impl KeyPair {
+    /// The pair libsodium derives from `seed`, the same for the same seed.
+    pub fn from_seed(seed: [u8; 32]) -> Result<Self, Error> {
+        let (pk, sk) = keypair_from_seed(&Seed(seed));
+        Ok(KeyPair {
+            privkey: PrivKey::from(sk.0),
+            pubkey: PubKey::from(pk.0),
+        })
+    }
+}
+
 impl CreateKey for KeyPair {
     type PrivKey = PrivKey;
     type PubKey = PubKey;
     type Error = Error;
 
+    /// The private key is the seed followed by the public key derived from
+    /// it. libsodium signs with the public key as given, so one which isn't
+    /// derived from the seed is rejected here.
     fn from_privkey(privkey: Self::PrivKey) -> Result<Self, Self::Error> {
-        let pubkey = PubKey::from(&privkey.0[32..]);
+        let seed = Seed::from_slice(&privkey.0[..32]).ok_or(Error::InvalidPrivKey)?;
+        let (pk, _) = keypair_from_seed(&seed);
+        if pk.0[..] != privkey.0[32..] {
+            return Err(Error::InvalidPrivKey);
+        }
+        let pubkey = PubKey::from(pk.0);
         Ok(KeyPair { privkey, pubkey })
     }
 
@@ -70,10 +93,27 @@ impl CreateKey for KeyPair {
     }
 }
 
+impl AddressFromPubKey for KeyPair {
+    type PubKey = PubKey;
+
+    fn address_preimage(pubkey: &PubKey, derivation: AddressDerivation) -> Vec<u8> {
+        // The stored key is the RFC 8032 encoding already.
+        match derivation {
+            AddressDerivation::Legacy | AddressDerivation::Canonical => pubkey.to_vec(),
+        }
+    }
+
+    fn address_from_pubkey(pubkey: &PubKey, derivation: AddressDerivation) -> Address {
+        H160::from(Self::address_preimage(pubkey, derivation).crypt_hash())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::types::H256;
     use cita_crypto_trait::CreateKey;
+    use std::str::FromStr;
 
     #[test]
     fn test_from_privkey() {
@@ -81,5 +121,57 @@ mod tests {
         let keypair2 = KeyPair::from_privkey(keypair1.privkey).unwrap();
         assert_eq!(keypair1.pubkey, keypair2.pubkey);
         assert_eq!(keypair1.privkey, keypair2.privkey);
+
+        // A public half which doesn't belong to the seed.
+        let mut privkey = keypair1.privkey;
+        privkey.0[63] ^= 1;
+        assert!(KeyPair::from_privkey(privkey).is_err());
+        assert!(KeyPair::from_privkey(PrivKey::default()).is_err());
+    }
+
+    #[test]
+    fn from_seed() {
+        // RFC 8032, test 1.
+        let seed =
+            H256::from_str("9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60")
+                .unwrap();
+        let keypair = KeyPair::from_seed(seed.0).unwrap();
+        assert_eq!(
+            keypair.pubkey(),
+            &PubKey::from_str("d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a")
+                .unwrap()
+        );
+        assert_eq!(&keypair.privkey().0[..32], &seed.0[..]);
+        let again = KeyPair::from_privkey(*keypair.privkey()).unwrap();
+        assert_eq!(again.pubkey(), keypair.pubkey());
+    }
+
+    #[cfg(feature = "blake2bhash")]
+    #[test]
+    fn address_vectors() {
+        let keypair = KeyPair::from_privkey(
+            PrivKey::from_str(concat!(
+                "9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60",
+                "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a"
+            ))
+            .unwrap(),
+        )
+        .unwrap();
+        let pubkey =
+            PubKey::from_str("d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a")
+                .unwrap();
+        assert_eq!(keypair.pubkey(), &pubkey);
+        assert_eq!(
+            address_from_pubkey(&pubkey, AddressDerivation::Legacy),
+            Address::from_str("8cb93cb09cca219b1c6c48e56f8a6c24b4eed90b").unwrap()
+        );
+        assert_eq!(
+            address_from_pubkey(&pubkey, AddressDerivation::Canonical),
+            Address::from_str("8cb93cb09cca219b1c6c48e56f8a6c24b4eed90b").unwrap()
+        );
+        assert_eq!(
+            keypair.address(),
+            address_from_pubkey(&pubkey, ADDRESS_DERIVATION)
+        );
     }
 }
@@ -24,6 +24,7 @@ use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use sodiumoxide::crypto::sign::{
     sign_detached, verify_detached, PublicKey as EdPublicKey, SecretKey, Signature as EdSignature,
 };
+use std::cmp;
 use std::fmt;
 use std::ops::{Deref, DerefMut};
 
@@ -47,11 +48,16 @@ impl PartialEq for Signature {
 
 impl Decodable for Signature {
     fn decode(rlp: &UntrustedRlp) -> Result<Self, DecoderError> {
-        rlp.decoder().decode_value(|bytes| {
-            let mut sig = [0u8; 96];
-            sig[0..96].copy_from_slice(bytes);
-            Ok(Signature(sig))
-        })
+        rlp.decoder()
+            .decode_value(|bytes| match bytes.len().cmp(&SIGNATURE_BYTES_LEN) {
+                cmp::Ordering::Less => Err(DecoderError::RlpIsTooShort),
+                cmp::Ordering::Greater => Err(DecoderError::RlpIsTooBig),
+                cmp::Ordering::Equal => {
+                    let mut sig = [0u8; SIGNATURE_BYTES_LEN];
+                    sig.copy_from_slice(bytes);
+                    Ok(Signature(sig))
+                }
+            })
     }
 }
 
@@ -12,9 +12,9 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use super::{Address, Error, PrivKey, PubKey, SECP256K1};
+use super::{Address, Error, PrivKey, PubKey, PUBKEY_BYTES_LEN, SECP256K1};
 use crate::types::H160;
-use cita_crypto_trait::CreateKey;
+use cita_crypto_trait::{AddressDerivation, AddressFromPubKey, CreateKey, ADDRESS_DERIVATION};
 use hashable::Hashable;
 use rand::thread_rng;
 use rustc_serialize::hex::ToHex;
@@ -22,7 +22,11 @@ use secp256k1::key;
 use std::fmt;
 
 pub fn pubkey_to_address(pubkey: &PubKey) -> Address {
-    H160::from(pubkey.crypt_hash())
+    address_from_pubkey(pubkey, ADDRESS_DERIVATION)
+}
+
+pub fn address_from_pubkey(pubkey: &PubKey, derivation: AddressDerivation) -> Address {
+    KeyPair::address_from_pubkey(pubkey, derivation)
 }
 
 /// key pair
@@ -40,6 +44,14 @@ impl fmt::Display for KeyPair {
     }
 }
 
+impl KeyPair {
+    /// The pair with `seed` as its secret, which fails in the unlikely case
+    /// it isn't below the curve order.
+    pub fn from_seed(seed: [u8; 32]) -> Result<Self, Error> {
+        KeyPair::from_privkey(PrivKey::from(seed))
+    }
+}
+
 impl CreateKey for KeyPair {
     type PrivKey = PrivKey;
     type PubKey = PubKey;
@@ -84,11 +96,32 @@ impl CreateKey for KeyPair {
     }
 }
 
+/// `PubKey` is the point without its `0x04` tag.
+impl AddressFromPubKey for KeyPair {
+    type PubKey = PubKey;
+
+    fn address_preimage(pubkey: &PubKey, derivation: AddressDerivation) -> Vec<u8> {
+        match derivation {
+            AddressDerivation::Legacy => pubkey.to_vec(),
+            AddressDerivation::Canonical => {
+                let mut bytes = Vec::with_capacity(PUBKEY_BYTES_LEN + 1);
+                bytes.push(0x04);
+                bytes.extend_from_slice(&pubkey.0);
+                bytes
+            }
+        }
+    }
+
+    fn address_from_pubkey(pubkey: &PubKey, derivation: AddressDerivation) -> Address {
+        H160::from(Self::address_preimage(pubkey, derivation).crypt_hash())
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{KeyPair, PrivKey};
+    use super::{address_from_pubkey, Address, KeyPair, PrivKey, PubKey};
     use crate::types::H256;
-    use cita_crypto_trait::CreateKey;
+    use cita_crypto_trait::{AddressDerivation, CreateKey, ADDRESS_DERIVATION};
     use std::str::FromStr;
 
     #[test]
@@ -99,4 +132,28 @@ mod tests {
         );
         let _ = KeyPair::from_privkey(privkey).unwrap();
     }
+
+    #[cfg(feature = "sha3hash")]
+    #[test]
+    fn address_vectors() {
+        let keypair = KeyPair::from_privkey(
+            PrivKey::from_str("a100df7a048e50ed308ea696dc600215098141cb391e9527329df289f9383f65")
+                .unwrap(),
+        )
+        .unwrap();
+        let pubkey = PubKey::from_str("8ce0db0b0359ffc5866ba61903cc2518c3675ef2cf380a7e54bde7ea20e6fa1ab45b7617346cd11b7610001ee6ae5b0155c41cad9527cbcdff44ec67848943a4").unwrap();
+        assert_eq!(keypair.pubkey(), &pubkey);
+        assert_eq!(
+            address_from_pubkey(&pubkey, AddressDerivation::Legacy),
+            Address::from_str("5b073e9233944b5e729e46d618f0d8edf3d9c34a").unwrap()
+        );
+        assert_eq!(
+            address_from_pubkey(&pubkey, AddressDerivation::Canonical),
+            Address::from_str("1759eeb687d53071317bdff2d6347122927fc4ba").unwrap()
+        );
+        assert_eq!(
+            keypair.address(),
+            address_from_pubkey(&pubkey, ADDRESS_DERIVATION)
+        );
+    }
 }
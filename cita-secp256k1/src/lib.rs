@@ -26,6 +26,8 @@ pub const ADDR_BYTES_LEN: usize = 20;
 pub const PUBKEY_BYTES_LEN: usize = 64;
 pub const PRIVKEY_BYTES_LEN: usize = 32;
 pub const SIGNATURE_BYTES_LEN: usize = 65;
+/// Of a `StandardSignature`, which has no recovery id.
+pub const STANDARD_SIGNATURE_BYTES_LEN: usize = 64;
 pub const HASH_BYTES_LEN: usize = 32;
 
 mod error;
@@ -14,6 +14,7 @@
 
 use super::{
     pubkey_to_address, Address, Error, Message, PrivKey, PubKey, SECP256K1, SIGNATURE_BYTES_LEN,
+    STANDARD_SIGNATURE_BYTES_LEN,
 };
 use crate::types::H256;
 use cita_crypto_trait::Sign;
@@ -21,20 +22,31 @@ use rlp::*;
 use rustc_serialize::hex::ToHex;
 use secp256k1::key::{PublicKey, SecretKey};
 use secp256k1::{
-    recovery::RecoverableSignature, recovery::RecoveryId, Error as SecpError,
-    Message as SecpMessage,
+    recovery::RecoverableSignature as SecpRecoverableSignature, recovery::RecoveryId,
+    Error as SecpError, Message as SecpMessage, Signature as SecpSignature,
 };
 use serde::de::{Error as SerdeError, SeqAccess, Visitor};
 use serde::ser::SerializeSeq;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
-use std::cmp::PartialEq;
+use std::cmp::{self, PartialEq};
 use std::fmt;
 use std::hash::{Hash, Hasher};
 use std::ops::{Deref, DerefMut};
 
-pub struct Signature(pub [u8; 65]);
+/// `r || s || v`, where the recovery id `v` gives back the key of the
+/// signer. This is what transactions and votes carry. The layout is
+/// stable: `r` and `s` are 32 bytes big endian, and `v` is 0 or 1.
+pub struct RecoverableSignature(pub [u8; 65]);
 
-impl Signature {
+/// The name all backends have for their signature, the recoverable one
+/// for secp256k1. Name the one you need in code of this backend only.
+pub type Signature = RecoverableSignature;
+
+/// `r || s`, without the recovery id. It verifies against a key, and
+/// recovers one only given the id back, see `to_recoverable`.
+pub struct StandardSignature(pub [u8; 64]);
+
+impl RecoverableSignature {
     /// Get a slice into the 'r' portion of the data.
     pub fn r(&self) -> &[u8] {
         &self.0[0..32]
@@ -51,211 +63,337 @@ impl Signature {
     }
 
     /// Create a signature object from the sig.
-    pub fn from_rsv(r: &H256, s: &H256, v: u8) -> Signature {
+    pub fn from_rsv(r: &H256, s: &H256, v: u8) -> RecoverableSignature {
         let mut sig = [0u8; 65];
         sig[0..32].copy_from_slice(&r.0);
         sig[32..64].copy_from_slice(&s.0);
         sig[64] = v;
-        Signature(sig)
+        RecoverableSignature(sig)
+    }
+
+    /// As `from_rsv`, but `Error::InvalidSignature` unless `is_valid`.
+    pub fn try_from_rsv(r: &H256, s: &H256, v: u8) -> Result<RecoverableSignature, Error> {
+        let sig = RecoverableSignature::from_rsv(r, s, v);
+        if sig.is_valid() {
+            Ok(sig)
+        } else {
+            Err(Error::InvalidSignature)
+        }
     }
 
     /// Check if this is a "low" signature.
     pub fn is_low_s(&self) -> bool {
-        H256::from_slice(self.s())
-            <= "7FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF5D576E7357A4501DDFE92F46681B20A0".into()
+        is_low_s(self.s())
     }
 
     /// Check if each component of the signature is in range.
     pub fn is_valid(&self) -> bool {
-        self.v() <= 1
-            && H256::from_slice(self.r())
-                < "fffffffffffffffffffffffffffffffebaaedce6af48a03bbfd25e8cd0364141".into()
-            && H256::from_slice(self.r()) >= 1.into()
-            && H256::from_slice(self.s())
-                < "fffffffffffffffffffffffffffffffebaaedce6af48a03bbfd25e8cd0364141".into()
-            && H256::from_slice(self.s()) >= 1.into()
+        self.v() <= 1 && is_valid_scalar(self.r()) && is_valid_scalar(self.s())
     }
-}
 
-// manual implementation large arrays don't have trait impls by default.
-// remove when integer generics exist
-impl PartialEq for Signature {
-    fn eq(&self, other: &Self) -> bool {
-        self.0[..] == other.0[..]
+    /// `r || s`, leaving out the recovery id.
+    pub fn to_standard(&self) -> StandardSignature {
+        let mut sig = [0u8; 64];
+        sig.copy_from_slice(&self.0[0..64]);
+        StandardSignature(sig)
+    }
+
+    fn to_secp(&self) -> Result<SecpRecoverableSignature, Error> {
+        let rec_id = RecoveryId::from_i32(i32::from(self.v()))?;
+        Ok(SecpRecoverableSignature::from_compact(
+            &self.0[0..64],
+            rec_id,
+        )?)
     }
 }
 
-impl Decodable for Signature {
-    fn decode(rlp: &UntrustedRlp) -> Result<Self, DecoderError> {
-        rlp.decoder().decode_value(|bytes| {
-            let mut sig = [0u8; 65];
-            sig[0..65].copy_from_slice(bytes);
-            Ok(Signature(sig))
-        })
+impl StandardSignature {
+    /// Get a slice into the 'r' portion of the data.
+    pub fn r(&self) -> &[u8] {
+        &self.0[0..32]
+    }
+
+    /// Get a slice into the 's' portion of the data.
+    pub fn s(&self) -> &[u8] {
+        &self.0[32..64]
+    }
+
+    /// Check if this is a "low" signature.
+    pub fn is_low_s(&self) -> bool {
+        is_low_s(self.s())
+    }
+
+    /// Check if each component of the signature is in range.
+    pub fn is_valid(&self) -> bool {
+        is_valid_scalar(self.r()) && is_valid_scalar(self.s())
+    }
+
+    /// The recoverable signature with `recovery_id`, which is 0 to 3.
+    /// Nothing checks it is the one of the signer: a wrong one recovers
+    /// another key.
+    pub fn to_recoverable(&self, recovery_id: u8) -> Result<RecoverableSignature, Error> {
+        RecoveryId::from_i32(i32::from(recovery_id))?;
+        let mut sig = [0u8; 65];
+        sig[0..64].copy_from_slice(&self.0);
+        sig[64] = recovery_id;
+        Ok(RecoverableSignature(sig))
+    }
+
+    pub fn verify_public(&self, pubkey: &PubKey, message: &Message) -> Result<bool, Error> {
+        verify_standard(&SecpSignature::from_compact(&self.0)?, pubkey, message)
     }
 }
 
-impl Encodable for Signature {
-    fn rlp_append(&self, s: &mut RlpStream) {
-        s.encoder().encode_value(&self.0[0..65]);
+fn is_low_s(s: &[u8]) -> bool {
+    H256::from_slice(s) <= "7FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF5D576E7357A4501DDFE92F46681B20A0".into()
+}
+
+fn is_valid_scalar(scalar: &[u8]) -> bool {
+    H256::from_slice(scalar)
+        < "fffffffffffffffffffffffffffffffebaaedce6af48a03bbfd25e8cd0364141".into()
+        && H256::from_slice(scalar) >= 1.into()
+}
+
+fn verify_standard(sig: &SecpSignature, pubkey: &PubKey, message: &Message) -> Result<bool, Error> {
+    let context = &SECP256K1;
+    let pdata: [u8; 65] = {
+        let mut temp = [4u8; 65];
+        temp[1..65].copy_from_slice(pubkey);
+        temp
+    };
+
+    let public_key = PublicKey::from_slice(&pdata)?;
+    match context.verify(&SecpMessage::from_slice(&message.0[..])?, sig, &public_key) {
+        Ok(_) => Ok(true),
+        Err(SecpError::IncorrectSignature) => Ok(false),
+        Err(x) => Err(Error::from(x)),
     }
 }
 
-// TODO: Maybe it should be implemented with rust macro(https://github.com/rust-lang/rfcs/issues/1038)
-impl<'de> Deserialize<'de> for Signature {
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
-    where
-        D: Deserializer<'de>,
-    {
-        struct SignatureVisitor;
+fn recover_secp(rsig: &SecpRecoverableSignature, message: &Message) -> Result<PubKey, Error> {
+    let context = &SECP256K1;
+    let publ = context.recover(&SecpMessage::from_slice(&message.0[..])?, rsig)?;
+    let serialized = publ.serialize_uncompressed();
+
+    let mut pubkey = PubKey::default();
+    pubkey.0.copy_from_slice(&serialized[1..65]);
+    Ok(pubkey)
+}
+
+macro_rules! impl_signature_bytes {
+    ($name:ident, $len:expr, $expecting:expr) => {
+        impl PartialEq for $name {
+            fn eq(&self, other: &Self) -> bool {
+                self.0[..] == other.0[..]
+            }
+        }
 
-        impl<'de> Visitor<'de> for SignatureVisitor {
-            type Value = Signature;
+        impl Decodable for $name {
+            fn decode(rlp: &UntrustedRlp) -> Result<Self, DecoderError> {
+                rlp.decoder()
+                    .decode_value(|bytes| match bytes.len().cmp(&$len) {
+                        cmp::Ordering::Less => Err(DecoderError::RlpIsTooShort),
+                        cmp::Ordering::Greater => Err(DecoderError::RlpIsTooBig),
+                        cmp::Ordering::Equal => {
+                            let mut sig = [0u8; $len];
+                            sig.copy_from_slice(bytes);
+                            Ok($name(sig))
+                        }
+                    })
+            }
+        }
 
-            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-                formatter.write_str("secp256k1 signature")
+        impl Encodable for $name {
+            fn rlp_append(&self, s: &mut RlpStream) {
+                s.encoder().encode_value(&self.0[..]);
             }
+        }
 
-            fn visit_seq<V>(self, mut visitor: V) -> Result<Self::Value, V::Error>
+        impl<'de> Deserialize<'de> for $name {
+            fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
             where
-                V: SeqAccess<'de>,
+                D: Deserializer<'de>,
             {
-                let mut signature = Signature([0u8; SIGNATURE_BYTES_LEN]);
-                for i in 0..SIGNATURE_BYTES_LEN {
-                    signature.0[i] = match visitor.next_element()? {
-                        Some(val) => val,
-                        None => return Err(SerdeError::invalid_length(SIGNATURE_BYTES_LEN, &self)),
+                struct SignatureVisitor;
+
+                impl<'de> Visitor<'de> for SignatureVisitor {
+                    type Value = $name;
+
+                    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                        formatter.write_str($expecting)
+                    }
+
+                    fn visit_seq<V>(self, mut visitor: V) -> Result<Self::Value, V::Error>
+                    where
+                        V: SeqAccess<'de>,
+                    {
+                        let mut signature = $name([0u8; $len]);
+                        for i in 0..$len {
+                            signature.0[i] = match visitor.next_element()? {
+                                Some(val) => val,
+                                None => return Err(SerdeError::invalid_length($len, &self)),
+                            }
+                        }
+                        Ok(signature)
                     }
                 }
-                Ok(signature)
+
+                let visitor = SignatureVisitor;
+                deserializer.deserialize_seq(visitor)
             }
         }
 
-        let visitor = SignatureVisitor;
-        deserializer.deserialize_seq(visitor)
-    }
-}
+        impl Serialize for $name {
+            fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+            where
+                S: Serializer,
+            {
+                let mut seq = serializer.serialize_seq(Some($len))?;
+                for i in 0..$len {
+                    seq.serialize_element(&self.0[i])?;
+                }
+                seq.end()
+            }
+        }
+
+        impl Eq for $name {}
 
-impl Serialize for Signature {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        let mut seq = serializer.serialize_seq(Some(SIGNATURE_BYTES_LEN))?;
-        for i in 0..SIGNATURE_BYTES_LEN {
-            seq.serialize_element(&self.0[i])?;
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+                write!(f, "{}", self.to_hex())
+            }
         }
-        seq.end()
-    }
-}
 
-// manual implementation required in Rust 1.13+, see `std::cmp::AssertParamIsEq`.
-impl Eq for Signature {}
+        impl Default for $name {
+            fn default() -> Self {
+                $name([0; $len])
+            }
+        }
 
-// also manual for the same reason, but the pretty printing might be useful.
-impl fmt::Debug for Signature {
-    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
-        f.debug_struct("Signature")
-            .field("r", &self.0[0..32].to_hex())
-            .field("s", &self.0[32..64].to_hex())
-            .field("v", &self.0[64..65].to_hex())
-            .finish()
-    }
-}
+        impl Hash for $name {
+            fn hash<H: Hasher>(&self, state: &mut H) {
+                self.0.hash(state);
+            }
+        }
 
-impl fmt::Display for Signature {
-    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
-        write!(f, "{}", self.to_hex())
-    }
-}
+        impl Clone for $name {
+            fn clone(&self) -> Self {
+                $name(self.0)
+            }
+        }
 
-impl Default for Signature {
-    fn default() -> Self {
-        Signature([0; 65])
-    }
-}
+        impl From<[u8; $len]> for $name {
+            fn from(s: [u8; $len]) -> Self {
+                $name(s)
+            }
+        }
 
-impl Hash for Signature {
-    fn hash<H: Hasher>(&self, state: &mut H) {
-        self.0.hash(state);
-    }
-}
+        impl Into<[u8; $len]> for $name {
+            fn into(self) -> [u8; $len] {
+                self.0
+            }
+        }
 
-impl Clone for Signature {
-    fn clone(&self) -> Self {
-        Signature(self.0)
-    }
-}
+        impl<'a> From<&'a [u8]> for $name {
+            fn from(slice: &'a [u8]) -> $name {
+                assert_eq!(slice.len(), $len);
+                let mut bytes = [0u8; $len];
+                bytes.copy_from_slice(&slice[..]);
+                $name(bytes)
+            }
+        }
 
-impl From<[u8; 65]> for Signature {
-    fn from(s: [u8; 65]) -> Self {
-        Signature(s)
-    }
-}
+        impl<'a> Into<&'a [u8]> for &'a $name {
+            fn into(self) -> &'a [u8] {
+                &self.0[..]
+            }
+        }
 
-impl Into<[u8; 65]> for Signature {
-    fn into(self) -> [u8; 65] {
-        self.0
-    }
-}
+        impl fmt::LowerHex for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                for i in &self.0[..] {
+                    write!(f, "{:02x}", i)?;
+                }
+                Ok(())
+            }
+        }
 
-impl<'a> From<&'a [u8]> for Signature {
-    fn from(slice: &'a [u8]) -> Signature {
-        assert_eq!(slice.len(), SIGNATURE_BYTES_LEN);
-        let mut bytes = [0u8; 65];
-        bytes.copy_from_slice(&slice[..]);
-        Signature(bytes)
-    }
-}
+        impl From<$name> for String {
+            fn from(s: $name) -> Self {
+                format!("{:x}", s)
+            }
+        }
 
-impl<'a> Into<&'a [u8]> for &'a Signature {
-    fn into(self) -> &'a [u8] {
-        &self.0[..]
-    }
-}
+        impl Deref for $name {
+            type Target = [u8; $len];
 
-impl fmt::LowerHex for Signature {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        for i in &self.0[..] {
-            write!(f, "{:02x}", i)?;
+            fn deref(&self) -> &Self::Target {
+                &self.0
+            }
         }
-        Ok(())
-    }
+
+        impl DerefMut for $name {
+            fn deref_mut(&mut self) -> &mut Self::Target {
+                &mut self.0
+            }
+        }
+    };
 }
 
-impl From<Signature> for String {
-    fn from(s: Signature) -> Self {
-        format!("{:x}", s)
+impl_signature_bytes!(
+    RecoverableSignature,
+    SIGNATURE_BYTES_LEN,
+    "secp256k1 signature"
+);
+impl_signature_bytes!(
+    StandardSignature,
+    STANDARD_SIGNATURE_BYTES_LEN,
+    "secp256k1 signature without recovery id"
+);
+
+impl fmt::Debug for RecoverableSignature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        f.debug_struct("RecoverableSignature")
+            .field("r", &self.0[0..32].to_hex())
+            .field("s", &self.0[32..64].to_hex())
+            .field("v", &self.0[64..65].to_hex())
+            .finish()
     }
 }
 
-impl Deref for Signature {
-    type Target = [u8; 65];
+impl fmt::Debug for StandardSignature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        f.debug_struct("StandardSignature")
+            .field("r", &self.0[0..32].to_hex())
+            .field("s", &self.0[32..64].to_hex())
+            .finish()
+    }
+}
 
-    fn deref(&self) -> &Self::Target {
-        &self.0
+impl From<RecoverableSignature> for StandardSignature {
+    fn from(sig: RecoverableSignature) -> Self {
+        sig.to_standard()
     }
 }
 
-impl DerefMut for Signature {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
+impl<'a> From<&'a RecoverableSignature> for StandardSignature {
+    fn from(sig: &'a RecoverableSignature) -> Self {
+        sig.to_standard()
     }
 }
 
 pub fn sign(privkey: &PrivKey, message: &Message) -> Result<Signature, Error> {
     let context = &SECP256K1;
-    // no way to create from raw byte array.
-    let sec: &SecretKey =
-        unsafe { &*(privkey as *const types::H256 as *const secp256k1::SecretKey) };
-    let s = context.sign_recoverable(&SecpMessage::from_slice(&message.0[..])?, sec);
+    // rust-secp256k1 panics on a key out of range, so check it first.
+    let sec = SecretKey::from_slice(&privkey.0[..])?;
+    let s = context.sign_recoverable(&SecpMessage::from_slice(&message.0[..])?, &sec);
     let (rec_id, data) = s.serialize_compact();
     let mut data_arr = [0; 65];
 
     // no need to check if s is low, it always is
     data_arr[0..64].copy_from_slice(&data[0..64]);
     data_arr[64] = rec_id.to_i32() as u8;
-    Ok(Signature(data_arr))
+    Ok(RecoverableSignature(data_arr))
 }
 
 pub fn verify_public(
@@ -263,25 +401,7 @@ pub fn verify_public(
     signature: &Signature,
     message: &Message,
 ) -> Result<bool, Error> {
-    let context = &SECP256K1;
-    let rsig = RecoverableSignature::from_compact(
-        &signature[0..64],
-        RecoveryId::from_i32(i32::from(signature[64]))?,
-    )?;
-    let sig = rsig.to_standard();
-
-    let pdata: [u8; 65] = {
-        let mut temp = [4u8; 65];
-        temp[1..65].copy_from_slice(pubkey);
-        temp
-    };
-
-    let public_key = PublicKey::from_slice(&pdata)?;
-    match context.verify(&SecpMessage::from_slice(&message.0[..])?, &sig, &public_key) {
-        Ok(_) => Ok(true),
-        Err(SecpError::IncorrectSignature) => Ok(false),
-        Err(x) => Err(Error::from(x)),
-    }
+    signature.verify_public(pubkey, message)
 }
 
 pub fn verify_address(
@@ -289,85 +409,35 @@ pub fn verify_address(
     signature: &Signature,
     message: &Message,
 ) -> Result<bool, Error> {
-    let pubkey = recover(signature, message)?;
-    let recovered_address = pubkey_to_address(&pubkey);
-    Ok(address == &recovered_address)
+    signature.verify_address(address, message)
 }
 
 pub fn recover(signature: &Signature, message: &Message) -> Result<PubKey, Error> {
-    let context = &SECP256K1;
-    let rsig = RecoverableSignature::from_compact(
-        &signature[0..64],
-        RecoveryId::from_i32(i32::from(signature[64]))?,
-    )?;
-    let publ = context.recover(&SecpMessage::from_slice(&message.0[..])?, &rsig)?;
-    let serialized = publ.serialize_uncompressed();
-
-    let mut pubkey = PubKey::default();
-    pubkey.0.copy_from_slice(&serialized[1..65]);
-    Ok(pubkey)
+    signature.recover(message)
 }
 
-impl Sign for Signature {
+impl Sign for RecoverableSignature {
     type PrivKey = PrivKey;
     type PubKey = PubKey;
     type Message = Message;
     type Error = Error;
 
     fn sign(privkey: &Self::PrivKey, message: &Self::Message) -> Result<Self, Self::Error> {
-        let context = &SECP256K1;
-        // no way to create from raw byte array.
-        let sec: &SecretKey =
-            unsafe { &*(privkey as *const types::H256 as *const secp256k1::SecretKey) };
-        let msg = SecpMessage::from_slice(&message.0[..]).unwrap();
-        let s = context.sign_recoverable(&msg, sec);
-        let (rec_id, data) = s.serialize_compact();
-        let mut data_arr = [0; 65];
-
-        // no need to check if s is low, it always is
-        data_arr[0..64].copy_from_slice(&data[0..64]);
-        data_arr[64] = rec_id.to_i32() as u8;
-        Ok(Signature(data_arr))
+        sign(privkey, message)
     }
 
     fn recover(&self, message: &Message) -> Result<Self::PubKey, Error> {
-        let context = &SECP256K1;
-        let rsig = RecoverableSignature::from_compact(
-            &self.0[0..64],
-            RecoveryId::from_i32(i32::from(self.0[64]))?,
-        )?;
-        let publ = context.recover(&SecpMessage::from_slice(&message.0[..])?, &rsig)?;
-        let serialized = publ.serialize_uncompressed();
-
-        let mut pubkey = PubKey::default();
-        pubkey.0.copy_from_slice(&serialized[1..65]);
-        Ok(pubkey)
+        recover_secp(&self.to_secp()?, message)
     }
 
+    /// Fails on a recovery id out of range, even though verifying
+    /// doesn't need it: the signature is broken.
     fn verify_public(
         &self,
         pubkey: &Self::PubKey,
         message: &Self::Message,
     ) -> Result<bool, Self::Error> {
-        let context = &SECP256K1;
-        let rsig = RecoverableSignature::from_compact(
-            &self.0[0..64],
-            RecoveryId::from_i32(i32::from(self.0[64]))?,
-        )?;
-        let sig = rsig.to_standard();
-
-        let pdata: [u8; 65] = {
-            let mut temp = [4u8; 65];
-            temp[1..65].copy_from_slice(pubkey);
-            temp
-        };
-
-        let publ = PublicKey::from_slice(&pdata)?;
-        match context.verify(&SecpMessage::from_slice(&message.0[..])?, &sig, &publ) {
-            Ok(_) => Ok(true),
-            Err(SecpError::IncorrectSignature) => Ok(false),
-            Err(x) => Err(Error::from(x)),
-        }
+        verify_standard(&self.to_secp()?.to_standard(), pubkey, message)
     }
 
     fn verify_address(
@@ -383,12 +453,13 @@ impl Sign for Signature {
 
 #[cfg(test)]
 mod tests {
-    use super::super::KeyPair;
-    use super::{PrivKey, Signature};
+    use super::super::{Error, KeyPair};
+    use super::{PrivKey, RecoverableSignature, Signature, StandardSignature};
     use crate::types::H256;
     use bincode::{deserialize, serialize, Infinite};
     use cita_crypto_trait::{CreateKey, Sign};
     use hashable::Hashable;
+    use rlp::{self, UntrustedRlp};
     use std::str::FromStr;
 
     #[test]
@@ -442,6 +513,132 @@ mod tests {
         assert_eq!(signature, de_result);
     }
 
+    #[test]
+    fn test_malformed_input() {
+        let message = H256::from(1);
+        // Neither zero nor the curve order is a key.
+        let order =
+            PrivKey::from_str("fffffffffffffffffffffffffffffffebaaedce6af48a03bbfd25e8cd0364141")
+                .unwrap();
+        for privkey in &[PrivKey::default(), order] {
+            assert!(Signature::sign(privkey, &message).is_err());
+            assert!(super::sign(privkey, &message).is_err());
+        }
+
+        for len in &[0, 64, 66] {
+            let encoded = rlp::encode(&vec![1u8; *len]);
+            assert!(UntrustedRlp::new(&encoded).as_val::<Signature>().is_err());
+        }
+        let encoded = rlp::encode(&vec![1u8; 65]);
+        assert!(UntrustedRlp::new(&encoded).as_val::<Signature>().is_ok());
+    }
+
+    #[test]
+    fn test_standard_conversions() {
+        let keypair = KeyPair::gen_keypair();
+        let message = "".to_owned().crypt_hash();
+        let sig = Signature::sign(keypair.privkey(), &message).unwrap();
+        let standard = sig.to_standard();
+        assert_eq!(standard.r(), sig.r());
+        assert_eq!(standard.s(), sig.s());
+        assert_eq!(StandardSignature::from(&sig), standard);
+        assert!(standard.verify_public(keypair.pubkey(), &message).unwrap());
+        assert!(!standard
+            .verify_public(keypair.pubkey(), &H256::from(1))
+            .unwrap());
+
+        let recoverable = standard.to_recoverable(sig.v()).unwrap();
+        assert_eq!(recoverable, sig);
+        assert_eq!(keypair.pubkey(), &recoverable.recover(&message).unwrap());
+    }
+
+    #[test]
+    fn test_corrupted_recovery_id() {
+        let keypair = KeyPair::gen_keypair();
+        let message = "".to_owned().crypt_hash();
+        let mut sig = Signature::sign(keypair.privkey(), &message).unwrap();
+        let v = sig.v();
+        // As left by a store which keeps 64 bytes only.
+        sig.0[64] = 27;
+        match sig.recover(&message) {
+            Err(Error::InvalidSignature) => {}
+            other => panic!("recovered {:?}", other),
+        }
+        assert!(sig.verify_public(keypair.pubkey(), &message).is_err());
+        match sig.to_standard().to_recoverable(27) {
+            Err(Error::InvalidSignature) => {}
+            other => panic!("converted to {:?}", other),
+        }
+
+        // What is left verifies, and recovers with the id back.
+        let standard = sig.to_standard();
+        assert!(standard.verify_public(keypair.pubkey(), &message).unwrap());
+        let sig = standard.to_recoverable(v).unwrap();
+        assert_eq!(keypair.pubkey(), &sig.recover(&message).unwrap());
+    }
+
+    #[test]
+    fn test_components() {
+        let keypair = KeyPair::gen_keypair();
+        let message = "components".to_owned().crypt_hash();
+        let sig = Signature::sign(keypair.privkey(), &message).unwrap();
+        let (r, s) = (H256::from_slice(sig.r()), H256::from_slice(sig.s()));
+        assert_eq!(&sig.0[..32], &r.0[..]);
+        assert_eq!(&sig.0[32..64], &s.0[..]);
+        assert_eq!(sig.0[64], sig.v());
+
+        let rebuilt = Signature::try_from_rsv(&r, &s, sig.v()).unwrap();
+        assert_eq!(rebuilt, sig);
+        assert!(rebuilt.verify_public(keypair.pubkey(), &message).unwrap());
+        assert_eq!(keypair.pubkey(), &rebuilt.recover(&message).unwrap());
+
+        let order =
+            H256::from_str("fffffffffffffffffffffffffffffffebaaedce6af48a03bbfd25e8cd0364141")
+                .unwrap();
+        for bad in &[H256::zero(), order] {
+            match Signature::try_from_rsv(bad, &s, sig.v()) {
+                Err(Error::InvalidSignature) => {}
+                other => panic!("built {:?}", other),
+            }
+            assert!(Signature::try_from_rsv(&r, bad, sig.v()).is_err());
+        }
+        assert!(Signature::try_from_rsv(&r, &s, 2).is_err());
+        assert!(Signature::try_from_rsv(&r, &s, 27).is_err());
+    }
+
+    #[test]
+    fn test_wire_format() {
+        let keypair = KeyPair::gen_keypair();
+        let message = "".to_owned().crypt_hash();
+        let sig = Signature::sign(keypair.privkey(), &message).unwrap();
+
+        // The 65 bytes blobs of today, in RLP and in bincode.
+        let blob = sig.0.to_vec();
+        let encoded = rlp::encode(&blob);
+        assert_eq!(&rlp::encode(&sig)[..], &encoded[..]);
+        let decoded: RecoverableSignature = UntrustedRlp::new(&encoded).as_val().unwrap();
+        assert_eq!(decoded, sig);
+        let se_result = serialize(&sig, Infinite).unwrap();
+        assert_eq!(se_result, serialize(&blob, Infinite).unwrap());
+        let de_result: Signature = deserialize(&se_result).unwrap();
+        assert_eq!(de_result, sig);
+
+        // A standard one is the first 64 of them, and takes only those.
+        let standard = sig.to_standard();
+        let short = blob[..64].to_vec();
+        assert_eq!(&rlp::encode(&standard)[..], &rlp::encode(&short)[..]);
+        assert!(UntrustedRlp::new(&encoded)
+            .as_val::<StandardSignature>()
+            .is_err());
+        assert!(UntrustedRlp::new(&rlp::encode(&short))
+            .as_val::<RecoverableSignature>()
+            .is_err());
+        let se_result = serialize(&standard, Infinite).unwrap();
+        assert_eq!(se_result, serialize(&short, Infinite).unwrap());
+        let de_result: StandardSignature = deserialize(&se_result).unwrap();
+        assert_eq!(de_result, standard);
+    }
+
     #[test]
     fn test_show_signature() {
         let sk = PrivKey::from(
@@ -17,12 +17,15 @@ use std::fmt;
 #[derive(Debug)]
 pub enum Error {
     RecoverError,
+    /// `r` or `s` out of range, see `Signature::from_rs`.
+    InvalidSignature,
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let message = match *self {
             Error::RecoverError => "Recover Error",
+            Error::InvalidSignature => "Invalid Signature",
         };
         f.write_fmt(format_args!("Crypto error: {}", message))
     }
@@ -12,27 +12,46 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use super::{Address, Error, PrivKey, PubKey};
+use super::{Address, Error, PrivKey, PubKey, PUBKEY_BYTES_LEN};
+use crate::secret::SecretBytes;
+use crate::signature::load_seckey;
 use crate::types::H160;
-use cita_crypto_trait::CreateKey;
+use cita_crypto_trait::{AddressDerivation, AddressFromPubKey, CreateKey, ADDRESS_DERIVATION};
 use hashable::Hashable;
 use libsm::sm2::signature::SigCtx;
 use rustc_serialize::hex::ToHex;
 use std::fmt;
 
 pub fn pubkey_to_address(pubkey: &PubKey) -> Address {
-    H160::from(pubkey.crypt_hash())
+    address_from_pubkey(pubkey, ADDRESS_DERIVATION)
 }
 
+pub fn address_from_pubkey(pubkey: &PubKey, derivation: AddressDerivation) -> Address {
+    KeyPair::address_from_pubkey(pubkey, derivation)
+}
+
+/// The private key is zeroed when the pair is dropped.
 #[derive(Default)]
 pub struct KeyPair {
     privkey: PrivKey,
     pubkey: PubKey,
 }
 
+impl KeyPair {
+    /// The pair with `seed` as its secret, which fails in the unlikely case
+    /// it isn't a valid one.
+    pub fn from_seed(seed: [u8; 32]) -> Result<Self, Error> {
+        KeyPair::from_privkey(PrivKey::from(seed))
+    }
+
+    /// Like `Display`, but with the private key. Only for debugging.
+    pub fn display_unsafe(&self) -> String {
+        format!("privkey:  {}\n{}", self.privkey.0.to_hex(), self)
+    }
+}
+
 impl fmt::Display for KeyPair {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
-        writeln!(f, "privkey:  {}", self.privkey.0.to_hex())?;
         writeln!(f, "pubkey:  {}", self.pubkey.0.to_hex())?;
         write!(f, "address:  {}", self.address().0.to_hex())
     }
@@ -45,21 +64,25 @@ impl CreateKey for KeyPair {
 
     fn from_privkey(privkey: Self::PrivKey) -> Result<Self, Self::Error> {
         let ctx = SigCtx::new();
-        ctx.load_seckey(&privkey.0)
-            .map_err(|_| Error::RecoverError)
-            .map(|sk| {
-                let pk = ctx.pk_from_sk(&sk);
-                let pubkey = PubKey::from(&ctx.serialize_pubkey(&pk, false)[1..]);
-                KeyPair { privkey, pubkey }
-            })
+        load_seckey(&ctx, &privkey).map(|sk| {
+            let pk = ctx.pk_from_sk(&sk);
+            let pubkey = PubKey::from(&ctx.serialize_pubkey(&pk, false)[1..]);
+            KeyPair { privkey, pubkey }
+        })
     }
 
     fn gen_keypair() -> Self {
         let ctx = SigCtx::new();
         let (pk, sk) = ctx.new_keypair();
-        let pubkey = PubKey::from(&ctx.serialize_pubkey(&pk, false)[1..]);
-        let privkey = PrivKey::from(&ctx.serialize_seckey(&sk)[..]);
-        KeyPair { privkey, pubkey }
+        let mut keypair = KeyPair {
+            privkey: PrivKey::default(),
+            pubkey: PubKey::from(&ctx.serialize_pubkey(&pk, false)[1..]),
+        };
+        // Straight into the pair's key, which zeroes itself, with no other
+        // copy left behind on the way.
+        let seckey = SecretBytes::new(ctx.serialize_seckey(&sk));
+        keypair.privkey.0.copy_from_slice(seckey.as_bytes());
+        keypair
     }
 
     fn privkey(&self) -> &Self::PrivKey {
@@ -75,10 +98,33 @@ impl CreateKey for KeyPair {
     }
 }
 
+/// `PubKey` is the point without its `0x04` tag.
+impl AddressFromPubKey for KeyPair {
+    type PubKey = PubKey;
+
+    fn address_preimage(pubkey: &PubKey, derivation: AddressDerivation) -> Vec<u8> {
+        match derivation {
+            AddressDerivation::Legacy => pubkey.to_vec(),
+            AddressDerivation::Canonical => {
+                let mut bytes = Vec::with_capacity(PUBKEY_BYTES_LEN + 1);
+                bytes.push(0x04);
+                bytes.extend_from_slice(&pubkey.0);
+                bytes
+            }
+        }
+    }
+
+    fn address_from_pubkey(pubkey: &PubKey, derivation: AddressDerivation) -> Address {
+        H160::from(Self::address_preimage(pubkey, derivation).crypt_hash())
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::KeyPair;
-    use cita_crypto_trait::CreateKey;
+    use super::{address_from_pubkey, Address, KeyPair, PrivKey, PubKey};
+    use cita_crypto_trait::{AddressDerivation, CreateKey, ADDRESS_DERIVATION};
+    use rustc_serialize::hex::ToHex;
+    use std::str::FromStr;
 
     #[test]
     fn test_gen_keypair() {
@@ -87,4 +133,37 @@ mod tests {
         let new_keypair = KeyPair::from_privkey(privkey).unwrap();
         assert_eq!(keypair.pubkey(), new_keypair.pubkey());
     }
+
+    #[test]
+    fn test_display_hides_privkey() {
+        let keypair = KeyPair::gen_keypair();
+        let privkey = keypair.privkey().0.to_hex();
+        assert!(!format!("{}", keypair).contains(&privkey));
+        assert!(keypair.display_unsafe().contains(&privkey));
+        assert!(keypair.display_unsafe().contains(&format!("{}", keypair)));
+    }
+
+    #[cfg(feature = "sm3hash")]
+    #[test]
+    fn address_vectors() {
+        let keypair = KeyPair::from_privkey(
+            PrivKey::from_str("3945208f7b2144b13f36e38ac6d39f95889393692860b51a42fb81ef4df7c5b8")
+                .unwrap(),
+        )
+        .unwrap();
+        let pubkey = PubKey::from_str("09f9df311e5421a150dd7d161e4bc5c672179fad1833fc076bb08ff356f35020ccea490ce26775a52dc6ea718cc1aa600aed05fbf35e084a6632f6072da9ad13").unwrap();
+        assert_eq!(keypair.pubkey(), &pubkey);
+        assert_eq!(
+            address_from_pubkey(&pubkey, AddressDerivation::Legacy),
+            Address::from_str("2119e1ecc071c2f3d915245c8d9c5a2cb131a89d").unwrap()
+        );
+        assert_eq!(
+            address_from_pubkey(&pubkey, AddressDerivation::Canonical),
+            Address::from_str("b900d57cd86b4677fa5dc70284c90de05fa77f9c").unwrap()
+        );
+        assert_eq!(
+            keypair.address(),
+            address_from_pubkey(&pubkey, ADDRESS_DERIVATION)
+        );
+    }
 }
@@ -18,15 +18,17 @@ use crate::types::{Address, H256, H512};
 
 mod error;
 mod keypair;
+mod nonce;
+mod secret;
 mod signature;
 mod signer;
 
 pub use self::error::*;
 pub use self::keypair::*;
+pub use self::secret::{PrivKey, SecretBytes};
 pub use self::signature::*;
 pub use self::signer::*;
 
-pub type PrivKey = H256;
 pub type PubKey = H512;
 pub type Message = H256;
 
@@ -0,0 +1,101 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Deterministic nonces following RFC 6979 section 3.2, with HMAC-SM3 as
+//! the HMAC and the SM2 digest `e = SM3(Z_A || M)` as the message hash.
+
+use hashable::{hmac_with, wipe};
+use libsm::sm3::hash::Sm3Hash;
+use num_bigint::BigUint;
+use num_traits::Zero;
+
+const HASH_LEN: usize = 32;
+const BLOCK_LEN: usize = 64;
+
+fn sm3(data: &[u8]) -> [u8; HASH_LEN] {
+    Sm3Hash::new(data).get_hash()
+}
+
+fn hmac_sm3(key: &[u8], parts: &[&[u8]]) -> [u8; HASH_LEN] {
+    hmac_with(sm3, BLOCK_LEN, key, parts)
+}
+
+pub(crate) struct NonceGenerator<'a> {
+    k: [u8; HASH_LEN],
+    v: [u8; HASH_LEN],
+    n: &'a BigUint,
+    retry: bool,
+}
+
+impl<'a> NonceGenerator<'a> {
+    /// `privkey` and `digest` must both be 32 bytes, `digest` already
+    /// reduced modulo `n`.
+    pub(crate) fn new(privkey: &[u8], digest: &[u8], n: &'a BigUint) -> Self {
+        let v = [0x01u8; HASH_LEN];
+        let k = [0x00u8; HASH_LEN];
+        let k = hmac_sm3(&k, &[&v, &[0x00], privkey, digest]);
+        let v = hmac_sm3(&k, &[&v]);
+        let k = hmac_sm3(&k, &[&v, &[0x01], privkey, digest]);
+        let v = hmac_sm3(&k, &[&v]);
+        NonceGenerator {
+            k,
+            v,
+            n,
+            retry: false,
+        }
+    }
+
+    /// The next candidate in `[1, n)`. Asking again after the signature
+    /// turned out invalid gives a fresh nonce.
+    pub(crate) fn next_nonce(&mut self) -> BigUint {
+        loop {
+            if self.retry {
+                self.k = hmac_sm3(&self.k, &[&self.v, &[0x00]]);
+                self.v = hmac_sm3(&self.k, &[&self.v]);
+            }
+            self.retry = true;
+            self.v = hmac_sm3(&self.k, &[&self.v]);
+            let nonce = BigUint::from_bytes_be(&self.v);
+            if !nonce.is_zero() && nonce < *self.n {
+                return nonce;
+            }
+        }
+    }
+}
+
+impl<'a> Drop for NonceGenerator<'a> {
+    fn drop(&mut self) {
+        wipe(&mut self.k);
+        wipe(&mut self.v);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::hmac_sm3;
+    use rustc_serialize::hex::ToHex;
+
+    #[test]
+    fn test_hmac_sm3() {
+        let key = [0x0bu8; 20];
+        assert_eq!(
+            hmac_sm3(&key, &[b"Hi ", b"There"]).to_hex(),
+            hmac_sm3(&key, &[b"Hi There"]).to_hex()
+        );
+        assert_eq!(
+            hmac_sm3(&key, &[b"Hi There"]).to_hex(),
+            "51b00d1fb49832bfb01c3ce27848e59f871d9ba938dc563b338ca964755cce70"
+        );
+    }
+}
@@ -0,0 +1,168 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{H256, PRIVKEY_BYTES_LEN};
+use hashable::{constant_time_eq, wipe};
+use std::fmt;
+use std::ops::Deref;
+use std::str::FromStr;
+
+/// Key material which is zeroed on drop and never printed.
+///
+/// Equality runs in constant time. There is deliberately no `Display`,
+/// and `Debug` only shows the length.
+pub struct SecretBytes(Vec<u8>);
+
+impl SecretBytes {
+    pub fn new(bytes: Vec<u8>) -> Self {
+        SecretBytes(bytes)
+    }
+
+    pub fn from_slice(bytes: &[u8]) -> Self {
+        SecretBytes(bytes.to_vec())
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl Drop for SecretBytes {
+    fn drop(&mut self) {
+        wipe(&mut self.0);
+    }
+}
+
+impl Clone for SecretBytes {
+    fn clone(&self) -> Self {
+        SecretBytes::from_slice(&self.0)
+    }
+}
+
+impl PartialEq for SecretBytes {
+    fn eq(&self, other: &Self) -> bool {
+        constant_time_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for SecretBytes {}
+
+impl fmt::Debug for SecretBytes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SecretBytes({} bytes)", self.0.len())
+    }
+}
+
+/// A private key, zeroed on drop and never printed.
+///
+/// Unlike the `H256` it once was it isn't `Copy`, so a key isn't copied
+/// around unnoticed: it goes by reference, and is cloned where another
+/// owner keeps it. Equality runs in constant time.
+#[derive(Default)]
+pub struct PrivKey(pub [u8; PRIVKEY_BYTES_LEN]);
+
+impl Drop for PrivKey {
+    fn drop(&mut self) {
+        wipe(&mut self.0);
+    }
+}
+
+impl Clone for PrivKey {
+    fn clone(&self) -> Self {
+        PrivKey(self.0)
+    }
+}
+
+impl PartialEq for PrivKey {
+    fn eq(&self, other: &Self) -> bool {
+        constant_time_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for PrivKey {}
+
+impl fmt::Debug for PrivKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "PrivKey(..)")
+    }
+}
+
+impl Deref for PrivKey {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl From<[u8; PRIVKEY_BYTES_LEN]> for PrivKey {
+    fn from(bytes: [u8; PRIVKEY_BYTES_LEN]) -> Self {
+        PrivKey(bytes)
+    }
+}
+
+/// Panics unless `bytes` is `PRIVKEY_BYTES_LEN` long.
+impl<'a> From<&'a [u8]> for PrivKey {
+    fn from(bytes: &'a [u8]) -> Self {
+        let mut privkey = PrivKey::default();
+        privkey.0.copy_from_slice(bytes);
+        privkey
+    }
+}
+
+impl FromStr for PrivKey {
+    type Err = <H256 as FromStr>::Err;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut hash = H256::from_str(s)?;
+        let privkey = PrivKey(hash.0);
+        wipe(&mut hash.0);
+        Ok(privkey)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PrivKey, SecretBytes};
+    use std::str::FromStr;
+
+    #[test]
+    fn test_secret_bytes() {
+        let secret = SecretBytes::from_slice(&[0xab; 32]);
+        assert_eq!(format!("{:?}", secret), "SecretBytes(32 bytes)");
+        assert_eq!(secret, secret.clone());
+        assert_ne!(secret, SecretBytes::new(vec![0xab; 31]));
+        assert_ne!(secret, SecretBytes::new(vec![0xac; 32]));
+    }
+
+    #[test]
+    fn test_privkey() {
+        let hex = "3945208f7b2144b13f36e38ac6d39f95889393692860b51a42fb81ef4df7c5b8";
+        let privkey = PrivKey::from_str(hex).unwrap();
+        assert_eq!(privkey.0[..2], [0x39, 0x45]);
+        assert_eq!(PrivKey::from(&privkey[..]), privkey);
+        assert_eq!(privkey.clone(), privkey);
+        assert_ne!(PrivKey::default(), privkey);
+        assert_eq!(format!("{:?}", privkey), "PrivKey(..)");
+        assert!(PrivKey::from_str("39").is_err());
+    }
+}
@@ -12,28 +12,130 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use super::{pubkey_to_address, Address, Error, Message, PrivKey, PubKey, SIGNATURE_BYTES_LEN};
+use super::{
+    pubkey_to_address, Address, Error, Message, PrivKey, PubKey, H256, SIGNATURE_BYTES_LEN,
+};
+use crate::nonce::NonceGenerator;
 use cita_crypto_trait::Sign;
+use libsm::sm2::ecc::EccCtx;
 use libsm::sm2::signature::{SigCtx, Signature as Sm2Signature};
+use num_bigint::BigUint;
+use num_traits::{One, Zero};
 use rlp::*;
 use rustc_serialize::hex::ToHex;
 use serde::de::{Error as SerdeError, SeqAccess, Visitor};
 use serde::ser::SerializeSeq;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::cmp;
 use std::fmt;
 use std::hash::{Hash, Hasher};
 use std::ops::{Deref, DerefMut};
 
+/// The user id libsm hashes into `Z_A` when signing.
+const DEFAULT_USER_ID: &str = "1234567812345678";
+
+/// Load a private key, which must be in `[1, n - 2]`. libsm takes any
+/// value up to `n`, but with `d = n - 1` there is no `(1 + d)^-1`, and no
+/// signature could ever be made.
+pub(crate) fn load_seckey(ctx: &SigCtx, privkey: &PrivKey) -> Result<BigUint, Error> {
+    let sk = ctx
+        .load_seckey(&privkey.0)
+        .map_err(|_| Error::RecoverError)?;
+    let max = EccCtx::new().get_n() - BigUint::from(2u32);
+    if sk.is_zero() || sk > max {
+        Err(Error::RecoverError)
+    } else {
+        Ok(sk)
+    }
+}
+
+/// `r || s || pk`, each big endian: `r` and `s` of 32 bytes, and the
+/// uncompressed key of the signer without its `04` prefix, of 64. The
+/// layout is stable, code taking the bytes apart may rely on it, but
+/// `r`, `s` and `from_rs` say what they are.
 pub struct Signature(pub [u8; 128]);
 
 impl Signature {
+    fn from_parts(r: &BigUint, s: &BigUint, pk: &[u8]) -> Self {
+        let mut sig_bytes = [0u8; SIGNATURE_BYTES_LEN];
+        let r_bytes = r.to_bytes_be();
+        let s_bytes = s.to_bytes_be();
+        sig_bytes[32 - r_bytes.len()..32].copy_from_slice(&r_bytes[..]);
+        sig_bytes[64 - s_bytes.len()..64].copy_from_slice(&s_bytes[..]);
+        sig_bytes[64..].copy_from_slice(pk);
+        sig_bytes.into()
+    }
+
+    /// Sign with a nonce derived from the key and the message (RFC 6979,
+    /// adapted to SM2), so the signature doesn't depend on the RNG.
+    ///
+    /// The result verifies like any other signature.
+    pub fn sign_deterministic(privkey: &PrivKey, message: &Message) -> Result<Self, Error> {
+        let ctx = SigCtx::new();
+        let curve = EccCtx::new();
+        let sk = load_seckey(&ctx, privkey)?;
+        let pk = ctx.pk_from_sk(&sk);
+        let n = curve.get_n();
+
+        let e = BigUint::from_bytes_be(&ctx.hash(DEFAULT_USER_ID, &pk, &message.0));
+        let mut digest = [0u8; 32];
+        let e_bytes = (&e % n).to_bytes_be();
+        digest[32 - e_bytes.len()..].copy_from_slice(&e_bytes);
+        let mut nonces = NonceGenerator::new(&privkey.0, &digest, n);
+
+        // (1 + d)^-1, n is prime.
+        let inv = (&sk + BigUint::one()).modpow(&(n - BigUint::from(2u32)), n);
+        loop {
+            let k = nonces.next_nonce();
+            let (x1, _) = curve.to_affine(&curve.g_mul(&k));
+            let r = (&e + x1.to_biguint()) % n;
+            if r.is_zero() || &r + &k == *n {
+                continue;
+            }
+            // s = (1 + d)^-1 * (k - r * d) mod n
+            let rd = (&r * &sk) % n;
+            let s = (&inv * ((&k + n - rd) % n)) % n;
+            if s.is_zero() {
+                continue;
+            }
+            return Ok(Signature::from_parts(
+                &r,
+                &s,
+                &ctx.serialize_pubkey(&pk, false)[1..],
+            ));
+        }
+    }
+
+    /// The signature of `pubkey` with `r` and `s`, which must be in
+    /// `[1, n - 1]`.
+    pub fn from_rs(r: &H256, s: &H256, pubkey: &PubKey) -> Result<Self, Error> {
+        let mut sig_bytes = [0u8; SIGNATURE_BYTES_LEN];
+        sig_bytes[..32].copy_from_slice(&r.0);
+        sig_bytes[32..64].copy_from_slice(&s.0);
+        sig_bytes[64..].copy_from_slice(&pubkey.0);
+        let sig = Signature(sig_bytes);
+        if sig.has_valid_rs() {
+            Ok(sig)
+        } else {
+            Err(Error::InvalidSignature)
+        }
+    }
+
+    pub fn r(&self) -> H256 {
+        H256::from_slice(self.r_bytes())
+    }
+
+    pub fn s(&self) -> H256 {
+        H256::from_slice(self.s_bytes())
+    }
+
     #[inline]
-    fn r(&self) -> &[u8] {
+    fn r_bytes(&self) -> &[u8] {
         &self.0[0..32]
     }
 
     #[inline]
-    fn s(&self) -> &[u8] {
+    fn s_bytes(&self) -> &[u8] {
         &self.0[32..64]
     }
 
@@ -41,6 +143,16 @@ impl Signature {
     fn pk(&self) -> &[u8] {
         &self.0[64..]
     }
+
+    /// Whether `r` and `s` are in `[1, n - 1]`, which libsm doesn't check.
+    fn has_valid_rs(&self) -> bool {
+        let curve = EccCtx::new();
+        let n = curve.get_n();
+        [self.r_bytes(), self.s_bytes()].iter().all(|bytes| {
+            let value = BigUint::from_bytes_be(bytes);
+            !value.is_zero() && value < *n
+        })
+    }
 }
 
 impl PartialEq for Signature {
@@ -51,11 +163,16 @@ impl PartialEq for Signature {
 
 impl Decodable for Signature {
     fn decode(rlp: &UntrustedRlp) -> Result<Self, DecoderError> {
-        rlp.decoder().decode_value(|bytes| {
-            let mut sig = [0u8; 128];
-            sig.copy_from_slice(bytes);
-            Ok(Signature(sig))
-        })
+        rlp.decoder()
+            .decode_value(|bytes| match bytes.len().cmp(&SIGNATURE_BYTES_LEN) {
+                cmp::Ordering::Less => Err(DecoderError::RlpIsTooShort),
+                cmp::Ordering::Greater => Err(DecoderError::RlpIsTooBig),
+                cmp::Ordering::Equal => {
+                    let mut sig = [0u8; SIGNATURE_BYTES_LEN];
+                    sig.copy_from_slice(bytes);
+                    Ok(Signature(sig))
+                }
+            })
     }
 }
 
@@ -119,8 +236,8 @@ impl Eq for Signature {}
 impl fmt::Debug for Signature {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
         f.debug_struct("Signature")
-            .field("r", &self.r().to_hex())
-            .field("s", &self.s().to_hex())
+            .field("r", &self.r_bytes().to_hex())
+            .field("s", &self.s_bytes().to_hex())
             .field("pk", &self.pk().to_hex())
             .finish()
     }
@@ -214,24 +331,23 @@ impl Sign for Signature {
 
     fn sign(privkey: &Self::PrivKey, message: &Self::Message) -> Result<Self, Error> {
         let ctx = SigCtx::new();
-        ctx.load_seckey(&privkey.0)
-            .map_err(|_| Error::RecoverError)
-            .map(|sk| {
-                let pk = ctx.pk_from_sk(&sk);
-                let signature = ctx.sign(&message, &sk, &pk);
-                let mut sig_bytes = [0u8; SIGNATURE_BYTES_LEN];
-                let r_bytes = signature.get_r().to_bytes_be();
-                let s_bytes = signature.get_s().to_bytes_be();
-                sig_bytes[32 - r_bytes.len()..32].copy_from_slice(&r_bytes[..]);
-                sig_bytes[64 - s_bytes.len()..64].copy_from_slice(&s_bytes[..]);
-                sig_bytes[64..].copy_from_slice(&ctx.serialize_pubkey(&pk, false)[1..]);
-                sig_bytes.into()
-            })
+        load_seckey(&ctx, privkey).map(|sk| {
+            let pk = ctx.pk_from_sk(&sk);
+            let signature = ctx.sign(&message, &sk, &pk);
+            Signature::from_parts(
+                signature.get_r(),
+                signature.get_s(),
+                &ctx.serialize_pubkey(&pk, false)[1..],
+            )
+        })
     }
 
     fn recover(&self, message: &Message) -> Result<Self::PubKey, Error> {
+        if !self.has_valid_rs() {
+            return Err(Error::RecoverError);
+        }
         let ctx = SigCtx::new();
-        let sig = Sm2Signature::new(self.r(), self.s());
+        let sig = Sm2Signature::new(self.r_bytes(), self.s_bytes());
         let mut pk_full = [0u8; 65];
         pk_full[0] = 4;
         pk_full[1..].copy_from_slice(self.pk());
@@ -248,9 +364,9 @@ impl Sign for Signature {
 
     fn verify_public(&self, pubkey: &Self::PubKey, message: &Self::Message) -> Result<bool, Error> {
         let pubkey_from_sig = PubKey::from(self.pk());
-        if pubkey_from_sig == *pubkey {
+        if pubkey_from_sig == *pubkey && self.has_valid_rs() {
             let ctx = SigCtx::new();
-            let sig = Sm2Signature::new(self.r(), self.s());
+            let sig = Sm2Signature::new(self.r_bytes(), self.s_bytes());
             let mut pk_full = [0u8; 65];
             pk_full[0] = 4;
             pk_full[1..].copy_from_slice(self.pk());
@@ -271,9 +387,14 @@ impl Sign for Signature {
 
 #[cfg(test)]
 mod tests {
-    use super::{Message, Signature};
+    use super::{Message, PrivKey, Signature, H256};
     use crate::keypair::KeyPair;
     use cita_crypto_trait::{CreateKey, Sign};
+    use rlp::{self, UntrustedRlp};
+    use rustc_serialize::hex::{FromHex, ToHex};
+    use std::str::FromStr;
+
+    const ORDER: &str = "fffffffeffffffffffffffffffffffff7203df6b21c6052b53bbf40939d54123";
 
     #[test]
     fn test_sign_verify() {
@@ -308,4 +429,104 @@ mod tests {
         let slice: &[u8] = sig.into();
         assert_eq!(Signature::from(slice), *sig);
     }
+
+    #[test]
+    fn test_sign_deterministic() {
+        // Private key of the GB/T 32918 example.
+        let privkey =
+            PrivKey::from_str("3945208f7b2144b13f36e38ac6d39f95889393692860b51a42fb81ef4df7c5b8")
+                .unwrap();
+        let keypair = KeyPair::from_privkey(privkey.clone()).unwrap();
+        assert_eq!(
+            keypair.pubkey().0.to_hex(),
+            "09f9df311e5421a150dd7d161e4bc5c672179fad1833fc076bb08ff356f35020\
+             ccea490ce26775a52dc6ea718cc1aa600aed05fbf35e084a6632f6072da9ad13"
+        );
+        let vectors = [
+            (
+                "0000000000000000000000000000000000000000000000000000000000000000",
+                "356e28e743de9c350eb324d2902c9d644c5ead5ae6431998a7a96b7c81a61906\
+                 7b74bd3bd56cf477cea786b31c0a324bf9dca9668676cb06d6f8ba6b90cc4b71",
+            ),
+            (
+                "a754189c5ec94e7345e88fc0cf249e3870ba40542409af2811ee120bae9f5e15",
+                "e35fe8753996ee8459d730fea14f1f51738e29202ce16c17c7d4996a37878738\
+                 701462013e7383254f7b37c989af54bf9d864d001b2b62fe042ca687d7e69f03",
+            ),
+        ];
+        for (msg, rs) in vectors.iter() {
+            let msg = Message::from_str(msg).unwrap();
+            let sig = Signature::sign_deterministic(&privkey, &msg).unwrap();
+            assert_eq!(sig.0[..64].to_hex(), *rs);
+            assert_eq!(&sig.0[64..], &keypair.pubkey().0[..]);
+            assert_eq!(sig, Signature::sign_deterministic(&privkey, &msg).unwrap());
+            assert!(sig.verify_public(keypair.pubkey(), &msg).unwrap());
+            assert_eq!(keypair.pubkey(), &sig.recover(&msg).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_malformed_input() {
+        let msg = Message::from(1);
+        let below_order =
+            PrivKey::from_str("fffffffeffffffffffffffffffffffff7203df6b21c6052b53bbf40939d54122")
+                .unwrap();
+        for privkey in &[
+            PrivKey::default(),
+            below_order,
+            PrivKey::from_str(ORDER).unwrap(),
+        ] {
+            assert!(KeyPair::from_privkey(privkey.clone()).is_err());
+            assert!(Signature::sign(privkey, &msg).is_err());
+            assert!(Signature::sign_deterministic(privkey, &msg).is_err());
+        }
+
+        // r or s out of range.
+        let keypair = KeyPair::gen_keypair();
+        let sig = Signature::sign(keypair.privkey(), &msg).unwrap();
+        let order = ORDER.from_hex().unwrap();
+        for offset in &[0, 32] {
+            for value in &[vec![0u8; 32], order.clone()] {
+                let mut bad = sig.clone();
+                bad.0[*offset..*offset + 32].copy_from_slice(value);
+                assert!(bad.recover(&msg).is_err());
+                assert!(!bad.verify_public(keypair.pubkey(), &msg).unwrap());
+            }
+        }
+
+        for len in &[0, 127, 129] {
+            let encoded = rlp::encode(&vec![1u8; *len]);
+            assert!(UntrustedRlp::new(&encoded).as_val::<Signature>().is_err());
+        }
+    }
+
+    #[test]
+    fn test_components() {
+        let keypair = KeyPair::gen_keypair();
+        let msg = Message::from(7);
+        let sig = Signature::sign(keypair.privkey(), &msg).unwrap();
+        assert_eq!(&sig.r().0[..], &sig.0[..32]);
+        assert_eq!(&sig.s().0[..], &sig.0[32..64]);
+
+        let rebuilt = Signature::from_rs(&sig.r(), &sig.s(), keypair.pubkey()).unwrap();
+        assert_eq!(rebuilt, sig);
+        assert!(rebuilt.verify_public(keypair.pubkey(), &msg).unwrap());
+        assert_eq!(keypair.pubkey(), &rebuilt.recover(&msg).unwrap());
+        // With another key the components don't verify.
+        let other = KeyPair::gen_keypair();
+        let misattributed = Signature::from_rs(&sig.r(), &sig.s(), other.pubkey()).unwrap();
+        assert!(!misattributed.verify_public(other.pubkey(), &msg).unwrap());
+
+        let order = H256::from_str(ORDER).unwrap();
+        let max = H256::from_str(&format!("{}2", &ORDER[..63])).unwrap();
+        assert!(Signature::from_rs(&max, &max, keypair.pubkey()).is_ok());
+        for bad in &[
+            H256::zero(),
+            order,
+            H256::from_str(&"f".repeat(64)).unwrap(),
+        ] {
+            assert!(Signature::from_rs(bad, &sig.s(), keypair.pubkey()).is_err());
+            assert!(Signature::from_rs(&sig.r(), bad, keypair.pubkey()).is_err());
+        }
+    }
 }
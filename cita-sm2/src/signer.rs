@@ -12,25 +12,57 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use super::{Address, KeyPair, PrivKey};
-use cita_crypto_trait::CreateKey;
+use super::{Address, Error, KeyPair, Message, PrivKey, Signature};
+use cita_crypto_trait::{CreateKey, Sign};
+
+/// How `Signer::sign` picks the per-signature nonce.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NonceMode {
+    /// From the RNG, as libsm does.
+    Random,
+    /// Derived from the key and message, see `Signature::sign_deterministic`.
+    Deterministic,
+}
+
+impl Default for NonceMode {
+    fn default() -> Self {
+        NonceMode::Random
+    }
+}
 
 #[derive(Default)]
 pub struct Signer {
     pub keypair: KeyPair,
     pub address: Address,
+    pub nonce_mode: NonceMode,
 }
 
-impl From<PrivKey> for Signer {
-    fn from(k: PrivKey) -> Self {
-        let keypair = KeyPair::from_privkey(k).unwrap();
+impl Signer {
+    pub fn with_nonce_mode(k: &PrivKey, nonce_mode: NonceMode) -> Self {
+        let keypair = KeyPair::from_privkey(k.clone()).unwrap();
         Signer {
             address: keypair.address(),
             keypair,
+            nonce_mode,
+        }
+    }
+
+    pub fn sign(&self, message: &Message) -> Result<Signature, Error> {
+        match self.nonce_mode {
+            NonceMode::Random => Signature::sign(self.keypair.privkey(), message),
+            NonceMode::Deterministic => {
+                Signature::sign_deterministic(self.keypair.privkey(), message)
+            }
         }
     }
 }
 
+impl<'a> From<&'a PrivKey> for Signer {
+    fn from(k: &'a PrivKey) -> Self {
+        Signer::with_nonce_mode(k, NonceMode::Random)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -39,9 +71,20 @@ mod tests {
     #[test]
     fn test_signer() {
         let keypair = KeyPair::gen_keypair();
-        let signer = Signer::from(keypair.privkey().clone());
+        let signer = Signer::from(keypair.privkey());
         assert_eq!(signer.keypair.privkey(), keypair.privkey());
         assert_eq!(signer.keypair.pubkey(), keypair.pubkey());
         assert_eq!(signer.address, keypair.address());
+        assert_eq!(signer.nonce_mode, NonceMode::Random);
+    }
+
+    #[test]
+    fn test_deterministic_signer() {
+        let keypair = KeyPair::gen_keypair();
+        let signer = Signer::with_nonce_mode(keypair.privkey(), NonceMode::Deterministic);
+        let msg = Message::default();
+        let sig = signer.sign(&msg).unwrap();
+        assert_eq!(sig, signer.sign(&msg).unwrap());
+        assert!(sig.verify_public(keypair.pubkey(), &msg).unwrap());
     }
 }
@@ -0,0 +1,522 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The nonces of the senders, as the chain and the pool know them.
+//!
+//! A sender's transactions go in nonce order. Once the chain has its nonce
+//! `n`, the next one it takes is `n + 1`, and the pool may hold a run of
+//! the ones after. A transaction is then
+//!
+//! ```text
+//! nonce <  on chain next                    Stale
+//! nonce <= next after the run of the pool   Ready
+//! otherwise                                 Gapped, from the next one
+//! ```
+//!
+//! One `NonceTracker` is meant to be shared, in an `Arc`, by auth and the
+//! pool, so they agree across reorgs: `revert_block` puts back the nonces
+//! from before a block, for the last `max_undo` blocks. Queries only take
+//! the read lock.
+//!
+//! Memory is bounded by `max_senders`. Past it, the senders which were
+//! least recently in a block or the pool are forgotten, but never one
+//! with nonces in the pool. A sender not tracked, never seen or forgotten,
+//! has no verdict: look its nonce up in the state, and `set_account` it.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::error::Error;
+use std::fmt;
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use quantity::BlockHeight;
+use Address;
+
+pub const DEFAULT_MAX_UNDO: usize = 64;
+pub const DEFAULT_MAX_SENDERS: usize = 100_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NonceVerdict {
+    Ready,
+    /// The nonces from `missing_from` on are missing before it.
+    Gapped {
+        missing_from: u64,
+    },
+    /// The chain has the nonce already.
+    Stale,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NonceError {
+    /// Blocks are observed in order, and reverted from the last one.
+    UnexpectedHeight {
+        expected: BlockHeight,
+        got: BlockHeight,
+    },
+    /// The undo log doesn't go back to `height` any more.
+    UndoUnavailable { height: BlockHeight },
+}
+
+impl fmt::Display for NonceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            NonceError::UnexpectedHeight { expected, got } => {
+                write!(f, "block at height {}, expected {}", got, expected)
+            }
+            NonceError::UndoUnavailable { height } => {
+                write!(f, "no undo log for the block at height {}", height)
+            }
+        }
+    }
+}
+
+impl Error for NonceError {}
+
+#[derive(Debug, Default)]
+struct Sender {
+    /// Next nonce the chain takes, none from the pool alone.
+    chain_next: Option<u64>,
+    pending: BTreeSet<u64>,
+    tick: u64,
+}
+
+impl Sender {
+    fn next_expected(&self) -> Option<u64> {
+        let mut next = self.chain_next?;
+        for &nonce in self.pending.range(next..) {
+            if nonce != next {
+                break;
+            }
+            next += 1;
+        }
+        Some(next)
+    }
+}
+
+/// The on chain next nonces a block changed, as they were before.
+#[derive(Debug)]
+struct Undo {
+    height: BlockHeight,
+    previous: Vec<(Address, Option<u64>)>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    senders: HashMap<Address, Sender>,
+    /// By the tick they were last active at, the oldest first.
+    recency: BTreeMap<u64, Address>,
+    tick: u64,
+    latest: Option<BlockHeight>,
+    undo: VecDeque<Undo>,
+}
+
+impl Inner {
+    /// The sender, now the most recently active.
+    fn touch(&mut self, address: Address) -> &mut Sender {
+        self.tick += 1;
+        let tick = self.tick;
+        let sender = self.senders.entry(address).or_default();
+        if sender.tick != 0 {
+            self.recency.remove(&sender.tick);
+        }
+        sender.tick = tick;
+        self.recency.insert(tick, address);
+        sender
+    }
+
+    fn forget(&mut self, address: &Address) {
+        if let Some(sender) = self.senders.remove(address) {
+            self.recency.remove(&sender.tick);
+        }
+    }
+
+    /// Forget inactive senders, the least recent first, down to `max`
+    /// if there are enough of them. The one just active stays.
+    fn evict(&mut self, max: usize) {
+        if self.senders.len() <= max {
+            return;
+        }
+        let excess = self.senders.len() - max;
+        let (senders, newest) = (&self.senders, self.tick);
+        let inactive: Vec<Address> = self
+            .recency
+            .iter()
+            .filter(|&(&tick, address)| tick != newest && senders[address].pending.is_empty())
+            .map(|(_, address)| *address)
+            .take(excess)
+            .collect();
+        for address in inactive {
+            self.forget(&address);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct NonceTracker {
+    max_undo: usize,
+    max_senders: usize,
+    inner: RwLock<Inner>,
+}
+
+impl Default for NonceTracker {
+    fn default() -> Self {
+        NonceTracker::new(DEFAULT_MAX_UNDO, DEFAULT_MAX_SENDERS)
+    }
+}
+
+impl NonceTracker {
+    pub fn new(max_undo: usize, max_senders: usize) -> Self {
+        NonceTracker {
+            max_undo,
+            max_senders,
+            inner: RwLock::new(Inner::default()),
+        }
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, Inner> {
+        // The state is consistent between calls, even when one panicked.
+        self.inner.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, Inner> {
+        self.inner.write().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// The last block observed and not reverted.
+    pub fn latest(&self) -> Option<BlockHeight> {
+        self.read().latest
+    }
+
+    /// The senders and nonces of the transactions of the block at
+    /// `height`, the one after the latest.
+    pub fn observe_block(
+        &self,
+        height: BlockHeight,
+        nonces: Vec<(Address, u64)>,
+    ) -> Result<(), NonceError> {
+        let mut inner = self.write();
+        if let Some(latest) = inner.latest {
+            if height != latest.next() {
+                return Err(NonceError::UnexpectedHeight {
+                    expected: latest.next(),
+                    got: height,
+                });
+            }
+        }
+
+        let mut previous: Vec<(Address, Option<u64>)> = Vec::new();
+        for (address, nonce) in nonces {
+            let sender = inner.touch(address);
+            if !previous.iter().any(|&(a, _)| a == address) {
+                previous.push((address, sender.chain_next));
+            }
+            let next = sender.chain_next.map_or(nonce + 1, |n| n.max(nonce + 1));
+            sender.chain_next = Some(next);
+            sender.pending = sender.pending.split_off(&next);
+        }
+
+        inner.latest = Some(height);
+        inner.undo.push_back(Undo { height, previous });
+        while inner.undo.len() > self.max_undo {
+            inner.undo.pop_front();
+        }
+        inner.evict(self.max_senders);
+        Ok(())
+    }
+
+    /// Take back the block at `height`, the latest, for a reorg. Its
+    /// transactions are for the pool to add again.
+    pub fn revert_block(&self, height: BlockHeight) -> Result<(), NonceError> {
+        let mut inner = self.write();
+        match inner.latest {
+            Some(latest) if latest == height => {}
+            Some(latest) => {
+                return Err(NonceError::UnexpectedHeight {
+                    expected: latest,
+                    got: height,
+                })
+            }
+            None => return Err(NonceError::UndoUnavailable { height }),
+        }
+        // The log ends with the latest block, when it has any.
+        let undo = match inner.undo.pop_back() {
+            Some(undo) => undo,
+            None => return Err(NonceError::UndoUnavailable { height }),
+        };
+        debug_assert_eq!(undo.height, height);
+
+        for (address, chain_next) in undo.previous {
+            // Unless forgotten since, then it is looked up again.
+            if let Some(sender) = inner.senders.get_mut(&address) {
+                sender.chain_next = chain_next;
+            }
+        }
+        inner.latest = height.0.checked_sub(1).map(BlockHeight);
+        Ok(())
+    }
+
+    /// The next nonce of `address` from the state, e.g. for a sender not
+    /// tracked. Not undone by `revert_block`.
+    pub fn set_account(&self, address: Address, next_nonce: u64) {
+        let mut inner = self.write();
+        let sender = inner.touch(address);
+        sender.chain_next = Some(next_nonce);
+        sender.pending = sender.pending.split_off(&next_nonce);
+        inner.evict(self.max_senders);
+    }
+
+    /// The pool holds `nonce` of `address`.
+    pub fn add_pending(&self, address: Address, nonce: u64) {
+        let mut inner = self.write();
+        inner.touch(address).pending.insert(nonce);
+        inner.evict(self.max_senders);
+    }
+
+    /// The pool dropped `nonce` of `address`.
+    pub fn remove_pending(&self, address: &Address, nonce: u64) {
+        let mut inner = self.write();
+        if let Some(sender) = inner.senders.get_mut(address) {
+            sender.pending.remove(&nonce);
+        }
+    }
+
+    /// The next nonce the chain takes from `address`.
+    pub fn chain_next(&self, address: &Address) -> Option<u64> {
+        self.read()
+            .senders
+            .get(address)
+            .and_then(|sender| sender.chain_next)
+    }
+
+    /// The nonce after those of the chain and the run of the pool.
+    pub fn next_expected(&self, address: &Address) -> Option<u64> {
+        self.read()
+            .senders
+            .get(address)
+            .and_then(Sender::next_expected)
+    }
+
+    /// None for a sender not tracked. A nonce the pool has already is
+    /// `Ready`, to replace it.
+    pub fn admissible(&self, address: &Address, tx_nonce: u64) -> Option<NonceVerdict> {
+        let inner = self.read();
+        let sender = inner.senders.get(address)?;
+        let chain_next = sender.chain_next?;
+        let next = sender.next_expected()?;
+        Some(if tx_nonce < chain_next {
+            NonceVerdict::Stale
+        } else if tx_nonce <= next {
+            NonceVerdict::Ready
+        } else {
+            NonceVerdict::Gapped { missing_from: next }
+        })
+    }
+
+    /// The senders tracked.
+    pub fn len(&self) -> usize {
+        self.read().senders.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.read().senders.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{NonceError, NonceTracker, NonceVerdict};
+    use std::sync::Arc;
+    use std::thread;
+    use {Address, BlockHeight};
+
+    fn sender(n: u64) -> Address {
+        Address::from(n)
+    }
+
+    #[test]
+    fn verdicts() {
+        let tracker = NonceTracker::default();
+        let a = sender(1);
+        assert_eq!(tracker.admissible(&a, 0), None);
+        tracker.set_account(a, 3);
+        assert_eq!(tracker.admissible(&a, 2), Some(NonceVerdict::Stale));
+        assert_eq!(tracker.admissible(&a, 3), Some(NonceVerdict::Ready));
+        assert_eq!(
+            tracker.admissible(&a, 5),
+            Some(NonceVerdict::Gapped { missing_from: 3 })
+        );
+
+        tracker.add_pending(a, 3);
+        tracker.add_pending(a, 4);
+        tracker.add_pending(a, 6);
+        assert_eq!(tracker.next_expected(&a), Some(5));
+        assert_eq!(tracker.admissible(&a, 4), Some(NonceVerdict::Ready));
+        assert_eq!(tracker.admissible(&a, 5), Some(NonceVerdict::Ready));
+        assert_eq!(
+            tracker.admissible(&a, 7),
+            Some(NonceVerdict::Gapped { missing_from: 5 })
+        );
+        tracker.add_pending(a, 5);
+        assert_eq!(tracker.next_expected(&a), Some(7));
+
+        tracker.remove_pending(&a, 4);
+        assert_eq!(tracker.next_expected(&a), Some(4));
+    }
+
+    #[test]
+    fn reorgs_revert() {
+        let tracker = NonceTracker::new(2, 100);
+        let (a, b) = (sender(1), sender(2));
+        tracker
+            .observe_block(BlockHeight(10), vec![(a, 0), (a, 1)])
+            .unwrap();
+        tracker.add_pending(a, 2);
+        tracker.add_pending(a, 3);
+        tracker
+            .observe_block(BlockHeight(11), vec![(a, 2), (b, 7)])
+            .unwrap();
+        assert_eq!(tracker.chain_next(&a), Some(3));
+        assert_eq!(tracker.next_expected(&a), Some(4));
+        assert_eq!(tracker.admissible(&a, 2), Some(NonceVerdict::Stale));
+        assert_eq!(tracker.chain_next(&b), Some(8));
+
+        assert_eq!(
+            tracker.observe_block(BlockHeight(13), vec![]),
+            Err(NonceError::UnexpectedHeight {
+                expected: BlockHeight(12),
+                got: BlockHeight(13)
+            })
+        );
+        assert_eq!(
+            tracker.revert_block(BlockHeight(10)),
+            Err(NonceError::UnexpectedHeight {
+                expected: BlockHeight(11),
+                got: BlockHeight(10)
+            })
+        );
+
+        tracker.revert_block(BlockHeight(11)).unwrap();
+        assert_eq!(tracker.latest(), Some(BlockHeight(10)));
+        assert_eq!(tracker.chain_next(&a), Some(2));
+        assert_eq!(tracker.chain_next(&b), None);
+        assert_eq!(tracker.admissible(&b, 7), None);
+        // The pool adds back what the block had.
+        tracker.add_pending(a, 2);
+        assert_eq!(tracker.next_expected(&a), Some(4));
+
+        // Another block at 11, on the new branch.
+        tracker
+            .observe_block(BlockHeight(11), vec![(a, 2), (a, 3)])
+            .unwrap();
+        assert_eq!(tracker.next_expected(&a), Some(4));
+        tracker.observe_block(BlockHeight(12), vec![]).unwrap();
+        tracker.observe_block(BlockHeight(13), vec![]).unwrap();
+
+        // The log keeps the last two blocks only.
+        tracker.revert_block(BlockHeight(13)).unwrap();
+        tracker.revert_block(BlockHeight(12)).unwrap();
+        assert_eq!(
+            tracker.revert_block(BlockHeight(11)),
+            Err(NonceError::UndoUnavailable {
+                height: BlockHeight(11)
+            })
+        );
+        assert_eq!(tracker.latest(), Some(BlockHeight(11)));
+    }
+
+    #[test]
+    fn eviction_keeps_active_senders() {
+        let tracker = NonceTracker::new(8, 3);
+        let pooled = sender(100);
+        tracker.set_account(pooled, 0);
+        tracker.add_pending(pooled, 0);
+        for n in 1..10 {
+            tracker.set_account(sender(n), n);
+        }
+        assert_eq!(tracker.len(), 3);
+        assert_eq!(tracker.next_expected(&pooled), Some(1));
+        // The most recent inactive ones are kept.
+        assert_eq!(tracker.chain_next(&sender(9)), Some(9));
+        assert_eq!(tracker.chain_next(&sender(8)), Some(8));
+        assert_eq!(tracker.admissible(&sender(1), 1), None);
+
+        // Active again is recent again.
+        tracker.set_account(sender(8), 8);
+        tracker.set_account(sender(20), 0);
+        assert_eq!(tracker.chain_next(&sender(8)), Some(8));
+        assert_eq!(tracker.chain_next(&sender(9)), None);
+
+        // Above the bound while everybody has nonces in the pool.
+        for n in 30..35 {
+            tracker.add_pending(sender(n), 0);
+        }
+        assert_eq!(tracker.len(), 6);
+        assert!(tracker.next_expected(&pooled).is_some());
+    }
+
+    #[test]
+    fn concurrent_observe_and_query() {
+        let tracker = Arc::new(NonceTracker::new(16, 64));
+        let senders: Vec<Address> = (1..=8).map(sender).collect();
+        for address in &senders {
+            tracker.set_account(*address, 0);
+        }
+
+        let writer = {
+            let tracker = Arc::clone(&tracker);
+            let senders = senders.clone();
+            thread::spawn(move || {
+                for height in 1..=500u64 {
+                    let nonces = senders.iter().map(|a| (*a, height - 1)).collect();
+                    tracker.observe_block(BlockHeight(height), nonces).unwrap();
+                    if height % 10 == 0 {
+                        tracker.revert_block(BlockHeight(height)).unwrap();
+                        let nonces = senders.iter().map(|a| (*a, height - 1)).collect();
+                        tracker.observe_block(BlockHeight(height), nonces).unwrap();
+                    }
+                }
+            })
+        };
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let tracker = Arc::clone(&tracker);
+                let senders = senders.clone();
+                thread::spawn(move || {
+                    for _ in 0..2_000 {
+                        for address in &senders {
+                            let next = tracker.chain_next(address).unwrap();
+                            match tracker.admissible(address, next) {
+                                Some(NonceVerdict::Ready) | Some(NonceVerdict::Stale) => {}
+                                // The block of `next` was reverted since.
+                                Some(NonceVerdict::Gapped { missing_from }) => {
+                                    assert_eq!(missing_from + 1, next)
+                                }
+                                None => panic!("{:?} forgotten", address),
+                            }
+                        }
+                    }
+                })
+            })
+            .collect();
+        writer.join().unwrap();
+        for reader in readers {
+            reader.join().unwrap();
+        }
+
+        assert_eq!(tracker.latest(), Some(BlockHeight(500)));
+        for address in &senders {
+            assert_eq!(tracker.next_expected(address), Some(500));
+            assert_eq!(tracker.admissible(address, 499), Some(NonceVerdict::Stale));
+        }
+    }
+}
@@ -0,0 +1,251 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! `U256` amounts for display.
+//!
+//! `as u64 as f64` saturates past `u64::MAX`. `to_f64_lossy` rounds to
+//! the nearest `f64` over the whole range, and `from_f64_lossy` refuses
+//! what no `U256` is. Amounts with decimals, like 18 for a token counted
+//! in wei, are best shown with `to_scaled_string`, which doesn't go
+//! through a float at all:
+//!
+//! ```text
+//! U256::from(1).to_scaled_string(18) == "0.000000000000000001"
+//! ```
+
+use std::error::Error;
+use std::fmt;
+
+use super::U256;
+
+/// Bits of an `f64` mantissa, the implicit one included.
+const MANTISSA_BITS: usize = 53;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FloatConvError {
+    NaN,
+    Infinite,
+    Negative,
+    /// At least 2^256.
+    Overflow,
+}
+
+impl fmt::Display for FloatConvError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let reason = match *self {
+            FloatConvError::NaN => "is NaN",
+            FloatConvError::Infinite => "is infinite",
+            FloatConvError::Negative => "is negative",
+            FloatConvError::Overflow => "doesn't fit 256 bits",
+        };
+        write!(f, "float {}", reason)
+    }
+}
+
+impl Error for FloatConvError {}
+
+pub trait LossyFloat: Sized {
+    /// The nearest `f64`, ties to even.
+    fn to_f64_lossy(&self) -> f64;
+    /// `value` without its fraction.
+    fn from_f64_lossy(value: f64) -> Result<Self, FloatConvError>;
+    /// In decimal, divided by `10^decimals` and with exactly `decimals`
+    /// fractional digits.
+    fn to_scaled_string(&self, decimals: u32) -> String;
+}
+
+impl LossyFloat for U256 {
+    fn to_f64_lossy(&self) -> f64 {
+        let bits = self.bits();
+        if bits <= 64 {
+            return self.low_u64() as f64;
+        }
+        // The top 64 bits round like the whole when the bits below them
+        // are folded into the lowest, which is below the rounding one.
+        let shift = bits - 64;
+        let mut top = (*self >> shift).low_u64();
+        if !(*self & ((U256::one() << shift) - U256::one())).is_zero() {
+            top |= 1;
+        }
+        (top as f64) * 2f64.powi(shift as i32)
+    }
+
+    fn from_f64_lossy(value: f64) -> Result<Self, FloatConvError> {
+        if value.is_nan() {
+            return Err(FloatConvError::NaN);
+        }
+        if value.is_infinite() {
+            return Err(FloatConvError::Infinite);
+        }
+        if value < 0.0 {
+            return Err(FloatConvError::Negative);
+        }
+        let bits = value.to_bits();
+        let exponent = ((bits >> 52) & 0x7ff) as i64;
+        if exponent == 0 {
+            // Zero or subnormal, below one either way.
+            return Ok(U256::zero());
+        }
+        let mantissa = (bits & ((1 << 52) - 1)) | (1 << 52);
+        // value = mantissa * 2^(exponent - 1075)
+        let exponent = exponent - 1075;
+        if exponent >= 0 {
+            let exponent = exponent as usize;
+            if exponent + MANTISSA_BITS > 256 {
+                return Err(FloatConvError::Overflow);
+            }
+            Ok(U256::from(mantissa) << exponent)
+        } else if exponent > -64 {
+            Ok(U256::from(mantissa >> -exponent))
+        } else {
+            Ok(U256::zero())
+        }
+    }
+
+    fn to_scaled_string(&self, decimals: u32) -> String {
+        let digits = self.to_string();
+        let decimals = decimals as usize;
+        if decimals == 0 {
+            return digits;
+        }
+        let digits = format!("{:0>width$}", digits, width = decimals + 1);
+        let (integer, fraction) = digits.split_at(digits.len() - decimals);
+        format!("{}.{}", integer, fraction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FloatConvError, LossyFloat};
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use std::str::FromStr;
+    use U256;
+
+    /// The standard library parses decimals to the nearest `f64`.
+    fn reference(value: U256) -> f64 {
+        f64::from_str(&value.to_string()).unwrap()
+    }
+
+    fn random(rng: &mut StdRng, bits: usize) -> U256 {
+        let words: Vec<u64> = (0..4).map(|_| rng.gen()).collect();
+        let value = U256([words[0], words[1], words[2], words[3]]);
+        if bits == 256 {
+            value
+        } else {
+            value & ((U256::one() << bits) - U256::one())
+        }
+    }
+
+    #[test]
+    fn to_f64_rounds_to_nearest() {
+        let two = U256::from(2);
+        let boundaries = vec![
+            U256::zero(),
+            U256::one(),
+            two.pow(53.into()) - U256::one(),
+            two.pow(53.into()),
+            two.pow(53.into()) + U256::one(),
+            two.pow(53.into()) + U256::from(3),
+            U256::from(u64::max_value()),
+            U256::from(u64::max_value()) + U256::one(),
+            // Half way between two floats, even down and odd up.
+            two.pow(64.into()) + two.pow(11.into()),
+            two.pow(64.into()) + two.pow(11.into()) * U256::from(3),
+            // Just above half way, only known from the low bits.
+            two.pow(100.into()) + two.pow(47.into()) + U256::one(),
+            U256::max_value(),
+        ];
+        for value in boundaries {
+            assert_eq!(value.to_f64_lossy(), reference(value), "{}", value);
+        }
+        assert_eq!(U256::max_value().to_f64_lossy(), 2f64.powi(256));
+
+        let mut rng = StdRng::from_seed([7u8; 32]);
+        for _ in 0..2000 {
+            let bits = rng.gen_range(1, 257);
+            let value = random(&mut rng, bits);
+            assert_eq!(value.to_f64_lossy(), reference(value), "{}", value);
+            if bits <= 64 {
+                assert_eq!(value.to_f64_lossy(), value.low_u64() as f64);
+            }
+        }
+    }
+
+    #[test]
+    fn from_f64_truncates_and_refuses() {
+        assert_eq!(U256::from_f64_lossy(0.0), Ok(U256::zero()));
+        assert_eq!(U256::from_f64_lossy(-0.0), Ok(U256::zero()));
+        assert_eq!(U256::from_f64_lossy(1e-310), Ok(U256::zero()));
+        assert_eq!(U256::from_f64_lossy(0.999), Ok(U256::zero()));
+        assert_eq!(U256::from_f64_lossy(42.9), Ok(U256::from(42)));
+        assert_eq!(
+            U256::from_f64_lossy(2f64.powi(64)),
+            Ok(U256::from(u64::max_value()) + U256::one())
+        );
+        assert_eq!(
+            U256::from_f64_lossy(2f64.powi(255) * 1.5),
+            Ok(U256::from(3) << 254)
+        );
+        assert_eq!(
+            U256::from_f64_lossy(2f64.powi(256)),
+            Err(FloatConvError::Overflow)
+        );
+        assert_eq!(
+            U256::from_f64_lossy(::std::f64::NAN),
+            Err(FloatConvError::NaN)
+        );
+        assert_eq!(
+            U256::from_f64_lossy(::std::f64::INFINITY),
+            Err(FloatConvError::Infinite)
+        );
+        assert_eq!(U256::from_f64_lossy(-1.0), Err(FloatConvError::Negative));
+        assert_eq!(FloatConvError::Negative.to_string(), "float is negative");
+
+        let mut rng = StdRng::from_seed([9u8; 32]);
+        for _ in 0..2000 {
+            let value: f64 = rng.gen::<f64>() * 2f64.powi(rng.gen_range(-4, 256));
+            let converted = U256::from_f64_lossy(value).unwrap();
+            let expected = U256::from_dec_str(&format!("{:.0}", value.trunc())).unwrap();
+            assert_eq!(converted, expected, "{}", value);
+            assert_eq!(converted.to_f64_lossy(), value.trunc());
+        }
+    }
+
+    #[test]
+    fn scaled_string() {
+        let wei = U256::one();
+        assert_eq!(wei.to_scaled_string(18), "0.000000000000000001");
+        assert_eq!(wei.to_scaled_string(0), "1");
+        assert_eq!(U256::zero().to_scaled_string(2), "0.00");
+
+        let ether = U256::from(10).pow(18.into());
+        assert_eq!(ether.to_scaled_string(18), "1.000000000000000000");
+        assert_eq!((ether - wei).to_scaled_string(18), "0.999999999999999999");
+        assert_eq!(
+            (ether * U256::from(1000) - wei).to_scaled_string(18),
+            "999.999999999999999999"
+        );
+        assert_eq!(
+            U256::max_value().to_scaled_string(18),
+            "115792089237316195423570985008687907853269984665640564039457.\
+             584007913129639935"
+        );
+        assert_eq!(
+            U256::max_value().to_scaled_string(80),
+            "0.00115792089237316195423570985008687907853269984665640564039457\
+             584007913129639935"
+        );
+    }
+}
@@ -0,0 +1,406 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Protocol upgrades, by the height they activate at.
+//!
+//! Rather than an `if height > SOME_CONST` of its own, a call site asks
+//! the schedule of the chain:
+//!
+//! ```text
+//! if schedule.active(Feature::ReceiptV2, height) { .. }
+//! ```
+//!
+//! The chain config has the forks in order, each turning its features on
+//! from its height on, for good:
+//!
+//! ```text
+//! [
+//!     {"height": 100, "features": ["chainIdV1"]},
+//!     {"height": 5000, "features": ["receiptV2", "proofV2"]}
+//! ]
+//! ```
+//!
+//! Heights must be strictly increasing. A feature this build doesn't know
+//! is an error, unless the schedule is loaded with its name allowed: a
+//! node of the release before reads the config of the next one, and keeps
+//! the names it can't do anything about.
+
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+use std::iter::FromIterator;
+
+use quantity::BlockHeight;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Feature {
+    /// Transactions carry a 32 bytes `chain_id_v1`.
+    ChainIdV1,
+    ReceiptV2,
+    ProofV2,
+}
+
+impl Feature {
+    pub const ALL: &'static [Feature] = &[Feature::ChainIdV1, Feature::ReceiptV2, Feature::ProofV2];
+
+    /// As the chain config has it.
+    pub fn name(self) -> &'static str {
+        match self {
+            Feature::ChainIdV1 => "chainIdV1",
+            Feature::ReceiptV2 => "receiptV2",
+            Feature::ProofV2 => "proofV2",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Feature> {
+        Feature::ALL
+            .iter()
+            .cloned()
+            .find(|feature| feature.name() == name)
+    }
+
+    fn bit(self) -> u64 {
+        1 << (self as u64)
+    }
+}
+
+impl fmt::Display for Feature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct FeatureSet(u64);
+
+impl FeatureSet {
+    pub fn empty() -> Self {
+        FeatureSet(0)
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn contains(self, feature: Feature) -> bool {
+        self.0 & feature.bit() != 0
+    }
+
+    pub fn insert(&mut self, feature: Feature) {
+        self.0 |= feature.bit();
+    }
+
+    pub fn union(self, other: FeatureSet) -> Self {
+        FeatureSet(self.0 | other.0)
+    }
+
+    /// In the order of `Feature::ALL`.
+    pub fn iter(self) -> impl Iterator<Item = Feature> {
+        Feature::ALL
+            .iter()
+            .cloned()
+            .filter(move |feature| self.contains(*feature))
+    }
+}
+
+impl FromIterator<Feature> for FeatureSet {
+    fn from_iter<I: IntoIterator<Item = Feature>>(iter: I) -> Self {
+        let mut set = FeatureSet::empty();
+        for feature in iter {
+            set.insert(feature);
+        }
+        set
+    }
+}
+
+impl fmt::Debug for FeatureSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
+
+/// One fork as the chain config has it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ForkConfig {
+    pub height: BlockHeight,
+    pub features: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fork {
+    pub height: BlockHeight,
+    pub features: FeatureSet,
+    /// Names allowed without being known to this build.
+    pub unknown: Vec<String>,
+}
+
+/// What to do with a feature this build doesn't know.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UnknownFeatures {
+    Reject,
+    /// Keep these names, reject others.
+    Allow(Vec<String>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ForkScheduleError {
+    NotIncreasing {
+        previous: BlockHeight,
+        height: BlockHeight,
+    },
+    UnknownFeature {
+        height: BlockHeight,
+        name: String,
+    },
+}
+
+impl fmt::Display for ForkScheduleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ForkScheduleError::NotIncreasing { previous, height } => write!(
+                f,
+                "fork at height {} isn't above the one at {}",
+                height, previous
+            ),
+            ForkScheduleError::UnknownFeature { height, ref name } => {
+                write!(f, "unknown feature {:?} at height {}", name, height)
+            }
+        }
+    }
+}
+
+impl Error for ForkScheduleError {}
+
+/// Deserialized from the chain config, which rejects unknown features.
+/// `ForkSchedule::new` takes the forks with an allowlist.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(try_from = "Vec<ForkConfig>", into = "Vec<ForkConfig>")]
+pub struct ForkSchedule {
+    forks: Vec<Fork>,
+}
+
+impl ForkSchedule {
+    pub fn new(
+        config: Vec<ForkConfig>,
+        unknown: &UnknownFeatures,
+    ) -> Result<Self, ForkScheduleError> {
+        let mut forks: Vec<Fork> = Vec::with_capacity(config.len());
+        for fork in config {
+            if let Some(previous) = forks.last() {
+                if fork.height <= previous.height {
+                    return Err(ForkScheduleError::NotIncreasing {
+                        previous: previous.height,
+                        height: fork.height,
+                    });
+                }
+            }
+            let mut features = FeatureSet::empty();
+            let mut unknown_names = Vec::new();
+            for name in fork.features {
+                match Feature::from_name(&name) {
+                    Some(feature) => features.insert(feature),
+                    None => match *unknown {
+                        UnknownFeatures::Allow(ref allowed) if allowed.contains(&name) => {
+                            unknown_names.push(name)
+                        }
+                        _ => {
+                            return Err(ForkScheduleError::UnknownFeature {
+                                height: fork.height,
+                                name,
+                            })
+                        }
+                    },
+                }
+            }
+            forks.push(Fork {
+                height: fork.height,
+                features,
+                unknown: unknown_names,
+            });
+        }
+        Ok(ForkSchedule { forks })
+    }
+
+    pub fn forks(&self) -> &[Fork] {
+        &self.forks
+    }
+
+    /// Those of the forks at `height` or below.
+    pub fn features_at<H: Into<BlockHeight>>(&self, height: H) -> FeatureSet {
+        let height = height.into();
+        self.forks
+            .iter()
+            .take_while(|fork| fork.height <= height)
+            .fold(FeatureSet::empty(), |set, fork| set.union(fork.features))
+    }
+
+    pub fn active<H: Into<BlockHeight>>(&self, feature: Feature, height: H) -> bool {
+        self.features_at(height).contains(feature)
+    }
+
+    /// The first height `feature` is active at, none if never.
+    pub fn activation(&self, feature: Feature) -> Option<BlockHeight> {
+        self.forks
+            .iter()
+            .find(|fork| fork.features.contains(feature))
+            .map(|fork| fork.height)
+    }
+}
+
+impl TryFrom<Vec<ForkConfig>> for ForkSchedule {
+    type Error = ForkScheduleError;
+
+    fn try_from(config: Vec<ForkConfig>) -> Result<Self, Self::Error> {
+        ForkSchedule::new(config, &UnknownFeatures::Reject)
+    }
+}
+
+impl From<ForkSchedule> for Vec<ForkConfig> {
+    fn from(schedule: ForkSchedule) -> Self {
+        schedule
+            .forks
+            .into_iter()
+            .map(|fork| ForkConfig {
+                height: fork.height,
+                features: fork
+                    .features
+                    .iter()
+                    .map(|feature| feature.name().to_owned())
+                    .chain(fork.unknown)
+                    .collect(),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        Feature, FeatureSet, ForkConfig, ForkSchedule, ForkScheduleError, UnknownFeatures,
+    };
+    use quantity::BlockHeight;
+    use serde_json;
+
+    fn config(forks: &[(u64, &[&str])]) -> Vec<ForkConfig> {
+        forks
+            .iter()
+            .map(|&(height, features)| ForkConfig {
+                height: BlockHeight(height),
+                features: features.iter().map(|name| (*name).to_owned()).collect(),
+            })
+            .collect()
+    }
+
+    fn schedule(forks: &[(u64, &[&str])]) -> ForkSchedule {
+        ForkSchedule::new(config(forks), &UnknownFeatures::Reject).unwrap()
+    }
+
+    #[test]
+    fn features_activate_at_their_height() {
+        let schedule = schedule(&[
+            (0, &[]),
+            (100, &["chainIdV1"]),
+            (5000, &["receiptV2", "proofV2"]),
+        ]);
+        assert!(schedule.features_at(0u64).is_empty());
+        assert!(!schedule.active(Feature::ChainIdV1, 99u64));
+        assert!(schedule.active(Feature::ChainIdV1, 100u64));
+        assert!(schedule.active(Feature::ChainIdV1, 101u64));
+        assert!(!schedule.active(Feature::ReceiptV2, 4999u64));
+        assert!(schedule.active(Feature::ReceiptV2, BlockHeight(5000)));
+        assert!(schedule.active(Feature::ProofV2, 5001u64));
+        assert_eq!(
+            schedule.features_at(::std::u64::MAX),
+            Feature::ALL.iter().cloned().collect::<FeatureSet>()
+        );
+        assert_eq!(
+            schedule.activation(Feature::ReceiptV2),
+            Some(BlockHeight(5000))
+        );
+
+        let empty = ForkSchedule::default();
+        assert!(!empty.active(Feature::ChainIdV1, ::std::u64::MAX));
+        assert_eq!(empty.activation(Feature::ChainIdV1), None);
+    }
+
+    #[test]
+    fn heights_must_increase() {
+        for forks in &[
+            vec![(100, &["chainIdV1"][..]), (100, &["receiptV2"][..])],
+            vec![(100, &["chainIdV1"][..]), (99, &["receiptV2"][..])],
+        ] {
+            assert_eq!(
+                ForkSchedule::new(config(forks), &UnknownFeatures::Reject),
+                Err(ForkScheduleError::NotIncreasing {
+                    previous: BlockHeight(100),
+                    height: BlockHeight(forks[1].0),
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn config_parsing() {
+        let json = concat!(
+            r#"[{"height":100,"features":["chainIdV1"]},"#,
+            r#"{"height":5000,"features":["receiptV2","proofV2"]}]"#
+        );
+        let schedule: ForkSchedule = serde_json::from_str(json).unwrap();
+        assert!(schedule.active(Feature::ProofV2, 5000u64));
+        assert_eq!(serde_json::to_string(&schedule).unwrap(), json);
+
+        let failures = vec![
+            (
+                r#"[{"height":100,"features":["chainIdV3"]}]"#,
+                "unknown feature \"chainIdV3\" at height 100",
+            ),
+            (
+                r#"[{"height":100,"features":[]},{"height":50,"features":[]}]"#,
+                "fork at height 50 isn't above the one at 100",
+            ),
+            (r#"[{"height":-1,"features":[]}]"#, "invalid value"),
+            (r#"[{"height":100}]"#, "missing field `features`"),
+            (r#"{"height":100,"features":[]}"#, "invalid type"),
+        ];
+        for (json, message) in failures {
+            let err = serde_json::from_str::<ForkSchedule>(json).unwrap_err();
+            assert!(err.to_string().contains(message), "{}: {}", json, err);
+        }
+    }
+
+    #[test]
+    fn unknown_features_by_allowlist() {
+        let forks = config(&[(100, &["chainIdV1", "stateV3"]), (200, &["blockV4"])]);
+        let allowed = UnknownFeatures::Allow(vec!["stateV3".to_owned(), "blockV4".to_owned()]);
+        let schedule = ForkSchedule::new(forks.clone(), &allowed).unwrap();
+        assert_eq!(
+            schedule.features_at(200u64),
+            [Feature::ChainIdV1].iter().cloned().collect::<FeatureSet>()
+        );
+        assert_eq!(schedule.forks()[0].unknown, vec!["stateV3".to_owned()]);
+        assert_eq!(schedule.forks()[1].unknown, vec!["blockV4".to_owned()]);
+        // Written back as read.
+        assert_eq!(Vec::<ForkConfig>::from(schedule), forks);
+
+        let partly = UnknownFeatures::Allow(vec!["stateV3".to_owned()]);
+        assert_eq!(
+            ForkSchedule::new(forks.clone(), &partly),
+            Err(ForkScheduleError::UnknownFeature {
+                height: BlockHeight(200),
+                name: "blockV4".to_owned(),
+            })
+        );
+        assert!(ForkSchedule::new(forks, &UnknownFeatures::Reject).is_err());
+    }
+}
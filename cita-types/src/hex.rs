@@ -0,0 +1,358 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Hashes and bytes from hex strings, as users write them.
+//!
+//! `FromStr` of the hash types is the one of `ethereum-types`, which takes
+//! the bare digits only and has a single error for any mistake. Here a
+//! `0x` or `0X` prefix is optional, the digits may be of any case, and
+//! the error says what is wrong and where:
+//!
+//! ```text
+//! H256::from_hex_str("0xAB..")   // Ok
+//! H160::from_hex_str("0x12")     // invalid length 2, expected 40 hex digits
+//! H160::from_hex_str("0x12g4..") // invalid character 'g' at index 4
+//! ```
+//!
+//! The index counts from the start of the string, prefix included.
+
+use std::error::Error;
+use std::fmt;
+
+use super::{Bloom, H128, H160, H256, H264, H32, H512, H520, H64};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FromHexErrorKind {
+    /// Of a hash, in hex digits.
+    InvalidLength {
+        expected: usize,
+        got: usize,
+    },
+    InvalidChar {
+        index: usize,
+        char: char,
+    },
+    /// Of bytes, which are two digits each.
+    OddLength,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FromHexError {
+    kind: FromHexErrorKind,
+}
+
+impl FromHexError {
+    pub fn kind(&self) -> FromHexErrorKind {
+        self.kind
+    }
+}
+
+impl From<FromHexErrorKind> for FromHexError {
+    fn from(kind: FromHexErrorKind) -> Self {
+        FromHexError { kind }
+    }
+}
+
+impl fmt::Display for FromHexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            FromHexErrorKind::InvalidLength { expected, got } => write!(
+                f,
+                "invalid length {}, expected {} hex digits",
+                got, expected
+            ),
+            FromHexErrorKind::InvalidChar { index, char } => {
+                write!(f, "invalid character {:?} at index {}", char, index)
+            }
+            FromHexErrorKind::OddLength => write!(f, "odd number of hex digits"),
+        }
+    }
+}
+
+impl Error for FromHexError {}
+
+/// `s` without its `0x` or `0X` prefix, none when it has none.
+pub fn strip_0x(s: &str) -> Option<&str> {
+    match s.get(..2) {
+        Some("0x") | Some("0X") => Some(&s[2..]),
+        _ => None,
+    }
+}
+
+/// The bytes of `s`, with or without prefix.
+pub fn decode_hex(s: &str) -> Result<Vec<u8>, FromHexError> {
+    let (offset, digits) = split_prefix(s);
+    let nibbles = nibbles(offset, digits)?;
+    if nibbles.len() % 2 != 0 {
+        return Err(FromHexErrorKind::OddLength.into());
+    }
+    Ok(pack(&nibbles))
+}
+
+pub trait FromHexStr: Sized {
+    /// With or without prefix.
+    fn from_hex_str(s: &str) -> Result<Self, FromHexError>;
+
+    /// Without prefix, where a `0x` must be an error, like `FromStr`
+    /// with its better errors.
+    fn from_str_strict(s: &str) -> Result<Self, FromHexError>;
+}
+
+fn split_prefix(s: &str) -> (usize, &str) {
+    match strip_0x(s) {
+        Some(digits) => (2, digits),
+        None => (0, s),
+    }
+}
+
+/// The value of each digit, or the first which isn't one.
+fn nibbles(offset: usize, digits: &str) -> Result<Vec<u8>, FromHexError> {
+    digits
+        .char_indices()
+        .map(|(index, c)| {
+            c.to_digit(16).map(|d| d as u8).ok_or_else(|| {
+                FromHexErrorKind::InvalidChar {
+                    index: offset + index,
+                    char: c,
+                }
+                .into()
+            })
+        })
+        .collect()
+}
+
+fn decode_fixed(offset: usize, digits: &str, bytes: usize) -> Result<Vec<u8>, FromHexError> {
+    let nibbles = nibbles(offset, digits)?;
+    if nibbles.len() != bytes * 2 {
+        return Err(FromHexErrorKind::InvalidLength {
+            expected: bytes * 2,
+            got: nibbles.len(),
+        }
+        .into());
+    }
+    Ok(pack(&nibbles))
+}
+
+fn pack(nibbles: &[u8]) -> Vec<u8> {
+    nibbles
+        .chunks(2)
+        .map(|pair| (pair[0] << 4) | pair[1])
+        .collect()
+}
+
+macro_rules! impl_from_hex_str {
+    ($( ($name:ident, $bytes:expr) ),+) => {
+        $(
+            impl FromHexStr for $name {
+                fn from_hex_str(s: &str) -> Result<Self, FromHexError> {
+                    let (offset, digits) = split_prefix(s);
+                    decode_fixed(offset, digits, $bytes).map(|bytes| $name::from_slice(&bytes))
+                }
+
+                fn from_str_strict(s: &str) -> Result<Self, FromHexError> {
+                    decode_fixed(0, s, $bytes).map(|bytes| $name::from_slice(&bytes))
+                }
+            }
+        )+
+    };
+}
+
+impl_from_hex_str!(
+    (Bloom, 256),
+    (H32, 4),
+    (H64, 8),
+    (H128, 16),
+    (H160, 20),
+    (H256, 32),
+    (H264, 33),
+    (H512, 64),
+    (H520, 65)
+);
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_hex, strip_0x, FromHexErrorKind, FromHexStr, H160, H256};
+    use std::str::FromStr;
+
+    const DIGITS: &str = "ed76641c68a1c641aee09a94b3b471f4dc0316efe5ac19cf488e2674cf8d05b5";
+
+    fn kind<T: FromHexStr>(s: &str) -> FromHexErrorKind {
+        match T::from_hex_str(s) {
+            Ok(_) => panic!("{} parsed", s),
+            Err(err) => err.kind(),
+        }
+    }
+
+    #[test]
+    fn prefix_and_case() {
+        let expected = H256::from_str(DIGITS).unwrap();
+        let upper = DIGITS.to_uppercase();
+        let mixed: String = DIGITS
+            .chars()
+            .enumerate()
+            .map(|(i, c)| {
+                if i % 2 == 0 {
+                    c.to_ascii_uppercase()
+                } else {
+                    c
+                }
+            })
+            .collect();
+        for digits in &[DIGITS, upper.as_str(), mixed.as_str()] {
+            for prefix in &["", "0x", "0X"] {
+                let s = format!("{}{}", prefix, digits);
+                assert_eq!(H256::from_hex_str(&s), Ok(expected), "{}", s);
+                assert_eq!(
+                    H256::from_str_strict(&s).is_ok(),
+                    prefix.is_empty(),
+                    "{}",
+                    s
+                );
+            }
+        }
+        // As the strict parser sees a prefix.
+        assert_eq!(
+            H256::from_str_strict(&format!("0x{}", &DIGITS[2..]))
+                .unwrap_err()
+                .kind(),
+            FromHexErrorKind::InvalidChar {
+                index: 1,
+                char: 'x'
+            }
+        );
+        assert_eq!(
+            H160::from_hex_str(&format!("0x{}", &DIGITS[..40])),
+            Ok(H160::from_str(&DIGITS[..40]).unwrap())
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let cases = vec![
+            (
+                "",
+                FromHexErrorKind::InvalidLength {
+                    expected: 40,
+                    got: 0,
+                },
+            ),
+            (
+                "0x",
+                FromHexErrorKind::InvalidLength {
+                    expected: 40,
+                    got: 0,
+                },
+            ),
+            (
+                "0x12",
+                FromHexErrorKind::InvalidLength {
+                    expected: 40,
+                    got: 2,
+                },
+            ),
+            (
+                &DIGITS[..39],
+                FromHexErrorKind::InvalidLength {
+                    expected: 40,
+                    got: 39,
+                },
+            ),
+            (
+                &DIGITS[..41],
+                FromHexErrorKind::InvalidLength {
+                    expected: 40,
+                    got: 41,
+                },
+            ),
+            (
+                "0x0x12",
+                FromHexErrorKind::InvalidChar {
+                    index: 3,
+                    char: 'x',
+                },
+            ),
+            (
+                "x12",
+                FromHexErrorKind::InvalidChar {
+                    index: 0,
+                    char: 'x',
+                },
+            ),
+            (
+                "0x12g4",
+                FromHexErrorKind::InvalidChar {
+                    index: 4,
+                    char: 'g',
+                },
+            ),
+            (
+                " 0x12",
+                FromHexErrorKind::InvalidChar {
+                    index: 0,
+                    char: ' ',
+                },
+            ),
+            (
+                "0x12é4",
+                FromHexErrorKind::InvalidChar {
+                    index: 4,
+                    char: 'é',
+                },
+            ),
+        ];
+        for (s, expected) in cases {
+            assert_eq!(kind::<H160>(s), expected, "{:?}", s);
+        }
+        assert_eq!(
+            kind::<H256>(&format!("0x{}z", DIGITS)),
+            FromHexErrorKind::InvalidChar {
+                index: 66,
+                char: 'z'
+            }
+        );
+    }
+
+    #[test]
+    fn error_messages() {
+        let message = |s: &str| H160::from_hex_str(s).unwrap_err().to_string();
+        assert_eq!(message("0x12"), "invalid length 2, expected 40 hex digits");
+        assert_eq!(message("0x12g4"), "invalid character 'g' at index 4");
+        assert_eq!(
+            decode_hex("0x123").unwrap_err().to_string(),
+            "odd number of hex digits"
+        );
+    }
+
+    #[test]
+    fn bytes() {
+        assert_eq!(decode_hex(""), Ok(vec![]));
+        assert_eq!(decode_hex("0x"), Ok(vec![]));
+        assert_eq!(decode_hex("0XaBcD"), Ok(vec![0xab, 0xcd]));
+        assert_eq!(decode_hex("0123"), Ok(vec![0x01, 0x23]));
+        assert_eq!(
+            decode_hex("0x123").unwrap_err().kind(),
+            FromHexErrorKind::OddLength
+        );
+        assert_eq!(
+            decode_hex("0x12zz").unwrap_err().kind(),
+            FromHexErrorKind::InvalidChar {
+                index: 4,
+                char: 'z'
+            }
+        );
+        assert_eq!(strip_0x("0X12"), Some("12"));
+        assert_eq!(strip_0x("12"), None);
+        assert_eq!(strip_0x(""), None);
+    }
+}
@@ -14,16 +14,34 @@
 
 extern crate ethereum_types;
 extern crate plain_hasher;
+#[cfg(any(test, feature = "rand"))]
+extern crate rand;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+#[cfg(test)]
+extern crate serde_json;
 
 use std::collections::{HashMap, HashSet};
 use std::hash;
 
+pub mod account_nonce;
+pub mod clock;
+pub mod float;
+pub mod forks;
+pub mod hex;
+pub mod log_index;
+pub mod quantity;
+#[cfg(feature = "rand")]
+pub mod random;
 pub mod traits;
+pub mod tx_validity;
 
 pub use ethereum_types::{Bloom, BloomInput, BloomRef};
 pub use ethereum_types::{H128, H160, H256, H264, H32, H512, H520, H64};
 pub use ethereum_types::{U128, U256, U512, U64};
 pub use plain_hasher::PlainHasher;
+pub use quantity::{BlockHeight, HeightDiff, Quota, TxIndex};
 
 pub type Address = H160;
 
@@ -52,9 +70,85 @@ pub fn delete_left0(s: &str) -> &str {
 
 #[inline]
 pub fn clean_0x(s: &str) -> &str {
-    if s.starts_with("0x") {
-        &s[2..]
-    } else {
-        s
+    hex::strip_0x(s).unwrap_or(s)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{H256FastMap, H256FastSet, H256};
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use std::collections::{HashMap, HashSet};
+
+    #[test]
+    fn fast_map_keeps_colliding_keys_apart() {
+        // `PlainHasher` folds the four 8-byte words of a hash together,
+        // so swapping the middle words yields the same hasher output.
+        let mut bytes1 = [0u8; 32];
+        let mut bytes2 = [0u8; 32];
+        for i in 0..8 {
+            bytes1[i] = 0x11;
+            bytes1[8 + i] = 0x22;
+            bytes1[16 + i] = 0x33;
+            bytes1[24 + i] = 0x44;
+        }
+        bytes2.copy_from_slice(&bytes1);
+        for i in 0..8 {
+            bytes2.swap(8 + i, 16 + i);
+        }
+        let key1 = H256::from(bytes1);
+        let key2 = H256::from(bytes2);
+        assert_eq!(key1[..8], key2[..8]);
+        assert_ne!(key1, key2);
+
+        let mut map = H256FastMap::default();
+        map.insert(key1, 1);
+        map.insert(key2, 2);
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.get(&key1), Some(&1));
+        assert_eq!(map.get(&key2), Some(&2));
+
+        let mut set = H256FastSet::default();
+        assert!(set.insert(key1));
+        assert!(set.insert(key2));
+        assert!(!set.insert(key1));
+    }
+
+    #[test]
+    fn fast_map_matches_std_map() {
+        let mut rng = StdRng::from_seed([42u8; 32]);
+        let mut fast = H256FastMap::default();
+        let mut std_map = HashMap::new();
+        let mut seen = Vec::new();
+
+        for i in 0..10_000u32 {
+            let op = rng.gen_range(0, 3);
+            // Reuse old keys now and then so removals and overwrites happen.
+            let key = if !seen.is_empty() && rng.gen_range(0, 4) == 0 {
+                seen[rng.gen_range(0, seen.len())]
+            } else {
+                let mut bytes = [0u8; 32];
+                rng.fill(&mut bytes);
+                let key = H256::from(bytes);
+                seen.push(key);
+                key
+            };
+            match op {
+                0 | 1 => {
+                    assert_eq!(fast.insert(key, i), std_map.insert(key, i));
+                }
+                _ => {
+                    assert_eq!(fast.remove(&key), std_map.remove(&key));
+                }
+            }
+        }
+
+        assert_eq!(fast.len(), std_map.len());
+        for (key, value) in std_map.iter() {
+            assert_eq!(fast.get(key), Some(value));
+        }
+        let fast_keys: HashSet<H256> = fast.keys().cloned().collect();
+        let std_keys: HashSet<H256> = std_map.keys().cloned().collect();
+        assert_eq!(fast_keys, std_keys);
     }
 }
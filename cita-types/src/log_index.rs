@@ -0,0 +1,556 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Per-segment index from log topics and addresses to the blocks using them.
+//!
+//! Blocks are grouped into segments of `segment_size` heights. For every
+//! segment the index keeps, per topic and per contract address, the set of
+//! block offsets which have at least one log mentioning it. Topics are
+//! indexed without their position, so `candidate_blocks` may return blocks
+//! which don't match, but never misses one which does.
+//!
+//! Segments are stored as key/value entries, meant for a kvdb column:
+//! the key is `segment (u64 BE) ++ tag ++ topic or address`, the value the
+//! encoded `BlockSet`.
+
+use super::{Address, H256FastMap, H256};
+use std::cmp;
+use std::collections::{BTreeMap, HashMap};
+
+pub const DEFAULT_SEGMENT_SIZE: u64 = 4096;
+
+const TAG_BLOCKS: u8 = b'b';
+const TAG_TOPIC: u8 = b't';
+const TAG_ADDRESS: u8 = b'a';
+
+/// Sorted set of block offsets, stored as runs of consecutive offsets.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BlockSet {
+    // (first offset, run length), sorted and neither overlapping nor adjacent.
+    runs: Vec<(u32, u32)>,
+}
+
+impl BlockSet {
+    pub fn new() -> Self {
+        BlockSet::default()
+    }
+
+    pub fn insert(&mut self, offset: u32) {
+        let idx = match self.runs.binary_search_by(|&(start, _)| start.cmp(&offset)) {
+            Ok(_) => return,
+            Err(idx) => idx,
+        };
+        if idx > 0 {
+            let (start, len) = self.runs[idx - 1];
+            if offset < start + len {
+                return;
+            }
+            if offset == start + len {
+                self.runs[idx - 1].1 += 1;
+                if idx < self.runs.len() && self.runs[idx].0 == offset + 1 {
+                    self.runs[idx - 1].1 += self.runs[idx].1;
+                    self.runs.remove(idx);
+                }
+                return;
+            }
+        }
+        if idx < self.runs.len() && self.runs[idx].0 == offset + 1 {
+            self.runs[idx].0 = offset;
+            self.runs[idx].1 += 1;
+        } else {
+            self.runs.insert(idx, (offset, 1));
+        }
+    }
+
+    pub fn contains(&self, offset: u32) -> bool {
+        match self.runs.binary_search_by(|&(start, _)| start.cmp(&offset)) {
+            Ok(_) => true,
+            Err(0) => false,
+            Err(idx) => {
+                let (start, len) = self.runs[idx - 1];
+                offset < start + len
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.runs.iter().map(|&(_, len)| len as usize).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.runs.is_empty()
+    }
+
+    pub fn iter<'a>(&'a self) -> impl Iterator<Item = u32> + 'a {
+        self.runs
+            .iter()
+            .flat_map(|&(start, len)| start..start + len)
+    }
+
+    pub fn intersect(&self, other: &BlockSet) -> BlockSet {
+        let mut runs = Vec::new();
+        let (mut i, mut j) = (0, 0);
+        while i < self.runs.len() && j < other.runs.len() {
+            let (a_start, a_len) = self.runs[i];
+            let (b_start, b_len) = other.runs[j];
+            let (a_end, b_end) = (a_start + a_len, b_start + b_len);
+            let start = cmp::max(a_start, b_start);
+            let end = cmp::min(a_end, b_end);
+            if start < end {
+                runs.push((start, end - start));
+            }
+            if a_end < b_end {
+                i += 1;
+            } else {
+                j += 1;
+            }
+        }
+        BlockSet { runs }
+    }
+
+    pub fn union(&self, other: &BlockSet) -> BlockSet {
+        let mut all: Vec<(u32, u32)> = self.runs.iter().chain(other.runs.iter()).cloned().collect();
+        all.sort();
+        let mut runs: Vec<(u32, u32)> = Vec::with_capacity(all.len());
+        for (start, len) in all {
+            if let Some(last) = runs.last_mut() {
+                let last_end = last.0 + last.1;
+                if start <= last_end {
+                    last.1 = cmp::max(last_end, start + len) - last.0;
+                    continue;
+                }
+            }
+            runs.push((start, len));
+        }
+        BlockSet { runs }
+    }
+
+    /// Each run as two little endian u32.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.runs.len() * 8);
+        for &(start, len) in &self.runs {
+            bytes.extend_from_slice(&start.to_le_bytes());
+            bytes.extend_from_slice(&len.to_le_bytes());
+        }
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Option<BlockSet> {
+        if bytes.len() % 8 != 0 {
+            return None;
+        }
+        let mut runs: Vec<(u32, u32)> = Vec::with_capacity(bytes.len() / 8);
+        for chunk in bytes.chunks(8) {
+            let mut start = [0u8; 4];
+            let mut len = [0u8; 4];
+            start.copy_from_slice(&chunk[..4]);
+            len.copy_from_slice(&chunk[4..]);
+            let (start, len) = (u32::from_le_bytes(start), u32::from_le_bytes(len));
+            // The runs before were checked, so only this end can overflow.
+            if len == 0
+                || start.checked_add(len).is_none()
+                || runs.last().map_or(false, |&(s, l)| start <= s + l)
+            {
+                return None;
+            }
+            runs.push((start, len));
+        }
+        Some(BlockSet { runs })
+    }
+}
+
+/// Which logs to look for, with the usual filter semantics: `None` matches
+/// anything, a list matches any of its entries.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct IndexFilter {
+    pub addresses: Option<Vec<Address>>,
+    pub topics: Vec<Option<Vec<H256>>>,
+}
+
+/// Returned by `load_entries` for an entry it can't decode.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidEntry;
+
+#[derive(Debug, Clone, Default)]
+struct Segment {
+    // Every block with at least one log.
+    blocks: BlockSet,
+    topics: H256FastMap<BlockSet>,
+    addresses: HashMap<Address, BlockSet>,
+}
+
+impl Segment {
+    fn candidates(&self, filter: &IndexFilter) -> BlockSet {
+        let mut result = self.blocks.clone();
+        if let Some(ref addresses) = filter.addresses {
+            let matching = addresses
+                .iter()
+                .filter_map(|address| self.addresses.get(address))
+                .fold(BlockSet::new(), |acc, set| acc.union(set));
+            result = result.intersect(&matching);
+        }
+        for topics in filter.topics.iter().filter_map(|topics| topics.as_ref()) {
+            let matching = topics
+                .iter()
+                .filter_map(|topic| self.topics.get(topic))
+                .fold(BlockSet::new(), |acc, set| acc.union(set));
+            result = result.intersect(&matching);
+        }
+        result
+    }
+}
+
+pub struct TopicIndex {
+    segment_size: u64,
+    segments: BTreeMap<u64, Segment>,
+}
+
+impl Default for TopicIndex {
+    fn default() -> Self {
+        TopicIndex::new(DEFAULT_SEGMENT_SIZE)
+    }
+}
+
+impl TopicIndex {
+    pub fn new(segment_size: u64) -> Self {
+        assert!(segment_size > 0 && segment_size <= u64::from(u32::max_value()));
+        TopicIndex {
+            segment_size,
+            segments: BTreeMap::new(),
+        }
+    }
+
+    pub fn segment_size(&self) -> u64 {
+        self.segment_size
+    }
+
+    pub fn segment_of(&self, height: u64) -> u64 {
+        height / self.segment_size
+    }
+
+    /// Index the logs of the block at `height`, given as
+    /// (contract address, topics) pairs.
+    pub fn insert_block(&mut self, height: u64, logs: &[(Address, Vec<H256>)]) {
+        if logs.is_empty() {
+            return;
+        }
+        let offset = (height % self.segment_size) as u32;
+        let segment = self
+            .segments
+            .entry(height / self.segment_size)
+            .or_insert_with(Segment::default);
+        segment.blocks.insert(offset);
+        for &(ref address, ref topics) in logs {
+            segment
+                .addresses
+                .entry(*address)
+                .or_insert_with(BlockSet::new)
+                .insert(offset);
+            for topic in topics {
+                segment
+                    .topics
+                    .entry(*topic)
+                    .or_insert_with(BlockSet::new)
+                    .insert(offset);
+            }
+        }
+    }
+
+    /// Heights in `[from, to]` which may hold logs matching `filter`.
+    pub fn candidate_blocks(&self, filter: &IndexFilter, from: u64, to: u64) -> Vec<u64> {
+        if from > to {
+            return Vec::new();
+        }
+        let mut heights = Vec::new();
+        for (number, segment) in self
+            .segments
+            .range(self.segment_of(from)..=self.segment_of(to))
+        {
+            let base = number * self.segment_size;
+            heights.extend(
+                segment
+                    .candidates(filter)
+                    .iter()
+                    .map(|offset| base + u64::from(offset))
+                    .filter(|height| *height >= from && *height <= to),
+            );
+        }
+        heights
+    }
+
+    /// Drop a segment, e.g. before rebuilding it after a reorg.
+    pub fn remove_segment(&mut self, segment: u64) {
+        self.segments.remove(&segment);
+    }
+
+    /// Replace a segment with the given blocks. Heights outside the segment
+    /// are ignored.
+    pub fn rebuild_segment<'a, I>(&mut self, segment: u64, blocks: I)
+    where
+        I: IntoIterator<Item = (u64, &'a [(Address, Vec<H256>)])>,
+    {
+        self.remove_segment(segment);
+        for (height, logs) in blocks {
+            if self.segment_of(height) == segment {
+                self.insert_block(height, logs);
+            }
+        }
+    }
+
+    /// Drop every segment lying entirely below `height`, matching chain
+    /// pruning. Returns the dropped segment numbers.
+    pub fn prune_before(&mut self, height: u64) -> Vec<u64> {
+        let keep = self.segment_of(height);
+        let kept = self.segments.split_off(&keep);
+        let pruned = ::std::mem::replace(&mut self.segments, kept);
+        pruned.into_iter().map(|(number, _)| number).collect()
+    }
+
+    /// The kvdb entries of a segment, empty if it has no logs.
+    pub fn segment_entries(&self, segment: u64) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let mut entries = Vec::new();
+        if let Some(seg) = self.segments.get(&segment) {
+            entries.push((entry_key(segment, TAG_BLOCKS, &[]), seg.blocks.encode()));
+            for (topic, set) in &seg.topics {
+                entries.push((entry_key(segment, TAG_TOPIC, &topic.0), set.encode()));
+            }
+            for (address, set) in &seg.addresses {
+                entries.push((entry_key(segment, TAG_ADDRESS, &address.0), set.encode()));
+            }
+        }
+        entries
+    }
+
+    /// Load entries written by `segment_entries`, replacing what is held
+    /// for their segments.
+    pub fn load_entries<I>(&mut self, entries: I) -> Result<(), InvalidEntry>
+    where
+        I: IntoIterator<Item = (Vec<u8>, Vec<u8>)>,
+    {
+        let mut loaded: BTreeMap<u64, Segment> = BTreeMap::new();
+        for (key, value) in entries {
+            if key.len() < 9 {
+                return Err(InvalidEntry);
+            }
+            let mut number = [0u8; 8];
+            number.copy_from_slice(&key[..8]);
+            let segment = loaded
+                .entry(u64::from_be_bytes(number))
+                .or_insert_with(Segment::default);
+            let set = BlockSet::decode(&value).ok_or(InvalidEntry)?;
+            let id = &key[9..];
+            match key[8] {
+                TAG_BLOCKS if id.is_empty() => segment.blocks = set,
+                TAG_TOPIC if id.len() == 32 => {
+                    segment.topics.insert(H256::from_slice(id), set);
+                }
+                TAG_ADDRESS if id.len() == 20 => {
+                    segment.addresses.insert(Address::from_slice(id), set);
+                }
+                _ => return Err(InvalidEntry),
+            }
+        }
+        self.segments.extend(loaded);
+        Ok(())
+    }
+}
+
+fn entry_key(segment: u64, tag: u8, id: &[u8]) -> Vec<u8> {
+    let mut key = Vec::with_capacity(9 + id.len());
+    key.extend_from_slice(&segment.to_be_bytes());
+    key.push(tag);
+    key.extend_from_slice(id);
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Address, H256};
+    use super::{BlockSet, IndexFilter, TopicIndex};
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    type Logs = Vec<(Address, Vec<H256>)>;
+
+    // 20k blocks, a third of them empty, logs from 50 contracts with
+    // topics drawn from 2000.
+    fn synthetic_chain() -> Vec<Logs> {
+        let mut rng = StdRng::from_seed([3u8; 32]);
+        (0..20_000)
+            .map(|_| {
+                if rng.gen_range(0, 3) == 0 {
+                    return Vec::new();
+                }
+                (0..rng.gen_range(1, 4))
+                    .map(|_| {
+                        let address = Address::from(rng.gen_range(0u64, 50));
+                        let topics = (0..rng.gen_range(1, 4))
+                            .map(|_| H256::from(rng.gen_range(0u64, 2000)))
+                            .collect();
+                        (address, topics)
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn build(chain: &[Logs], segment_size: u64) -> TopicIndex {
+        let mut index = TopicIndex::new(segment_size);
+        for (height, logs) in chain.iter().enumerate() {
+            index.insert_block(height as u64, logs);
+        }
+        index
+    }
+
+    fn matches(logs: &Logs, filter: &IndexFilter) -> bool {
+        logs.iter().any(|&(ref address, ref topics)| {
+            filter
+                .addresses
+                .as_ref()
+                .map_or(true, |addresses| addresses.contains(address))
+                && filter.topics.iter().enumerate().all(|(i, wanted)| {
+                    wanted.as_ref().map_or(true, |wanted| {
+                        topics.get(i).map_or(false, |topic| wanted.contains(topic))
+                    })
+                })
+        })
+    }
+
+    #[test]
+    fn block_set_operations() {
+        let mut set = BlockSet::new();
+        for offset in &[5, 3, 4, 10, 11, 7, 6, 12] {
+            set.insert(*offset);
+        }
+        set.insert(4);
+        assert_eq!(
+            set.iter().collect::<Vec<_>>(),
+            vec![3, 4, 5, 6, 7, 10, 11, 12]
+        );
+        assert_eq!(set.len(), 8);
+        assert!(set.contains(11) && !set.contains(8) && !set.contains(0));
+
+        let mut other = BlockSet::new();
+        for offset in &[1, 2, 6, 7, 8, 12] {
+            other.insert(*offset);
+        }
+        assert_eq!(
+            set.intersect(&other).iter().collect::<Vec<_>>(),
+            vec![6, 7, 12]
+        );
+        assert_eq!(
+            set.union(&other).iter().collect::<Vec<_>>(),
+            vec![1, 2, 3, 4, 5, 6, 7, 8, 10, 11, 12]
+        );
+        assert_eq!(BlockSet::decode(&set.encode()), Some(set));
+        assert_eq!(BlockSet::decode(&[1, 2, 3]), None);
+        let mut past_the_end = Vec::new();
+        past_the_end.extend_from_slice(&::std::u32::MAX.to_le_bytes());
+        past_the_end.extend_from_slice(&2u32.to_le_bytes());
+        assert_eq!(BlockSet::decode(&past_the_end), None);
+    }
+
+    #[test]
+    fn candidates_cover_matches() {
+        let chain = synthetic_chain();
+        let index = build(&chain, 1024);
+        let filters = vec![
+            IndexFilter {
+                addresses: None,
+                topics: vec![Some(vec![H256::from(17)])],
+            },
+            IndexFilter {
+                addresses: Some(vec![Address::from(3)]),
+                topics: vec![None, Some((0u64..200).map(H256::from).collect())],
+            },
+            IndexFilter {
+                addresses: Some(vec![Address::from(1), Address::from(2)]),
+                topics: vec![],
+            },
+        ];
+        let (from, to) = (1_500u64, 18_000u64);
+        for filter in &filters {
+            let candidates = index.candidate_blocks(filter, from, to);
+            let expected: Vec<u64> = (from..=to)
+                .filter(|height| matches(&chain[*height as usize], filter))
+                .collect();
+            assert!(!expected.is_empty());
+            assert!(expected.iter().all(|height| candidates.contains(height)));
+            assert!(candidates.iter().all(|h| *h >= from && *h <= to));
+            assert!((candidates.len() as u64) < (to - from) / 10);
+        }
+    }
+
+    #[test]
+    fn rebuild_after_reorg() {
+        let chain = synthetic_chain();
+        let mut index = build(&chain[..4000], 1024);
+        let fork_topic = IndexFilter {
+            addresses: None,
+            topics: vec![Some(vec![H256::from(5000)])],
+        };
+        let old_address = IndexFilter {
+            addresses: Some(vec![Address::from(2)]),
+            topics: vec![],
+        };
+        assert!(index.candidate_blocks(&fork_topic, 0, 3999).is_empty());
+        let before = index.candidate_blocks(&old_address, 0, 2999);
+
+        // Blocks from 3000 are replaced by a fork with other logs.
+        let mut fork: Vec<Logs> = chain[..4000].to_vec();
+        for logs in &mut fork[3000..] {
+            *logs = vec![(Address::from(1), vec![H256::from(5000)])];
+        }
+        for segment in index.segment_of(3000)..=index.segment_of(3999) {
+            let start = segment * index.segment_size();
+            let end = ::std::cmp::min(start + index.segment_size(), 4000);
+            let blocks: Vec<(u64, &[(Address, Vec<H256>)])> = (start..end)
+                .map(|height| (height, &fork[height as usize][..]))
+                .collect();
+            index.rebuild_segment(segment, blocks);
+        }
+
+        assert_eq!(
+            index.candidate_blocks(&fork_topic, 0, 3999),
+            (3000..4000).collect::<Vec<_>>()
+        );
+        assert_eq!(index.candidate_blocks(&old_address, 0, 3999), before);
+    }
+
+    #[test]
+    fn entries_round_trip_and_prune() {
+        let chain = synthetic_chain();
+        let index = build(&chain[..5000], 1000);
+        let mut loaded = TopicIndex::new(1000);
+        for segment in 0..5 {
+            loaded.load_entries(index.segment_entries(segment)).unwrap();
+        }
+        let filter = IndexFilter {
+            addresses: Some(vec![Address::from(7)]),
+            topics: vec![Some(vec![H256::from(42)])],
+        };
+        assert_eq!(
+            loaded.candidate_blocks(&filter, 0, 4999),
+            index.candidate_blocks(&filter, 0, 4999)
+        );
+        assert!(loaded
+            .load_entries(vec![(vec![0u8; 9], vec![1u8])])
+            .is_err());
+
+        assert_eq!(loaded.prune_before(2500), vec![0, 1]);
+        assert!(loaded.segment_entries(1).is_empty());
+        assert!(!loaded.segment_entries(2).is_empty());
+        assert!(loaded.candidate_blocks(&filter, 0, 1999).is_empty());
+    }
+}
@@ -0,0 +1,279 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Integer quantities which must not be mixed up with each other.
+//!
+//! Each is serialized, and RLP encoded in the `rlp` crate, exactly like the
+//! integer it wraps. Only the arithmetic which makes sense is there: two
+//! heights are a `HeightDiff` apart, but can't be added.
+//!
+//! ```compile_fail
+//! use cita_types::BlockHeight;
+//! let _ = BlockHeight(1) + BlockHeight(2);
+//! ```
+//!
+//! ```compile_fail
+//! use cita_types::{BlockHeight, TxIndex};
+//! let _: BlockHeight = TxIndex(1).into();
+//! ```
+//!
+//! ```compile_fail
+//! use cita_types::{BlockHeight, Quota};
+//! let _ = Quota(1) + BlockHeight(2).0;
+//! ```
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::convert::TryFrom;
+use std::fmt;
+use std::num::TryFromIntError;
+use std::ops::{Add, AddAssign, Sub, SubAssign};
+
+macro_rules! impl_quantity {
+    ($name: ident, $inner: ident) => {
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                fmt::Display::fmt(&self.0, f)
+            }
+        }
+
+        impl From<$inner> for $name {
+            fn from(value: $inner) -> Self {
+                $name(value)
+            }
+        }
+
+        impl From<$name> for $inner {
+            fn from(value: $name) -> Self {
+                value.0
+            }
+        }
+
+        impl Serialize for $name {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                self.0.serialize(serializer)
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $name {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                $inner::deserialize(deserializer).map($name)
+            }
+        }
+    };
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BlockHeight(pub u64);
+
+/// How many blocks one height is above another.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct HeightDiff(pub u64);
+
+/// Position of a transaction in its block.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TxIndex(pub u32);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Quota(pub u64);
+
+impl_quantity!(BlockHeight, u64);
+impl_quantity!(HeightDiff, u64);
+impl_quantity!(TxIndex, u32);
+impl_quantity!(Quota, u64);
+
+impl BlockHeight {
+    /// The height after this one.
+    pub fn next(self) -> Self {
+        BlockHeight(self.0 + 1)
+    }
+
+    /// How far `self` is above `other`, none if it is below.
+    pub fn checked_sub(self, other: BlockHeight) -> Option<HeightDiff> {
+        self.0.checked_sub(other.0).map(HeightDiff)
+    }
+}
+
+impl Sub for BlockHeight {
+    type Output = HeightDiff;
+
+    fn sub(self, other: BlockHeight) -> HeightDiff {
+        HeightDiff(self.0 - other.0)
+    }
+}
+
+impl Add<HeightDiff> for BlockHeight {
+    type Output = BlockHeight;
+
+    fn add(self, diff: HeightDiff) -> BlockHeight {
+        BlockHeight(self.0 + diff.0)
+    }
+}
+
+impl Sub<HeightDiff> for BlockHeight {
+    type Output = BlockHeight;
+
+    fn sub(self, diff: HeightDiff) -> BlockHeight {
+        BlockHeight(self.0 - diff.0)
+    }
+}
+
+impl AddAssign<HeightDiff> for BlockHeight {
+    fn add_assign(&mut self, diff: HeightDiff) {
+        self.0 += diff.0;
+    }
+}
+
+impl SubAssign<HeightDiff> for BlockHeight {
+    fn sub_assign(&mut self, diff: HeightDiff) {
+        self.0 -= diff.0;
+    }
+}
+
+impl Add for HeightDiff {
+    type Output = HeightDiff;
+
+    fn add(self, other: HeightDiff) -> HeightDiff {
+        HeightDiff(self.0 + other.0)
+    }
+}
+
+impl TxIndex {
+    /// The index after this one.
+    pub fn next(self) -> Self {
+        TxIndex(self.0 + 1)
+    }
+}
+
+impl TryFrom<u64> for TxIndex {
+    type Error = TryFromIntError;
+
+    fn try_from(value: u64) -> Result<Self, Self::Error> {
+        u32::try_from(value).map(TxIndex)
+    }
+}
+
+impl TryFrom<usize> for TxIndex {
+    type Error = TryFromIntError;
+
+    fn try_from(value: usize) -> Result<Self, Self::Error> {
+        u32::try_from(value).map(TxIndex)
+    }
+}
+
+impl From<TxIndex> for usize {
+    fn from(index: TxIndex) -> Self {
+        index.0 as usize
+    }
+}
+
+impl Quota {
+    pub fn checked_sub(self, other: Quota) -> Option<Quota> {
+        self.0.checked_sub(other.0).map(Quota)
+    }
+
+    pub fn saturating_sub(self, other: Quota) -> Quota {
+        Quota(self.0.saturating_sub(other.0))
+    }
+}
+
+impl Add for Quota {
+    type Output = Quota;
+
+    fn add(self, other: Quota) -> Quota {
+        Quota(self.0 + other.0)
+    }
+}
+
+impl Sub for Quota {
+    type Output = Quota;
+
+    fn sub(self, other: Quota) -> Quota {
+        Quota(self.0 - other.0)
+    }
+}
+
+impl AddAssign for Quota {
+    fn add_assign(&mut self, other: Quota) {
+        self.0 += other.0;
+    }
+}
+
+impl SubAssign for Quota {
+    fn sub_assign(&mut self, other: Quota) {
+        self.0 -= other.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BlockHeight, HeightDiff, Quota, TxIndex};
+    use serde_json;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn serialized_as_integers() {
+        assert_eq!(serde_json::to_string(&BlockHeight(42)).unwrap(), "42");
+        assert_eq!(
+            serde_json::to_string(&vec![TxIndex(0), TxIndex(7)]).unwrap(),
+            serde_json::to_string(&vec![0u32, 7]).unwrap()
+        );
+        assert_eq!(
+            serde_json::to_string(&Quota(u64::max_value())).unwrap(),
+            u64::max_value().to_string()
+        );
+        assert_eq!(
+            serde_json::from_str::<BlockHeight>("42").unwrap(),
+            BlockHeight(42)
+        );
+        assert_eq!(
+            serde_json::from_str::<HeightDiff>("3").unwrap(),
+            HeightDiff(3)
+        );
+        assert!(serde_json::from_str::<TxIndex>("4294967296").is_err());
+        assert!(serde_json::from_str::<Quota>("-1").is_err());
+    }
+
+    #[test]
+    fn height_arithmetic() {
+        let height = BlockHeight(10);
+        assert_eq!(height - BlockHeight(4), HeightDiff(6));
+        assert_eq!(height + HeightDiff(5), BlockHeight(15));
+        assert_eq!(height - HeightDiff(5), BlockHeight(5));
+        assert_eq!(BlockHeight(4).checked_sub(height), None);
+        assert_eq!(height.checked_sub(height), Some(HeightDiff(0)));
+        assert_eq!(height.next(), BlockHeight(11));
+
+        let mut height = height;
+        height += HeightDiff(2) + HeightDiff(1);
+        assert_eq!(height, BlockHeight(13));
+        height -= HeightDiff(13);
+        assert_eq!(height, BlockHeight(0));
+    }
+
+    #[test]
+    fn conversions() {
+        assert_eq!(BlockHeight::from(3), BlockHeight(3));
+        assert_eq!(u64::from(BlockHeight(3)), 3);
+        assert_eq!(TxIndex::try_from(5usize), Ok(TxIndex(5)));
+        assert!(TxIndex::try_from(u64::from(u32::max_value()) + 1).is_err());
+        assert_eq!(usize::from(TxIndex(5)), 5);
+        assert_eq!(Quota(5).checked_sub(Quota(6)), None);
+        assert_eq!(Quota(5).saturating_sub(Quota(6)), Quota(0));
+        assert_eq!(Quota(5) + Quota(6) - Quota(1), Quota(10));
+        assert_eq!(
+            format!("{} {} {}", BlockHeight(1), TxIndex(2), Quota(3)),
+            "1 2 3"
+        );
+    }
+}
@@ -0,0 +1,90 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use rand::distributions::Distribution;
+use rand::{Rng, RngCore};
+
+use super::{H128, H160, H256, H264, H32, H512, H520, H64};
+
+/// Samples fixed-size hashes whose bytes are all uniformly random.
+///
+/// The hash types come from `ethereum-types`, so the orphan rule forbids
+/// implementing `Distribution<H256>` for `rand::distributions::Standard` here.
+/// Use `rng.sample(UniformHash)` or `H256::random_with(&mut rng)` instead.
+#[derive(Debug, Clone, Copy)]
+pub struct UniformHash;
+
+pub trait RandomHash
+where
+    Self: ::std::marker::Sized,
+{
+    fn random_with<R: Rng + ?Sized>(rng: &mut R) -> Self;
+}
+
+macro_rules! impl_random_for_hashes {
+    ($( $name:ident ),+ $(,)*) => {
+        $(
+            impl Distribution<$name> for UniformHash {
+                fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> $name {
+                    let mut hash = $name::zero();
+                    rng.fill_bytes(&mut hash.0);
+                    hash
+                }
+            }
+
+            impl RandomHash for $name {
+                #[inline]
+                fn random_with<R: Rng + ?Sized>(rng: &mut R) -> Self {
+                    UniformHash.sample(rng)
+                }
+            }
+        )+
+    };
+}
+
+impl_random_for_hashes!(H32, H64, H128, H160, H256, H264, H512, H520);
+
+/// Random byte vectors, handy for keys and values in tests.
+pub fn random_bytes<R: Rng + ?Sized>(rng: &mut R, len: usize) -> Vec<u8> {
+    let mut bytes = vec![0u8; len];
+    rng.fill_bytes(&mut bytes);
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{random_bytes, RandomHash, UniformHash};
+    use super::{H160, H256, H512};
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn sample_hashes() {
+        let mut rng = StdRng::from_seed([7u8; 32]);
+        let h1: H256 = rng.sample(UniformHash);
+        let h2 = H256::random_with(&mut rng);
+        assert_ne!(h1, h2);
+        assert!(!h1.is_zero());
+        assert!(!H160::random_with(&mut rng).is_zero());
+        assert!(!H512::random_with(&mut rng).is_zero());
+    }
+
+    #[test]
+    fn sample_is_reproducible() {
+        let mut rng1 = StdRng::from_seed([1u8; 32]);
+        let mut rng2 = StdRng::from_seed([1u8; 32]);
+        assert_eq!(H256::random_with(&mut rng1), H256::random_with(&mut rng2));
+        assert_eq!(random_bytes(&mut rng1, 13), random_bytes(&mut rng2, 13));
+    }
+}
@@ -0,0 +1,253 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Which blocks may include a transaction, by its `valid_until_block`.
+//!
+//! With the chain at `current_height`, the next block is `current_height + 1`,
+//! and a transaction valid until `v` may go into the blocks up to `v`,
+//! included. So it is valid when
+//!
+//! ```text
+//! current_height < v <= current_height + max_window
+//! ```
+//!
+//! It expired once the chain reached `v`, and is refused when `v` is
+//! further ahead than `max_window`, so it can't be replayed long after it
+//! was signed. A policy may take `v = 0` to mean it never expires.
+//!
+//! Packaging the old way, the window starts at the current height instead,
+//! `current_height <= v < current_height + max_window`; that is
+//! `ValidityPolicy::legacy`.
+
+use std::error::Error;
+use std::fmt;
+
+/// The window of the chain so far, `BLOCKLIMIT` in `util`.
+pub const DEFAULT_MAX_WINDOW: u64 = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ValidityPolicy {
+    pub max_window: u64,
+    /// Whether `valid_until_block = 0` is valid at any height. Otherwise it
+    /// is expired like any height reached.
+    pub allow_zero_means_forever: bool,
+    /// Whether the window includes `current_height` itself, and so ends a
+    /// block earlier.
+    pub includes_current: bool,
+}
+
+impl Default for ValidityPolicy {
+    fn default() -> Self {
+        ValidityPolicy {
+            max_window: DEFAULT_MAX_WINDOW,
+            allow_zero_means_forever: false,
+            includes_current: false,
+        }
+    }
+}
+
+impl ValidityPolicy {
+    pub fn new(max_window: u64, allow_zero_means_forever: bool) -> Self {
+        ValidityPolicy {
+            max_window,
+            allow_zero_means_forever,
+            includes_current: false,
+        }
+    }
+
+    /// The window of `package_backword_compatible` in `tx_pool`.
+    pub fn legacy() -> Self {
+        ValidityPolicy {
+            includes_current: true,
+            ..ValidityPolicy::default()
+        }
+    }
+
+    pub fn never_expires(&self, tx_valid_until: u64) -> bool {
+        self.allow_zero_means_forever && tx_valid_until == 0
+    }
+
+    /// Whether the block after `current_height` may include the transaction.
+    pub fn check(&self, tx_valid_until: u64, current_height: u64) -> Result<(), ValidityError> {
+        if self.never_expires(tx_valid_until) {
+            return Ok(());
+        }
+        let (expired, window) = if self.includes_current {
+            (
+                tx_valid_until < current_height,
+                self.max_window.saturating_sub(1),
+            )
+        } else {
+            (tx_valid_until <= current_height, self.max_window)
+        };
+        if expired {
+            return Err(ValidityError::Expired {
+                valid_until: tx_valid_until,
+                current_height,
+            });
+        }
+        if tx_valid_until - current_height > window {
+            return Err(ValidityError::BeyondWindow {
+                valid_until: tx_valid_until,
+                max: current_height.saturating_add(window),
+            });
+        }
+        Ok(())
+    }
+
+    /// How many more blocks may include the transaction, none for one which
+    /// never expires. Zero once expired.
+    pub fn remaining_blocks(&self, tx_valid_until: u64, current_height: u64) -> Option<u64> {
+        if self.never_expires(tx_valid_until) {
+            None
+        } else {
+            Some(tx_valid_until.saturating_sub(current_height))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValidityError {
+    /// The chain reached `valid_until` already.
+    Expired {
+        valid_until: u64,
+        current_height: u64,
+    },
+    /// Valid until later than `max`, the end of the window.
+    BeyondWindow { valid_until: u64, max: u64 },
+}
+
+impl fmt::Display for ValidityError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ValidityError::Expired {
+                valid_until,
+                current_height,
+            } => write!(
+                f,
+                "valid until block {}, and the chain is at {}",
+                valid_until, current_height
+            ),
+            ValidityError::BeyondWindow { valid_until, max } => {
+                write!(f, "valid until block {}, later than {}", valid_until, max)
+            }
+        }
+    }
+}
+
+impl Error for ValidityError {}
+
+#[cfg(test)]
+mod tests {
+    use super::{ValidityError, ValidityPolicy, DEFAULT_MAX_WINDOW};
+
+    #[test]
+    fn boundaries() {
+        let policy = ValidityPolicy::default();
+        assert_eq!(policy.max_window, DEFAULT_MAX_WINDOW);
+        let current = 1000;
+
+        let expired = |valid_until| {
+            Err(ValidityError::Expired {
+                valid_until,
+                current_height: current,
+            })
+        };
+        let beyond = |valid_until| {
+            Err(ValidityError::BeyondWindow {
+                valid_until,
+                max: 1100,
+            })
+        };
+        let matrix = [
+            (current - 1, expired(current - 1)),
+            (current, expired(current)),
+            (current + 1, Ok(())),
+            (current + 99, Ok(())),
+            (current + 100, Ok(())),
+            (current + 101, beyond(current + 101)),
+        ];
+        for &(valid_until, ref expected) in matrix.iter() {
+            assert_eq!(
+                &policy.check(valid_until, current),
+                expected,
+                "{}",
+                valid_until
+            );
+        }
+
+        assert_eq!(policy.remaining_blocks(current + 1, current), Some(1));
+        assert_eq!(policy.remaining_blocks(current + 100, current), Some(100));
+        assert_eq!(policy.remaining_blocks(current, current), Some(0));
+        assert_eq!(policy.remaining_blocks(current - 1, current), Some(0));
+    }
+
+    #[test]
+    fn legacy_boundaries() {
+        let policy = ValidityPolicy::legacy();
+        let current = 1000;
+        assert_eq!(
+            policy.check(current - 1, current),
+            Err(ValidityError::Expired {
+                valid_until: current - 1,
+                current_height: current,
+            })
+        );
+        assert_eq!(policy.check(current, current), Ok(()));
+        assert_eq!(policy.check(current + 99, current), Ok(()));
+        assert_eq!(
+            policy.check(current + 100, current),
+            Err(ValidityError::BeyondWindow {
+                valid_until: current + 100,
+                max: current + 99,
+            })
+        );
+        assert_eq!(policy.check(0, 0), Ok(()));
+    }
+
+    #[test]
+    fn zero_means_forever_when_allowed() {
+        let strict = ValidityPolicy::default();
+        assert_eq!(
+            strict.check(0, 0),
+            Err(ValidityError::Expired {
+                valid_until: 0,
+                current_height: 0,
+            })
+        );
+        assert_eq!(strict.remaining_blocks(0, 5), Some(0));
+        assert_eq!(strict.check(1, 0), Ok(()));
+
+        let forever = ValidityPolicy::new(10, true);
+        assert_eq!(forever.check(0, 0), Ok(()));
+        assert_eq!(forever.check(0, ::std::u64::MAX), Ok(()));
+        assert_eq!(forever.remaining_blocks(0, 5), None);
+        // Any other height is checked as usual.
+        assert!(forever.check(5, 5).is_err());
+        assert!(forever.check(16, 5).is_err());
+        assert_eq!(forever.check(15, 5), Ok(()));
+    }
+
+    #[test]
+    fn window_at_the_end_of_heights() {
+        let policy = ValidityPolicy::new(::std::u64::MAX, false);
+        assert_eq!(policy.check(::std::u64::MAX, 0), Ok(()));
+        let policy = ValidityPolicy::default();
+        assert_eq!(policy.check(::std::u64::MAX, ::std::u64::MAX - 1), Ok(()));
+        assert_eq!(
+            format!("{}", policy.check(5, 10).unwrap_err()),
+            "valid until block 5, and the chain is at 10"
+        );
+    }
+}
@@ -0,0 +1,346 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The backends behind the `Backend` trait, and their shapes.
+
+use std::fmt::Debug;
+
+#[allow(unused_imports)]
+use crate::harness::{Backend, Operation, Shape, DECODER_ERRORS, INVALID_LENGTH};
+
+/// Parse `bytes` into a fixed length type, whose `From` panics on any other
+/// length.
+#[allow(dead_code)]
+fn fixed<'a, T: From<&'a [u8]>>(bytes: &'a [u8], len: usize) -> Result<T, String> {
+    if bytes.len() == len {
+        Ok(T::from(bytes))
+    } else {
+        Err(INVALID_LENGTH.to_owned())
+    }
+}
+
+/// The variant name out of the `Debug` of an error.
+#[allow(dead_code)]
+fn error_name<E: Debug>(error: E) -> String {
+    let debug = format!("{:?}", error);
+    debug
+        .split(|c| c == '(' || c == ' ' || c == '{')
+        .next()
+        .unwrap_or_default()
+        .to_owned()
+}
+
+#[allow(unused_macros)]
+macro_rules! impl_backend {
+    ($backend:ident, $krate:ident, $shape:expr) => {
+        pub struct $backend;
+
+        impl Backend for $backend {
+            fn shape(&self) -> &Shape {
+                &$shape
+            }
+
+            fn gen_keypair(&self) -> (Vec<u8>, Vec<u8>) {
+                use cita_crypto_trait::CreateKey;
+                let keypair = $krate::KeyPair::gen_keypair();
+                (keypair.privkey().to_vec(), keypair.pubkey().to_vec())
+            }
+
+            fn from_privkey(&self, privkey: &[u8]) -> Result<Vec<u8>, String> {
+                use cita_crypto_trait::CreateKey;
+                let privkey: $krate::PrivKey = fixed(privkey, $krate::PRIVKEY_BYTES_LEN)?;
+                $krate::KeyPair::from_privkey(privkey)
+                    .map(|keypair| keypair.pubkey().to_vec())
+                    .map_err(error_name)
+            }
+
+            fn sign(&self, privkey: &[u8], message: &[u8]) -> Result<Vec<u8>, String> {
+                use cita_crypto_trait::Sign;
+                let privkey: $krate::PrivKey = fixed(privkey, $krate::PRIVKEY_BYTES_LEN)?;
+                let message: $krate::Message = fixed(message, $krate::HASH_BYTES_LEN)?;
+                $krate::Signature::sign(&privkey, &message)
+                    .map(|signature| signature.to_vec())
+                    .map_err(error_name)
+            }
+
+            fn recover(&self, signature: &[u8], message: &[u8]) -> Result<Vec<u8>, String> {
+                use cita_crypto_trait::Sign;
+                let signature: $krate::Signature = fixed(signature, $krate::SIGNATURE_BYTES_LEN)?;
+                let message: $krate::Message = fixed(message, $krate::HASH_BYTES_LEN)?;
+                signature
+                    .recover(&message)
+                    .map(|pubkey| pubkey.to_vec())
+                    .map_err(error_name)
+            }
+
+            fn verify(
+                &self,
+                signature: &[u8],
+                pubkey: &[u8],
+                message: &[u8],
+            ) -> Result<bool, String> {
+                use cita_crypto_trait::Sign;
+                let signature: $krate::Signature = fixed(signature, $krate::SIGNATURE_BYTES_LEN)?;
+                let pubkey: $krate::PubKey = fixed(pubkey, $krate::PUBKEY_BYTES_LEN)?;
+                let message: $krate::Message = fixed(message, $krate::HASH_BYTES_LEN)?;
+                signature
+                    .verify_public(&pubkey, &message)
+                    .map_err(error_name)
+            }
+
+            fn address(&self, pubkey: &[u8]) -> Result<Vec<u8>, String> {
+                let pubkey: $krate::PubKey = fixed(pubkey, $krate::PUBKEY_BYTES_LEN)?;
+                Ok($krate::pubkey_to_address(&pubkey).to_vec())
+            }
+
+            fn decode_signature(&self, encoded: &[u8]) -> Result<Vec<u8>, String> {
+                rlp::UntrustedRlp::new(encoded)
+                    .as_val::<$krate::Signature>()
+                    .map(|signature| signature.to_vec())
+                    .map_err(error_name)
+            }
+        }
+    };
+}
+
+#[cfg(feature = "secp256k1")]
+fn secp256k1_errors(operation: Operation) -> &'static [&'static str] {
+    match operation {
+        Operation::FromPrivKey | Operation::Sign => &["InvalidPrivKey"],
+        // An all zero message isn't a valid one to libsecp256k1.
+        Operation::Recover => &["InvalidSignature", "InvalidMessage"],
+        Operation::Verify => &["InvalidSignature", "InvalidPubKey", "InvalidMessage"],
+        Operation::Address => &[],
+        Operation::DecodeSignature => DECODER_ERRORS,
+    }
+}
+
+#[cfg(feature = "secp256k1")]
+pub static SECP256K1: Shape = Shape {
+    name: "secp256k1",
+    privkey_len: 32,
+    pubkey_len: 64,
+    signature_len: 65,
+    privkey_order: Some("fffffffffffffffffffffffffffffffebaaedce6af48a03bbfd25e8cd0364141"),
+    scalars: &[
+        (
+            0,
+            "fffffffffffffffffffffffffffffffebaaedce6af48a03bbfd25e8cd0364141",
+        ),
+        (
+            32,
+            "fffffffffffffffffffffffffffffffebaaedce6af48a03bbfd25e8cd0364141",
+        ),
+    ],
+    recovery_id: Some(64),
+    embedded_pubkey: None,
+    errors: secp256k1_errors,
+};
+
+#[cfg(feature = "secp256k1")]
+impl_backend!(Secp256k1, cita_secp256k1, SECP256K1);
+
+#[cfg(feature = "sm2")]
+fn sm2_errors(operation: Operation) -> &'static [&'static str] {
+    match operation {
+        Operation::Address => &[],
+        Operation::DecodeSignature => DECODER_ERRORS,
+        _ => &["RecoverError"],
+    }
+}
+
+#[cfg(feature = "sm2")]
+pub static SM2: Shape = Shape {
+    name: "sm2",
+    privkey_len: 32,
+    pubkey_len: 64,
+    signature_len: 128,
+    privkey_order: Some("fffffffeffffffffffffffffffffffff7203df6b21c6052b53bbf40939d54123"),
+    scalars: &[
+        (
+            0,
+            "fffffffeffffffffffffffffffffffff7203df6b21c6052b53bbf40939d54123",
+        ),
+        (
+            32,
+            "fffffffeffffffffffffffffffffffff7203df6b21c6052b53bbf40939d54123",
+        ),
+    ],
+    recovery_id: None,
+    embedded_pubkey: Some(64),
+    errors: sm2_errors,
+};
+
+#[cfg(feature = "sm2")]
+impl_backend!(Sm2, cita_sm2, SM2);
+
+#[cfg(feature = "ed25519")]
+fn ed25519_errors(operation: Operation) -> &'static [&'static str] {
+    match operation {
+        Operation::FromPrivKey | Operation::Sign => &["InvalidPrivKey"],
+        Operation::Recover => &["InvalidSignature"],
+        Operation::Verify => &["InvalidPubKey", "InvalidSignature"],
+        Operation::Address => &[],
+        Operation::DecodeSignature => DECODER_ERRORS,
+    }
+}
+
+#[cfg(feature = "ed25519")]
+pub static ED25519: Shape = Shape {
+    name: "ed25519",
+    privkey_len: 64,
+    pubkey_len: 32,
+    signature_len: 96,
+    // The private key is a seed and its public key, and any seed is valid.
+    privkey_order: None,
+    // Only `S` is a scalar, little endian.
+    scalars: &[(
+        32,
+        "edd3f55c1a631258d69cf7a2def9de1400000000000000000000000000000010",
+    )],
+    recovery_id: None,
+    embedded_pubkey: Some(64),
+    errors: ed25519_errors,
+};
+
+#[cfg(feature = "ed25519")]
+impl_backend!(Ed25519, cita_ed25519, ED25519);
+
+/// One of each backend compiled in.
+pub fn compiled() -> Vec<Box<dyn Backend>> {
+    let backends: &[fn() -> Box<dyn Backend>] = &[
+        #[cfg(feature = "secp256k1")]
+        || Box::new(Secp256k1),
+        #[cfg(feature = "sm2")]
+        || Box::new(Sm2),
+        #[cfg(feature = "ed25519")]
+        || Box::new(Ed25519),
+    ];
+    backends.iter().map(|new| new()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+    #[allow(unused_imports)]
+    use rustc_serialize::hex::FromHex;
+
+    /// sha256 of "cita".
+    #[allow(dead_code)]
+    const MESSAGE: &str = "76ba03ff49a1b8b3e54ca77a33b4343ae6267adcee85038a225a62f9ec373720";
+
+    #[allow(dead_code)]
+    fn hex(value: &str) -> Vec<u8> {
+        value.from_hex().unwrap()
+    }
+
+    #[test]
+    fn error_names() {
+        #[derive(Debug)]
+        #[allow(dead_code)]
+        enum Error {
+            Unit,
+            Tuple(u8),
+            Struct { field: u8 },
+        }
+        assert_eq!(error_name(Error::Unit), "Unit");
+        assert_eq!(error_name(Error::Tuple(1)), "Tuple");
+        assert_eq!(error_name(Error::Struct { field: 1 }), "Struct");
+    }
+
+    /// Vectors from RFC 6979 signing with low `s`, made independently of
+    /// libsecp256k1.
+    #[cfg(feature = "secp256k1")]
+    #[test]
+    fn secp256k1_reference() {
+        let backend = Secp256k1;
+        let message = hex(MESSAGE);
+        for (privkey, pubkey, signature) in &[
+            (
+                "0000000000000000000000000000000000000000000000000000000000000001",
+                "79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798\
+                 483ada7726a3c4655da4fbfc0e1108a8fd17b448a68554199c47d08ffb10d4b8",
+                "3f14c4b232c58efaf36fd3923e78021bf36632f13ec01fe469b9400dea230f1a\
+                 43f1e70a2fc36a7248fecea3dc972813689b54c7b338a088c63697021b8c9641\
+                 01",
+            ),
+            (
+                "a100df7a048e50ed308ea696dc600215098141cb391e9527329df289f9383f65",
+                "8ce0db0b0359ffc5866ba61903cc2518c3675ef2cf380a7e54bde7ea20e6fa1a\
+                 b45b7617346cd11b7610001ee6ae5b0155c41cad9527cbcdff44ec67848943a4",
+                "ac447ac5f2e86669b030e0de246282a6d8c5c1ef594b881bb9b7d4fd2d4a05a0\
+                 5ab5e15f1ec5de81947417b6dcb0d7c80ebe95688f5647ddd2af69d17bcb5b07\
+                 01",
+            ),
+        ] {
+            let (privkey, pubkey, signature) = (hex(privkey), hex(pubkey), hex(signature));
+            assert_eq!(backend.from_privkey(&privkey), Ok(pubkey.clone()));
+            assert_eq!(backend.sign(&privkey, &message), Ok(signature.clone()));
+            assert_eq!(backend.verify(&signature, &pubkey, &message), Ok(true));
+            assert_eq!(backend.recover(&signature, &message), Ok(pubkey));
+        }
+
+        // The largest private key.
+        let privkey = hex("fffffffffffffffffffffffffffffffebaaedce6af48a03bbfd25e8cd0364140");
+        let pubkey = hex(
+            "79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798\
+             b7c52588d95c3b9aa25b0403f1eef75702e84bb7597aabe663b82f6f04ef2777",
+        );
+        assert_eq!(backend.from_privkey(&privkey), Ok(pubkey));
+    }
+
+    /// TEST 1 key of RFC 8032, signing `MESSAGE`.
+    #[cfg(feature = "ed25519")]
+    #[test]
+    fn ed25519_reference() {
+        let backend = Ed25519;
+        let message = hex(MESSAGE);
+        let pubkey = hex("d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a");
+        let mut privkey = hex("9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60");
+        privkey.extend_from_slice(&pubkey);
+        let mut signature = hex(
+            "f07c2d666a2fc932ec7f3e937d7e410bf276e1e091f2f7672282d8b46ff2b073\
+             67a4ccd93852125a7ec7f9511260713d1273192100bed40490df273c0e721a01",
+        );
+        signature.extend_from_slice(&pubkey);
+
+        assert_eq!(backend.from_privkey(&privkey), Ok(pubkey.clone()));
+        assert_eq!(backend.sign(&privkey, &message), Ok(signature.clone()));
+        assert_eq!(backend.verify(&signature, &pubkey, &message), Ok(true));
+        assert_eq!(backend.recover(&signature, &message), Ok(pubkey));
+    }
+
+    /// A signature of GB/T 32918 with the default user id, made
+    /// independently of libsm. Signing is randomized, so only verifying is
+    /// compared.
+    #[cfg(feature = "sm2")]
+    #[test]
+    fn sm2_reference() {
+        let backend = Sm2;
+        let message = hex(MESSAGE);
+        let privkey = hex("3945208f7b2144b13f36e38ac6d39f95889393692860b51a42fb81ef4df7c5b8");
+        let signature = hex(
+            "d4fb63150bd1350b4a6c6b4ea57bac5ac605994cb89aa550ba7e039becb63e3e\
+             6cc0534662dfb19b17191d14032ea7a64afa379425850314d8e58e53a9912fe8\
+             09f9df311e5421a150dd7d161e4bc5c672179fad1833fc076bb08ff356f35020\
+             ccea490ce26775a52dc6ea718cc1aa600aed05fbf35e084a6632f6072da9ad13",
+        );
+        let pubkey = signature[64..].to_vec();
+
+        assert_eq!(backend.from_privkey(&privkey), Ok(pubkey.clone()));
+        assert_eq!(backend.verify(&signature, &pubkey, &message), Ok(true));
+        assert_eq!(backend.recover(&signature, &message), Ok(pubkey));
+    }
+}
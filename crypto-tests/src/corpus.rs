@@ -0,0 +1,250 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Malformed input, made from a valid key and signature by truncating,
+//! extending and overwriting them where the backend's `Shape` says.
+
+use rustc_serialize::hex::FromHex;
+
+use crate::harness::Shape;
+
+/// The message the valid signature of a corpus is for.
+pub const MESSAGE: [u8; 32] = [0x5a; 32];
+
+#[derive(Debug, Clone)]
+pub struct Case {
+    pub name: String,
+    pub bytes: Vec<u8>,
+    /// It must fail, or not verify.
+    pub rejected: bool,
+}
+
+impl Case {
+    fn new<S: Into<String>>(name: S, bytes: Vec<u8>, rejected: bool) -> Self {
+        Case {
+            name: name.into(),
+            bytes,
+            rejected,
+        }
+    }
+}
+
+pub struct Corpus {
+    pub privkeys: Vec<Case>,
+    pub pubkeys: Vec<Case>,
+    pub signatures: Vec<Case>,
+    /// RLP of the signatures, and RLP which is broken.
+    pub encoded_signatures: Vec<Case>,
+    /// `MESSAGE` first.
+    pub messages: Vec<[u8; 32]>,
+}
+
+/// `valid` with a length no type takes.
+fn wrong_lengths(valid: &[u8]) -> Vec<Case> {
+    let mut extended = valid.to_vec();
+    extended.push(0);
+    vec![
+        Case::new("empty", vec![], true),
+        Case::new("truncated", valid[..valid.len() - 1].to_vec(), true),
+        Case::new("one byte too many", extended, true),
+    ]
+}
+
+/// `valid`, its wrong lengths, and all zeros and ones.
+fn basic(valid: &[u8]) -> Vec<Case> {
+    let mut cases = wrong_lengths(valid);
+    cases.push(Case::new("valid", valid.to_vec(), false));
+    cases.push(Case::new("zero", vec![0; valid.len()], true));
+    cases.push(Case::new("all ones", vec![0xff; valid.len()], true));
+    cases
+}
+
+fn overwrite(valid: &[u8], offset: usize, value: &[u8]) -> Vec<u8> {
+    let mut bytes = valid.to_vec();
+    bytes[offset..offset + value.len()].copy_from_slice(value);
+    bytes
+}
+
+fn hex(value: &str) -> Vec<u8> {
+    value.from_hex().expect("hex in a backend shape")
+}
+
+impl Corpus {
+    /// `signature` is made by `privkey` for `MESSAGE`.
+    pub fn new(shape: &Shape, privkey: &[u8], pubkey: &[u8], signature: &[u8]) -> Self {
+        let mut privkeys = basic(privkey);
+        if let Some(order) = shape.privkey_order {
+            let order = hex(order);
+            let mut below = order.clone();
+            // None of the orders ends with a zero byte.
+            *below.last_mut().unwrap() -= 1;
+            privkeys.push(Case::new("order", order, true));
+            privkeys.push(Case::new("order - 1", below, false));
+        }
+
+        let mut other_pubkey = pubkey.to_vec();
+        *other_pubkey.last_mut().unwrap() ^= 1;
+        let mut pubkeys = basic(pubkey);
+        pubkeys.push(Case::new("last bit flipped", other_pubkey.clone(), true));
+
+        let mut signatures = basic(signature);
+        for &(offset, order) in shape.scalars {
+            let order = hex(order);
+            let zero = vec![0; order.len()];
+            signatures.push(Case::new(
+                format!("scalar at {} zero", offset),
+                overwrite(signature, offset, &zero),
+                true,
+            ));
+            signatures.push(Case::new(
+                format!("scalar at {} the order", offset),
+                overwrite(signature, offset, &order),
+                true,
+            ));
+        }
+        if let Some(offset) = shape.recovery_id {
+            for &id in &[2u8, 3, 4, 27, 255] {
+                // Verifying doesn't look at a recovery id in range, only
+                // recovering does.
+                signatures.push(Case::new(
+                    format!("recovery id {}", id),
+                    overwrite(signature, offset, &[id]),
+                    id > 3,
+                ));
+            }
+        }
+        if let Some(offset) = shape.embedded_pubkey {
+            signatures.push(Case::new(
+                "embedded key zero",
+                overwrite(signature, offset, &vec![0; shape.pubkey_len]),
+                true,
+            ));
+            signatures.push(Case::new(
+                "embedded key of another",
+                overwrite(signature, offset, &other_pubkey),
+                true,
+            ));
+        }
+
+        // Decoding only checks the length.
+        let mut encoded_signatures: Vec<Case> = signatures
+            .iter()
+            .map(|case| {
+                Case::new(
+                    format!("rlp of {}", case.name),
+                    rlp::encode(&case.bytes).to_vec(),
+                    case.bytes.len() != shape.signature_len,
+                )
+            })
+            .collect();
+        let valid = rlp::encode(&signature.to_vec()).to_vec();
+        let mut trailing = valid.clone();
+        trailing.push(0);
+        let mut long_form = vec![0xb8, 0x05];
+        long_form.extend_from_slice(&signature[..5]);
+        encoded_signatures.extend(vec![
+            Case::new("no rlp", vec![], true),
+            Case::new(
+                "rlp list",
+                rlp::encode_list::<Vec<u8>, _>(&[signature.to_vec()]).to_vec(),
+                true,
+            ),
+            Case::new("rlp cut off", valid[..valid.len() / 2].to_vec(), true),
+            Case::new("rlp long form of a short length", long_form, true),
+            Case::new("rlp with trailing bytes", trailing, false),
+        ]);
+
+        Corpus {
+            privkeys,
+            pubkeys,
+            signatures,
+            encoded_signatures,
+            messages: vec![MESSAGE, [0; 32], [0xff; 32]],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Corpus, MESSAGE};
+    use crate::harness::{Operation, Shape};
+
+    fn no_errors(_: Operation) -> &'static [&'static str] {
+        &[]
+    }
+
+    #[test]
+    fn cases_from_shape() {
+        let shape = Shape {
+            name: "test",
+            privkey_len: 4,
+            pubkey_len: 2,
+            signature_len: 8,
+            privkey_order: Some("ffffff41"),
+            scalars: &[(0, "0000ff41")],
+            recovery_id: Some(7),
+            embedded_pubkey: Some(4),
+            errors: no_errors,
+        };
+        let corpus = Corpus::new(&shape, &[1; 4], &[2; 2], &[3; 8]);
+        assert_eq!(corpus.messages[0], MESSAGE);
+
+        let find = |name: &str| {
+            corpus
+                .signatures
+                .iter()
+                .find(|case| case.name == name)
+                .unwrap()
+                .clone()
+        };
+        assert_eq!(
+            find("scalar at 0 the order").bytes,
+            vec![0, 0, 0xff, 0x41, 3, 3, 3, 3]
+        );
+        assert_eq!(find("recovery id 27").bytes, vec![3, 3, 3, 3, 3, 3, 3, 27]);
+        assert!(!find("recovery id 3").rejected);
+        assert_eq!(
+            find("embedded key of another").bytes,
+            vec![3, 3, 3, 3, 2, 3, 3, 3]
+        );
+        assert!(!find("valid").rejected);
+
+        let order_below = corpus
+            .privkeys
+            .iter()
+            .find(|case| case.name == "order - 1")
+            .unwrap();
+        assert_eq!(order_below.bytes, vec![0xff, 0xff, 0xff, 0x40]);
+
+        // Only the wrong lengths and the broken RLP can't be decoded.
+        let rejected: Vec<&str> = corpus
+            .encoded_signatures
+            .iter()
+            .filter(|case| case.rejected)
+            .map(|case| case.name.as_str())
+            .collect();
+        assert_eq!(
+            rejected,
+            vec![
+                "rlp of empty",
+                "rlp of truncated",
+                "rlp of one byte too many",
+                "no rlp",
+                "rlp list",
+                "rlp cut off",
+                "rlp long form of a short length",
+            ]
+        );
+    }
+}
@@ -0,0 +1,317 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+
+use crate::corpus::{Case, Corpus, MESSAGE};
+
+/// Error of input which doesn't have the length of the type it's parsed
+/// into. The typed API can't be called with it, so every operation can
+/// return it.
+pub const INVALID_LENGTH: &str = "InvalidLength";
+
+/// What decoding RLP can fail with, besides `Custom`.
+pub const DECODER_ERRORS: &[&str] = &[
+    "RlpIsTooBig",
+    "RlpIsTooShort",
+    "RlpExpectedToBeList",
+    "RlpExpectedToBeData",
+    "RlpIncorrectListLen",
+    "RlpDataLenWithZeroPrefix",
+    "RlpListLenWithZeroPrefix",
+    "RlpInvalidIndirection",
+    "RlpInconsistentLengthAndData",
+    "RlpInvalidLength",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Operation {
+    FromPrivKey,
+    Sign,
+    Recover,
+    Verify,
+    /// Parsing a public key and deriving its address.
+    Address,
+    DecodeSignature,
+}
+
+/// What a backend looks like from outside.
+pub struct Shape {
+    pub name: &'static str,
+    pub privkey_len: usize,
+    pub pubkey_len: usize,
+    pub signature_len: usize,
+    /// The group order as hex, for backends whose private key is a scalar.
+    pub privkey_order: Option<&'static str>,
+    /// Offset of each scalar in the signature, and the group order in that
+    /// scalar's encoding, as hex.
+    pub scalars: &'static [(usize, &'static str)],
+    /// Offset of the recovery id in the signature.
+    pub recovery_id: Option<usize>,
+    /// Offset of the public key carried in the signature.
+    pub embedded_pubkey: Option<usize>,
+    /// The error variants each operation is documented to return.
+    pub errors: fn(Operation) -> &'static [&'static str],
+}
+
+/// One backend, taking and returning bytes so the same corpus runs on all.
+///
+/// Errors are the names of the variants.
+pub trait Backend {
+    fn shape(&self) -> &Shape;
+    /// Private and public key.
+    fn gen_keypair(&self) -> (Vec<u8>, Vec<u8>);
+    fn from_privkey(&self, privkey: &[u8]) -> Result<Vec<u8>, String>;
+    fn sign(&self, privkey: &[u8], message: &[u8]) -> Result<Vec<u8>, String>;
+    fn recover(&self, signature: &[u8], message: &[u8]) -> Result<Vec<u8>, String>;
+    fn verify(&self, signature: &[u8], pubkey: &[u8], message: &[u8]) -> Result<bool, String>;
+    fn address(&self, pubkey: &[u8]) -> Result<Vec<u8>, String>;
+    fn decode_signature(&self, encoded: &[u8]) -> Result<Vec<u8>, String>;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    /// Succeeded, with `true` or `false` for `Verify`.
+    Ok(bool),
+    Err(String),
+    Panic(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct Finding {
+    pub operation: Operation,
+    pub case: String,
+    /// The input must not be accepted: not succeed, or not verify.
+    pub rejected: bool,
+    pub outcome: Outcome,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:?} of {}: {:?}",
+            self.operation, self.case, self.outcome
+        )
+    }
+}
+
+pub struct Report {
+    pub backend: &'static str,
+    pub findings: Vec<Finding>,
+}
+
+impl Report {
+    pub fn panics(&self) -> Vec<&Finding> {
+        self.findings
+            .iter()
+            .filter(|finding| match finding.outcome {
+                Outcome::Panic(_) => true,
+                _ => false,
+            })
+            .collect()
+    }
+
+    /// Errors the operation isn't documented to return.
+    pub fn undocumented(&self, shape: &Shape) -> Vec<&Finding> {
+        self.findings
+            .iter()
+            .filter(|finding| match finding.outcome {
+                Outcome::Err(ref error) => {
+                    error != INVALID_LENGTH
+                        && !(shape.errors)(finding.operation).contains(&error.as_str())
+                }
+                _ => false,
+            })
+            .collect()
+    }
+
+    /// Input which should have been rejected but was accepted. An address
+    /// is only the hash of the key, so any key of the right length has one.
+    pub fn accepted(&self) -> Vec<&Finding> {
+        self.findings
+            .iter()
+            .filter(|finding| {
+                finding.rejected
+                    && finding.operation != Operation::Address
+                    && finding.outcome == Outcome::Ok(true)
+            })
+            .collect()
+    }
+}
+
+fn attempt<T, F>(f: F) -> Result<Result<T, String>, String>
+where
+    F: FnOnce() -> Result<T, String>,
+{
+    panic::catch_unwind(AssertUnwindSafe(f)).map_err(|payload| {
+        payload
+            .downcast_ref::<&str>()
+            .map(|message| (*message).to_owned())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_default()
+    })
+}
+
+struct Runner {
+    findings: Vec<Finding>,
+}
+
+impl Runner {
+    fn record<T, F>(&mut self, operation: Operation, case: &Case, f: F)
+    where
+        F: FnOnce() -> Result<T, String>,
+        T: Accepted,
+    {
+        let outcome = match attempt(f) {
+            Ok(Ok(value)) => Outcome::Ok(value.accepted()),
+            Ok(Err(error)) => Outcome::Err(error),
+            Err(message) => Outcome::Panic(message),
+        };
+        self.findings.push(Finding {
+            operation,
+            case: case.name.clone(),
+            rejected: case.rejected,
+            outcome,
+        });
+    }
+}
+
+/// Whether a successful result means the input was accepted.
+trait Accepted {
+    fn accepted(&self) -> bool;
+}
+
+impl Accepted for bool {
+    fn accepted(&self) -> bool {
+        *self
+    }
+}
+
+impl Accepted for Vec<u8> {
+    fn accepted(&self) -> bool {
+        true
+    }
+}
+
+/// Run every operation on the corpus of `backend`.
+pub fn run(backend: &dyn Backend) -> Report {
+    let (privkey, pubkey) = backend.gen_keypair();
+    let message = MESSAGE;
+    let signature = backend
+        .sign(&privkey, &message)
+        .expect("signing with a generated key");
+    let corpus = Corpus::new(backend.shape(), &privkey, &pubkey, &signature);
+
+    let mut runner = Runner { findings: vec![] };
+    for case in &corpus.privkeys {
+        runner.record(Operation::FromPrivKey, case, || {
+            backend.from_privkey(&case.bytes)
+        });
+        runner.record(Operation::Sign, case, || {
+            backend.sign(&case.bytes, &message)
+        });
+    }
+    for case in &corpus.signatures {
+        for message in &corpus.messages {
+            runner.record(Operation::Recover, case, || {
+                backend.recover(&case.bytes, message)
+            });
+        }
+        runner.record(Operation::Verify, case, || {
+            backend.verify(&case.bytes, &pubkey, &message)
+        });
+    }
+    for case in &corpus.pubkeys {
+        runner.record(Operation::Verify, case, || {
+            backend.verify(&signature, &case.bytes, &message)
+        });
+        runner.record(Operation::Address, case, || backend.address(&case.bytes));
+    }
+    for case in &corpus.encoded_signatures {
+        runner.record(Operation::DecodeSignature, case, || {
+            backend.decode_signature(&case.bytes)
+        });
+    }
+
+    Report {
+        backend: backend.shape().name,
+        findings: runner.findings,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{run, Operation, Outcome};
+    use crate::backends::compiled;
+
+    #[test]
+    fn corpus_is_handled() {
+        for backend in compiled() {
+            let shape = backend.shape();
+            let report = run(&*backend);
+            assert!(!report.findings.is_empty());
+            for (problem, findings) in &[
+                ("panicked", report.panics()),
+                ("returned an undocumented error", report.undocumented(shape)),
+                ("accepted malformed input", report.accepted()),
+            ] {
+                let list: Vec<String> = findings.iter().map(|f| f.to_string()).collect();
+                assert!(
+                    list.is_empty(),
+                    "{} {}:\n{}",
+                    report.backend,
+                    problem,
+                    list.join("\n")
+                );
+            }
+
+            // The untouched inputs are in the corpus, and work.
+            for finding in &report.findings {
+                if finding.case == "valid" && finding.operation != Operation::Recover {
+                    assert_eq!(
+                        finding.outcome,
+                        Outcome::Ok(true),
+                        "{} {}",
+                        report.backend,
+                        finding
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn valid_input_round_trips() {
+        let message = [7u8; 32];
+        for backend in compiled() {
+            let name = backend.shape().name;
+            let (privkey, pubkey) = backend.gen_keypair();
+            assert_eq!(
+                backend.from_privkey(&privkey),
+                Ok(pubkey.clone()),
+                "{}",
+                name
+            );
+            let signature = backend.sign(&privkey, &message).unwrap();
+            assert_eq!(signature.len(), backend.shape().signature_len, "{}", name);
+            assert_eq!(backend.verify(&signature, &pubkey, &message), Ok(true));
+            assert_eq!(backend.recover(&signature, &message), Ok(pubkey.clone()));
+            let encoded = rlp::encode(&signature);
+            assert_eq!(backend.decode_signature(&encoded), Ok(signature));
+            assert_eq!(backend.address(&pubkey).unwrap().len(), 20);
+        }
+    }
+}
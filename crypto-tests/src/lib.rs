@@ -0,0 +1,36 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Negative tests of the signature backends.
+//!
+//! `corpus` holds malformed keys and signatures, and `harness` runs them
+//! through every operation the `cita-crypto` facade offers, on each backend
+//! compiled in, checking that nothing panics and that errors are the ones
+//! the backend is documented to return. `backends` also checks valid input
+//! against independent reference vectors.
+//!
+//! Unlike `cita-crypto`, any number of backends can be compiled in at once,
+//! with one hash:
+//!
+//! ```text
+//! cargo test -p crypto-tests --features secp256k1,sm2,ed25519,sha3hash
+//! ```
+
+pub mod backends;
+pub mod corpus;
+pub mod harness;
+
+pub use crate::backends::compiled;
+pub use crate::corpus::{Case, Corpus};
+pub use crate::harness::{run, Backend, Finding, Operation, Outcome, Report, Shape};
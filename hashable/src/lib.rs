@@ -20,6 +20,8 @@ extern crate libsm;
 extern crate tiny_keccak as sha3;
 
 use cita_types::H256;
+use std::ptr;
+use std::sync::atomic::{compiler_fence, Ordering};
 
 /// The hash of the empty bytes string.
 #[cfg(feature = "sha3hash")]
@@ -72,6 +74,24 @@ pub const HASH_EMPTY_LIST_RLP: H256 = H256([
     0x2b, 0x49, 0x76, 0x8d, 0x46, 0x0f, 0xb1, 0x74, 0x12, 0xc1, 0xb6, 0xc8, 0xfa, 0x64, 0xed, 0x48,
 ]);
 
+/// Name of the hash algorithm this build uses, for stamping data which
+/// must not be read by a build using another one.
+#[cfg(feature = "sha3hash")]
+pub const HASH_NAME: &str = "sha3";
+#[cfg(feature = "blake2bhash")]
+pub const HASH_NAME: &str = "blake2b";
+#[cfg(feature = "sm3hash")]
+pub const HASH_NAME: &str = "sm3";
+
+/// Bytes the hash of this build takes at a time, the block length `B` of
+/// HMAC: the rate of Keccak-256, and the blocks of BLAKE2b and of SM3.
+#[cfg(feature = "sha3hash")]
+pub const HASH_BLOCK_LEN: usize = 136;
+#[cfg(feature = "blake2bhash")]
+pub const HASH_BLOCK_LEN: usize = 128;
+#[cfg(feature = "sm3hash")]
+pub const HASH_BLOCK_LEN: usize = 64;
+
 #[cfg(feature = "blake2bhash")]
 pub const BLAKE2BKEY: &str = "CryptapeCryptape";
 
@@ -130,10 +150,113 @@ where
     }
 }
 
+const HMAC_LEN: usize = 32;
+
+/// Overwrite `bytes` with zeros in a way the optimizer won't drop, for
+/// keys and whatever was derived from them.
+pub fn wipe(bytes: &mut [u8]) {
+    for byte in bytes.iter_mut() {
+        unsafe { ptr::write_volatile(byte, 0) };
+    }
+    compiler_fence(Ordering::SeqCst);
+}
+
+/// Compare without returning early, so timing doesn't tell where the first
+/// difference is, as MACs and keys must be. Only the lengths are compared
+/// in the open.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter()
+        .zip(b.iter())
+        .fold(0u8, |acc, (x, y)| acc | (x ^ y))
+        == 0
+}
+
+/// HMAC (RFC 2104) of `parts`, one after the other, with `hash`, a hash of
+/// 32 bytes over blocks of `block_len`, `HASH_BLOCK_LEN` for the hash of
+/// the build. The buffers holding the key are wiped before returning.
+pub fn hmac_with<F>(hash: F, block_len: usize, key: &[u8], parts: &[&[u8]]) -> [u8; HMAC_LEN]
+where
+    F: Fn(&[u8]) -> [u8; HMAC_LEN],
+{
+    let mut block = vec![0u8; block_len];
+    if key.len() > block_len {
+        block[..HMAC_LEN].copy_from_slice(&hash(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+
+    let len = parts.iter().map(|part| part.len()).sum::<usize>();
+    let mut inner = Vec::with_capacity(block_len + len);
+    inner.extend(block.iter().map(|b| b ^ 0x36));
+    for part in parts {
+        inner.extend_from_slice(part);
+    }
+    let inner_hash = hash(&inner[..]);
+
+    let mut outer = Vec::with_capacity(block_len + HMAC_LEN);
+    outer.extend(block.iter().map(|b| b ^ 0x5c));
+    outer.extend_from_slice(&inner_hash);
+    let mac = hash(&outer[..]);
+
+    wipe(&mut block);
+    wipe(&mut inner);
+    wipe(&mut outer);
+    mac
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn hmac_parts() {
+        // Not a real hash, only enough to tell where the bytes went.
+        let hash = |data: &[u8]| {
+            let mut out = [0u8; 32];
+            for (i, byte) in data.iter().enumerate() {
+                out[i % 32] = out[i % 32].wrapping_mul(31).wrapping_add(*byte);
+            }
+            out
+        };
+        let key = [0x0bu8; 20];
+        assert_eq!(
+            hmac_with(hash, 64, &key, &[b"Hi ", b"There"]),
+            hmac_with(hash, 64, &key, &[b"Hi There"])
+        );
+        assert_ne!(
+            hmac_with(hash, 64, &key, &[b"Hi There"]),
+            hmac_with(hash, 64, &[0x0cu8; 20], &[b"Hi There"])
+        );
+        // Only a key longer than a block is hashed first.
+        let long_key = [0x0bu8; 100];
+        assert_eq!(
+            hmac_with(hash, 64, &long_key, &[b"Hi There"]),
+            hmac_with(hash, 64, &hash(&long_key), &[b"Hi There"])
+        );
+        assert_ne!(
+            hmac_with(hash, 136, &long_key, &[b"Hi There"]),
+            hmac_with(hash, 136, &hash(&long_key), &[b"Hi There"])
+        );
+        assert_ne!(
+            hmac_with(hash, 64, &key, &[b"Hi There"]),
+            hmac_with(hash, 136, &key, &[b"Hi There"])
+        );
+    }
+
+    #[test]
+    fn wipe_and_compare() {
+        let mut bytes = [7u8; 16];
+        wipe(&mut bytes);
+        assert_eq!(bytes, [0u8; 16]);
+        assert!(constant_time_eq(&[], &[]));
+        assert!(constant_time_eq(&[1, 2], &[1, 2]));
+        assert!(!constant_time_eq(&[1, 2], &[1, 3]));
+        assert!(!constant_time_eq(&[1, 2], &[1, 2, 3]));
+    }
+
     #[test]
     #[cfg(feature = "sha3hash")]
     fn sha3_empty() {
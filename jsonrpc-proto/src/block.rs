@@ -17,6 +17,7 @@ use jsonrpc_types::rpc_types::{
     Block, BlockBody, BlockHeader, BlockTransaction, FullTransaction, Proof, RpcBlock,
 };
 use jsonrpc_types::Error;
+use libproto::body::BodyPolicy;
 
 use crate::from_into::TryFromProto;
 
@@ -24,6 +25,15 @@ pub trait BlockExt {
     type Error;
 
     fn try_from_rpc_block(rpc_block: RpcBlock) -> Result<Block, Self::Error>;
+
+    /// Like `try_from_rpc_block`, refusing a body the given `policy`
+    /// doesn't pass, see `libproto::body`. `BodyPolicy::default()` checks
+    /// the limits, root, duplicates and signatures, not the nonce order,
+    /// which needs `ordered_nonces`.
+    fn try_from_rpc_block_strict(
+        rpc_block: RpcBlock,
+        policy: &BodyPolicy,
+    ) -> Result<Block, Self::Error>;
 }
 
 impl TryFromProto<libproto::BlockHeader> for BlockHeader {
@@ -58,31 +68,50 @@ impl BlockExt for Block {
     type Error = Error;
 
     fn try_from_rpc_block(rpc_block: RpcBlock) -> Result<Self, Self::Error> {
-        use crate::error::ErrorExt;
-        use libproto::TryFrom;
+        from_rpc_block(rpc_block, None)
+    }
 
-        let mut blk = libproto::Block::try_from(&rpc_block.block) // from chain
-            .map_err(|err| Error::rpc_block_decode_error(Box::new(err)))?;
+    fn try_from_rpc_block_strict(
+        rpc_block: RpcBlock,
+        policy: &BodyPolicy,
+    ) -> Result<Self, Self::Error> {
+        from_rpc_block(rpc_block, Some(policy))
+    }
+}
 
-        let block_transactions = blk.take_body().take_transactions();
-        let transactions = if rpc_block.include_txs {
-            block_transactions
-                .into_iter()
-                .map(|x| FullTransaction::try_from_proto(x).map(BlockTransaction::Full))
-                .collect::<Result<Vec<BlockTransaction>, Error>>()?
-        } else {
-            block_transactions
-                .into_iter()
-                .map(|x| BlockTransaction::Hash(H256::from_slice(x.get_tx_hash())))
-                .collect()
-        };
-        let header = BlockHeader::try_from_proto(blk.take_header())?;
+fn from_rpc_block(rpc_block: RpcBlock, policy: Option<&BodyPolicy>) -> Result<Block, Error> {
+    use crate::error::ErrorExt;
+    use libproto::TryFrom;
 
-        Ok(Block {
-            version: blk.version,
-            header,
-            body: BlockBody { transactions },
-            hash: H256::from_slice(&rpc_block.hash),
-        })
+    let mut blk = libproto::Block::try_from(&rpc_block.block) // from chain
+        .map_err(|err| Error::rpc_block_decode_error(Box::new(err)))?;
+    if let Some(policy) = policy {
+        let transactions_root = H256::from(blk.get_header().get_transactions_root());
+        blk.get_body()
+            .validate(policy, &transactions_root)
+            .map_err(Error::rpc_block_body_error)?;
     }
+
+    let block_transactions = blk.take_body().take_transactions();
+    let transactions = if rpc_block.include_txs {
+        block_transactions
+            .into_iter()
+            .map(|x| FullTransaction::try_from_proto(x).map(BlockTransaction::Full))
+            .collect::<Result<Vec<BlockTransaction>, Error>>()?
+    } else {
+        block_transactions
+            .into_iter()
+            .map(|x| BlockTransaction::Hash(H256::from_slice(x.get_tx_hash())))
+            .collect()
+    };
+    let header = BlockHeader::try_from_proto(blk.take_header())?;
+
+    Ok(Block {
+        version: blk.version,
+        header,
+        body: BlockBody { transactions },
+        hash: H256::from_slice(&rpc_block.hash),
+        canonical: None,
+        confirmations: None,
+    })
 }
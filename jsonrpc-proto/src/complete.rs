@@ -22,7 +22,8 @@ use jsonrpc_types::rpc_request::{
     SendTransactionParams, UninstallFilterParams,
 };
 use jsonrpc_types::rpc_request::{Call, JsonRpcRequest, PartialCall, PartialRequest, Request};
-use jsonrpc_types::{rpc_types::Params as PartialParams, Error};
+use jsonrpc_types::rpc_types::{parse_params, Params as PartialParams};
+use jsonrpc_types::Error;
 use libproto::Request as ProtoRequest;
 use serde_json;
 
@@ -67,7 +68,7 @@ macro_rules! partial_call_complete {
                                 if pparams.len() != $params_name::required_len() {
                                     Err(Error::invalid_params_len())
                                 } else {
-                                    Ok(Call::$enum_name{ params: serde_json::from_value(params)? })
+                                    Ok(Call::$enum_name{ params: parse_params(params)? })
                                 }
                             } else {
                                 if $params_name::required_len() == 0 {
@@ -109,6 +110,8 @@ impl CompleteInto for PartialRequest {
 mod tests {
     use super::*;
     use cita_types::H256;
+    use jsonrpc_types::ErrorCode;
+    use serde_json::{json, Value};
 
     #[test]
     fn test_get_transaction_receipt_params_complete() {
@@ -192,4 +195,62 @@ mod tests {
             Error::method_not_found()
         );
     }
+
+    // Inputs of the `call` fuzz target which panicked or were taken wrongly.
+    #[test]
+    fn test_fuzzed_params_complete_error() {
+        let long = format!("0x{}", "f".repeat(100_000));
+        let deep: Value =
+            serde_json::from_str(&format!("{}{}", "[".repeat(100), "]".repeat(100))).unwrap();
+        let testdata = vec![
+            (
+                json!({"method": "getBlockByNumber", "params": [long, false]}),
+                "too large",
+            ),
+            (
+                json!({"method": "getBalance", "params": [long, "latest"]}),
+                "longer than",
+            ),
+            (
+                json!({"method": "getTransactionReceipt", "params": [long]}),
+                "longer than",
+            ),
+            (
+                json!({"method": "uninstallFilter", "params": [long]}),
+                "too large",
+            ),
+            (
+                json!({"method": "getBlockByNumber", "params": [1e5, false]}),
+                "floating point",
+            ),
+            (
+                json!({"method": "getLogs", "params": [{"fromBlock": 1e5}]}),
+                "floating point",
+            ),
+            (
+                json!({"method": "getFilterLogs", "params": [1.5]}),
+                "floating point",
+            ),
+            (
+                json!({"method": "getBlockByNumber", "params": [deep, false]}),
+                "nested deeper",
+            ),
+            (
+                json!({"method": "getLogs", "params": [{"topics": deep}]}),
+                "nested deeper",
+            ),
+        ];
+        for (mut request, expected) in testdata.into_iter() {
+            request["jsonrpc"] = json!("2.0");
+            request["id"] = json!(1);
+            let req_str = request.to_string();
+            assert!(serde_json::from_str::<Call>(&req_str).is_err());
+            assert!(serde_json::from_str::<Request>(&req_str).is_err());
+
+            let part_req = serde_json::from_str::<PartialRequest>(&req_str).unwrap();
+            let err = part_req.complete().unwrap_err();
+            assert_eq!(err.code, ErrorCode::InvalidParams);
+            assert!(err.message.contains(expected), "{}", err.message);
+        }
+    }
 }
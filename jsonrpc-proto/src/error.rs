@@ -32,6 +32,11 @@ pub trait ErrorExt {
         Error::server_error(ERR_CODE_INTERNAL_ERROR, err.to_string())
     }
 
+    fn rpc_block_body_error(err: libproto::body::BodyError) -> Error {
+        error!("jsonrpc_proto: invalid block body from chain {}", err);
+        Error::server_error(ERR_CODE_INTERNAL_ERROR, err.to_string())
+    }
+
     fn transaction_data_encode_error(err: libproto::TryIntoConvertError) -> Error {
         error!("jsonrpc_proto: fail to encode content {:?}", err);
         Error::server_error(ERR_CODE_INTERNAL_ERROR, ERR_MSG_TX_CONTENT_ENCODE_ERROR)
@@ -27,4 +27,5 @@ pub mod from_into;
 pub mod proof;
 pub mod request;
 pub mod response;
+pub mod timeline;
 pub mod transaction;
@@ -12,7 +12,8 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use cita_types::{clean_0x, traits::LowerHex};
+use cita_types::hex::{FromHexErrorKind, FromHexStr};
+use cita_types::{clean_0x, traits::LowerHex, Address};
 use jsonrpc_types::{
     rpc_request::*, // bring in varied Params
     rpc_types::{BlockParamsByHash, BlockParamsByNumber, CountOrCode},
@@ -425,7 +426,6 @@ impl TryIntoProto<ProtoRequest> for EstimateQuotaParams {
 impl SendRawTransactionParamsExt for SendRawTransactionParams {
     fn extract_unverified_tx(data: &[u8]) -> Result<UnverifiedTransaction, Error> {
         use libproto::TryFrom;
-        use rustc_serialize::hex::FromHex;
 
         let un_tx = UnverifiedTransaction::try_from(data).map_err(|_err| {
             let err_msg = format!(
@@ -438,15 +438,16 @@ impl SendRawTransactionParamsExt for SendRawTransactionParams {
             let tx = un_tx.get_transaction();
             let version = tx.get_version();
             if version == 0 {
-                let to = clean_0x(tx.get_to());
-                if to.len() != 40 && !to.is_empty() {
-                    return Err(Error::invalid_params(
-                        "param 'to' has invalid length, expected 40, or are you creating contract?",
-                    ));
-                } else {
-                    let _ = to.from_hex().map_err(|err| {
-                        let err_msg = format!("param not hex string : {:?}", err);
-                        Error::parse_error_with_message(err_msg)
+                let to = tx.get_to();
+                if !clean_0x(to).is_empty() {
+                    Address::from_hex_str(to).map_err(|err| match err.kind() {
+                        FromHexErrorKind::InvalidLength { .. } => Error::invalid_params(
+                            "param 'to' has invalid length, expected 40, or are you creating contract?",
+                        ),
+                        _ => Error::parse_error_with_message(format!(
+                            "param not hex string : {}",
+                            err
+                        )),
                     })?;
                 }
                 trace!(
@@ -13,7 +13,7 @@
 // limitations under the License.
 
 use jsonrpc_types::{
-    rpc_request::{RequestInfo, ResponseResult},
+    rpc_request::{LogsResult, RequestInfo, ResponseResult},
     rpc_response::{Output, RpcFailure, RpcSuccess},
     rpc_types::{
         Block, FilterChanges, Log, MetaData, PeersInfo, Receipt, RpcBlock, RpcTransaction,
@@ -77,7 +77,7 @@ impl OutputExt for Output {
                         success.set_result(ResponseResult::Call(x.into())).output()
                     }
                     Response_oneof_data::logs(serialized) => {
-                        serde_json::from_str::<Vec<Log>>(&serialized)
+                        serde_json::from_str::<LogsResult>(&serialized)
                             .map(|logs| success.set_result(ResponseResult::GetLogs(logs)).output())
                             .unwrap_or_else(|_| Output::system_error(0))
                     }
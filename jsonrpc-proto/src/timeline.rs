@@ -0,0 +1,92 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use jsonrpc_types::rpc_types::{Data, TimelineStage, TransactionTimeline};
+use libproto::lifecycle::Timeline;
+
+use crate::from_into::FromProto;
+
+impl<'a> FromProto<&'a Timeline> for TransactionTimeline {
+    fn from_proto(timeline: &Timeline) -> Self {
+        let latencies = timeline.latencies();
+        let stages = timeline
+            .events()
+            .into_iter()
+            .enumerate()
+            .map(|(i, event)| {
+                let height = event.get_height();
+                let detail = event.get_detail();
+                TimelineStage {
+                    stage: event.get_stage().name().to_owned(),
+                    timestamp: event.get_timestamp(),
+                    height: height.map(Into::into),
+                    detail: if height.is_none() && !detail.is_empty() {
+                        Some(Data::new(detail.to_vec()))
+                    } else {
+                        None
+                    },
+                    latency: i
+                        .checked_sub(1)
+                        .and_then(|i| latencies.get(i))
+                        .and_then(|latency| latency.millis),
+                }
+            })
+            .collect();
+        TransactionTimeline {
+            hash: timeline.get_tx_hash(),
+            stages,
+            missing: timeline
+                .missing()
+                .into_iter()
+                .map(|stage| stage.name().to_owned())
+                .collect(),
+            complete: timeline.is_complete(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::from_into::FromProto;
+    use cita_types::H256;
+    use jsonrpc_types::rpc_types::{Data, TransactionTimeline};
+    use libproto::lifecycle::{LifecycleCollector, TxLifecycleEvent, TxStage};
+    use std::time::Duration;
+
+    #[test]
+    fn timeline_from_the_collector() {
+        let hash = H256::from(1);
+        let mut collector = LifecycleCollector::new(8, Duration::from_secs(60));
+        collector.ingest(TxLifecycleEvent::new(hash, TxStage::Proposed, 1_300).with_height(42));
+        collector
+            .ingest(TxLifecycleEvent::new(hash, TxStage::Received, 1_000).with_detail(vec![1, 2]));
+        collector.ingest(TxLifecycleEvent::new(hash, TxStage::Executed, 1_250).with_height(42));
+
+        let timeline =
+            TransactionTimeline::from_proto(collector.timeline(&hash).expect("collected"));
+        assert_eq!(timeline.hash, hash);
+        assert!(!timeline.complete);
+        assert_eq!(timeline.missing, vec!["verified", "pooled"]);
+
+        let stages: Vec<&str> = timeline.stages.iter().map(|s| s.stage.as_str()).collect();
+        assert_eq!(stages, vec!["received", "proposed", "executed"]);
+        assert_eq!(timeline.stages[0].detail, Some(Data::new(vec![1, 2])));
+        assert_eq!(timeline.stages[0].height, None);
+        assert_eq!(timeline.stages[1].height, Some(42u64.into()));
+        assert_eq!(timeline.stages[1].detail, None);
+        let latencies: Vec<Option<u64>> = timeline.stages.iter().map(|s| s.latency).collect();
+        // The executor's clock is behind.
+        assert_eq!(latencies, vec![None, Some(300), None]);
+    }
+}
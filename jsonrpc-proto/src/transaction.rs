@@ -91,6 +91,7 @@ mod tests {
 
     use super::*;
     use cita_crypto::{CreateKey, KeyPair};
+    use proof_srv::testchain::{Anomaly, ChainBuilder, TestChain};
 
     fn new_dummy_signed_ptx() -> (KeyPair, ProtoSignedTransaction) {
         let keypair = KeyPair::gen_keypair();
@@ -106,7 +107,7 @@ mod tests {
         ptx.set_chain_id_v1(vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 0]);
         ptx.set_version(1);
 
-        let sig_ptx = ptx.sign(*keypair.privkey());
+        let sig_ptx = ptx.sign(keypair.privkey());
         (keypair, sig_ptx)
     }
 
@@ -153,4 +154,79 @@ mod tests {
         assert_eq!(rpc_tx.block_hash, block_hash);
         assert_eq!(rpc_tx.index, U256::from(0));
     }
+
+    fn dummy_chain() -> TestChain {
+        ChainBuilder::new(2077, 4)
+            .at(1, Anomaly::Version(1))
+            .build(2)
+    }
+
+    #[test]
+    fn full_transaction_of_chain_block() {
+        let chain = dummy_chain();
+        let block = chain.block(2);
+        let sig_ptx = block.transactions()[0].clone();
+
+        let mut full_tx = ProtoFullTransaction::new();
+        full_tx.set_transaction(sig_ptx.clone());
+        full_tx.set_block_number(block.height());
+        full_tx.set_block_hash(block.hash.to_vec());
+        full_tx.set_index(0);
+
+        let rpc_tx = RpcTransaction::try_from_proto(full_tx).unwrap();
+        assert_eq!(rpc_tx.hash, H256::from_slice(sig_ptx.get_tx_hash()));
+        assert_eq!(rpc_tx.from, sig_ptx.from());
+        assert!(chain.senders().position(&rpc_tx.from).is_some());
+        assert_eq!(rpc_tx.block_number, U256::from(2));
+        assert_eq!(rpc_tx.block_hash, block.hash);
+        assert_eq!(rpc_tx.index, U256::from(0));
+    }
+
+    #[test]
+    fn data_survives_the_round_trip() {
+        use jsonrpc_types::rpc_types::Log;
+        use libproto::TryFrom;
+
+        let chain = dummy_chain();
+        let block = chain.block(2);
+        let template = block.transactions()[0].get_transaction().clone();
+        for len in &[0, 1, 3, 32, 57, 1000, 1024 * 1024] {
+            let bytes: Vec<u8> = (0..*len).map(|i: usize| (i * 31 + i / 251) as u8).collect();
+            let json = serde_json::to_string(&Data::new(bytes.clone())).unwrap();
+
+            // Sent to the RPC, signed into the proto.
+            let data: Data = serde_json::from_str(&json).unwrap();
+            let mut tx = template.clone();
+            tx.set_data(data.into());
+            let stx = chain.sign(0, tx);
+            assert_eq!(stx.get_transaction().get_data(), &bytes[..]);
+
+            // Given back by the RPC, and read from its content.
+            let full_tx = FullTransaction::try_from_proto(stx.clone()).unwrap();
+            let full_tx: FullTransaction =
+                serde_json::from_str(&serde_json::to_string(&full_tx).unwrap()).unwrap();
+            let content: Vec<u8> = full_tx.content.into();
+            let utx = ProtoUnverifiedTransaction::try_from(&content).unwrap();
+            let verified = libproto::SignedTransaction::verify_transaction(utx).unwrap();
+            assert_eq!(verified.from(), chain.senders().address(0));
+            assert_eq!(verified.get_transaction().get_data(), &bytes[..]);
+
+            // Logged by the executor, in the receipt.
+            let log = Log {
+                address: chain.senders().address(0),
+                topics: vec![],
+                data: Data::new(verified.get_transaction().get_data().to_vec()),
+                block_hash: Some(block.hash),
+                block_number: Some(U256::from(2)),
+                transaction_hash: Some(stx.crypt_hash()),
+                transaction_index: Some(U256::from(0)),
+                log_index: Some(U256::from(0)),
+                transaction_log_index: Some(U256::from(0)),
+            };
+            let serialized = serde_json::to_string(&log).unwrap();
+            let log: Log = serde_json::from_str(&serialized).unwrap();
+            let logged: Vec<u8> = log.data.into();
+            assert_eq!(logged, bytes);
+        }
+    }
 }
@@ -0,0 +1,36 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Requests as the server parses them, whole and then completed with the
+//! params of each call, which may fail but never panic. Run with `cargo
+//! fuzz run call`. Inputs it found go to the tests of jsonrpc-proto's
+//! `complete`.
+
+#![no_main]
+
+use jsonrpc_proto::complete::Complete;
+use jsonrpc_types::rpc_request::{Call, RpcRequest};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = serde_json::from_slice::<Call>(data);
+    let requests = match serde_json::from_slice::<RpcRequest>(data) {
+        Ok(RpcRequest::Single(request)) => vec![request],
+        Ok(RpcRequest::Batch(requests)) => requests,
+        Err(_) => return,
+    };
+    for request in requests {
+        let _ = request.complete();
+    }
+});
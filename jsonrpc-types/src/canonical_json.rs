@@ -0,0 +1,823 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Canonical JSON of block headers and blocks, for audit trails kept and
+//! signed outside the chain.
+//!
+//! The form follows RFC 8785: keys sorted, no whitespace, the shortest
+//! string escapes. Hashes and addresses are lowercase `0x` hex, `U256`
+//! values decimal strings, and the other integers plain numbers, up to
+//! `MAX_SAFE_INTEGER`. Above it an integer is a decimal string too, as
+//! I-JSON readers can't keep it exact as a number. A block only carries
+//! its header and the hashes of its transactions.
+//!
+//! Parsing accepts nothing but this form, so one value has exactly one
+//! text and one hash.
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::error;
+use std::fmt;
+
+use cita_types::{Address, H256, U256};
+use hashable::Hashable;
+use rustc_serialize::hex::{FromHex, ToHex};
+
+use crate::rpc_types::{BftProof, Block, BlockBody, BlockHeader, BlockTransaction, Proof};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CanonicalJsonError {
+    /// Not canonical JSON at this byte offset.
+    Syntax(usize),
+    /// An object key which isn't greater than the one before it.
+    KeyOrder(String),
+    MissingField(&'static str),
+    UnknownField(String),
+    /// A field whose value has the wrong type or is written the wrong way.
+    InvalidField(&'static str),
+}
+
+impl fmt::Display for CanonicalJsonError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CanonicalJsonError::Syntax(offset) => {
+                write!(f, "not canonical JSON at byte {}", offset)
+            }
+            CanonicalJsonError::KeyOrder(ref key) => write!(f, "key {} out of order", key),
+            CanonicalJsonError::MissingField(field) => write!(f, "missing field {}", field),
+            CanonicalJsonError::UnknownField(ref field) => write!(f, "unknown field {}", field),
+            CanonicalJsonError::InvalidField(field) => write!(f, "invalid field {}", field),
+        }
+    }
+}
+
+impl error::Error for CanonicalJsonError {}
+
+/// A type with a canonical JSON form.
+pub trait CanonicalJson: Sized {
+    fn to_value(&self) -> Value;
+    fn from_value(value: Value) -> Result<Self, CanonicalJsonError>;
+}
+
+pub fn to_canonical_json<T: CanonicalJson>(value: &T) -> String {
+    let mut out = String::new();
+    value.to_value().write(&mut out);
+    out
+}
+
+pub fn from_canonical_json<T: CanonicalJson>(json: &str) -> Result<T, CanonicalJsonError> {
+    let mut parser = Parser {
+        input: json.as_bytes(),
+        pos: 0,
+    };
+    let value = parser.value()?;
+    if parser.pos != json.len() {
+        return Err(CanonicalJsonError::Syntax(parser.pos));
+    }
+    T::from_value(value)
+}
+
+/// Hash of the canonical JSON, with the hash the crate is built with.
+pub fn canonical_json_hash<T: CanonicalJson>(value: &T) -> H256 {
+    to_canonical_json(value).crypt_hash()
+}
+
+/// The largest integer a canonical form writes as a number, 2^53 - 1.
+pub const MAX_SAFE_INTEGER: u64 = (1 << 53) - 1;
+
+/// The JSON values canonical forms are made of. Objects keep their keys
+/// sorted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    Null,
+    Number(u64),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    fn object(mut fields: Vec<(String, Value)>) -> Self {
+        fields.sort_by(|a, b| a.0.cmp(&b.0));
+        Value::Object(fields)
+    }
+
+    fn hex(bytes: &[u8]) -> Self {
+        Value::String(format!("0x{}", bytes.to_hex()))
+    }
+
+    fn write(&self, out: &mut String) {
+        match *self {
+            Value::Null => out.push_str("null"),
+            Value::Number(n) if n > MAX_SAFE_INTEGER => write_string(&n.to_string(), out),
+            Value::Number(n) => out.push_str(&n.to_string()),
+            Value::String(ref s) => write_string(s, out),
+            Value::Array(ref items) => {
+                out.push('[');
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    item.write(out);
+                }
+                out.push(']');
+            }
+            Value::Object(ref fields) => {
+                out.push('{');
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    write_string(key, out);
+                    out.push(':');
+                    value.write(out);
+                }
+                out.push('}');
+            }
+        }
+    }
+
+    fn into_number(self, field: &'static str) -> Result<u64, CanonicalJsonError> {
+        match self {
+            Value::Number(n) => Ok(n),
+            // Only written as a string if it is too large for a number.
+            Value::String(ref s) => parse_u256(s)
+                .filter(|n| *n > U256::from(MAX_SAFE_INTEGER) && *n <= U256::from(::std::u64::MAX))
+                .map(|n| n.low_u64())
+                .ok_or(CanonicalJsonError::InvalidField(field)),
+            _ => Err(CanonicalJsonError::InvalidField(field)),
+        }
+    }
+
+    fn into_string(self, field: &'static str) -> Result<String, CanonicalJsonError> {
+        match self {
+            Value::String(s) => Ok(s),
+            _ => Err(CanonicalJsonError::InvalidField(field)),
+        }
+    }
+
+    fn into_u256(self, field: &'static str) -> Result<U256, CanonicalJsonError> {
+        parse_u256(&self.into_string(field)?).ok_or(CanonicalJsonError::InvalidField(field))
+    }
+
+    fn into_h256(self, field: &'static str) -> Result<H256, CanonicalJsonError> {
+        parse_hex(&self.into_string(field)?, 32)
+            .map(|bytes| H256::from_slice(&bytes))
+            .ok_or(CanonicalJsonError::InvalidField(field))
+    }
+
+    fn into_address(self, field: &'static str) -> Result<Address, CanonicalJsonError> {
+        parse_hex(&self.into_string(field)?, 20)
+            .map(|bytes| Address::from_slice(&bytes))
+            .ok_or(CanonicalJsonError::InvalidField(field))
+    }
+
+    /// The values of an object which has exactly the `keys`, in sorted
+    /// order.
+    fn into_fields(
+        self,
+        field: &'static str,
+        keys: &[&'static str],
+    ) -> Result<Vec<Value>, CanonicalJsonError> {
+        let fields = match self {
+            Value::Object(fields) => fields,
+            _ => return Err(CanonicalJsonError::InvalidField(field)),
+        };
+        let mut wanted = keys.iter();
+        let mut values = Vec::with_capacity(keys.len());
+        for (key, value) in fields {
+            match wanted.next() {
+                Some(&expected) if expected == key => values.push(value),
+                Some(&expected) if expected < key.as_str() => {
+                    return Err(CanonicalJsonError::MissingField(expected));
+                }
+                _ => return Err(CanonicalJsonError::UnknownField(key)),
+            }
+        }
+        match wanted.next() {
+            Some(&missing) => Err(CanonicalJsonError::MissingField(missing)),
+            None => Ok(values),
+        }
+    }
+}
+
+fn write_string(s: &str, out: &mut String) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\u{8}' => out.push_str("\\b"),
+            '\u{c}' => out.push_str("\\f"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c < ' ' => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+/// `0x` and lowercase hex of exactly `len` bytes.
+fn parse_hex(s: &str, len: usize) -> Option<Vec<u8>> {
+    if !s.starts_with("0x") {
+        return None;
+    }
+    let digits = &s[2..];
+    if digits.len() != len * 2
+        || !digits
+            .bytes()
+            .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+    {
+        return None;
+    }
+    digits.from_hex().ok()
+}
+
+/// Decimal without leading zeros, in range.
+fn parse_u256(s: &str) -> Option<U256> {
+    if s.is_empty() || (s.len() > 1 && s.starts_with('0')) {
+        return None;
+    }
+    let ten = U256::from(10);
+    let mut n = U256::zero();
+    for b in s.bytes() {
+        if !b.is_ascii_digit() {
+            return None;
+        }
+        let (shifted, over_mul) = n.overflowing_mul(ten);
+        let (sum, over_add) = shifted.overflowing_add(U256::from(b - b'0'));
+        if over_mul || over_add {
+            return None;
+        }
+        n = sum;
+    }
+    Some(n)
+}
+
+struct Parser<'a> {
+    input: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error<T>(&self) -> Result<T, CanonicalJsonError> {
+        Err(CanonicalJsonError::Syntax(self.pos))
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.pos).copied()
+    }
+
+    fn eat(&mut self, b: u8) -> Result<(), CanonicalJsonError> {
+        if self.peek() != Some(b) {
+            return self.error();
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn value(&mut self) -> Result<Value, CanonicalJsonError> {
+        match self.peek() {
+            Some(b'n') => {
+                if !self.input[self.pos..].starts_with(b"null") {
+                    return self.error();
+                }
+                self.pos += 4;
+                Ok(Value::Null)
+            }
+            Some(b'0'..=b'9') => self.number().map(Value::Number),
+            Some(b'"') => self.string().map(Value::String),
+            Some(b'[') => self.array(),
+            Some(b'{') => self.object(),
+            _ => self.error(),
+        }
+    }
+
+    fn number(&mut self) -> Result<u64, CanonicalJsonError> {
+        let start = self.pos;
+        while let Some(b'0'..=b'9') = self.peek() {
+            self.pos += 1;
+        }
+        let digits = &self.input[start..self.pos];
+        if digits.len() > 1 && digits[0] == b'0' {
+            return Err(CanonicalJsonError::Syntax(start));
+        }
+        // Fractions and exponents are never canonical for an integer.
+        if let Some(b'.') | Some(b'e') | Some(b'E') = self.peek() {
+            return self.error();
+        }
+        digits
+            .iter()
+            .try_fold(0u64, |n, &b| {
+                n.checked_mul(10)?.checked_add(u64::from(b - b'0'))
+            })
+            .filter(|n| *n <= MAX_SAFE_INTEGER)
+            .ok_or(CanonicalJsonError::Syntax(start))
+    }
+
+    fn string(&mut self) -> Result<String, CanonicalJsonError> {
+        self.eat(b'"')?;
+        let mut out = Vec::new();
+        loop {
+            match self.peek() {
+                None => return self.error(),
+                Some(b'"') => break,
+                Some(b'\\') => {
+                    self.pos += 1;
+                    let unescaped = match self.peek() {
+                        Some(b'"') => b'"',
+                        Some(b'\\') => b'\\',
+                        Some(b'b') => 8,
+                        Some(b'f') => 12,
+                        Some(b'n') => b'\n',
+                        Some(b'r') => b'\r',
+                        Some(b't') => b'\t',
+                        Some(b'u') => self.control_escape()?,
+                        _ => return self.error(),
+                    };
+                    out.push(unescaped);
+                }
+                Some(b) if b < b' ' => return self.error(),
+                Some(b) => out.push(b),
+            }
+            self.pos += 1;
+        }
+        self.pos += 1;
+        // The input is a `str`, and escapes only make ASCII.
+        Ok(String::from_utf8(out).expect("slices of a str at ASCII boundaries"))
+    }
+
+    /// The `\u` escape of a control character without a short escape,
+    /// leaving `pos` on its last digit.
+    fn control_escape(&mut self) -> Result<u8, CanonicalJsonError> {
+        let start = self.pos - 1;
+        let digits = self.input.get(self.pos + 1..self.pos + 5).unwrap_or(&[]);
+        let valid = digits.len() == 4
+            && digits
+                .iter()
+                .all(|&b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b));
+        let c = if valid {
+            u8::from_str_radix(std::str::from_utf8(digits).unwrap(), 16).unwrap_or(0xff)
+        } else {
+            0xff
+        };
+        let short = [8, 9, 10, 12, 13].contains(&c);
+        if c >= b' ' || short {
+            return Err(CanonicalJsonError::Syntax(start));
+        }
+        self.pos += 4;
+        Ok(c)
+    }
+
+    fn array(&mut self) -> Result<Value, CanonicalJsonError> {
+        self.eat(b'[')?;
+        let mut items = Vec::new();
+        if self.peek() != Some(b']') {
+            loop {
+                items.push(self.value()?);
+                if self.peek() != Some(b',') {
+                    break;
+                }
+                self.pos += 1;
+            }
+        }
+        self.eat(b']')?;
+        Ok(Value::Array(items))
+    }
+
+    fn object(&mut self) -> Result<Value, CanonicalJsonError> {
+        self.eat(b'{')?;
+        let mut fields: Vec<(String, Value)> = Vec::new();
+        if self.peek() != Some(b'}') {
+            loop {
+                let key = self.string()?;
+                if fields.last().map_or(false, |last| last.0 >= key) {
+                    return Err(CanonicalJsonError::KeyOrder(key));
+                }
+                self.eat(b':')?;
+                let value = self.value()?;
+                fields.push((key, value));
+                if self.peek() != Some(b',') {
+                    break;
+                }
+                self.pos += 1;
+            }
+        }
+        self.eat(b'}')?;
+        Ok(Value::Object(fields))
+    }
+}
+
+const HEADER_KEYS: [&str; 9] = [
+    "number",
+    "prevHash",
+    "proof",
+    "proposer",
+    "quotaUsed",
+    "receiptsRoot",
+    "stateRoot",
+    "timestamp",
+    "transactionsRoot",
+];
+const BFT_KEYS: [&str; 4] = ["commits", "height", "proposal", "round"];
+const BLOCK_KEYS: [&str; 4] = ["hash", "header", "transactionHashes", "version"];
+
+fn proof_to_value(proof: &Option<Proof>) -> Value {
+    match *proof {
+        None => Value::Null,
+        Some(Proof::Raft) => Value::String("Raft".to_owned()),
+        Some(Proof::Bft(ref bft)) => {
+            let commits = bft
+                .commits
+                .iter()
+                .map(|(address, commit)| {
+                    (
+                        format!("0x{}", address.to_hex()),
+                        Value::String(commit.clone()),
+                    )
+                })
+                .collect();
+            let bft = Value::object(vec![
+                ("commits".to_owned(), Value::object(commits)),
+                ("height".to_owned(), Value::Number(bft.height as u64)),
+                ("proposal".to_owned(), Value::hex(&bft.proposal)),
+                ("round".to_owned(), Value::Number(bft.round as u64)),
+            ]);
+            Value::object(vec![("Bft".to_owned(), bft)])
+        }
+    }
+}
+
+fn proof_from_value(value: Value) -> Result<Option<Proof>, CanonicalJsonError> {
+    let bft = match value {
+        Value::Null => return Ok(None),
+        Value::String(ref s) if s == "Raft" => return Ok(Some(Proof::Raft)),
+        Value::Object(_) => value.into_fields("proof", &["Bft"])?.remove(0),
+        _ => return Err(CanonicalJsonError::InvalidField("proof")),
+    };
+    let mut fields = bft.into_fields("proof", &BFT_KEYS)?.into_iter();
+    let mut next = || fields.next().unwrap();
+    let commits = match next() {
+        Value::Object(commits) => commits
+            .into_iter()
+            .map(|(address, commit)| {
+                let address = Value::String(address).into_address("commits")?;
+                Ok((address, commit.into_string("commits")?))
+            })
+            .collect::<Result<HashMap<_, _>, CanonicalJsonError>>()?,
+        _ => return Err(CanonicalJsonError::InvalidField("commits")),
+    };
+    let height = usize::try_from(next().into_number("height")?)
+        .map_err(|_| CanonicalJsonError::InvalidField("height"))?;
+    let proposal = next().into_h256("proposal")?;
+    let round = usize::try_from(next().into_number("round")?)
+        .map_err(|_| CanonicalJsonError::InvalidField("round"))?;
+    Ok(Some(Proof::Bft(BftProof {
+        proposal,
+        height,
+        round,
+        commits,
+    })))
+}
+
+impl CanonicalJson for BlockHeader {
+    fn to_value(&self) -> Value {
+        let values = vec![
+            Value::String(self.number.to_string()),
+            Value::hex(&self.prev_hash),
+            proof_to_value(&self.proof),
+            Value::hex(&self.proposer),
+            Value::String(self.quota_used.to_string()),
+            Value::hex(&self.receipts_root),
+            Value::hex(&self.state_root),
+            Value::Number(self.timestamp),
+            Value::hex(&self.transactions_root),
+        ];
+        Value::Object(
+            HEADER_KEYS
+                .iter()
+                .map(|key| (*key).to_owned())
+                .zip(values)
+                .collect(),
+        )
+    }
+
+    fn from_value(value: Value) -> Result<Self, CanonicalJsonError> {
+        let mut fields = value.into_fields("header", &HEADER_KEYS)?.into_iter();
+        let mut next = || fields.next().unwrap();
+        Ok(BlockHeader {
+            number: next().into_u256("number")?,
+            prev_hash: next().into_h256("prevHash")?,
+            proof: proof_from_value(next())?,
+            proposer: next().into_address("proposer")?,
+            quota_used: next().into_u256("quotaUsed")?,
+            receipts_root: next().into_h256("receiptsRoot")?,
+            state_root: next().into_h256("stateRoot")?,
+            timestamp: next().into_number("timestamp")?,
+            transactions_root: next().into_h256("transactionsRoot")?,
+        })
+    }
+}
+
+/// The transactions are kept as hashes only, a parsed block has
+/// `BlockTransaction::Hash` for each.
+impl CanonicalJson for Block {
+    fn to_value(&self) -> Value {
+        let hashes = self
+            .body
+            .transactions
+            .iter()
+            .map(|tx| match *tx {
+                BlockTransaction::Full(ref tx) => Value::hex(&tx.hash),
+                BlockTransaction::Hash(ref hash) => Value::hex(hash),
+            })
+            .collect();
+        let values = vec![
+            Value::hex(&self.hash),
+            self.header.to_value(),
+            Value::Array(hashes),
+            Value::Number(u64::from(self.version)),
+        ];
+        Value::Object(
+            BLOCK_KEYS
+                .iter()
+                .map(|key| (*key).to_owned())
+                .zip(values)
+                .collect(),
+        )
+    }
+
+    fn from_value(value: Value) -> Result<Self, CanonicalJsonError> {
+        let mut fields = value.into_fields("block", &BLOCK_KEYS)?.into_iter();
+        let mut next = || fields.next().unwrap();
+        let hash = next().into_h256("hash")?;
+        let header = BlockHeader::from_value(next())?;
+        let transactions = match next() {
+            Value::Array(hashes) => hashes
+                .into_iter()
+                .map(|hash| {
+                    hash.into_h256("transactionHashes")
+                        .map(BlockTransaction::Hash)
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+            _ => return Err(CanonicalJsonError::InvalidField("transactionHashes")),
+        };
+        let version = u32::try_from(next().into_number("version")?)
+            .map_err(|_| CanonicalJsonError::InvalidField("version"))?;
+        Ok(Block {
+            version,
+            hash,
+            header,
+            body: BlockBody { transactions },
+            canonical: None,
+            confirmations: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        canonical_json_hash, from_canonical_json, to_canonical_json, CanonicalJsonError,
+        MAX_SAFE_INTEGER,
+    };
+    use crate::rpc_types::{
+        BftProof, Block, BlockBody, BlockHeader, BlockTransaction, FullTransaction, Proof,
+    };
+    use cita_types::{Address, H256, U256};
+    use rustc_serialize::hex::ToHex;
+    use std::collections::HashMap;
+
+    const HEADER: &str = concat!(
+        r#"{"number":"100","#,
+        r#""prevHash":"0x1111111111111111111111111111111111111111111111111111111111111111","#,
+        r#""proof":{"Bft":{"commits":{"#,
+        r#""0x0101010101010101010101010101010101010101":"0xaa","#,
+        r#""0x0202020202020202020202020202020202020202":"0xbb\"\n\u001f"},"#,
+        r#""height":99,"#,
+        r#""proposal":"0x2222222222222222222222222222222222222222222222222222222222222222","#,
+        r#""round":1}},"#,
+        r#""proposer":"0xabababababababababababababababababababab","#,
+        r#""quotaUsed":"18446744073709551616","#,
+        r#""receiptsRoot":"0x3333333333333333333333333333333333333333333333333333333333333333","#,
+        r#""stateRoot":"0x4444444444444444444444444444444444444444444444444444444444444444","#,
+        r#""timestamp":1524000000000,"#,
+        r#""transactionsRoot":"0x5555555555555555555555555555555555555555555555555555555555555555"}"#,
+    );
+
+    #[cfg(feature = "sha3hash")]
+    const HASHES: [&str; 2] = [
+        "99d91c7ed1c557d7544747b524b257eb28991c99e75764f6d99d3d0c5ba611df",
+        "54b16b3a2fd78e6f6319333f7801c41f90d9e97b18695193456bbc82a6860c1e",
+    ];
+    #[cfg(feature = "blake2bhash")]
+    const HASHES: [&str; 2] = [
+        "ad1e8a65fc39cea32d318a2c882bac93358a9427284f6ee92e5b451acf78b9c9",
+        "5bfe2a91dcbe4504e52218e6bb3ab199097fb6c5ddb1e115c06c814bd5eaddea",
+    ];
+    #[cfg(feature = "sm3hash")]
+    const HASHES: [&str; 2] = [
+        "c4c9e61c27649b26e7dafe4f57ec4ff0e188d041acf5a7a5eaa6687772e9f0ad",
+        "d75fe69fe16d103fa926e741e02e4a17a8f8d2ba3b679e35fa609d17c539ac63",
+    ];
+
+    fn header() -> BlockHeader {
+        let mut commits = HashMap::new();
+        commits.insert(Address::from([2; 20]), "0xbb\"\n\u{1f}".to_owned());
+        commits.insert(Address::from([1; 20]), "0xaa".to_owned());
+        BlockHeader {
+            timestamp: 1_524_000_000_000,
+            prev_hash: H256::from([0x11; 32]),
+            number: U256::from(100),
+            state_root: H256::from([0x44; 32]),
+            transactions_root: H256::from([0x55; 32]),
+            receipts_root: H256::from([0x33; 32]),
+            quota_used: U256::from(::std::u64::MAX) + U256::from(1),
+            proof: Some(Proof::Bft(BftProof {
+                proposal: H256::from([0x22; 32]),
+                height: 99,
+                round: 1,
+                commits,
+            })),
+            proposer: Address::from([0xab; 20]),
+        }
+    }
+
+    fn block() -> Block {
+        Block {
+            version: 2,
+            hash: H256::from([0x66; 32]),
+            header: header(),
+            body: BlockBody {
+                transactions: vec![
+                    BlockTransaction::Hash(H256::from([0x77; 32])),
+                    BlockTransaction::Full(FullTransaction {
+                        hash: H256::from([0x88; 32]),
+                        content: vec![1, 2, 3].into(),
+                        from: Address::from([0x99; 20]),
+                    }),
+                ],
+            },
+            canonical: None,
+            confirmations: None,
+        }
+    }
+
+    fn block_json() -> String {
+        format!(
+            r#"{{"hash":"0x{}","header":{},"transactionHashes":["0x{}","0x{}"],"version":2}}"#,
+            "66".repeat(32),
+            HEADER,
+            "77".repeat(32),
+            "88".repeat(32)
+        )
+    }
+
+    #[test]
+    fn header_round_trip() {
+        assert_eq!(to_canonical_json(&header()), HEADER);
+        assert_eq!(from_canonical_json::<BlockHeader>(HEADER), Ok(header()));
+
+        for proof in &[None, Some(Proof::Raft)] {
+            let mut header = header();
+            header.proof = proof.clone();
+            let json = to_canonical_json(&header);
+            assert_eq!(from_canonical_json(&json), Ok(header));
+        }
+    }
+
+    #[test]
+    fn block_round_trip() {
+        let json = block_json();
+        assert_eq!(to_canonical_json(&block()), json);
+        // Full transactions come back as their hashes.
+        let parsed: Block = from_canonical_json(&json).unwrap();
+        assert_eq!(
+            parsed.body.transactions,
+            vec![
+                BlockTransaction::Hash(H256::from([0x77; 32])),
+                BlockTransaction::Hash(H256::from([0x88; 32])),
+            ]
+        );
+        assert_eq!(to_canonical_json(&parsed), json);
+    }
+
+    #[cfg(any(feature = "sha3hash", feature = "blake2bhash", feature = "sm3hash"))]
+    #[test]
+    fn golden_hashes() {
+        assert_eq!(canonical_json_hash(&header()).to_hex(), HASHES[0]);
+        assert_eq!(canonical_json_hash(&block()).to_hex(), HASHES[1]);
+    }
+
+    #[test]
+    fn large_integers_are_strings() {
+        let mut header = header();
+        header.timestamp = MAX_SAFE_INTEGER;
+        let json = to_canonical_json(&header);
+        assert!(json.contains(r#""timestamp":9007199254740991,"#));
+        assert_eq!(from_canonical_json(&json), Ok(header.clone()));
+
+        for timestamp in &[MAX_SAFE_INTEGER + 1, ::std::u64::MAX] {
+            header.timestamp = *timestamp;
+            let json = to_canonical_json(&header);
+            assert!(json.contains(&format!(r#""timestamp":"{}","#, timestamp)));
+            assert_eq!(from_canonical_json(&json), Ok(header.clone()));
+        }
+
+        let reject = |json: &str| from_canonical_json::<BlockHeader>(json).unwrap_err();
+        let at = HEADER.find(r#""timestamp":"#).unwrap() + 12;
+        // As a number past the limit, as a string below it or past u64.
+        assert_eq!(
+            reject(&HEADER.replace("1524000000000", "9007199254740992")),
+            CanonicalJsonError::Syntax(at)
+        );
+        for string in &[r#""1524000000000""#, r#""18446744073709551616""#] {
+            assert_eq!(
+                reject(&HEADER.replace("1524000000000", string)),
+                CanonicalJsonError::InvalidField("timestamp")
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_non_canonical() {
+        let reject = |json: &str| from_canonical_json::<BlockHeader>(json).unwrap_err();
+
+        let reordered = HEADER.replacen(
+            r#""number":"100","prevHash":"0x1111111111111111111111111111111111111111111111111111111111111111""#,
+            r#""prevHash":"0x1111111111111111111111111111111111111111111111111111111111111111","number":"100""#,
+            1,
+        );
+        assert_eq!(
+            reject(&reordered),
+            CanonicalJsonError::KeyOrder("number".to_owned())
+        );
+        let reordered_commits = HEADER
+            .replace("0x0101", "0xtemp")
+            .replace("0x0202", "0x0101")
+            .replace("0xtemp", "0x0202");
+        match reject(&reordered_commits) {
+            CanonicalJsonError::KeyOrder(_) => {}
+            other => panic!("rejected with {:?}", other),
+        }
+
+        assert_eq!(
+            reject(&HEADER.replace("0xabab", "0xABab")),
+            CanonicalJsonError::InvalidField("proposer")
+        );
+        assert_eq!(
+            reject(&HEADER.replace("0x1111", "0X1111")),
+            CanonicalJsonError::InvalidField("prevHash")
+        );
+        assert_eq!(
+            reject(&HEADER.replace(r#""100""#, r#""0100""#)),
+            CanonicalJsonError::InvalidField("number")
+        );
+        assert_eq!(
+            reject(&HEADER.replace(r#""100""#, "100")),
+            CanonicalJsonError::InvalidField("number")
+        );
+        assert_eq!(
+            reject(&HEADER.replace(r#""round":1"#, r#""round":01"#)),
+            CanonicalJsonError::Syntax(HEADER.find(r#""round":1"#).unwrap() + 8)
+        );
+        assert_eq!(
+            reject(&HEADER.replace(r#""timestamp":"#, r#""timestamp": "#)),
+            CanonicalJsonError::Syntax(HEADER.find(r#""timestamp":"#).unwrap() + 12)
+        );
+        assert_eq!(
+            reject(&format!("{} ", HEADER)),
+            CanonicalJsonError::Syntax(HEADER.len())
+        );
+        assert_eq!(
+            reject(&HEADER.replace(r#""number":"100","#, "")),
+            CanonicalJsonError::MissingField("number")
+        );
+        assert_eq!(
+            reject(&HEADER.replace(r#"{"number""#, r#"{"extra":null,"number""#)),
+            CanonicalJsonError::UnknownField("extra".to_owned())
+        );
+
+        // Escapes other than the shortest one for each character.
+        for escape in &[r#"""#, r#"\/"#, r#"\u000a"#, r#"\u001F"#, r#"\u0041"#] {
+            let json = HEADER.replace(r#"\u001f"#, escape);
+            match reject(&json) {
+                CanonicalJsonError::Syntax(_) => {}
+                other => panic!("rejected {} with {:?}", escape, other),
+            }
+        }
+    }
+}
@@ -0,0 +1,584 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! RPC payloads as JSON, or as CBOR for clients which ask for it, with
+//! the `cbor` feature.
+//!
+//! CBOR has the same structure as the JSON, field names included, but
+//! hashes, addresses, data and quantities are byte strings instead of
+//! hex text, and the optional fields of responses are always written,
+//! as null if missing, whatever the `NullStyle`. The types decode back as
+//! they were from either.
+//!
+//! Two things stay as they are in JSON. `PartialRequest` keeps params as
+//! `serde_json::Value`s, which can't be byte strings, so a CBOR request
+//! decodes to one only if its params are the JSON ones; decode a typed
+//! `Request` otherwise. And `ResponseResult` tries its variants in turn,
+//! so decode a result as the type of its method: in CBOR an empty `Data`
+//! is a zero `Quantity` as well.
+
+use std::error;
+use std::fmt;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// Largest payload `decode` accepts by default.
+pub const DEFAULT_MAX_PAYLOAD: usize = 4 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ContentType {
+    Json,
+    Cbor,
+}
+
+impl Default for ContentType {
+    fn default() -> Self {
+        ContentType::Json
+    }
+}
+
+impl ContentType {
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            ContentType::Json => "application/json",
+            ContentType::Cbor => "application/cbor",
+        }
+    }
+
+    /// The type of a `Content-Type` header, ignoring parameters like
+    /// `charset`. None if it is neither.
+    pub fn from_header(value: &str) -> Option<Self> {
+        let mime_type = value.split(';').next().unwrap_or("").trim();
+        if mime_type.eq_ignore_ascii_case(ContentType::Json.mime_type()) {
+            Some(ContentType::Json)
+        } else if mime_type.eq_ignore_ascii_case(ContentType::Cbor.mime_type()) {
+            Some(ContentType::Cbor)
+        } else {
+            None
+        }
+    }
+
+    /// What to answer a request with `Accept` header `accept`: the type
+    /// it prefers by quality, the first of them on a tie. JSON without the
+    /// header, for wildcards, and when it accepts neither.
+    pub fn negotiate(accept: Option<&str>) -> Self {
+        let accept = match accept {
+            Some(accept) => accept,
+            None => return ContentType::Json,
+        };
+        let mut best: Option<(ContentType, f32)> = None;
+        for range in accept.split(',') {
+            let mut parts = range.split(';');
+            let mime_type = parts.next().unwrap_or("").trim();
+            let content_type = match ContentType::from_header(mime_type) {
+                Some(content_type) => content_type,
+                None if mime_type == "*/*" || mime_type == "application/*" => ContentType::Json,
+                None => continue,
+            };
+            let quality = parts
+                .filter_map(|param| {
+                    let mut param = param.splitn(2, '=');
+                    match (param.next(), param.next()) {
+                        (Some(name), Some(value)) if name.trim() == "q" => {
+                            value.trim().parse().ok()
+                        }
+                        _ => None,
+                    }
+                })
+                .next()
+                .unwrap_or(1.0);
+            if quality > 0.0 && best.map_or(true, |(_, best)| quality > best) {
+                best = Some((content_type, quality));
+            }
+        }
+        best.map_or(ContentType::Json, |(content_type, _)| content_type)
+    }
+}
+
+impl fmt::Display for ContentType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.mime_type())
+    }
+}
+
+#[derive(Debug)]
+pub enum CodecError {
+    /// A payload of `size` bytes, more than `limit`.
+    TooLarge {
+        size: usize,
+        limit: usize,
+    },
+    Json(serde_json::Error),
+    Cbor(serde_cbor::Error),
+}
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CodecError::TooLarge { size, limit } => {
+                write!(
+                    f,
+                    "payload of {} bytes exceeds the limit of {}",
+                    size, limit
+                )
+            }
+            CodecError::Json(ref err) => write!(f, "invalid JSON payload: {}", err),
+            CodecError::Cbor(ref err) => write!(f, "invalid CBOR payload: {}", err),
+        }
+    }
+}
+
+impl error::Error for CodecError {}
+
+impl From<serde_json::Error> for CodecError {
+    fn from(err: serde_json::Error) -> Self {
+        CodecError::Json(err)
+    }
+}
+
+impl From<serde_cbor::Error> for CodecError {
+    fn from(err: serde_cbor::Error) -> Self {
+        CodecError::Cbor(err)
+    }
+}
+
+pub fn encode<T>(content_type: ContentType, value: &T) -> Result<Vec<u8>, CodecError>
+where
+    T: Serialize,
+{
+    match content_type {
+        ContentType::Json => Ok(serde_json::to_vec(value)?),
+        ContentType::Cbor => Ok(serde_cbor::to_vec(value)?),
+    }
+}
+
+/// Decode `payload`, refused unread when it is over `limit` bytes.
+pub fn decode<T>(content_type: ContentType, payload: &[u8], limit: usize) -> Result<T, CodecError>
+where
+    T: DeserializeOwned,
+{
+    if payload.len() > limit {
+        return Err(CodecError::TooLarge {
+            size: payload.len(),
+            limit,
+        });
+    }
+    match content_type {
+        ContentType::Json => Ok(serde_json::from_slice(payload)?),
+        ContentType::Cbor => Ok(serde_cbor::from_slice(payload)?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, encode, CodecError, ContentType, DEFAULT_MAX_PAYLOAD};
+    use crate::rpc_request::{
+        GetBlockByNumberParams, Request, RequestInfo, ResponseResult, RpcRequest,
+        SendRawTransactionParams,
+    };
+    use crate::rpc_response::{Output, RpcFailure, RpcResponse, RpcSuccess};
+    use crate::rpc_types::{
+        AdminCall, AmendRequest, BftProof, Block, BlockBody, BlockHeader, BlockNumber,
+        BlockParamsByHash, BlockParamsByNumber, BlockTag, BlockTransaction, Boolean, CallRequest,
+        ConfirmationToken, CountOrCode, Data, Data20, Data32, EconomicalModel, Filter,
+        FilterChanges, FullTransaction, Id, Integer, Log, MetaData, PageRequest, Paginated, Params,
+        PeersInfo, PoolStatus, Proof, Quantity, Receipt, RpcBlock, RpcModules, RpcTransaction,
+        SnapshotCommand, SnapshotRequest, SoftwareVersion, SyncStatus, TxResponse, VariadicValue,
+        Version, VersionInfo,
+    };
+    use crate::Error;
+    use cita_types::{Address, Bloom, H160, H256, U256};
+    use serde::de::DeserializeOwned;
+    use serde::Serialize;
+    use std::collections::HashMap;
+    use std::fmt::Debug;
+
+    fn round_trip<T>(value: &T)
+    where
+        T: Serialize + DeserializeOwned + PartialEq + Debug,
+    {
+        for &content_type in &[ContentType::Json, ContentType::Cbor] {
+            let payload = encode(content_type, value).unwrap();
+            let decoded: T = decode(content_type, &payload, DEFAULT_MAX_PAYLOAD)
+                .unwrap_or_else(|err| panic!("{:?} as {}: {}", value, content_type, err));
+            assert_eq!(&decoded, value, "as {}", content_type);
+        }
+    }
+
+    fn full_transaction(n: u64) -> FullTransaction {
+        FullTransaction {
+            hash: H256::from(n),
+            content: Data::new(vec![n as u8; 200]),
+            from: Address::from(n),
+        }
+    }
+
+    fn block(transactions: Vec<BlockTransaction>) -> Block {
+        let mut commits = HashMap::new();
+        commits.insert(Address::from(1), "0x".to_owned() + &"ab".repeat(65));
+        commits.insert(Address::from(2), "0x".to_owned() + &"cd".repeat(65));
+        Block {
+            version: 2,
+            hash: H256::from(0xb10c),
+            header: BlockHeader {
+                timestamp: 1_570_000_000_000,
+                prev_hash: H256::from(0xb10b),
+                number: U256::from(123_456),
+                state_root: H256::from(1),
+                transactions_root: H256::from(2),
+                receipts_root: H256::from(3),
+                quota_used: U256::from(21_000 * transactions.len() as u64),
+                proof: Some(Proof::Bft(BftProof {
+                    proposal: H256::from(0xb10c),
+                    height: 123_455,
+                    round: 0,
+                    commits,
+                })),
+                proposer: Address::from(1),
+            },
+            body: BlockBody { transactions },
+            canonical: None,
+            confirmations: None,
+        }
+    }
+
+    fn log(n: u64) -> Log {
+        Log {
+            address: H160::from(n),
+            topics: vec![H256::from(n), H256::from(n + 1)],
+            data: Data::new(vec![1, 2, 3]),
+            block_hash: Some(H256::from(0xb10c)),
+            block_number: Some(U256::from(123_456)),
+            transaction_hash: Some(H256::from(n)),
+            transaction_index: Some(U256::zero()),
+            log_index: Some(U256::from(n)),
+            transaction_log_index: None,
+        }
+    }
+
+    fn receipt() -> Receipt {
+        Receipt {
+            transaction_hash: Some(H256::from(7)),
+            transaction_index: Some(U256::from(0)),
+            block_hash: Some(H256::from(0xb10c)),
+            block_number: Some(U256::from(123_456)),
+            cumulative_quota_used: U256::from(42_000),
+            quota_used: Some(U256::from(21_000)),
+            contract_address: None,
+            logs: vec![log(1), log(2)],
+            state_root: None,
+            logs_bloom: Bloom::from([5u8; 256]),
+            error_code: Some(3),
+            error_message: Some("Out of quota.".to_owned()),
+        }
+    }
+
+    #[test]
+    fn basic_types() {
+        round_trip(&BlockTag::Pending);
+        round_trip(&Boolean::new(true));
+        round_trip(&Data::new(vec![]));
+        round_trip(&Data::new(vec![0, 0xab, 0xcd]));
+        round_trip(&Data20::new(H160::from(20)));
+        round_trip(&Data32::new(H256::from(32)));
+        round_trip(&EconomicalModel::Charge);
+        round_trip(&Integer::new(u64::max_value()));
+        round_trip(&Quantity::new(U256::zero()));
+        round_trip(&Quantity::new(U256::max_value()));
+        round_trip(&VariadicValue::<Data20>::null());
+        round_trip(&VariadicValue::single(Data20::new(H160::from(1))));
+        round_trip(&VariadicValue::multiple(vec![Data20::new(H160::from(1))]));
+        round_trip(&BlockNumber::earliest());
+        round_trip(&BlockNumber::new(U256::from(9).into()));
+        round_trip(&Id::Null);
+        round_trip(&Id::Num(7));
+        round_trip(&Id::Str("seven".to_owned()));
+        round_trip(&Version::V2);
+        round_trip(&Params::Array(vec![json!("0x01"), json!(true), json!(9)]));
+    }
+
+    #[test]
+    fn chain_types() {
+        let transactions = vec![
+            BlockTransaction::Full(full_transaction(1)),
+            BlockTransaction::Full(full_transaction(2)),
+        ];
+        round_trip(&block(transactions.clone()));
+        round_trip(&block(vec![BlockTransaction::Hash(H256::from(1))]));
+        let mut header = block(vec![]).header;
+        header.proof = None;
+        round_trip(&header);
+        round_trip(&BlockBody { transactions });
+        round_trip(&Proof::Raft);
+        round_trip(&full_transaction(3));
+        round_trip(&RpcTransaction {
+            hash: H256::from(3),
+            content: Data::new(vec![3; 10]),
+            from: Address::from(3),
+            block_number: U256::from(123_456),
+            block_hash: H256::from(0xb10c),
+            index: U256::from(0),
+        });
+        round_trip(&log(1));
+        round_trip(&receipt());
+        round_trip(&TxResponse::new(H256::from(4), "OK".to_owned()));
+        round_trip(&TxResponse::with_admission(
+            H256::from(4),
+            "OK".to_owned(),
+            PoolStatus::Replaced,
+            Some(H256::from(5)),
+            Some(0),
+        ));
+        round_trip(&SyncStatus::NotSyncing);
+        round_trip(&SyncStatus::new(1, 10, Some(300)));
+    }
+
+    #[test]
+    fn node_types() {
+        round_trip(&MetaData {
+            chain_id: 1,
+            chain_id_v1: U256::from(1).into(),
+            chain_name: "test-chain".to_owned(),
+            operator: "test-operator".to_owned(),
+            website: "https://www.example.com".to_owned(),
+            genesis_timestamp: 1_570_000_000_000,
+            validators: vec![Data20::new(H160::from(1)), Data20::new(H160::from(2))],
+            nodes: vec![Data20::new(H160::from(3))],
+            block_interval: 3000,
+            token_name: "Nervos AppChain Test Token".to_owned(),
+            token_symbol: "NATT".to_owned(),
+            token_avatar: "https://example.com/avatar.png".to_owned(),
+            version: 2,
+            economical_model: EconomicalModel::Quota,
+        });
+        let mut peers = HashMap::new();
+        peers.insert(Address::from(1), "127.0.0.1".to_owned());
+        round_trip(&PeersInfo {
+            amount: 1,
+            peers: Some(peers),
+            error_message: None,
+        });
+        round_trip(&SoftwareVersion::new("v1.0.0".to_owned()));
+        round_trip(&VersionInfo::new(
+            "v1.0.0".to_owned(),
+            vec![0, 1, 2],
+            "secp256k1",
+        ));
+        round_trip(&RpcModules::new().with_module("cita", "2"));
+    }
+
+    #[test]
+    fn query_types() {
+        round_trip(&CallRequest::new(
+            Some(Data20::new(H160::from(1))),
+            Data20::new(H160::from(2)),
+            Some(Data::new(vec![0xab, 0xcd])),
+        ));
+        round_trip(&CallRequest::new(None, Data20::new(H160::from(2)), None));
+        let filter = Filter::new(
+            BlockNumber::new(U256::from(1).into()),
+            BlockNumber::pending(),
+            Some(VariadicValue::single(Data20::new(H160::from(1)))),
+            Some(vec![
+                VariadicValue::null(),
+                VariadicValue::multiple(vec![Data32::new(H256::from(1))]),
+            ]),
+        );
+        round_trip(&filter);
+        round_trip(&filter.with_page(PageRequest::new(Some(Data::new(vec![1])), Some(10))));
+        round_trip(&FilterChanges::Empty);
+        round_trip(&FilterChanges::Logs(vec![log(1)]));
+        round_trip(&FilterChanges::Hashes(vec![Data32::new(H256::from(1))]));
+        round_trip(&PageRequest::default());
+        round_trip(&Paginated::new(
+            vec![log(1)],
+            Some(Data::new(vec![2])),
+            Some(5),
+        ));
+        round_trip(&CountOrCode::new(vec![1; 20], BlockNumber::latest()));
+        round_trip(&BlockParamsByHash::new(vec![2; 32], true));
+        round_trip(&BlockParamsByNumber::new(BlockNumber::earliest(), false));
+        round_trip(&RpcBlock::new(vec![2; 32], true, vec![3; 100]));
+    }
+
+    #[test]
+    fn admin_types() {
+        let snapshot = SnapshotRequest {
+            cmd: SnapshotCommand::Snapshot,
+            start_height: Some(U256::from(1).into()),
+            end_height: None,
+            file: Some("snapshot.rlp".to_owned()),
+        };
+        round_trip(&snapshot);
+        round_trip(&AdminCall {
+            request: snapshot,
+            confirmation: Some(ConfirmationToken {
+                expires_at: Integer::new(1_570_000_060),
+                mac: Data32::new(H256::from(9)),
+            }),
+        });
+        let address = Data20::new(H160::from(1));
+        for amend in vec![
+            AmendRequest::Code {
+                address: address.clone(),
+                code: Data::new(vec![0x60, 0x80]),
+            },
+            AmendRequest::Abi {
+                address: address.clone(),
+                abi: Data::new(b"[]".to_vec()),
+            },
+            AmendRequest::Kv {
+                address: address.clone(),
+                key: Data32::new(H256::from(1)),
+                value: Data32::new(H256::from(2)),
+            },
+            AmendRequest::Balance {
+                address,
+                balance: U256::from(100).into(),
+            },
+        ] {
+            round_trip(&amend);
+        }
+    }
+
+    #[test]
+    fn requests_and_responses() {
+        round_trip(
+            &GetBlockByNumberParams::new(BlockNumber::latest(), Boolean::new(true)).into_request(1),
+        );
+        round_trip(&SendRawTransactionParams::new(Data::new(vec![1; 64])).into_request(2));
+
+        let info = RequestInfo::new(Some(Version::V2), Id::Num(1));
+        let success = RpcSuccess::new(info.clone())
+            .set_result(ResponseResult::GetBlockByHash(block(vec![
+                BlockTransaction::Full(full_transaction(1)),
+            ])))
+            .output();
+        let failure = Output::Failure(RpcFailure::from_options(
+            info,
+            Error::invalid_params("no block"),
+        ));
+        round_trip(&success);
+        round_trip(&failure);
+        round_trip(&RpcResponse::Batch(vec![success, failure]));
+
+        // Params kept as JSON values decode from CBOR too, as text.
+        let request = json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "getBlockByNumber",
+            "params": ["0x1", true]
+        });
+        let payload = encode(ContentType::Cbor, &request).unwrap();
+        let decoded: RpcRequest = decode(ContentType::Cbor, &payload, DEFAULT_MAX_PAYLOAD).unwrap();
+        let expected: RpcRequest = serde_json::from_value(request).unwrap();
+        assert_eq!(decoded, expected);
+    }
+
+    #[test]
+    fn hashes_are_bytes() {
+        let hash = Data32::new(H256::from(1));
+        let payload = encode(ContentType::Cbor, &hash).unwrap();
+        // A major type 2 header with the length in the next byte, then
+        // the 32 bytes.
+        assert_eq!(&payload[..2], &[0x58, 32]);
+        assert_eq!(&payload[2..], &H256::from(1)[..]);
+
+        let quantity = Quantity::new(U256::from(0x1234));
+        assert_eq!(
+            encode(ContentType::Cbor, &quantity).unwrap(),
+            vec![0x42, 0x12, 0x34]
+        );
+        assert_eq!(
+            encode(ContentType::Json, &quantity).unwrap(),
+            b"\"0x1234\"".to_vec()
+        );
+
+        // The wrong length is as wrong as bad hex.
+        let short = encode(ContentType::Cbor, &Data::new(vec![1; 31])).unwrap();
+        assert!(decode::<Data32>(ContentType::Cbor, &short, DEFAULT_MAX_PAYLOAD).is_err());
+    }
+
+    #[test]
+    fn payload_limit() {
+        let payload = encode(ContentType::Cbor, &block(vec![])).unwrap();
+        match decode::<Block>(ContentType::Cbor, &payload, payload.len() - 1) {
+            Err(CodecError::TooLarge { size, limit }) => {
+                assert_eq!((size, limit), (payload.len(), payload.len() - 1))
+            }
+            other => panic!("{:?}", other),
+        }
+        assert!(decode::<Block>(ContentType::Cbor, &payload, payload.len()).is_ok());
+        assert!(decode::<Block>(ContentType::Cbor, &payload[1..], DEFAULT_MAX_PAYLOAD).is_err());
+        assert!(decode::<Block>(ContentType::Json, &payload, DEFAULT_MAX_PAYLOAD).is_err());
+    }
+
+    #[test]
+    fn block_response_size() {
+        let transactions = (0..100)
+            .map(|n| BlockTransaction::Full(full_transaction(n)))
+            .collect();
+        let response = RpcSuccess::new(RequestInfo::new(Some(Version::V2), Id::Num(1)))
+            .set_result(ResponseResult::GetBlockByHash(block(transactions)))
+            .output();
+        let json = encode(ContentType::Json, &response).unwrap();
+        let cbor = encode(ContentType::Cbor, &response).unwrap();
+        // A block of 100 transactions of 200 bytes is about 55.6 kB of
+        // JSON and 28.4 kB of CBOR. What goes is the hex of the hashes,
+        // addresses and transactions, the field names stay.
+        assert!(
+            cbor.len() * 100 < json.len() * 55,
+            "{} {}",
+            cbor.len(),
+            json.len()
+        );
+    }
+
+    #[test]
+    fn negotiation() {
+        assert_eq!(
+            ContentType::from_header("application/json; charset=utf-8"),
+            Some(ContentType::Json)
+        );
+        assert_eq!(
+            ContentType::from_header("Application/CBOR"),
+            Some(ContentType::Cbor)
+        );
+        assert_eq!(ContentType::from_header("text/plain"), None);
+
+        let cases = vec![
+            (None, ContentType::Json),
+            (Some("application/cbor"), ContentType::Cbor),
+            (
+                Some("application/json, application/cbor;q=0.9"),
+                ContentType::Json,
+            ),
+            (
+                Some("application/cbor, application/json"),
+                ContentType::Cbor,
+            ),
+            (
+                Some("application/json;q=0.5, application/cbor"),
+                ContentType::Cbor,
+            ),
+            (Some("application/cbor;q=0, */*"), ContentType::Json),
+            (Some("text/html"), ContentType::Json),
+            (Some("*/*"), ContentType::Json),
+        ];
+        for (accept, expected) in cases {
+            assert_eq!(ContentType::negotiate(accept), expected, "{:?}", accept);
+        }
+    }
+}
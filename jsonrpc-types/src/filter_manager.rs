@@ -0,0 +1,571 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Server side state of `newBlockFilter`, `newFilter` and `getFilterChanges`.
+//!
+//! A filter reports what changed since it was last polled, starting with
+//! the blocks after the one that was the best when it was created. It
+//! remembers the hashes of the blocks it reported, so after a reorg it
+//! goes on from the block where the chain forked: hashes of retired blocks
+//! are not reported again, and neither are logs, which can't be flagged as
+//! removed in `Log`.
+//!
+//! Filters nobody polled for `timeout` are dropped, and a client, whatever
+//! `K` identifies it by, has at most `max_per_client` of them.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::time::Duration;
+
+use cita_types::H256;
+pub use util::timer::{Clock, MockClock, SystemClock};
+use util::timer::{TimerHandle, TimerWheel};
+
+use crate::rpc_types::{BlockNumber, Filter, FilterChanges, Log, Quantity};
+
+/// Idle time after which a filter is dropped by default.
+pub const DEFAULT_FILTER_TIMEOUT: Duration = Duration::from_secs(300);
+pub const DEFAULT_MAX_FILTERS_PER_CLIENT: usize = 64;
+/// Reported blocks a filter remembers, deeper reorgs report again from
+/// the oldest of them.
+pub const REORG_DEPTH: usize = 64;
+
+/// The chain as it is when a filter is created or polled.
+pub trait ChainState {
+    fn best_height(&self) -> u64;
+    /// Hash of the canonical block at `height`.
+    fn block_hash(&self, height: u64) -> Option<H256>;
+    /// Logs matching `filter` in the canonical blocks `from..=to`, the
+    /// block range of `filter` itself is already applied.
+    fn logs(&self, filter: &Filter, from: u64, to: u64) -> Vec<Log>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct FilterId(u64);
+
+impl FilterId {
+    pub fn new(id: u64) -> Self {
+        FilterId(id)
+    }
+
+    pub fn value(self) -> u64 {
+        self.0
+    }
+}
+
+impl From<Quantity> for FilterId {
+    fn from(id: Quantity) -> Self {
+        FilterId(id.into())
+    }
+}
+
+impl From<FilterId> for Quantity {
+    fn from(id: FilterId) -> Self {
+        id.0.into()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FilterError {
+    /// Never created, uninstalled or expired.
+    NotFound,
+    /// The client has `max_per_client` filters already.
+    TooManyFilters,
+}
+
+impl fmt::Display for FilterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FilterError::NotFound => write!(f, "filter not found"),
+            FilterError::TooManyFilters => write!(f, "too many filters"),
+        }
+    }
+}
+
+impl ::std::error::Error for FilterError {}
+
+#[derive(Debug)]
+enum Kind {
+    Blocks,
+    Logs(Filter),
+}
+
+#[derive(Debug)]
+struct Installed<K> {
+    client: K,
+    kind: Kind,
+    /// Height up to which changes were reported.
+    polled: u64,
+    /// Heights and hashes of the blocks reported, the newest last.
+    seen: VecDeque<(u64, H256)>,
+    /// Drops it when it wasn't polled for the timeout.
+    expiry: TimerHandle,
+}
+
+impl<K> Installed<K> {
+    /// Height of the newest reported block which is still canonical.
+    fn fork_point<S: ChainState>(&self, state: &S) -> u64 {
+        let canonical = self
+            .seen
+            .iter()
+            .rev()
+            .find(|&&(height, hash)| state.block_hash(height) == Some(hash));
+        match (canonical, self.seen.front()) {
+            (Some(&(height, _)), _) => height,
+            (None, Some(&(oldest, _))) => oldest.saturating_sub(1),
+            (None, None) => self.polled,
+        }
+    }
+
+    fn remember(&mut self, height: u64, hash: H256) {
+        self.seen.push_back((height, hash));
+        while self.seen.len() > REORG_DEPTH {
+            self.seen.pop_front();
+        }
+    }
+}
+
+fn height_of(number: &BlockNumber) -> Option<u64> {
+    match *number {
+        BlockNumber::Height(ref height) => Some(height.clone().into()),
+        BlockNumber::Tag(_) => None,
+    }
+}
+
+/// Outside of the generic manager, so it doesn't need its parameters to
+/// be `'static`.
+fn expire_filter(id: FilterId) -> impl FnOnce(&mut Vec<FilterId>) + Send + 'static {
+    move |expired: &mut Vec<FilterId>| expired.push(id)
+}
+
+pub struct FilterManager<K, C = SystemClock> {
+    timers: TimerWheel<Vec<FilterId>, C>,
+    timeout: Duration,
+    max_per_client: usize,
+    next_id: u64,
+    filters: HashMap<FilterId, Installed<K>>,
+}
+
+impl<K> FilterManager<K, SystemClock>
+where
+    K: Eq,
+{
+    pub fn new() -> Self {
+        Self::with_clock(SystemClock::default())
+    }
+}
+
+impl<K> Default for FilterManager<K, SystemClock>
+where
+    K: Eq,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, C> FilterManager<K, C>
+where
+    K: Eq,
+    C: Clock,
+{
+    pub fn with_clock(clock: C) -> Self {
+        FilterManager {
+            timers: TimerWheel::with_clock(clock),
+            timeout: DEFAULT_FILTER_TIMEOUT,
+            max_per_client: DEFAULT_MAX_FILTERS_PER_CLIENT,
+            next_id: 1,
+            filters: HashMap::new(),
+        }
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn max_per_client(mut self, max: usize) -> Self {
+        self.max_per_client = max;
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.filters.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+
+    /// Filter for the hashes of new blocks.
+    pub fn create_block_filter<S: ChainState>(
+        &mut self,
+        client: K,
+        state: &S,
+    ) -> Result<FilterId, FilterError> {
+        self.install(client, Kind::Blocks, state)
+    }
+
+    /// Filter for the logs of new blocks, `fromBlock` and `toBlock` can
+    /// only narrow that.
+    pub fn create_log_filter<S: ChainState>(
+        &mut self,
+        client: K,
+        filter: Filter,
+        state: &S,
+    ) -> Result<FilterId, FilterError> {
+        self.install(client, Kind::Logs(filter), state)
+    }
+
+    pub fn uninstall(&mut self, id: FilterId) -> bool {
+        match self.filters.remove(&id) {
+            Some(filter) => {
+                self.timers.cancel(filter.expiry);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Drop the filters idle for the timeout, returns how many.
+    pub fn expire(&mut self) -> usize {
+        let mut expired = Vec::new();
+        self.timers.tick(&mut expired);
+        for id in &expired {
+            self.filters.remove(id);
+        }
+        expired.len()
+    }
+
+    /// What changed since the filter was last polled.
+    pub fn poll<S: ChainState>(
+        &mut self,
+        id: FilterId,
+        state: &S,
+    ) -> Result<FilterChanges, FilterError> {
+        self.expire();
+        let filter = self.filters.get_mut(&id).ok_or(FilterError::NotFound)?;
+        self.timers.cancel(filter.expiry);
+        filter.expiry = self.timers.schedule_after(self.timeout, expire_filter(id));
+
+        let fork = filter.fork_point(state);
+        while filter
+            .seen
+            .back()
+            .map_or(false, |&(height, _)| height > fork)
+        {
+            filter.seen.pop_back();
+        }
+        let mut from = fork + 1;
+        let mut to = state.best_height();
+        if let Kind::Logs(ref spec) = filter.kind {
+            if let Some(start) = height_of(&spec.from_block) {
+                from = from.max(start);
+            }
+            if let Some(end) = height_of(&spec.to_block) {
+                to = to.min(end);
+            }
+        }
+        filter.polled = fork.max(to);
+        if from > to {
+            return Ok(FilterChanges::Empty);
+        }
+
+        let mut hashes = Vec::new();
+        for height in from..=to {
+            if let Some(hash) = state.block_hash(height) {
+                filter.remember(height, hash);
+                hashes.push(hash.into());
+            }
+        }
+        let changes = match filter.kind {
+            Kind::Blocks if !hashes.is_empty() => FilterChanges::Hashes(hashes),
+            Kind::Logs(ref spec) => {
+                let logs = state.logs(spec, from, to);
+                if logs.is_empty() {
+                    FilterChanges::Empty
+                } else {
+                    FilterChanges::Logs(logs)
+                }
+            }
+            Kind::Blocks => FilterChanges::Empty,
+        };
+        Ok(changes)
+    }
+
+    fn install<S: ChainState>(
+        &mut self,
+        client: K,
+        kind: Kind,
+        state: &S,
+    ) -> Result<FilterId, FilterError> {
+        self.expire();
+        let installed = self
+            .filters
+            .values()
+            .filter(|filter| filter.client == client)
+            .count();
+        if installed >= self.max_per_client {
+            return Err(FilterError::TooManyFilters);
+        }
+
+        let id = FilterId(self.next_id);
+        self.next_id += 1;
+        let best = state.best_height();
+        let mut filter = Installed {
+            client,
+            kind,
+            polled: best,
+            seen: VecDeque::new(),
+            expiry: self.timers.schedule_after(self.timeout, expire_filter(id)),
+        };
+        if let Some(hash) = state.block_hash(best) {
+            filter.remember(best, hash);
+        }
+        self.filters.insert(id, filter);
+        Ok(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ChainState, FilterError, FilterId, FilterManager, MockClock};
+    use crate::rpc_types::{BlockNumber, Data32, Filter, FilterChanges, Log, Quantity};
+    use cita_types::{H160, H256, U256};
+    use serde_json;
+    use std::time::Duration;
+
+    /// Hashes by height, block `h` of fork `f` has the hash `h * 100 + f`,
+    /// and one log per block.
+    struct Chain {
+        forks: Vec<u64>,
+    }
+
+    impl Chain {
+        fn new(best: u64) -> Self {
+            Chain {
+                forks: vec![0; best as usize + 1],
+            }
+        }
+
+        fn hash(height: u64, fork: u64) -> H256 {
+            H256::from(height * 100 + fork)
+        }
+
+        fn grow(&mut self, blocks: u64, fork: u64) {
+            for _ in 0..blocks {
+                self.forks.push(fork);
+            }
+        }
+
+        /// Replace the blocks above `height` with `blocks` of `fork`.
+        fn reorg(&mut self, height: u64, blocks: u64, fork: u64) {
+            self.forks.truncate(height as usize + 1);
+            self.grow(blocks, fork);
+        }
+    }
+
+    impl ChainState for Chain {
+        fn best_height(&self) -> u64 {
+            self.forks.len() as u64 - 1
+        }
+
+        fn block_hash(&self, height: u64) -> Option<H256> {
+            self.forks
+                .get(height as usize)
+                .map(|&fork| Chain::hash(height, fork))
+        }
+
+        fn logs(&self, _filter: &Filter, from: u64, to: u64) -> Vec<Log> {
+            (from..=to)
+                .filter_map(|height| {
+                    self.block_hash(height).map(|hash| Log {
+                        address: H160::from(1),
+                        topics: vec![],
+                        data: vec![].into(),
+                        block_hash: Some(hash),
+                        block_number: Some(U256::from(height)),
+                        transaction_hash: None,
+                        transaction_index: None,
+                        log_index: None,
+                        transaction_log_index: None,
+                    })
+                })
+                .collect()
+        }
+    }
+
+    fn any() -> Filter {
+        Filter::new(BlockNumber::latest(), BlockNumber::latest(), None, None)
+    }
+
+    fn hashes(blocks: &[(u64, u64)]) -> FilterChanges {
+        FilterChanges::Hashes(
+            blocks
+                .iter()
+                .map(|&(height, fork)| Data32::new(Chain::hash(height, fork)))
+                .collect(),
+        )
+    }
+
+    fn log_blocks(changes: FilterChanges) -> Vec<H256> {
+        match changes {
+            FilterChanges::Logs(logs) => {
+                logs.into_iter().filter_map(|log| log.block_hash).collect()
+            }
+            FilterChanges::Empty => vec![],
+            other => panic!("not logs: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn poll_after_reorg() {
+        let mut chain = Chain::new(2);
+        let mut manager = FilterManager::with_clock(MockClock::default());
+        let blocks = manager.create_block_filter("client", &chain).unwrap();
+        let logs = manager.create_log_filter("client", any(), &chain).unwrap();
+        assert_eq!(manager.poll(blocks, &chain), Ok(FilterChanges::Empty));
+
+        chain.grow(2, 0);
+        assert_eq!(manager.poll(blocks, &chain), Ok(hashes(&[(3, 0), (4, 0)])));
+        assert_eq!(
+            log_blocks(manager.poll(logs, &chain).unwrap()),
+            vec![Chain::hash(3, 0), Chain::hash(4, 0)]
+        );
+        assert_eq!(manager.poll(blocks, &chain), Ok(FilterChanges::Empty));
+
+        // Block 4 is retired, 3 stays.
+        chain.reorg(3, 2, 1);
+        assert_eq!(manager.poll(blocks, &chain), Ok(hashes(&[(4, 1), (5, 1)])));
+        assert_eq!(
+            log_blocks(manager.poll(logs, &chain).unwrap()),
+            vec![Chain::hash(4, 1), Chain::hash(5, 1)]
+        );
+
+        // A shorter fork, then growing on it.
+        chain.reorg(2, 1, 2);
+        assert_eq!(manager.poll(blocks, &chain), Ok(hashes(&[(3, 2)])));
+        chain.grow(1, 2);
+        assert_eq!(manager.poll(blocks, &chain), Ok(hashes(&[(4, 2)])));
+
+        // Deeper than remembered: report from the oldest remembered block.
+        let mut chain = Chain::new(0);
+        let deep = manager.create_block_filter("client", &chain).unwrap();
+        chain.grow(super::REORG_DEPTH as u64 + 10, 0);
+        manager.poll(deep, &chain).unwrap();
+        chain.reorg(0, super::REORG_DEPTH as u64 + 10, 3);
+        match manager.poll(deep, &chain).unwrap() {
+            FilterChanges::Hashes(hashes) => {
+                assert_eq!(hashes.len(), super::REORG_DEPTH);
+                assert_eq!(hashes[0], Data32::new(Chain::hash(11, 3)));
+            }
+            other => panic!("not hashes: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn log_filter_block_range() {
+        let mut chain = Chain::new(2);
+        let mut manager = FilterManager::with_clock(MockClock::default());
+        let filter = Filter::new(
+            BlockNumber::new(5u64.into()),
+            BlockNumber::new(6u64.into()),
+            None,
+            None,
+        );
+        let id = manager.create_log_filter(1, filter, &chain).unwrap();
+        chain.grow(2, 0);
+        assert_eq!(manager.poll(id, &chain), Ok(FilterChanges::Empty));
+        chain.grow(4, 0);
+        assert_eq!(
+            log_blocks(manager.poll(id, &chain).unwrap()),
+            vec![Chain::hash(5, 0), Chain::hash(6, 0)]
+        );
+        chain.grow(1, 0);
+        assert_eq!(manager.poll(id, &chain), Ok(FilterChanges::Empty));
+    }
+
+    #[test]
+    fn expiry_reclaims_ids() {
+        let chain = Chain::new(0);
+        let clock = MockClock::default();
+        let mut manager = FilterManager::with_clock(clock.clone())
+            .timeout(Duration::from_secs(10))
+            .max_per_client(1);
+        let first = manager.create_block_filter("client", &chain).unwrap();
+
+        // Polling keeps it alive.
+        clock.advance(Duration::from_secs(8));
+        assert!(manager.poll(first, &chain).is_ok());
+        clock.advance(Duration::from_secs(8));
+        assert!(manager.poll(first, &chain).is_ok());
+
+        clock.advance(Duration::from_secs(11));
+        assert_eq!(manager.poll(first, &chain), Err(FilterError::NotFound));
+        assert!(manager.is_empty());
+
+        // The slot is free again, and the id is not handed out twice.
+        let second = manager.create_block_filter("client", &chain).unwrap();
+        assert_ne!(second, first);
+        clock.advance(Duration::from_secs(11));
+        assert_eq!(manager.expire(), 1);
+        assert!(!manager.uninstall(second));
+    }
+
+    #[test]
+    fn per_client_cap() {
+        let chain = Chain::new(0);
+        let mut manager = FilterManager::with_clock(MockClock::default()).max_per_client(2);
+        let first = manager.create_block_filter("a", &chain).unwrap();
+        manager.create_log_filter("a", any(), &chain).unwrap();
+        assert_eq!(
+            manager.create_block_filter("a", &chain),
+            Err(FilterError::TooManyFilters)
+        );
+        assert!(manager.create_block_filter("b", &chain).is_ok());
+        assert_eq!(manager.len(), 3);
+
+        assert!(manager.uninstall(first));
+        assert!(manager.create_block_filter("a", &chain).is_ok());
+        assert_eq!(manager.poll(first, &chain), Err(FilterError::NotFound));
+    }
+
+    #[test]
+    fn changes_serialization() {
+        let mut chain = Chain::new(0);
+        let mut manager = FilterManager::with_clock(MockClock::default());
+        let blocks = manager.create_block_filter((), &chain).unwrap();
+        let logs = manager.create_log_filter((), any(), &chain).unwrap();
+        assert_eq!(
+            serde_json::to_value(manager.poll(blocks, &chain).unwrap()).unwrap(),
+            json!([])
+        );
+
+        chain.grow(1, 0);
+        assert_eq!(
+            serde_json::to_value(manager.poll(blocks, &chain).unwrap()).unwrap(),
+            json!(["0x0000000000000000000000000000000000000000000000000000000000000064"])
+        );
+        let value = serde_json::to_value(manager.poll(logs, &chain).unwrap()).unwrap();
+        assert_eq!(value[0]["blockNumber"], json!("0x1"));
+        assert_eq!(
+            serde_json::from_value::<FilterChanges>(value).unwrap(),
+            FilterChanges::Logs(chain.logs(&any(), 1, 1))
+        );
+
+        let id: Quantity = blocks.into();
+        assert_eq!(serde_json::to_value(&id).unwrap(), json!("0x1"));
+        assert_eq!(FilterId::from(id), blocks);
+    }
+}
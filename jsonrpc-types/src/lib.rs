@@ -27,6 +27,11 @@ mod macros;
 
 mod error;
 pub use crate::error::{Error, ErrorCode};
+pub mod canonical_json;
+#[cfg(feature = "cbor")]
+pub mod codec;
+pub mod filter_manager;
+pub mod limits;
 pub mod rpc_request;
 pub mod rpc_response;
 pub mod rpc_types;
@@ -0,0 +1,424 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! How big and how costly a parsed request may be.
+//!
+//! The server checks each call against its `RequestLimits` before it is
+//! handled, and answers one over a limit with the `Error` of its
+//! `LimitViolation`, which names the limit, its maximum and the value
+//! sent. In a batch only the calls over a limit fail, unless the batch
+//! itself is too long.
+//!
+//! The block range of a filter is only known once its tags are resolved,
+//! so `check_with` takes the heights `latest` and `pending` stand for.
+//! `check` alone only sees explicit heights and `earliest`.
+
+use crate::error::{Error, ErrorCode};
+use crate::rpc_request::{Call, Request};
+use crate::rpc_types::{BlockNumber, BlockTag, Data, Filter, VariadicValue};
+use cita_types::U256;
+use serde::Serialize;
+use std::fmt;
+
+/// Code of the errors for requests over a limit.
+pub const LIMIT_EXCEEDED: i64 = -32_005;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Limit {
+    MaxBatch,
+    MaxBlockRange,
+    MaxFilterAddresses,
+    MaxFilterTopics,
+    MaxDataBytes,
+}
+
+impl Limit {
+    pub fn name(self) -> &'static str {
+        match self {
+            Limit::MaxBatch => "max_batch",
+            Limit::MaxBlockRange => "max_block_range",
+            Limit::MaxFilterAddresses => "max_filter_addresses",
+            Limit::MaxFilterTopics => "max_filter_topics",
+            Limit::MaxDataBytes => "max_data_bytes",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct LimitViolation {
+    pub limit: Limit,
+    pub max: u64,
+    pub value: u64,
+}
+
+impl fmt::Display for LimitViolation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} is {}, the request has {}",
+            self.limit.name(),
+            self.max,
+            self.value
+        )
+    }
+}
+
+impl ::std::error::Error for LimitViolation {}
+
+impl From<LimitViolation> for Error {
+    fn from(violation: LimitViolation) -> Self {
+        Error {
+            code: ErrorCode::ServerError(LIMIT_EXCEEDED),
+            message: format!("Limit exceeded: {}", violation),
+            data: serde_json::to_value(violation).ok(),
+        }
+    }
+}
+
+/// Heights the block tags stand for, see `RequestLimits::check_with`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Heights {
+    pub latest: Option<u64>,
+    pub pending: Option<u64>,
+}
+
+impl Heights {
+    /// With the chain at `latest`, which `pending` is the block after.
+    pub fn at(latest: u64) -> Self {
+        Heights {
+            latest: Some(latest),
+            pending: Some(latest.saturating_add(1)),
+        }
+    }
+
+    pub fn resolve(&self, number: &BlockNumber) -> Option<u64> {
+        match *number {
+            BlockNumber::Tag(BlockTag::Earliest) => Some(0),
+            BlockNumber::Tag(BlockTag::Latest) => self.latest,
+            BlockNumber::Tag(BlockTag::Pending) => self.pending,
+            BlockNumber::Height(ref height) => {
+                let height: U256 = height.clone().into();
+                if height > U256::from(u64::max_value()) {
+                    Some(u64::max_value())
+                } else {
+                    Some(height.low_u64())
+                }
+            }
+        }
+    }
+}
+
+/// Every limit is inclusive. The defaults limit nothing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RequestLimits {
+    /// Calls in a batch.
+    pub max_batch: usize,
+    /// Blocks a filter spans, both ends included.
+    pub max_block_range: u64,
+    /// Addresses of a filter.
+    pub max_filter_addresses: usize,
+    /// Topics of a filter, counting every alternative of each position.
+    pub max_filter_topics: usize,
+    /// Bytes of a transaction or call data. A `Data` over `MAX_DATA_LEN`
+    /// doesn't even deserialize.
+    pub max_data_bytes: usize,
+}
+
+impl Default for RequestLimits {
+    fn default() -> Self {
+        RequestLimits {
+            max_batch: usize::max_value(),
+            max_block_range: u64::max_value(),
+            max_filter_addresses: usize::max_value(),
+            max_filter_topics: usize::max_value(),
+            max_data_bytes: usize::max_value(),
+        }
+    }
+}
+
+fn within(limit: Limit, max: usize, value: usize) -> Result<(), LimitViolation> {
+    within_u64(limit, max as u64, value as u64)
+}
+
+fn within_u64(limit: Limit, max: u64, value: u64) -> Result<(), LimitViolation> {
+    if value > max {
+        Err(LimitViolation { limit, max, value })
+    } else {
+        Ok(())
+    }
+}
+
+fn variadic_len<T>(value: &VariadicValue<T>) -> usize
+where
+    T: serde::de::DeserializeOwned + Serialize,
+{
+    match *value {
+        VariadicValue::Null => 0,
+        VariadicValue::Single(_) => 1,
+        VariadicValue::Multiple(ref values) => values.len(),
+    }
+}
+
+impl RequestLimits {
+    pub fn check(&self, call: &Call) -> Result<(), LimitViolation> {
+        self.check_with(call, &Heights::default())
+    }
+
+    /// Like `check`, with the tags of filters resolved to `heights`. A
+    /// range with an end not resolved isn't checked.
+    pub fn check_with(&self, call: &Call, heights: &Heights) -> Result<(), LimitViolation> {
+        match *call {
+            Call::GetLogs { ref params } => self.check_filter(&params.0, heights),
+            Call::NewFilter { ref params } => self.check_filter(&params.0, heights),
+            Call::SendRawTransaction { ref params } => self.check_data(&params.0),
+            Call::SendTransaction { ref params } => self.check_data(&params.0),
+            Call::Call { ref params } => params
+                .0
+                .data
+                .as_ref()
+                .map_or(Ok(()), |data| self.check_data(data)),
+            Call::EstimateQuota { ref params } => params
+                .0
+                .data
+                .as_ref()
+                .map_or(Ok(()), |data| self.check_data(data)),
+            _ => Ok(()),
+        }
+    }
+
+    /// The outcome of each request of a batch, in order, or the violation
+    /// of `max_batch` for the whole of it.
+    pub fn check_batch(
+        &self,
+        requests: &[Request],
+        heights: &Heights,
+    ) -> Result<Vec<Result<(), LimitViolation>>, LimitViolation> {
+        within(Limit::MaxBatch, self.max_batch, requests.len())?;
+        Ok(requests
+            .iter()
+            .map(|request| self.check_with(&request.call, heights))
+            .collect())
+    }
+
+    pub fn check_filter(&self, filter: &Filter, heights: &Heights) -> Result<(), LimitViolation> {
+        if let (Some(from), Some(to)) = (
+            heights.resolve(&filter.from_block),
+            heights.resolve(&filter.to_block),
+        ) {
+            // An empty range when the ends are the wrong way round.
+            let blocks = if to < from {
+                0
+            } else {
+                (to - from).saturating_add(1)
+            };
+            within_u64(Limit::MaxBlockRange, self.max_block_range, blocks)?;
+        }
+        if let Some(ref address) = filter.address {
+            within(
+                Limit::MaxFilterAddresses,
+                self.max_filter_addresses,
+                variadic_len(address),
+            )?;
+        }
+        if let Some(ref topics) = filter.topics {
+            let count = topics.iter().map(variadic_len).sum();
+            within(Limit::MaxFilterTopics, self.max_filter_topics, count)?;
+        }
+        Ok(())
+    }
+
+    pub fn check_data(&self, data: &Data) -> Result<(), LimitViolation> {
+        within(Limit::MaxDataBytes, self.max_data_bytes, data.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Heights, Limit, LimitViolation, RequestLimits, LIMIT_EXCEEDED};
+    use crate::error::{Error, ErrorCode};
+    use crate::rpc_request::{
+        BlockNumberParams, Call, CallParams, GetLogsParams, NewFilterParams,
+        SendRawTransactionParams,
+    };
+    use crate::rpc_types::{BlockNumber, CallRequest, Data, Filter, VariadicValue};
+    use cita_types::{H160, H256};
+
+    fn violation(limit: Limit, max: u64, value: u64) -> Result<(), LimitViolation> {
+        Err(LimitViolation { limit, max, value })
+    }
+
+    fn range(from: u64, to: u64) -> Filter {
+        Filter::new(
+            BlockNumber::new(from.into()),
+            BlockNumber::new(to.into()),
+            None,
+            None,
+        )
+    }
+
+    #[test]
+    fn block_range() {
+        let limits = RequestLimits {
+            max_block_range: 100,
+            ..RequestLimits::default()
+        };
+        let heights = Heights::default();
+        let logs = |filter: Filter| Call::from(GetLogsParams::new(filter));
+        assert_eq!(limits.check(&logs(range(1, 100))), Ok(()));
+        assert_eq!(
+            limits.check(&logs(range(1, 101))),
+            violation(Limit::MaxBlockRange, 100, 101)
+        );
+        assert_eq!(limits.check(&logs(range(50, 10))), Ok(()));
+        assert_eq!(
+            limits.check(&Call::from(NewFilterParams::new(range(0, 100)))),
+            violation(Limit::MaxBlockRange, 100, 101)
+        );
+
+        // The whole chain, only known once `latest` is.
+        let all = Filter::new(BlockNumber::earliest(), BlockNumber::latest(), None, None);
+        assert_eq!(limits.check_with(&logs(all.clone()), &heights), Ok(()));
+        assert_eq!(
+            limits.check_with(&logs(all.clone()), &Heights::at(99)),
+            Ok(())
+        );
+        assert_eq!(
+            limits.check_with(&logs(all), &Heights::at(100)),
+            violation(Limit::MaxBlockRange, 100, 101)
+        );
+        let pending = Filter::new(BlockNumber::latest(), BlockNumber::pending(), None, None);
+        assert_eq!(Heights::at(7).resolve(&BlockNumber::pending()), Some(8));
+        assert_eq!(limits.check_with(&logs(pending), &Heights::at(7)), Ok(()));
+
+        let huge = Filter::new(
+            BlockNumber::earliest(),
+            BlockNumber::new(cita_types::U256::max_value().into()),
+            None,
+            None,
+        );
+        assert_eq!(
+            limits.check(&logs(huge)),
+            violation(Limit::MaxBlockRange, 100, u64::max_value())
+        );
+    }
+
+    #[test]
+    fn filter_addresses_and_topics() {
+        let limits = RequestLimits {
+            max_filter_addresses: 2,
+            max_filter_topics: 3,
+            ..RequestLimits::default()
+        };
+        let addresses = |n: u64| {
+            let mut filter = range(0, 0);
+            filter.address = Some(VariadicValue::multiple(
+                (0..n).map(|i| H160::from(i).into()).collect(),
+            ));
+            Call::from(GetLogsParams::new(filter))
+        };
+        assert_eq!(limits.check(&addresses(2)), Ok(()));
+        assert_eq!(
+            limits.check(&addresses(3)),
+            violation(Limit::MaxFilterAddresses, 2, 3)
+        );
+
+        let topics = |alternatives: u64| {
+            let mut filter = range(0, 0);
+            filter.topics = Some(vec![
+                VariadicValue::single(H256::from(1).into()),
+                VariadicValue::null(),
+                VariadicValue::multiple((0..alternatives).map(|i| H256::from(i).into()).collect()),
+            ]);
+            Call::from(GetLogsParams::new(filter))
+        };
+        assert_eq!(limits.check(&topics(2)), Ok(()));
+        assert_eq!(
+            limits.check(&topics(3)),
+            violation(Limit::MaxFilterTopics, 3, 4)
+        );
+    }
+
+    #[test]
+    fn data_bytes() {
+        let limits = RequestLimits {
+            max_data_bytes: 4,
+            ..RequestLimits::default()
+        };
+        let raw = |n: usize| Call::from(SendRawTransactionParams::new(Data::new(vec![0; n])));
+        assert_eq!(limits.check(&raw(4)), Ok(()));
+        assert_eq!(limits.check(&raw(5)), violation(Limit::MaxDataBytes, 4, 5));
+
+        let call = |data: Option<Data>| {
+            Call::from(CallParams::new(
+                CallRequest::new(None, H160::from(1).into(), data),
+                BlockNumber::latest(),
+            ))
+        };
+        assert_eq!(limits.check(&call(None)), Ok(()));
+        assert_eq!(
+            limits.check(&call(Some(Data::new(vec![0; 5])))),
+            violation(Limit::MaxDataBytes, 4, 5)
+        );
+        assert_eq!(RequestLimits::default().check(&raw(1 << 20)), Ok(()));
+    }
+
+    #[test]
+    fn batch_rejects_only_the_bad_entry() {
+        let limits = RequestLimits {
+            max_batch: 3,
+            max_data_bytes: 4,
+            ..RequestLimits::default()
+        };
+        let requests = vec![
+            BlockNumberParams::new().into_request(1),
+            SendRawTransactionParams::new(Data::new(vec![0; 5])).into_request(2),
+            SendRawTransactionParams::new(Data::new(vec![0; 4])).into_request(3),
+        ];
+        let heights = Heights::default();
+        assert_eq!(
+            limits.check_batch(&requests, &heights),
+            Ok(vec![Ok(()), violation(Limit::MaxDataBytes, 4, 5), Ok(())])
+        );
+
+        let mut longer = requests.clone();
+        longer.push(BlockNumberParams::new().into_request(4));
+        assert_eq!(
+            limits.check_batch(&longer, &heights),
+            Err(LimitViolation {
+                limit: Limit::MaxBatch,
+                max: 3,
+                value: 4,
+            })
+        );
+    }
+
+    #[test]
+    fn violation_as_error() {
+        let error = Error::from(LimitViolation {
+            limit: Limit::MaxBatch,
+            max: 3,
+            value: 4,
+        });
+        assert_eq!(error.code, ErrorCode::ServerError(LIMIT_EXCEEDED));
+        assert_eq!(
+            error.message,
+            "Limit exceeded: max_batch is 3, the request has 4"
+        );
+        assert_eq!(
+            error.data,
+            Some(json!({ "limit": "max_batch", "max": 3, "value": 4 }))
+        );
+    }
+}
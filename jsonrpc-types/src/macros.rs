@@ -67,3 +67,34 @@ macro_rules! enum_number {
         }
     }
 }
+
+/// `Serialize` and `Deserialize` of a response type whose optional fields
+/// follow the `NullStyle`. The type derives them with
+/// `#[serde(remote = "Type")]`, as functions of its own, which these call.
+macro_rules! null_style_serde {
+    ($name:ident $(<$param:ident>)*) => {
+        impl$(<$param: ::serde::Serialize>)* ::serde::Serialize for $name$(<$param>)* {
+            fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+            where
+                S: ::serde::Serializer,
+            {
+                crate::rpc_types::null_style::serialize(
+                    self,
+                    serializer,
+                    <$name$(<$param>)*>::serialize,
+                )
+            }
+        }
+
+        impl<'de $(, $param: ::serde::Deserialize<'de>)*> ::serde::Deserialize<'de>
+            for $name$(<$param>)*
+        {
+            fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+            where
+                D: ::serde::Deserializer<'de>,
+            {
+                <$name$(<$param>)*>::deserialize(deserializer)
+            }
+        }
+    }
+}
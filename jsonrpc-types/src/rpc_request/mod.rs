@@ -27,6 +27,7 @@ pub use self::request::{
     SendTransactionParams, UninstallFilterParams,
 };
 pub use self::request::{
-    Call, JsonRpcRequest, PartialCall, PartialRequest, Request, RequestInfo, ResponseResult,
+    Call, JsonRpcRequest, Logs, LogsResult, PartialCall, PartialRequest, Request, RequestInfo,
+    ResponseResult,
 };
 pub use self::rpcrequest::RpcRequest;
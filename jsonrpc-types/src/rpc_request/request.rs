@@ -19,12 +19,33 @@ use crate::internals::construct_params;
 
 use crate::rpc_types::{
     Block, BlockNumber, Boolean, CallRequest, Data, Data20, Data32, Filter, FilterChanges, Id, Log,
-    MetaData, OneItemTupleTrick, PeersInfo, Quantity, Receipt, RpcTransaction, SoftwareVersion,
-    TxResponse, Version,
+    MetaData, OneItemTupleTrick, Paginated, PeersInfo, Quantity, Receipt, RpcTransaction,
+    SoftwareVersion, TxResponse, Version,
 };
 
 pub type Logs = Vec<Log>;
 
+/// Result of `getLogs`: every log, or one page of them when the filter
+/// has a `page`.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum LogsResult {
+    All(Logs),
+    Page(Paginated<Log>),
+}
+
+impl From<Logs> for LogsResult {
+    fn from(logs: Logs) -> Self {
+        LogsResult::All(logs)
+    }
+}
+
+impl From<Paginated<Log>> for LogsResult {
+    fn from(page: Paginated<Log>) -> Self {
+        LogsResult::Page(page)
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct RequestInfo {
     pub jsonrpc: Option<Version>,
@@ -226,7 +247,7 @@ macro_rules! impl_for_each_jsonrpc_requests {
             (GetBlockByHash, GetBlockByHashParams: [Data32, Boolean], Block),
             (GetBlockByNumber, GetBlockByNumberParams: [BlockNumber, Boolean], Block),
             (GetTransactionReceipt, GetTransactionReceiptParams: [Data32], Receipt),
-            (GetLogs, GetLogsParams: [Filter], Logs),
+            (GetLogs, GetLogsParams: [Filter], LogsResult),
             (Call, CallParams: [CallRequest, BlockNumber], Data),
             (GetTransaction, GetTransactionParams: [Data32], RpcTransaction),
             (GetTransactionCount, GetTransactionCountParams: [Data20, BlockNumber], Quantity),
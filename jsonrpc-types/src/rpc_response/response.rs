@@ -16,7 +16,6 @@ use std::vec::Vec;
 
 use serde::de::Error as SError;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
-use serde_json::{from_value, Value};
 
 use crate::error::Error;
 use crate::rpc_request::{RequestInfo, ResponseResult};
@@ -80,11 +79,19 @@ impl<'a> Deserialize<'a> for Output {
     where
         D: Deserializer<'a>,
     {
-        let v: Value = Deserialize::deserialize(deserializer)?;
-        from_value(v.clone())
-            .map(Output::Failure)
-            .or_else(|_| from_value(v).map(Output::Success))
-            .map_err(|_| D::Error::custom("")) // types must match
+        // Not through a `serde_json::Value`, which has no byte strings.
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Either {
+            Failure(RpcFailure),
+            Success(Box<RpcSuccess>),
+        }
+
+        match Either::deserialize(deserializer) {
+            Ok(Either::Failure(failure)) => Ok(Output::Failure(failure)),
+            Ok(Either::Success(success)) => Ok(Output::Success(success)),
+            Err(_) => Err(D::Error::custom("")), // types must match
+        }
     }
 }
 
@@ -129,11 +136,18 @@ impl<'a> Deserialize<'a> for RpcResponse {
     where
         D: Deserializer<'a>,
     {
-        let v: Value = Deserialize::deserialize(deserializer)?;
-        from_value(v.clone())
-            .map(RpcResponse::Batch)
-            .or_else(|_| from_value(v).map(RpcResponse::Single))
-            .map_err(|_| D::Error::custom("")) // types must match
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Either {
+            Batch(Vec<Output>),
+            Single(Box<Output>),
+        }
+
+        match Either::deserialize(deserializer) {
+            Ok(Either::Batch(batch)) => Ok(RpcResponse::Batch(batch)),
+            Ok(Either::Single(single)) => Ok(RpcResponse::Single(single)),
+            Err(_) => Err(D::Error::custom("")), // types must match
+        }
     }
 }
 
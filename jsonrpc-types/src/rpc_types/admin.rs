@@ -0,0 +1,334 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Admin requests: snapshots and amending chain state.
+//!
+//! Unknown fields are rejected, so a typo fails instead of being ignored.
+//! The requests are destructive, which is why they go through two calls:
+//! the first one gets a `ConfirmationToken` back, the second one repeats
+//! the request with the token, see `AdminCall`.
+
+use std::fmt;
+
+use cita_types::H256;
+use hashable::constant_time_eq;
+use serde::Serialize;
+use serde_json;
+
+use crate::rpc_types::pagination::hmac;
+use crate::rpc_types::{Data, Data20, Data32, Integer, Quantity};
+
+/// Seconds a confirmation token stays valid by default.
+pub const DEFAULT_CONFIRMATION_TTL: u64 = 60;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum SnapshotCommand {
+    Snapshot,
+    Restore,
+    Clear,
+}
+
+/// Snapshot request params.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(deny_unknown_fields)]
+pub struct SnapshotRequest {
+    pub cmd: SnapshotCommand,
+    #[serde(
+        rename = "startHeight",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub start_height: Option<Quantity>,
+    #[serde(rename = "endHeight", default, skip_serializing_if = "Option::is_none")]
+    pub end_height: Option<Quantity>,
+    /// Snapshot file, relative to the node's snapshot directory
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+}
+
+/// Amend request params, tagged by `kind`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(tag = "kind", rename_all = "lowercase", deny_unknown_fields)]
+pub enum AmendRequest {
+    /// Replace the code of a contract
+    Code { address: Data20, code: Data },
+    /// Replace the ABI of a contract
+    Abi { address: Data20, abi: Data },
+    /// Set one storage slot of a contract
+    Kv {
+        address: Data20,
+        key: Data32,
+        value: Data32,
+    },
+    /// Set the balance of an account
+    Balance { address: Data20, balance: Quantity },
+}
+
+/// Proof that the caller saw what an admin request is going to do.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(deny_unknown_fields)]
+pub struct ConfirmationToken {
+    /// Unix time in seconds after which the token is rejected
+    #[serde(rename = "expiresAt")]
+    pub expires_at: Integer,
+    pub mac: Data32,
+}
+
+/// An admin request, with the token from the first call when confirming.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(deny_unknown_fields)]
+pub struct AdminCall<T> {
+    pub request: T,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub confirmation: Option<ConfirmationToken>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfirmationError {
+    Expired,
+    /// Made for another request, with another key, or modified.
+    Invalid,
+}
+
+impl fmt::Display for ConfirmationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConfirmationError::Expired => write!(f, "confirmation token expired"),
+            ConfirmationError::Invalid => write!(f, "invalid confirmation token"),
+        }
+    }
+}
+
+impl ::std::error::Error for ConfirmationError {}
+
+/// The MAC covers the request as JSON, which is canonical since fields
+/// are written in declaration order, and the expiry.
+fn confirmation_mac<T: Serialize>(key: &[u8], request: &T, expires_at: u64) -> H256 {
+    let mut message = serde_json::to_vec(request).expect("admin request serialization");
+    message.extend_from_slice(&expires_at.to_be_bytes());
+    H256::from(hmac(key, &message))
+}
+
+/// Token for confirming `request` until `now + ttl`, `key` is known only
+/// to the server.
+pub fn issue_confirmation<T: Serialize>(
+    key: &[u8],
+    request: &T,
+    now: u64,
+    ttl: u64,
+) -> ConfirmationToken {
+    let expires_at = now.saturating_add(ttl);
+    ConfirmationToken {
+        expires_at: Integer::new(expires_at),
+        mac: Data32::new(confirmation_mac(key, request, expires_at)),
+    }
+}
+
+/// Check a token echoed back with `request`.
+pub fn verify_confirmation<T: Serialize>(
+    key: &[u8],
+    request: &T,
+    token: &ConfirmationToken,
+    now: u64,
+) -> Result<(), ConfirmationError> {
+    let expires_at: u64 = token.expires_at.clone().into();
+    let expected = confirmation_mac(key, request, expires_at);
+    let mac: H256 = token.mac.clone().into();
+    if !constant_time_eq(&expected, &mac) {
+        return Err(ConfirmationError::Invalid);
+    }
+    if now > expires_at {
+        return Err(ConfirmationError::Expired);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        issue_confirmation, verify_confirmation, AdminCall, AmendRequest, ConfirmationError,
+        SnapshotCommand, SnapshotRequest, DEFAULT_CONFIRMATION_TTL,
+    };
+    use cita_types::{H160, H256, U256};
+    use serde_json;
+
+    const KEY: &[u8] = b"admin secret";
+
+    #[test]
+    fn snapshot_serialization() {
+        let request = SnapshotRequest {
+            cmd: SnapshotCommand::Snapshot,
+            start_height: Some(U256::from(10).into()),
+            end_height: Some(U256::from(20).into()),
+            file: Some("snap".to_owned()),
+        };
+        let value = json!({
+            "cmd": "snapshot",
+            "startHeight": "0xa",
+            "endHeight": "0x14",
+            "file": "snap",
+        });
+        assert_eq!(serde_json::to_value(&request).unwrap(), value);
+        assert_eq!(
+            serde_json::from_value::<SnapshotRequest>(value).unwrap(),
+            request
+        );
+
+        let clear: SnapshotRequest = serde_json::from_value(json!({ "cmd": "clear" })).unwrap();
+        assert_eq!(clear.cmd, SnapshotCommand::Clear);
+        assert_eq!(clear.file, None);
+
+        let err = serde_json::from_value::<SnapshotRequest>(json!({ "cmd": "snapshop" }))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("snapshop"), "{}", err);
+        assert!(err.contains("`snapshot`, `restore`, `clear`"), "{}", err);
+
+        // A typo'd field is an error, not a no-op.
+        assert!(serde_json::from_value::<SnapshotRequest>(
+            json!({ "cmd": "snapshot", "start_height": "0x1" })
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn amend_serialization() {
+        let address = H160::from(0x1234);
+        let requests = vec![
+            (
+                AmendRequest::Code {
+                    address: address.into(),
+                    code: vec![0x60, 0x00].into(),
+                },
+                json!({
+                    "kind": "code",
+                    "address": "0x0000000000000000000000000000000000001234",
+                    "code": "0x6000",
+                }),
+            ),
+            (
+                AmendRequest::Kv {
+                    address: address.into(),
+                    key: H256::from(1).into(),
+                    value: H256::from(2).into(),
+                },
+                json!({
+                    "kind": "kv",
+                    "address": "0x0000000000000000000000000000000000001234",
+                    "key": "0x0000000000000000000000000000000000000000000000000000000000000001",
+                    "value": "0x0000000000000000000000000000000000000000000000000000000000000002",
+                }),
+            ),
+            (
+                AmendRequest::Balance {
+                    address: address.into(),
+                    balance: U256::from(100).into(),
+                },
+                json!({
+                    "kind": "balance",
+                    "address": "0x0000000000000000000000000000000000001234",
+                    "balance": "0x64",
+                }),
+            ),
+        ];
+        for (request, value) in requests {
+            assert_eq!(serde_json::to_value(&request).unwrap(), value);
+            assert_eq!(
+                serde_json::from_value::<AmendRequest>(value).unwrap(),
+                request
+            );
+        }
+
+        // Wrong kind, wrong field type, unknown field.
+        for value in vec![
+            json!({ "kind": "storage", "address": "0x0000000000000000000000000000000000001234" }),
+            json!({ "kind": "balance", "address": "0x1234", "balance": "0x1" }),
+            json!({
+                "kind": "abi",
+                "address": "0x0000000000000000000000000000000000001234",
+                "abi": "0x00",
+                "code": "0x00",
+            }),
+        ] {
+            assert!(serde_json::from_value::<AmendRequest>(value).is_err());
+        }
+    }
+
+    #[test]
+    fn confirmation_flow() {
+        let request = AmendRequest::Balance {
+            address: H160::from(7).into(),
+            balance: U256::from(1).into(),
+        };
+        let now = 1_000_000;
+
+        // First call, no token yet.
+        let first: AdminCall<AmendRequest> = serde_json::from_value(json!({
+            "request": serde_json::to_value(&request).unwrap(),
+        }))
+        .unwrap();
+        assert_eq!(first.confirmation, None);
+        let token = issue_confirmation(KEY, &first.request, now, DEFAULT_CONFIRMATION_TTL);
+
+        // Second call echoes it.
+        let second = AdminCall {
+            request: request.clone(),
+            confirmation: Some(token.clone()),
+        };
+        let second: AdminCall<AmendRequest> =
+            serde_json::from_str(&serde_json::to_string(&second).unwrap()).unwrap();
+        let echoed = second.confirmation.unwrap();
+        assert_eq!(echoed, token);
+        assert_eq!(
+            verify_confirmation(KEY, &second.request, &echoed, now + 10),
+            Ok(())
+        );
+        assert_eq!(
+            verify_confirmation(
+                KEY,
+                &second.request,
+                &echoed,
+                now + DEFAULT_CONFIRMATION_TTL
+            ),
+            Ok(())
+        );
+
+        // Expired.
+        assert_eq!(
+            verify_confirmation(KEY, &request, &token, now + DEFAULT_CONFIRMATION_TTL + 1),
+            Err(ConfirmationError::Expired)
+        );
+
+        // For another request, with another key, or with the expiry moved.
+        let other = AmendRequest::Balance {
+            address: H160::from(7).into(),
+            balance: U256::from(1000).into(),
+        };
+        assert_eq!(
+            verify_confirmation(KEY, &other, &token, now),
+            Err(ConfirmationError::Invalid)
+        );
+        assert_eq!(
+            verify_confirmation(b"other key", &request, &token, now),
+            Err(ConfirmationError::Invalid)
+        );
+        let mut moved = token.clone();
+        moved.expires_at = (now + 3600).into();
+        assert_eq!(
+            verify_confirmation(KEY, &request, &moved, now + 120),
+            Err(ConfirmationError::Invalid)
+        );
+    }
+}
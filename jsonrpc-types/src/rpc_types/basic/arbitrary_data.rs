@@ -12,13 +12,24 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use rustc_serialize::hex::FromHex;
 use serde::de::{self, Visitor};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
+use cita_types::hex::{decode_hex, strip_0x};
 use cita_types::traits::LowerHex;
 
+use super::shorten;
+
+/// Most bytes a `Data` is deserialized with. `RequestLimits` may allow
+/// less, but never more, since a longer one is refused before it is
+/// decoded.
+pub const MAX_DATA_LEN: usize = 2 * 1024 * 1024;
+
 /// Arbitrary length bytes (wrapper structure around vector of bytes).
+///
+/// Deserialized only from `0x` and an even number of hexadecimal digits,
+/// `0x` alone for no bytes, in human readable formats. Made from bytes
+/// and turned back into them as they are, nothing padded or trimmed.
 #[derive(Debug, PartialEq, Eq, Default, Hash, Clone)]
 pub struct Data(Vec<u8>);
 
@@ -26,6 +37,14 @@ impl Data {
     pub fn new(data: Vec<u8>) -> Data {
         Data(data)
     }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
 }
 
 impl Serialize for Data {
@@ -33,7 +52,11 @@ impl Serialize for Data {
     where
         S: Serializer,
     {
-        serializer.serialize_str(self.0.lower_hex_with_0x().as_ref())
+        if serializer.is_human_readable() {
+            serializer.serialize_str(self.0.lower_hex_with_0x().as_ref())
+        } else {
+            serializer.serialize_bytes(&self.0)
+        }
     }
 }
 
@@ -42,7 +65,11 @@ impl<'de> Deserialize<'de> for Data {
     where
         D: Deserializer<'de>,
     {
-        deserializer.deserialize_str(DataVisitor)
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_str(DataVisitor)
+        } else {
+            deserializer.deserialize_byte_buf(DataVisitor)
+        }
     }
 }
 
@@ -59,35 +86,17 @@ impl<'de> Visitor<'de> for DataVisitor {
     where
         E: de::Error,
     {
-        if value.is_empty() {
-            Ok(Data::new(Vec::new()))
-        } else if value.len() >= 2
-            && (&value[0..2] == "0x" || &value[0..2] == "0X")
-            && value.len() & 1 == 0
-        {
-            let data = FromHex::from_hex(&value[2..]).map_err(|_| {
-                if value.len() > 12 {
-                    E::custom(format!(
-                        "invalid hexadecimal string: [{}..(omit {})..{}]",
-                        &value[..6],
-                        value.len() - 12,
-                        &value[value.len() - 6..value.len()]
-                    ))
-                } else {
-                    E::custom(format!("invalid hexadecimal string: [{}]", value))
-                }
-            })?;
-            Ok(Data::new(data))
-        } else if value.len() > 12 {
-            Err(E::custom(format!(
-                "invalid format: [{}..(omit {})..{}]",
-                &value[..6],
-                value.len() - 12,
-                &value[value.len() - 6..value.len()]
-            )))
-        } else {
-            Err(E::custom(format!("invalid format: [{}]", value)))
-        }
+        let digits = strip_0x(value)
+            .ok_or_else(|| E::custom(format!("invalid format: [{}]", shorten(value))))?;
+        check_len(digits.len() / 2)?;
+        let data = decode_hex(value).map_err(|err| {
+            E::custom(format!(
+                "invalid hexadecimal string [{}]: {}",
+                shorten(value),
+                err
+            ))
+        })?;
+        Ok(Data::new(data))
     }
 
     fn visit_string<E>(self, value: String) -> Result<Self::Value, E>
@@ -96,6 +105,36 @@ impl<'de> Visitor<'de> for DataVisitor {
     {
         self.visit_str(value.as_ref())
     }
+
+    fn visit_bytes<E>(self, value: &[u8]) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        check_len(value.len())?;
+        Ok(Data::new(value.to_vec()))
+    }
+
+    fn visit_byte_buf<E>(self, value: Vec<u8>) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        check_len(value.len())?;
+        Ok(Data::new(value))
+    }
+}
+
+fn check_len<E>(len: usize) -> Result<(), E>
+where
+    E: de::Error,
+{
+    if len > MAX_DATA_LEN {
+        Err(E::custom(format!(
+            "data too long: {} bytes, at most {}",
+            len, MAX_DATA_LEN
+        )))
+    } else {
+        Ok(())
+    }
 }
 
 impl From<Vec<u8>> for Data {
@@ -112,7 +151,8 @@ impl Into<Vec<u8>> for Data {
 
 #[cfg(test)]
 mod tests {
-    use super::Data;
+    use super::{Data, MAX_DATA_LEN};
+    use bincode::{deserialize, serialize, Infinite};
     use rustc_serialize::hex::FromHex;
     use serde_json;
 
@@ -130,7 +170,11 @@ mod tests {
             (r#""ab""#, None),
             (r#""0x123""#, None),
             (r#""0xabcdefgh""#, None),
-            (r#""""#, Some(Data::new(vec![]))),
+            (r#""""#, None),
+            (r#""0""#, None),
+            (r#""0x0""#, None),
+            (r#""0x 12""#, None),
+            (r#""0x１２""#, None),
             (r#""0x""#, Some(Data::new(vec![]))),
             (r#""0x12""#, Some(Data::new(vec![0x12]))),
             (r#""0X12""#, Some(Data::new(vec![0x12]))),
@@ -146,5 +190,40 @@ mod tests {
                 assert!(result.is_err());
             }
         }
+
+        let err = serde_json::from_str::<Data>(r#""0x12zz""#).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("invalid hexadecimal string [0x12zz]: invalid character 'z' at index 4"));
+    }
+
+    #[test]
+    fn deserialize_up_to_the_cap() {
+        let hex = |len: usize| format!(r#""0x{}""#, "ab".repeat(len));
+        let data: Data = serde_json::from_str(&hex(MAX_DATA_LEN)).unwrap();
+        assert_eq!(data.len(), MAX_DATA_LEN);
+
+        let err = serde_json::from_str::<Data>(&hex(MAX_DATA_LEN + 1)).unwrap_err();
+        assert!(err.to_string().starts_with(&format!(
+            "data too long: {} bytes, at most {}",
+            MAX_DATA_LEN + 1,
+            MAX_DATA_LEN
+        )));
+
+        let bytes = serialize(&Data::new(vec![7; MAX_DATA_LEN + 1]), Infinite).unwrap();
+        assert!(deserialize::<Data>(&bytes).is_err());
+    }
+
+    #[test]
+    fn round_trip_is_lossless() {
+        for len in &[0, 1, 2, 31, 32, 33, 255, 1024 * 1024] {
+            let bytes: Vec<u8> = (0..*len).map(|i| (i * 7 + i / 256) as u8).collect();
+            let data = Data::from(bytes.clone());
+            let serialized = serde_json::to_string(&data).unwrap();
+            assert_eq!(serialized.len(), 2 * len + 4);
+            let deserialized: Data = serde_json::from_str(&serialized).unwrap();
+            let back: Vec<u8> = deserialized.into();
+            assert_eq!(back, bytes);
+        }
     }
 }
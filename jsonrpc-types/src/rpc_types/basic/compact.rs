@@ -0,0 +1,231 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Hex text for human readable formats, byte strings for the others.
+//!
+//! JSON has hashes and quantities as `0x` prefixed hex, over twice the
+//! size of their bytes. A serializer which isn't human readable, like the
+//! one of CBOR, gets the bytes instead: all of them for a hash, the
+//! shortest big endian ones for a quantity. Fields of the `cita_types`
+//! types, whose serde impls write hex always, use this module in
+//! `#[serde(with)]`, or `option` and `vec` of it.
+//!
+//! Reading accepts both either way. serde buffers an untagged enum
+//! before trying its variants, and what it reads from the buffer claims
+//! to be human readable, whatever the format was.
+
+use std::fmt;
+use std::marker::PhantomData;
+
+use serde::de::{self, DeserializeOwned, IntoDeserializer, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use cita_types::{Bloom, H160, H256, U256};
+
+pub(crate) trait Compact: Sized {
+    fn to_compact(&self) -> Vec<u8>;
+    fn from_compact(bytes: &[u8]) -> Option<Self>;
+}
+
+macro_rules! impl_compact_for_hash {
+    ($($hash:ident => $len:expr),+) => {
+        $(
+            impl Compact for $hash {
+                fn to_compact(&self) -> Vec<u8> {
+                    self.to_vec()
+                }
+
+                fn from_compact(bytes: &[u8]) -> Option<Self> {
+                    if bytes.len() == $len {
+                        Some($hash::from_slice(bytes))
+                    } else {
+                        None
+                    }
+                }
+            }
+        )+
+    };
+}
+
+impl_compact_for_hash!(H160 => 20, H256 => 32, Bloom => 256);
+
+impl Compact for U256 {
+    fn to_compact(&self) -> Vec<u8> {
+        let mut bytes = [0u8; 32];
+        self.to_big_endian(&mut bytes);
+        let zeros = bytes.iter().take_while(|&&b| b == 0).count();
+        bytes[zeros..].to_vec()
+    }
+
+    fn from_compact(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() <= 32 {
+            Some(U256::from_big_endian(bytes))
+        } else {
+            None
+        }
+    }
+}
+
+pub(crate) fn serialize<T, S>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
+where
+    T: Compact + Serialize,
+    S: Serializer,
+{
+    if serializer.is_human_readable() {
+        value.serialize(serializer)
+    } else {
+        serializer.serialize_bytes(&value.to_compact())
+    }
+}
+
+pub(crate) fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+where
+    T: Compact + DeserializeOwned,
+    D: Deserializer<'de>,
+{
+    let visitor = CompactVisitor(PhantomData);
+    if deserializer.is_human_readable() {
+        deserializer.deserialize_str(visitor)
+    } else {
+        deserializer.deserialize_bytes(visitor)
+    }
+}
+
+struct CompactVisitor<T>(PhantomData<T>);
+
+impl<'de, T> Visitor<'de> for CompactVisitor<T>
+where
+    T: Compact + DeserializeOwned,
+{
+    type Value = T;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a hexadecimal string or bytes")
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<T, E>
+    where
+        E: de::Error,
+    {
+        T::deserialize(value.into_deserializer())
+    }
+
+    fn visit_bytes<E>(self, value: &[u8]) -> Result<T, E>
+    where
+        E: de::Error,
+    {
+        T::from_compact(value).ok_or_else(|| E::invalid_length(value.len(), &self))
+    }
+}
+
+struct Ser<'a, T>(&'a T);
+
+impl<'a, T> Serialize for Ser<'a, T>
+where
+    T: Compact + Serialize,
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serialize(self.0, serializer)
+    }
+}
+
+struct De<T>(T);
+
+impl<'de, T> Deserialize<'de> for De<T>
+where
+    T: Compact + DeserializeOwned,
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserialize(deserializer).map(De)
+    }
+}
+
+pub(crate) mod option {
+    use super::{Compact, De, Ser};
+    use serde::de::DeserializeOwned;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub(crate) fn serialize<T, S>(value: &Option<T>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        T: Compact + Serialize,
+        S: Serializer,
+    {
+        match value {
+            Some(value) => serializer.serialize_some(&Ser(value)),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub(crate) fn deserialize<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+    where
+        T: Compact + DeserializeOwned,
+        D: Deserializer<'de>,
+    {
+        Option::<De<T>>::deserialize(deserializer).map(|value| value.map(|De(value)| value))
+    }
+}
+
+pub(crate) mod vec {
+    use super::{Compact, De, Ser};
+    use serde::de::DeserializeOwned;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub(crate) fn serialize<T, S>(values: &[T], serializer: S) -> Result<S::Ok, S::Error>
+    where
+        T: Compact + Serialize,
+        S: Serializer,
+    {
+        serializer.collect_seq(values.iter().map(Ser))
+    }
+
+    pub(crate) fn deserialize<'de, T, D>(deserializer: D) -> Result<Vec<T>, D::Error>
+    where
+        T: Compact + DeserializeOwned,
+        D: Deserializer<'de>,
+    {
+        Vec::<De<T>>::deserialize(deserializer)
+            .map(|values| values.into_iter().map(|De(value)| value).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Compact;
+    use cita_types::{H160, U256};
+
+    #[test]
+    fn shortest_quantities() {
+        assert_eq!(U256::from(0).to_compact(), Vec::<u8>::new());
+        assert_eq!(U256::from(0x1234).to_compact(), vec![0x12, 0x34]);
+        assert_eq!(U256::max_value().to_compact(), vec![0xff; 32]);
+        for value in &[U256::from(0), U256::from(0x1234), U256::max_value()] {
+            assert_eq!(U256::from_compact(&value.to_compact()), Some(*value));
+        }
+        assert_eq!(U256::from_compact(&[1; 33]), None);
+    }
+
+    #[test]
+    fn hashes_have_their_length() {
+        let address = H160::from(7);
+        assert_eq!(H160::from_compact(&address.to_compact()), Some(address));
+        assert_eq!(H160::from_compact(&[0; 19]), None);
+        assert_eq!(H160::from_compact(&[0; 32]), None);
+    }
+}
@@ -12,14 +12,16 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::str::FromStr;
-
 use serde::de::{self, Visitor};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
+use cita_types::hex::{strip_0x, FromHexStr};
 use cita_types::traits::LowerHex;
 use cita_types::{H160, H256};
 
+use super::compact::Compact;
+use super::shorten;
+
 /// Fixed length bytes (wrapper structure around H256).
 #[derive(Debug, PartialEq, Eq, Default, Hash, Clone)]
 pub struct Data32(H256);
@@ -44,7 +46,11 @@ macro_rules! impl_for_fixed_type {
             where
                 S: Serializer,
             {
-                serializer.serialize_str(self.0.lower_hex_with_0x().as_ref())
+                if serializer.is_human_readable() {
+                    serializer.serialize_str(self.0.lower_hex_with_0x().as_ref())
+                } else {
+                    serializer.serialize_bytes(&self.0)
+                }
             }
         }
 
@@ -53,7 +59,11 @@ macro_rules! impl_for_fixed_type {
             where
                 D: Deserializer<'de>,
             {
-                deserializer.deserialize_str($visitor)
+                if deserializer.is_human_readable() {
+                    deserializer.deserialize_str($visitor)
+                } else {
+                    deserializer.deserialize_bytes($visitor)
+                }
             }
         }
 
@@ -68,34 +78,26 @@ macro_rules! impl_for_fixed_type {
             where
                 E: de::Error,
             {
-                if value.len() == 2 + $outer_size * 2
-                    && (&value[0..2] == "0x" || &value[0..2] == "0X")
-                {
-                    let data = $inner::from_str(&value[2..]).map_err(|_| {
-                        if value.len() > 12 {
-                            E::custom(format!(
-                                "invalid hexadecimal string: [{}..(omit {})..{}]",
-                                &value[..6],
-                                value.len() - 12,
-                                &value[value.len() - 6..value.len()]
-                            ))
-                        } else {
-                            E::custom(format!("invalid hexadecimal string: [{}]", value))
-                        }
-                    })?;
-                    Ok($outer::new(data))
-                } else {
-                    if value.len() > 12 {
-                        Err(E::custom(format!(
-                            "invalid format: [{}..(omit {})..{}]",
-                            &value[..6],
-                            value.len() - 12,
-                            &value[value.len() - 6..value.len()]
-                        )))
-                    } else {
-                        Err(E::custom(format!("invalid format: [{}]", value)))
-                    }
+                let digits = match strip_0x(value) {
+                    Some(digits) => digits,
+                    None => return Err(E::custom(format!("invalid format: [{}]", shorten(value)))),
+                };
+                // Before the digits are decoded, which a long string would
+                // make costly.
+                if digits.len() > $outer_size * 2 {
+                    return Err(E::custom(format!(
+                        "invalid hexadecimal string [{}]: longer than {} digits",
+                        shorten(value),
+                        $outer_size * 2
+                    )));
                 }
+                $inner::from_hex_str(value).map($outer::new).map_err(|err| {
+                    E::custom(format!(
+                        "invalid hexadecimal string [{}]: {}",
+                        shorten(value),
+                        err
+                    ))
+                })
             }
 
             fn visit_string<E>(self, value: String) -> Result<Self::Value, E>
@@ -104,6 +106,15 @@ macro_rules! impl_for_fixed_type {
             {
                 self.visit_str(value.as_ref())
             }
+
+            fn visit_bytes<E>(self, value: &[u8]) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                $inner::from_compact(value)
+                    .map($outer::new)
+                    .ok_or_else(|| E::invalid_length(value.len(), &self))
+            }
         }
 
         impl From<$inner> for $outer {
@@ -167,6 +178,9 @@ macro_rules! test_for_fixed_type {
                     let result: Result<$outer, serde_json::Error> = serde_json::from_str(&data);
                     assert!(result.is_err());
                 }
+                let data = format!(r#""0x{}""#, "f".repeat(100_000));
+                let err = serde_json::from_str::<$outer>(&data).unwrap_err();
+                assert!(err.to_string().contains("longer than"), "{}", err);
 
                 let testdata = vec![
                     ("g", None),
@@ -15,6 +15,7 @@
 use serde::de::{self, Visitor};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
+use super::float_rejected;
 use crate::Error;
 
 /// A unsigned integer (wrapper structure around u64).
@@ -60,6 +61,13 @@ impl<'de> Visitor<'de> for IntegerVisitor {
     {
         Ok(Integer::new(value))
     }
+
+    fn visit_f64<E>(self, value: f64) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        Err(float_rejected("integer", value))
+    }
 }
 
 impl From<u64> for Integer {
@@ -136,6 +144,9 @@ mod tests {
             (r#"a"#, None),
             (r#"0"#, Some(0u64)),
             (r#"10"#, Some(10u64)),
+            (r#"1e5"#, None),
+            (r#"10.0"#, None),
+            (r#"-1"#, None),
         ];
         for (data, expected_opt) in testdata.into_iter() {
             let result: Result<Integer, serde_json::Error> = serde_json::from_str(data);
@@ -145,6 +156,13 @@ mod tests {
                 assert!(result.is_err());
             }
         }
+        let err = serde_json::from_str::<Integer>("1e5").unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("invalid integer: floating point number 100000.0"),
+            "{}",
+            err
+        );
     }
 
     #[test]
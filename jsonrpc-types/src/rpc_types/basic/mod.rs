@@ -14,13 +14,14 @@
 
 mod arbitrary_data;
 mod boolean;
+pub(crate) mod compact;
 mod fixed_data;
 mod integer;
 mod quantity;
 mod tags;
 mod variadic;
 
-pub use self::arbitrary_data::Data;
+pub use self::arbitrary_data::{Data, MAX_DATA_LEN};
 pub use self::boolean::Boolean;
 pub use self::fixed_data::{Data20, Data32};
 pub use self::integer::Integer;
@@ -28,6 +29,34 @@ pub use self::quantity::Quantity;
 pub use self::tags::{BlockTag, EconomicalModel};
 pub use self::variadic::VariadicValue;
 
+pub(crate) use self::quantity::QuantityVisitor;
+
+use serde::de;
+
+/// `value` as the errors quote it, the middle of a long one left out.
+pub(crate) fn shorten(value: &str) -> String {
+    let len = value.chars().count();
+    if len > 12 {
+        let head: String = value.chars().take(6).collect();
+        let tail: String = value.chars().skip(len - 6).collect();
+        format!("{}..(omit {})..{}", head, len - 12, tail)
+    } else {
+        value.to_owned()
+    }
+}
+
+/// The error for a JSON number with a fraction or an exponent, like `1e5`,
+/// sent for an integer `what`.
+pub(crate) fn float_rejected<E>(what: &str, value: f64) -> E
+where
+    E: de::Error,
+{
+    E::custom(format!(
+        "invalid {}: floating point number {:?}, expected an integer",
+        what, value
+    ))
+}
+
 // serde: Tuple enums with single element should not be a json-array
 // https://github.com/serde-rs/serde/pull/111
 #[derive(Debug, Clone, PartialEq)]
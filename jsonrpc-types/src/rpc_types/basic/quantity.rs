@@ -17,9 +17,17 @@ use std::str::FromStr;
 use serde::de::{self, Visitor};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
+use cita_types::hex::strip_0x;
 use cita_types::traits::LowerHex;
 use cita_types::U256;
 
+use super::compact::Compact;
+use super::{float_rejected, shorten};
+
+/// Digits of the largest `U256`, in hexadecimal and in decimal.
+const MAX_HEX_DIGITS: usize = 64;
+const MAX_DEC_DIGITS: usize = 78;
+
 /// A big unsigned integer (wrapper structure around U256).
 #[derive(Debug, PartialEq, Eq, Default, Hash, Clone)]
 pub struct Quantity(U256);
@@ -35,7 +43,11 @@ impl Serialize for Quantity {
     where
         S: Serializer,
     {
-        serializer.serialize_str(self.0.lower_hex_with_0x().as_ref())
+        if serializer.is_human_readable() {
+            serializer.serialize_str(self.0.lower_hex_with_0x().as_ref())
+        } else {
+            serializer.serialize_bytes(&self.0.to_compact())
+        }
     }
 }
 
@@ -44,11 +56,16 @@ impl<'de> Deserialize<'de> for Quantity {
     where
         D: Deserializer<'de>,
     {
-        deserializer.deserialize_str(QuantityVisitor)
+        if deserializer.is_human_readable() {
+            // Any, to tell a float sent for a quantity.
+            deserializer.deserialize_any(QuantityVisitor)
+        } else {
+            deserializer.deserialize_bytes(QuantityVisitor)
+        }
     }
 }
 
-struct QuantityVisitor;
+pub(crate) struct QuantityVisitor;
 
 impl<'de> Visitor<'de> for QuantityVisitor {
     type Value = Quantity;
@@ -61,33 +78,36 @@ impl<'de> Visitor<'de> for QuantityVisitor {
     where
         E: de::Error,
     {
-        if value.len() > 2 && (&value[0..2] == "0x" || &value[0..2] == "0X") {
-            let data = U256::from_str(&value[2..]).map_err(|_| {
-                if value.len() > 12 {
-                    E::custom(format!(
-                        "invalid hexadecimal string: [{}..(omit {})..{}]",
-                        &value[..6],
-                        value.len() - 12,
-                        &value[value.len() - 6..value.len()]
-                    ))
-                } else {
-                    E::custom(format!("invalid hexadecimal string: [{}]", value))
-                }
+        // Leading zeros aside, digits past what 256 bits hold aren't
+        // decoded, `U256::from_str` would panic on them.
+        if let Some(digits) = strip_0x(value).filter(|digits| !digits.is_empty()) {
+            let digits = digits.trim_start_matches('0');
+            if digits.len() > MAX_HEX_DIGITS {
+                return Err(E::custom(format!(
+                    "hexadecimal string too large: [{}]",
+                    shorten(value)
+                )));
+            }
+            if digits.is_empty() {
+                return Ok(Quantity::default());
+            }
+            let data = U256::from_str(digits).map_err(|_| {
+                E::custom(format!("invalid hexadecimal string: [{}]", shorten(value)))
             })?;
             Ok(Quantity::new(data))
         } else if !value.is_empty() {
-            let data = U256::from_dec_str(&value[..]).map_err(|_| {
-                if value.len() > 12 {
-                    E::custom(format!(
-                        "invalid decimal string: [{}..(omit {})..{}]",
-                        &value[..6],
-                        value.len() - 12,
-                        &value[value.len() - 6..value.len()]
-                    ))
-                } else {
-                    E::custom(format!("invalid decimal string: [{}]", value))
-                }
-            })?;
+            let digits = value.trim_start_matches('0');
+            if digits.len() > MAX_DEC_DIGITS {
+                return Err(E::custom(format!(
+                    "decimal string too large: [{}]",
+                    shorten(value)
+                )));
+            }
+            if digits.is_empty() {
+                return Ok(Quantity::default());
+            }
+            let data = U256::from_dec_str(digits)
+                .map_err(|_| E::custom(format!("invalid decimal string: [{}]", shorten(value))))?;
             Ok(Quantity::new(data))
         } else {
             Err(E::custom("invalid input: string is empty".to_string()))
@@ -100,6 +120,22 @@ impl<'de> Visitor<'de> for QuantityVisitor {
     {
         self.visit_str(value.as_ref())
     }
+
+    fn visit_f64<E>(self, value: f64) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        Err(float_rejected("quantity", value))
+    }
+
+    fn visit_bytes<E>(self, value: &[u8]) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        U256::from_compact(value)
+            .map(Quantity::new)
+            .ok_or_else(|| E::invalid_length(value.len(), &self))
+    }
 }
 
 impl<'a> From<&'a [u8]> for Quantity {
@@ -188,6 +224,11 @@ mod tests {
             (r#""0x000a""#, Some(10u64)),
             (r#""0xabcdef""#, Some(11_259_375u64)),
             (r#""0XABCDEF""#, Some(11_259_375u64)),
+            (r#""0x000""#, Some(0u64)),
+            (r#""000""#, Some(0u64)),
+            (r#"10"#, None),
+            (r#"1e5"#, None),
+            (r#"1.5"#, None),
         ];
         for (data, expected_opt) in testdata.into_iter() {
             let result: Result<Quantity, serde_json::Error> = serde_json::from_str(data);
@@ -198,4 +239,46 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn deserialize_at_most_256_bits() {
+        let max = format!(r#""0x{}""#, "f".repeat(64));
+        let result: Quantity = serde_json::from_str(&max).unwrap();
+        assert_eq!(result, Quantity::new(U256::max_value()));
+        let padded = format!(r#""0x{}{}""#, "0".repeat(1000), "f".repeat(64));
+        let result: Quantity = serde_json::from_str(&padded).unwrap();
+        assert_eq!(result, Quantity::new(U256::max_value()));
+        let max = format!(r#""{}""#, U256::max_value());
+        let result: Quantity = serde_json::from_str(&max).unwrap();
+        assert_eq!(result, Quantity::new(U256::max_value()));
+
+        let testdata = vec![
+            (
+                format!(r#""0x{}""#, "f".repeat(65)),
+                "hexadecimal string too large",
+            ),
+            (
+                format!(r#""0x{}""#, "f".repeat(100_000)),
+                "hexadecimal string too large",
+            ),
+            (
+                format!(r#""{}""#, "9".repeat(79)),
+                "decimal string too large",
+            ),
+            (
+                format!(r#""{}0""#, U256::max_value()),
+                "decimal string too large",
+            ),
+            // 78 digits, but more than 256 bits.
+            (format!(r#""{}""#, "9".repeat(78)), "invalid decimal string"),
+            (
+                "1e5".to_owned(),
+                "invalid quantity: floating point number 100000.0",
+            ),
+        ];
+        for (data, expected) in testdata.into_iter() {
+            let err = serde_json::from_str::<Quantity>(&data).unwrap_err();
+            assert!(err.to_string().starts_with(expected), "{}", err);
+        }
+    }
 }
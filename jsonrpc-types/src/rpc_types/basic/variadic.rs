@@ -14,7 +14,6 @@
 
 use serde::de::{self, DeserializeOwned};
 use serde::{Deserialize, Deserializer, Serialize};
-use serde_json::{self, Value};
 
 /// Variadic value.
 #[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize)]
@@ -54,16 +53,21 @@ where
     where
         D: Deserializer<'de>,
     {
-        let v: Value = Deserialize::deserialize(deserializer)?;
-
-        if v.is_null() {
-            return Ok(VariadicValue::Null);
+        // Not through a `serde_json::Value`, which has no byte strings.
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Either<T> {
+            Null,
+            Single(T),
+            Multiple(Vec<T>),
         }
 
-        serde_json::from_value(v.clone())
-            .map(VariadicValue::Single)
-            .or_else(|_| serde_json::from_value(v).map(VariadicValue::Multiple))
-            .map_err(|_| de::Error::custom("invalid type"))
+        match Either::deserialize(deserializer) {
+            Ok(Either::Null) => Ok(VariadicValue::Null),
+            Ok(Either::Single(data)) => Ok(VariadicValue::Single(data)),
+            Ok(Either::Multiple(data)) => Ok(VariadicValue::Multiple(data)),
+            Err(_) => Err(de::Error::custom("invalid type")),
+        }
     }
 }
 
@@ -14,7 +14,7 @@
 
 use cita_types::{Address, H256, U256};
 
-use crate::rpc_types::{BlockTransaction, Proof};
+use crate::rpc_types::{BlockTransaction, Proof, Quantity};
 
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub struct BlockBody {
@@ -22,27 +22,137 @@ pub struct BlockBody {
 }
 
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(remote = "BlockHeader")]
 pub struct BlockHeader {
     pub timestamp: u64,
-    #[serde(rename = "prevHash")]
+    #[serde(rename = "prevHash", with = "crate::rpc_types::basic::compact")]
     pub prev_hash: H256,
+    #[serde(with = "crate::rpc_types::basic::compact")]
     pub number: U256,
-    #[serde(rename = "stateRoot")]
+    #[serde(rename = "stateRoot", with = "crate::rpc_types::basic::compact")]
     pub state_root: H256,
-    #[serde(rename = "transactionsRoot")]
+    #[serde(rename = "transactionsRoot", with = "crate::rpc_types::basic::compact")]
     pub transactions_root: H256,
-    #[serde(rename = "receiptsRoot")]
+    #[serde(rename = "receiptsRoot", with = "crate::rpc_types::basic::compact")]
     pub receipts_root: H256,
-    #[serde(rename = "quotaUsed")]
+    #[serde(rename = "quotaUsed", with = "crate::rpc_types::basic::compact")]
     pub quota_used: U256,
+    #[serde(default, skip_serializing_if = "crate::rpc_types::null_style::skip")]
     pub proof: Option<Proof>,
+    #[serde(with = "crate::rpc_types::basic::compact")]
     pub proposer: Address,
 }
 
+null_style_serde!(BlockHeader);
+
+/// `canonical` and `confirmations` are left out unless the server fills
+/// them in, so clients from before they were added read blocks as ever.
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(remote = "Block")]
 pub struct Block {
     pub version: u32,
+    #[serde(with = "crate::rpc_types::basic::compact")]
     pub hash: H256,
     pub header: BlockHeader,
     pub body: BlockBody,
+    /// On the best chain.
+    #[serde(default, skip_serializing_if = "crate::rpc_types::null_style::skip")]
+    pub canonical: Option<bool>,
+    /// Blocks of the best chain from this one to the head, both included.
+    #[serde(default, skip_serializing_if = "crate::rpc_types::null_style::skip")]
+    pub confirmations: Option<Quantity>,
+}
+
+null_style_serde!(Block);
+
+impl Block {
+    /// Where the block is with the head of the best chain at
+    /// `head_height`. One off the best chain has no confirmations.
+    pub fn with_chain_position(mut self, head_height: u64, canonical: bool) -> Self {
+        let height = self.header.number.low_u64();
+        let confirmations = if canonical && height <= head_height {
+            head_height - height + 1
+        } else {
+            0
+        };
+        self.canonical = Some(canonical);
+        self.confirmations = Some(confirmations.into());
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Block, BlockBody, BlockHeader};
+    use cita_types::{Address, H256, U256};
+    use serde_json;
+
+    fn block(number: u64) -> Block {
+        Block {
+            version: 2,
+            hash: H256::from(0xb10c),
+            header: BlockHeader {
+                timestamp: 1_570_000_000_000,
+                prev_hash: H256::from(0xb10b),
+                number: U256::from(number),
+                state_root: H256::from(1),
+                transactions_root: H256::from(2),
+                receipts_root: H256::from(3),
+                quota_used: U256::from(0),
+                proof: None,
+                proposer: Address::from(4),
+            },
+            body: BlockBody {
+                transactions: vec![],
+            },
+            canonical: None,
+            confirmations: None,
+        }
+    }
+
+    #[test]
+    fn chain_position_is_left_out_by_default() {
+        let value = serde_json::to_value(block(10)).unwrap();
+        assert!(value.get("canonical").is_none());
+        assert!(value.get("confirmations").is_none());
+        assert_eq!(serde_json::from_value::<Block>(value).unwrap(), block(10));
+    }
+
+    #[test]
+    fn chain_position() {
+        let canonical = block(10).with_chain_position(12, true);
+        let value = serde_json::to_value(&canonical).unwrap();
+        assert_eq!(value["canonical"], json!(true));
+        assert_eq!(value["confirmations"], json!("0x3"));
+        assert_eq!(serde_json::from_value::<Block>(value).unwrap(), canonical);
+
+        assert_eq!(
+            block(12).with_chain_position(12, true).confirmations,
+            Some(1u64.into())
+        );
+        let retired = block(10).with_chain_position(12, false);
+        assert_eq!(retired.canonical, Some(false));
+        assert_eq!(retired.confirmations, Some(0u64.into()));
+    }
+
+    #[test]
+    fn older_clients_read_an_enriched_block() {
+        // `Block` as it was before the chain position.
+        #[derive(Debug, Deserialize)]
+        struct OldBlock {
+            version: u32,
+            #[serde(with = "crate::rpc_types::basic::compact")]
+            hash: H256,
+            header: BlockHeader,
+            body: BlockBody,
+        }
+
+        let enriched = block(10).with_chain_position(12, true);
+        let json = serde_json::to_string(&enriched).unwrap();
+        let old: OldBlock = serde_json::from_str(&json).unwrap();
+        assert_eq!(old.version, enriched.version);
+        assert_eq!(old.hash, enriched.hash);
+        assert_eq!(old.header, enriched.header);
+        assert_eq!(old.body, enriched.body);
+    }
 }
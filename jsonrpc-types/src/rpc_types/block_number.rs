@@ -12,9 +12,13 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer};
+
+use super::basic::{float_rejected, QuantityVisitor};
 use crate::rpc_types::{BlockTag, Quantity};
 
-#[derive(Serialize, Deserialize, Debug, Clone, Hash, PartialEq, Eq)]
+#[derive(Serialize, Debug, Clone, Hash, PartialEq, Eq)]
 #[serde(untagged)]
 pub enum BlockNumber {
     /// Block Tag
@@ -47,6 +51,53 @@ impl Default for BlockNumber {
     }
 }
 
+// By hand rather than untagged, which only tells that neither variant
+// matched, not why the height didn't.
+impl<'de> Deserialize<'de> for BlockNumber {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(BlockNumberVisitor)
+    }
+}
+
+struct BlockNumberVisitor;
+
+impl<'de> Visitor<'de> for BlockNumberVisitor {
+    type Value = BlockNumber;
+
+    fn expecting(&self, formatter: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        formatter.write_str("a block tag or a height")
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        match value {
+            "latest" => Ok(BlockNumber::latest()),
+            "earliest" => Ok(BlockNumber::earliest()),
+            "pending" => Ok(BlockNumber::pending()),
+            _ => QuantityVisitor.visit_str(value).map(BlockNumber::Height),
+        }
+    }
+
+    fn visit_f64<E>(self, value: f64) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        Err(float_rejected("height", value))
+    }
+
+    fn visit_bytes<E>(self, value: &[u8]) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        QuantityVisitor.visit_bytes(value).map(BlockNumber::Height)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::BlockNumber;
@@ -89,6 +140,9 @@ mod tests {
             (r#""0xA""#, Some(BlockNumber::new(10u64.into()))),
             (r#""0Xa""#, Some(BlockNumber::new(10u64.into()))),
             (r#""0XA""#, Some(BlockNumber::new(10u64.into()))),
+            (r#""Latest""#, None),
+            (r#"1e5"#, None),
+            (r#"[]"#, None),
         ];
         for (data, expected_opt) in testdata.into_iter() {
             let result: Result<BlockNumber, serde_json::Error> = serde_json::from_str(data);
@@ -99,4 +153,24 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn deserialize_errors_tell_why() {
+        let testdata = vec![
+            (
+                "1e5".to_owned(),
+                "invalid height: floating point number 100000.0",
+            ),
+            (
+                format!(r#""0x{}""#, "f".repeat(100_000)),
+                "hexadecimal string too large",
+            ),
+            (r#""0xg""#.to_owned(), "invalid hexadecimal string"),
+            ("10".to_owned(), "invalid type: integer `10`"),
+        ];
+        for (data, expected) in testdata.into_iter() {
+            let err = serde_json::from_str::<BlockNumber>(&data).unwrap_err();
+            assert!(err.to_string().starts_with(expected), "{}", err);
+        }
+    }
 }
@@ -16,9 +16,9 @@ use super::Log;
 use serde::de::Error;
 use serde::ser::Serialize;
 use serde::{Deserialize, Deserializer, Serializer};
-use serde_json::{from_value, Value};
+use serde_json::Value;
 
-use crate::rpc_types::{BlockNumber, Data20, Data32, VariadicValue};
+use crate::rpc_types::{BlockNumber, Data20, Data32, PageRequest, VariadicValue};
 
 /// Filter Address
 pub type FilterAddress = VariadicValue<Data20>;
@@ -50,6 +50,9 @@ pub struct Filter {
     /// Limit
     #[serde(skip_serializing_if = "Option::is_none")]
     pub limit: Option<usize>,
+    /// Ask for the logs one page at a time
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub page: Option<PageRequest>,
 }
 
 impl Filter {
@@ -65,8 +68,14 @@ impl Filter {
             address,
             topics,
             limit: None,
+            page: None,
         }
     }
+
+    pub fn with_page(mut self, page: PageRequest) -> Self {
+        self.page = Some(page);
+        self
+    }
 }
 
 // Results of the filter_changes RPC.
@@ -98,27 +107,33 @@ impl<'de> Deserialize<'de> for FilterChanges {
     where
         D: Deserializer<'de>,
     {
-        let v: Value = Deserialize::deserialize(deserializer)?;
-        match v.clone() {
-            Value::Array(filter_change) => {
-                if filter_change.is_empty() {
+        // Not through a `serde_json::Value`, which has no byte strings.
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Changes {
+            Logs(Vec<Log>),
+            Hashes(Vec<Data32>),
+        }
+
+        match Option::<Changes>::deserialize(deserializer) {
+            Ok(None) => Ok(FilterChanges::Empty),
+            Ok(Some(Changes::Logs(logs))) => {
+                if logs.is_empty() {
                     Ok(FilterChanges::Empty)
                 } else {
-                    from_value(v.clone())
-                        .map(FilterChanges::Logs)
-                        .or_else(|_| from_value(v).map(FilterChanges::Hashes))
-                        .map_err(|_| D::Error::custom("Invalid type."))
+                    Ok(FilterChanges::Logs(logs))
                 }
             }
-            Value::Null => Ok(FilterChanges::Empty),
-            _ => Err(D::Error::custom("Invalid type.")),
+            Ok(Some(Changes::Hashes(hashes))) => Ok(FilterChanges::Hashes(hashes)),
+            Err(_) => Err(D::Error::custom("Invalid type.")),
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{BlockNumber, Data32, Filter, FilterChanges, Log, VariadicValue};
+    use super::{BlockNumber, Data32, Filter, FilterChanges, Log, PageRequest, VariadicValue};
+    use crate::rpc_types::Data;
     use cita_types::{H160, H256, U256};
     use serde_json;
     use std::convert::Into;
@@ -170,6 +185,20 @@ mod tests {
         );
     }
 
+    #[test]
+    fn filter_with_page() {
+        let filter = Filter::new(BlockNumber::earliest(), BlockNumber::latest(), None, None)
+            .with_page(PageRequest::new(Some(Data::new(vec![1, 2])), Some(50)));
+        let value = json!({
+            "fromBlock": "earliest",
+            "address": null,
+            "topics": null,
+            "page": { "cursor": "0x0102", "limit": "0x32" },
+        });
+        assert_eq!(serde_json::to_value(&filter).unwrap(), value);
+        assert_eq!(serde_json::from_value::<Filter>(value).unwrap(), filter);
+    }
+
     #[test]
     fn test_filter_changes_serde() {
         assert_eq!("[]", serde_json::to_string(&FilterChanges::Empty).unwrap());
@@ -17,33 +17,68 @@ use cita_types::{H160, H256, U256};
 
 /// Log
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Hash, Clone)]
+#[serde(remote = "Log")]
 pub struct Log {
     /// H160
+    #[serde(with = "crate::rpc_types::basic::compact")]
     pub address: H160,
     /// Topics
+    #[serde(with = "crate::rpc_types::basic::compact::vec")]
     pub topics: Vec<H256>,
     /// Data
     pub data: Data,
     /// Block Hash
-    #[serde(rename = "blockHash")]
+    #[serde(
+        rename = "blockHash",
+        default,
+        skip_serializing_if = "crate::rpc_types::null_style::skip",
+        with = "crate::rpc_types::basic::compact::option"
+    )]
     pub block_hash: Option<H256>,
     /// Block Height
-    #[serde(rename = "blockNumber")]
+    #[serde(
+        rename = "blockNumber",
+        default,
+        skip_serializing_if = "crate::rpc_types::null_style::skip",
+        with = "crate::rpc_types::basic::compact::option"
+    )]
     pub block_number: Option<U256>,
     /// Transaction Hash
-    #[serde(rename = "transactionHash")]
+    #[serde(
+        rename = "transactionHash",
+        default,
+        skip_serializing_if = "crate::rpc_types::null_style::skip",
+        with = "crate::rpc_types::basic::compact::option"
+    )]
     pub transaction_hash: Option<H256>,
     /// Transaction Index
-    #[serde(rename = "transactionIndex")]
+    #[serde(
+        rename = "transactionIndex",
+        default,
+        skip_serializing_if = "crate::rpc_types::null_style::skip",
+        with = "crate::rpc_types::basic::compact::option"
+    )]
     pub transaction_index: Option<U256>,
     /// Log Index in Block
-    #[serde(rename = "logIndex")]
+    #[serde(
+        rename = "logIndex",
+        default,
+        skip_serializing_if = "crate::rpc_types::null_style::skip",
+        with = "crate::rpc_types::basic::compact::option"
+    )]
     pub log_index: Option<U256>,
     /// Log Index in Transaction
-    #[serde(rename = "transactionLogIndex")]
+    #[serde(
+        rename = "transactionLogIndex",
+        default,
+        skip_serializing_if = "crate::rpc_types::null_style::skip",
+        with = "crate::rpc_types::basic::compact::option"
+    )]
     pub transaction_log_index: Option<U256>,
 }
 
+null_style_serde!(Log);
+
 #[cfg(test)]
 mod tests {
     use super::*;
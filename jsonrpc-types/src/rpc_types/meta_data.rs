@@ -37,6 +37,9 @@ pub struct MetaData {
     pub genesis_timestamp: u64,
     /// Node address list which validate blocks
     pub validators: Vec<Data20>,
+    /// Node address list which may join the network, observers included
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub nodes: Vec<Data20>,
     /// The interval time for creating a block (milliseconds)
     #[serde(rename = "blockInterval")]
     pub block_interval: u64,
@@ -98,6 +101,7 @@ mod tests {
             .map(|s| Address::from_str(s).unwrap())
             .map(|s| s.into())
             .collect::<Vec<_>>(),
+            nodes: vec![],
             block_interval: 3000,
             token_name: "Nervos".to_owned(),
             token_symbol: "NOS".to_owned(),
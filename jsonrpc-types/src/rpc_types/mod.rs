@@ -12,6 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+mod admin;
 mod basic;
 mod block;
 mod block_number;
@@ -20,36 +21,55 @@ mod exchange;
 mod filter;
 mod log;
 mod meta_data;
+mod null_style;
+mod pagination;
 mod peers_info;
 mod proof;
 mod receipt;
+mod reorg;
 mod software_version;
 mod specs;
+mod sync_status;
 mod transaction;
 mod tx_response;
+mod tx_timeline;
+mod version_info;
 
 #[cfg(test)]
 mod tests;
 
 pub use self::basic::{
     BlockTag, Boolean, Data, Data20, Data32, EconomicalModel, Integer, OneItemTupleTrick, Quantity,
-    VariadicValue,
+    VariadicValue, MAX_DATA_LEN,
 };
 pub use self::exchange::{BlockParamsByHash, BlockParamsByNumber, CountOrCode, RpcBlock};
-pub use self::specs::{Id, Params, Version};
+pub use self::specs::{parse_params, Id, Params, Version, MAX_PARAMS_DEPTH};
 
+pub use self::admin::{
+    issue_confirmation, verify_confirmation, AdminCall, AmendRequest, ConfirmationError,
+    ConfirmationToken, SnapshotCommand, SnapshotRequest, DEFAULT_CONFIRMATION_TTL,
+};
 pub use self::block::{Block, BlockBody, BlockHeader};
 pub use self::block_number::BlockNumber;
 pub use self::call_request::CallRequest;
 pub use self::filter::{Filter, FilterAddress, FilterChanges, Topic};
 pub use self::log::Log;
 pub use self::meta_data::MetaData;
+pub use self::null_style::NullStyle;
+pub use self::pagination::{
+    decode_cursor, encode_cursor, CursorError, PageRequest, Paginated, DEFAULT_PAGE_LIMIT,
+    MAX_PAGE_LIMIT,
+};
 pub use self::peers_info::PeersInfo;
 pub use self::proof::{BftProof, Proof};
 pub use self::receipt::Receipt;
+pub use self::reorg::ReorgNotification;
 pub use self::software_version::SoftwareVersion;
+pub use self::sync_status::{SyncInfo, SyncStatus};
 pub use self::transaction::{BlockTransaction, FullTransaction, RpcTransaction};
-pub use self::tx_response::TxResponse;
+pub use self::tx_response::{PoolStatus, TxResponse};
+pub use self::tx_timeline::{TimelineStage, TransactionTimeline};
+pub use self::version_info::{RpcModules, VersionInfo, CRYPTO_FEATURES, HASH_FEATURES};
 
 use serde_json;
 
@@ -0,0 +1,321 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! How responses write the optional fields they have no value for.
+//!
+//! SDKs disagree on whether `"contractAddress": null` and a missing
+//! `contractAddress` are the same, so the server picks one `NullStyle`
+//! per API version and serializes its responses in it:
+//!
+//! ```ignore
+//! let body = NullStyle::ExplicitNull.scope(|| serde_json::to_string(&response))?;
+//! ```
+//!
+//! Outside a scope fields are omitted, `OmitMissing`. Parsing accepts
+//! both forms in either style.
+//!
+//! The style applies to every optional field of the responses: `Block`,
+//! `BlockHeader` (`proof`), `Receipt`, `Log`, `TxResponse`, `Paginated`,
+//! `PeersInfo` and `TimelineStage`. The optional fields of requests,
+//! `CallRequest`, `Filter`, `PageRequest` and the admin ones, are always
+//! omitted, and so is the `jsonrpc` of the response envelope.
+//!
+//! Formats that aren't human readable, like CBOR, may have no field names
+//! to tell which fields are left out, so they get every field whatever the
+//! style. bincode 0.8 predates `is_human_readable` and says it is, so
+//! serialize for it under `ExplicitNull`.
+
+use serde::Serializer;
+use std::cell::Cell;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NullStyle {
+    /// Leave the field out.
+    OmitMissing,
+    /// Write the field as `null`.
+    ExplicitNull,
+}
+
+impl Default for NullStyle {
+    fn default() -> Self {
+        NullStyle::OmitMissing
+    }
+}
+
+thread_local! {
+    static CURRENT: Cell<NullStyle> = Cell::new(NullStyle::default());
+}
+
+/// Puts the style before back, even if the serialization panics.
+struct Restore(NullStyle);
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        CURRENT.with(|current| current.set(self.0));
+    }
+}
+
+impl NullStyle {
+    /// The style responses are serialized in on this thread.
+    pub fn current() -> Self {
+        CURRENT.with(Cell::get)
+    }
+
+    /// Runs `f` with responses serialized in this style on this thread.
+    /// Scopes nest.
+    pub fn scope<T, F>(self, f: F) -> T
+    where
+        F: FnOnce() -> T,
+    {
+        let _restore = Restore(CURRENT.with(|current| current.replace(self)));
+        f()
+    }
+}
+
+/// `skip_serializing_if` of the optional fields of responses.
+pub(crate) fn skip<T>(value: &Option<T>) -> bool {
+    value.is_none() && NullStyle::current() == NullStyle::OmitMissing
+}
+
+/// The `Serialize` of the responses, `derived` in `ExplicitNull` if the
+/// format isn't human readable.
+pub(crate) fn serialize<T, S, F>(value: &T, serializer: S, derived: F) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+    F: FnOnce(&T, S) -> Result<S::Ok, S::Error>,
+{
+    if serializer.is_human_readable() {
+        derived(value, serializer)
+    } else {
+        NullStyle::ExplicitNull.scope(|| derived(value, serializer))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::NullStyle;
+    use crate::rpc_types::{
+        Block, BlockBody, BlockHeader, BlockTransaction, Log, PoolStatus, Receipt, TxResponse,
+    };
+    use cita_types::{Address, Bloom, H256, U256};
+    use serde::de::DeserializeOwned;
+    use serde::Serialize;
+    use serde_json::{self, Value};
+    use std::fmt::Debug;
+
+    /// `value` is `omitted` by default and `explicit` with nulls, and
+    /// parses back from both.
+    fn golden<T>(value: &T, omitted: Value, explicit: Value)
+    where
+        T: Serialize + DeserializeOwned + PartialEq + Debug,
+    {
+        assert_eq!(serde_json::to_value(value).unwrap(), omitted);
+        assert_eq!(
+            NullStyle::ExplicitNull
+                .scope(|| serde_json::to_value(value))
+                .unwrap(),
+            explicit
+        );
+        assert_eq!(
+            NullStyle::OmitMissing
+                .scope(|| serde_json::to_value(value))
+                .unwrap(),
+            omitted
+        );
+        assert_eq!(&serde_json::from_value::<T>(omitted).unwrap(), value);
+        assert_eq!(&serde_json::from_value::<T>(explicit).unwrap(), value);
+    }
+
+    #[test]
+    fn scopes() {
+        assert_eq!(NullStyle::current(), NullStyle::OmitMissing);
+        NullStyle::ExplicitNull.scope(|| {
+            assert_eq!(NullStyle::current(), NullStyle::ExplicitNull);
+            NullStyle::OmitMissing.scope(|| {
+                assert_eq!(NullStyle::current(), NullStyle::OmitMissing);
+            });
+            assert_eq!(NullStyle::current(), NullStyle::ExplicitNull);
+        });
+        let panicked = ::std::panic::catch_unwind(|| {
+            NullStyle::ExplicitNull.scope(|| panic!("while serializing"))
+        });
+        assert!(panicked.is_err());
+        assert_eq!(NullStyle::current(), NullStyle::OmitMissing);
+    }
+
+    #[cfg(feature = "cbor")]
+    #[test]
+    fn formats_not_human_readable_get_every_field() {
+        let response = TxResponse::new(H256::from(1), "OK".to_owned());
+        let encoded = serde_cbor::to_vec(&response).unwrap();
+        match serde_cbor::from_slice(&encoded).unwrap() {
+            serde_cbor::Value::Map(fields) => assert_eq!(fields.len(), 5),
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(
+            serde_cbor::from_slice::<TxResponse>(&encoded).unwrap(),
+            response
+        );
+    }
+
+    #[test]
+    fn transaction() {
+        let response = TxResponse::new(H256::from(1), "OK".to_owned());
+        golden(
+            &response,
+            json!({"hash": format!("{:#066x}", 1), "status": "OK"}),
+            json!({
+                "hash": format!("{:#066x}", 1),
+                "status": "OK",
+                "poolStatus": null,
+                "replacedHash": null,
+                "poolPosition": null,
+            }),
+        );
+
+        let response = TxResponse::with_admission(
+            H256::from(1),
+            "OK".to_owned(),
+            PoolStatus::Replaced,
+            Some(H256::from(2)),
+            None,
+        );
+        golden(
+            &response,
+            json!({
+                "hash": format!("{:#066x}", 1),
+                "status": "OK",
+                "poolStatus": "replaced",
+                "replacedHash": format!("{:#066x}", 2),
+            }),
+            json!({
+                "hash": format!("{:#066x}", 1),
+                "status": "OK",
+                "poolStatus": "replaced",
+                "replacedHash": format!("{:#066x}", 2),
+                "poolPosition": null,
+            }),
+        );
+    }
+
+    #[test]
+    fn block() {
+        let block = Block {
+            version: 2,
+            hash: H256::from(0xb10c),
+            header: BlockHeader {
+                timestamp: 1_570_000_000_000,
+                prev_hash: H256::from(0xb10b),
+                number: U256::from(10),
+                state_root: H256::from(1),
+                transactions_root: H256::from(2),
+                receipts_root: H256::from(3),
+                quota_used: U256::from(0),
+                proof: None,
+                proposer: Address::from(4),
+            },
+            body: BlockBody {
+                transactions: vec![BlockTransaction::Hash(H256::from(5))],
+            },
+            canonical: None,
+            confirmations: None,
+        };
+        let header = json!({
+            "timestamp": 1_570_000_000_000u64,
+            "prevHash": format!("{:#066x}", 0xb10b),
+            "number": "0xa",
+            "stateRoot": format!("{:#066x}", 1),
+            "transactionsRoot": format!("{:#066x}", 2),
+            "receiptsRoot": format!("{:#066x}", 3),
+            "quotaUsed": "0x0",
+            "proposer": format!("{:#042x}", 4),
+        });
+        let mut explicit_header = header.clone();
+        explicit_header["proof"] = Value::Null;
+        let body = json!({"transactions": [format!("{:#066x}", 5)]});
+        golden(
+            &block,
+            json!({
+                "version": 2,
+                "hash": format!("{:#066x}", 0xb10c),
+                "header": header,
+                "body": body,
+            }),
+            json!({
+                "version": 2,
+                "hash": format!("{:#066x}", 0xb10c),
+                "header": explicit_header,
+                "body": body,
+                "canonical": null,
+                "confirmations": null,
+            }),
+        );
+    }
+
+    #[test]
+    fn receipt() {
+        let receipt = Receipt {
+            transaction_hash: Some(H256::from(1)),
+            transaction_index: Some(0.into()),
+            block_hash: Some(H256::from(2)),
+            block_number: Some(0x10.into()),
+            cumulative_quota_used: 0x20.into(),
+            quota_used: Some(0x20.into()),
+            contract_address: None,
+            logs: vec![Log {
+                address: Address::from(3),
+                topics: vec![],
+                data: vec![].into(),
+                block_hash: Some(H256::from(2)),
+                block_number: Some(0x10.into()),
+                transaction_hash: Some(H256::from(1)),
+                transaction_index: Some(0.into()),
+                log_index: Some(0.into()),
+                transaction_log_index: None,
+            }],
+            state_root: None,
+            logs_bloom: Bloom::default(),
+            error_code: None,
+            error_message: None,
+        };
+        let log = json!({
+            "address": format!("{:#042x}", 3),
+            "topics": [],
+            "data": "0x",
+            "blockHash": format!("{:#066x}", 2),
+            "blockNumber": "0x10",
+            "transactionHash": format!("{:#066x}", 1),
+            "transactionIndex": "0x0",
+            "logIndex": "0x0",
+        });
+        let mut explicit_log = log.clone();
+        explicit_log["transactionLogIndex"] = Value::Null;
+        let omitted = json!({
+            "transactionHash": format!("{:#066x}", 1),
+            "transactionIndex": "0x0",
+            "blockHash": format!("{:#066x}", 2),
+            "blockNumber": "0x10",
+            "cumulativeQuotaUsed": "0x20",
+            "quotaUsed": "0x20",
+            "logs": [log],
+            "logsBloom": format!("0x{}", "0".repeat(512)),
+        });
+        let mut explicit = omitted.clone();
+        explicit["logs"] = json!([explicit_log]);
+        for field in &["contractAddress", "root", "errorCode", "errorMessage"] {
+            explicit[*field] = Value::Null;
+        }
+        golden(&receipt, omitted, explicit);
+    }
+}
@@ -0,0 +1,250 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::cmp;
+use std::fmt;
+
+use cita_types::U256;
+use hashable::{constant_time_eq, hmac_with, Hashable, HASH_BLOCK_LEN};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json;
+
+use crate::rpc_types::{Data, Quantity};
+
+/// Page size used when the request doesn't give one.
+pub const DEFAULT_PAGE_LIMIT: u64 = 100;
+/// Largest page size a server hands out, whatever the request asks for.
+pub const MAX_PAGE_LIMIT: u64 = 1000;
+
+const MAC_LEN: usize = 32;
+
+/// Page request params.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, Hash, PartialEq, Eq)]
+pub struct PageRequest {
+    /// Cursor returned as `nextCursor` by the previous page, none for the first
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cursor: Option<Data>,
+    /// Max items wanted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit: Option<Quantity>,
+}
+
+impl PageRequest {
+    pub fn new(cursor: Option<Data>, limit: Option<u64>) -> Self {
+        PageRequest {
+            cursor,
+            limit: limit.map(|limit| U256::from(limit).into()),
+        }
+    }
+
+    /// The page size to use: `default` when the request has none,
+    /// otherwise the requested one clamped into `[1, max]`.
+    pub fn clamped_limit(&self, default: u64, max: u64) -> u64 {
+        match self.limit {
+            None => cmp::min(default, max),
+            Some(ref limit) => {
+                let limit: U256 = limit.clone().into();
+                if limit > U256::from(max) {
+                    max
+                } else {
+                    cmp::max(limit.low_u64(), 1)
+                }
+            }
+        }
+    }
+}
+
+/// One page of a large result.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(remote = "Paginated")]
+pub struct Paginated<T> {
+    pub items: Vec<T>,
+    /// Pass back in `PageRequest::cursor` to get the next page,
+    /// none on the last page
+    #[serde(
+        rename = "nextCursor",
+        default,
+        skip_serializing_if = "crate::rpc_types::null_style::skip"
+    )]
+    pub next_cursor: Option<Data>,
+    /// Total number of items, if known
+    #[serde(default, skip_serializing_if = "crate::rpc_types::null_style::skip")]
+    pub total: Option<Quantity>,
+}
+
+null_style_serde!(Paginated<T>);
+
+impl<T> Paginated<T> {
+    pub fn new(items: Vec<T>, next_cursor: Option<Data>, total: Option<u64>) -> Self {
+        Paginated {
+            items,
+            next_cursor,
+            total: total.map(|total| U256::from(total).into()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CursorError {
+    /// Not something `encode_cursor` produced.
+    Malformed,
+    /// Signed with another key, or modified.
+    Forged,
+}
+
+impl fmt::Display for CursorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CursorError::Malformed => write!(f, "malformed cursor"),
+            CursorError::Forged => write!(f, "invalid cursor signature"),
+        }
+    }
+}
+
+impl ::std::error::Error for CursorError {}
+
+pub(crate) fn hmac(key: &[u8], message: &[u8]) -> [u8; MAC_LEN] {
+    hmac_with(|data| data.crypt_hash().0, HASH_BLOCK_LEN, key, &[message])
+}
+
+/// Turn a server side position into an opaque cursor.
+///
+/// The cursor is the JSON of `position` followed by an HMAC over it keyed
+/// with `key`, which only the server knows, so clients can't forge one.
+pub fn encode_cursor<T: Serialize>(key: &[u8], position: &T) -> Data {
+    let mut bytes = serde_json::to_vec(position).expect("cursor position serialization");
+    let mac = hmac(key, &bytes);
+    bytes.extend_from_slice(&mac);
+    Data::new(bytes)
+}
+
+/// Get the position back out of a cursor made by `encode_cursor`.
+pub fn decode_cursor<T: DeserializeOwned>(key: &[u8], cursor: &Data) -> Result<T, CursorError> {
+    let bytes: Vec<u8> = cursor.clone().into();
+    if bytes.len() < MAC_LEN {
+        return Err(CursorError::Malformed);
+    }
+    let (position, mac) = bytes.split_at(bytes.len() - MAC_LEN);
+    if !constant_time_eq(&hmac(key, position), mac) {
+        return Err(CursorError::Forged);
+    }
+    serde_json::from_slice(position).map_err(|_| CursorError::Malformed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        decode_cursor, encode_cursor, CursorError, PageRequest, Paginated, DEFAULT_PAGE_LIMIT,
+        MAX_PAGE_LIMIT,
+    };
+    use crate::rpc_types::Data;
+    use serde_json;
+
+    const KEY: &[u8] = b"cursor secret";
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Position {
+        height: u64,
+        index: u32,
+    }
+
+    #[test]
+    fn cursor_round_trip() {
+        let position = Position {
+            height: 1024,
+            index: 7,
+        };
+        let cursor = encode_cursor(KEY, &position);
+        assert_eq!(decode_cursor::<Position>(KEY, &cursor), Ok(position));
+
+        // Cursors travel as plain hex data.
+        let json = serde_json::to_string(&cursor).unwrap();
+        let parsed: Data = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, cursor);
+    }
+
+    #[test]
+    fn cursor_tampering() {
+        let cursor = encode_cursor(KEY, &(100u64, 3u32));
+        let mut bytes: Vec<u8> = cursor.clone().into();
+
+        // Move the position without knowing the key.
+        let forged: Vec<u8> = {
+            let mut forged = serde_json::to_vec(&(999u64, 3u32)).unwrap();
+            forged.extend_from_slice(&bytes[bytes.len() - 32..]);
+            forged
+        };
+        assert_eq!(
+            decode_cursor::<(u64, u32)>(KEY, &Data::new(forged)),
+            Err(CursorError::Forged)
+        );
+
+        bytes[0] ^= 1;
+        assert_eq!(
+            decode_cursor::<(u64, u32)>(KEY, &Data::new(bytes)),
+            Err(CursorError::Forged)
+        );
+        assert_eq!(
+            decode_cursor::<(u64, u32)>(b"other key", &cursor),
+            Err(CursorError::Forged)
+        );
+        assert_eq!(
+            decode_cursor::<(u64, u32)>(KEY, &Data::new(vec![1, 2, 3])),
+            Err(CursorError::Malformed)
+        );
+        // Valid signature over something else than the expected type.
+        assert_eq!(
+            decode_cursor::<(u64, u32)>(KEY, &encode_cursor(KEY, &"text")),
+            Err(CursorError::Malformed)
+        );
+    }
+
+    #[test]
+    fn limit_clamping() {
+        let limit =
+            |limit| PageRequest::new(None, limit).clamped_limit(DEFAULT_PAGE_LIMIT, MAX_PAGE_LIMIT);
+        assert_eq!(limit(None), DEFAULT_PAGE_LIMIT);
+        assert_eq!(limit(Some(0)), 1);
+        assert_eq!(limit(Some(20)), 20);
+        assert_eq!(limit(Some(MAX_PAGE_LIMIT)), MAX_PAGE_LIMIT);
+        assert_eq!(limit(Some(u64::max_value())), MAX_PAGE_LIMIT);
+        assert_eq!(PageRequest::new(None, None).clamped_limit(50, 10), 10);
+    }
+
+    #[test]
+    fn page_serialization() {
+        let page = Paginated::new(vec![1u32, 2], Some(Data::new(vec![0xab])), Some(10));
+        let value = json!({
+            "items": [1, 2],
+            "nextCursor": "0xab",
+            "total": "0xa",
+        });
+        assert_eq!(serde_json::to_value(&page).unwrap(), value);
+        assert_eq!(
+            serde_json::from_value::<Paginated<u32>>(value).unwrap(),
+            page
+        );
+
+        let last = Paginated::new(vec![3u32], None, None);
+        assert_eq!(
+            serde_json::to_value(&last).unwrap(),
+            json!({ "items": [3] })
+        );
+        assert_eq!(
+            serde_json::to_value(PageRequest::new(None, Some(5))).unwrap(),
+            json!({ "limit": "0x5" })
+        );
+    }
+}
@@ -16,17 +16,26 @@ use cita_types::Address;
 use std::collections::HashMap;
 
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(remote = "PeersInfo")]
 pub struct PeersInfo {
     pub amount: u32,
+    #[serde(default, skip_serializing_if = "crate::rpc_types::null_style::skip")]
     pub peers: Option<HashMap<Address, String>>,
 
-    #[serde(rename = "errorMessage")]
+    #[serde(
+        rename = "errorMessage",
+        default,
+        skip_serializing_if = "crate::rpc_types::null_style::skip"
+    )]
     pub error_message: Option<String>,
 }
 
+null_style_serde!(PeersInfo);
+
 #[cfg(test)]
 mod tests {
     use super::PeersInfo;
+    use crate::rpc_types::NullStyle;
     use cita_types::Address;
     use serde_json;
     use std::collections::HashMap;
@@ -44,7 +53,6 @@ mod tests {
                 format!("0x{:x}", addr2).to_string(): "32.52.64.32",
                 format!("0x{:x}", addr3).to_string(): "67.68.32.21",
             },
-            "errorMessage": serde_json::Value::Null,
         });
 
         let mut peers = HashMap::new();
@@ -65,7 +73,6 @@ mod tests {
     fn peers_info_serialization_with_error_msg() {
         let value = json!({
             "amount": 0,
-            "peers": serde_json::Value::Null,
             "errorMessage": "Disabled interface",
         });
 
@@ -75,6 +82,8 @@ mod tests {
             error_message: Some("Disabled interface".to_owned()),
         };
 
-        assert_eq!(serde_json::to_value(peers_info).unwrap(), value);
+        assert_eq!(serde_json::to_value(&peers_info).unwrap(), value);
+        let explicit = NullStyle::ExplicitNull.scope(|| serde_json::to_value(&peers_info));
+        assert_eq!(explicit.unwrap()["peers"], serde_json::Value::Null);
     }
 }
@@ -24,6 +24,7 @@ pub enum Proof {
 
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub struct BftProof {
+    #[serde(with = "crate::rpc_types::basic::compact")]
     pub proposal: H256,
     pub height: usize,
     pub round: usize,
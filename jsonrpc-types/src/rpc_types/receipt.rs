@@ -17,44 +17,98 @@ use cita_types::{Bloom, H160, H256, U256};
 
 /// Receipt
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(remote = "Receipt")]
 pub struct Receipt {
     /// Transaction Hash
-    #[serde(rename = "transactionHash")]
+    #[serde(
+        rename = "transactionHash",
+        default,
+        skip_serializing_if = "crate::rpc_types::null_style::skip",
+        with = "crate::rpc_types::basic::compact::option"
+    )]
     pub transaction_hash: Option<H256>,
     /// Transaction index
-    #[serde(rename = "transactionIndex")]
+    #[serde(
+        rename = "transactionIndex",
+        default,
+        skip_serializing_if = "crate::rpc_types::null_style::skip",
+        with = "crate::rpc_types::basic::compact::option"
+    )]
     pub transaction_index: Option<U256>,
     /// Block hash
-    #[serde(rename = "blockHash")]
+    #[serde(
+        rename = "blockHash",
+        default,
+        skip_serializing_if = "crate::rpc_types::null_style::skip",
+        with = "crate::rpc_types::basic::compact::option"
+    )]
     pub block_hash: Option<H256>,
     /// Block
-    #[serde(rename = "blockNumber")]
+    #[serde(
+        rename = "blockNumber",
+        default,
+        skip_serializing_if = "crate::rpc_types::null_style::skip",
+        with = "crate::rpc_types::basic::compact::option"
+    )]
     pub block_number: Option<U256>,
     /// Cumulative quota used
-    #[serde(rename = "cumulativeQuotaUsed")]
+    #[serde(
+        rename = "cumulativeQuotaUsed",
+        with = "crate::rpc_types::basic::compact"
+    )]
     pub cumulative_quota_used: U256,
     /// Quota used
-    #[serde(rename = "quotaUsed")]
+    #[serde(
+        rename = "quotaUsed",
+        default,
+        skip_serializing_if = "crate::rpc_types::null_style::skip",
+        with = "crate::rpc_types::basic::compact::option"
+    )]
     pub quota_used: Option<U256>,
     /// Contract address
-    #[serde(rename = "contractAddress")]
+    #[serde(
+        rename = "contractAddress",
+        default,
+        skip_serializing_if = "crate::rpc_types::null_style::skip",
+        with = "crate::rpc_types::basic::compact::option"
+    )]
     pub contract_address: Option<H160>,
     /// Logs
     pub logs: Vec<Log>,
     /// State Root
-    #[serde(rename = "root")]
+    #[serde(
+        rename = "root",
+        default,
+        skip_serializing_if = "crate::rpc_types::null_style::skip",
+        with = "crate::rpc_types::basic::compact::option"
+    )]
     pub state_root: Option<H256>,
     /// Logs bloom
-    #[serde(rename = "logsBloom")]
+    #[serde(rename = "logsBloom", with = "crate::rpc_types::basic::compact")]
     pub logs_bloom: Bloom,
+    /// Receipt error code, stable unlike the message. The table is
+    /// `libproto::receipt_error`.
+    #[serde(
+        rename = "errorCode",
+        default,
+        skip_serializing_if = "crate::rpc_types::null_style::skip"
+    )]
+    pub error_code: Option<u8>,
     /// Receipt error message
-    #[serde(rename = "errorMessage")]
+    #[serde(
+        rename = "errorMessage",
+        default,
+        skip_serializing_if = "crate::rpc_types::null_style::skip"
+    )]
     pub error_message: Option<String>,
 }
 
+null_style_serde!(Receipt);
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::rpc_types::NullStyle;
     use bincode::{deserialize, serialize, Infinite};
     use cita_types::{Bloom, H256};
     use serde_json;
@@ -97,6 +151,7 @@ mod tests {
             }],
             logs_bloom: Bloom::from(15),
             state_root: Some(H256::from(10)),
+            error_code: None,
             error_message: None,
         };
 
@@ -143,12 +198,16 @@ mod tests {
             }],
             logs_bloom: Bloom::from(15),
             state_root: Some(H256::from(10)),
+            error_code: None,
             error_message: None,
         };
 
         println!("{:?}", receipt);
 
-        let encoded: Vec<u8> = serialize(&receipt, Infinite).unwrap();
+        // Bincode has no field names to tell which are left out, and 0.8
+        // says it is human readable.
+        let encoded: Vec<u8> =
+            NullStyle::ExplicitNull.scope(|| serialize(&receipt, Infinite).unwrap());
 
         println!("{:?}", encoded);
 
@@ -158,4 +217,32 @@ mod tests {
 
         assert_eq!(decoded, receipt);
     }
+
+    #[test]
+    fn receipt_error_fields() {
+        let receipt: Receipt = serde_json::from_value(json!({
+            "transactionHash": null,
+            "transactionIndex": null,
+            "blockHash": null,
+            "blockNumber": null,
+            "cumulativeQuotaUsed": "0x0",
+            "quotaUsed": null,
+            "contractAddress": null,
+            "logs": [],
+            "root": null,
+            "logsBloom": format!("0x{}", "0".repeat(512)),
+            "errorMessage": "Out of quota.",
+        }))
+        .unwrap();
+        // Servers which only send the message.
+        assert_eq!(receipt.error_code, None);
+
+        let receipt = Receipt {
+            error_code: Some(11),
+            ..receipt
+        };
+        let value = serde_json::to_value(&receipt).unwrap();
+        assert_eq!(value["errorCode"], json!(11));
+        assert_eq!(value["errorMessage"], json!("Out of quota."));
+    }
 }
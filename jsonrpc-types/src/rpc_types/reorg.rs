@@ -0,0 +1,102 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use cita_types::H256;
+
+/// The best chain went back from `old_head` to `common_ancestor`, and on
+/// to `new_head` along other blocks.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct ReorgNotification {
+    #[serde(rename = "oldHead", with = "crate::rpc_types::basic::compact")]
+    pub old_head: H256,
+    #[serde(rename = "newHead", with = "crate::rpc_types::basic::compact")]
+    pub new_head: H256,
+    #[serde(rename = "commonAncestor", with = "crate::rpc_types::basic::compact")]
+    pub common_ancestor: H256,
+    /// Off the best chain now, the oldest first.
+    #[serde(
+        rename = "retiredHashes",
+        with = "crate::rpc_types::basic::compact::vec"
+    )]
+    pub retired_hashes: Vec<H256>,
+    /// In place of the retired ones, the oldest first.
+    #[serde(
+        rename = "adoptedHashes",
+        with = "crate::rpc_types::basic::compact::vec"
+    )]
+    pub adopted_hashes: Vec<H256>,
+}
+
+impl ReorgNotification {
+    /// The heads are the newest of each branch, the ancestor for one
+    /// without blocks.
+    pub fn new(
+        common_ancestor: H256,
+        retired_hashes: Vec<H256>,
+        adopted_hashes: Vec<H256>,
+    ) -> Self {
+        ReorgNotification {
+            old_head: *retired_hashes.last().unwrap_or(&common_ancestor),
+            new_head: *adopted_hashes.last().unwrap_or(&common_ancestor),
+            common_ancestor,
+            retired_hashes,
+            adopted_hashes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ReorgNotification;
+    use cita_types::H256;
+    use serde_json;
+
+    #[test]
+    fn heads_are_the_newest_of_each_branch() {
+        let reorg = ReorgNotification::new(
+            H256::from(1),
+            vec![H256::from(2), H256::from(3)],
+            vec![H256::from(4), H256::from(5), H256::from(6)],
+        );
+        assert_eq!(reorg.old_head, H256::from(3));
+        assert_eq!(reorg.new_head, H256::from(6));
+
+        // Blocks dropped back to the ancestor, none adopted yet.
+        let reorg = ReorgNotification::new(H256::from(1), vec![H256::from(2)], vec![]);
+        assert_eq!(reorg.old_head, H256::from(2));
+        assert_eq!(reorg.new_head, H256::from(1));
+    }
+
+    #[test]
+    fn serialization() {
+        let hash = |n: u64| format!("{:#066x}", n);
+        let reorg = ReorgNotification::new(
+            H256::from(1),
+            vec![H256::from(2)],
+            vec![H256::from(3), H256::from(4)],
+        );
+        let value = json!({
+            "oldHead": hash(2),
+            "newHead": hash(4),
+            "commonAncestor": hash(1),
+            "retiredHashes": [hash(2)],
+            "adoptedHashes": [hash(3), hash(4)],
+        });
+        assert_eq!(serde_json::to_value(&reorg).unwrap(), value);
+        assert_eq!(
+            serde_json::from_value::<ReorgNotification>(value).unwrap(),
+            reorg
+        );
+    }
+}
@@ -17,5 +17,5 @@ mod params;
 mod version;
 
 pub use self::id::Id;
-pub use self::params::Params;
+pub use self::params::{parse_params, Params, MAX_PARAMS_DEPTH};
 pub use self::version::Version;
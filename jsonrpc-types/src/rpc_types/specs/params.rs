@@ -18,6 +18,10 @@ use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use serde_json::{self, from_value, Value};
 use std::fmt;
 
+/// How deep params may nest arrays and objects, the params array or
+/// object itself counted.
+pub const MAX_PARAMS_DEPTH: usize = 32;
+
 /// Request parameters
 #[derive(Debug, PartialEq, Clone)]
 pub enum Params {
@@ -41,7 +45,7 @@ impl Params {
             Params::None => Value::Null,
         };
 
-        from_value(value).map_err(|e| Error::invalid_params(format!("Invalid params: {}.", e)))
+        parse_params(value)
     }
 
     pub fn len(&self) -> usize {
@@ -57,6 +61,41 @@ impl Params {
     }
 }
 
+/// `value` parsed into params of the expected types, an invalid params
+/// error when it nests deeper than `MAX_PARAMS_DEPTH` or they don't match.
+pub fn parse_params<D>(value: Value) -> Result<D, Error>
+where
+    D: DeserializeOwned,
+{
+    if depth(&value) > MAX_PARAMS_DEPTH {
+        return Err(Error::invalid_params(format!(
+            "Invalid params: nested deeper than {}.",
+            MAX_PARAMS_DEPTH
+        )));
+    }
+    from_value(value).map_err(|e| Error::invalid_params(format!("Invalid params: {}.", e)))
+}
+
+/// Without recursion, so that how deep is known before deserializing.
+fn depth(value: &Value) -> usize {
+    let mut deepest = 0;
+    let mut values = vec![(value, 0)];
+    while let Some((value, level)) = values.pop() {
+        match *value {
+            Value::Array(ref items) => {
+                deepest = deepest.max(level + 1);
+                values.extend(items.iter().map(|item| (item, level + 1)));
+            }
+            Value::Object(ref map) => {
+                deepest = deepest.max(level + 1);
+                values.extend(map.values().map(|item| (item, level + 1)));
+            }
+            _ => {}
+        }
+    }
+    deepest
+}
+
 impl Serialize for Params {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -125,8 +164,8 @@ impl<'a> Visitor<'a> for ParamsVisitor {
 
 #[cfg(test)]
 mod tests {
-    use super::Params;
-    use crate::error::Error;
+    use super::{Params, MAX_PARAMS_DEPTH};
+    use crate::error::{Error, ErrorCode};
     use crate::rpc_types::Filter;
     use serde_json::{self, Map, Number, Value};
 
@@ -224,4 +263,19 @@ mod tests {
         let filter: Filter = params.unwrap().parse().unwrap();
         println!("params parse filter = {:?}", filter);
     }
+
+    #[test]
+    fn should_reject_deep_params() {
+        let nested = |depth: usize| format!("{}{}", "[".repeat(depth), "]".repeat(depth));
+        let params = serde_json::from_str::<Params>(&nested(MAX_PARAMS_DEPTH)).unwrap();
+        assert!(params.parse::<Value>().is_ok());
+
+        let params = serde_json::from_str::<Params>(&nested(MAX_PARAMS_DEPTH + 1)).unwrap();
+        let err = params.parse::<Value>().unwrap_err();
+        assert_eq!(err.code, ErrorCode::InvalidParams);
+        assert_eq!(err.message, "Invalid params: nested deeper than 32.");
+        let s = format!(r#"[{{"key": {}}}]"#, nested(MAX_PARAMS_DEPTH));
+        let params = serde_json::from_str::<Params>(&s).unwrap();
+        assert!(params.parse::<Value>().is_err());
+    }
 }
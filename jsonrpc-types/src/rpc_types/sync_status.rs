@@ -0,0 +1,127 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use serde::de::{self, Deserialize, Deserializer};
+use serde::ser::{Serialize, Serializer};
+
+use crate::rpc_types::Quantity;
+
+/// Progress of a node catching up with its peers.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct SyncInfo {
+    #[serde(rename = "startingBlock")]
+    pub starting_block: Quantity,
+    #[serde(rename = "currentBlock")]
+    pub current_block: Quantity,
+    #[serde(rename = "highestBlock")]
+    pub highest_block: Quantity,
+}
+
+/// The `syncing` response: `false`, or the progress while catching up.
+#[derive(Debug, PartialEq, Clone)]
+pub enum SyncStatus {
+    NotSyncing,
+    Syncing(SyncInfo),
+}
+
+impl SyncStatus {
+    /// `highest` is the best height known from peers, e.g. the
+    /// `sync_target` of libproto's `PeerBestTracker`. Not syncing without
+    /// it, or once `current` reached it.
+    pub fn new(starting: u64, current: u64, highest: Option<u64>) -> Self {
+        match highest {
+            Some(highest) if highest > current => SyncStatus::Syncing(SyncInfo {
+                starting_block: starting.into(),
+                current_block: current.into(),
+                highest_block: highest.into(),
+            }),
+            _ => SyncStatus::NotSyncing,
+        }
+    }
+
+    pub fn is_syncing(&self) -> bool {
+        match *self {
+            SyncStatus::Syncing(_) => true,
+            SyncStatus::NotSyncing => false,
+        }
+    }
+}
+
+impl Serialize for SyncStatus {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match *self {
+            SyncStatus::NotSyncing => serializer.serialize_bool(false),
+            SyncStatus::Syncing(ref info) => info.serialize(serializer),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for SyncStatus {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Either {
+            Flag(bool),
+            Info(SyncInfo),
+        }
+
+        match Either::deserialize(deserializer)? {
+            Either::Flag(false) => Ok(SyncStatus::NotSyncing),
+            Either::Flag(true) => Err(de::Error::invalid_value(
+                de::Unexpected::Bool(true),
+                &"false or the sync progress",
+            )),
+            Either::Info(info) => Ok(SyncStatus::Syncing(info)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SyncStatus;
+    use serde_json;
+
+    #[test]
+    fn not_syncing_is_false() {
+        let status = SyncStatus::new(0, 10, None);
+        assert_eq!(status, SyncStatus::NotSyncing);
+        assert_eq!(serde_json::to_string(&status).unwrap(), "false");
+        assert_eq!(SyncStatus::new(0, 10, Some(10)), SyncStatus::NotSyncing);
+        assert_eq!(
+            serde_json::from_str::<SyncStatus>("false").unwrap(),
+            SyncStatus::NotSyncing
+        );
+        assert!(serde_json::from_str::<SyncStatus>("true").is_err());
+    }
+
+    #[test]
+    fn syncing_is_an_object() {
+        let status = SyncStatus::new(2, 10, Some(300));
+        assert!(status.is_syncing());
+        let value = json!({
+            "startingBlock": "0x2",
+            "currentBlock": "0xa",
+            "highestBlock": "0x12c",
+        });
+        assert_eq!(serde_json::to_value(&status).unwrap(), value);
+        assert_eq!(serde_json::from_value::<SyncStatus>(value).unwrap(), status);
+        assert!(serde_json::from_str::<SyncStatus>(r#"{"currentBlock":"0xa"}"#).is_err());
+    }
+}
@@ -19,20 +19,25 @@ use crate::rpc_types::Data;
 // TODO: No need Deserialize. Just because test in trans.rs
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub struct FullTransaction {
+    #[serde(with = "crate::rpc_types::basic::compact")]
     pub hash: H256,
     pub content: Data,
+    #[serde(with = "crate::rpc_types::basic::compact")]
     pub from: Address,
 }
 
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub struct RpcTransaction {
+    #[serde(with = "crate::rpc_types::basic::compact")]
     pub hash: H256,
     pub content: Data,
+    #[serde(with = "crate::rpc_types::basic::compact")]
     pub from: Address,
-    #[serde(rename = "blockNumber")]
+    #[serde(rename = "blockNumber", with = "crate::rpc_types::basic::compact")]
     pub block_number: U256,
-    #[serde(rename = "blockHash")]
+    #[serde(rename = "blockHash", with = "crate::rpc_types::basic::compact")]
     pub block_hash: H256,
+    #[serde(with = "crate::rpc_types::basic::compact")]
     pub index: U256,
 }
 
@@ -40,5 +45,5 @@ pub struct RpcTransaction {
 #[serde(untagged)]
 pub enum BlockTransaction {
     Full(FullTransaction),
-    Hash(H256),
+    Hash(#[serde(with = "crate::rpc_types::basic::compact")] H256),
 }
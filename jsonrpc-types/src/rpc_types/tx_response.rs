@@ -12,17 +12,141 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::rpc_types::Quantity;
 use cita_types::H256;
 
+/// How the transaction pool admitted a transaction.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PoolStatus {
+    /// Ready to be packaged.
+    Pending,
+    /// Held back by the pool, e.g. waiting on a nonce gap.
+    Queued,
+    /// Took the place of an earlier transaction.
+    Replaced,
+}
+
 //TODO respone contain error
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(remote = "TxResponse")]
 pub struct TxResponse {
+    #[serde(with = "crate::rpc_types::basic::compact")]
     pub hash: H256,
     pub status: String,
+    /// Pool admission details, omitted when the node does not report them.
+    #[serde(
+        rename = "poolStatus",
+        default,
+        skip_serializing_if = "crate::rpc_types::null_style::skip"
+    )]
+    pub pool_status: Option<PoolStatus>,
+    #[serde(
+        rename = "replacedHash",
+        default,
+        skip_serializing_if = "crate::rpc_types::null_style::skip",
+        with = "crate::rpc_types::basic::compact::option"
+    )]
+    pub replaced_hash: Option<H256>,
+    #[serde(
+        rename = "poolPosition",
+        default,
+        skip_serializing_if = "crate::rpc_types::null_style::skip"
+    )]
+    pub pool_position: Option<Quantity>,
 }
 
+null_style_serde!(TxResponse);
+
 impl TxResponse {
     pub fn new(hash: H256, status: String) -> Self {
-        TxResponse { hash, status }
+        TxResponse {
+            hash,
+            status,
+            pool_status: None,
+            replaced_hash: None,
+            pool_position: None,
+        }
+    }
+
+    /// Build the response from the result of inserting into the transaction pool.
+    pub fn with_admission(
+        hash: H256,
+        status: String,
+        pool_status: PoolStatus,
+        replaced_hash: Option<H256>,
+        pool_position: Option<u64>,
+    ) -> Self {
+        TxResponse {
+            hash,
+            status,
+            pool_status: Some(pool_status),
+            replaced_hash,
+            pool_position: pool_position.map(Into::into),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PoolStatus, TxResponse};
+    use cita_types::H256;
+    use serde_json;
+
+    #[test]
+    fn serialize_without_admission() {
+        let resp = TxResponse::new(H256::from(1), "OK".to_owned());
+        let value = json!({
+            "hash": "0x0000000000000000000000000000000000000000000000000000000000000001",
+            "status": "OK",
+        });
+        assert_eq!(serde_json::to_value(&resp).unwrap(), value);
+        assert_eq!(serde_json::from_value::<TxResponse>(value).unwrap(), resp);
+    }
+
+    #[test]
+    fn serialize_with_admission() {
+        let resp = TxResponse::with_admission(
+            H256::from(1),
+            "OK".to_owned(),
+            PoolStatus::Replaced,
+            Some(H256::from(2)),
+            Some(3),
+        );
+        let value = json!({
+            "hash": "0x0000000000000000000000000000000000000000000000000000000000000001",
+            "status": "OK",
+            "poolStatus": "replaced",
+            "replacedHash": "0x0000000000000000000000000000000000000000000000000000000000000002",
+            "poolPosition": "0x3",
+        });
+        assert_eq!(serde_json::to_value(&resp).unwrap(), value);
+        assert_eq!(serde_json::from_value::<TxResponse>(value).unwrap(), resp);
+
+        let resp = TxResponse::with_admission(
+            H256::from(1),
+            "OK".to_owned(),
+            PoolStatus::Queued,
+            None,
+            None,
+        );
+        assert_eq!(
+            serde_json::to_value(&resp).unwrap(),
+            json!({
+                "hash": "0x0000000000000000000000000000000000000000000000000000000000000001",
+                "status": "OK",
+                "poolStatus": "queued",
+            })
+        );
+    }
+
+    #[test]
+    fn deserialize_unknown_pool_status() {
+        let value = json!({
+            "hash": "0x0000000000000000000000000000000000000000000000000000000000000001",
+            "status": "OK",
+            "poolStatus": "dropped",
+        });
+        assert!(serde_json::from_value::<TxResponse>(value).is_err());
     }
 }
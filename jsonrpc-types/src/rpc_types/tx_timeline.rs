@@ -0,0 +1,96 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::rpc_types::{Data, Quantity};
+use cita_types::H256;
+
+/// The response of `getTransactionTimeline`: where a transaction got to,
+/// as the services told it.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TransactionTimeline {
+    #[serde(with = "crate::rpc_types::basic::compact")]
+    pub hash: H256,
+    /// The stages reported, in the order of the stages.
+    pub stages: Vec<TimelineStage>,
+    /// The stages before the last one reported that were never reported.
+    pub missing: Vec<String>,
+    /// Every stage reported, up to the receipt stored.
+    pub complete: bool,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(remote = "TimelineStage")]
+pub struct TimelineStage {
+    pub stage: String,
+    /// Milliseconds since the epoch, by the clock of the service.
+    pub timestamp: u64,
+    #[serde(default, skip_serializing_if = "crate::rpc_types::null_style::skip")]
+    pub height: Option<Quantity>,
+    #[serde(default, skip_serializing_if = "crate::rpc_types::null_style::skip")]
+    pub detail: Option<Data>,
+    /// Milliseconds since the stage reported before, none for the first
+    /// or a service whose clock is behind.
+    #[serde(default, skip_serializing_if = "crate::rpc_types::null_style::skip")]
+    pub latency: Option<u64>,
+}
+
+null_style_serde!(TimelineStage);
+
+#[cfg(test)]
+mod tests {
+    use super::{TimelineStage, TransactionTimeline};
+    use cita_types::H256;
+    use serde_json;
+
+    #[test]
+    fn serialization() {
+        let timeline = TransactionTimeline {
+            hash: H256::from(1),
+            stages: vec![
+                TimelineStage {
+                    stage: "received".to_owned(),
+                    timestamp: 1_000,
+                    height: None,
+                    detail: None,
+                    latency: None,
+                },
+                TimelineStage {
+                    stage: "proposed".to_owned(),
+                    timestamp: 1_300,
+                    height: Some(42u64.into()),
+                    detail: None,
+                    latency: Some(300),
+                },
+            ],
+            missing: vec!["verified".to_owned(), "pooled".to_owned()],
+            complete: false,
+        };
+        let value = json!({
+            "hash": format!("{:#066x}", 1),
+            "stages": [
+                {"stage": "received", "timestamp": 1000},
+                {"stage": "proposed", "timestamp": 1300, "height": "0x2a", "latency": 300},
+            ],
+            "missing": ["verified", "pooled"],
+            "complete": false,
+        });
+        assert_eq!(serde_json::to_value(&timeline).unwrap(), value);
+        assert_eq!(
+            serde_json::from_value::<TransactionTimeline>(value).unwrap(),
+            timeline
+        );
+    }
+}
@@ -0,0 +1,189 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::BTreeMap;
+
+use hashable::HASH_NAME;
+
+use crate::rpc_types::Quantity;
+
+/// The hash features, and the `HASH_NAME` of each.
+pub const HASH_FEATURES: [(&str, &str); 3] = [
+    ("blake2bhash", "blake2b"),
+    ("sha3hash", "sha3"),
+    ("sm3hash", "sm3"),
+];
+/// The crypto features, which are also their `SIGNATURE_NAME`s.
+pub const CRYPTO_FEATURES: [&str; 3] = ["ed25519", "secp256k1", "sm2"];
+
+/// What a node was built with, for clients to find out what it supports.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct VersionInfo {
+    #[serde(rename = "softwareVersion")]
+    pub software_version: String,
+    #[serde(rename = "protocolVersions")]
+    pub protocol_versions: Vec<Quantity>,
+    #[serde(rename = "hashAlgorithm")]
+    pub hash_algorithm: String,
+    #[serde(rename = "cryptoAlgorithm")]
+    pub crypto_algorithm: String,
+    /// Every hash and crypto feature, and whether it is enabled.
+    pub features: BTreeMap<String, bool>,
+}
+
+impl VersionInfo {
+    /// `crypto` is the service's `cita_crypto::SIGNATURE_NAME`, the hash
+    /// is the one this crate is built with.
+    pub fn new(software_version: String, protocol_versions: Vec<u64>, crypto: &str) -> Self {
+        let mut features = BTreeMap::new();
+        for &(feature, name) in &HASH_FEATURES {
+            features.insert(feature.to_owned(), name == HASH_NAME);
+        }
+        for &feature in &CRYPTO_FEATURES {
+            features.insert(feature.to_owned(), feature == crypto);
+        }
+        VersionInfo {
+            software_version,
+            protocol_versions: protocol_versions.into_iter().map(Into::into).collect(),
+            hash_algorithm: HASH_NAME.to_owned(),
+            crypto_algorithm: crypto.to_owned(),
+            features,
+        }
+    }
+
+    /// Report another feature, like `canonical-address`.
+    pub fn with_feature(mut self, feature: &str, enabled: bool) -> Self {
+        self.features.insert(feature.to_owned(), enabled);
+        self
+    }
+}
+
+/// The enabled RPC modules, and the version of each.
+#[derive(Debug, PartialEq, Clone, Default, Serialize, Deserialize)]
+pub struct RpcModules(pub BTreeMap<String, String>);
+
+impl RpcModules {
+    pub fn new() -> Self {
+        RpcModules::default()
+    }
+
+    pub fn with_module(mut self, module: &str, version: &str) -> Self {
+        self.0.insert(module.to_owned(), version.to_owned());
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RpcModules, VersionInfo};
+    use serde_json;
+
+    #[cfg(feature = "sha3hash")]
+    const HASH: (&str, [bool; 3]) = ("sha3", [false, true, false]);
+    #[cfg(feature = "blake2bhash")]
+    const HASH: (&str, [bool; 3]) = ("blake2b", [true, false, false]);
+    #[cfg(feature = "sm3hash")]
+    const HASH: (&str, [bool; 3]) = ("sm3", [false, false, true]);
+
+    fn enabled(info: &VersionInfo) -> Vec<&str> {
+        info.features
+            .iter()
+            .filter(|(_, enabled)| **enabled)
+            .map(|(feature, _)| feature.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn version_info_serialization() {
+        let (hash, [blake2b, sha3, sm3]) = HASH;
+        let info = VersionInfo::new("0.22.0".to_owned(), vec![0, 1], "secp256k1")
+            .with_feature("canonical-address", false);
+        let value = json!({
+            "softwareVersion": "0.22.0",
+            "protocolVersions": ["0x0", "0x1"],
+            "hashAlgorithm": hash,
+            "cryptoAlgorithm": "secp256k1",
+            "features": {
+                "blake2bhash": blake2b,
+                "canonical-address": false,
+                "ed25519": false,
+                "secp256k1": true,
+                "sha3hash": sha3,
+                "sm2": false,
+                "sm3hash": sm3,
+            },
+        });
+        assert_eq!(serde_json::to_value(&info).unwrap(), value);
+
+        // Fields in declaration order, features sorted.
+        let json = serde_json::to_string(&info).unwrap();
+        let keys = [
+            "softwareVersion",
+            "protocolVersions",
+            "hashAlgorithm",
+            "cryptoAlgorithm",
+            "features",
+            "\"blake2bhash\"",
+            "\"canonical-address\"",
+            "\"ed25519\"",
+            "\"secp256k1\"",
+            "\"sha3hash\"",
+            "\"sm2\"",
+            "\"sm3hash\"",
+        ];
+        let offsets: Vec<usize> = keys.iter().map(|key| json.find(key).unwrap()).collect();
+        assert!(offsets.windows(2).all(|pair| pair[0] < pair[1]), "{}", json);
+        assert_eq!(serde_json::from_str::<VersionInfo>(&json).unwrap(), info);
+    }
+
+    #[cfg(feature = "sha3hash")]
+    #[test]
+    fn sha3_feature_map() {
+        let info = VersionInfo::new("0.22.0".to_owned(), vec![1], "secp256k1");
+        assert_eq!(info.hash_algorithm, "sha3");
+        assert_eq!(enabled(&info), vec!["secp256k1", "sha3hash"]);
+    }
+
+    #[cfg(feature = "sm3hash")]
+    #[test]
+    fn sm3_feature_map() {
+        let info = VersionInfo::new("0.22.0".to_owned(), vec![1], "sm2");
+        assert_eq!(info.hash_algorithm, "sm3");
+        assert_eq!(enabled(&info), vec!["sm2", "sm3hash"]);
+    }
+
+    #[cfg(feature = "blake2bhash")]
+    #[test]
+    fn blake2b_feature_map() {
+        let info = VersionInfo::new("0.22.0".to_owned(), vec![1], "ed25519");
+        assert_eq!(info.hash_algorithm, "blake2b");
+        assert_eq!(enabled(&info), vec!["blake2bhash", "ed25519"]);
+    }
+
+    #[test]
+    fn rpc_modules_serialization() {
+        let modules = RpcModules::new()
+            .with_module("net", "1.0")
+            .with_module("cita", "1.0")
+            .with_module("admin", "0.1");
+        assert_eq!(
+            serde_json::to_string(&modules).unwrap(),
+            r#"{"admin":"0.1","cita":"1.0","net":"1.0"}"#
+        );
+        assert_eq!(
+            serde_json::from_str::<RpcModules>(r#"{"cita":"1.0"}"#).unwrap(),
+            RpcModules::new().with_module("cita", "1.0")
+        );
+    }
+}
@@ -0,0 +1,479 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! What a proposed body must be beyond decoding.
+//!
+//! Decoding takes whatever transactions a proposer put in a body. With
+//! `BlockBody::validate` a body is refused when it
+//!
+//! ```text
+//! has more transactions or bytes than the policy allows
+//! isn't the body of the transactions root of its header
+//! has a transaction twice
+//! has a transaction whose signature doesn't recover, or whose hash isn't its own
+//! has the nonces of a sender out of order, with `ordered_nonces`
+//! ```
+//!
+//! The senders are recovered from the signatures, and returned to be
+//! used on. Where they are known already, say auth verified the
+//! transactions, `validate_with_senders` takes them instead and doesn't
+//! recover any.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use protobuf::Message as MessageTrait;
+
+use crate::types::{Address, H256};
+use crate::{BlockBody, SignedTransaction};
+
+/// Transactions in a body.
+pub const DEFAULT_MAX_TRANSACTIONS: usize = 30_000;
+/// Bytes of a body, encoded.
+pub const DEFAULT_MAX_BODY_BYTES: usize = 64 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BodyPolicy {
+    pub max_transactions: usize,
+    pub max_bytes: usize,
+    /// Whether the nonces of each sender must be decimal and increase in
+    /// the order of the body. Off by default, as CITA clients send random
+    /// nonces; chains counting them turn it on.
+    pub ordered_nonces: bool,
+}
+
+impl Default for BodyPolicy {
+    fn default() -> Self {
+        BodyPolicy {
+            max_transactions: DEFAULT_MAX_TRANSACTIONS,
+            max_bytes: DEFAULT_MAX_BODY_BYTES,
+            ordered_nonces: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BodyError {
+    TooManyTransactions {
+        count: usize,
+        max: usize,
+    },
+    TooLarge {
+        bytes: usize,
+        max: usize,
+    },
+    /// The root of the transactions isn't the one of the header.
+    RootMismatch {
+        expected: H256,
+        computed: H256,
+    },
+    /// The transaction at `index` is the one at `first` again.
+    Duplicate {
+        index: usize,
+        first: usize,
+        hash: H256,
+    },
+    /// The signature at `index` doesn't recover.
+    BadSignature {
+        index: usize,
+        hash: H256,
+    },
+    /// The transaction at `index` hashes to `computed`, not to the hash
+    /// it came with.
+    HashMismatch {
+        index: usize,
+        hash: H256,
+        computed: H256,
+    },
+    /// The senders given aren't one for each transaction.
+    SendersMismatch {
+        senders: usize,
+        transactions: usize,
+    },
+    /// The nonce at `index` isn't a decimal number.
+    BadNonce {
+        index: usize,
+        hash: H256,
+    },
+    /// At `index`, `sender` has `nonce` after `previous`.
+    NonceOutOfOrder {
+        index: usize,
+        hash: H256,
+        sender: Address,
+        nonce: u64,
+        previous: u64,
+    },
+}
+
+impl fmt::Display for BodyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BodyError::TooManyTransactions { count, max } => {
+                write!(f, "{} transactions, at most {} allowed", count, max)
+            }
+            BodyError::TooLarge { bytes, max } => {
+                write!(f, "body of {} bytes, at most {} allowed", bytes, max)
+            }
+            BodyError::RootMismatch { expected, computed } => write!(
+                f,
+                "transactions root {:x}, the header has {:x}",
+                computed, expected
+            ),
+            BodyError::Duplicate { index, first, hash } => write!(
+                f,
+                "transaction {} ({:x}) is transaction {} again",
+                index, hash, first
+            ),
+            BodyError::BadSignature { index, hash } => {
+                write!(f, "transaction {} ({:x}) has a bad signature", index, hash)
+            }
+            BodyError::HashMismatch {
+                index,
+                hash,
+                computed,
+            } => write!(
+                f,
+                "transaction {} came with hash {:x}, it hashes to {:x}",
+                index, hash, computed
+            ),
+            BodyError::SendersMismatch {
+                senders,
+                transactions,
+            } => write!(
+                f,
+                "{} senders given for {} transactions",
+                senders, transactions
+            ),
+            BodyError::BadNonce { index, hash } => write!(
+                f,
+                "transaction {} ({:x}) has a nonce which isn't a number",
+                index, hash
+            ),
+            BodyError::NonceOutOfOrder {
+                index,
+                hash,
+                sender,
+                nonce,
+                previous,
+            } => write!(
+                f,
+                "transaction {} ({:x}) of {:x} has nonce {} after {}",
+                index, hash, sender, nonce, previous
+            ),
+        }
+    }
+}
+
+impl Error for BodyError {}
+
+impl BlockBody {
+    /// Validate the body of a header with `transactions_root`, returning
+    /// the senders recovered.
+    pub fn validate(
+        &self,
+        policy: &BodyPolicy,
+        transactions_root: &H256,
+    ) -> Result<Vec<Address>, BodyError> {
+        self.check_shape(policy, transactions_root)?;
+        let senders = self
+            .get_transactions()
+            .iter()
+            .enumerate()
+            .map(|(index, stx)| {
+                let hash = stx.crypt_hash();
+                let recovered =
+                    SignedTransaction::verify_transaction(stx.get_transaction_with_sig().clone())
+                        .map_err(|_| BodyError::BadSignature { index, hash })?;
+                if recovered.crypt_hash() != hash {
+                    return Err(BodyError::HashMismatch {
+                        index,
+                        hash,
+                        computed: recovered.crypt_hash(),
+                    });
+                }
+                Ok(recovered.from())
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        self.check_nonces(policy, &senders)?;
+        Ok(senders)
+    }
+
+    /// Like `validate`, with the senders of the transactions known.
+    pub fn validate_with_senders(
+        &self,
+        policy: &BodyPolicy,
+        transactions_root: &H256,
+        senders: &[Address],
+    ) -> Result<(), BodyError> {
+        self.check_shape(policy, transactions_root)?;
+        let transactions = self.get_transactions().len();
+        if senders.len() != transactions {
+            return Err(BodyError::SendersMismatch {
+                senders: senders.len(),
+                transactions,
+            });
+        }
+        self.check_nonces(policy, senders)
+    }
+
+    /// Limits, root and duplicates, which need no sender.
+    fn check_shape(&self, policy: &BodyPolicy, transactions_root: &H256) -> Result<(), BodyError> {
+        let count = self.get_transactions().len();
+        if count > policy.max_transactions {
+            return Err(BodyError::TooManyTransactions {
+                count,
+                max: policy.max_transactions,
+            });
+        }
+        let bytes = self.compute_size() as usize;
+        if bytes > policy.max_bytes {
+            return Err(BodyError::TooLarge {
+                bytes,
+                max: policy.max_bytes,
+            });
+        }
+        let computed = self.transactions_root();
+        if computed != *transactions_root {
+            return Err(BodyError::RootMismatch {
+                expected: *transactions_root,
+                computed,
+            });
+        }
+        let mut seen = HashMap::with_capacity(count);
+        for (index, hash) in self.transaction_hashes().into_iter().enumerate() {
+            if let Some(&first) = seen.get(&hash) {
+                return Err(BodyError::Duplicate { index, first, hash });
+            }
+            seen.insert(hash, index);
+        }
+        Ok(())
+    }
+
+    fn check_nonces(&self, policy: &BodyPolicy, senders: &[Address]) -> Result<(), BodyError> {
+        if !policy.ordered_nonces {
+            return Ok(());
+        }
+        let mut last: HashMap<Address, u64> = HashMap::new();
+        for (index, (stx, sender)) in self.get_transactions().iter().zip(senders).enumerate() {
+            let hash = stx.crypt_hash();
+            let nonce = stx
+                .get_transaction_with_sig()
+                .get_transaction()
+                .get_nonce()
+                .parse::<u64>()
+                .map_err(|_| BodyError::BadNonce { index, hash })?;
+            if let Some(&previous) = last.get(sender) {
+                if nonce <= previous {
+                    return Err(BodyError::NonceOutOfOrder {
+                        index,
+                        hash,
+                        sender: *sender,
+                        nonce,
+                        previous,
+                    });
+                }
+            }
+            last.insert(*sender, nonce);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BodyError, BodyPolicy};
+    use crate::crypto::{CreateKey, KeyPair};
+    use crate::types::{Address, H256};
+    use crate::{BlockBody, SignedTransaction, Transaction};
+
+    fn tx(keypair: &KeyPair, nonce: &str) -> SignedTransaction {
+        let mut tx = Transaction::new();
+        tx.set_nonce(nonce.to_owned());
+        tx.set_valid_until_block(100);
+        tx.set_quota(1_000_000);
+        tx.sign(keypair.privkey())
+    }
+
+    fn body_of(stxs: Vec<SignedTransaction>) -> (BlockBody, H256) {
+        let body = BlockBody::from_transactions(stxs);
+        let root = body.transactions_root();
+        (body, root)
+    }
+
+    #[test]
+    fn large_valid_body() {
+        let keypairs: Vec<KeyPair> = (0..4).map(|_| KeyPair::gen_keypair()).collect();
+        let stxs = (0..200)
+            .map(|i| tx(&keypairs[i % 4], &(i / 4 * 3).to_string()))
+            .collect();
+        let (body, root) = body_of(stxs);
+        let senders = body.validate(&BodyPolicy::default(), &root).unwrap();
+        assert_eq!(senders.len(), 200);
+        assert_eq!(senders[5], body.get_transactions()[5].from());
+        assert_eq!(senders[1], senders[197]);
+        assert_ne!(senders[0], senders[1]);
+
+        // Empty, with the root of nothing.
+        let (empty, root) = body_of(vec![]);
+        assert_eq!(empty.validate(&BodyPolicy::default(), &root), Ok(vec![]));
+    }
+
+    #[test]
+    fn limits_and_root() {
+        let keypair = KeyPair::gen_keypair();
+        let (body, root) = body_of(vec![tx(&keypair, "1"), tx(&keypair, "2")]);
+        let policy = BodyPolicy {
+            max_transactions: 1,
+            ..BodyPolicy::default()
+        };
+        assert_eq!(
+            body.validate(&policy, &root),
+            Err(BodyError::TooManyTransactions { count: 2, max: 1 })
+        );
+        let policy = BodyPolicy {
+            max_bytes: 100,
+            ..BodyPolicy::default()
+        };
+        match body.validate(&policy, &root) {
+            Err(BodyError::TooLarge { bytes, max: 100 }) => assert!(bytes > 100),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let other = H256::from(1);
+        assert_eq!(
+            body.validate(&BodyPolicy::default(), &other),
+            Err(BodyError::RootMismatch {
+                expected: other,
+                computed: root,
+            })
+        );
+    }
+
+    #[test]
+    fn duplicates_and_forgeries() {
+        let keypair = KeyPair::gen_keypair();
+        let first = tx(&keypair, "1");
+        let hash = first.crypt_hash();
+        let (body, root) = body_of(vec![first.clone(), tx(&keypair, "2"), first.clone()]);
+        let err = body.validate(&BodyPolicy::default(), &root).unwrap_err();
+        assert_eq!(
+            err,
+            BodyError::Duplicate {
+                index: 2,
+                first: 0,
+                hash,
+            }
+        );
+        assert!(err.to_string().starts_with("transaction 2 ("));
+
+        let mut forged = tx(&keypair, "3");
+        forged.mut_transaction_with_sig().set_signature(vec![0; 3]);
+        let forged_hash = forged.crypt_hash();
+        let (body, root) = body_of(vec![first.clone(), forged]);
+        assert_eq!(
+            body.validate(&BodyPolicy::default(), &root),
+            Err(BodyError::BadSignature {
+                index: 1,
+                hash: forged_hash,
+            })
+        );
+
+        // Another transaction's hash, so it passes for that one.
+        let mut disguised = tx(&keypair, "3");
+        let computed = disguised.crypt_hash();
+        disguised.set_tx_hash(H256::from(7).to_vec());
+        let (body, root) = body_of(vec![first, disguised]);
+        assert_eq!(
+            body.validate(&BodyPolicy::default(), &root),
+            Err(BodyError::HashMismatch {
+                index: 1,
+                hash: H256::from(7),
+                computed,
+            })
+        );
+    }
+
+    #[test]
+    fn nonces_in_order_per_sender() {
+        let alice = KeyPair::gen_keypair();
+        let bob = KeyPair::gen_keypair();
+        let late = tx(&alice, "5");
+        let late_hash = late.crypt_hash();
+        let (body, root) = body_of(vec![tx(&alice, "7"), tx(&bob, "1"), late, tx(&bob, "2")]);
+        let alice_address = body.get_transactions()[0].from();
+        let ordered = BodyPolicy {
+            ordered_nonces: true,
+            ..BodyPolicy::default()
+        };
+        assert_eq!(
+            body.validate(&ordered, &root),
+            Err(BodyError::NonceOutOfOrder {
+                index: 2,
+                hash: late_hash,
+                sender: alice_address,
+                nonce: 5,
+                previous: 7,
+            })
+        );
+        assert!(body.validate(&BodyPolicy::default(), &root).is_ok());
+
+        let random = tx(&bob, "e0c6b7d2");
+        let random_hash = random.crypt_hash();
+        let (body, root) = body_of(vec![tx(&bob, "1"), random]);
+        assert_eq!(
+            body.validate(&ordered, &root),
+            Err(BodyError::BadNonce {
+                index: 1,
+                hash: random_hash,
+            })
+        );
+        assert!(body.validate(&BodyPolicy::default(), &root).is_ok());
+    }
+
+    #[test]
+    fn supplied_senders_are_used() {
+        let alice = KeyPair::gen_keypair();
+        let (body, root) = body_of(vec![tx(&alice, "2"), tx(&alice, "1")]);
+        let ordered = BodyPolicy {
+            ordered_nonces: true,
+            ..BodyPolicy::default()
+        };
+        assert!(body.validate(&ordered, &root).is_err());
+
+        // Told apart by the senders given, the signatures aren't looked at.
+        let senders = vec![Address::from(1), Address::from(2)];
+        assert_eq!(
+            body.validate_with_senders(&ordered, &root, &senders),
+            Ok(())
+        );
+        let mut forged = body.clone();
+        for stx in forged.mut_transactions().iter_mut() {
+            stx.mut_transaction_with_sig().set_signature(vec![]);
+        }
+        let forged_root = forged.transactions_root();
+        assert_eq!(forged_root, root);
+        assert_eq!(
+            forged.validate_with_senders(&ordered, &root, &senders),
+            Ok(())
+        );
+        assert_eq!(
+            body.validate_with_senders(&BodyPolicy::default(), &root, &senders[..1]),
+            Err(BodyError::SendersMismatch {
+                senders: 1,
+                transactions: 2,
+            })
+        );
+    }
+}
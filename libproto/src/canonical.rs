@@ -0,0 +1,350 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The byte form messages are hashed and signed over.
+//!
+//! `write_to_bytes` is only as deterministic as the generated code
+//! happens to be: it writes fields in declaration order, passes unknown
+//! fields through and walks maps in `HashMap` order. The canonical form
+//! pins every choice down:
+//!
+//! 1. Fields are written in ascending tag order, whatever the declaration
+//!    order is.
+//! 2. Unknown fields are never written, so bytes a newer peer added can't
+//!    change a hash this build computes.
+//! 3. Singular scalar fields holding their default value (zero, `false`,
+//!    empty string or bytes, the enum value 0) are omitted. A set message
+//!    field is written even when empty, an unset one is omitted.
+//! 4. Repeated fields are written unpacked, one tag per element, in the
+//!    order of the list. Default elements are kept.
+//! 5. Map entries are written as nested messages holding both key (tag 1)
+//!    and value (tag 2), sorted by the bytes of the encoded entry.
+//! 6. Nested messages are canonicalized by the same rules. Exclusions only
+//!    apply to the fields of the outermost message.
+//!
+//! The canonical form equals `write_to_bytes` bit for bit for messages
+//! whose fields are declared in tag order and which carry no unknown
+//! fields, maps or packed repeated fields. proto3 packs repeated scalars
+//! by default, and rule 4 writes them unpacked. `Transaction` has only
+//! singular scalar fields, so the hashes computed before this module
+//! still match for every transaction seen so far.
+
+use hashable::Hashable;
+use protobuf::descriptor::FieldDescriptorProto_Type;
+use protobuf::reflect::{FieldDescriptor, ProtobufValue, ReflectFieldRef, ReflectValueRef};
+use protobuf::Message as MessageTrait;
+
+use crate::types::H256;
+
+/// A field number, as written in the `.proto` definition.
+pub type FieldTag = u32;
+
+const WIRE_VARINT: u32 = 0;
+const WIRE_FIXED64: u32 = 1;
+const WIRE_LENGTH_DELIMITED: u32 = 2;
+const WIRE_FIXED32: u32 = 5;
+
+/// Canonical encoding of `msg`, leaving out the top level fields listed in
+/// `exclusions`, e.g. a signature field when computing what gets signed.
+pub fn canonical_bytes<T: MessageTrait>(msg: &T, exclusions: &[FieldTag]) -> Vec<u8> {
+    let mut out = Vec::new();
+    write_message(msg, exclusions, &mut out);
+    out
+}
+
+/// Hash of `canonical_bytes(msg, exclusions)`.
+pub fn canonical_hash<T: MessageTrait>(msg: &T, exclusions: &[FieldTag]) -> H256 {
+    canonical_bytes(msg, exclusions).crypt_hash()
+}
+
+/// What the generated code writes, which hashes were computed over before
+/// the canonical form.
+pub fn legacy_bytes<T: MessageTrait>(msg: &T) -> Vec<u8> {
+    msg.write_to_bytes()
+        .expect("write_to_bytes of an initialized message")
+}
+
+/// Whether the canonical hash of `msg` is the same as the one computed over
+/// `legacy_bytes`. It encodes `msg` both ways, use it in tests and tools
+/// rather than on every hash.
+pub fn matches_legacy<T: MessageTrait>(msg: &T) -> bool {
+    canonical_bytes(msg, &[]) == legacy_bytes(msg)
+}
+
+fn write_message(msg: &dyn MessageTrait, exclusions: &[FieldTag], out: &mut Vec<u8>) {
+    let mut fields: Vec<&FieldDescriptor> = msg
+        .descriptor()
+        .fields()
+        .iter()
+        .filter(|field| !exclusions.contains(&field_tag(field)))
+        .collect();
+    fields.sort_by_key(|field| field_tag(field));
+
+    for field in fields {
+        let tag = field_tag(field);
+        let field_type = Some(field.proto().get_field_type());
+        match field.get_reflect(msg) {
+            ReflectFieldRef::Optional(Some(value)) => {
+                if !is_default(&value) {
+                    write_value(tag, field_type, value, out);
+                }
+            }
+            ReflectFieldRef::Optional(None) => {}
+            ReflectFieldRef::Repeated(values) => {
+                for value in values.reflect_iter() {
+                    write_value(tag, field_type, ProtobufValue::as_ref(value), out);
+                }
+            }
+            ReflectFieldRef::Map(map) => {
+                let mut entries: Vec<Vec<u8>> = map
+                    .reflect_iter()
+                    .map(|(key, value)| {
+                        let mut entry = Vec::new();
+                        write_value(1, None, ProtobufValue::as_ref(key), &mut entry);
+                        write_value(2, None, ProtobufValue::as_ref(value), &mut entry);
+                        entry
+                    })
+                    .collect();
+                entries.sort();
+                for entry in entries {
+                    write_length_delimited(tag, &entry, out);
+                }
+            }
+        }
+    }
+}
+
+fn field_tag(field: &FieldDescriptor) -> FieldTag {
+    field.proto().get_number() as FieldTag
+}
+
+fn is_default(value: &ReflectValueRef) -> bool {
+    match *value {
+        ReflectValueRef::U32(v) => v == 0,
+        ReflectValueRef::U64(v) => v == 0,
+        ReflectValueRef::I32(v) => v == 0,
+        ReflectValueRef::I64(v) => v == 0,
+        ReflectValueRef::F32(v) => v == 0.0,
+        ReflectValueRef::F64(v) => v == 0.0,
+        ReflectValueRef::Bool(v) => !v,
+        ReflectValueRef::String(v) => v.is_empty(),
+        ReflectValueRef::Bytes(v) => v.is_empty(),
+        ReflectValueRef::Enum(v) => v.value() == 0,
+        ReflectValueRef::Message(_) => false,
+    }
+}
+
+/// `field_type` picks between the encodings sharing a rust type, map keys
+/// and values come without one and use the plain varint forms.
+fn write_value(
+    tag: FieldTag,
+    field_type: Option<FieldDescriptorProto_Type>,
+    value: ReflectValueRef,
+    out: &mut Vec<u8>,
+) {
+    use protobuf::descriptor::FieldDescriptorProto_Type::*;
+
+    match value {
+        ReflectValueRef::U32(v) => match field_type {
+            Some(TYPE_FIXED32) => write_fixed32(tag, v, out),
+            _ => write_varint_field(tag, u64::from(v), out),
+        },
+        ReflectValueRef::U64(v) => match field_type {
+            Some(TYPE_FIXED64) => write_fixed64(tag, v, out),
+            _ => write_varint_field(tag, v, out),
+        },
+        ReflectValueRef::I32(v) => match field_type {
+            Some(TYPE_SINT32) => write_varint_field(tag, u64::from(zigzag32(v)), out),
+            Some(TYPE_SFIXED32) => write_fixed32(tag, v as u32, out),
+            _ => write_varint_field(tag, i64::from(v) as u64, out),
+        },
+        ReflectValueRef::I64(v) => match field_type {
+            Some(TYPE_SINT64) => write_varint_field(tag, zigzag64(v), out),
+            Some(TYPE_SFIXED64) => write_fixed64(tag, v as u64, out),
+            _ => write_varint_field(tag, v as u64, out),
+        },
+        ReflectValueRef::F32(v) => write_fixed32(tag, v.to_bits(), out),
+        ReflectValueRef::F64(v) => write_fixed64(tag, v.to_bits(), out),
+        ReflectValueRef::Bool(v) => write_varint_field(tag, v as u64, out),
+        ReflectValueRef::String(v) => write_length_delimited(tag, v.as_bytes(), out),
+        ReflectValueRef::Bytes(v) => write_length_delimited(tag, v, out),
+        ReflectValueRef::Enum(v) => write_varint_field(tag, i64::from(v.value()) as u64, out),
+        ReflectValueRef::Message(m) => {
+            let mut nested = Vec::new();
+            write_message(m, &[], &mut nested);
+            write_length_delimited(tag, &nested, out);
+        }
+    }
+}
+
+fn zigzag32(v: i32) -> u32 {
+    ((v << 1) ^ (v >> 31)) as u32
+}
+
+fn zigzag64(v: i64) -> u64 {
+    ((v << 1) ^ (v >> 63)) as u64
+}
+
+fn write_varint(mut v: u64, out: &mut Vec<u8>) {
+    while v >= 0x80 {
+        out.push((v as u8 & 0x7f) | 0x80);
+        v >>= 7;
+    }
+    out.push(v as u8);
+}
+
+fn write_tag(tag: FieldTag, wire_type: u32, out: &mut Vec<u8>) {
+    write_varint(u64::from(tag << 3 | wire_type), out);
+}
+
+fn write_varint_field(tag: FieldTag, v: u64, out: &mut Vec<u8>) {
+    write_tag(tag, WIRE_VARINT, out);
+    write_varint(v, out);
+}
+
+fn write_fixed32(tag: FieldTag, v: u32, out: &mut Vec<u8>) {
+    write_tag(tag, WIRE_FIXED32, out);
+    out.extend_from_slice(&v.to_le_bytes());
+}
+
+fn write_fixed64(tag: FieldTag, v: u64, out: &mut Vec<u8>) {
+    write_tag(tag, WIRE_FIXED64, out);
+    out.extend_from_slice(&v.to_le_bytes());
+}
+
+fn write_length_delimited(tag: FieldTag, bytes: &[u8], out: &mut Vec<u8>) {
+    write_tag(tag, WIRE_LENGTH_DELIMITED, out);
+    write_varint(bytes.len() as u64, out);
+    out.extend_from_slice(bytes);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{canonical_bytes, canonical_hash, legacy_bytes, matches_legacy};
+    use crate::{AccountGasLimit, Transaction, UnverifiedTransaction};
+    use protobuf::Message as MessageTrait;
+    use rustc_serialize::hex::ToHex;
+
+    // Golden vectors. The bytes don't depend on the hash feature, the
+    // hashes are listed per feature.
+    const TX_V0: &str = "0a0331323312013018ff93ebdc03209f8d062a0101320101";
+    const TX_V0_WITHOUT_NONCE: &str = "0a0331323318ff93ebdc03209f8d062a0101320101";
+    const TX_V1: &str =
+        "12013018ff93ebdc03209f8d062a010132010140014a03010203520a01020304050607080900";
+
+    #[cfg(feature = "sha3hash")]
+    const HASHES: [&str; 3] = [
+        "388cf9690b099bf69017e26dc53e61f0c6db05fc7436fd27b5273028fd4af300",
+        "607f2812f3f265f5f51cbb85cb7855fc8b81666c3f45c7d0e37094bc7b2c2726",
+        "4d24aec3f3615637dc72942898bd7170616859ed24c17f14aba4b15cf814a645",
+    ];
+    #[cfg(feature = "blake2bhash")]
+    const HASHES: [&str; 3] = [
+        "2772760cbf82f7d4da492926a60d0e4947214576dd1e68f1d3d84d8a8493e676",
+        "a0bdb447eb76e58e703c33041e8de9499724a5fa6165de0a8349a563aa80d4d4",
+        "e42bbfdde475ad92c7a14f792ef97b8e80c6d5ad22f7ee67dc4816fc52360d3d",
+    ];
+    #[cfg(feature = "sm3hash")]
+    const HASHES: [&str; 3] = [
+        "eee16af123965c601ee2cd9ad888c1b13f13a38000116f096b2ef34e3caf0792",
+        "554705dc6d07fe2d1a85f9dc8320bfb7b8fe25688619c3eede5a7dae1ebeebc8",
+        "db0167ff806b39e3fc319f94f5c19994ea8b88fcb38782c92ad46d5a9894bf4a",
+    ];
+
+    fn tx_v0() -> Transaction {
+        let mut tx = Transaction::new();
+        tx.set_data(vec![1]);
+        tx.set_nonce("0".to_string());
+        tx.set_to("123".to_string());
+        tx.set_valid_until_block(99999);
+        tx.set_quota(999_999_999);
+        tx.set_value(vec![1]);
+        tx.set_chain_id(0);
+        tx.set_version(0);
+        tx
+    }
+
+    fn tx_v1() -> Transaction {
+        let mut tx = Transaction::new();
+        tx.set_data(vec![1]);
+        tx.set_nonce("0".to_string());
+        tx.set_to_v1(vec![1, 2, 3]);
+        tx.set_valid_until_block(99999);
+        tx.set_quota(999_999_999);
+        tx.set_value(vec![1]);
+        tx.set_chain_id_v1(vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 0]);
+        tx.set_version(1);
+        tx
+    }
+
+    fn unverified_tx() -> UnverifiedTransaction {
+        let mut utx = UnverifiedTransaction::new();
+        utx.set_transaction(tx_v0());
+        utx.set_signature(vec![0x11; 65]);
+        utx
+    }
+
+    #[test]
+    fn golden_bytes() {
+        assert_eq!(canonical_bytes(&tx_v0(), &[]).to_hex(), TX_V0);
+        assert_eq!(canonical_bytes(&tx_v1(), &[]).to_hex(), TX_V1);
+        // The nested transaction, then the 65 signature bytes.
+        assert_eq!(
+            canonical_bytes(&unverified_tx(), &[]).to_hex(),
+            format!("0a18{}1241{}", TX_V0, "11".repeat(65))
+        );
+        // Excluding the nonce.
+        assert_eq!(
+            canonical_bytes(&tx_v0(), &[2]).to_hex(),
+            TX_V0_WITHOUT_NONCE
+        );
+        // Excluding a tag the message doesn't have is a no-op.
+        assert_eq!(canonical_bytes(&tx_v0(), &[42]).to_hex(), TX_V0);
+    }
+
+    #[cfg(any(feature = "sha3hash", feature = "blake2bhash", feature = "sm3hash"))]
+    #[test]
+    fn golden_hashes() {
+        assert_eq!(canonical_hash(&tx_v0(), &[]).to_hex(), HASHES[0]);
+        assert_eq!(canonical_hash(&tx_v1(), &[]).to_hex(), HASHES[1]);
+        assert_eq!(canonical_hash(&unverified_tx(), &[]).to_hex(), HASHES[2]);
+    }
+
+    #[test]
+    fn legacy_compatibility() {
+        assert!(matches_legacy(&tx_v0()));
+        assert!(matches_legacy(&tx_v1()));
+        assert!(matches_legacy(&unverified_tx()));
+
+        // Unknown fields are dropped, the legacy encoding keeps them.
+        let mut tx = tx_v0();
+        tx.mut_unknown_fields().add_varint(100, 1);
+        assert!(!matches_legacy(&tx));
+        assert_eq!(canonical_bytes(&tx, &[]).to_hex(), TX_V0);
+        assert_eq!(legacy_bytes(&tx).len(), TX_V0.len() / 2 + 3);
+        assert_eq!(tx.write_to_bytes().unwrap(), legacy_bytes(&tx));
+    }
+
+    #[test]
+    fn map_order() {
+        let mut limits = AccountGasLimit::new();
+        limits.mut_specific_quota_limit().insert("b".to_string(), 2);
+        limits.mut_specific_quota_limit().insert("a".to_string(), 1);
+        limits.mut_specific_quota_limit().insert("c".to_string(), 0);
+        assert_eq!(
+            canonical_bytes(&limits, &[]).to_hex(),
+            "12050a0161100112050a0162100212050a01631000"
+        );
+    }
+}
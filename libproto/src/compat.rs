@@ -0,0 +1,544 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Wire compatibility of the generated code across schema changes.
+//!
+//! `samples` builds one message of every type from the descriptors the
+//! generated code embeds, with every field set, and the bytes are checked
+//! in as a golden corpus under `compat/`:
+//!
+//! - `current.txt` is the schema as it is, headed by its `fingerprint`.
+//!   Decoding and re-encoding each entry with the generated code must give
+//!   the same bytes, and generating it again must too. A field renumbered
+//!   or retyped by regenerating the code changes one of them.
+//! - `previous.txt` is what the previous schema wrote, headed by its
+//!   fingerprint. It must still decode, with the fields added since left
+//!   at their defaults. Those are listed in `ADDED`, and the current
+//!   samples without them must give `previous.txt` back.
+//!
+//! When the schema changes on purpose, list the new fields in `ADDED` and
+//! regenerate `current.txt` with the ignored test `regenerate_corpus`. On a
+//! release, `current.txt` becomes `previous.txt` and `ADDED` is emptied.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use protobuf::descriptor::{
+    DescriptorProto, EnumDescriptorProto, FieldDescriptorProto, FieldDescriptorProto_Label,
+    FieldDescriptorProto_Type, FileDescriptorProto,
+};
+use protobuf::{CodedOutputStream, Message as MessageTrait, ProtobufEnum, ProtobufResult};
+use rustc_serialize::hex::{FromHex, ToHex};
+
+use crate::protos::*;
+
+/// Fields added since `previous.txt` was taken, by message.
+pub const ADDED: &[(&str, &[u32])] = &[];
+
+/// How deep samples nest messages, fields of messages deeper down are
+/// left out.
+const MAX_DEPTH: usize = 3;
+
+/// The descriptor of every `.proto` file, in a fixed order.
+pub fn files() -> Vec<&'static FileDescriptorProto> {
+    vec![
+        auth::file_descriptor_proto(),
+        blockchain::file_descriptor_proto(),
+        communication::file_descriptor_proto(),
+        compact_block::file_descriptor_proto(),
+        consensus::file_descriptor_proto(),
+        executor::file_descriptor_proto(),
+        request::file_descriptor_proto(),
+        response::file_descriptor_proto(),
+        snapshot::file_descriptor_proto(),
+        sync::file_descriptor_proto(),
+    ]
+}
+
+/// What of the schema matters on the wire, one line per message, field,
+/// enum and enum value: names, numbers, labels, types and oneofs.
+pub fn schema() -> String {
+    let mut out = String::new();
+    for file in files() {
+        writeln!(out, "file {}", file.get_name()).unwrap();
+        let prefix = package_prefix(file);
+        for message in file.get_message_type() {
+            describe_message(&prefix, message, &mut out);
+        }
+        for e in file.get_enum_type() {
+            describe_enum(&prefix, e, &mut out);
+        }
+    }
+    out
+}
+
+/// 64-bit FNV-1a of `schema`, as hex.
+pub fn fingerprint() -> String {
+    let hash = schema()
+        .bytes()
+        .fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        });
+    format!("{:016x}", hash)
+}
+
+fn package_prefix(file: &FileDescriptorProto) -> String {
+    if file.get_package().is_empty() {
+        String::new()
+    } else {
+        format!(".{}", file.get_package())
+    }
+}
+
+fn describe_message(prefix: &str, message: &DescriptorProto, out: &mut String) {
+    let name = format!("{}.{}", prefix, message.get_name());
+    writeln!(out, "message {}", name).unwrap();
+    for field in message.get_field() {
+        let oneof = if field.has_oneof_index() {
+            field.get_oneof_index().to_string()
+        } else {
+            "-".to_owned()
+        };
+        writeln!(
+            out,
+            "field {} {} {} {} {} {}",
+            field.get_number(),
+            field.get_name(),
+            field.get_label().value(),
+            field.get_field_type().value(),
+            field.get_type_name(),
+            oneof
+        )
+        .unwrap();
+    }
+    for nested in message.get_nested_type() {
+        describe_message(&name, nested, out);
+    }
+    for e in message.get_enum_type() {
+        describe_enum(&name, e, out);
+    }
+}
+
+fn describe_enum(prefix: &str, e: &EnumDescriptorProto, out: &mut String) {
+    writeln!(out, "enum {}.{}", prefix, e.get_name()).unwrap();
+    for value in e.get_value() {
+        writeln!(out, "value {} {}", value.get_number(), value.get_name()).unwrap();
+    }
+}
+
+/// Messages and enums by full name, e.g. `.Transaction`.
+struct Index {
+    messages: HashMap<String, &'static DescriptorProto>,
+    enums: HashMap<String, &'static EnumDescriptorProto>,
+    /// Top level messages, in order.
+    order: Vec<String>,
+    /// Fields left out, by message.
+    skipped: HashMap<String, &'static [u32]>,
+}
+
+impl Index {
+    fn new(skipped: &[(&str, &'static [u32])]) -> Self {
+        let mut index = Index {
+            messages: HashMap::new(),
+            enums: HashMap::new(),
+            order: Vec::new(),
+            skipped: skipped
+                .iter()
+                .map(|&(name, fields)| (format!(".{}", name), fields))
+                .collect(),
+        };
+        for file in files() {
+            let prefix = package_prefix(file);
+            for message in file.get_message_type() {
+                index
+                    .order
+                    .push(format!("{}.{}", prefix, message.get_name()));
+                index.add_message(&prefix, message);
+            }
+            for e in file.get_enum_type() {
+                index
+                    .enums
+                    .insert(format!("{}.{}", prefix, e.get_name()), e);
+            }
+        }
+        index
+    }
+
+    fn add_message(&mut self, prefix: &str, message: &'static DescriptorProto) {
+        let name = format!("{}.{}", prefix, message.get_name());
+        for nested in message.get_nested_type() {
+            self.add_message(&name, nested);
+        }
+        for e in message.get_enum_type() {
+            self.enums.insert(format!("{}.{}", name, e.get_name()), e);
+        }
+        self.messages.insert(name, message);
+    }
+
+    /// The fields of `name` which are written.
+    fn fields(&self, name: &str) -> Vec<&'static FieldDescriptorProto> {
+        let skipped = self.skipped.get(name).cloned().unwrap_or(&[]);
+        self.messages[name]
+            .get_field()
+            .iter()
+            .filter(|field| !skipped.contains(&(field.get_number() as u32)))
+            .collect()
+    }
+
+    /// Every field of `name` set, fields of a oneof only once: to the
+    /// field numbered `alternative`, or else to its first field. Plain
+    /// fields come first and oneofs after, as the generated code writes
+    /// them.
+    fn sample(
+        &self,
+        name: &str,
+        depth: usize,
+        alternative: Option<u32>,
+    ) -> ProtobufResult<Vec<u8>> {
+        let fields = self.fields(name);
+        let mut bytes = Vec::new();
+        {
+            let mut os = CodedOutputStream::vec(&mut bytes);
+            for field in fields.iter().filter(|field| !field.has_oneof_index()) {
+                self.write_field(field, depth, &mut os)?;
+            }
+            for oneof in 0..self.messages[name].get_oneof_decl().len() as i32 {
+                let members: Vec<_> = fields
+                    .iter()
+                    .filter(|field| field.has_oneof_index() && field.get_oneof_index() == oneof)
+                    .collect();
+                let chosen = members
+                    .iter()
+                    .find(|field| Some(field.get_number() as u32) == alternative)
+                    .or_else(|| members.first());
+                if let Some(field) = chosen {
+                    self.write_field(field, depth, &mut os)?;
+                }
+            }
+            os.flush()?;
+        }
+        Ok(bytes)
+    }
+
+    fn is_map(&self, field: &FieldDescriptorProto) -> bool {
+        field.get_field_type() == FieldDescriptorProto_Type::TYPE_MESSAGE
+            && self.messages[field.get_type_name()]
+                .get_options()
+                .get_map_entry()
+    }
+
+    /// Set to a value that isn't the default, so it's written: its own
+    /// number, `true`, its name, or the highest enum value. Repeated fields
+    /// get two elements, maps one entry.
+    fn write_field(
+        &self,
+        field: &FieldDescriptorProto,
+        depth: usize,
+        os: &mut CodedOutputStream,
+    ) -> ProtobufResult<()> {
+        use self::FieldDescriptorProto_Type::*;
+
+        let number = field.get_number() as u32;
+        let repeated = field.get_label() == FieldDescriptorProto_Label::LABEL_REPEATED;
+        let times = if repeated && !self.is_map(field) {
+            2
+        } else {
+            1
+        };
+        for _ in 0..times {
+            match field.get_field_type() {
+                TYPE_MESSAGE => {
+                    if depth >= MAX_DEPTH {
+                        return Ok(());
+                    }
+                    let nested = self.sample(field.get_type_name(), depth + 1, None)?;
+                    os.write_bytes(number, &nested)?;
+                }
+                TYPE_ENUM => {
+                    let highest = self.enums[field.get_type_name()]
+                        .get_value()
+                        .iter()
+                        .map(|value| value.get_number())
+                        .max()
+                        .unwrap_or(0);
+                    if highest == 0 {
+                        return Ok(());
+                    }
+                    os.write_enum(number, highest)?;
+                }
+                TYPE_STRING => os.write_string(number, field.get_name())?,
+                TYPE_BYTES => os.write_bytes(number, field.get_name().as_bytes())?,
+                TYPE_BOOL => os.write_bool(number, true)?,
+                TYPE_INT32 => os.write_int32(number, number as i32)?,
+                TYPE_INT64 => os.write_int64(number, i64::from(number))?,
+                TYPE_UINT32 => os.write_uint32(number, number)?,
+                TYPE_UINT64 => os.write_uint64(number, u64::from(number))?,
+                TYPE_SINT32 => os.write_sint32(number, number as i32)?,
+                TYPE_SINT64 => os.write_sint64(number, i64::from(number))?,
+                TYPE_FIXED32 => os.write_fixed32(number, number)?,
+                TYPE_FIXED64 => os.write_fixed64(number, u64::from(number))?,
+                TYPE_SFIXED32 => os.write_sfixed32(number, number as i32)?,
+                TYPE_SFIXED64 => os.write_sfixed64(number, i64::from(number))?,
+                TYPE_FLOAT => os.write_float(number, number as f32)?,
+                TYPE_DOUBLE => os.write_double(number, f64::from(number))?,
+                TYPE_GROUP => unreachable!("groups are proto2 only"),
+            }
+        }
+        Ok(())
+    }
+
+    /// A sample of each top level message, named after it. A message with
+    /// a oneof gets one per field of it, named `Message.field`.
+    fn samples(&self) -> Vec<(String, Vec<u8>)> {
+        let mut samples = Vec::new();
+        for name in &self.order {
+            if self.messages[name].get_options().get_map_entry() {
+                continue;
+            }
+            let alternatives: Vec<_> = self
+                .fields(name)
+                .into_iter()
+                .filter(|field| field.has_oneof_index())
+                .collect();
+            let short = name.trim_start_matches('.');
+            if alternatives.is_empty() {
+                let bytes = self.sample(name, 0, None).expect("sample of a message");
+                samples.push((short.to_owned(), bytes));
+            }
+            for field in alternatives {
+                let bytes = self
+                    .sample(name, 0, Some(field.get_number() as u32))
+                    .expect("sample of a message");
+                samples.push((format!("{}.{}", short, field.get_name()), bytes));
+            }
+        }
+        samples
+    }
+}
+
+/// Samples of the current schema, what `current.txt` holds.
+pub fn samples() -> Vec<(String, Vec<u8>)> {
+    Index::new(&[]).samples()
+}
+
+/// Samples without the `ADDED` fields, what `previous.txt` holds. Those of
+/// messages added since are empty, and not in `previous.txt`.
+pub fn previous_samples() -> Vec<(String, Vec<u8>)> {
+    Index::new(ADDED).samples()
+}
+
+/// The corpus file of `samples`, `schema` being their fingerprint.
+pub fn render_corpus(schema: Option<&str>, samples: &[(String, Vec<u8>)]) -> String {
+    let mut out = String::from("# Generated by libproto::compat, do not edit.\n");
+    if let Some(schema) = schema {
+        writeln!(out, "# schema {}", schema).unwrap();
+    }
+    for (name, bytes) in samples {
+        writeln!(out, "{} {}", name, bytes.to_hex()).unwrap();
+    }
+    out
+}
+
+/// The fingerprint and samples of a corpus file.
+pub fn parse_corpus(text: &str) -> Result<(Option<String>, Vec<(String, Vec<u8>)>), String> {
+    let mut schema = None;
+    let mut samples = Vec::new();
+    for line in text.lines() {
+        let header = "# schema ";
+        if line.starts_with(header) {
+            schema = Some(line[header.len()..].to_owned());
+        }
+        if line.starts_with('#') || line.is_empty() {
+            continue;
+        }
+        let mut parts = line.splitn(2, ' ');
+        let name = parts.next().unwrap_or_default();
+        let hex = parts.next().unwrap_or_default();
+        let bytes = hex
+            .from_hex()
+            .map_err(|e| format!("bad hex for {}: {}", name, e))?;
+        samples.push((name.to_owned(), bytes));
+    }
+    Ok((schema, samples))
+}
+
+fn reencode_as<T: MessageTrait>(bytes: &[u8]) -> ProtobufResult<Vec<u8>> {
+    protobuf::parse_from_bytes::<T>(bytes)?.write_to_bytes()
+}
+
+/// Decode `bytes` as the message the sample `name` is of, and encode it
+/// again. `None` for a name of no message.
+pub fn reencode(name: &str, bytes: &[u8]) -> Option<ProtobufResult<Vec<u8>>> {
+    macro_rules! by_name {
+        ($($message:ident),* $(,)*) => {
+            match name.split('.').next().unwrap_or_default() {
+                $(stringify!($message) => Some(reencode_as::<$message>(bytes)),)*
+                _ => None,
+            }
+        };
+    }
+    by_name!(
+        BlockTxHashes,
+        BlockTxHashesReq,
+        Miscellaneous,
+        MiscellaneousReq,
+        VerifyBlockReq,
+        VerifyBlockResp,
+        VerifyTxReq,
+        AccountGasLimit,
+        BlackList,
+        Block,
+        BlockBody,
+        BlockHeader,
+        BlockTxs,
+        BlockWithProof,
+        CompactBlock,
+        CompactBlockBody,
+        Proof,
+        RichStatus,
+        SignedTransaction,
+        StateSignal,
+        Status,
+        Transaction,
+        UnverifiedTransaction,
+        InnerMessage,
+        BlockTxn,
+        GetBlockTxn,
+        CompactProposal,
+        CompactSignedProposal,
+        Proposal,
+        SignedProposal,
+        Vote,
+        ConsensusConfig,
+        ExecutedHeader,
+        ExecutedInfo,
+        ExecutedResult,
+        LogEntry,
+        Receipt,
+        ReceiptErrorWithOption,
+        ReceiptWithOption,
+        StateRoot,
+        BatchRequest,
+        Call,
+        Request,
+        StateProof,
+        StorageKey,
+        FullTransaction,
+        Response,
+        SnapshotReq,
+        SnapshotResp,
+        SyncRequest,
+        SyncResponse,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CURRENT: &str = include_str!("../compat/current.txt");
+    const PREVIOUS: &str = include_str!("../compat/previous.txt");
+
+    fn corpus(text: &str) -> (Option<String>, HashMap<String, Vec<u8>>) {
+        let (schema, samples) = parse_corpus(text).unwrap();
+        (schema, samples.into_iter().collect())
+    }
+
+    #[test]
+    fn schema_unchanged() {
+        let (schema, _) = corpus(CURRENT);
+        assert_eq!(
+            schema.as_ref().map(String::as_str),
+            Some(fingerprint().as_str()),
+            "The protobuf schema changed since compat/current.txt was generated. \
+             If that's meant to be, list every field added in compat::ADDED, \
+             then regenerate the corpus with \
+             `cargo test -p libproto compat::tests::regenerate_corpus -- --ignored` \
+             and check its diff only adds bytes. Renumbering or retyping a field \
+             breaks nodes of the previous version."
+        );
+    }
+
+    #[test]
+    fn current_corpus_round_trips() {
+        let (_, golden) = corpus(CURRENT);
+        let samples = samples();
+        assert_eq!(golden.len(), samples.len());
+        for (name, bytes) in &samples {
+            assert_eq!(
+                golden.get(name),
+                Some(bytes),
+                "{} generated differently",
+                name
+            );
+            let reencoded = reencode(name, bytes).expect("a known message").unwrap();
+            assert_eq!(&reencoded, bytes, "{} re-encoded differently", name);
+        }
+    }
+
+    #[test]
+    fn previous_corpus_still_decodes() {
+        let (_, golden) = corpus(PREVIOUS);
+        let samples: HashMap<String, Vec<u8>> = previous_samples().into_iter().collect();
+        for (name, bytes) in &samples {
+            match golden.get(name) {
+                Some(golden) => assert_eq!(
+                    golden, bytes,
+                    "{} differs from the previous schema, check compat::ADDED",
+                    name
+                ),
+                None => assert!(
+                    bytes.is_empty(),
+                    "{} is new, list its fields in compat::ADDED",
+                    name
+                ),
+            }
+        }
+        for (name, bytes) in &golden {
+            assert!(samples.contains_key(name), "{} was removed", name);
+            // Nothing is lost or gained, so the added fields are unset.
+            let reencoded = reencode(name, bytes).expect("a known message").unwrap();
+            assert_eq!(&reencoded, bytes, "{} re-encoded differently", name);
+        }
+
+        let tx: Transaction = protobuf::parse_from_bytes(&golden["Transaction"]).unwrap();
+        assert_eq!(tx.get_to(), "to");
+        assert_eq!(tx.get_version(), 8);
+        assert_eq!(tx.get_to_v1(), b"to_v1");
+        let req: VerifyTxReq = protobuf::parse_from_bytes(&golden["VerifyTxReq"]).unwrap();
+        assert_eq!(req.get_chain_id(), 8);
+        assert_eq!(req.get_chain_id_v1(), b"chain_id_v1");
+        let status: Status = protobuf::parse_from_bytes(&golden["Status"]).unwrap();
+        assert_eq!(status.get_height(), 2);
+    }
+
+    #[test]
+    fn corpus_files_parse() {
+        let samples = vec![("Status".to_owned(), vec![0x0a, 0x00])];
+        let text = render_corpus(Some("00ff"), &samples);
+        assert_eq!(parse_corpus(&text), Ok((Some("00ff".to_owned()), samples)));
+        assert!(parse_corpus("Status 0a0").is_err());
+        assert!(parse_corpus("Status 0x").is_err());
+        assert!(reencode("NoSuchMessage", &[]).is_none());
+    }
+
+    /// Write `compat/current.txt` for the schema as it is.
+    #[test]
+    #[ignore]
+    fn regenerate_corpus() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/compat/current.txt");
+        let text = render_corpus(Some(&fingerprint()), &samples());
+        ::std::fs::write(path, text).unwrap();
+    }
+}
@@ -24,17 +24,32 @@ extern crate rustc_serialize;
 extern crate serde_derive;
 extern crate cita_merklehash;
 extern crate snappy;
-
+#[cfg(test)]
+#[macro_use]
+extern crate serde_json;
+
+pub mod body;
+pub mod canonical;
+pub mod compat;
+pub mod lifecycle;
+pub mod metrics;
+pub mod policy;
 pub mod protos;
+pub mod receipt_error;
 pub use crate::protos::*;
 mod autoimpl;
 pub mod router;
+pub mod snapshot_manifest;
+pub mod stats;
+pub mod sync_progress;
+pub mod tx_origin;
 
 use crate::crypto::{CreateKey, KeyPair, PrivKey, PubKey, Sign, Signature, SIGNATURE_BYTES_LEN};
+use crate::types::tx_validity::{ValidityError, ValidityPolicy};
 use crate::types::{Address, H256};
 use cita_merklehash::{merge, Tree, HASH_NULL};
 use hashable::Hashable;
-use protobuf::RepeatedField;
+use protobuf::{Message as MessageTrait, RepeatedField};
 use rlp::{Decodable, DecoderError, Encodable, RlpStream, UntrustedRlp};
 use rustc_serialize::hex::ToHex;
 use std::convert::From;
@@ -71,29 +86,81 @@ impl From<RichStatus> for Status {
     }
 }
 
+impl RichStatus {
+    /// Addresses taking part in consensus.
+    pub fn validator_addresses(&self) -> Vec<Address> {
+        self.get_validators()
+            .iter()
+            .map(|v| Address::from_slice(v))
+            .collect()
+    }
+
+    /// Addresses allowed to join the network, observers included.
+    pub fn node_addresses(&self) -> Vec<Address> {
+        self.get_nodes()
+            .iter()
+            .map(|n| Address::from_slice(n))
+            .collect()
+    }
+}
+
+/// Transaction hashes are taken over the canonical encoding. The two
+/// messages hashed have only singular fields, declared in tag order, so
+/// that is what `write_to_bytes` produced for every transaction so far. It
+/// only differs when the message carries fields this build doesn't know.
+/// Older nodes hash those bytes too and would compute another hash, so say
+/// so.
+fn tx_hash<T: TxMessage>(msg: &T) -> H256 {
+    if msg.carries_unknown_fields() {
+        warn!(
+            "{} carries unknown fields, older nodes compute another hash for it",
+            msg.descriptor().name()
+        );
+    }
+    canonical::canonical_hash(msg, &[])
+}
+
+trait TxMessage: MessageTrait {
+    /// The message or one nested in it.
+    fn carries_unknown_fields(&self) -> bool;
+}
+
+impl TxMessage for Transaction {
+    fn carries_unknown_fields(&self) -> bool {
+        self.get_unknown_fields().iter().next().is_some()
+    }
+}
+
+impl TxMessage for UnverifiedTransaction {
+    fn carries_unknown_fields(&self) -> bool {
+        self.get_unknown_fields().iter().next().is_some()
+            || self.get_transaction().carries_unknown_fields()
+    }
+}
+
 impl Transaction {
     /// Signs the transaction by PrivKey.
-    pub fn sign(&self, sk: PrivKey) -> SignedTransaction {
-        let keypair = KeyPair::from_privkey(sk).unwrap();
+    pub fn sign(&self, sk: &PrivKey) -> SignedTransaction {
+        // Only the sm2 key isn't `Copy`.
+        #[allow(clippy::clone_on_copy)]
+        let keypair = KeyPair::from_privkey(sk.clone()).unwrap();
         let pubkey = keypair.pubkey();
         let unverified_tx = self.build_unverified(sk);
 
         // Build SignedTransaction
         let mut signed_tx = SignedTransaction::new();
         signed_tx.set_signer(pubkey.to_vec());
-        let bytes: Vec<u8> = (&unverified_tx).try_into().unwrap();
-        signed_tx.set_tx_hash(bytes.crypt_hash().to_vec());
+        signed_tx.set_tx_hash(unverified_tx.crypt_hash().to_vec());
         signed_tx.set_transaction_with_sig(unverified_tx);
         signed_tx
     }
 
     /// Build UnverifiedTransaction
-    pub fn build_unverified(&self, sk: PrivKey) -> UnverifiedTransaction {
+    pub fn build_unverified(&self, sk: &PrivKey) -> UnverifiedTransaction {
         let mut unverified_tx = UnverifiedTransaction::new();
-        let bytes: Vec<u8> = self.try_into().unwrap();
-        let hash = bytes.crypt_hash();
+        let hash = tx_hash(self);
         unverified_tx.set_transaction(self.clone());
-        let signature = Signature::sign(&sk, &hash).unwrap();
+        let signature = Signature::sign(sk, &hash).unwrap();
         unverified_tx.set_signature(signature.to_vec());
         unverified_tx.set_crypto(Crypto::DEFAULT);
         unverified_tx
@@ -103,8 +170,7 @@ impl Transaction {
 impl UnverifiedTransaction {
     /// Try to recover the public key.
     pub fn recover_public(&self) -> Result<(PubKey, H256), (H256, String)> {
-        let bytes: Vec<u8> = self.get_transaction().try_into().unwrap();
-        let hash = bytes.crypt_hash();
+        let hash = tx_hash(self.get_transaction());
         let tx_hash = self.crypt_hash();
         if self.get_signature().len() != SIGNATURE_BYTES_LEN {
             trace!("Invalid signature length {}", hash);
@@ -130,14 +196,12 @@ impl UnverifiedTransaction {
     }
 
     pub fn crypt_hash(&self) -> H256 {
-        let bytes: Vec<u8> = self.try_into().unwrap();
-        bytes.crypt_hash()
+        tx_hash(self)
     }
 
     pub fn tx_verify_req_msg(&self) -> VerifyTxReq {
         let version = self.get_transaction().get_version();
-        let bytes: Vec<u8> = self.get_transaction().try_into().unwrap();
-        let hash = bytes.crypt_hash();
+        let hash = tx_hash(self.get_transaction());
         let mut verify_tx_req = VerifyTxReq::new();
         verify_tx_req.set_valid_until_block(self.get_transaction().get_valid_until_block());
         // tx hash
@@ -163,6 +227,17 @@ impl UnverifiedTransaction {
     }
 }
 
+impl VerifyTxReq {
+    /// Whether the block after `current_height` may include the transaction.
+    pub fn check_valid_until_block(
+        &self,
+        policy: &ValidityPolicy,
+        current_height: u64,
+    ) -> Result<(), ValidityError> {
+        policy.check(self.get_valid_until_block(), current_height)
+    }
+}
+
 impl Deref for SignedTransaction {
     type Target = UnverifiedTransaction;
 
@@ -426,7 +501,7 @@ mod tests {
         tx.set_chain_id(0);
         tx.set_version(0);
 
-        let signed_tx = tx.sign(*pv);
+        let signed_tx = tx.sign(pv);
         assert_eq!(
             signed_tx.crypt_hash(),
             signed_tx.get_transaction_with_sig().crypt_hash()
@@ -450,10 +525,33 @@ mod tests {
         tx.set_chain_id_v1(vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 0]);
         tx.set_version(1);
 
-        let signed_tx = tx.sign(*pv);
+        let signed_tx = tx.sign(pv);
         assert_eq!(
             signed_tx.crypt_hash(),
             signed_tx.get_transaction_with_sig().crypt_hash()
         );
     }
+
+    #[test]
+    fn verify_req_valid_until_block() {
+        use super::{CreateKey, KeyPair, Transaction};
+        use crate::types::tx_validity::{ValidityError, ValidityPolicy};
+        let keypair = KeyPair::gen_keypair();
+
+        let mut tx = Transaction::new();
+        tx.set_valid_until_block(200);
+        tx.set_quota(999999999);
+        let req = tx.build_unverified(keypair.privkey()).tx_verify_req_msg();
+
+        let policy = ValidityPolicy::default();
+        assert_eq!(req.check_valid_until_block(&policy, 100), Ok(()));
+        assert_eq!(
+            req.check_valid_until_block(&policy, 99),
+            Err(ValidityError::BeyondWindow {
+                valid_until: 200,
+                max: 199,
+            })
+        );
+        assert!(req.check_valid_until_block(&policy, 200).is_err());
+    }
 }
@@ -0,0 +1,542 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Where a transaction got to, told by the services it went through.
+//!
+//! Each service tells of a `TxLifecycleEvent` when a transaction reaches
+//! its stage: jsonrpc when it received it, auth when it verified it and put
+//! it in the pool, consensus when it proposed it, the executor when it
+//! executed it and chain when it stored the receipt. A `LifecycleEmitter`
+//! makes the events, and nothing at all while switched off. One
+//! `LifecycleCollector` puts the events of each transaction together into
+//! its `Timeline`.
+//!
+//! To be sent between services, routed as `<service>.tx_lifecycle`, the
+//! event has to be in cita-proto first, as
+//!
+//! ```text
+//! enum TxStage {
+//!     Received = 0;
+//!     Verified = 1;
+//!     Pooled = 2;
+//!     Proposed = 3;
+//!     Executed = 4;
+//!     ReceiptStored = 5;
+//! }
+//!
+//! message TxLifecycleEvent {
+//!     bytes tx_hash = 1;
+//!     TxStage stage = 2;
+//!     // Milliseconds since the epoch.
+//!     uint64 timestamp = 3;
+//!     // Of the stages at a height the height, 8 bytes big endian.
+//!     bytes detail = 4;
+//! }
+//! ```
+//!
+//! with a `TxLifecycleEvent` variant in the content of `InnerMessage`.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::router::SubModules;
+use crate::types::H256;
+
+/// In the order a transaction goes through them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum TxStage {
+    Received = 0,
+    Verified = 1,
+    Pooled = 2,
+    Proposed = 3,
+    Executed = 4,
+    ReceiptStored = 5,
+}
+
+pub const TX_STAGES: [TxStage; 6] = [
+    TxStage::Received,
+    TxStage::Verified,
+    TxStage::Pooled,
+    TxStage::Proposed,
+    TxStage::Executed,
+    TxStage::ReceiptStored,
+];
+
+impl TxStage {
+    /// The service telling of it.
+    pub fn service(self) -> SubModules {
+        match self {
+            TxStage::Received => SubModules::Jsonrpc,
+            TxStage::Verified | TxStage::Pooled => SubModules::Auth,
+            TxStage::Proposed => SubModules::Consensus,
+            TxStage::Executed => SubModules::Executor,
+            TxStage::ReceiptStored => SubModules::Chain,
+        }
+    }
+
+    /// Whether its detail is the height.
+    pub fn has_height(self) -> bool {
+        self >= TxStage::Proposed
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            TxStage::Received => "received",
+            TxStage::Verified => "verified",
+            TxStage::Pooled => "pooled",
+            TxStage::Proposed => "proposed",
+            TxStage::Executed => "executed",
+            TxStage::ReceiptStored => "receiptStored",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TxLifecycleEvent {
+    tx_hash: H256,
+    stage: TxStage,
+    timestamp: u64,
+    detail: Vec<u8>,
+}
+
+impl TxLifecycleEvent {
+    /// `timestamp` in milliseconds since the epoch.
+    pub fn new(tx_hash: H256, stage: TxStage, timestamp: u64) -> Self {
+        TxLifecycleEvent {
+            tx_hash,
+            stage,
+            timestamp,
+            detail: Vec::new(),
+        }
+    }
+
+    pub fn with_detail(mut self, detail: Vec<u8>) -> Self {
+        self.detail = detail;
+        self
+    }
+
+    pub fn with_height(self, height: u64) -> Self {
+        self.with_detail(height.to_be_bytes().to_vec())
+    }
+
+    pub fn get_tx_hash(&self) -> H256 {
+        self.tx_hash
+    }
+
+    pub fn get_stage(&self) -> TxStage {
+        self.stage
+    }
+
+    /// Milliseconds since the epoch.
+    pub fn get_timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    pub fn get_detail(&self) -> &[u8] {
+        &self.detail
+    }
+
+    /// Of the stages at a height.
+    pub fn get_height(&self) -> Option<u64> {
+        if !self.stage.has_height() || self.detail.len() != 8 {
+            return None;
+        }
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&self.detail);
+        Some(u64::from_be_bytes(bytes))
+    }
+}
+
+fn unix_now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs() * 1000 + u64::from(since.subsec_millis()))
+        .unwrap_or(0)
+}
+
+/// Makes the events of a service. Clones share the switch, which can be
+/// turned at runtime, and while off no event is even made.
+#[derive(Debug, Clone)]
+pub struct LifecycleEmitter {
+    enabled: Arc<AtomicBool>,
+}
+
+impl LifecycleEmitter {
+    pub fn new(enabled: bool) -> Self {
+        LifecycleEmitter {
+            enabled: Arc::new(AtomicBool::new(enabled)),
+        }
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// None while switched off.
+    pub fn emit(&self, event: TxLifecycleEvent) -> Option<TxLifecycleEvent> {
+        if !self.is_enabled() {
+            return None;
+        }
+        Some(event)
+    }
+
+    fn stage(
+        &self,
+        tx_hash: H256,
+        stage: TxStage,
+        height: Option<u64>,
+    ) -> Option<TxLifecycleEvent> {
+        if !self.is_enabled() {
+            return None;
+        }
+        let event = TxLifecycleEvent::new(tx_hash, stage, unix_now_ms());
+        self.emit(match height {
+            Some(height) => event.with_height(height),
+            None => event,
+        })
+    }
+
+    /// By jsonrpc.
+    pub fn received(&self, tx_hash: H256) -> Option<TxLifecycleEvent> {
+        self.stage(tx_hash, TxStage::Received, None)
+    }
+
+    /// By auth.
+    pub fn verified(&self, tx_hash: H256) -> Option<TxLifecycleEvent> {
+        self.stage(tx_hash, TxStage::Verified, None)
+    }
+
+    /// By auth.
+    pub fn pooled(&self, tx_hash: H256) -> Option<TxLifecycleEvent> {
+        self.stage(tx_hash, TxStage::Pooled, None)
+    }
+
+    /// By consensus, in the proposal for `height`.
+    pub fn proposed(&self, tx_hash: H256, height: u64) -> Option<TxLifecycleEvent> {
+        self.stage(tx_hash, TxStage::Proposed, Some(height))
+    }
+
+    /// By the executor.
+    pub fn executed(&self, tx_hash: H256, height: u64) -> Option<TxLifecycleEvent> {
+        self.stage(tx_hash, TxStage::Executed, Some(height))
+    }
+
+    /// By chain.
+    pub fn receipt_stored(&self, tx_hash: H256, height: u64) -> Option<TxLifecycleEvent> {
+        self.stage(tx_hash, TxStage::ReceiptStored, Some(height))
+    }
+}
+
+/// From one stage reported to the next one reported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StageLatency {
+    pub from: TxStage,
+    pub to: TxStage,
+    /// None when `to` was reported earlier than `from`, by a service
+    /// whose clock is behind.
+    pub millis: Option<u64>,
+}
+
+/// The events of one transaction, by stage.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Timeline {
+    tx_hash: H256,
+    events: BTreeMap<TxStage, TxLifecycleEvent>,
+}
+
+impl Timeline {
+    pub fn get_tx_hash(&self) -> H256 {
+        self.tx_hash
+    }
+
+    pub fn get(&self, stage: TxStage) -> Option<&TxLifecycleEvent> {
+        self.events.get(&stage)
+    }
+
+    /// In the order of the stages, whichever order they came in.
+    pub fn events(&self) -> Vec<&TxLifecycleEvent> {
+        self.events.values().collect()
+    }
+
+    /// The furthest stage reported.
+    pub fn reached(&self) -> Option<TxStage> {
+        self.events.keys().next_back().cloned()
+    }
+
+    /// The stages before the one reached that no service reported, lost
+    /// or never sent.
+    pub fn missing(&self) -> Vec<TxStage> {
+        match self.reached() {
+            Some(reached) => TX_STAGES
+                .iter()
+                .cloned()
+                .take_while(|stage| *stage < reached)
+                .filter(|stage| !self.events.contains_key(stage))
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Every stage reported, the receipt stored.
+    pub fn is_complete(&self) -> bool {
+        self.events.len() == TX_STAGES.len()
+    }
+
+    /// Between the stages reported, a missing one left out.
+    pub fn latencies(&self) -> Vec<StageLatency> {
+        let events = self.events();
+        events
+            .windows(2)
+            .map(|pair| StageLatency {
+                from: pair[0].stage,
+                to: pair[1].stage,
+                millis: pair[1].timestamp.checked_sub(pair[0].timestamp),
+            })
+            .collect()
+    }
+
+    fn last_timestamp(&self) -> u64 {
+        self.events
+            .values()
+            .map(|event| event.timestamp)
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+/// Takes in the `TxLifecycleEvent`s of all services and keeps the
+/// timelines of the latest `capacity` transactions, each for `retention`
+/// after its last event.
+#[derive(Debug)]
+pub struct LifecycleCollector {
+    capacity: usize,
+    retention: Duration,
+    timelines: HashMap<H256, Timeline>,
+    /// The first seen first.
+    order: VecDeque<H256>,
+}
+
+impl LifecycleCollector {
+    pub fn new(capacity: usize, retention: Duration) -> Self {
+        LifecycleCollector {
+            capacity: capacity.max(1),
+            retention,
+            timelines: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    /// False for a stage reported before, of which the first report is
+    /// kept. A new transaction over the capacity evicts the first seen.
+    pub fn ingest(&mut self, event: TxLifecycleEvent) -> bool {
+        let tx_hash = event.tx_hash;
+        if !self.timelines.contains_key(&tx_hash) {
+            if self.timelines.len() >= self.capacity {
+                if let Some(oldest) = self.order.pop_front() {
+                    self.timelines.remove(&oldest);
+                }
+            }
+            self.order.push_back(tx_hash);
+            self.timelines.insert(
+                tx_hash,
+                Timeline {
+                    tx_hash,
+                    events: BTreeMap::new(),
+                },
+            );
+        }
+        let events = &mut self
+            .timelines
+            .get_mut(&tx_hash)
+            .expect("just inserted")
+            .events;
+        if events.contains_key(&event.stage) {
+            return false;
+        }
+        events.insert(event.stage, event);
+        true
+    }
+
+    pub fn timeline(&self, tx_hash: &H256) -> Option<&Timeline> {
+        self.timelines.get(tx_hash)
+    }
+
+    /// Drop the timelines whose last event is older than `retention`
+    /// before `now`, in milliseconds since the epoch, returning how many.
+    pub fn expire(&mut self, now: u64) -> usize {
+        let retention = self.retention.as_secs() * 1000 + u64::from(self.retention.subsec_millis());
+        let before = self.timelines.len();
+        let timelines = &mut self.timelines;
+        self.order.retain(|tx_hash| {
+            let keep = timelines
+                .get(tx_hash)
+                .map(|timeline| timeline.last_timestamp().saturating_add(retention) >= now)
+                .unwrap_or(false);
+            if !keep {
+                timelines.remove(tx_hash);
+            }
+            keep
+        });
+        before - self.timelines.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.timelines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.timelines.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LifecycleCollector, LifecycleEmitter, StageLatency, TxLifecycleEvent, TxStage};
+    use crate::router::SubModules;
+    use crate::types::H256;
+    use std::time::Duration;
+
+    fn event(tx: u64, stage: TxStage, timestamp: u64) -> TxLifecycleEvent {
+        let event = TxLifecycleEvent::new(H256::from(tx), stage, timestamp);
+        if stage.has_height() {
+            event.with_height(42)
+        } else {
+            event
+        }
+    }
+
+    #[test]
+    fn emitter_switches_off() {
+        let emitter = LifecycleEmitter::new(true);
+        let executed = emitter.executed(H256::from(7), 42).unwrap();
+        assert_eq!(executed.get_tx_hash(), H256::from(7));
+        assert_eq!(executed.get_stage(), TxStage::Executed);
+        assert_eq!(executed.get_stage().service(), SubModules::Executor);
+        assert_eq!(executed.get_height(), Some(42));
+        assert!(executed.get_timestamp() > 1_500_000_000_000);
+        let received = emitter.received(H256::from(7)).unwrap();
+        assert_eq!(received.get_height(), None);
+        assert!(received.get_detail().is_empty());
+
+        // Switched off by another clone.
+        emitter.clone().set_enabled(false);
+        assert!(!emitter.is_enabled());
+        assert_eq!(emitter.received(H256::from(7)), None);
+        assert_eq!(emitter.emit(event(7, TxStage::Pooled, 1)), None);
+    }
+
+    #[test]
+    fn timelines_assemble_out_of_order() {
+        let mut collector = LifecycleCollector::new(16, Duration::from_secs(60));
+        let stages = vec![
+            (TxStage::Executed, 1_900),
+            (TxStage::Received, 1_000),
+            (TxStage::ReceiptStored, 1_950),
+            (TxStage::Pooled, 1_030),
+            (TxStage::Proposed, 1_400),
+            (TxStage::Verified, 1_020),
+        ];
+        for (stage, timestamp) in stages {
+            assert!(collector.ingest(event(1, stage, timestamp)));
+        }
+        // Told twice, the first is kept.
+        assert!(!collector.ingest(event(1, TxStage::Received, 1_010)));
+
+        let timeline = collector.timeline(&H256::from(1)).unwrap();
+        assert!(timeline.is_complete());
+        assert!(timeline.missing().is_empty());
+        assert_eq!(timeline.reached(), Some(TxStage::ReceiptStored));
+        let stages: Vec<TxStage> = timeline.events().iter().map(|e| e.get_stage()).collect();
+        assert_eq!(stages, super::TX_STAGES.to_vec());
+        assert_eq!(
+            timeline.get(TxStage::Received).unwrap().get_timestamp(),
+            1_000
+        );
+        let millis: Vec<Option<u64>> = timeline.latencies().iter().map(|l| l.millis).collect();
+        assert_eq!(
+            millis,
+            vec![Some(20), Some(10), Some(370), Some(500), Some(50)]
+        );
+        assert_eq!(
+            timeline.get(TxStage::Proposed).unwrap().get_height(),
+            Some(42)
+        );
+        assert_eq!(collector.timeline(&H256::from(2)), None);
+    }
+
+    #[test]
+    fn missing_stages_are_gaps() {
+        let mut collector = LifecycleCollector::new(16, Duration::from_secs(60));
+        collector.ingest(event(1, TxStage::Received, 1_000));
+        collector.ingest(event(1, TxStage::Proposed, 1_300));
+        // The executor's clock is behind.
+        collector.ingest(event(1, TxStage::Executed, 1_250));
+
+        let timeline = collector.timeline(&H256::from(1)).unwrap();
+        assert!(!timeline.is_complete());
+        assert_eq!(timeline.reached(), Some(TxStage::Executed));
+        assert_eq!(timeline.missing(), vec![TxStage::Verified, TxStage::Pooled]);
+        assert_eq!(
+            timeline.latencies(),
+            vec![
+                StageLatency {
+                    from: TxStage::Received,
+                    to: TxStage::Proposed,
+                    millis: Some(300),
+                },
+                StageLatency {
+                    from: TxStage::Proposed,
+                    to: TxStage::Executed,
+                    millis: None,
+                },
+            ]
+        );
+
+        // Only received so far, nothing missing yet.
+        collector.ingest(event(2, TxStage::Received, 1_000));
+        let timeline = collector.timeline(&H256::from(2)).unwrap();
+        assert!(timeline.missing().is_empty());
+        assert!(timeline.latencies().is_empty());
+    }
+
+    #[test]
+    fn retention_evicts() {
+        let mut collector = LifecycleCollector::new(3, Duration::from_secs(10));
+        for tx in 1..=3 {
+            collector.ingest(event(tx, TxStage::Received, tx * 1_000));
+        }
+        // Later stages don't make a transaction newer.
+        collector.ingest(event(1, TxStage::Verified, 4_000));
+        collector.ingest(event(4, TxStage::Received, 4_000));
+        assert_eq!(collector.len(), 3);
+        assert_eq!(collector.timeline(&H256::from(1)), None);
+
+        // 2 and 3 last heard of at 2s and 3s.
+        assert_eq!(collector.expire(12_000), 0);
+        assert_eq!(collector.expire(12_001), 1);
+        assert_eq!(collector.timeline(&H256::from(2)), None);
+        assert_eq!(collector.expire(14_001), 2);
+        assert!(collector.is_empty());
+
+        // Room again after expiring.
+        collector.ingest(event(5, TxStage::Received, 20_000));
+        assert_eq!(collector.len(), 1);
+    }
+}
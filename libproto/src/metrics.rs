@@ -0,0 +1,618 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Metrics of the services, collected in one place.
+//!
+//! Every service tells its `ServiceMetrics` now and then, e.g. auth with
+//! the latency of verifying transactions. One `MetricsCollector` takes
+//! them all in and renders them for Prometheus to scrape in one place.
+//!
+//! To be sent on the MQ, routed as `<service>.service_metrics`, they have
+//! to be in cita-proto first, as
+//!
+//! ```text
+//! message MetricLabel {
+//!     string name = 1;
+//!     string value = 2;
+//! }
+//!
+//! message MetricBucket {
+//!     double upper_bound = 1;
+//!     uint64 count = 2;
+//! }
+//!
+//! enum MetricKind {
+//!     Counter = 0;
+//!     Gauge = 1;
+//!     Histogram = 2;
+//! }
+//!
+//! message Metric {
+//!     string name = 1;
+//!     MetricKind kind = 2;
+//!     // The sum of the observations of a histogram.
+//!     double value = 3;
+//!     repeated MetricLabel labels = 4;
+//!     // Of a histogram, cumulative like Prometheus'.
+//!     repeated MetricBucket buckets = 5;
+//!     uint64 count = 6;
+//! }
+//!
+//! message ServiceMetrics {
+//!     string service = 1;
+//!     // Milliseconds since the epoch.
+//!     uint64 timestamp = 2;
+//!     repeated Metric metrics = 3;
+//! }
+//! ```
+//!
+//! with a `ServiceMetrics` variant in the content of `InnerMessage`.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+use crate::router::SubModules;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetricKind {
+    Counter = 0,
+    Gauge = 1,
+    Histogram = 2,
+}
+
+impl MetricKind {
+    fn type_name(self) -> &'static str {
+        match self {
+            MetricKind::Counter => "counter",
+            MetricKind::Gauge => "gauge",
+            MetricKind::Histogram => "histogram",
+        }
+    }
+}
+
+/// The observations up to `upper_bound`, those of the buckets before
+/// included.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bucket {
+    pub upper_bound: f64,
+    pub count: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metric {
+    name: String,
+    kind: MetricKind,
+    value: f64,
+    labels: Vec<(String, String)>,
+    buckets: Vec<Bucket>,
+    count: u64,
+}
+
+impl Metric {
+    pub fn counter<S: Into<String>>(name: S, value: f64) -> Self {
+        Metric::new(name.into(), MetricKind::Counter, value)
+    }
+
+    pub fn gauge<S: Into<String>>(name: S, value: f64) -> Self {
+        Metric::new(name.into(), MetricKind::Gauge, value)
+    }
+
+    /// `count` observations adding up to `sum`, in cumulative `buckets`
+    /// of increasing bounds. The `+Inf` one may be left out.
+    pub fn histogram<S: Into<String>>(name: S, buckets: Vec<Bucket>, sum: f64, count: u64) -> Self {
+        let mut metric = Metric::new(name.into(), MetricKind::Histogram, sum);
+        metric.buckets = buckets;
+        metric.count = count;
+        metric
+    }
+
+    fn new(name: String, kind: MetricKind, value: f64) -> Self {
+        Metric {
+            name,
+            kind,
+            value,
+            labels: Vec::new(),
+            buckets: Vec::new(),
+            count: 0,
+        }
+    }
+
+    pub fn with_label<N: Into<String>, V: Into<String>>(mut self, name: N, value: V) -> Self {
+        self.labels.push((name.into(), value.into()));
+        self
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn get_kind(&self) -> MetricKind {
+        self.kind
+    }
+
+    /// Of a counter or a gauge, and the sum of a histogram.
+    pub fn get_value(&self) -> f64 {
+        self.value
+    }
+
+    pub fn get_labels(&self) -> &[(String, String)] {
+        &self.labels
+    }
+
+    pub fn get_buckets(&self) -> &[Bucket] {
+        &self.buckets
+    }
+
+    /// The observations of a histogram.
+    pub fn get_count(&self) -> u64 {
+        self.count
+    }
+}
+
+/// The metrics of one service at `timestamp`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ServiceMetrics {
+    service: String,
+    timestamp: u64,
+    metrics: Vec<Metric>,
+}
+
+impl ServiceMetrics {
+    pub fn new(service: SubModules, timestamp: u64) -> Self {
+        ServiceMetrics {
+            service: service.to_string(),
+            timestamp,
+            metrics: Vec::new(),
+        }
+    }
+
+    pub fn get_service(&self) -> &str {
+        &self.service
+    }
+
+    /// Milliseconds since the epoch.
+    pub fn get_timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    pub fn get_metrics(&self) -> &[Metric] {
+        &self.metrics
+    }
+
+    pub fn push(&mut self, metric: Metric) {
+        self.metrics.push(metric);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct SeriesKey {
+    name: String,
+    service: String,
+    labels: Vec<(String, String)>,
+}
+
+/// What a counter or a histogram counted before the restarts of its
+/// service, added to what it counts since.
+#[derive(Debug, Clone, Default)]
+struct Carried {
+    value: f64,
+    count: u64,
+    buckets: Vec<u64>,
+}
+
+#[derive(Debug)]
+struct Series {
+    last: Metric,
+    carried: Carried,
+    seen: Instant,
+}
+
+impl Series {
+    fn update(&mut self, metric: Metric, now: Instant) {
+        let last = &self.last;
+        if metric.kind != last.kind || !same_bounds(&metric.buckets, &last.buckets) {
+            self.carried = Carried::default();
+        } else {
+            match metric.kind {
+                MetricKind::Counter if metric.value < last.value => {
+                    self.carried.value += last.value;
+                }
+                MetricKind::Histogram if metric.count < last.count => {
+                    self.carried.value += last.value;
+                    self.carried.count += last.count;
+                    self.carried.buckets.resize(last.buckets.len(), 0);
+                    for (carried, bucket) in self.carried.buckets.iter_mut().zip(&last.buckets) {
+                        *carried += bucket.count;
+                    }
+                }
+                _ => {}
+            }
+        }
+        self.last = metric;
+        self.seen = now;
+    }
+}
+
+fn same_bounds(a: &[Bucket], b: &[Bucket]) -> bool {
+    a.len() == b.len()
+        && a.iter()
+            .zip(b)
+            .all(|(a, b)| a.upper_bound.to_bits() == b.upper_bound.to_bits())
+}
+
+/// Takes in the `ServiceMetrics` of all services and renders them merged,
+/// in the text format of Prometheus, with a `service` label added.
+///
+/// A service which restarted counts from zero again. A counter or a
+/// histogram going down is taken for that, and keeps adding up from what
+/// it had counted, so it never goes back for Prometheus either.
+///
+/// Services send every `interval`. A series not sent for `stale_after`
+/// of them, e.g. of a service gone away, is left out after `expire`.
+#[derive(Debug)]
+pub struct MetricsCollector {
+    interval: Duration,
+    stale_after: u32,
+    series: BTreeMap<SeriesKey, Series>,
+    timestamps: HashMap<String, u64>,
+}
+
+impl MetricsCollector {
+    pub fn new(interval: Duration, stale_after: u32) -> Self {
+        MetricsCollector {
+            interval,
+            stale_after: stale_after.max(1),
+            series: BTreeMap::new(),
+            timestamps: HashMap::new(),
+        }
+    }
+
+    pub fn ingest(&mut self, metrics: ServiceMetrics) -> bool {
+        self.ingest_at(metrics, Instant::now())
+    }
+
+    /// False for metrics older than the last of their service, which
+    /// are out of order and left out.
+    pub fn ingest_at(&mut self, metrics: ServiceMetrics, now: Instant) -> bool {
+        let ServiceMetrics {
+            service,
+            timestamp,
+            metrics,
+        } = metrics;
+        match self.timestamps.get(&service) {
+            Some(&last) if timestamp < last => return false,
+            _ => {}
+        }
+        self.timestamps.insert(service.clone(), timestamp);
+
+        for metric in metrics {
+            let key = SeriesKey {
+                name: metric.name.clone(),
+                service: service.clone(),
+                labels: metric.labels.clone(),
+            };
+            if let Some(kind) = self.kind_of(&key) {
+                if kind != metric.kind {
+                    warn!(
+                        "{} sent {} as a {}, which is a {}",
+                        service,
+                        metric.name,
+                        metric.kind.type_name(),
+                        kind.type_name()
+                    );
+                    continue;
+                }
+            }
+            match self.series.get_mut(&key) {
+                Some(series) => series.update(metric, now),
+                None => {
+                    self.series.insert(
+                        key,
+                        Series {
+                            last: metric,
+                            carried: Carried::default(),
+                            seen: now,
+                        },
+                    );
+                }
+            }
+        }
+        true
+    }
+
+    /// The kind of the other series of the same name, which all share
+    /// one `# TYPE` line.
+    fn kind_of(&self, key: &SeriesKey) -> Option<MetricKind> {
+        self.series
+            .iter()
+            .find(|&(other, _)| other.name == key.name && other != key)
+            .map(|(_, series)| series.last.kind)
+    }
+
+    /// Drop the series last sent more than `stale_after` intervals
+    /// before `now`, returning how many.
+    pub fn expire(&mut self, now: Instant) -> usize {
+        let max_age = self.interval * self.stale_after;
+        let stale: Vec<SeriesKey> = self
+            .series
+            .iter()
+            .filter(|(_, series)| now.saturating_duration_since(series.seen) > max_age)
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in &stale {
+            self.series.remove(key);
+        }
+        stale.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.series.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.series.is_empty()
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let mut name: Option<&str> = None;
+        for (key, series) in &self.series {
+            let metric = &series.last;
+            if name != Some(key.name.as_str()) {
+                name = Some(key.name.as_str());
+                let _ = writeln!(out, "# TYPE {} {}", key.name, metric.kind.type_name());
+            }
+            let carried = &series.carried;
+            match metric.kind {
+                MetricKind::Counter | MetricKind::Gauge => {
+                    let value = carried.value + metric.value;
+                    sample(&mut out, &key.name, key, None, value);
+                }
+                MetricKind::Histogram => {
+                    let count = carried.count + metric.count;
+                    let bucket_name = format!("{}_bucket", key.name);
+                    let mut has_inf = false;
+                    for (i, bucket) in metric.buckets.iter().enumerate() {
+                        has_inf |= bucket.upper_bound.is_infinite();
+                        let carried = carried.buckets.get(i).cloned().unwrap_or(0);
+                        let le = format_value(bucket.upper_bound);
+                        let value = (carried + bucket.count) as f64;
+                        sample(&mut out, &bucket_name, key, Some(&le), value);
+                    }
+                    if !has_inf {
+                        sample(&mut out, &bucket_name, key, Some("+Inf"), count as f64);
+                    }
+                    let sum = carried.value + metric.value;
+                    sample(&mut out, &format!("{}_sum", key.name), key, None, sum);
+                    sample(
+                        &mut out,
+                        &format!("{}_count", key.name),
+                        key,
+                        None,
+                        count as f64,
+                    );
+                }
+            }
+        }
+        out
+    }
+}
+
+fn sample(out: &mut String, name: &str, key: &SeriesKey, le: Option<&str>, value: f64) {
+    let _ = write!(out, "{}{{service=\"{}\"", name, escape(&key.service));
+    for (label, label_value) in &key.labels {
+        let _ = write!(out, ",{}=\"{}\"", label, escape(label_value));
+    }
+    if let Some(le) = le {
+        let _ = write!(out, ",le=\"{}\"", le);
+    }
+    let _ = writeln!(out, "}} {}", format_value(value));
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn format_value(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".to_owned()
+    } else if value.is_infinite() {
+        if value > 0.0 { "+Inf" } else { "-Inf" }.to_owned()
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Bucket, Metric, MetricsCollector, ServiceMetrics};
+    use crate::router::SubModules;
+    use std::time::{Duration, Instant};
+
+    const INTERVAL: Duration = Duration::from_secs(10);
+
+    fn latency(counts: [u64; 3], sum: f64) -> Metric {
+        let buckets = vec![
+            Bucket {
+                upper_bound: 0.005,
+                count: counts[0],
+            },
+            Bucket {
+                upper_bound: 0.05,
+                count: counts[1],
+            },
+            Bucket {
+                upper_bound: 0.5,
+                count: counts[2],
+            },
+        ];
+        Metric::histogram("verify_latency_seconds", buckets, sum, counts[2] + 1)
+            .with_label("kind", "tx")
+    }
+
+    fn send(service: SubModules, timestamp: u64, metrics: Vec<Metric>) -> ServiceMetrics {
+        let mut msg = ServiceMetrics::new(service, timestamp);
+        for metric in metrics {
+            msg.push(metric);
+        }
+        msg
+    }
+
+    #[test]
+    fn histogram_renders() {
+        let metrics = send(
+            SubModules::Auth,
+            1_570_000_000_000,
+            vec![
+                latency([3, 7, 9], 1.25),
+                Metric::counter("verified_total", 10.0),
+                Metric::gauge("pool_size", 0.0).with_label("pool", "tx"),
+            ],
+        );
+        assert_eq!(metrics.get_service(), "auth");
+        assert_eq!(metrics.get_timestamp(), 1_570_000_000_000);
+        let histogram = &metrics.get_metrics()[0];
+        let bounds: Vec<f64> = histogram
+            .get_buckets()
+            .iter()
+            .map(|b| b.upper_bound)
+            .collect();
+        assert_eq!(bounds, vec![0.005, 0.05, 0.5]);
+        assert_eq!(histogram.get_count(), 10);
+
+        let mut collector = MetricsCollector::new(INTERVAL, 3);
+        assert!(collector.ingest(metrics));
+        let rendered = collector.render();
+        for line in &[
+            "verify_latency_seconds_bucket{service=\"auth\",kind=\"tx\",le=\"0.005\"} 3",
+            "verify_latency_seconds_bucket{service=\"auth\",kind=\"tx\",le=\"0.05\"} 7",
+            "verify_latency_seconds_bucket{service=\"auth\",kind=\"tx\",le=\"0.5\"} 9",
+            "verify_latency_seconds_bucket{service=\"auth\",kind=\"tx\",le=\"+Inf\"} 10",
+            "verify_latency_seconds_sum{service=\"auth\",kind=\"tx\"} 1.25",
+            "verify_latency_seconds_count{service=\"auth\",kind=\"tx\"} 10",
+        ] {
+            assert!(rendered.lines().any(|l| l == *line), "{}", rendered);
+        }
+    }
+
+    #[test]
+    fn restarts_keep_adding_up() {
+        let mut collector = MetricsCollector::new(INTERVAL, 3);
+        let now = Instant::now();
+        let mut timestamp = 0;
+        let mut ingest = |counter: f64, histogram: Metric| {
+            timestamp += 10_000;
+            let metrics = send(
+                SubModules::Auth,
+                timestamp,
+                vec![Metric::counter("verified_total", counter), histogram],
+            );
+            assert!(collector.ingest_at(metrics, now));
+            collector.render()
+        };
+
+        ingest(10.0, latency([1, 2, 3], 0.5));
+        let rendered = ingest(15.0, latency([2, 4, 6], 1.0));
+        assert!(rendered.contains("verified_total{service=\"auth\"} 15\n"));
+        // Restarted.
+        let rendered = ingest(3.0, latency([1, 1, 1], 0.25));
+        assert!(rendered.contains("verified_total{service=\"auth\"} 18\n"));
+        assert!(rendered.contains("le=\"0.005\"} 3\n"));
+        assert!(rendered.contains("le=\"0.5\"} 7\n"));
+        assert!(rendered.contains("le=\"+Inf\"} 9\n"));
+        assert!(
+            rendered.contains("verify_latency_seconds_sum{service=\"auth\",kind=\"tx\"} 1.25\n")
+        );
+        let rendered = ingest(4.0, latency([1, 1, 2], 0.5));
+        assert!(rendered.contains("verified_total{service=\"auth\"} 19\n"));
+        assert!(rendered.contains("le=\"+Inf\"} 10\n"));
+    }
+
+    #[test]
+    fn merged_rendering() {
+        let mut collector = MetricsCollector::new(INTERVAL, 3);
+        let now = Instant::now();
+        let auth = send(
+            SubModules::Auth,
+            2_000,
+            vec![
+                Metric::counter("messages_total", 7.0),
+                Metric::gauge("queue_len", 2.0).with_label("queue", "tx"),
+            ],
+        );
+        let chain = send(
+            SubModules::Chain,
+            1_000,
+            vec![Metric::counter("messages_total", 3.0)],
+        );
+        assert!(collector.ingest_at(chain, now));
+        assert!(collector.ingest_at(auth, now));
+        // Out of order, older than the last one.
+        let late = send(
+            SubModules::Auth,
+            1_500,
+            vec![Metric::counter("messages_total", 6.0)],
+        );
+        assert!(!collector.ingest_at(late, now));
+        // Another kind for a name in use is left out.
+        let wrong = send(
+            SubModules::Chain,
+            3_000,
+            vec![Metric::gauge("messages_total", 1.0).with_label("queue", "block")],
+        );
+        assert!(collector.ingest_at(wrong, now));
+
+        assert_eq!(
+            collector.render(),
+            concat!(
+                "# TYPE messages_total counter\n",
+                "messages_total{service=\"auth\"} 7\n",
+                "messages_total{service=\"chain\"} 3\n",
+                "# TYPE queue_len gauge\n",
+                "queue_len{service=\"auth\",queue=\"tx\"} 2\n",
+            )
+        );
+    }
+
+    #[test]
+    fn stale_series_are_dropped() {
+        let mut collector = MetricsCollector::new(INTERVAL, 3);
+        let start = Instant::now();
+        let both = send(
+            SubModules::Auth,
+            1,
+            vec![Metric::gauge("queue_len", 1.0), Metric::gauge("peers", 4.0)],
+        );
+        assert!(collector.ingest_at(both, start));
+        let later = start + INTERVAL * 2;
+        let one = send(SubModules::Auth, 2, vec![Metric::gauge("peers", 5.0)]);
+        assert!(collector.ingest_at(one, later));
+
+        assert_eq!(collector.expire(start + INTERVAL * 3), 0);
+        assert_eq!(
+            collector.expire(start + INTERVAL * 3 + Duration::from_secs(1)),
+            1
+        );
+        assert_eq!(
+            collector.render(),
+            "# TYPE peers gauge\npeers{service=\"auth\"} 5\n"
+        );
+        assert_eq!(collector.expire(later + INTERVAL * 4), 1);
+        assert!(collector.is_empty());
+    }
+}
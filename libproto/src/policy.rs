@@ -0,0 +1,472 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Limits on the content of transactions, in one place.
+//!
+//! A `TxPolicy` is loaded from the chain's system config, and `check`
+//! reports every limit a transaction breaks.
+
+use std::fmt;
+
+use protobuf::Message as MessageTrait;
+
+use crate::types::hex::FromHexStr;
+use crate::types::{Address, H256};
+use crate::{SignedTransaction, UnverifiedTransaction};
+
+pub const DEFAULT_MAX_TX_BYTES: usize = 1024 * 1024;
+pub const DEFAULT_MAX_DATA_BYTES: usize = 1024 * 1024;
+/// `value` is a 256 bits integer.
+pub const MAX_VALUE_BYTES: usize = 32;
+/// Versions this build knows how to read.
+pub const SUPPORTED_VERSIONS: VersionRange = VersionRange { min: 0, max: 2 };
+
+const ADDRESS_BYTES: usize = 20;
+
+/// Versions from `min` to `max`, both included.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VersionRange {
+    pub min: u32,
+    pub max: u32,
+}
+
+impl VersionRange {
+    pub fn contains(self, version: u32) -> bool {
+        self.min <= version && version <= self.max
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default, rename_all = "camelCase")]
+pub struct TxPolicy {
+    /// Size of the encoded `UnverifiedTransaction`.
+    pub max_tx_bytes: usize,
+    pub max_data_bytes: usize,
+    pub require_chain_id: bool,
+    /// Contracts may be created, with an empty `to`.
+    pub allow_create: bool,
+    pub version_range: VersionRange,
+}
+
+impl Default for TxPolicy {
+    fn default() -> Self {
+        TxPolicy {
+            max_tx_bytes: DEFAULT_MAX_TX_BYTES,
+            max_data_bytes: DEFAULT_MAX_DATA_BYTES,
+            require_chain_id: true,
+            allow_create: true,
+            version_range: SUPPORTED_VERSIONS,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Violation {
+    TxTooLarge {
+        size: usize,
+        max: usize,
+    },
+    DataTooLarge {
+        size: usize,
+        max: usize,
+    },
+    ValueTooLong {
+        len: usize,
+    },
+    UnsupportedVersion {
+        version: u32,
+        range: VersionRange,
+    },
+    /// `to` is neither empty nor an address.
+    InvalidTo,
+    CreateNotAllowed,
+    MissingChainId,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Violation::TxTooLarge { size, max } => {
+                write!(f, "transaction is {} bytes, more than {}", size, max)
+            }
+            Violation::DataTooLarge { size, max } => {
+                write!(f, "data is {} bytes, more than {}", size, max)
+            }
+            Violation::ValueTooLong { len } => {
+                write!(f, "value is {} bytes, more than {}", len, MAX_VALUE_BYTES)
+            }
+            Violation::UnsupportedVersion { version, range } => write!(
+                f,
+                "version {} is not in {}..={}",
+                version, range.min, range.max
+            ),
+            Violation::InvalidTo => write!(f, "to is not an address"),
+            Violation::CreateNotAllowed => write!(f, "creating contracts is not allowed"),
+            Violation::MissingChainId => write!(f, "chain id is missing"),
+        }
+    }
+}
+
+/// Every limit a transaction breaks, never empty.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PolicyViolation {
+    violations: Vec<Violation>,
+}
+
+impl PolicyViolation {
+    pub fn violations(&self) -> &[Violation] {
+        &self.violations
+    }
+}
+
+impl fmt::Display for PolicyViolation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let violations: Vec<String> = self.violations.iter().map(ToString::to_string).collect();
+        write!(f, "{}", violations.join(", "))
+    }
+}
+
+impl ::std::error::Error for PolicyViolation {}
+
+impl TxPolicy {
+    pub fn check(&self, tx: &UnverifiedTransaction) -> Result<(), PolicyViolation> {
+        let mut violations = Vec::new();
+        let size = tx.compute_size() as usize;
+        if size > self.max_tx_bytes {
+            violations.push(Violation::TxTooLarge {
+                size,
+                max: self.max_tx_bytes,
+            });
+        }
+
+        let transaction = tx.get_transaction();
+        let data = transaction.get_data().len();
+        if data > self.max_data_bytes {
+            violations.push(Violation::DataTooLarge {
+                size: data,
+                max: self.max_data_bytes,
+            });
+        }
+        let value = transaction.get_value().len();
+        if value > MAX_VALUE_BYTES {
+            violations.push(Violation::ValueTooLong { len: value });
+        }
+
+        let version = transaction.get_version();
+        if !self.version_range.contains(version) || !SUPPORTED_VERSIONS.contains(version) {
+            violations.push(Violation::UnsupportedVersion {
+                version,
+                range: self.version_range,
+            });
+        } else {
+            // Where `to` and the chain id are depends on the version.
+            let (create, valid_to, chain_id) = if version == 0 {
+                let to = transaction.get_to();
+                (
+                    to.is_empty(),
+                    to.is_empty() || Address::from_hex_str(to).is_ok(),
+                    transaction.get_chain_id() != 0,
+                )
+            } else {
+                let to = transaction.get_to_v1();
+                let chain_id = transaction.get_chain_id_v1();
+                (
+                    to.is_empty(),
+                    to.is_empty() || to.len() == ADDRESS_BYTES,
+                    chain_id.iter().any(|b| *b != 0),
+                )
+            };
+            if !valid_to {
+                violations.push(Violation::InvalidTo);
+            }
+            if create && !self.allow_create {
+                violations.push(Violation::CreateNotAllowed);
+            }
+            if self.require_chain_id && !chain_id {
+                violations.push(Violation::MissingChainId);
+            }
+        }
+
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(PolicyViolation { violations })
+        }
+    }
+}
+
+/// Why a transaction of a batch was not accepted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TxRejection {
+    Policy(H256, PolicyViolation),
+    Signature(H256, String),
+}
+
+impl TxRejection {
+    /// Hash of the rejected `UnverifiedTransaction`.
+    pub fn tx_hash(&self) -> H256 {
+        match *self {
+            TxRejection::Policy(hash, _) | TxRejection::Signature(hash, _) => hash,
+        }
+    }
+}
+
+/// Check a batch against `policy`, then recover the senders of the ones
+/// passing it. The results are in the order of `txs`.
+pub fn verify_batch(
+    policy: &TxPolicy,
+    txs: Vec<UnverifiedTransaction>,
+) -> Vec<Result<SignedTransaction, TxRejection>> {
+    txs.into_iter()
+        .map(|tx| {
+            if let Err(violation) = policy.check(&tx) {
+                return Err(TxRejection::Policy(tx.crypt_hash(), violation));
+            }
+            let (public, tx_hash) = tx
+                .recover_public()
+                .map_err(|(hash, reason)| TxRejection::Signature(hash, reason))?;
+            let mut signed_tx = SignedTransaction::new();
+            signed_tx.set_signer(public.to_vec());
+            signed_tx.set_tx_hash(tx_hash.to_vec());
+            signed_tx.set_transaction_with_sig(tx);
+            Ok(signed_tx)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{verify_batch, TxPolicy, TxRejection, VersionRange, Violation};
+    use crate::crypto::{CreateKey, KeyPair};
+    use crate::{Transaction, UnverifiedTransaction};
+    use protobuf::Message as MessageTrait;
+    use serde_json;
+
+    fn tx(version: u32) -> Transaction {
+        let mut tx = Transaction::new();
+        tx.set_version(version);
+        tx.set_nonce("0".to_owned());
+        tx.set_quota(100);
+        tx.set_value(vec![1; 32]);
+        if version == 0 {
+            tx.set_to("0x0000000000000000000000000000000000001234".to_owned());
+            tx.set_chain_id(1);
+        } else {
+            tx.set_to_v1(vec![0x12; 20]);
+            tx.set_chain_id_v1(vec![1; 32]);
+        }
+        tx
+    }
+
+    fn unverified(tx: Transaction) -> UnverifiedTransaction {
+        tx.build_unverified(KeyPair::gen_keypair().privkey())
+    }
+
+    fn violations(policy: &TxPolicy, tx: &UnverifiedTransaction) -> Vec<Violation> {
+        match policy.check(tx) {
+            Ok(()) => Vec::new(),
+            Err(violation) => violation.violations().to_vec(),
+        }
+    }
+
+    #[test]
+    fn size_limits() {
+        let mut tx = tx(1);
+        tx.set_data(vec![0; 100]);
+        let uv = unverified(tx.clone());
+        let size = uv.compute_size() as usize;
+
+        let mut policy = TxPolicy::default();
+        policy.max_tx_bytes = size;
+        assert!(policy.check(&uv).is_ok());
+        policy.max_tx_bytes = size - 1;
+        assert_eq!(
+            violations(&policy, &uv),
+            vec![Violation::TxTooLarge {
+                size,
+                max: size - 1
+            }]
+        );
+
+        let mut policy = TxPolicy::default();
+        policy.max_data_bytes = 100;
+        assert!(policy.check(&uv).is_ok());
+        policy.max_data_bytes = 101;
+        assert!(policy.check(&uv).is_ok());
+        policy.max_data_bytes = 99;
+        assert_eq!(
+            violations(&policy, &uv),
+            vec![Violation::DataTooLarge { size: 100, max: 99 }]
+        );
+
+        let policy = TxPolicy::default();
+        tx.set_value(vec![1; 33]);
+        assert_eq!(
+            violations(&policy, &unverified(tx.clone())),
+            vec![Violation::ValueTooLong { len: 33 }]
+        );
+        tx.set_value(vec![1; 31]);
+        assert!(policy.check(&unverified(tx)).is_ok());
+    }
+
+    #[test]
+    fn version_range() {
+        let mut policy = TxPolicy::default();
+        policy.version_range = VersionRange { min: 1, max: 1 };
+        let range = policy.version_range;
+        assert_eq!(
+            violations(&policy, &unverified(tx(0))),
+            vec![Violation::UnsupportedVersion { version: 0, range }]
+        );
+        assert!(policy.check(&unverified(tx(1))).is_ok());
+        assert_eq!(
+            violations(&policy, &unverified(tx(2))),
+            vec![Violation::UnsupportedVersion { version: 2, range }]
+        );
+
+        // Not even a wide policy accepts what this build can't read.
+        policy.version_range = VersionRange { min: 0, max: 10 };
+        let mut tx3 = tx(1);
+        tx3.set_version(3);
+        assert_eq!(
+            violations(&policy, &unverified(tx3)).len(),
+            1,
+            "version 3 is unsupported"
+        );
+    }
+
+    #[test]
+    fn to_and_chain_id() {
+        let policy = TxPolicy::default();
+        for version in 0..3 {
+            assert!(policy.check(&unverified(tx(version))).is_ok());
+        }
+
+        let mut bad_to = tx(0);
+        bad_to.set_to("0x1234".to_owned());
+        assert_eq!(
+            violations(&policy, &unverified(bad_to)),
+            vec![Violation::InvalidTo]
+        );
+        let mut bad_to = tx(1);
+        bad_to.set_to_v1(vec![0x12; 21]);
+        assert_eq!(
+            violations(&policy, &unverified(bad_to)),
+            vec![Violation::InvalidTo]
+        );
+
+        let mut create = tx(1);
+        create.clear_to_v1();
+        assert!(policy.check(&unverified(create.clone())).is_ok());
+        let mut no_create = TxPolicy::default();
+        no_create.allow_create = false;
+        assert_eq!(
+            violations(&no_create, &unverified(create)),
+            vec![Violation::CreateNotAllowed]
+        );
+
+        let mut no_chain_id = tx(1);
+        no_chain_id.set_chain_id_v1(vec![0; 32]);
+        assert_eq!(
+            violations(&policy, &unverified(no_chain_id.clone())),
+            vec![Violation::MissingChainId]
+        );
+        let mut optional = TxPolicy::default();
+        optional.require_chain_id = false;
+        assert!(optional.check(&unverified(no_chain_id)).is_ok());
+    }
+
+    #[test]
+    fn every_violation_is_reported() {
+        let mut policy = TxPolicy::default();
+        policy.max_data_bytes = 10;
+        policy.allow_create = false;
+        let mut tx = tx(0);
+        tx.set_data(vec![0; 11]);
+        tx.set_value(vec![0; 40]);
+        tx.clear_to();
+        tx.set_chain_id(0);
+        let uv = unverified(tx);
+        assert_eq!(
+            violations(&policy, &uv),
+            vec![
+                Violation::DataTooLarge { size: 11, max: 10 },
+                Violation::ValueTooLong { len: 40 },
+                Violation::CreateNotAllowed,
+                Violation::MissingChainId,
+            ]
+        );
+        assert_eq!(
+            policy.check(&uv).unwrap_err().to_string(),
+            "data is 11 bytes, more than 10, value is 40 bytes, more than 32, \
+             creating contracts is not allowed, chain id is missing"
+        );
+    }
+
+    #[test]
+    fn batch_reports_policy_and_signature() {
+        let mut policy = TxPolicy::default();
+        policy.max_data_bytes = 10;
+        let good = unverified(tx(1));
+        let mut big = tx(1);
+        big.set_data(vec![0; 11]);
+        let big = unverified(big);
+        let mut bad_sig = unverified(tx(2));
+        bad_sig.set_signature(vec![0; 3]);
+
+        let results = verify_batch(&policy, vec![good.clone(), big.clone(), bad_sig.clone()]);
+        assert_eq!(results.len(), 3);
+        assert_eq!(
+            results[0].as_ref().unwrap().get_transaction_with_sig(),
+            &good
+        );
+        match results[1] {
+            Err(TxRejection::Policy(hash, ref violation)) => {
+                assert_eq!(hash, big.crypt_hash());
+                assert_eq!(
+                    violation.violations(),
+                    &[Violation::DataTooLarge { size: 11, max: 10 }]
+                );
+            }
+            ref other => panic!("unexpected {:?}", other),
+        }
+        match results[2] {
+            Err(ref rejection @ TxRejection::Signature(..)) => {
+                assert_eq!(rejection.tx_hash(), bad_sig.crypt_hash())
+            }
+            ref other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn load_from_system_config() {
+        let policy: TxPolicy = serde_json::from_value(json!({
+            "maxTxBytes": 2048,
+            "allowCreate": false,
+            "versionRange": { "min": 1, "max": 2 },
+        }))
+        .unwrap();
+        assert_eq!(
+            policy,
+            TxPolicy {
+                max_tx_bytes: 2048,
+                allow_create: false,
+                version_range: VersionRange { min: 1, max: 2 },
+                ..TxPolicy::default()
+            }
+        );
+        let value = serde_json::to_value(&policy).unwrap();
+        assert_eq!(serde_json::from_value::<TxPolicy>(value).unwrap(), policy);
+    }
+}
@@ -0,0 +1,337 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Receipt errors as a stable numeric code plus a canonical message.
+//!
+//! The table is frozen: codes are never renumbered or reused, and the
+//! messages never change. New errors get the next free code.
+//!
+//! | code | error                        | message                                                     |
+//! |------|------------------------------|-------------------------------------------------------------|
+//! | 1    | `NoTransactionPermission`    | No transaction permission.                                  |
+//! | 2    | `NotEnoughBaseQuota`         | Not enough base quota.                                      |
+//! | 3    | `BlockQuotaLimitReached`     | Block quota limit reached.                                  |
+//! | 4    | `AccountQuotaLimitReached`   | Account quota limit reached.                                |
+//! | 5    | `InvalidTransactionNonce`    | Invalid transaction nonce.                                  |
+//! | 6    | `NotEnoughCash`              | Not enough cash.                                            |
+//! | 7    | `NoContractPermission`       | No contract permission.                                     |
+//! | 8    | `NoCallPermission`           | No Call contract permission.                                |
+//! | 9    | `ExecutionInternal`          | Execution internal error.                                   |
+//! | 10   | `TransactionMalformed`       | Malformed transaction.                                      |
+//! | 11   | `OutOfQuota`                 | Out of quota.                                               |
+//! | 12   | `BadJumpDestination`         | Jump position wasn't marked with JUMPDEST instruction.      |
+//! | 13   | `BadInstruction`             | Instruction is not supported.                               |
+//! | 14   | `StackUnderflow`             | Not enough stack elements to execute instruction.           |
+//! | 15   | `OutOfStack`                 | Execution would exceed defined Stack Limit.                 |
+//! | 16   | `Internal`                   | EVM internal error.                                         |
+//! | 17   | `MutableCallInStaticContext` | Mutable call in static context.                             |
+//! | 18   | `OutOfBounds`                | Out of bounds.                                              |
+//! | 19   | `Reverted`                   | Reverted.                                                   |
+//!
+//! Every other code decodes to `Reserved`, so receipts written by a newer
+//! node still decode.
+
+use crate::protos::ReceiptError;
+use rlp::{Decodable, DecoderError, Encodable, RlpStream, UntrustedRlp};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ReceiptErrorCode {
+    NoTransactionPermission,
+    NotEnoughBaseQuota,
+    BlockQuotaLimitReached,
+    AccountQuotaLimitReached,
+    InvalidTransactionNonce,
+    NotEnoughCash,
+    NoContractPermission,
+    NoCallPermission,
+    ExecutionInternal,
+    TransactionMalformed,
+    OutOfQuota,
+    BadJumpDestination,
+    BadInstruction,
+    StackUnderflow,
+    OutOfStack,
+    Internal,
+    MutableCallInStaticContext,
+    OutOfBounds,
+    Reverted,
+    /// A code this build doesn't know.
+    Reserved(u8),
+}
+
+/// Every known error, in code order.
+pub const KNOWN_ERRORS: [ReceiptErrorCode; 19] = [
+    ReceiptErrorCode::NoTransactionPermission,
+    ReceiptErrorCode::NotEnoughBaseQuota,
+    ReceiptErrorCode::BlockQuotaLimitReached,
+    ReceiptErrorCode::AccountQuotaLimitReached,
+    ReceiptErrorCode::InvalidTransactionNonce,
+    ReceiptErrorCode::NotEnoughCash,
+    ReceiptErrorCode::NoContractPermission,
+    ReceiptErrorCode::NoCallPermission,
+    ReceiptErrorCode::ExecutionInternal,
+    ReceiptErrorCode::TransactionMalformed,
+    ReceiptErrorCode::OutOfQuota,
+    ReceiptErrorCode::BadJumpDestination,
+    ReceiptErrorCode::BadInstruction,
+    ReceiptErrorCode::StackUnderflow,
+    ReceiptErrorCode::OutOfStack,
+    ReceiptErrorCode::Internal,
+    ReceiptErrorCode::MutableCallInStaticContext,
+    ReceiptErrorCode::OutOfBounds,
+    ReceiptErrorCode::Reverted,
+];
+
+impl ReceiptErrorCode {
+    pub fn code(self) -> u8 {
+        match self {
+            ReceiptErrorCode::NoTransactionPermission => 1,
+            ReceiptErrorCode::NotEnoughBaseQuota => 2,
+            ReceiptErrorCode::BlockQuotaLimitReached => 3,
+            ReceiptErrorCode::AccountQuotaLimitReached => 4,
+            ReceiptErrorCode::InvalidTransactionNonce => 5,
+            ReceiptErrorCode::NotEnoughCash => 6,
+            ReceiptErrorCode::NoContractPermission => 7,
+            ReceiptErrorCode::NoCallPermission => 8,
+            ReceiptErrorCode::ExecutionInternal => 9,
+            ReceiptErrorCode::TransactionMalformed => 10,
+            ReceiptErrorCode::OutOfQuota => 11,
+            ReceiptErrorCode::BadJumpDestination => 12,
+            ReceiptErrorCode::BadInstruction => 13,
+            ReceiptErrorCode::StackUnderflow => 14,
+            ReceiptErrorCode::OutOfStack => 15,
+            ReceiptErrorCode::Internal => 16,
+            ReceiptErrorCode::MutableCallInStaticContext => 17,
+            ReceiptErrorCode::OutOfBounds => 18,
+            ReceiptErrorCode::Reverted => 19,
+            ReceiptErrorCode::Reserved(code) => code,
+        }
+    }
+
+    pub fn from_code(code: u8) -> Self {
+        KNOWN_ERRORS
+            .iter()
+            .cloned()
+            .find(|error| error.code() == code)
+            .unwrap_or(ReceiptErrorCode::Reserved(code))
+    }
+
+    /// The canonical message, also what older nodes stored in receipts.
+    pub fn message(self) -> &'static str {
+        match self {
+            ReceiptErrorCode::NoTransactionPermission => "No transaction permission.",
+            ReceiptErrorCode::NotEnoughBaseQuota => "Not enough base quota.",
+            ReceiptErrorCode::BlockQuotaLimitReached => "Block quota limit reached.",
+            ReceiptErrorCode::AccountQuotaLimitReached => "Account quota limit reached.",
+            ReceiptErrorCode::InvalidTransactionNonce => "Invalid transaction nonce.",
+            ReceiptErrorCode::NotEnoughCash => "Not enough cash.",
+            ReceiptErrorCode::NoContractPermission => "No contract permission.",
+            ReceiptErrorCode::NoCallPermission => "No Call contract permission.",
+            ReceiptErrorCode::ExecutionInternal => "Execution internal error.",
+            ReceiptErrorCode::TransactionMalformed => "Malformed transaction.",
+            ReceiptErrorCode::OutOfQuota => "Out of quota.",
+            ReceiptErrorCode::BadJumpDestination => {
+                "Jump position wasn't marked with JUMPDEST instruction."
+            }
+            ReceiptErrorCode::BadInstruction => "Instruction is not supported.",
+            ReceiptErrorCode::StackUnderflow => "Not enough stack elements to execute instruction.",
+            ReceiptErrorCode::OutOfStack => "Execution would exceed defined Stack Limit.",
+            ReceiptErrorCode::Internal => "EVM internal error.",
+            ReceiptErrorCode::MutableCallInStaticContext => "Mutable call in static context.",
+            ReceiptErrorCode::OutOfBounds => "Out of bounds.",
+            ReceiptErrorCode::Reverted => "Reverted.",
+            ReceiptErrorCode::Reserved(_) => "Reserved error.",
+        }
+    }
+
+    /// The error an old string based receipt carries.
+    pub fn from_message(message: &str) -> Option<Self> {
+        KNOWN_ERRORS
+            .iter()
+            .cloned()
+            .find(|error| error.message() == message)
+    }
+}
+
+impl fmt::Display for ReceiptErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message())
+    }
+}
+
+impl Encodable for ReceiptErrorCode {
+    fn rlp_append(&self, s: &mut RlpStream) {
+        s.append(&self.code());
+    }
+}
+
+impl Decodable for ReceiptErrorCode {
+    /// Takes the code, or the message older nodes wrote instead. Messages
+    /// are never shorter than two bytes, so the two can't be confused.
+    fn decode(rlp: &UntrustedRlp) -> Result<Self, DecoderError> {
+        let bytes = rlp.data()?;
+        if bytes.len() <= 1 {
+            let code: u8 = rlp.as_val()?;
+            return Ok(ReceiptErrorCode::from_code(code));
+        }
+        ::std::str::from_utf8(bytes)
+            .ok()
+            .and_then(ReceiptErrorCode::from_message)
+            .ok_or(DecoderError::Custom("Unknown receipt error message"))
+    }
+}
+
+impl From<ReceiptError> for ReceiptErrorCode {
+    fn from(error: ReceiptError) -> Self {
+        match error {
+            ReceiptError::NotEnoughBaseQuota => ReceiptErrorCode::NotEnoughBaseQuota,
+            ReceiptError::BlockQuotaLimitReached => ReceiptErrorCode::BlockQuotaLimitReached,
+            ReceiptError::AccountQuotaLimitReached => ReceiptErrorCode::AccountQuotaLimitReached,
+            ReceiptError::InvalidTransactionNonce => ReceiptErrorCode::InvalidTransactionNonce,
+            ReceiptError::NotEnoughCash => ReceiptErrorCode::NotEnoughCash,
+            ReceiptError::NoTransactionPermission => ReceiptErrorCode::NoTransactionPermission,
+            ReceiptError::NoContractPermission => ReceiptErrorCode::NoContractPermission,
+            ReceiptError::NoCallPermission => ReceiptErrorCode::NoCallPermission,
+            ReceiptError::ExecutionInternal => ReceiptErrorCode::ExecutionInternal,
+            ReceiptError::TransactionMalformed => ReceiptErrorCode::TransactionMalformed,
+            ReceiptError::OutOfQuota => ReceiptErrorCode::OutOfQuota,
+            ReceiptError::BadJumpDestination => ReceiptErrorCode::BadJumpDestination,
+            ReceiptError::BadInstruction => ReceiptErrorCode::BadInstruction,
+            ReceiptError::StackUnderflow => ReceiptErrorCode::StackUnderflow,
+            ReceiptError::OutOfStack => ReceiptErrorCode::OutOfStack,
+            ReceiptError::Internal => ReceiptErrorCode::Internal,
+            ReceiptError::MutableCallInStaticContext => {
+                ReceiptErrorCode::MutableCallInStaticContext
+            }
+            ReceiptError::OutOfBounds => ReceiptErrorCode::OutOfBounds,
+            ReceiptError::Reverted => ReceiptErrorCode::Reverted,
+        }
+    }
+}
+
+impl ReceiptErrorCode {
+    /// The protobuf enum, which has no counterpart for `Reserved`.
+    pub fn to_proto(self) -> Option<ReceiptError> {
+        let error = match self {
+            ReceiptErrorCode::NotEnoughBaseQuota => ReceiptError::NotEnoughBaseQuota,
+            ReceiptErrorCode::BlockQuotaLimitReached => ReceiptError::BlockQuotaLimitReached,
+            ReceiptErrorCode::AccountQuotaLimitReached => ReceiptError::AccountQuotaLimitReached,
+            ReceiptErrorCode::InvalidTransactionNonce => ReceiptError::InvalidTransactionNonce,
+            ReceiptErrorCode::NotEnoughCash => ReceiptError::NotEnoughCash,
+            ReceiptErrorCode::NoTransactionPermission => ReceiptError::NoTransactionPermission,
+            ReceiptErrorCode::NoContractPermission => ReceiptError::NoContractPermission,
+            ReceiptErrorCode::NoCallPermission => ReceiptError::NoCallPermission,
+            ReceiptErrorCode::ExecutionInternal => ReceiptError::ExecutionInternal,
+            ReceiptErrorCode::TransactionMalformed => ReceiptError::TransactionMalformed,
+            ReceiptErrorCode::OutOfQuota => ReceiptError::OutOfQuota,
+            ReceiptErrorCode::BadJumpDestination => ReceiptError::BadJumpDestination,
+            ReceiptErrorCode::BadInstruction => ReceiptError::BadInstruction,
+            ReceiptErrorCode::StackUnderflow => ReceiptError::StackUnderflow,
+            ReceiptErrorCode::OutOfStack => ReceiptError::OutOfStack,
+            ReceiptErrorCode::Internal => ReceiptError::Internal,
+            ReceiptErrorCode::MutableCallInStaticContext => {
+                ReceiptError::MutableCallInStaticContext
+            }
+            ReceiptErrorCode::OutOfBounds => ReceiptError::OutOfBounds,
+            ReceiptErrorCode::Reverted => ReceiptError::Reverted,
+            ReceiptErrorCode::Reserved(_) => return None,
+        };
+        Some(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ReceiptErrorCode, KNOWN_ERRORS};
+    use crate::protos::ReceiptError;
+    use protobuf::ProtobufEnum;
+    use rlp::{self, DecoderError, UntrustedRlp};
+
+    #[test]
+    fn code_table() {
+        // Frozen, see the module docs.
+        let table = [
+            (1, "No transaction permission."),
+            (2, "Not enough base quota."),
+            (3, "Block quota limit reached."),
+            (4, "Account quota limit reached."),
+            (5, "Invalid transaction nonce."),
+            (6, "Not enough cash."),
+            (7, "No contract permission."),
+            (8, "No Call contract permission."),
+            (9, "Execution internal error."),
+            (10, "Malformed transaction."),
+            (11, "Out of quota."),
+            (12, "Jump position wasn't marked with JUMPDEST instruction."),
+            (13, "Instruction is not supported."),
+            (14, "Not enough stack elements to execute instruction."),
+            (15, "Execution would exceed defined Stack Limit."),
+            (16, "EVM internal error."),
+            (17, "Mutable call in static context."),
+            (18, "Out of bounds."),
+            (19, "Reverted."),
+        ];
+        assert_eq!(KNOWN_ERRORS.len(), table.len());
+        for (error, (code, message)) in KNOWN_ERRORS.iter().zip(table.iter()) {
+            assert_eq!(error.code(), *code);
+            assert_eq!(&error.to_string(), message);
+            assert_eq!(ReceiptErrorCode::from_code(*code), *error);
+            assert_eq!(ReceiptErrorCode::from_message(message), Some(*error));
+        }
+        assert_eq!(
+            ReceiptErrorCode::from_code(0),
+            ReceiptErrorCode::Reserved(0)
+        );
+        assert_eq!(
+            ReceiptErrorCode::from_code(200),
+            ReceiptErrorCode::Reserved(200)
+        );
+        assert_eq!(ReceiptErrorCode::Reserved(200).code(), 200);
+
+        for value in 0..19 {
+            let proto = ReceiptError::from_i32(value).unwrap();
+            assert_eq!(ReceiptErrorCode::from(proto).to_proto(), Some(proto));
+        }
+        assert_eq!(ReceiptErrorCode::Reserved(42).to_proto(), None);
+    }
+
+    #[test]
+    fn rlp_codes() {
+        for error in KNOWN_ERRORS.iter() {
+            let encoded = rlp::encode(error);
+            assert_eq!(&*encoded, &[error.code()]);
+            assert_eq!(rlp::decode::<ReceiptErrorCode>(&encoded), *error);
+        }
+        let encoded = rlp::encode(&ReceiptErrorCode::Reserved(99));
+        assert_eq!(
+            rlp::decode::<ReceiptErrorCode>(&encoded),
+            ReceiptErrorCode::Reserved(99)
+        );
+    }
+
+    #[test]
+    fn legacy_string_receipts() {
+        // Older nodes stored the message itself.
+        for error in KNOWN_ERRORS.iter() {
+            let legacy = rlp::encode(&error.message().to_owned());
+            assert_eq!(rlp::decode::<ReceiptErrorCode>(&legacy), *error);
+        }
+        let legacy = rlp::encode(&"Something else.".to_owned());
+        assert_eq!(
+            UntrustedRlp::new(&legacy).as_val::<ReceiptErrorCode>(),
+            Err(DecoderError::Custom("Unknown receipt error message"))
+        );
+    }
+}
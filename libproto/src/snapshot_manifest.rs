@@ -0,0 +1,648 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Fetching the state of a snapshot to bootstrap a full node.
+//!
+//! A node serving a snapshot sends its `SnapshotManifest`: the block it was
+//! taken at, the state root and the hash of every chunk. The authorities at
+//! that height sign the manifest, and a `ManifestVerifier` accepts it once
+//! more than two thirds of them did, like `BftProof::check` a block. Only
+//! then are chunks requested, and each `SnapshotChunkResp` is checked on its
+//! own against the hash of its index in the verified manifest.
+//!
+//! To be sent between nodes, the messages have to be in cita-proto
+//! first, as
+//!
+//! ```text
+//! message SnapshotCommit {
+//!     bytes address = 1;
+//!     bytes signature = 2;
+//! }
+//!
+//! message SnapshotManifest {
+//!     uint64 height = 1;
+//!     bytes block_hash = 2;
+//!     bytes state_root = 3;
+//!     repeated bytes chunk_hashes = 4;
+//!     uint64 chunk_count = 5;
+//!     uint32 format_version = 6;
+//!     repeated SnapshotCommit commits = 7;
+//! }
+//!
+//! message SnapshotChunkReq {
+//!     uint64 height = 1;
+//!     uint64 index = 2;
+//! }
+//!
+//! message SnapshotChunkResp {
+//!     uint64 height = 1;
+//!     uint64 index = 2;
+//!     bytes chunk = 3;
+//! }
+//! ```
+//!
+//! routed as `snapshot.snapshot_manifest`, `synchronizer.snapshot_chunk_req`
+//! and `snapshot.snapshot_chunk_resp`.
+
+use std::collections::HashSet;
+use std::fmt;
+
+use protobuf::{CodedOutputStream, ProtobufResult};
+use rustc_serialize::hex::ToHex;
+
+use crate::crypto::{pubkey_to_address, CreateKey, KeyPair, Sign, Signature, SIGNATURE_BYTES_LEN};
+use crate::types::{Address, H256};
+use hashable::Hashable;
+
+/// An authority signing a manifest.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SnapshotCommit {
+    address: Vec<u8>,
+    signature: Vec<u8>,
+}
+
+impl SnapshotCommit {
+    pub fn new() -> Self {
+        SnapshotCommit::default()
+    }
+
+    pub fn get_address(&self) -> &[u8] {
+        &self.address
+    }
+
+    pub fn set_address(&mut self, v: Vec<u8>) {
+        self.address = v;
+    }
+
+    pub fn get_signature(&self) -> &[u8] {
+        &self.signature
+    }
+
+    pub fn set_signature(&mut self, v: Vec<u8>) {
+        self.signature = v;
+    }
+}
+
+/// The state of the chain at `height`, in `chunk_count` chunks.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SnapshotManifest {
+    height: u64,
+    block_hash: Vec<u8>,
+    state_root: Vec<u8>,
+    chunk_hashes: Vec<Vec<u8>>,
+    chunk_count: u64,
+    format_version: u32,
+    commits: Vec<SnapshotCommit>,
+}
+
+impl SnapshotManifest {
+    pub fn new() -> Self {
+        SnapshotManifest::default()
+    }
+
+    pub fn get_height(&self) -> u64 {
+        self.height
+    }
+
+    pub fn set_height(&mut self, v: u64) {
+        self.height = v;
+    }
+
+    pub fn get_block_hash(&self) -> &[u8] {
+        &self.block_hash
+    }
+
+    pub fn set_block_hash(&mut self, v: Vec<u8>) {
+        self.block_hash = v;
+    }
+
+    pub fn get_state_root(&self) -> &[u8] {
+        &self.state_root
+    }
+
+    pub fn set_state_root(&mut self, v: Vec<u8>) {
+        self.state_root = v;
+    }
+
+    /// The hash of each chunk, by index.
+    pub fn get_chunk_hashes(&self) -> &[Vec<u8>] {
+        &self.chunk_hashes
+    }
+
+    pub fn set_chunk_hashes(&mut self, v: Vec<Vec<u8>>) {
+        self.chunk_hashes = v;
+    }
+
+    pub fn get_chunk_count(&self) -> u64 {
+        self.chunk_count
+    }
+
+    pub fn set_chunk_count(&mut self, v: u64) {
+        self.chunk_count = v;
+    }
+
+    /// How the state is laid out in the chunks.
+    pub fn get_format_version(&self) -> u32 {
+        self.format_version
+    }
+
+    pub fn set_format_version(&mut self, v: u32) {
+        self.format_version = v;
+    }
+
+    pub fn get_commits(&self) -> &[SnapshotCommit] {
+        &self.commits
+    }
+
+    pub fn set_commits(&mut self, v: Vec<SnapshotCommit>) {
+        self.commits = v;
+    }
+
+    /// What the authorities sign: the hash of every field but the commits.
+    pub fn signing_hash(&self) -> H256 {
+        self.signing_bytes().crypt_hash()
+    }
+
+    /// The canonical form of every field but the commits, which
+    /// `canonical::canonical_bytes(manifest, &[7])` gives once the message
+    /// is generated, so signatures stay valid.
+    fn signing_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        self.write_signed_fields(&mut bytes)
+            .expect("writing to a vec never fails");
+        bytes
+    }
+
+    fn write_signed_fields(&self, bytes: &mut Vec<u8>) -> ProtobufResult<()> {
+        let mut os = CodedOutputStream::vec(bytes);
+        if self.height != 0 {
+            os.write_uint64(1, self.height)?;
+        }
+        if !self.block_hash.is_empty() {
+            os.write_bytes(2, &self.block_hash)?;
+        }
+        if !self.state_root.is_empty() {
+            os.write_bytes(3, &self.state_root)?;
+        }
+        // Repeated fields keep their empty elements.
+        for hash in &self.chunk_hashes {
+            os.write_bytes(4, hash)?;
+        }
+        if self.chunk_count != 0 {
+            os.write_uint64(5, self.chunk_count)?;
+        }
+        if self.format_version != 0 {
+            os.write_uint32(6, self.format_version)?;
+        }
+        os.flush()
+    }
+
+    /// Add the commit of `keypair`, after every other field is set.
+    pub fn sign(&mut self, keypair: &KeyPair) {
+        let signature = Signature::sign(keypair.privkey(), &self.signing_hash())
+            .expect("the key of a keypair is valid");
+        let mut commit = SnapshotCommit::new();
+        commit.set_address(keypair.address().to_vec());
+        commit.set_signature(signature.to_vec());
+        self.commits.push(commit);
+    }
+}
+
+/// Asks for chunk `index` of the snapshot at `height`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SnapshotChunkReq {
+    height: u64,
+    index: u64,
+}
+
+impl SnapshotChunkReq {
+    pub fn new() -> Self {
+        SnapshotChunkReq::default()
+    }
+
+    pub fn get_height(&self) -> u64 {
+        self.height
+    }
+
+    pub fn set_height(&mut self, v: u64) {
+        self.height = v;
+    }
+
+    pub fn get_index(&self) -> u64 {
+        self.index
+    }
+
+    pub fn set_index(&mut self, v: u64) {
+        self.index = v;
+    }
+}
+
+/// Chunk `index` of the snapshot at `height`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SnapshotChunkResp {
+    height: u64,
+    index: u64,
+    chunk: Vec<u8>,
+}
+
+impl SnapshotChunkResp {
+    pub fn new() -> Self {
+        SnapshotChunkResp::default()
+    }
+
+    pub fn get_height(&self) -> u64 {
+        self.height
+    }
+
+    pub fn set_height(&mut self, v: u64) {
+        self.height = v;
+    }
+
+    pub fn get_index(&self) -> u64 {
+        self.index
+    }
+
+    pub fn set_index(&mut self, v: u64) {
+        self.index = v;
+    }
+
+    pub fn get_chunk(&self) -> &[u8] {
+        &self.chunk
+    }
+
+    pub fn set_chunk(&mut self, v: Vec<u8>) {
+        self.chunk = v;
+    }
+
+    pub fn take_chunk(&mut self) -> Vec<u8> {
+        ::std::mem::replace(&mut self.chunk, Vec::new())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SnapshotError {
+    /// `chunk_count` isn't the number of chunk hashes.
+    ChunkCountMismatch {
+        count: u64,
+        hashes: usize,
+    },
+    /// The hash of chunk `index` isn't 32 bytes.
+    InvalidChunkHash {
+        index: usize,
+    },
+    /// A commit whose signature doesn't recover to its address.
+    InvalidSignature {
+        address: Vec<u8>,
+    },
+    /// A commit of an address not in the authorities.
+    NotAuthority {
+        address: Address,
+    },
+    /// Too few authorities signed.
+    NotEnoughSigners {
+        signers: usize,
+        authorities: usize,
+    },
+    /// A chunk of the snapshot at another height.
+    WrongHeight {
+        expected: u64,
+        got: u64,
+    },
+    ChunkOutOfRange {
+        index: u64,
+        count: u64,
+    },
+    ChunkHashMismatch {
+        index: u64,
+    },
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SnapshotError::ChunkCountMismatch { count, hashes } => write!(
+                f,
+                "manifest has {} chunks but {} chunk hashes",
+                count, hashes
+            ),
+            SnapshotError::InvalidChunkHash { index } => {
+                write!(f, "hash of chunk {} is not 32 bytes", index)
+            }
+            SnapshotError::InvalidSignature { ref address } => {
+                write!(f, "invalid signature of 0x{}", address.to_hex())
+            }
+            SnapshotError::NotAuthority { ref address } => {
+                write!(f, "signer 0x{} is not an authority", address.to_hex())
+            }
+            SnapshotError::NotEnoughSigners {
+                signers,
+                authorities,
+            } => write!(
+                f,
+                "{} of {} authorities signed, more than two thirds must",
+                signers, authorities
+            ),
+            SnapshotError::WrongHeight { expected, got } => {
+                write!(f, "chunk of the snapshot at {}, not at {}", got, expected)
+            }
+            SnapshotError::ChunkOutOfRange { index, count } => {
+                write!(f, "chunk {} of a snapshot with {} chunks", index, count)
+            }
+            SnapshotError::ChunkHashMismatch { index } => {
+                write!(f, "chunk {} doesn't match its hash", index)
+            }
+        }
+    }
+}
+
+impl ::std::error::Error for SnapshotError {}
+
+/// Checks manifests against the authorities at their height.
+#[derive(Debug, Clone)]
+pub struct ManifestVerifier {
+    authorities: Vec<Address>,
+}
+
+impl ManifestVerifier {
+    pub fn new(authorities: Vec<Address>) -> Self {
+        ManifestVerifier { authorities }
+    }
+
+    /// Every commit must be a valid signature of an authority, and more
+    /// than two thirds of the authorities must have signed. An authority
+    /// signing twice counts once.
+    pub fn verify(&self, manifest: SnapshotManifest) -> Result<VerifiedManifest, SnapshotError> {
+        if manifest.chunk_count != manifest.chunk_hashes.len() as u64 {
+            return Err(SnapshotError::ChunkCountMismatch {
+                count: manifest.chunk_count,
+                hashes: manifest.chunk_hashes.len(),
+            });
+        }
+        if let Some(index) = manifest.chunk_hashes.iter().position(|h| h.len() != 32) {
+            return Err(SnapshotError::InvalidChunkHash { index });
+        }
+
+        let hash = manifest.signing_hash();
+        let mut signers = HashSet::new();
+        for commit in &manifest.commits {
+            let invalid = || SnapshotError::InvalidSignature {
+                address: commit.address.clone(),
+            };
+            if commit.signature.len() != SIGNATURE_BYTES_LEN {
+                return Err(invalid());
+            }
+            let pubkey = Signature::from(commit.get_signature())
+                .recover(&hash)
+                .map_err(|_| invalid())?;
+            let address = pubkey_to_address(&pubkey);
+            if address[..] != commit.address[..] {
+                return Err(invalid());
+            }
+            if !self.authorities.contains(&address) {
+                return Err(SnapshotError::NotAuthority { address });
+            }
+            signers.insert(address);
+        }
+        if 2 * self.authorities.len() >= 3 * signers.len() {
+            return Err(SnapshotError::NotEnoughSigners {
+                signers: signers.len(),
+                authorities: self.authorities.len(),
+            });
+        }
+        Ok(VerifiedManifest { manifest })
+    }
+}
+
+/// A manifest enough authorities signed, to check chunks against.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerifiedManifest {
+    manifest: SnapshotManifest,
+}
+
+impl VerifiedManifest {
+    pub fn manifest(&self) -> &SnapshotManifest {
+        &self.manifest
+    }
+
+    /// Whether `resp` is the chunk the manifest lists at its index.
+    pub fn verify_chunk(&self, resp: &SnapshotChunkResp) -> Result<(), SnapshotError> {
+        if resp.height != self.manifest.height {
+            return Err(SnapshotError::WrongHeight {
+                expected: self.manifest.height,
+                got: resp.height,
+            });
+        }
+        if resp.index >= self.manifest.chunk_count {
+            return Err(SnapshotError::ChunkOutOfRange {
+                index: resp.index,
+                count: self.manifest.chunk_count,
+            });
+        }
+        let expected = &self.manifest.chunk_hashes[resp.index as usize];
+        if resp.chunk.crypt_hash()[..] != expected[..] {
+            return Err(SnapshotError::ChunkHashMismatch { index: resp.index });
+        }
+        Ok(())
+    }
+
+    /// A request for every chunk of the snapshot.
+    pub fn chunk_requests(&self) -> Vec<SnapshotChunkReq> {
+        (0..self.manifest.chunk_count)
+            .map(|index| {
+                let mut req = SnapshotChunkReq::new();
+                req.set_height(self.manifest.height);
+                req.set_index(index);
+                req
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ManifestVerifier, SnapshotChunkResp, SnapshotError, SnapshotManifest};
+    use crate::crypto::{CreateKey, KeyPair};
+    use crate::types::H256;
+    use hashable::Hashable;
+
+    fn chunks() -> Vec<Vec<u8>> {
+        (0..3u8).map(|i| vec![i; 100 + usize::from(i)]).collect()
+    }
+
+    fn manifest() -> SnapshotManifest {
+        let chunks = chunks();
+        let mut manifest = SnapshotManifest::new();
+        manifest.set_height(1000);
+        manifest.set_block_hash(H256::from(0xb10c).to_vec());
+        manifest.set_state_root(H256::from(0x5747).to_vec());
+        manifest.set_chunk_count(chunks.len() as u64);
+        manifest.set_chunk_hashes(chunks.iter().map(|c| c.crypt_hash().to_vec()).collect());
+        manifest.set_format_version(1);
+        manifest
+    }
+
+    fn chunk(height: u64, index: u64, chunk: Vec<u8>) -> SnapshotChunkResp {
+        let mut resp = SnapshotChunkResp::new();
+        resp.set_height(height);
+        resp.set_index(index);
+        resp.set_chunk(chunk);
+        resp
+    }
+
+    fn authorities(n: usize) -> (Vec<KeyPair>, ManifestVerifier) {
+        let keypairs: Vec<KeyPair> = (0..n).map(|_| KeyPair::gen_keypair()).collect();
+        let addresses = keypairs.iter().map(KeyPair::address).collect();
+        (keypairs, ManifestVerifier::new(addresses))
+    }
+
+    #[test]
+    fn signing_hash_leaves_out_the_commits() {
+        let (keypairs, _) = authorities(2);
+        let mut small = SnapshotManifest::new();
+        assert!(small.signing_bytes().is_empty());
+        small.set_height(1);
+        small.set_format_version(1);
+        // An empty hash is still an element.
+        small.set_chunk_hashes(vec![Vec::new()]);
+        assert_eq!(
+            small.signing_bytes(),
+            vec![0x08, 0x01, 0x22, 0x00, 0x30, 0x01]
+        );
+
+        let mut manifest = manifest();
+        let unsigned = manifest.signing_hash();
+        keypairs.iter().for_each(|keypair| manifest.sign(keypair));
+        assert_eq!(manifest.get_commits().len(), 2);
+        assert_eq!(manifest.signing_hash(), unsigned);
+        manifest.set_chunk_count(4);
+        assert_ne!(manifest.signing_hash(), unsigned);
+    }
+
+    #[test]
+    fn signature_threshold() {
+        let (keypairs, verifier) = authorities(4);
+        let mut manifest = manifest();
+        manifest.sign(&keypairs[0]);
+        manifest.sign(&keypairs[1]);
+        assert_eq!(
+            verifier.verify(manifest.clone()),
+            Err(SnapshotError::NotEnoughSigners {
+                signers: 2,
+                authorities: 4,
+            })
+        );
+
+        // Signing twice doesn't count twice.
+        let mut twice = manifest.clone();
+        twice.sign(&keypairs[1]);
+        assert!(verifier.verify(twice).is_err());
+
+        manifest.sign(&keypairs[2]);
+        let verified = verifier.verify(manifest.clone()).unwrap();
+        assert_eq!(verified.manifest(), &manifest);
+
+        // A signer outside of the authorities.
+        let mut outsider = manifest.clone();
+        let stranger = KeyPair::gen_keypair();
+        outsider.sign(&stranger);
+        assert_eq!(
+            verifier.verify(outsider),
+            Err(SnapshotError::NotAuthority {
+                address: stranger.address(),
+            })
+        );
+
+        // Changed after it was signed.
+        let mut tampered = manifest.clone();
+        tampered.set_state_root(H256::from(1).to_vec());
+        match verifier.verify(tampered) {
+            Err(SnapshotError::InvalidSignature { .. }) => {}
+            other => panic!("{:?}", other),
+        }
+
+        // A commit claiming another address.
+        let mut forged = manifest.clone();
+        let mut commits = forged.get_commits().to_vec();
+        commits[0].set_address(keypairs[3].address().to_vec());
+        forged.set_commits(commits);
+        assert!(verifier.verify(forged).is_err());
+
+        assert!(ManifestVerifier::new(Vec::new()).verify(manifest).is_err());
+    }
+
+    #[test]
+    fn chunks_are_checked_against_the_manifest() {
+        let (keypairs, verifier) = authorities(1);
+        let mut manifest = manifest();
+        manifest.sign(&keypairs[0]);
+        let verified = verifier.verify(manifest).unwrap();
+
+        let requests = verified.chunk_requests();
+        assert_eq!(requests.len(), 3);
+        for (req, data) in requests.iter().zip(chunks()) {
+            assert_eq!(req.get_height(), 1000);
+            let resp = chunk(req.get_height(), req.get_index(), data);
+            assert_eq!(verified.verify_chunk(&resp), Ok(()));
+        }
+
+        let mut corrupt = chunks()[1].clone();
+        corrupt[0] ^= 1;
+        assert_eq!(
+            verified.verify_chunk(&chunk(1000, 1, corrupt)),
+            Err(SnapshotError::ChunkHashMismatch { index: 1 })
+        );
+        assert_eq!(
+            verified.verify_chunk(&chunk(1000, 0, chunks()[1].clone())),
+            Err(SnapshotError::ChunkHashMismatch { index: 0 })
+        );
+        assert_eq!(
+            verified.verify_chunk(&chunk(1000, 3, Vec::new())),
+            Err(SnapshotError::ChunkOutOfRange { index: 3, count: 3 })
+        );
+        assert_eq!(
+            verified.verify_chunk(&chunk(999, 0, chunks()[0].clone())),
+            Err(SnapshotError::WrongHeight {
+                expected: 1000,
+                got: 999,
+            })
+        );
+    }
+
+    #[test]
+    fn malformed_manifests() {
+        let (keypairs, verifier) = authorities(1);
+        let mut miscounted = manifest();
+        miscounted.set_chunk_count(4);
+        miscounted.sign(&keypairs[0]);
+        assert_eq!(
+            verifier.verify(miscounted),
+            Err(SnapshotError::ChunkCountMismatch {
+                count: 4,
+                hashes: 3,
+            })
+        );
+
+        let mut short_hash = manifest();
+        short_hash.set_chunk_hashes(vec![vec![0; 32], vec![0; 20], vec![0; 32]]);
+        short_hash.sign(&keypairs[0]);
+        assert_eq!(
+            verifier.verify(short_hash),
+            Err(SnapshotError::InvalidChunkHash { index: 1 })
+        );
+        assert_eq!(
+            format!("{}", SnapshotError::ChunkOutOfRange { index: 3, count: 3 }),
+            "chunk 3 of a snapshot with 3 chunks"
+        );
+    }
+}
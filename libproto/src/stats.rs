@@ -0,0 +1,368 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Per-proposer statistics for monitoring.
+//!
+//! Chain counts one `ProposerStats` per authority every N blocks. To be
+//! sent to other services, routed as `chain.proposer_stats`, it has to be
+//! in cita-proto first, as
+//!
+//! ```text
+//! message ProposerStats {
+//!     bytes proposer = 1;
+//!     uint64 blocks_proposed = 2;
+//!     uint64 expected_slots = 3;
+//!     double avg_commits_per_proof = 4;
+//!     uint64 start_height = 5;
+//!     uint64 end_height = 6;
+//! }
+//! ```
+//!
+//! with a `ProposerStats` variant in the content of `InnerMessage`.
+
+use std::collections::BTreeMap;
+
+use rustc_serialize::hex::ToHex;
+
+use crate::protos::Block;
+use crate::types::Address;
+
+/// What `StatsAccumulator` needs of a proof, implemented by
+/// `proof::BftProof`.
+pub trait ProofVoters {
+    /// Who signed the proof.
+    fn voters(&self) -> Vec<Address>;
+    /// The round the block was committed in.
+    fn round(&self) -> u64;
+}
+
+/// Statistics of one proposer over the blocks `start_height..=end_height`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ProposerStats {
+    pub proposer: Address,
+    pub blocks_proposed: u64,
+    /// Heights where the proposer was first in turn, round 0.
+    pub expected_slots: u64,
+    /// Authorities committing, on average, to the blocks it proposed.
+    pub avg_commits_per_proof: f64,
+    pub start_height: u64,
+    pub end_height: u64,
+}
+
+impl ProposerStats {
+    /// Slots in turn which somebody else proposed, in some later round.
+    pub fn missed_slots(&self) -> u64 {
+        self.expected_slots.saturating_sub(self.blocks_proposed)
+    }
+}
+
+/// A `ProposerStats` for dashboards: serialized, numbers stay numbers and
+/// the proposer is hex.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DashboardStats {
+    pub proposer: String,
+    pub blocks_proposed: u64,
+    pub expected_slots: u64,
+    pub missed_slots: u64,
+    pub avg_commits_per_proof: f64,
+    pub start_height: u64,
+    pub end_height: u64,
+}
+
+impl<'a> From<&'a ProposerStats> for DashboardStats {
+    fn from(stats: &'a ProposerStats) -> Self {
+        DashboardStats {
+            proposer: format!("0x{}", stats.proposer.to_hex()),
+            blocks_proposed: stats.blocks_proposed,
+            expected_slots: stats.expected_slots,
+            missed_slots: stats.missed_slots(),
+            avg_commits_per_proof: stats.avg_commits_per_proof,
+            start_height: stats.start_height,
+            end_height: stats.end_height,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Tally {
+    proposed: u64,
+    expected: u64,
+    commits: u64,
+}
+
+/// Builds `ProposerStats` from the blocks of a chain and their proofs.
+///
+/// A window closes after `window` blocks, or earlier when the authorities
+/// change, so every window has one authority set. Closing it yields a
+/// `ProposerStats` per authority, and per other address which proposed a
+/// block in it, ready to be taken with `take`.
+///
+/// The proposer in turn is the round 0 one. cita-bft moves on to the next
+/// authority every round, so it is found from the proposer in the header
+/// and the round of the proof: `round` places before the proposer.
+#[derive(Debug)]
+pub struct StatsAccumulator {
+    window: u64,
+    authorities: Vec<Address>,
+    start_height: u64,
+    end_height: u64,
+    blocks: u64,
+    tallies: BTreeMap<Address, Tally>,
+    ready: Vec<ProposerStats>,
+}
+
+impl StatsAccumulator {
+    pub fn new(window: u64, authorities: Vec<Address>) -> Self {
+        StatsAccumulator {
+            window: window.max(1),
+            authorities,
+            start_height: 0,
+            end_height: 0,
+            blocks: 0,
+            tallies: BTreeMap::new(),
+            ready: Vec::new(),
+        }
+    }
+
+    pub fn authorities(&self) -> &[Address] {
+        &self.authorities
+    }
+
+    /// The authorities for the blocks pushed from now on. A different set
+    /// closes the current window.
+    pub fn set_authorities(&mut self, authorities: Vec<Address>) {
+        if authorities != self.authorities {
+            self.flush();
+            self.authorities = authorities;
+        }
+    }
+
+    /// Count `block`, with `proof` the proof committing it. That proof is
+    /// in the header of the next block, or in a `BlockWithProof`.
+    pub fn push<P: ProofVoters + ?Sized>(&mut self, block: &Block, proof: &P) {
+        let header = block.get_header();
+        let height = header.get_height();
+        if self.blocks == 0 {
+            self.start_height = height;
+        }
+        self.end_height = height;
+        self.blocks += 1;
+
+        let proposer = header.get_proposer();
+        if proposer.len() == 20 {
+            let proposer = Address::from_slice(proposer);
+            let authorities = &self.authorities;
+            if let Some(position) = authorities.iter().position(|a| *a == proposer) {
+                let n = authorities.len() as u64;
+                let in_turn = (position as u64 + n - proof.round() % n) % n;
+                self.tallies
+                    .entry(authorities[in_turn as usize])
+                    .or_default()
+                    .expected += 1;
+            }
+            let commits = proof
+                .voters()
+                .iter()
+                .filter(|voter| authorities.contains(voter))
+                .count() as u64;
+            let tally = self.tallies.entry(proposer).or_default();
+            tally.proposed += 1;
+            tally.commits += commits;
+        } else {
+            warn!(
+                "block {} has a malformed proposer 0x{}",
+                height,
+                proposer.to_hex()
+            );
+        }
+
+        if self.blocks >= self.window {
+            self.flush();
+        }
+    }
+
+    /// Close the current window, even though it is not full.
+    pub fn flush(&mut self) {
+        if self.blocks == 0 {
+            return;
+        }
+        let mut tallies = ::std::mem::replace(&mut self.tallies, BTreeMap::new());
+        let mut addresses = self.authorities.clone();
+        addresses.extend(
+            tallies
+                .keys()
+                .filter(|address| !self.authorities.contains(address))
+                .cloned(),
+        );
+        for address in addresses {
+            let tally = tallies.remove(&address).unwrap_or_default();
+            let avg_commits_per_proof = if tally.proposed == 0 {
+                0.0
+            } else {
+                tally.commits as f64 / tally.proposed as f64
+            };
+            self.ready.push(ProposerStats {
+                proposer: address,
+                blocks_proposed: tally.proposed,
+                expected_slots: tally.expected,
+                avg_commits_per_proof,
+                start_height: self.start_height,
+                end_height: self.end_height,
+            });
+        }
+        self.blocks = 0;
+    }
+
+    /// The stats of the windows closed so far.
+    pub fn take(&mut self) -> Vec<ProposerStats> {
+        ::std::mem::replace(&mut self.ready, Vec::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DashboardStats, ProofVoters, ProposerStats, StatsAccumulator};
+    use crate::protos::Block;
+    use crate::types::Address;
+    use serde_json;
+
+    struct Votes(u64, Vec<Address>);
+
+    impl ProofVoters for Votes {
+        fn voters(&self) -> Vec<Address> {
+            self.1.clone()
+        }
+
+        fn round(&self) -> u64 {
+            self.0
+        }
+    }
+
+    fn block(height: u64, proposer: Address) -> Block {
+        let mut block = Block::new();
+        block.mut_header().set_height(height);
+        block.mut_header().set_proposer(proposer.to_vec());
+        block
+    }
+
+    fn find(stats: &[ProposerStats], address: Address) -> &ProposerStats {
+        stats.iter().find(|s| s.proposer == address).unwrap()
+    }
+
+    fn check(stats: &ProposerStats, proposed: u64, expected: u64, avg: f64) {
+        assert_eq!(stats.blocks_proposed, proposed);
+        assert_eq!(stats.expected_slots, expected);
+        assert!((stats.avg_commits_per_proof - avg).abs() < 1e-9);
+    }
+
+    fn sample() -> ProposerStats {
+        ProposerStats {
+            proposer: Address::from(0xab),
+            blocks_proposed: 3,
+            expected_slots: 4,
+            avg_commits_per_proof: 2.5,
+            start_height: 101,
+            end_height: 200,
+        }
+    }
+
+    #[test]
+    fn validator_change_splits_window() {
+        let (a, b, c, d) = (
+            Address::from(1),
+            Address::from(2),
+            Address::from(3),
+            Address::from(4),
+        );
+        let mut acc = StatsAccumulator::new(6, vec![a, b, c]);
+
+        // a proposes height 2 in round 1, so c was in turn and missed it.
+        acc.push(&block(1, b), &Votes(0, vec![a, b, c]));
+        acc.push(&block(2, a), &Votes(1, vec![a, b]));
+        acc.push(&block(3, c), &Votes(0, vec![a, b, c]));
+        acc.push(&block(4, b), &Votes(0, vec![a, b, c]));
+        assert!(acc.take().is_empty());
+
+        // The same set again changes nothing.
+        acc.set_authorities(vec![a, b, c]);
+        assert!(acc.take().is_empty());
+
+        // c leaves and d joins at height 5.
+        acc.set_authorities(vec![a, b, d]);
+        let first = acc.take();
+        assert_eq!(first.len(), 3);
+        assert!(first
+            .iter()
+            .all(|s| s.start_height == 1 && s.end_height == 4));
+        check(find(&first, a), 1, 0, 2.0);
+        check(find(&first, b), 2, 2, 3.0);
+        check(find(&first, c), 1, 2, 3.0);
+        assert_eq!(find(&first, c).missed_slots(), 1);
+
+        // a takes over height 8 in round 2, after b and d, and c's late
+        // vote is not counted.
+        acc.push(&block(5, d), &Votes(0, vec![a, b, c, d]));
+        acc.push(&block(6, a), &Votes(0, vec![a, b, d]));
+        acc.push(&block(7, b), &Votes(0, vec![a, b, d]));
+        acc.push(&block(8, a), &Votes(2, vec![a, b, d]));
+        acc.push(&block(9, a), &Votes(0, vec![a, d]));
+        acc.push(&block(10, b), &Votes(0, vec![a, b, d]));
+        let second = acc.take();
+        assert_eq!(second.len(), 3);
+        assert!(second
+            .iter()
+            .all(|s| s.start_height == 5 && s.end_height == 10));
+        check(find(&second, a), 3, 2, 8.0 / 3.0);
+        check(find(&second, b), 2, 3, 3.0);
+        check(find(&second, d), 1, 1, 3.0);
+        assert_eq!(find(&second, b).missed_slots(), 1);
+
+        // Nothing pushed since.
+        acc.flush();
+        assert!(acc.take().is_empty());
+    }
+
+    #[test]
+    fn outsider_proposer_is_reported() {
+        let (a, b, x) = (Address::from(1), Address::from(2), Address::from(9));
+        let mut acc = StatsAccumulator::new(10, vec![a, b]);
+        acc.push(&block(1, x), &Votes(0, vec![a, b]));
+        acc.push(&block(2, a), &Votes(0, vec![a, b]));
+        acc.flush();
+        let stats = acc.take();
+        let addresses: Vec<Address> = stats.iter().map(|s| s.proposer).collect();
+        assert_eq!(addresses, vec![a, b, x]);
+        // Nobody is known to be in turn when an outsider proposes.
+        check(find(&stats, a), 1, 1, 2.0);
+        check(find(&stats, b), 0, 0, 0.0);
+        check(find(&stats, x), 1, 0, 2.0);
+    }
+
+    #[test]
+    fn json_for_dashboards() {
+        assert_eq!(
+            serde_json::to_value(DashboardStats::from(&sample())).unwrap(),
+            json!({
+                "proposer": "0x00000000000000000000000000000000000000ab",
+                "blocksProposed": 3,
+                "expectedSlots": 4,
+                "missedSlots": 1,
+                "avgCommitsPerProof": 2.5,
+                "startHeight": 101,
+                "endHeight": 200,
+            })
+        );
+    }
+}
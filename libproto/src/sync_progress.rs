@@ -0,0 +1,182 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! How far the network is ahead of us.
+//!
+//! Peers broadcast their `Status`, with the height they are at.
+//! `PeerBestTracker` keeps the latest of each peer, by the origin of its
+//! message, and forgets a peer which didn't send one for a while, so a peer
+//! gone away doesn't keep a node syncing towards its height forever.
+//!
+//! A peer still syncing only tells its own height. Telling the best height
+//! it heard of as well needs it in cita-proto first, as
+//!
+//! ```text
+//! // blockchain.proto, in Status
+//! uint64 best_known_height = 3;
+//! bool syncing = 4;
+//! ```
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::autoimpl::Origin;
+use crate::protos::Status;
+use crate::types::{BlockHeight, HeightDiff};
+
+/// How long the status of a peer counts.
+pub const DEFAULT_PEER_STATUS_TTL: Duration = Duration::from_secs(30);
+
+#[derive(Debug)]
+pub struct PeerBestTracker {
+    ttl: Duration,
+    peers: HashMap<Origin, (BlockHeight, Instant)>,
+}
+
+impl Default for PeerBestTracker {
+    fn default() -> Self {
+        PeerBestTracker::new(DEFAULT_PEER_STATUS_TTL)
+    }
+}
+
+impl PeerBestTracker {
+    pub fn new(ttl: Duration) -> Self {
+        PeerBestTracker {
+            ttl,
+            peers: HashMap::new(),
+        }
+    }
+
+    /// The best height `status` tells of, the peer's own.
+    pub fn best_height(status: &Status) -> BlockHeight {
+        BlockHeight(status.get_height())
+    }
+
+    pub fn observe(&mut self, origin: Origin, status: &Status) {
+        self.observe_at(origin, status, Instant::now());
+    }
+
+    /// Replace what `origin` told before, even with a lower height.
+    pub fn observe_at(&mut self, origin: Origin, status: &Status, now: Instant) {
+        self.peers.insert(origin, (Self::best_height(status), now));
+    }
+
+    /// Forget `origin`, e.g. when it disconnected.
+    pub fn remove(&mut self, origin: Origin) {
+        self.peers.remove(&origin);
+    }
+
+    /// Forget the peers last heard of more than the TTL before `now`,
+    /// returning how many.
+    pub fn expire(&mut self, now: Instant) -> usize {
+        let ttl = self.ttl;
+        let before = self.peers.len();
+        self.peers
+            .retain(|_, &mut (_, seen)| now.saturating_duration_since(seen) <= ttl);
+        before - self.peers.len()
+    }
+
+    /// Peers not expired yet.
+    pub fn len(&self) -> usize {
+        self.peers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+
+    /// The highest height any peer knows of, none without peers.
+    pub fn sync_target(&mut self) -> Option<BlockHeight> {
+        self.sync_target_at(Instant::now())
+    }
+
+    pub fn sync_target_at(&mut self, now: Instant) -> Option<BlockHeight> {
+        self.expire(now);
+        self.peers.values().map(|&(height, _)| height).max()
+    }
+
+    /// Whether `own_height` is at most `tolerance` below the sync target.
+    /// Without peers there is nothing to catch up with.
+    pub fn is_synced(&mut self, own_height: impl Into<BlockHeight>, tolerance: HeightDiff) -> bool {
+        self.is_synced_at(own_height, tolerance, Instant::now())
+    }
+
+    pub fn is_synced_at(
+        &mut self,
+        own_height: impl Into<BlockHeight>,
+        tolerance: HeightDiff,
+        now: Instant,
+    ) -> bool {
+        let own_height = own_height.into();
+        self.sync_target_at(now)
+            .and_then(|target| target.checked_sub(own_height))
+            .map_or(true, |behind| behind <= tolerance)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PeerBestTracker;
+    use crate::protos::Status;
+    use crate::types::{BlockHeight, HeightDiff};
+    use std::time::{Duration, Instant};
+
+    fn status(height: u64) -> Status {
+        let mut status = Status::new();
+        status.set_height(height);
+        status
+    }
+
+    #[test]
+    fn tracks_the_best_height() {
+        let start = Instant::now();
+        let mut tracker = PeerBestTracker::new(Duration::from_secs(10));
+        assert_eq!(tracker.sync_target_at(start), None);
+        assert!(tracker.is_synced_at(0, HeightDiff(0), start));
+
+        tracker.observe_at(1, &status(100), start);
+        tracker.observe_at(2, &status(120), start);
+        tracker.observe_at(3, &status(110), start);
+        assert_eq!(tracker.sync_target_at(start), Some(BlockHeight(120)));
+        assert!(!tracker.is_synced_at(100, HeightDiff(5), start));
+        assert!(tracker.is_synced_at(115, HeightDiff(5), start));
+        assert!(tracker.is_synced_at(BlockHeight(130), HeightDiff(0), start));
+
+        // A newer status of a peer replaces its older one.
+        tracker.observe_at(2, &status(95), start);
+        assert_eq!(tracker.sync_target_at(start), Some(BlockHeight(110)));
+        tracker.remove(3);
+        assert_eq!(tracker.sync_target_at(start), Some(BlockHeight(100)));
+        assert_eq!(tracker.len(), 2);
+    }
+
+    #[test]
+    fn forgets_stale_peers() {
+        let start = Instant::now();
+        let mut tracker = PeerBestTracker::new(Duration::from_secs(10));
+        tracker.observe_at(1, &status(500), start);
+        tracker.observe_at(2, &status(100), start + Duration::from_secs(8));
+
+        let later = start + Duration::from_secs(10);
+        assert_eq!(tracker.sync_target_at(later), Some(BlockHeight(500)));
+        let later = start + Duration::from_secs(11);
+        assert_eq!(tracker.sync_target_at(later), Some(BlockHeight(100)));
+        assert_eq!(tracker.len(), 1);
+        assert!(tracker.is_synced_at(100, HeightDiff(0), later));
+
+        assert_eq!(tracker.expire(start + Duration::from_secs(19)), 1);
+        assert!(tracker.is_empty());
+        assert_eq!(tracker.sync_target_at(start), None);
+    }
+}
@@ -0,0 +1,59 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Where a transaction entered the node.
+//!
+//! A transaction is handed from service to service in a `Request`, then a
+//! `VerifyTxReq`, and the first hop is the last one knowing where it came
+//! from. To carry it further both messages need it in cita-proto first, as
+//!
+//! ```text
+//! // blockchain.proto
+//! enum TxOriginKind {
+//!     Peer = 0;
+//!     Rpc = 1;
+//!     Resubmit = 2;
+//! }
+//!
+//! // auth.proto, in VerifyTxReq
+//! blockchain.TxOriginKind origin = 12;
+//! uint32 origin_node = 13;
+//!
+//! // request.proto, in Request, outside the req oneof
+//! blockchain.TxOriginKind origin = 30;
+//! uint32 origin_node = 31;
+//! ```
+//!
+//! with Peer the zero value, so messages from older nodes read as relayed
+//! by an unknown peer, which is what they were as far as we can tell.
+
+use crate::autoimpl::{Origin, ZERO_ORIGIN};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TxOrigin {
+    /// Sent to our own jsonrpc.
+    Rpc,
+    /// Relayed by the node `node_short_id`, `ZERO_ORIGIN` when unknown.
+    Peer { node_short_id: Origin },
+    /// Put back by this node, e.g. from a block which didn't make it.
+    Resubmit,
+}
+
+impl Default for TxOrigin {
+    fn default() -> Self {
+        TxOrigin::Peer {
+            node_short_id: ZERO_ORIGIN,
+        }
+    }
+}
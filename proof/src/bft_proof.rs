@@ -17,6 +17,7 @@ use cita_directories::DataPath;
 use crypto::{pubkey_to_address, Sign, Signature};
 use hashable::Hashable;
 use libproto::blockchain::{Proof, ProofType};
+use libproto::stats::ProofVoters;
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::prelude::*;
@@ -99,6 +100,13 @@ impl BftProof {
         false
     }
 
+    /// The authorities which committed, sorted.
+    pub fn voters(&self) -> Vec<Address> {
+        let mut voters: Vec<Address> = self.commits.keys().cloned().collect();
+        voters.sort();
+        voters
+    }
+
     // Check proof commits
     pub fn check(&self, h: usize, authorities: &[Address]) -> bool {
         if h == 0 {
@@ -112,13 +120,9 @@ impl BftProof {
         }
         self.commits.iter().all(|(sender, sig)| {
             if authorities.contains(sender) {
-                let msg = serialize(
-                    &(h, self.round, Step::Precommit, sender, Some(self.proposal)),
-                    Infinite,
-                )
-                .unwrap();
+                let msg = precommit_hash(h, self.round, sender, self.proposal);
                 let signature = Signature(sig.0);
-                if let Ok(pubkey) = signature.recover(&msg.crypt_hash()) {
+                if let Ok(pubkey) = signature.recover(&msg) {
                     return pubkey_to_address(&pubkey) == *sender;
                 }
             }
@@ -127,6 +131,31 @@ impl BftProof {
     }
 }
 
+/// What `sender` signs to precommit `proposal`.
+pub(crate) fn precommit_hash(
+    height: usize,
+    round: usize,
+    sender: &Address,
+    proposal: H256,
+) -> H256 {
+    serialize(
+        &(height, round, Step::Precommit, sender, Some(proposal)),
+        Infinite,
+    )
+    .unwrap()
+    .crypt_hash()
+}
+
+impl ProofVoters for BftProof {
+    fn voters(&self) -> Vec<Address> {
+        BftProof::voters(self)
+    }
+
+    fn round(&self) -> u64 {
+        self.round as u64
+    }
+}
+
 impl From<Proof> for BftProof {
     fn from(p: Proof) -> Self {
         let decoded: BftProof =
@@ -147,7 +176,8 @@ impl Into<Proof> for BftProof {
 
 #[cfg(test)]
 mod tests {
-    use super::{BftProof, H256};
+    use super::{BftProof, ProofVoters, H256};
+    use crate::test_utils::{assemble_proof, SignVote, ValidatorSet};
     use libproto::blockchain::Proof;
     use std::collections::HashMap;
 
@@ -157,5 +187,77 @@ mod tests {
         let proto_proof: Proof = o_proof.clone().into();
         let de_proof: BftProof = proto_proof.into();
         assert_eq!(o_proof, de_proof);
+
+        let validators = ValidatorSet::generate(4);
+        let o_proof = assemble_proof((0..3).map(|v| validators.sign_vote(v, 5, 1, H256::from(7))));
+        let proto_proof: Proof = o_proof.clone().into();
+        let de_proof: BftProof = proto_proof.into();
+        assert_eq!(o_proof, de_proof);
+        assert!(de_proof.check(5, &validators.authorities()));
+    }
+
+    #[test]
+    fn proof_voters() {
+        let validators = ValidatorSet::generate(3);
+        let proof = assemble_proof(
+            [2, 0]
+                .iter()
+                .map(|&v| validators.sign_vote(v, 1, 2, H256::default())),
+        );
+        let mut voters = vec![validators.address(0), validators.address(2)];
+        voters.sort();
+        assert_eq!(proof.voters(), voters);
+        assert_eq!(ProofVoters::voters(&proof), voters);
+        assert_eq!(ProofVoters::round(&proof), 2);
+    }
+
+    #[test]
+    fn proof_check() {
+        let validators = ValidatorSet::generate(4);
+        let authorities = validators.authorities();
+        let proposal = H256::from(42);
+        let votes = |voters: &[usize]| {
+            assemble_proof(
+                voters
+                    .iter()
+                    .map(|&v| validators.sign_vote(v, 10, 2, proposal)),
+            )
+        };
+
+        // More than two thirds of the authorities.
+        assert!(votes(&[0, 1, 2]).check(10, &authorities));
+        assert!(votes(&[0, 1, 2, 3]).check(10, &authorities));
+        assert!(!votes(&[0, 1]).check(10, &authorities));
+        assert!(!votes(&[0, 1, 2]).check(11, &authorities));
+        assert!(!votes(&[0, 1, 2]).check(10, &authorities[1..]));
+        // Nothing to check for the genesis block.
+        assert!(votes(&[0]).check(0, &authorities));
+
+        // A signature for another proposal, round or height.
+        for (height, round, other) in
+            vec![(10, 2, H256::from(43)), (10, 3, proposal), (9, 2, proposal)]
+        {
+            let mut proof = votes(&[0, 1, 2]);
+            let forged = validators.sign_vote(1, height, round, other);
+            proof.commits.insert(forged.sender, forged.signature);
+            assert!(!proof.check(10, &authorities));
+        }
+
+        // A signer which isn't an authority.
+        let others = ValidatorSet::from_seed(1, 1);
+        let mut proof = votes(&[0, 1, 2]);
+        let other = others.sign_vote(0, 10, 2, proposal);
+        proof.commits.insert(other.sender, other.signature);
+        assert!(!proof.check(10, &authorities));
+    }
+
+    #[test]
+    #[should_panic(expected = "differ")]
+    fn votes_must_agree() {
+        let validators = ValidatorSet::generate(2);
+        assemble_proof(vec![
+            validators.sign_vote(0, 1, 0, H256::from(1)),
+            validators.sign_vote(1, 1, 0, H256::from(2)),
+        ]);
     }
 }
@@ -0,0 +1,418 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Whether the stored blocks of a height range still add up.
+//!
+//! For each height the transactions and receipts roots of the header are
+//! recomputed from the stored body and receipts, the merkle roots of
+//! their hashes. The chain service reads the blocks, as a `BlockReader`.
+//!
+//! Heights are verified in rounds, each worker taking `batch` heights of
+//! a round, and the progress callback hears of each round. When it says
+//! to stop, the report has the heights left, to resume from later:
+//!
+//! ```text
+//! let report = verify_range(reader, 0..height, &options, &mut |progress| {
+//!     save(progress.next);
+//!     !interrupted()
+//! });
+//! ```
+
+use std::fmt;
+use std::ops::Range;
+use std::sync::Arc;
+use std::thread;
+
+use cita_merklehash::{merge, Tree, HASH_NULL};
+use libproto::{BlockBody, BlockHeader};
+use types::traits::LowerHex;
+use types::H256;
+
+/// The stored chain, as `verify_range` reads it.
+pub trait BlockReader: Send + Sync {
+    fn header(&self, height: u64) -> Option<BlockHeader>;
+    fn body(&self, height: u64) -> Option<BlockBody>;
+    /// Hashes of the receipts, in the order of the transactions, as the
+    /// receipts root is made of them.
+    fn receipt_hashes(&self, height: u64) -> Option<Vec<H256>>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Part {
+    Header,
+    Body,
+    Receipts,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Root {
+    Transactions,
+    Receipts,
+}
+
+impl fmt::Display for Root {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Root::Transactions => write!(f, "transactions root"),
+            Root::Receipts => write!(f, "receipts root"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// The reader has nothing for it.
+    Missing(Part),
+    /// The root in the header isn't the one recomputed.
+    Root {
+        root: Root,
+        in_header: Vec<u8>,
+        computed: H256,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    pub height: u64,
+    pub problem: Problem,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.problem {
+            Problem::Missing(part) => write!(f, "height {}: {:?} missing", self.height, part),
+            Problem::Root {
+                root,
+                ref in_header,
+                computed,
+            } => write!(
+                f,
+                "height {}: {} is {} in the header, {} recomputed",
+                self.height,
+                root,
+                in_header.lower_hex_with_0x(),
+                computed.lower_hex_with_0x()
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VerifyOptions {
+    /// Threads verifying at the same time.
+    pub workers: usize,
+    /// Heights a worker takes each round.
+    pub batch: u64,
+}
+
+impl Default for VerifyOptions {
+    fn default() -> Self {
+        VerifyOptions {
+            workers: 4,
+            batch: 64,
+        }
+    }
+}
+
+/// After a round.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    pub verified: u64,
+    pub total: u64,
+    pub mismatches: usize,
+    /// The heights below it are verified.
+    pub next: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VerifyRangeReport {
+    pub verified: u64,
+    /// By height.
+    pub mismatches: Vec<Mismatch>,
+    /// The heights left when stopped, none after the whole range.
+    pub remaining: Option<Range<u64>>,
+}
+
+impl VerifyRangeReport {
+    pub fn is_consistent(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+}
+
+/// Verify the heights of `range`, telling `progress` after each round,
+/// and stopping early once it returns false.
+pub fn verify_range(
+    reader: Arc<dyn BlockReader>,
+    range: Range<u64>,
+    options: &VerifyOptions,
+    progress: &mut dyn FnMut(&Progress) -> bool,
+) -> VerifyRangeReport {
+    let workers = options.workers.max(1) as u64;
+    let batch = options.batch.max(1);
+    let total = range.end.saturating_sub(range.start);
+    let mut report = VerifyRangeReport::default();
+    let mut next = range.start;
+    while next < range.end {
+        let round_end = next
+            .saturating_add(workers.saturating_mul(batch))
+            .min(range.end);
+        let handles: Vec<_> = (0..workers)
+            .map(|worker| next + worker * batch)
+            .take_while(|start| *start < round_end)
+            .map(|start| {
+                let heights = start..(start + batch).min(round_end);
+                let reader = Arc::clone(&reader);
+                thread::spawn(move || {
+                    heights
+                        .flat_map(|height| verify_height(&*reader, height))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        for handle in handles {
+            let mismatches = handle.join().expect("a block reader panicked");
+            report.mismatches.extend(mismatches);
+        }
+        report.verified += round_end - next;
+        next = round_end;
+
+        let go_on = progress(&Progress {
+            verified: report.verified,
+            total,
+            mismatches: report.mismatches.len(),
+            next,
+        });
+        if !go_on && next < range.end {
+            report.remaining = Some(next..range.end);
+            break;
+        }
+    }
+    report
+}
+
+fn verify_height(reader: &dyn BlockReader, height: u64) -> Vec<Mismatch> {
+    let mismatch = |problem| Mismatch { height, problem };
+    let header = match reader.header(height) {
+        Some(header) => header,
+        None => return vec![mismatch(Problem::Missing(Part::Header))],
+    };
+    let transactions_root = reader.body(height).map(|body| body.transactions_root());
+    let receipts_root = reader.receipt_hashes(height).map(merkle_root);
+    vec![
+        check(
+            Root::Transactions,
+            header.get_transactions_root(),
+            transactions_root.ok_or(Part::Body),
+        ),
+        check(
+            Root::Receipts,
+            header.get_receipts_root(),
+            receipts_root.ok_or(Part::Receipts),
+        ),
+    ]
+    .into_iter()
+    .flatten()
+    .map(mismatch)
+    .collect()
+}
+
+fn check(root: Root, in_header: &[u8], computed: Result<H256, Part>) -> Option<Problem> {
+    match computed {
+        Err(part) => Some(Problem::Missing(part)),
+        Ok(computed) if in_header == &computed[..] => None,
+        Ok(computed) => Some(Problem::Root {
+            root,
+            in_header: in_header.to_vec(),
+            computed,
+        }),
+    }
+}
+
+fn merkle_root(hashes: Vec<H256>) -> H256 {
+    *Tree::from_hashes(hashes, merge)
+        .get_root_hash()
+        .unwrap_or(&HASH_NULL)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        verify_range, BlockReader, Mismatch, Part, Problem, Progress, Root, VerifyOptions,
+    };
+    use crate::testchain::{Anomaly, ChainBuilder, TestBlock};
+    use hashable::Hashable;
+    use libproto::{BlockBody, BlockHeader};
+    use protobuf::Message;
+    use std::sync::Arc;
+    use types::H256;
+
+    struct Stored {
+        blocks: Vec<TestBlock>,
+        without_body: Option<u64>,
+    }
+
+    impl BlockReader for Stored {
+        fn header(&self, height: u64) -> Option<BlockHeader> {
+            let block = self.blocks.get(height as usize)?;
+            Some(block.block.get_header().clone())
+        }
+
+        fn body(&self, height: u64) -> Option<BlockBody> {
+            if self.without_body == Some(height) {
+                return None;
+            }
+            let block = self.blocks.get(height as usize)?;
+            Some(block.block.get_body().clone())
+        }
+
+        fn receipt_hashes(&self, height: u64) -> Option<Vec<H256>> {
+            let block = self.blocks.get(height as usize)?;
+            let hashes = block
+                .receipts
+                .iter()
+                .map(|receipt| receipt.write_to_bytes().unwrap().crypt_hash())
+                .collect();
+            Some(hashes)
+        }
+    }
+
+    fn stored() -> Stored {
+        let chain = ChainBuilder::new(4096, 4)
+            .at(2, Anomaly::EmptyBlock)
+            .at(5, Anomaly::Version(1))
+            .build(20);
+        Stored {
+            blocks: chain.blocks().to_vec(),
+            without_body: None,
+        }
+    }
+
+    fn options(workers: usize, batch: u64) -> VerifyOptions {
+        VerifyOptions { workers, batch }
+    }
+
+    #[test]
+    fn a_fabricated_chain_is_consistent() {
+        let reader = Arc::new(stored());
+        let mut rounds = Vec::new();
+        let report = verify_range(reader, 0..21, &options(3, 2), &mut |progress| {
+            rounds.push(*progress);
+            true
+        });
+        assert!(report.is_consistent(), "{:?}", report.mismatches);
+        assert_eq!(report.verified, 21);
+        assert_eq!(report.remaining, None);
+        assert_eq!(
+            rounds
+                .iter()
+                .map(|progress| progress.next)
+                .collect::<Vec<_>>(),
+            vec![6, 12, 18, 21]
+        );
+        assert_eq!(
+            rounds.last(),
+            Some(&Progress {
+                verified: 21,
+                total: 21,
+                mismatches: 0,
+                next: 21,
+            })
+        );
+    }
+
+    #[test]
+    fn a_corrupted_receipt_is_found_at_its_height() {
+        let mut chain = stored();
+        chain.blocks[7].receipts[1].set_quota_used("0".to_owned());
+        chain.without_body = Some(12);
+        let expected = chain.blocks[7]
+            .block
+            .get_header()
+            .get_receipts_root()
+            .to_vec();
+        let reader = Arc::new(chain);
+        let report = verify_range(reader, 0..21, &options(4, 3), &mut |_| true);
+
+        assert_eq!(report.mismatches.len(), 2);
+        let corrupted = &report.mismatches[0];
+        assert_eq!(corrupted.height, 7);
+        match corrupted.problem {
+            Problem::Root {
+                root,
+                ref in_header,
+                computed,
+            } => {
+                assert_eq!(root, Root::Receipts);
+                assert_eq!(in_header, &expected);
+                assert_ne!(&computed[..], &expected[..]);
+            }
+            ref problem => panic!("unexpected {:?}", problem),
+        }
+        assert!(corrupted
+            .to_string()
+            .starts_with("height 7: receipts root is 0x"));
+        assert_eq!(
+            report.mismatches[1],
+            Mismatch {
+                height: 12,
+                problem: Problem::Missing(Part::Body),
+            }
+        );
+
+        // Past the tip there is nothing.
+        let reader = Arc::new(stored());
+        let report = verify_range(reader, 20..22, &options(1, 1), &mut |_| true);
+        assert_eq!(
+            report.mismatches,
+            vec![Mismatch {
+                height: 21,
+                problem: Problem::Missing(Part::Header),
+            }]
+        );
+    }
+
+    #[test]
+    fn stopped_runs_resume_where_they_stopped() {
+        let mut chain = stored();
+        chain.blocks[3].receipts[0].set_quota_used("0".to_owned());
+        chain.blocks[15].receipts[2].set_quota_used("0".to_owned());
+        let reader: Arc<dyn BlockReader> = Arc::new(chain);
+        let whole = verify_range(
+            Arc::clone(&reader),
+            0..21,
+            &VerifyOptions::default(),
+            &mut |_| true,
+        );
+        assert_eq!(whole.mismatches.len(), 2);
+
+        let mut mismatches = Vec::new();
+        let mut range = 0..21;
+        let mut runs = 0;
+        loop {
+            runs += 1;
+            let mut rounds = 0;
+            let report = verify_range(Arc::clone(&reader), range, &options(2, 2), &mut |_| {
+                rounds += 1;
+                rounds < 2
+            });
+            mismatches.extend(report.mismatches);
+            match report.remaining {
+                Some(remaining) => range = remaining,
+                None => break,
+            }
+        }
+        assert_eq!(runs, 3);
+        assert_eq!(mismatches, whole.mismatches);
+    }
+}
@@ -0,0 +1,375 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A proof of any consensus, in the proof slot of a block header.
+//!
+//! The protobuf `Proof` has no `Raw` type, so a raw proof is stored with
+//! the default type and its own type number as an unknown value of the
+//! type field. Older builds read it as an `AuthorityRound` proof, which
+//! they don't verify anyway, and keep the value when re-encoding.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+use libproto::blockchain::{BlockHeader, Proof as ProtoProof, ProofType as ProtoProofType};
+use protobuf::Message;
+use types::Address;
+
+use crate::bft_proof::BftProof;
+
+/// Field number of `type` in the protobuf `Proof`.
+const TYPE_FIELD: u32 = 2;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum ProofType {
+    AuthorityRound,
+    Raft,
+    Bft,
+    /// For experiments, checked by a verifier registered at runtime.
+    Raw,
+}
+
+impl ProofType {
+    /// The number in the protobuf type field.
+    pub fn value(self) -> u64 {
+        match self {
+            ProofType::AuthorityRound => 0,
+            ProofType::Raft => 1,
+            ProofType::Bft => 2,
+            ProofType::Raw => 3,
+        }
+    }
+
+    pub fn from_value(value: u64) -> Option<Self> {
+        match value {
+            0 => Some(ProofType::AuthorityRound),
+            1 => Some(ProofType::Raft),
+            2 => Some(ProofType::Bft),
+            3 => Some(ProofType::Raw),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ProofType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            ProofType::AuthorityRound => "authority_round",
+            ProofType::Raft => "raft",
+            ProofType::Bft => "bft",
+            ProofType::Raw => "raw",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for ProofType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "authority_round" => Ok(ProofType::AuthorityRound),
+            "raft" => Ok(ProofType::Raft),
+            "bft" => Ok(ProofType::Bft),
+            "raw" => Ok(ProofType::Raw),
+            _ => Err(format!("unknown proof type {}", s)),
+        }
+    }
+}
+
+/// A proof and what kind of proof it is, opaque to everyone but the
+/// verifier of its type.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Proof {
+    pub proof_type: ProofType,
+    pub content: Vec<u8>,
+}
+
+impl Proof {
+    pub fn new(proof_type: ProofType, content: Vec<u8>) -> Self {
+        Proof {
+            proof_type,
+            content,
+        }
+    }
+}
+
+impl From<BftProof> for Proof {
+    fn from(proof: BftProof) -> Self {
+        Proof::from(Into::<ProtoProof>::into(proof))
+    }
+}
+
+impl From<ProtoProof> for Proof {
+    fn from(proof: ProtoProof) -> Self {
+        let unknown = proof
+            .get_unknown_fields()
+            .get(TYPE_FIELD)
+            .and_then(|values| values.varint.last())
+            .cloned();
+        let proof_type = match (proof.get_field_type(), unknown) {
+            (ProtoProofType::AuthorityRound, Some(value)) => {
+                ProofType::from_value(value).unwrap_or(ProofType::Raw)
+            }
+            (ProtoProofType::AuthorityRound, None) => ProofType::AuthorityRound,
+            (ProtoProofType::Raft, _) => ProofType::Raft,
+            (ProtoProofType::Bft, _) => ProofType::Bft,
+        };
+        Proof {
+            proof_type,
+            content: proof.get_content().to_vec(),
+        }
+    }
+}
+
+impl Into<ProtoProof> for Proof {
+    fn into(self) -> ProtoProof {
+        let mut proof = ProtoProof::new();
+        proof.set_content(self.content);
+        match self.proof_type {
+            ProofType::AuthorityRound => proof.set_field_type(ProtoProofType::AuthorityRound),
+            ProofType::Raft => proof.set_field_type(ProtoProofType::Raft),
+            ProofType::Bft => proof.set_field_type(ProtoProofType::Bft),
+            ProofType::Raw => proof
+                .mut_unknown_fields()
+                .add_varint(TYPE_FIELD, ProofType::Raw.value()),
+        }
+        proof
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VerifyError {
+    /// No verifier for proofs of this type.
+    Unsupported(ProofType),
+    Invalid(ProofType),
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            VerifyError::Unsupported(proof_type) => {
+                write!(f, "no verifier for {} proofs", proof_type)
+            }
+            VerifyError::Invalid(proof_type) => write!(f, "invalid {} proof", proof_type),
+        }
+    }
+}
+
+impl ::std::error::Error for VerifyError {}
+
+pub trait ProofVerifier: Send + Sync {
+    /// Check `proof` commits the block at `height`, by `authorities`.
+    fn verify(&self, proof: &Proof, height: u64, authorities: &[Address]) -> bool;
+}
+
+/// Checks the commits with `BftProof::check`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct BftVerifier;
+
+impl ProofVerifier for BftVerifier {
+    fn verify(&self, proof: &Proof, height: u64, authorities: &[Address]) -> bool {
+        let mut proto = ProtoProof::new();
+        proto.set_content(proof.content.clone());
+        BftProof::from(proto).check(height as usize, authorities)
+    }
+}
+
+/// Raft proofs carry nothing, the authorities are trusted.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RaftVerifier;
+
+impl ProofVerifier for RaftVerifier {
+    fn verify(&self, _proof: &Proof, _height: u64, _authorities: &[Address]) -> bool {
+        true
+    }
+}
+
+/// The verifier of each proof type. `Bft` and `Raft` ones are built in,
+/// the `Raw` one is registered by whoever experiments with it.
+pub struct Verifiers {
+    verifiers: HashMap<ProofType, Box<dyn ProofVerifier>>,
+}
+
+impl Default for Verifiers {
+    fn default() -> Self {
+        let mut verifiers: HashMap<ProofType, Box<dyn ProofVerifier>> = HashMap::new();
+        verifiers.insert(ProofType::Bft, Box::new(BftVerifier));
+        verifiers.insert(ProofType::Raft, Box::new(RaftVerifier));
+        Verifiers { verifiers }
+    }
+}
+
+impl Verifiers {
+    pub fn new() -> Self {
+        Verifiers::default()
+    }
+
+    /// Verify `Raw` proofs with `verifier`, replacing the one before.
+    pub fn register_raw(&mut self, verifier: Box<dyn ProofVerifier>) {
+        self.verifiers.insert(ProofType::Raw, verifier);
+    }
+
+    pub fn verify(
+        &self,
+        proof: &Proof,
+        height: u64,
+        authorities: &[Address],
+    ) -> Result<(), VerifyError> {
+        let verifier = self
+            .verifiers
+            .get(&proof.proof_type)
+            .ok_or_else(|| VerifyError::Unsupported(proof.proof_type))?;
+        if verifier.verify(proof, height, authorities) {
+            Ok(())
+        } else {
+            Err(VerifyError::Invalid(proof.proof_type))
+        }
+    }
+
+    /// Verify the proof in `header`, which commits the block before it.
+    /// The genesis block has none to verify.
+    pub fn verify_header(
+        &self,
+        header: &BlockHeader,
+        authorities: &[Address],
+    ) -> Result<(), VerifyError> {
+        let height = header.get_height();
+        if height == 0 {
+            return Ok(());
+        }
+        let proof = Proof::from(header.get_proof().clone());
+        self.verify(&proof, height - 1, authorities)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Proof, ProofType, ProofVerifier, Verifiers, VerifyError};
+    use crate::test_utils::{assemble_proof, SignVote};
+    use crate::testchain::ChainBuilder;
+    use crate::CitaProof;
+    use libproto::blockchain::{BlockHeader, Proof as ProtoProof, ProofType as ProtoProofType};
+    use protobuf::Message;
+    use types::Address;
+
+    fn header(height: u64, proof: ProtoProof) -> BlockHeader {
+        let mut header = BlockHeader::new();
+        header.set_height(height);
+        header.set_proof(proof);
+        header
+    }
+
+    /// Encoded and parsed again, like a header from the network.
+    fn reparse(header: &BlockHeader) -> BlockHeader {
+        protobuf::parse_from_bytes(&header.write_to_bytes().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn bft_proofs_verify_as_before() {
+        let chain = ChainBuilder::new(11, 4).build(8);
+        let (block, parent) = (chain.block(8), chain.block(7));
+        let authorities = &parent.authorities;
+        let verifiers = Verifiers::new();
+
+        let good = parent.proof.clone();
+        let votes = (0..2).map(|v| chain.validators().sign_vote(v, 7, 0, parent.hash));
+        let few = assemble_proof(votes);
+        let other_height = chain.block(6).proof.clone();
+        for proof in &[good, few, other_height] {
+            let proto: ProtoProof = proof.clone().into();
+            let mut header = block.block.get_header().clone();
+            header.set_proof(proto.clone());
+            let header = reparse(&header);
+            assert_eq!(Proof::from(proto).proof_type, ProofType::Bft);
+            assert_eq!(
+                verifiers.verify_header(&header, authorities).is_ok(),
+                proof.check(7, authorities)
+            );
+            // And the header still decodes as before.
+            assert_eq!(
+                CitaProof::from(header.get_proof().clone()),
+                CitaProof::Bft(proof.clone())
+            );
+        }
+        assert!(parent.proof.check(7, authorities));
+        assert!(!chain.block(6).proof.check(7, authorities));
+
+        assert_eq!(
+            verifiers.verify_header(chain.block(0).block.get_header(), authorities),
+            Ok(())
+        );
+    }
+
+    /// Accepts proofs whose content is the height, signed by nobody.
+    struct HeightVerifier;
+
+    impl ProofVerifier for HeightVerifier {
+        fn verify(&self, proof: &Proof, height: u64, _authorities: &[Address]) -> bool {
+            proof.content == height.to_be_bytes()
+        }
+    }
+
+    #[test]
+    fn raw_proof_round_trip() {
+        let raw = Proof::new(ProofType::Raw, 41u64.to_be_bytes().to_vec());
+        let proto: ProtoProof = raw.clone().into();
+        // What older builds see.
+        assert_eq!(proto.get_field_type(), ProtoProofType::AuthorityRound);
+
+        let header = reparse(&header(42, proto));
+        assert_eq!(Proof::from(header.get_proof().clone()), raw);
+
+        let mut verifiers = Verifiers::new();
+        assert_eq!(
+            verifiers.verify_header(&header, &[]),
+            Err(VerifyError::Unsupported(ProofType::Raw))
+        );
+        verifiers.register_raw(Box::new(HeightVerifier));
+        assert_eq!(verifiers.verify_header(&header, &[]), Ok(()));
+
+        let wrong = reparse(&header(43, raw.into()));
+        assert_eq!(
+            verifiers.verify_header(&wrong, &[]),
+            Err(VerifyError::Invalid(ProofType::Raw))
+        );
+    }
+
+    #[test]
+    fn proof_type_names() {
+        for proof_type in &[
+            ProofType::AuthorityRound,
+            ProofType::Raft,
+            ProofType::Bft,
+            ProofType::Raw,
+        ] {
+            let name = proof_type.to_string();
+            assert_eq!(name.parse::<ProofType>(), Ok(*proof_type));
+            assert_eq!(
+                ::serde_json::to_string(proof_type).unwrap(),
+                format!("\"{}\"", name)
+            );
+            assert_eq!(ProofType::from_value(proof_type.value()), Some(*proof_type));
+        }
+        assert!("pow".parse::<ProofType>().is_err());
+
+        // An empty protobuf proof is what it always was.
+        assert_eq!(
+            Proof::from(ProtoProof::new()).proof_type,
+            ProofType::AuthorityRound
+        );
+    }
+}
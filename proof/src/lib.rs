@@ -20,8 +20,18 @@ extern crate libproto;
 #[macro_use]
 extern crate serde_derive;
 extern crate cita_directories;
+extern crate cita_merklehash;
+extern crate protobuf;
+#[cfg(test)]
+extern crate serde_json;
 
 mod bft_proof;
+pub mod consistency;
+pub mod envelope;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod test_utils;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod testchain;
 
 pub use bft_proof::{BftProof, Step};
 use libproto::blockchain::{Proof, ProofType};
@@ -0,0 +1,84 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Precommits and proofs of a `ValidatorSet`, with the `test-utils`
+//! feature.
+//!
+//! ```text
+//! let validators = ValidatorSet::generate(4);
+//! let votes = (0..3).map(|v| validators.sign_vote(v, 10, 0, proposal));
+//! assert!(assemble_proof(votes).check(10, &validators.authorities()));
+//! ```
+
+use crate::bft_proof::{precommit_hash, BftProof};
+use crypto::Signature;
+use std::collections::HashMap;
+use types::{Address, H256};
+
+pub use crypto::test_utils::ValidatorSet;
+
+/// The precommit of `sender`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Commit {
+    pub height: usize,
+    pub round: usize,
+    pub proposal: H256,
+    pub sender: Address,
+    pub signature: Signature,
+}
+
+pub trait SignVote {
+    /// The precommit of `validator` for `proposal`, as `BftProof::check`
+    /// verifies it.
+    fn sign_vote(&self, validator: usize, height: usize, round: usize, proposal: H256) -> Commit;
+}
+
+impl SignVote for ValidatorSet {
+    fn sign_vote(&self, validator: usize, height: usize, round: usize, proposal: H256) -> Commit {
+        let sender = self.address(validator);
+        let signature = self.sign(validator, &precommit_hash(height, round, &sender, proposal));
+        Commit {
+            height,
+            round,
+            proposal,
+            sender,
+            signature,
+        }
+    }
+}
+
+/// The proof of `votes`, which must be for the same proposal, height and
+/// round. Whether there are enough of them is up to `BftProof::check`.
+pub fn assemble_proof<I>(votes: I) -> BftProof
+where
+    I: IntoIterator<Item = Commit>,
+{
+    let mut votes = votes.into_iter().peekable();
+    let (height, round, proposal) = match votes.peek() {
+        Some(vote) => (vote.height, vote.round, vote.proposal),
+        None => panic!("a proof needs at least one vote"),
+    };
+    let commits: HashMap<Address, Signature> = votes
+        .map(|vote| {
+            assert_eq!(
+                (vote.height, vote.round, vote.proposal),
+                (height, round, proposal),
+                "votes of {:?} and others differ",
+                vote.sender
+            );
+            (vote.sender, vote.signature)
+        })
+        .collect();
+    BftProof::new(height, round, proposal, commits)
+}
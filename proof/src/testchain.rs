@@ -0,0 +1,499 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A chain of blocks for tests, with the `test-utils` feature.
+//!
+//! ```text
+//! let chain = ChainBuilder::new(7, 4)
+//!     .at(3, Anomaly::EmptyBlock)
+//!     .at(5, Anomaly::AuthorityChange(3))
+//!     .build(8);
+//! assert!(chain.block(6).proof.check(6, &chain.block(6).authorities));
+//! ```
+//!
+//! Everything follows from the seed: the validators and the senders are
+//! `ValidatorSet`s, the transactions are drawn from the seed and signed
+//! by the senders, and each block has the commits of every authority of
+//! its height. So the blocks, and their hashes, are the same in every run
+//! of the same build. For that the proofs are encoded with their commits
+//! in address order, where `BftProof::into` follows the order of the
+//! `HashMap`.
+//!
+//! Block `h` carries the proof of block `h - 1` in its header, as on a
+//! real chain, and its own proof in `TestBlock::proof`. The transactions
+//! and receipts roots are the merkle roots of their hashes, the state
+//! root is made up.
+
+use crate::bft_proof::BftProof;
+use crate::test_utils::{assemble_proof, SignVote, ValidatorSet};
+use bincode::{serialize, Infinite};
+use cita_merklehash::{merge, Tree, HASH_NULL};
+use crypto::Signature;
+use hashable::Hashable;
+use libproto::blockchain::{Proof, ProofType};
+use libproto::{
+    canonical, Block, BlockBody, BlockHeader, BlockWithProof, Crypto, Receipt, SignedTransaction,
+    Transaction, UnverifiedTransaction,
+};
+use protobuf::Message;
+use std::collections::BTreeMap;
+use types::traits::LowerHex;
+use types::{Address, H256};
+
+/// Milliseconds between blocks.
+pub const BLOCK_INTERVAL: u64 = 3000;
+/// Timestamp of the genesis block.
+pub const GENESIS_TIMESTAMP: u64 = 1_546_300_800_000;
+pub const QUOTA_LIMIT: u64 = 1_073_741_824;
+
+/// Seeds of the senders, apart from the validators'.
+const SENDERS_SEED: u64 = 0x5e4d_e75e;
+const SENDERS: usize = 4;
+
+/// Something on the chain the usual blocks don't have.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Anomaly {
+    /// A block without transactions.
+    EmptyBlock,
+    /// This and the following blocks are of the version. Transactions
+    /// are of version 0 in blocks of version 0, and 2 at most otherwise.
+    Version(u32),
+    /// The first validators of the seed are the authorities from this
+    /// block on.
+    AuthorityChange(usize),
+}
+
+/// A block, with what the chain knows about it.
+#[derive(Debug, Clone)]
+pub struct TestBlock {
+    pub block: Block,
+    pub hash: H256,
+    /// Of this block, in the header of the next one.
+    pub proof: BftProof,
+    /// Who signed the proof.
+    pub authorities: Vec<Address>,
+    /// Those of the transactions, in their order.
+    pub receipts: Vec<Receipt>,
+}
+
+impl TestBlock {
+    pub fn height(&self) -> u64 {
+        self.block.get_header().get_height()
+    }
+
+    pub fn transactions(&self) -> &[SignedTransaction] {
+        self.block.get_body().get_transactions()
+    }
+
+    /// The block and its own proof, as sync sends it.
+    pub fn with_proof(&self) -> BlockWithProof {
+        let mut block_with_proof = BlockWithProof::new();
+        block_with_proof.set_blk(self.block.clone());
+        block_with_proof.set_proof(sorted_proof(&self.proof));
+        block_with_proof
+    }
+}
+
+pub struct TestChain {
+    blocks: Vec<TestBlock>,
+    validators: ValidatorSet,
+    senders: ValidatorSet,
+}
+
+impl TestChain {
+    /// From the genesis block on.
+    pub fn blocks(&self) -> &[TestBlock] {
+        &self.blocks
+    }
+
+    pub fn block(&self, height: u64) -> &TestBlock {
+        &self.blocks[height as usize]
+    }
+
+    pub fn tip(&self) -> &TestBlock {
+        self.blocks.last().expect("a chain has the genesis block")
+    }
+
+    /// Every validator of any height.
+    pub fn validators(&self) -> &ValidatorSet {
+        &self.validators
+    }
+
+    /// Who signed the transactions.
+    pub fn senders(&self) -> &ValidatorSet {
+        &self.senders
+    }
+
+    /// `tx` signed by sender `sender`, like those of the chain.
+    pub fn sign(&self, sender: usize, tx: Transaction) -> SignedTransaction {
+        sign(&self.senders, sender, tx)
+    }
+}
+
+pub struct ChainBuilder {
+    seed: u64,
+    validators: usize,
+    txs_per_block: usize,
+    chain_id: u32,
+    anomalies: BTreeMap<u64, Vec<Anomaly>>,
+}
+
+impl ChainBuilder {
+    /// A chain of `validators` authorities, with three transactions a
+    /// block.
+    pub fn new(seed: u64, validators: usize) -> Self {
+        ChainBuilder {
+            seed,
+            validators,
+            txs_per_block: 3,
+            chain_id: 1,
+            anomalies: BTreeMap::new(),
+        }
+    }
+
+    pub fn txs_per_block(mut self, txs_per_block: usize) -> Self {
+        self.txs_per_block = txs_per_block;
+        self
+    }
+
+    pub fn chain_id(mut self, chain_id: u32) -> Self {
+        self.chain_id = chain_id;
+        self
+    }
+
+    /// `anomaly` at `height`. Those of one height apply in their order.
+    pub fn at(mut self, height: u64, anomaly: Anomaly) -> Self {
+        self.anomalies.entry(height).or_default().push(anomaly);
+        self
+    }
+
+    /// The genesis block and `blocks` more.
+    pub fn build(&self, blocks: u64) -> TestChain {
+        let most = self
+            .anomalies
+            .values()
+            .flatten()
+            .filter_map(|anomaly| match *anomaly {
+                Anomaly::AuthorityChange(n) => Some(n),
+                _ => None,
+            })
+            .fold(self.validators, usize::max);
+        let validators = ValidatorSet::from_seed(self.seed, most);
+        let senders = ValidatorSet::from_seed(self.seed ^ SENDERS_SEED, SENDERS);
+        let mut draws = Draws::new(self.seed);
+
+        let mut authorities = self.validators;
+        let mut version = 0;
+        let mut chain: Vec<TestBlock> = Vec::new();
+        let mut nonces = [0u64; SENDERS];
+        for height in 0..=blocks {
+            let mut empty = height == 0;
+            for anomaly in self.anomalies.get(&height).into_iter().flatten() {
+                match *anomaly {
+                    Anomaly::EmptyBlock => empty = true,
+                    Anomaly::Version(v) => version = v,
+                    Anomaly::AuthorityChange(n) => authorities = n,
+                }
+            }
+
+            let mut stxs = Vec::new();
+            let mut receipts = Vec::new();
+            if !empty {
+                for index in 0..self.txs_per_block {
+                    let sender = (height as usize + index) % SENDERS;
+                    let tx = self.transaction(&mut draws, height, version, nonces[sender]);
+                    let stx = sign(&senders, sender, tx);
+                    let mut receipt = Receipt::new();
+                    receipt.set_transaction_hash(stx.get_tx_hash().to_vec());
+                    receipt.set_quota_used(format!("{:x}", stx_quota_used(&stx)));
+                    receipt.set_account_nonce(nonces[sender]);
+                    nonces[sender] += 1;
+                    stxs.push(stx);
+                    receipts.push(receipt);
+                }
+            }
+
+            let previous = chain.last();
+            let body = BlockBody::from_transactions(stxs);
+            let mut header = BlockHeader::new();
+            header.set_height(height);
+            header.set_timestamp(GENESIS_TIMESTAMP + height * BLOCK_INTERVAL);
+            header.set_prevhash(
+                previous
+                    .map(|block| block.hash)
+                    .unwrap_or_default()
+                    .to_vec(),
+            );
+            if let Some(previous) = previous {
+                header.set_proof(sorted_proof(&previous.proof));
+            }
+            header.set_transactions_root(body.transactions_root().to_vec());
+            header.set_receipts_root(receipts_root(&receipts).to_vec());
+            header.set_state_root(state_root(previous, height).to_vec());
+            header.set_quota_used(receipts.iter().map(quota_used).sum());
+            header.set_quota_limit(QUOTA_LIMIT);
+            header.set_proposer(validators.address(height as usize % authorities).to_vec());
+
+            let mut block = Block::new();
+            block.set_version(version);
+            block.set_header(header);
+            block.set_body(body);
+            let hash = block.crypt_hash();
+            let proof = assemble_proof(
+                (0..authorities).map(|v| validators.sign_vote(v, height as usize, 0, hash)),
+            );
+            chain.push(TestBlock {
+                block,
+                hash,
+                proof,
+                authorities: validators.authorities()[..authorities].to_vec(),
+                receipts,
+            });
+        }
+        TestChain {
+            blocks: chain,
+            validators,
+            senders,
+        }
+    }
+
+    fn transaction(&self, draws: &mut Draws, height: u64, version: u32, nonce: u64) -> Transaction {
+        let mut tx = Transaction::new();
+        let to = Address::from(draws.draw() % 16);
+        if version == 0 {
+            tx.set_to(to.lower_hex());
+            tx.set_chain_id(self.chain_id);
+        } else {
+            tx.set_version(version.min(2));
+            tx.set_to_v1(to.to_vec());
+            tx.set_chain_id_v1(H256::from(u64::from(self.chain_id)).to_vec());
+        }
+        tx.set_nonce(format!("{}", nonce));
+        tx.set_valid_until_block(height + 99);
+        tx.set_quota(21_000 + draws.draw() % 10_000);
+        tx.set_value(H256::from(draws.draw() % 1000).to_vec());
+        let len = 4 + draws.draw() as usize % 60;
+        tx.set_data((0..len).map(|_| draws.draw() as u8).collect());
+        tx
+    }
+}
+
+/// Signed by sender `sender`, as `Transaction::sign` does.
+fn sign(senders: &ValidatorSet, sender: usize, tx: Transaction) -> SignedTransaction {
+    let signature = senders.sign(sender, &canonical::canonical_hash(&tx, &[]));
+    let mut utx = UnverifiedTransaction::new();
+    utx.set_transaction(tx);
+    utx.set_signature(signature.to_vec());
+    utx.set_crypto(Crypto::DEFAULT);
+
+    let mut stx = SignedTransaction::new();
+    stx.set_signer(senders.keypair(sender).pubkey().to_vec());
+    stx.set_tx_hash(utx.crypt_hash().to_vec());
+    stx.set_transaction_with_sig(utx);
+    stx
+}
+
+/// Most of the quota, by the data.
+fn stx_quota_used(stx: &SignedTransaction) -> u64 {
+    let tx = stx.get_transaction_with_sig().get_transaction();
+    tx.get_quota() - tx.get_data().len() as u64
+}
+
+fn quota_used(receipt: &Receipt) -> u64 {
+    u64::from_str_radix(receipt.get_quota_used(), 16).expect("the builder wrote hex")
+}
+
+/// How bincode encodes a `BftProof`, with the commits in address order.
+#[derive(Serialize)]
+struct SortedProof<'a> {
+    proposal: &'a H256,
+    height: usize,
+    round: usize,
+    commits: BTreeMap<&'a Address, &'a Signature>,
+}
+
+/// As `BftProof::into`, but the same bytes whatever order the commits are
+/// in. `BftProof` decodes it as any other proof.
+fn sorted_proof(proof: &BftProof) -> Proof {
+    let sorted = SortedProof {
+        proposal: &proof.proposal,
+        height: proof.height,
+        round: proof.round,
+        commits: proof.commits.iter().collect(),
+    };
+    let mut proto = Proof::new();
+    proto.set_content(serialize(&sorted, Infinite).unwrap());
+    proto.set_field_type(ProofType::Bft);
+    proto
+}
+
+fn receipts_root(receipts: &[Receipt]) -> H256 {
+    let hashes = receipts
+        .iter()
+        .map(|receipt| receipt.write_to_bytes().unwrap().crypt_hash())
+        .collect();
+    *Tree::from_hashes(hashes, merge)
+        .get_root_hash()
+        .unwrap_or(&HASH_NULL)
+}
+
+fn state_root(previous: Option<&TestBlock>, height: u64) -> H256 {
+    let mut preimage = previous
+        .map(|block| block.block.get_header().get_state_root().to_vec())
+        .unwrap_or_default();
+    preimage.extend_from_slice(&height.to_be_bytes());
+    preimage.crypt_hash()
+}
+
+/// SplitMix64, as for the validators' seeds.
+struct Draws {
+    state: u64,
+}
+
+impl Draws {
+    fn new(seed: u64) -> Self {
+        Draws { state: seed }
+    }
+
+    fn draw(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{sorted_proof, Anomaly, ChainBuilder, TestChain};
+    use crate::bft_proof::BftProof;
+    use crate::envelope::Verifiers;
+    use crate::test_utils::{assemble_proof, SignVote, ValidatorSet};
+    use cita_merklehash::HASH_NULL;
+    use libproto::SignedTransaction;
+    use types::H256;
+
+    fn hashes(chain: &TestChain) -> Vec<H256> {
+        chain.blocks().iter().map(|block| block.hash).collect()
+    }
+
+    #[test]
+    fn same_seed_same_chain() {
+        let chain = ChainBuilder::new(7, 4).build(5);
+        assert_eq!(chain.blocks().len(), 6);
+        assert_eq!(hashes(&chain), hashes(&ChainBuilder::new(7, 4).build(5)));
+        assert_ne!(hashes(&chain), hashes(&ChainBuilder::new(8, 4).build(5)));
+        // A longer chain starts the same.
+        assert_eq!(
+            hashes(&chain)[..],
+            hashes(&ChainBuilder::new(7, 4).build(9))[..6]
+        );
+        assert_eq!(
+            chain.tip().with_proof(),
+            ChainBuilder::new(7, 4).build(5).tip().with_proof()
+        );
+    }
+
+    #[test]
+    fn proofs_ignore_commit_order() {
+        let validators = ValidatorSet::generate(8);
+        let votes: Vec<_> = (0..8)
+            .map(|v| validators.sign_vote(v, 3, 0, H256::from(9)))
+            .collect();
+        let proof = assemble_proof(votes.iter().cloned());
+        let reversed = assemble_proof(votes.into_iter().rev());
+        assert_eq!(sorted_proof(&proof), sorted_proof(&reversed));
+        assert_eq!(BftProof::from(sorted_proof(&reversed)), proof);
+    }
+
+    #[test]
+    fn blocks_are_consistent() {
+        let chain = ChainBuilder::new(7, 4).txs_per_block(5).build(6);
+        let verifiers = Verifiers::new();
+        for pair in chain.blocks().windows(2) {
+            let (previous, block) = (&pair[0], &pair[1]);
+            let header = block.block.get_header();
+            assert!(block.block.check_hash());
+            assert_eq!(header.get_prevhash(), &previous.hash.0[..]);
+            assert_eq!(
+                header.get_timestamp() - previous.block.get_header().get_timestamp(),
+                super::BLOCK_INTERVAL
+            );
+            assert_eq!(block.hash, block.block.crypt_hash());
+            assert_eq!(block.transactions().len(), 5);
+            assert_eq!(block.receipts.len(), 5);
+
+            // The proof in the header is that of the previous block.
+            let proof = BftProof::from(header.get_proof().clone());
+            assert_eq!(proof, previous.proof);
+            assert_eq!(proof.proposal, previous.hash);
+            assert_eq!(
+                verifiers.verify_header(header, &previous.authorities),
+                Ok(())
+            );
+            assert!(block
+                .proof
+                .check(block.height() as usize, &block.authorities));
+
+            for (stx, receipt) in block.transactions().iter().zip(&block.receipts) {
+                let verified =
+                    SignedTransaction::verify_transaction(stx.get_transaction_with_sig().clone())
+                        .unwrap();
+                assert_eq!(verified.get_tx_hash(), stx.get_tx_hash());
+                assert!(chain.senders().position(&verified.from()).is_some());
+                assert_eq!(receipt.get_transaction_hash(), stx.get_tx_hash());
+            }
+        }
+        assert!(chain.block(0).transactions().is_empty());
+    }
+
+    #[test]
+    fn anomalies() {
+        let chain = ChainBuilder::new(3, 4)
+            .at(2, Anomaly::EmptyBlock)
+            .at(3, Anomaly::Version(1))
+            .at(4, Anomaly::AuthorityChange(7))
+            .build(6);
+
+        let empty = chain.block(2);
+        assert!(empty.transactions().is_empty());
+        assert_eq!(
+            empty.block.get_header().get_transactions_root(),
+            &HASH_NULL.0[..]
+        );
+        assert_eq!(empty.block.get_header().get_quota_used(), 0);
+        assert_eq!(chain.block(3).transactions().len(), 3);
+
+        assert_eq!(chain.block(2).block.get_version(), 0);
+        for height in 3..=6 {
+            assert_eq!(chain.block(height).block.get_version(), 1);
+        }
+        let tx = chain.block(4).transactions()[0]
+            .get_transaction_with_sig()
+            .get_transaction();
+        assert_eq!(tx.get_version(), 1);
+        assert_eq!(tx.get_to(), "");
+
+        let (before, after) = (chain.block(3), chain.block(4));
+        assert_eq!(before.authorities.len(), 4);
+        assert_eq!(after.authorities.len(), 7);
+        assert_eq!(after.authorities[..4], before.authorities[..]);
+        // Three of seven don't make a proof.
+        assert!(!after.proof.check(4, &after.authorities[..3]));
+        assert!(!before.proof.check(3, &after.authorities));
+        assert!(after.proof.check(4, &after.authorities));
+        assert_eq!(chain.validators().len(), 7);
+    }
+}
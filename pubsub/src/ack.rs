@@ -0,0 +1,198 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Consumer acknowledgement modes.
+//!
+//! With `AutoAck` a message is acked as soon as it arrives, so one which
+//! was received but not processed yet is lost when the service crashes.
+//! The other modes leave the ack to the service: unacked messages are
+//! delivered again after a crash, with `Delivery::redelivered` set.
+//!
+//! With rabbitmq the acks go out from the consumer thread, on its next
+//! delivery, or within `ACK_FLUSH_INTERVAL` when no more come.
+
+use crate::channel::Sender;
+use cita_types::H256;
+
+/// A watermark matching the default prefetch count.
+pub const DEFAULT_WATERMARK: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AckMode {
+    /// Ack on receipt. `Delivery::ack` and `Delivery::nack` do nothing.
+    AutoAck,
+    /// The service acks or nacks every delivery once it is handled.
+    /// Consumption pauses while `watermark` deliveries are unacked.
+    AckOnHandled { watermark: usize },
+    /// For request/reply services: a delivery handed back with its reply,
+    /// see `Reply::to`, is acked once the reply is published, or requeued
+    /// if publishing fails. Pauses like `AckOnHandled`.
+    AckOnPublishOfReply { watermark: usize },
+}
+
+impl Default for AckMode {
+    fn default() -> Self {
+        AckMode::AutoAck
+    }
+}
+
+impl AckMode {
+    /// How many deliveries may be unacked before consumption pauses, none
+    /// for `AutoAck`.
+    pub fn watermark(self) -> Option<usize> {
+        match self {
+            AckMode::AutoAck => None,
+            AckMode::AckOnHandled { watermark } | AckMode::AckOnPublishOfReply { watermark } => {
+                Some(watermark.max(1))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AckKind {
+    Ack,
+    Nack { requeue: bool },
+}
+
+/// What a `Delivery` reports back to the backend which handed it out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AckCommand {
+    pub delivery_tag: u64,
+    pub kind: AckKind,
+}
+
+/// A received message, for the modes where the service acks.
+#[derive(Debug)]
+pub struct Delivery {
+    pub routing_key: String,
+    pub body: Vec<u8>,
+    /// The message was delivered before and not acked, the handler may
+    /// have seen it already.
+    pub redelivered: bool,
+    delivery_tag: u64,
+    acks: Option<Sender<AckCommand>>,
+    idempotency_key: Option<H256>,
+}
+
+impl Delivery {
+    /// `acks` is none when the message was acked on receipt.
+    pub fn new(
+        routing_key: String,
+        body: Vec<u8>,
+        redelivered: bool,
+        delivery_tag: u64,
+        acks: Option<Sender<AckCommand>>,
+    ) -> Self {
+        Delivery {
+            routing_key,
+            body,
+            redelivered,
+            delivery_tag,
+            acks,
+            idempotency_key: None,
+        }
+    }
+
+    /// The key the publisher attached, see `Reply::with_idempotency_key`.
+    pub fn with_idempotency_key(mut self, key: H256) -> Self {
+        self.idempotency_key = Some(key);
+        self
+    }
+
+    pub fn delivery_tag(&self) -> u64 {
+        self.delivery_tag
+    }
+
+    pub fn idempotency_key(&self) -> Option<H256> {
+        self.idempotency_key
+    }
+
+    pub fn ack(self) {
+        self.send(AckKind::Ack);
+    }
+
+    /// Reject the message, `requeue` puts it back to be delivered again.
+    pub fn nack(self, requeue: bool) {
+        self.send(AckKind::Nack { requeue });
+    }
+
+    fn send(self, kind: AckKind) {
+        if let Some(ref acks) = self.acks {
+            // The consumer is gone, the broker will redeliver anyway.
+            let _ = acks.send(AckCommand {
+                delivery_tag: self.delivery_tag,
+                kind,
+            });
+        }
+    }
+}
+
+/// A message to publish, possibly answering a delivery.
+#[derive(Debug)]
+pub struct Reply {
+    pub routing_key: String,
+    pub body: Vec<u8>,
+    pub request: Option<Delivery>,
+    /// What consumers tell copies of the message by, see `idempotency`.
+    pub idempotency_key: Option<H256>,
+}
+
+impl Reply {
+    /// Answer `request`. The publisher acks it once the reply is out.
+    pub fn to(request: Delivery, routing_key: String, body: Vec<u8>) -> Self {
+        Reply {
+            routing_key,
+            body,
+            request: Some(request),
+            idempotency_key: None,
+        }
+    }
+
+    /// Sent with `key`, for the copies of one message to have the same
+    /// key even when their bodies differ, e.g. a result computed again
+    /// after a restart.
+    pub fn with_idempotency_key(mut self, key: H256) -> Self {
+        self.idempotency_key = Some(key);
+        self
+    }
+
+    /// Settle the request after publishing. A failed publish requeues it,
+    /// so the request is handled again instead of being lost.
+    pub fn published(self, ok: bool) {
+        settle(self.request, ok);
+    }
+}
+
+/// What `Reply::published` does, for a reply already taken apart.
+pub fn settle(request: Option<Delivery>, published: bool) {
+    if let Some(request) = request {
+        if published {
+            request.ack();
+        } else {
+            request.nack(true);
+        }
+    }
+}
+
+impl From<(String, Vec<u8>)> for Reply {
+    fn from((routing_key, body): (String, Vec<u8>)) -> Self {
+        Reply {
+            routing_key,
+            body,
+            request: None,
+            idempotency_key: None,
+        }
+    }
+}
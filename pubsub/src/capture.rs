@@ -0,0 +1,730 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Capture MQ traffic to a file and replay it later.
+//!
+//! A capture file starts with a header, followed by length-prefixed records:
+//!
+//! ```text
+//! header: magic(8) chain_id(u32) crypto_len(u8) crypto hash_len(u8) hash
+//!         namespace_len(u8) namespace
+//! record: len(u32) elapsed_us(u64) key_len(u32) key payload crypt_hash(32)
+//! ```
+//!
+//! All integers are little endian. `crypt_hash` covers every byte of the
+//! record before it. Files of the first version, without the namespace,
+//! are still read.
+//!
+//! Keys are kept without the namespace of the chain captured, as its
+//! services saw them, so a capture replays into any namespace.
+
+use crate::channel::{Receiver, Sender};
+use crate::namespace::{Namespace, NamespaceError};
+use cita_types::H256;
+use hashable::Hashable;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::time::Duration;
+
+const MAGIC: &[u8; 8] = b"CITACAP\x02";
+/// Without the namespace in the header.
+const MAGIC_V1: &[u8; 8] = b"CITACAP\x01";
+const HASH_LEN: usize = 32;
+/// The longest record read or written, that of a message at RabbitMQ's
+/// default limit of 128 MiB. A longer length prefix is taken for
+/// corruption rather than allocated.
+pub const MAX_RECORD_LEN: usize = 128 << 20;
+
+pub use hashable::HASH_NAME as HASH_ALGORITHM;
+
+#[derive(Debug)]
+pub enum CaptureError {
+    Io(io::Error),
+    BadMagic,
+    /// The file was hashed with another algorithm than this build uses.
+    HashMismatch(String),
+    /// Record `index` (counting from 0) fails its integrity check.
+    Corrupted(usize),
+    /// The sink was dropped during a replay.
+    Disconnected,
+    /// The header names a namespace which isn't a single word.
+    InvalidNamespace(NamespaceError),
+}
+
+impl fmt::Display for CaptureError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CaptureError::Io(ref err) => write!(f, "capture io error: {}", err),
+            CaptureError::BadMagic => write!(f, "not a capture file"),
+            CaptureError::HashMismatch(ref hash) => write!(
+                f,
+                "capture hashed with {}, this build uses {}",
+                hash, HASH_ALGORITHM
+            ),
+            CaptureError::Corrupted(index) => write!(f, "capture record {} is corrupted", index),
+            CaptureError::Disconnected => write!(f, "replay sink disconnected"),
+            CaptureError::InvalidNamespace(ref err) => write!(f, "capture {}", err),
+        }
+    }
+}
+
+impl ::std::error::Error for CaptureError {}
+
+impl From<io::Error> for CaptureError {
+    fn from(err: io::Error) -> Self {
+        CaptureError::Io(err)
+    }
+}
+
+pub type CaptureResult<T> = Result<T, CaptureError>;
+
+pub use cita_types::clock::{Clock, MockClock, SystemClock};
+
+/// Routing key patterns, with the same wildcards as a topic exchange:
+/// `*` matches exactly one word and `#` matches zero or more words.
+///
+/// An empty filter matches every key.
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    patterns: Vec<String>,
+}
+
+impl Filter {
+    pub fn new<K>(patterns: Vec<K>) -> Self
+    where
+        K: Into<String>,
+    {
+        Filter {
+            patterns: patterns.into_iter().map(Into::into).collect(),
+        }
+    }
+
+    pub fn matches(&self, key: &str) -> bool {
+        if self.patterns.is_empty() {
+            return true;
+        }
+        let words: Vec<&str> = key.split('.').collect();
+        self.patterns.iter().any(|pattern| {
+            let pattern: Vec<&str> = pattern.split('.').collect();
+            topic_matches(&pattern, &words)
+        })
+    }
+}
+
+fn topic_matches(pattern: &[&str], words: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => words.is_empty(),
+        Some((&"#", rest)) => (0..=words.len()).any(|skip| topic_matches(rest, &words[skip..])),
+        Some((first, rest)) => match words.split_first() {
+            Some((word, others)) => (*first == "*" || first == word) && topic_matches(rest, others),
+            None => false,
+        },
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaptureHeader {
+    pub chain_id: u32,
+    /// Name of the crypto feature the capturing node was built with.
+    pub crypto: String,
+    /// Name of the hash feature, which is also the record hash.
+    pub hash: String,
+    /// Namespace of the chain captured, empty without.
+    pub namespace: String,
+}
+
+impl CaptureHeader {
+    pub fn new(chain_id: u32, crypto: &str) -> Self {
+        CaptureHeader {
+            chain_id,
+            crypto: crypto.to_owned(),
+            hash: HASH_ALGORITHM.to_owned(),
+            namespace: String::new(),
+        }
+    }
+
+    pub fn with_namespace(mut self, namespace: &Namespace) -> Self {
+        self.namespace = namespace.name().to_owned();
+        self
+    }
+
+    fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&self.chain_id.to_le_bytes())?;
+        for name in &[&self.crypto, &self.hash, &self.namespace] {
+            writer.write_all(&[name.len() as u8])?;
+            writer.write_all(name.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn read_from<R: Read>(reader: &mut R) -> CaptureResult<Self> {
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC && &magic != MAGIC_V1 {
+            return Err(CaptureError::BadMagic);
+        }
+        let mut chain_id = [0u8; 4];
+        reader.read_exact(&mut chain_id)?;
+        let crypto = read_name(reader)?;
+        let hash = read_name(reader)?;
+        let namespace = if &magic == MAGIC {
+            read_name(reader)?
+        } else {
+            String::new()
+        };
+        Ok(CaptureHeader {
+            chain_id: u32::from_le_bytes(chain_id),
+            crypto,
+            hash,
+            namespace,
+        })
+    }
+}
+
+fn read_name<R: Read>(reader: &mut R) -> CaptureResult<String> {
+    let mut len = [0u8; 1];
+    reader.read_exact(&mut len)?;
+    let mut name = vec![0u8; len[0] as usize];
+    reader.read_exact(&mut name)?;
+    String::from_utf8(name).map_err(|_| CaptureError::BadMagic)
+}
+
+/// One captured message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    /// Time since the recorder was created.
+    pub elapsed: Duration,
+    pub key: String,
+    pub payload: Vec<u8>,
+}
+
+impl Record {
+    fn encode(&self) -> Vec<u8> {
+        let micros = self.elapsed.as_secs() * 1_000_000 + u64::from(self.elapsed.subsec_micros());
+        let mut body = Vec::with_capacity(12 + self.key.len() + self.payload.len() + HASH_LEN);
+        body.extend_from_slice(&micros.to_le_bytes());
+        body.extend_from_slice(&(self.key.len() as u32).to_le_bytes());
+        body.extend_from_slice(self.key.as_bytes());
+        body.extend_from_slice(&self.payload);
+        let hash = body.crypt_hash();
+        body.extend_from_slice(&hash.0);
+        body
+    }
+
+    fn decode(body: &[u8]) -> Option<Self> {
+        if body.len() < 12 + HASH_LEN {
+            return None;
+        }
+        let (data, hash) = body.split_at(body.len() - HASH_LEN);
+        if data.crypt_hash() != H256::from_slice(hash) {
+            return None;
+        }
+        let mut micros = [0u8; 8];
+        micros.copy_from_slice(&data[..8]);
+        let mut key_len = [0u8; 4];
+        key_len.copy_from_slice(&data[8..12]);
+        let key_len = u32::from_le_bytes(key_len) as usize;
+        if 12 + key_len > data.len() {
+            return None;
+        }
+        let key = String::from_utf8(data[12..12 + key_len].to_vec()).ok()?;
+        Some(Record {
+            elapsed: Duration::from_micros(u64::from_le_bytes(micros)),
+            key,
+            payload: data[12 + key_len..].to_vec(),
+        })
+    }
+}
+
+/// Writes consumed messages into a capture.
+pub struct Recorder<W: Write, C: Clock> {
+    writer: W,
+    clock: C,
+    start: Duration,
+    filter: Filter,
+    namespace: Namespace,
+}
+
+impl Recorder<BufWriter<File>, SystemClock> {
+    pub fn create<P: AsRef<Path>>(
+        path: P,
+        header: &CaptureHeader,
+        filter: Filter,
+    ) -> CaptureResult<Self> {
+        let file = File::create(path)?;
+        Recorder::new(BufWriter::new(file), header, filter, SystemClock::default())
+    }
+}
+
+impl<W: Write, C: Clock> Recorder<W, C> {
+    pub fn new(
+        mut writer: W,
+        header: &CaptureHeader,
+        filter: Filter,
+        clock: C,
+    ) -> CaptureResult<Self> {
+        let namespace =
+            Namespace::new(&header.namespace).map_err(CaptureError::InvalidNamespace)?;
+        header.write_to(&mut writer)?;
+        let start = clock.now();
+        Ok(Recorder {
+            writer,
+            clock,
+            start,
+            filter,
+            namespace,
+        })
+    }
+
+    /// Append a message, unless the filter rejects its key.
+    /// Returns whether the message was written.
+    pub fn record(&mut self, key: &str, payload: &[u8]) -> CaptureResult<bool> {
+        if !self.filter.matches(key) {
+            return Ok(false);
+        }
+        let record = Record {
+            elapsed: self.clock.now() - self.start,
+            key: key.to_owned(),
+            payload: payload.to_vec(),
+        };
+        let body = record.encode();
+        if body.len() > MAX_RECORD_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} bytes of {} can't be captured", payload.len(), key),
+            )
+            .into());
+        }
+        self.writer.write_all(&(body.len() as u32).to_le_bytes())?;
+        self.writer.write_all(&body)?;
+        Ok(true)
+    }
+
+    /// Append a message with the key the broker carries, the header's
+    /// namespace stripped. Those of other namespaces aren't written.
+    pub fn record_namespaced(&mut self, key: &str, payload: &[u8]) -> CaptureResult<bool> {
+        match self.namespace.strip(key) {
+            Some(key) => self.record(key, payload),
+            None => Ok(false),
+        }
+    }
+
+    /// Forward everything from `rx` to `tx`, recording it on the way,
+    /// until either side disconnects.
+    pub fn tee(
+        &mut self,
+        rx: &Receiver<(String, Vec<u8>)>,
+        tx: &Sender<(String, Vec<u8>)>,
+    ) -> CaptureResult<()> {
+        for (key, payload) in rx.iter() {
+            self.record(&key, &payload)?;
+            if tx.send((key, payload)).is_err() {
+                break;
+            }
+        }
+        self.flush()
+    }
+
+    pub fn flush(&mut self) -> CaptureResult<()> {
+        self.writer.flush().map_err(Into::into)
+    }
+
+    pub fn into_inner(mut self) -> CaptureResult<W> {
+        self.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// Reads the records of a capture in order, checking each one.
+pub struct CaptureReader<R: Read> {
+    reader: R,
+    header: CaptureHeader,
+    index: usize,
+    failed: bool,
+}
+
+impl CaptureReader<BufReader<File>> {
+    pub fn open<P: AsRef<Path>>(path: P) -> CaptureResult<Self> {
+        let file = File::open(path)?;
+        CaptureReader::new(BufReader::new(file))
+    }
+}
+
+impl<R: Read> CaptureReader<R> {
+    pub fn new(mut reader: R) -> CaptureResult<Self> {
+        let header = CaptureHeader::read_from(&mut reader)?;
+        if header.hash != HASH_ALGORITHM {
+            return Err(CaptureError::HashMismatch(header.hash));
+        }
+        Ok(CaptureReader {
+            reader,
+            header,
+            index: 0,
+            failed: false,
+        })
+    }
+
+    pub fn header(&self) -> &CaptureHeader {
+        &self.header
+    }
+
+    fn read_record(&mut self) -> CaptureResult<Option<Record>> {
+        let mut len = [0u8; 4];
+        match self.reader.read_exact(&mut len) {
+            Ok(()) => {}
+            Err(ref err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err.into()),
+        }
+        let len = u32::from_le_bytes(len) as usize;
+        if len > MAX_RECORD_LEN {
+            return Err(CaptureError::Corrupted(self.index));
+        }
+        // Grown as the bytes come, a truncated file allocates no more than
+        // it holds.
+        let mut body = Vec::new();
+        (&mut self.reader).take(len as u64).read_to_end(&mut body)?;
+        if body.len() < len {
+            return Err(CaptureError::Corrupted(self.index));
+        }
+        Record::decode(&body)
+            .map(Some)
+            .ok_or(CaptureError::Corrupted(self.index))
+    }
+}
+
+impl<R: Read> Iterator for CaptureReader<R> {
+    type Item = CaptureResult<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let ret = self.read_record();
+        self.index += 1;
+        match ret {
+            Ok(record) => record.map(Ok),
+            Err(err) => {
+                // The length prefix may be what got corrupted, so nothing
+                // after a bad record can be trusted.
+                self.failed = true;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+/// Feeds a capture back into a channel, keeping the original pacing.
+pub struct Replayer<C: Clock> {
+    clock: C,
+    filter: Filter,
+    namespace: Namespace,
+}
+
+impl Default for Replayer<SystemClock> {
+    fn default() -> Self {
+        Replayer::new(SystemClock::default(), Filter::default())
+    }
+}
+
+impl<C: Clock> Replayer<C> {
+    pub fn new(clock: C, filter: Filter) -> Self {
+        Replayer {
+            clock,
+            filter,
+            namespace: Namespace::default(),
+        }
+    }
+
+    /// Send the keys in `namespace`, to feed a broker rather than a
+    /// service. The filter still applies to the keys without it.
+    pub fn into_namespace(mut self, namespace: Namespace) -> Self {
+        self.namespace = namespace;
+        self
+    }
+
+    /// Replay the capture at `path` into `sink`.
+    ///
+    /// `speed_factor` 2.0 replays twice as fast as recorded, 0 sends
+    /// everything without waiting. Returns the number of records sent.
+    pub fn replay<P: AsRef<Path>>(
+        &self,
+        path: P,
+        speed_factor: f64,
+        sink: &Sender<(String, Vec<u8>)>,
+    ) -> CaptureResult<usize> {
+        self.replay_from(CaptureReader::open(path)?, speed_factor, sink)
+    }
+
+    pub fn replay_from<R: Read>(
+        &self,
+        reader: CaptureReader<R>,
+        speed_factor: f64,
+        sink: &Sender<(String, Vec<u8>)>,
+    ) -> CaptureResult<usize> {
+        let start = self.clock.now();
+        let mut origin = None;
+        let mut sent = 0;
+        for record in reader {
+            let record = record?;
+            if !self.filter.matches(&record.key) {
+                continue;
+            }
+            let first = *origin.get_or_insert(record.elapsed);
+            if speed_factor > 0.0 {
+                // A clock stepped back sends at once.
+                let offset = record
+                    .elapsed
+                    .checked_sub(first)
+                    .unwrap_or_else(|| Duration::from_secs(0));
+                let micros = offset.as_secs() * 1_000_000 + u64::from(offset.subsec_micros());
+                let target = Duration::from_micros((micros as f64 / speed_factor) as u64);
+                let now = self.clock.now() - start;
+                if target > now {
+                    self.clock.sleep(target - now);
+                }
+            }
+            sink.send((self.namespace.wrap(&record.key), record.payload))
+                .map_err(|_| CaptureError::Disconnected)?;
+            sent += 1;
+        }
+        Ok(sent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channel::unbounded;
+
+    fn capture(filter: Filter) -> Vec<u8> {
+        let clock = MockClock::default();
+        let header = CaptureHeader::new(1, "secp256k1");
+        let mut recorder = Recorder::new(Vec::new(), &header, filter, clock.clone()).unwrap();
+        let (tx, rx) = unbounded();
+        let (tee_tx, tee_rx) = unbounded();
+        for (delay, key) in &[
+            (0, "consensus.msg"),
+            (100, "net.blk"),
+            (250, "consensus.status"),
+        ] {
+            clock.advance(Duration::from_millis(*delay));
+            tx.send((key.to_string(), key.as_bytes().to_vec())).unwrap();
+            recorder.record(key, key.as_bytes()).unwrap();
+        }
+        drop(tx);
+        // Whatever is consumed through tee still reaches the real consumer.
+        let mut passthrough = Recorder::new(Vec::new(), &header, Filter::default(), clock).unwrap();
+        passthrough.tee(&rx, &tee_tx).unwrap();
+        assert_eq!(tee_rx.try_iter().count(), 3);
+        recorder.into_inner().unwrap()
+    }
+
+    #[test]
+    fn filter_patterns() {
+        let filter = Filter::new(vec!["consensus.*", "*.rpc.#"]);
+        assert!(filter.matches("consensus.msg"));
+        assert!(!filter.matches("consensus.msg.extra"));
+        assert!(filter.matches("jsonrpc.rpc"));
+        assert!(filter.matches("jsonrpc.rpc.request.tx"));
+        assert!(!filter.matches("net.blk"));
+        assert!(Filter::default().matches("anything"));
+    }
+
+    #[test]
+    fn round_trip_keeps_timing() {
+        let file = capture(Filter::default());
+        let reader = CaptureReader::new(&file[..]).unwrap();
+        assert_eq!(reader.header(), &CaptureHeader::new(1, "secp256k1"));
+
+        let clock = MockClock::default();
+        let (tx, rx) = unbounded();
+        let replayer = Replayer::new(clock.clone(), Filter::default());
+        assert_eq!(replayer.replay_from(reader, 1.0, &tx).unwrap(), 3);
+        assert_eq!(clock.now(), Duration::from_millis(350));
+        let keys: Vec<String> = rx.try_iter().map(|(key, _)| key).collect();
+        assert_eq!(keys, vec!["consensus.msg", "net.blk", "consensus.status"]);
+
+        let clock = MockClock::default();
+        let replayer = Replayer::new(clock.clone(), Filter::default());
+        let reader = CaptureReader::new(&file[..]).unwrap();
+        replayer.replay_from(reader, 2.0, &tx).unwrap();
+        assert_eq!(clock.now(), Duration::from_millis(175));
+
+        let clock = MockClock::default();
+        let replayer = Replayer::new(clock.clone(), Filter::default());
+        let reader = CaptureReader::new(&file[..]).unwrap();
+        replayer.replay_from(reader, 0.0, &tx).unwrap();
+        assert_eq!(clock.now(), Duration::from_millis(0));
+    }
+
+    #[test]
+    fn filter_on_capture_and_replay() {
+        let file = capture(Filter::new(vec!["consensus.#"]));
+        let reader = CaptureReader::new(&file[..]).unwrap();
+        let elapsed: Vec<Duration> = reader.map(|r| r.unwrap().elapsed).collect();
+        assert_eq!(
+            elapsed,
+            vec![Duration::from_millis(0), Duration::from_millis(350)]
+        );
+
+        let file = capture(Filter::default());
+        let (tx, rx) = unbounded();
+        let clock = MockClock::default();
+        let replayer = Replayer::new(clock.clone(), Filter::new(vec!["net.*"]));
+        let reader = CaptureReader::new(&file[..]).unwrap();
+        assert_eq!(replayer.replay_from(reader, 1.0, &tx).unwrap(), 1);
+        assert_eq!(rx.try_recv().unwrap().0, "net.blk");
+        // Timing is relative to the first replayed record.
+        assert_eq!(clock.now(), Duration::from_millis(0));
+    }
+
+    #[test]
+    fn detect_corrupted_record() {
+        let mut file = capture(Filter::default());
+        // Flip the last payload byte of the second record.
+        let header_len = MAGIC.len() + 4 + 1 + "secp256k1".len() + 1 + HASH_ALGORITHM.len() + 1;
+        let first_len = 4 + 12 + "consensus.msg".len() * 2 + HASH_LEN;
+        let second_payload_end = header_len + first_len + 4 + 12 + "net.blk".len() * 2;
+        file[second_payload_end - 1] ^= 0xff;
+
+        let mut reader = CaptureReader::new(&file[..]).unwrap();
+        assert!(reader.next().unwrap().is_ok());
+        match reader.next() {
+            Some(Err(CaptureError::Corrupted(1))) => {}
+            other => panic!("expected corruption, got {:?}", other),
+        }
+        assert!(reader.next().is_none());
+
+        let (tx, _rx) = unbounded();
+        let replayer = Replayer::new(MockClock::default(), Filter::default());
+        let reader = CaptureReader::new(&file[..]).unwrap();
+        assert!(replayer.replay_from(reader, 0.0, &tx).is_err());
+    }
+
+    /// `capture` of nothing, with `records` appended as written.
+    fn capture_of(records: &[Record]) -> Vec<u8> {
+        let header = CaptureHeader::new(1, "secp256k1");
+        let recorder =
+            Recorder::new(Vec::new(), &header, Filter::default(), MockClock::default()).unwrap();
+        let mut file = recorder.into_inner().unwrap();
+        for record in records {
+            let body = record.encode();
+            file.extend_from_slice(&(body.len() as u32).to_le_bytes());
+            file.extend_from_slice(&body);
+        }
+        file
+    }
+
+    fn record_at(millis: u64, key: &str) -> Record {
+        Record {
+            elapsed: Duration::from_millis(millis),
+            key: key.to_owned(),
+            payload: key.as_bytes().to_vec(),
+        }
+    }
+
+    #[test]
+    fn reject_oversized_records() {
+        let mut file = capture_of(&[record_at(0, "net.blk")]);
+        file.extend_from_slice(&(MAX_RECORD_LEN as u32 + 1).to_le_bytes());
+        file.extend_from_slice(&[0; 64]);
+        let mut reader = CaptureReader::new(&file[..]).unwrap();
+        assert!(reader.next().unwrap().is_ok());
+        match reader.next() {
+            Some(Err(CaptureError::Corrupted(1))) => {}
+            other => panic!("expected corruption, got {:?}", other),
+        }
+
+        // Within the limit, but longer than what is left.
+        let mut file = capture_of(&[]);
+        file.extend_from_slice(&1000u32.to_le_bytes());
+        file.extend_from_slice(&[0; 64]);
+        match CaptureReader::new(&file[..]).unwrap().next() {
+            Some(Err(CaptureError::Corrupted(0))) => {}
+            other => panic!("expected corruption, got {:?}", other),
+        }
+
+        let header = CaptureHeader::new(1, "secp256k1");
+        let mut recorder =
+            Recorder::new(Vec::new(), &header, Filter::default(), MockClock::default()).unwrap();
+        match recorder.record("net.blk", &vec![0; MAX_RECORD_LEN]) {
+            Err(CaptureError::Io(ref err)) if err.kind() == io::ErrorKind::InvalidInput => {}
+            other => panic!("expected too large, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn replay_timestamps_going_backwards() {
+        let file = capture_of(&[
+            record_at(100, "net.a"),
+            record_at(50, "net.b"),
+            record_at(300, "net.c"),
+        ]);
+        let (tx, rx) = unbounded();
+        let clock = MockClock::default();
+        let replayer = Replayer::new(clock.clone(), Filter::default());
+        let reader = CaptureReader::new(&file[..]).unwrap();
+        assert_eq!(replayer.replay_from(reader, 1.0, &tx).unwrap(), 3);
+        let keys: Vec<String> = rx.try_iter().map(|(key, _)| key).collect();
+        assert_eq!(keys, vec!["net.a", "net.b", "net.c"]);
+        assert_eq!(clock.now(), Duration::from_millis(200));
+    }
+
+    #[test]
+    fn reject_other_files() {
+        match CaptureReader::new(&b"not a capture at all"[..]) {
+            Err(CaptureError::BadMagic) => {}
+            _ => panic!("expected bad magic"),
+        }
+    }
+
+    #[test]
+    fn namespaced_capture_replays_elsewhere() {
+        let side = Namespace::new("side").unwrap();
+        let header = CaptureHeader::new(2, "secp256k1").with_namespace(&side);
+        let mut recorder =
+            Recorder::new(Vec::new(), &header, Filter::default(), MockClock::default()).unwrap();
+        assert!(recorder.record_namespaced("side.net.tx", b"own").unwrap());
+        assert!(!recorder
+            .record_namespaced("main.net.tx", b"foreign")
+            .unwrap());
+        let file = recorder.into_inner().unwrap();
+
+        let reader = CaptureReader::new(&file[..]).unwrap();
+        assert_eq!(reader.header().namespace, "side");
+        let (tx, rx) = unbounded();
+        let replayer = Replayer::new(MockClock::default(), Filter::new(vec!["net.*"]))
+            .into_namespace(Namespace::new("staging").unwrap());
+        assert_eq!(replayer.replay_from(reader, 0.0, &tx).unwrap(), 1);
+        assert_eq!(
+            rx.try_recv().unwrap(),
+            ("staging.net.tx".to_owned(), b"own".to_vec())
+        );
+
+        // A file of the first version has no namespace.
+        let mut old = Vec::new();
+        old.extend_from_slice(MAGIC_V1);
+        old.extend_from_slice(&2u32.to_le_bytes());
+        for name in &["secp256k1", HASH_ALGORITHM] {
+            old.push(name.len() as u8);
+            old.extend_from_slice(name.as_bytes());
+        }
+        let reader = CaptureReader::new(&old[..]).unwrap();
+        assert_eq!(reader.header(), &CaptureHeader::new(2, "secp256k1"));
+        assert_eq!(reader.count(), 0);
+    }
+}
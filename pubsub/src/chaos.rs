@@ -0,0 +1,350 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Perturbing what the in-memory broker delivers, for tests of services
+//! which must cope with a misbehaving network.
+//!
+//! A `ChaosPolicy` has rules by routing key pattern, the first matching
+//! the key of a message applies: it may be dropped, duplicated, delayed
+//! on the broker's clock, and put ahead of a few messages queued before
+//! it. Every decision comes from one RNG seeded by the policy and is
+//! logged as a `Perturbation`, so a run with the same seed, publishes and
+//! clock does exactly the same again.
+//!
+//! ```text
+//! let policy = ChaosPolicy::from_seed(7)
+//!     .rule(vec!["consensus.#"], ChaosRule::new().drop(0.1).reorder(3));
+//! broker.set_chaos(policy, clock.clone());
+//! ```
+
+use crate::capture::{Clock, Filter};
+use std::time::Duration;
+
+/// SplitMix64, good enough to shuffle messages and the same everywhere.
+#[derive(Debug, Clone)]
+pub struct ChaosRng {
+    state: u64,
+}
+
+impl ChaosRng {
+    pub fn new(seed: u64) -> Self {
+        ChaosRng { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in `0..n`, zero for an empty range.
+    pub fn below(&mut self, n: u64) -> u64 {
+        if n == 0 {
+            0
+        } else {
+            self.next_u64() % n
+        }
+    }
+
+    /// True with probability `p`.
+    pub fn chance(&mut self, p: f64) -> bool {
+        // The top 53 bits, as many as an f64 holds.
+        ((self.next_u64() >> 11) as f64) < p * (1u64 << 53) as f64
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Latency {
+    None,
+    Fixed(Duration),
+    /// Uniform in `min..=max`, by the millisecond.
+    Uniform {
+        min: Duration,
+        max: Duration,
+    },
+}
+
+impl Latency {
+    fn sample(self, rng: &mut ChaosRng) -> Duration {
+        match self {
+            Latency::None => Duration::from_millis(0),
+            Latency::Fixed(latency) => latency,
+            Latency::Uniform { min, max } => {
+                let min_ms = min.as_millis() as u64;
+                let max_ms = (max.as_millis() as u64).max(min_ms);
+                Duration::from_millis(min_ms + rng.below(max_ms - min_ms + 1))
+            }
+        }
+    }
+}
+
+/// What happens to the messages of the keys a rule is for. Nothing,
+/// until the builder methods say otherwise.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChaosRule {
+    latency: Latency,
+    drop: f64,
+    duplicate: f64,
+    reorder_window: usize,
+}
+
+impl Default for ChaosRule {
+    fn default() -> Self {
+        ChaosRule {
+            latency: Latency::None,
+            drop: 0.0,
+            duplicate: 0.0,
+            reorder_window: 0,
+        }
+    }
+}
+
+impl ChaosRule {
+    pub fn new() -> Self {
+        ChaosRule::default()
+    }
+
+    pub fn latency(mut self, latency: Latency) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    /// Drop a message with probability `p`.
+    pub fn drop(mut self, p: f64) -> Self {
+        self.drop = p;
+        self
+    }
+
+    /// Deliver a message twice with probability `p`.
+    pub fn duplicate(mut self, p: f64) -> Self {
+        self.duplicate = p;
+        self
+    }
+
+    /// Put a message ahead of at most `window` messages queued before it.
+    pub fn reorder(mut self, window: usize) -> Self {
+        self.reorder_window = window;
+        self
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ChaosPolicy {
+    seed: u64,
+    rules: Vec<(Filter, ChaosRule)>,
+}
+
+impl ChaosPolicy {
+    /// A policy without rules, taking its decisions from `seed`.
+    pub fn from_seed(seed: u64) -> Self {
+        ChaosPolicy {
+            seed,
+            rules: Vec::new(),
+        }
+    }
+
+    /// Apply `rule` to the keys matching `patterns`, unless an earlier
+    /// rule matches them too.
+    pub fn rule<K>(mut self, patterns: Vec<K>, rule: ChaosRule) -> Self
+    where
+        K: Into<String>,
+    {
+        self.rules.push((Filter::new(patterns), rule));
+        self
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    fn rule_for(&self, routing_key: &str) -> Option<&ChaosRule> {
+        self.rules
+            .iter()
+            .find(|(filter, _)| filter.matches(routing_key))
+            .map(|(_, rule)| rule)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PerturbationKind {
+    Dropped,
+    Duplicated,
+    Delayed(Duration),
+    /// Queued ahead of `ahead` messages.
+    Reordered {
+        ahead: usize,
+    },
+}
+
+/// One decision of the policy, about the `message`th publish, counted
+/// from 0, as delivered to `queue`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Perturbation {
+    pub message: u64,
+    pub queue: String,
+    pub routing_key: String,
+    pub kind: PerturbationKind,
+}
+
+/// The policy applied by the broker.
+pub(crate) struct Chaos {
+    policy: ChaosPolicy,
+    rng: ChaosRng,
+    clock: Box<dyn Clock + Send>,
+    next_message: u64,
+    log: Vec<Perturbation>,
+}
+
+impl Chaos {
+    pub(crate) fn new<C: Clock + Send + 'static>(policy: ChaosPolicy, clock: C) -> Self {
+        Chaos {
+            rng: ChaosRng::new(policy.seed),
+            policy,
+            clock: Box::new(clock),
+            next_message: 0,
+            log: Vec::new(),
+        }
+    }
+
+    pub(crate) fn now(&self) -> Duration {
+        self.clock.now()
+    }
+
+    /// Number the next publish.
+    pub(crate) fn next_message(&mut self) -> u64 {
+        let message = self.next_message;
+        self.next_message += 1;
+        message
+    }
+
+    pub(crate) fn log(&self) -> &[Perturbation] {
+        &self.log
+    }
+
+    fn record(&mut self, message: u64, queue: &str, routing_key: &str, kind: PerturbationKind) {
+        self.log.push(Perturbation {
+            message,
+            queue: queue.to_owned(),
+            routing_key: routing_key.to_owned(),
+            kind,
+        });
+    }
+
+    /// The delay of each copy of `message` to queue, none if it is
+    /// dropped. A single one without delay when no rule matches.
+    pub(crate) fn copies(&mut self, message: u64, queue: &str, routing_key: &str) -> Vec<Duration> {
+        let rule = match self.policy.rule_for(routing_key) {
+            Some(rule) => rule.clone(),
+            None => return vec![Duration::from_millis(0)],
+        };
+        if self.rng.chance(rule.drop) {
+            self.record(message, queue, routing_key, PerturbationKind::Dropped);
+            return Vec::new();
+        }
+        let mut copies = 1;
+        if self.rng.chance(rule.duplicate) {
+            self.record(message, queue, routing_key, PerturbationKind::Duplicated);
+            copies += 1;
+        }
+        (0..copies)
+            .map(|_| {
+                let delay = rule.latency.sample(&mut self.rng);
+                if delay > Duration::from_millis(0) {
+                    self.record(
+                        message,
+                        queue,
+                        routing_key,
+                        PerturbationKind::Delayed(delay),
+                    );
+                }
+                delay
+            })
+            .collect()
+    }
+
+    /// How many of the `queued` messages to put `message` ahead of.
+    pub(crate) fn ahead(
+        &mut self,
+        message: u64,
+        queue: &str,
+        routing_key: &str,
+        queued: usize,
+    ) -> usize {
+        let window = match self.policy.rule_for(routing_key) {
+            Some(rule) => rule.reorder_window.min(queued),
+            None => return 0,
+        };
+        let ahead = self.rng.below(window as u64 + 1) as usize;
+        if ahead > 0 {
+            self.record(
+                message,
+                queue,
+                routing_key,
+                PerturbationKind::Reordered { ahead },
+            );
+        }
+        ahead
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ChaosPolicy, ChaosRng, ChaosRule, Latency};
+    use std::time::Duration;
+
+    #[test]
+    fn rng_is_seeded() {
+        let draws = |seed| {
+            let mut rng = ChaosRng::new(seed);
+            (0..8).map(|_| rng.below(1000)).collect::<Vec<_>>()
+        };
+        assert_eq!(draws(1), draws(1));
+        assert_ne!(draws(1), draws(2));
+
+        let mut rng = ChaosRng::new(3);
+        assert!((0..100).all(|_| !rng.chance(0.0)));
+        assert!((0..100).all(|_| rng.chance(1.0)));
+        let hits = (0..10_000).filter(|_| rng.chance(0.25)).count();
+        assert!(hits > 2_300 && hits < 2_700, "{}", hits);
+    }
+
+    #[test]
+    fn latency_in_range() {
+        let mut rng = ChaosRng::new(5);
+        let latency = Latency::Uniform {
+            min: Duration::from_millis(10),
+            max: Duration::from_millis(20),
+        };
+        for _ in 0..100 {
+            let delay = latency.sample(&mut rng);
+            assert!(delay >= Duration::from_millis(10) && delay <= Duration::from_millis(20));
+        }
+        assert_eq!(
+            Latency::Fixed(Duration::from_millis(7)).sample(&mut rng),
+            Duration::from_millis(7)
+        );
+    }
+
+    #[test]
+    fn first_matching_rule_applies() {
+        let policy = ChaosPolicy::from_seed(0)
+            .rule(vec!["consensus.vote"], ChaosRule::new().drop(1.0))
+            .rule(vec!["consensus.#"], ChaosRule::new().reorder(2));
+        assert_eq!(policy.rule_for("consensus.vote").unwrap().drop, 1.0);
+        assert_eq!(policy.rule_for("consensus.proposal").unwrap().drop, 0.0);
+        assert!(policy.rule_for("auth.tx").is_none());
+    }
+}
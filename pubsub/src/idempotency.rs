@@ -0,0 +1,443 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Dropping the copies of messages which must not be handled twice.
+//!
+//! The broker delivers a message again when it wasn't acked, and a
+//! publisher restarting may publish it again. For most keys handling a
+//! copy does no harm, for some, like the executed results, it does. A
+//! `Deduplicator` designates those keys and remembers in a `SeenStore`
+//! the messages of them it handled, across restarts with a `FileStore`:
+//!
+//! ```text
+//! match dedup.admit(&delivery) {
+//!     Admission::Duplicate => delivery.ack(),
+//!     admission => {
+//!         handle(&delivery);
+//!         if let Admission::Fresh(key) = admission {
+//!             dedup.handled(key, height)?;
+//!         }
+//!         delivery.ack();
+//!     }
+//! }
+//! ```
+//!
+//! A message is told by the key its publisher attached, see
+//! `Reply::with_idempotency_key`, and by the hash of its body without.
+//! The rabbitmq backend sends the key as the message id, in the modes
+//! where the service acks, as `start_pubsub` hands out bodies only.
+//!
+//! Handled is recorded after handling, so a crash in between has the
+//! message handled again, and only then. Each key is kept with a mark,
+//! the height of the message say, until `prune` is told a watermark past
+//! it, e.g. the finalized height, below which no copy is expected any
+//! more, or is told apart by the service anyway.
+
+use crate::ack::Delivery;
+use crate::capture::Filter;
+use crate::events::{EventKind, Events};
+use cita_types::H256;
+use hashable::Hashable;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Bytes of a `FileStore` record, the key and the mark little endian.
+const RECORD_LEN: usize = 32 + 8;
+
+/// The keys of the messages handled, with their marks.
+pub trait SeenStore {
+    fn contains(&self, key: &H256) -> bool;
+    /// Record `key` at `mark`, kept until a watermark past the mark.
+    fn insert(&mut self, key: H256, mark: u64) -> io::Result<()>;
+    /// Forget the keys with marks below `watermark`, returning how many.
+    fn prune(&mut self, watermark: u64) -> io::Result<usize>;
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Lost with the process, for tests and for keys a restart needn't
+/// remember.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryStore {
+    marks: HashMap<H256, u64>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        MemoryStore::default()
+    }
+}
+
+impl SeenStore for MemoryStore {
+    fn contains(&self, key: &H256) -> bool {
+        self.marks.contains_key(key)
+    }
+
+    fn insert(&mut self, key: H256, mark: u64) -> io::Result<()> {
+        // Recorded again, kept for the later mark.
+        let kept = self.marks.entry(key).or_insert(mark);
+        *kept = (*kept).max(mark);
+        Ok(())
+    }
+
+    fn prune(&mut self, watermark: u64) -> io::Result<usize> {
+        let before = self.marks.len();
+        self.marks.retain(|_, mark| *mark >= watermark);
+        Ok(before - self.marks.len())
+    }
+
+    fn len(&self) -> usize {
+        self.marks.len()
+    }
+}
+
+/// Appends each key to a file, read back on `open`. Pruning rewrites it.
+#[derive(Debug)]
+pub struct FileStore {
+    path: PathBuf,
+    file: File,
+    seen: MemoryStore,
+}
+
+impl FileStore {
+    /// The keys recorded in `path` before, none for a new file. A record
+    /// cut short by a crash is left out.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut seen = MemoryStore::new();
+        if path.exists() {
+            for record in fs::read(&path)?.chunks(RECORD_LEN) {
+                if record.len() == RECORD_LEN {
+                    let mut mark = [0u8; 8];
+                    mark.copy_from_slice(&record[32..]);
+                    seen.insert(H256::from_slice(&record[..32]), u64::from_le_bytes(mark))?;
+                }
+            }
+        }
+        let mut store = FileStore {
+            file: OpenOptions::new().append(true).create(true).open(&path)?,
+            path,
+            seen,
+        };
+        // Without the cut record, which the next one would be read with.
+        store.rewrite()?;
+        Ok(store)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Replace the file by the keys kept, atomically.
+    fn rewrite(&mut self) -> io::Result<()> {
+        let tmp = self.path.with_extension("tmp");
+        {
+            let mut file = File::create(&tmp)?;
+            for (key, mark) in &self.seen.marks {
+                file.write_all(&record(key, *mark))?;
+            }
+            file.sync_all()?;
+        }
+        fs::rename(&tmp, &self.path)?;
+        self.file = OpenOptions::new().append(true).open(&self.path)?;
+        Ok(())
+    }
+}
+
+fn record(key: &H256, mark: u64) -> Vec<u8> {
+    let mut bytes = key.to_vec();
+    bytes.extend_from_slice(&mark.to_le_bytes());
+    bytes
+}
+
+impl SeenStore for FileStore {
+    fn contains(&self, key: &H256) -> bool {
+        self.seen.contains(key)
+    }
+
+    fn insert(&mut self, key: H256, mark: u64) -> io::Result<()> {
+        self.file.write_all(&record(&key, mark))?;
+        self.file.sync_data()?;
+        self.seen.insert(key, mark)
+    }
+
+    fn prune(&mut self, watermark: u64) -> io::Result<usize> {
+        let pruned = self.seen.prune(watermark)?;
+        if pruned > 0 {
+            self.rewrite()?;
+        }
+        Ok(pruned)
+    }
+
+    fn len(&self) -> usize {
+        self.seen.len()
+    }
+}
+
+/// The key `delivery` is told by.
+pub fn key_of(delivery: &Delivery) -> H256 {
+    delivery
+        .idempotency_key()
+        .unwrap_or_else(|| delivery.body.crypt_hash())
+}
+
+/// What `Deduplicator::admit` says of a delivery.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Admission {
+    /// Of a key not designated, handled as always.
+    Undesignated,
+    /// Not handled before, to be recorded with the key once handled.
+    Fresh(H256),
+    /// Handled before, to be acked without handling it.
+    Duplicate,
+}
+
+pub struct Deduplicator<S> {
+    /// None for no keys, an empty `Filter` would match every key.
+    designated: Option<Filter>,
+    store: S,
+    events: Events,
+    suppressed: usize,
+}
+
+impl<S: SeenStore> Deduplicator<S> {
+    /// For the routing keys matching the topic patterns `designated`.
+    pub fn new<K>(designated: Vec<K>, store: S) -> Self
+    where
+        K: Into<String>,
+    {
+        Deduplicator {
+            designated: if designated.is_empty() {
+                None
+            } else {
+                Some(Filter::new(designated))
+            },
+            store,
+            events: Events::none(),
+            suppressed: 0,
+        }
+    }
+
+    /// Telling each duplicate suppressed to `events`.
+    pub fn with_events(mut self, events: Events) -> Self {
+        self.events = events;
+        self
+    }
+
+    pub fn is_designated(&self, routing_key: &str) -> bool {
+        match self.designated {
+            Some(ref designated) => designated.matches(routing_key),
+            None => false,
+        }
+    }
+
+    pub fn admit(&mut self, delivery: &Delivery) -> Admission {
+        if !self.is_designated(&delivery.routing_key) {
+            return Admission::Undesignated;
+        }
+        let key = key_of(delivery);
+        if !self.store.contains(&key) {
+            return Admission::Fresh(key);
+        }
+        self.suppressed += 1;
+        self.events.emit(EventKind::DuplicateSuppressed {
+            routing_key: delivery.routing_key.clone(),
+        });
+        Admission::Duplicate
+    }
+
+    /// Record the message of `key` handled, at `mark`.
+    pub fn handled(&mut self, key: H256, mark: u64) -> io::Result<()> {
+        self.store.insert(key, mark)
+    }
+
+    /// Forget the messages with marks below `watermark`, so copies of
+    /// them are handled again.
+    pub fn prune(&mut self, watermark: u64) -> io::Result<usize> {
+        self.store.prune(watermark)
+    }
+
+    /// Duplicates suppressed since created.
+    pub fn suppressed(&self) -> usize {
+        self.suppressed
+    }
+
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    pub fn into_store(self) -> S {
+        self.store
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Admission, Deduplicator, FileStore, MemoryStore, SeenStore};
+    use crate::ack::{AckMode, Reply};
+    use crate::events::{Events, Status};
+    use crate::memory::MemoryBroker;
+    use cita_types::H256;
+    use std::fs;
+    use std::path::PathBuf;
+
+    const RESULT: &str = "executor.executed_result";
+
+    fn store_path(name: &str) -> PathBuf {
+        let path = ::std::env::temp_dir().join(format!(
+            "pubsub-idempotency-{}-{}",
+            name,
+            ::std::process::id()
+        ));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    /// Handle what is in the queue, returning the bodies handled.
+    fn consume<S: SeenStore>(
+        broker: &MemoryBroker,
+        dedup: &mut Deduplicator<S>,
+        height: u64,
+    ) -> Vec<Vec<u8>> {
+        let mut handled = Vec::new();
+        while let Some(delivery) = broker.next_delivery("chain") {
+            match dedup.admit(&delivery) {
+                Admission::Duplicate => {}
+                admission => {
+                    handled.push(delivery.body.clone());
+                    if let Admission::Fresh(key) = admission {
+                        dedup.handled(key, height).unwrap();
+                    }
+                }
+            }
+            delivery.ack();
+        }
+        handled
+    }
+
+    #[test]
+    fn duplicates_suppressed_across_restarts() {
+        let path = store_path("restart");
+        let broker = MemoryBroker::new();
+        broker.declare(
+            "chain",
+            vec![RESULT],
+            AckMode::AckOnHandled { watermark: 10 },
+        );
+        let (events, rx) = Events::channel();
+        let mut dedup =
+            Deduplicator::new(vec![RESULT], FileStore::open(&path).unwrap()).with_events(events);
+
+        let result = |body: &[u8]| Reply::from((RESULT.to_owned(), body.to_vec()));
+        broker.publish_reply(result(b"height 1"));
+        broker.publish_reply(result(b"height 1"));
+        // Computed again after the executor restarted, the same result.
+        broker.publish_reply(result(b"height 2").with_idempotency_key(H256::from(2)));
+        broker.publish_reply(result(b"height 2, again").with_idempotency_key(H256::from(2)));
+        assert_eq!(
+            consume(&broker, &mut dedup, 1),
+            vec![b"height 1".to_vec(), b"height 2".to_vec()]
+        );
+        assert_eq!(dedup.suppressed(), 2);
+
+        // Handled, and the consumer crashes before acking.
+        broker.publish_reply(result(b"height 3"));
+        let delivery = broker.next_delivery("chain").unwrap();
+        let key = match dedup.admit(&delivery) {
+            Admission::Fresh(key) => key,
+            admission => panic!("unexpected {:?}", admission),
+        };
+        dedup.handled(key, 3).unwrap();
+        broker.crash("chain");
+        drop(dedup);
+
+        let mut dedup = Deduplicator::new(vec![RESULT], FileStore::open(&path).unwrap());
+        assert_eq!(dedup.store().len(), 3);
+        broker.publish(RESULT, b"height 1");
+        broker.publish(RESULT, b"height 4");
+        assert_eq!(consume(&broker, &mut dedup, 4), vec![b"height 4".to_vec()]);
+        assert_eq!(dedup.suppressed(), 2);
+
+        let mut status = Status::default();
+        status.update(&rx);
+        assert_eq!(status.suppressed, 2);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn pruned_below_the_watermark() {
+        let path = store_path("prune");
+        let mut store = FileStore::open(&path).unwrap();
+        for height in 4..8 {
+            store.insert(H256::from(height), height).unwrap();
+        }
+        // Recorded again later, kept for the later mark.
+        store.insert(H256::from(5), 9).unwrap();
+        assert_eq!(store.prune(6).unwrap(), 1);
+        assert!(!store.contains(&H256::from(4)));
+        assert!(store.contains(&H256::from(5)));
+        assert!(store.contains(&H256::from(6)));
+        assert!(store.contains(&H256::from(7)));
+        assert_eq!(store.prune(6).unwrap(), 0);
+
+        // Pruned for good, and a record cut short is left out.
+        store.insert(H256::from(8), 8).unwrap();
+        drop(store);
+        let mut bytes = fs::read(&path).unwrap();
+        bytes.extend_from_slice(&[0xff; 12]);
+        fs::write(&path, bytes).unwrap();
+        let mut store = FileStore::open(&path).unwrap();
+        assert_eq!(store.len(), 4);
+        assert!(!store.contains(&H256::from(4)));
+        store.insert(H256::from(9), 9).unwrap();
+        drop(store);
+        let store = FileStore::open(&path).unwrap();
+        assert_eq!(store.len(), 5);
+        assert!(store.contains(&H256::from(9)));
+
+        let mut store = MemoryStore::new();
+        store.insert(H256::from(1), 1).unwrap();
+        assert_eq!(store.prune(2).unwrap(), 1);
+        assert!(store.is_empty());
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn other_keys_unaffected() {
+        let broker = MemoryBroker::new();
+        broker.declare(
+            "chain",
+            vec![RESULT, "auth.block_txs"],
+            AckMode::AckOnHandled { watermark: 10 },
+        );
+        let mut dedup = Deduplicator::new(vec!["executor.*"], MemoryStore::new());
+        assert!(dedup.is_designated(RESULT));
+        assert!(!dedup.is_designated("auth.block_txs"));
+        let none = Deduplicator::new(Vec::<String>::new(), MemoryStore::new());
+        assert!(!none.is_designated(RESULT));
+
+        broker.publish("auth.block_txs", b"txs");
+        broker.publish("auth.block_txs", b"txs");
+        broker.publish(RESULT, b"txs");
+        broker.publish(RESULT, b"txs");
+        assert_eq!(consume(&broker, &mut dedup, 1).len(), 3);
+        assert_eq!(dedup.suppressed(), 1);
+        assert_eq!(dedup.into_store().len(), 1);
+    }
+}
@@ -13,21 +13,66 @@
 // limitations under the License.
 
 extern crate amqp;
+#[macro_use]
+extern crate cita_logger as logger;
 pub extern crate crossbeam_channel as channel;
+
+pub mod ack;
+pub mod capture;
+pub mod chaos;
+pub mod events;
+pub mod idempotency;
+pub mod memory;
+pub mod namespace;
+pub mod qos;
+pub mod readiness;
+pub mod schema;
+
+use crate::ack::{AckCommand, AckKind, AckMode, Delivery, Reply};
+use crate::capture::SystemClock;
 use crate::channel::Receiver;
 use crate::channel::Sender;
+use crate::events::{Event, EventKind, Events};
+use crate::namespace::{Namespace, NamespaceCounters, NamespaceError};
+use crate::qos::{FlowControl, FlowCounters, Qos, Watermarks};
+use crate::readiness::{Barrier, ReadinessError};
+use crate::schema::{SchemaError, SchemaRegistry, ANNOUNCE_INTERVAL, SCHEMA_KEY};
 use amqp::{protocol, Basic, Channel, Consumer, Session, Table};
+use cita_config::{ConfigError, Layered, Resolved};
+use cita_types::H256;
 use dotenv::dotenv;
+use serde_derive::Deserialize;
 use std::process;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::thread;
+use std::time::Duration;
 
 pub struct Handler {
     tx: Sender<(String, Vec<u8>)>,
+    flow: FlowControl,
+    namespace: Namespace,
 }
 
 impl Handler {
     pub fn new(tx: Sender<(String, Vec<u8>)>) -> Self {
-        Handler { tx }
+        Handler::with_flow(tx, FlowControl::new("", None))
+    }
+
+    /// Pause as `flow` says, with `tx` the forwarding channel.
+    pub fn with_flow(tx: Sender<(String, Vec<u8>)>, flow: FlowControl) -> Self {
+        Handler {
+            tx,
+            flow,
+            namespace: Namespace::default(),
+        }
+    }
+
+    /// Strip `namespace` from the routing keys, see `namespace`.
+    pub fn in_namespace(mut self, namespace: Namespace) -> Self {
+        self.namespace = namespace;
+        self
     }
 }
 
@@ -39,30 +84,303 @@ impl Consumer for Handler {
         _: protocol::basic::BasicProperties,
         body: Vec<u8>,
     ) {
-        let _ = self.tx.send((deliver.routing_key, body));
+        if let Some(key) = self.namespace.consume(deliver.routing_key) {
+            if self.tx.send((key, body)).is_ok() {
+                // The ack waits, so the broker sends no more than the
+                // prefetch while paused.
+                let tx = &self.tx;
+                self.flow.wait(|| tx.len());
+            }
+        }
+        let _ = channel.basic_ack(deliver.delivery_tag, false);
+    }
+}
+
+/// How long the acks of an idle consumer wait to go out, see
+/// `spawn_ack_flusher`.
+pub const ACK_FLUSH_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The content type of the messages waking an idle `AckingHandler`.
+const WAKE_CONTENT_TYPE: &str = "application/x-cita-pubsub-wake";
+
+/// Consumer handing out `Delivery`s, acked as `mode` says.
+pub struct AckingHandler {
+    tx: Sender<Delivery>,
+    mode: AckMode,
+    acks_tx: Sender<AckCommand>,
+    acks_rx: Receiver<AckCommand>,
+    unacked: usize,
+    namespace: Namespace,
+    wake_pending: Arc<AtomicBool>,
+}
+
+impl AckingHandler {
+    pub fn new(tx: Sender<Delivery>, mode: AckMode) -> Self {
+        let (acks_tx, acks_rx) = channel::unbounded();
+        AckingHandler {
+            tx,
+            mode,
+            acks_tx,
+            acks_rx,
+            unacked: 0,
+            namespace: Namespace::default(),
+            wake_pending: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Strip `namespace` from the routing keys, see `namespace`.
+    pub fn in_namespace(mut self, namespace: Namespace) -> Self {
+        self.namespace = namespace;
+        self
+    }
+
+    fn settle(&mut self, channel: &mut Channel, command: AckCommand) {
+        let _ = match command.kind {
+            AckKind::Ack => channel.basic_ack(command.delivery_tag, false),
+            AckKind::Nack { requeue } => channel.basic_nack(command.delivery_tag, false, requeue),
+        };
+        self.unacked = self.unacked.saturating_sub(1);
+    }
+}
+
+impl Consumer for AckingHandler {
+    fn handle_delivery(
+        &mut self,
+        channel: &mut Channel,
+        deliver: protocol::basic::Deliver,
+        properties: protocol::basic::BasicProperties,
+        body: Vec<u8>,
+    ) {
+        // The channel belongs to this thread, so acks the service sent
+        // meanwhile go out from here.
+        while let Ok(command) = self.acks_rx.try_recv() {
+            self.settle(channel, command);
+        }
+        if properties.content_type.as_ref().map(String::as_str) == Some(WAKE_CONTENT_TYPE) {
+            self.wake_pending.store(false, Ordering::SeqCst);
+            let _ = channel.basic_ack(deliver.delivery_tag, false);
+            return;
+        }
+
+        let routing_key = match self.namespace.consume(deliver.routing_key) {
+            Some(key) => key,
+            None => {
+                let _ = channel.basic_ack(deliver.delivery_tag, false);
+                return;
+            }
+        };
+        let watermark = match self.mode.watermark() {
+            Some(watermark) => watermark,
+            None => {
+                let delivery = Delivery::new(
+                    routing_key,
+                    body,
+                    deliver.redelivered,
+                    deliver.delivery_tag,
+                    None,
+                );
+                let _ = self.tx.send(keyed(delivery, &properties));
+                let _ = channel.basic_ack(deliver.delivery_tag, false);
+                return;
+            }
+        };
+
+        let delivery = Delivery::new(
+            routing_key,
+            body,
+            deliver.redelivered,
+            deliver.delivery_tag,
+            Some(self.acks_tx.clone()),
+        );
+        let delivery = keyed(delivery, &properties);
+        self.unacked += 1;
+        if self.tx.send(delivery).is_err() {
+            return;
+        }
+        // Pause consumption until the service catches up. Blocking here
+        // stops reading from the broker, which doesn't send more than the
+        // prefetch count unacked anyway.
+        while self.unacked >= watermark {
+            match self.acks_rx.recv() {
+                Ok(command) => self.settle(channel, command),
+                Err(_) => break,
+            }
+        }
+    }
+}
+
+/// Get the acks `handler` is left with out. Only the consumer thread may
+/// use its channel, and it only runs on a delivery, so when acks wait for
+/// longer than `ACK_FLUSH_INTERVAL` this publishes a wake-up message
+/// straight to `queue`. The handler acks it with the others.
+///
+/// A wake-up taken by another consumer of the queue is lost, the acks go
+/// out with the next message then.
+fn spawn_ack_flusher(mut channel: Channel, queue: String, handler: &AckingHandler) {
+    let acks = handler.acks_rx.clone();
+    let wake_pending = Arc::clone(&handler.wake_pending);
+    let _ = thread::Builder::new()
+        .name("ack flusher".to_string())
+        .spawn(move || {
+            loop {
+                thread::sleep(ACK_FLUSH_INTERVAL);
+                if acks.is_empty() || wake_pending.swap(true, Ordering::SeqCst) {
+                    continue;
+                }
+                let woken = channel.basic_publish(
+                    "",
+                    &queue,
+                    false,
+                    false,
+                    protocol::basic::BasicProperties {
+                        content_type: Some(WAKE_CONTENT_TYPE.to_string()),
+                        ..Default::default()
+                    },
+                    vec![],
+                );
+                if woken.is_err() {
+                    break;
+                }
+            }
+            let _ = channel.close(200, "Bye");
+        });
+}
+
+/// With the idempotency key the publisher sent as the message id.
+fn keyed(delivery: Delivery, properties: &protocol::basic::BasicProperties) -> Delivery {
+    let key = properties
+        .message_id
+        .as_ref()
+        .and_then(|id| H256::from_str(id).ok());
+    match key {
+        Some(key) => delivery.with_idempotency_key(key),
+        None => delivery,
+    }
+}
+
+/// Consumer of the schema announcements, answering peers heard of the
+/// first time, see `schema`.
+pub struct SchemaHandler {
+    registry: SchemaRegistry,
+    announce: Sender<(String, Vec<u8>)>,
+}
+
+impl SchemaHandler {
+    pub fn new(registry: SchemaRegistry, announce: Sender<(String, Vec<u8>)>) -> Self {
+        SchemaHandler { registry, announce }
+    }
+}
+
+impl Consumer for SchemaHandler {
+    fn handle_delivery(
+        &mut self,
+        channel: &mut Channel,
+        deliver: protocol::basic::Deliver,
+        _: protocol::basic::BasicProperties,
+        body: Vec<u8>,
+    ) {
+        if let Some(answer) = self.registry.receive(&body) {
+            let _ = self.announce.send(answer);
+        }
         let _ = channel.basic_ack(deliver.delivery_tag, false);
     }
 }
 
 pub const AMQP_URL: &str = "AMQP_URL";
+/// Prefix of the variables setting `ConnectionConfig` fields, e.g.
+/// `CITA_PUBSUB_PREFETCH`.
+pub const ENV_PREFIX: &str = "CITA_PUBSUB";
+/// The exchange, in front of which goes the namespace.
+const EXCHANGE: &str = "cita";
 
-pub fn start_rabbitmq(
-    name: &str,
-    keys: Vec<String>,
-    tx: Sender<(String, Vec<u8>)>,
-    rx: Receiver<(String, Vec<u8>)>,
-) {
-    let amqp_url = std::env::var(AMQP_URL).unwrap_or_else(|_| panic!("{} must be set", AMQP_URL));
-    let mut session = match Session::open_url(&amqp_url) {
+const CONNECTION_DEFAULTS: &str = "
+prefetch = 10
+prefetch_size = 0
+high_watermark = 0
+low_watermark = 0
+strict_namespace = false
+";
+
+impl Default for Qos {
+    fn default() -> Self {
+        Layered::<ConnectionConfig>::new()
+            .defaults_str(CONNECTION_DEFAULTS)
+            .expect("valid connection defaults")
+            // Has no default, and no part in the qos.
+            .set("amqp_url", "")
+            .build()
+            .expect("valid connection defaults")
+            .into_inner()
+            .qos()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ConnectionConfig {
+    pub amqp_url: String,
+    pub prefetch: u16,
+    pub prefetch_size: u32,
+    /// Depths of the forwarding channel to pause and resume consuming at,
+    /// a high watermark of 0 never pauses.
+    pub high_watermark: usize,
+    pub low_watermark: usize,
+    /// Drop the messages consumed from outside the namespace, see
+    /// `namespace`.
+    pub strict_namespace: bool,
+}
+
+impl ConnectionConfig {
+    /// Defaults and the process environment. `AMQP_URL` is still read,
+    /// and wins over `CITA_PUBSUB_AMQP_URL`.
+    pub fn layered() -> Layered<ConnectionConfig> {
+        ConnectionConfig::layered_from(::std::env::vars().collect())
+    }
+
+    /// Like `layered`, with the variables given explicitly.
+    pub fn layered_from(vars: Vec<(String, String)>) -> Layered<ConnectionConfig> {
+        let amqp_url = vars
+            .iter()
+            .find(|(name, _)| name == AMQP_URL)
+            .map(|(_, value)| value.clone());
+        Layered::new()
+            .defaults_str(CONNECTION_DEFAULTS)
+            .expect("valid connection defaults")
+            .env_from(ENV_PREFIX, vars)
+            .env_var_from(AMQP_URL, "amqp_url", amqp_url)
+    }
+
+    pub fn load() -> Result<Resolved<ConnectionConfig>, ConfigError> {
+        ConnectionConfig::layered().build()
+    }
+
+    pub fn qos(&self) -> Qos {
+        Qos {
+            prefetch_count: self.prefetch,
+            prefetch_size: self.prefetch_size,
+            watermarks: if self.high_watermark == 0 {
+                None
+            } else {
+                Some(Watermarks::new(self.high_watermark, self.low_watermark))
+            },
+        }
+    }
+
+    pub fn namespace(&self, name: &str) -> Result<Namespace, NamespaceError> {
+        Namespace::new(name).map(|namespace| namespace.strict(self.strict_namespace))
+    }
+}
+
+fn open_channel(amqp_url: &str, qos: &Qos, exchange: &str) -> Channel {
+    let mut session = match Session::open_url(amqp_url) {
         Ok(session) => session,
         Err(error) => panic!("failed to open url {} : {:?}", amqp_url, error),
     };
 
     let mut channel = session.open_channel(1).expect("Can't open channel");
-    let _ = channel.basic_prefetch(10);
+    let _ = channel.basic_qos(qos.prefetch_size, qos.prefetch_count, false);
     channel
         .exchange_declare(
-            "cita",
+            exchange,
             "topic",
             false,
             true,
@@ -72,7 +390,19 @@ pub fn start_rabbitmq(
             Table::new(),
         )
         .unwrap();
+    channel
+}
 
+fn spawn_consumer<C>(
+    mut channel: Channel,
+    exchange: &str,
+    name: &str,
+    keys: Vec<String>,
+    callback: C,
+    events: Events,
+) where
+    C: Consumer + 'static,
+{
     //queue: &str, passive: bool, durable: bool, exclusive: bool, auto_delete: bool, nowait: bool, arguments: Table
     channel
         .queue_declare(name, false, true, false, false, false, Table::new())
@@ -80,10 +410,9 @@ pub fn start_rabbitmq(
 
     for key in keys {
         channel
-            .queue_bind(name, "cita", &key, false, Table::new())
+            .queue_bind(name, exchange, &key, false, Table::new())
             .unwrap();
     }
-    let callback = Handler::new(tx);
     //queue: &str, consumer_tag: &str, no_local: bool, no_ack: bool, exclusive: bool, nowait: bool, arguments: Table
     channel
         .basic_consume(callback, name, "", false, false, false, false, Table::new())
@@ -94,31 +423,21 @@ pub fn start_rabbitmq(
         .name("subscriber".to_string())
         .spawn(move || {
             channel.start_consuming();
+            events.emit(EventKind::Disconnected {
+                error: "consumer stopped".to_owned(),
+            });
             let _ = channel.close(200, "Bye");
             // Rabbitmq connection closed, amqp didn't handle this failure
             // So we must exit the process, wait for restart
             process::exit(0);
         });
+}
 
-    let mut session = match Session::open_url(&amqp_url) {
-        Ok(session) => session,
-        Err(error) => panic!("failed to open url {} : {:?}", amqp_url, error),
-    };
-    let mut channel = session.open_channel(1).expect("Can't open channel");
-    let _ = channel.basic_prefetch(10);
-    channel
-        .exchange_declare(
-            "cita",
-            "topic",
-            false,
-            true,
-            false,
-            false,
-            false,
-            Table::new(),
-        )
-        .unwrap();
-
+fn spawn_publisher<T>(mut channel: Channel, namespace: Namespace, rx: Receiver<T>, events: Events)
+where
+    T: Into<Reply> + Send + 'static,
+{
+    let exchange = namespace.wrap(EXCHANGE);
     // thread send msg to mq
     let _ = thread::Builder::new()
         .name("publisher".to_string())
@@ -128,19 +447,29 @@ pub fn start_rabbitmq(
                 if ret.is_err() {
                     break;
                 }
-                let (routing_key, msg) = ret.unwrap();
+                let Reply {
+                    routing_key,
+                    body,
+                    request,
+                    idempotency_key,
+                } = ret.unwrap().into();
                 let ret = channel.basic_publish(
-                    "cita",
-                    &routing_key,
+                    &exchange,
+                    &namespace.wrap(&routing_key),
                     false,
                     false,
                     protocol::basic::BasicProperties {
                         content_type: Some("text".to_string()),
+                        message_id: idempotency_key.map(|key| format!("{:x}", key)),
                         ..Default::default()
                     },
-                    msg,
+                    body,
                 );
-                if ret.is_err() {
+                ack::settle(request, ret.is_ok());
+                if let Err(error) = ret {
+                    events.emit(EventKind::Disconnected {
+                        error: format!("{:?}", error),
+                    });
                     break;
                 }
             }
@@ -151,15 +480,484 @@ pub fn start_rabbitmq(
         });
 }
 
+/// Whether the queue `name` is declared, on a connection of its own since
+/// the broker closes the channel of a passive declare which fails.
+fn queue_exists(amqp_url: &str, name: &str) -> bool {
+    let mut session = match Session::open_url(amqp_url) {
+        Ok(session) => session,
+        Err(_) => return false,
+    };
+    match session.open_channel(1) {
+        Ok(mut channel) => {
+            //queue: &str, passive: bool, durable: bool, exclusive: bool, auto_delete: bool, nowait: bool, arguments: Table
+            let exists = channel
+                .queue_declare(name, true, false, false, false, false, Table::new())
+                .is_ok();
+            let _ = channel.close(200, "Bye");
+            exists
+        }
+        Err(_) => false,
+    }
+}
+
+fn load_connection_config() -> ConnectionConfig {
+    ConnectionConfig::load()
+        .unwrap_or_else(|err| panic!("{}", config_error_message(&err)))
+        .into_inner()
+}
+
+/// The error names the variable or file at fault, only a missing URL has
+/// none to name.
+fn config_error_message(err: &ConfigError) -> String {
+    match *err {
+        ConfigError::Invalid {
+            key: Some(ref key),
+            source: None,
+            ..
+        } if key == "amqp_url" => format!("{} or {}_AMQP_URL must be set", AMQP_URL, ENV_PREFIX),
+        ref err => format!("invalid pubsub config: {}", err),
+    }
+}
+
+/// Declare the exchange and the queue `name` of `namespace`, with `keys`
+/// bound in it.
+fn spawn_namespaced_consumer<C>(
+    channel: Channel,
+    namespace: &Namespace,
+    name: &str,
+    keys: Vec<String>,
+    callback: C,
+    events: Events,
+) where
+    C: Consumer + 'static,
+{
+    let keys = keys.iter().map(|key| namespace.wrap(key)).collect();
+    spawn_consumer(
+        channel,
+        &namespace.wrap(EXCHANGE),
+        &namespace.wrap(name),
+        keys,
+        callback,
+        events,
+    );
+}
+
+pub fn start_rabbitmq(
+    namespace: &str,
+    name: &str,
+    keys: Vec<String>,
+    tx: Sender<(String, Vec<u8>)>,
+    rx: Receiver<(String, Vec<u8>)>,
+) -> Result<(), NamespaceError> {
+    let config = load_connection_config();
+    let qos = config.qos();
+    let namespace = config.namespace(namespace)?;
+    start_rabbitmq_with_qos(
+        &config.amqp_url,
+        &namespace,
+        name,
+        keys,
+        qos,
+        tx,
+        rx,
+        Events::none(),
+    );
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+fn start_rabbitmq_with_qos(
+    amqp_url: &str,
+    namespace: &Namespace,
+    name: &str,
+    keys: Vec<String>,
+    qos: Qos,
+    tx: Sender<(String, Vec<u8>)>,
+    rx: Receiver<(String, Vec<u8>)>,
+    events: Events,
+) -> FlowCounters {
+    let flow = FlowControl::new(name, qos.watermarks);
+    let counters = flow.counters();
+    let exchange = namespace.wrap(EXCHANGE);
+    let consumer = open_channel(amqp_url, &qos, &exchange);
+    let publisher = open_channel(amqp_url, &qos, &exchange);
+    events.emit(EventKind::Connected {
+        endpoint: events::redact_url(amqp_url),
+    });
+    spawn_namespaced_consumer(
+        consumer,
+        namespace,
+        name,
+        keys,
+        Handler::with_flow(tx, flow).in_namespace(namespace.clone()),
+        events.clone(),
+    );
+    spawn_publisher(publisher, namespace.clone(), rx, events);
+    counters
+}
+
+/// Like `start_rabbitmq`, with deliveries acked as `mode` says. In the
+/// modes where the service acks, the prefetch count is the watermark.
+pub fn start_rabbitmq_with_ack(
+    namespace: &str,
+    name: &str,
+    keys: Vec<String>,
+    mode: AckMode,
+    tx: Sender<Delivery>,
+    rx: Receiver<Reply>,
+) -> Result<(), NamespaceError> {
+    let config = load_connection_config();
+    let namespace = config.namespace(namespace)?;
+    let exchange = namespace.wrap(EXCHANGE);
+    let mut qos = config.qos();
+    let publisher_qos = qos;
+    if let Some(watermark) = mode.watermark() {
+        qos.prefetch_count = watermark.min(u16::max_value() as usize) as u16;
+    }
+    let channel = open_channel(&config.amqp_url, &qos, &exchange);
+    let handler = AckingHandler::new(tx, mode).in_namespace(namespace.clone());
+    if mode.watermark().is_some() {
+        let flusher = open_channel(&config.amqp_url, &publisher_qos, &exchange);
+        spawn_ack_flusher(flusher, namespace.wrap(name), &handler);
+    }
+    spawn_namespaced_consumer(channel, &namespace, name, keys, handler, Events::none());
+    let channel = open_channel(&config.amqp_url, &publisher_qos, &exchange);
+    spawn_publisher(channel, namespace, rx, Events::none());
+    Ok(())
+}
+
+/// Consume `keys` into the queue `name`, and publish what comes from
+/// `rx`, all in `namespace`, see `namespace`. Chains sharing a broker
+/// each have their own, derived from the chain id, the empty one leaves
+/// the names as they are. Nothing is started if `namespace` isn't a
+/// single word.
 pub fn start_pubsub<K>(
+    namespace: &str,
     name: &str,
     keys: Vec<K>,
     tx: Sender<(String, Vec<u8>)>,
     rx: Receiver<(String, Vec<u8>)>,
-) where
+) -> Result<(), NamespaceError>
+where
     K: Into<String>,
 {
     dotenv().ok();
     let keys: Vec<String> = keys.into_iter().map(Into::into).collect();
-    start_rabbitmq(name, keys, tx, rx);
+    start_rabbitmq(namespace, name, keys, tx, rx)
+}
+
+/// Like `start_pubsub`, strict as `namespace` says instead of the
+/// configuration. The counters tell how many messages came from outside
+/// the namespace.
+pub fn start_pubsub_with_namespace<K>(
+    namespace: Namespace,
+    name: &str,
+    keys: Vec<K>,
+    tx: Sender<(String, Vec<u8>)>,
+    rx: Receiver<(String, Vec<u8>)>,
+) -> NamespaceCounters
+where
+    K: Into<String>,
+{
+    dotenv().ok();
+    let keys: Vec<String> = keys.into_iter().map(Into::into).collect();
+    let config = load_connection_config();
+    start_rabbitmq_with_qos(
+        &config.amqp_url,
+        &namespace,
+        name,
+        keys,
+        config.qos(),
+        tx,
+        rx,
+        Events::none(),
+    );
+    namespace.counters()
+}
+
+/// Like `start_pubsub`, with `qos` instead of the configured one. The
+/// counters tell how often the consumer paused, and wake it once messages
+/// are taken out of `tx`, see `qos`.
+pub fn start_pubsub_with_qos<K>(
+    namespace: &str,
+    name: &str,
+    keys: Vec<K>,
+    qos: Qos,
+    tx: Sender<(String, Vec<u8>)>,
+    rx: Receiver<(String, Vec<u8>)>,
+) -> Result<FlowCounters, NamespaceError>
+where
+    K: Into<String>,
+{
+    dotenv().ok();
+    let keys: Vec<String> = keys.into_iter().map(Into::into).collect();
+    let config = load_connection_config();
+    let namespace = config.namespace(namespace)?;
+    Ok(start_rabbitmq_with_qos(
+        &config.amqp_url,
+        &namespace,
+        name,
+        keys,
+        qos,
+        tx,
+        rx,
+        Events::none(),
+    ))
+}
+
+/// Like `start_pubsub`, with the connection's events sent to the
+/// returned channel, see `events`.
+///
+/// The rabbitmq backend doesn't reconnect: after `Disconnected` the
+/// process exits to be restarted, as with `start_pubsub`. Publishes wait
+/// in `rx` meanwhile, so its buffer doesn't overflow either. For the
+/// other events see `memory::MemoryBroker`.
+pub fn start_pubsub_with_events<K>(
+    namespace: &str,
+    name: &str,
+    keys: Vec<K>,
+    tx: Sender<(String, Vec<u8>)>,
+    rx: Receiver<(String, Vec<u8>)>,
+) -> Result<Receiver<Event>, NamespaceError>
+where
+    K: Into<String>,
+{
+    dotenv().ok();
+    let keys: Vec<String> = keys.into_iter().map(Into::into).collect();
+    let config = load_connection_config();
+    let namespace = config.namespace(namespace)?;
+    let (events, events_rx) = Events::channel();
+    start_rabbitmq_with_qos(
+        &config.amqp_url,
+        &namespace,
+        name,
+        keys,
+        config.qos(),
+        tx,
+        rx,
+        events,
+    );
+    Ok(events_rx)
+}
+
+/// Announce the schema of `registry` and collect those of the peers, on
+/// connections of their own.
+fn start_schema_exchange(
+    amqp_url: &str,
+    namespace: &Namespace,
+    name: &str,
+    qos: &Qos,
+    registry: SchemaRegistry,
+) {
+    let exchange = namespace.wrap(EXCHANGE);
+    let consumer = open_channel(amqp_url, qos, &exchange);
+    let publisher = open_channel(amqp_url, qos, &exchange);
+    let (announce_tx, announce_rx) = channel::unbounded();
+    spawn_namespaced_consumer(
+        consumer,
+        namespace,
+        &schema::schema_queue(name),
+        vec![SCHEMA_KEY.to_owned()],
+        SchemaHandler::new(registry.clone(), announce_tx.clone()),
+        Events::none(),
+    );
+    spawn_publisher(publisher, namespace.clone(), announce_rx, Events::none());
+    schema::spawn_announcer(registry, announce_tx, ANNOUNCE_INTERVAL);
+}
+
+/// Like `start_pubsub`, announcing the schema of `registry` and
+/// collecting the peers', see `schema`.
+///
+/// The announcements are exchanged first. A strict registry then waits
+/// `settle` for the peers, and if a critical one is incompatible the
+/// service isn't started and the error says why. The announcements go on
+/// either way, `compatibility_report` of `registry` tells what is known.
+pub fn start_pubsub_with_schema<K>(
+    namespace: &str,
+    name: &str,
+    keys: Vec<K>,
+    registry: SchemaRegistry,
+    settle: Duration,
+    tx: Sender<(String, Vec<u8>)>,
+    rx: Receiver<(String, Vec<u8>)>,
+) -> Result<(), SchemaError>
+where
+    K: Into<String>,
+{
+    dotenv().ok();
+    let keys: Vec<String> = keys.into_iter().map(Into::into).collect();
+    let config = load_connection_config();
+    let namespace = config.namespace(namespace)?;
+    let qos = config.qos();
+    start_schema_exchange(&config.amqp_url, &namespace, name, &qos, registry.clone());
+    if registry.is_strict() {
+        thread::sleep(settle);
+    }
+    if let Err(err) = registry.check() {
+        warn!("{} not started: {}", name, err);
+        return Err(err);
+    }
+    start_rabbitmq_with_qos(
+        &config.amqp_url,
+        &namespace,
+        name,
+        keys,
+        qos,
+        tx,
+        rx,
+        Events::none(),
+    );
+    Ok(())
+}
+
+/// Like `start_pubsub_with_events`, publishing only once the queues of
+/// the peers `barrier` requires are declared, see `readiness`.
+///
+/// The queue `name` is declared and consumed first, so peers waiting for
+/// it see it. If the barrier times out and fails nothing is published
+/// and the error says who is missing, the service is expected to exit.
+/// A queue is declared before it is bound to its keys, so a peer just
+/// found may still miss a message for the moment it takes.
+pub fn start_pubsub_with_required<K>(
+    namespace: &str,
+    name: &str,
+    keys: Vec<K>,
+    barrier: &Barrier,
+    tx: Sender<(String, Vec<u8>)>,
+    rx: Receiver<(String, Vec<u8>)>,
+) -> Result<Receiver<Event>, ReadinessError>
+where
+    K: Into<String>,
+{
+    dotenv().ok();
+    let keys: Vec<String> = keys.into_iter().map(Into::into).collect();
+    let config = load_connection_config();
+    let namespace = config.namespace(namespace)?;
+    let qos = config.qos();
+    let (events, events_rx) = Events::channel();
+    let exchange = namespace.wrap(EXCHANGE);
+    let consumer = open_channel(&config.amqp_url, &qos, &exchange);
+    events.emit(EventKind::Connected {
+        endpoint: events::redact_url(&config.amqp_url),
+    });
+    let flow = FlowControl::new(name, qos.watermarks);
+    spawn_namespaced_consumer(
+        consumer,
+        &namespace,
+        name,
+        keys,
+        Handler::with_flow(tx, flow).in_namespace(namespace.clone()),
+        events.clone(),
+    );
+
+    let ready = barrier.wait(&SystemClock::default(), &events, |peer| {
+        queue_exists(&config.amqp_url, &namespace.wrap(peer))
+    });
+    if let Err(err) = ready {
+        warn!("{} not started: {}", name, err);
+        return Err(err);
+    }
+    let publisher = open_channel(&config.amqp_url, &qos, &exchange);
+    spawn_publisher(publisher, namespace, rx, events);
+    Ok(events_rx)
+}
+
+/// Like `start_pubsub`, see `ack` for the modes.
+pub fn start_pubsub_with_ack<K>(
+    namespace: &str,
+    name: &str,
+    keys: Vec<K>,
+    mode: AckMode,
+    tx: Sender<Delivery>,
+    rx: Receiver<Reply>,
+) -> Result<(), NamespaceError>
+where
+    K: Into<String>,
+{
+    dotenv().ok();
+    let keys: Vec<String> = keys.into_iter().map(Into::into).collect();
+    start_rabbitmq_with_ack(namespace, name, keys, mode, tx, rx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{config_error_message, ConnectionConfig, AMQP_URL};
+    use crate::qos::{Qos, Watermarks};
+    use cita_config::Source;
+
+    fn vars(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn connection_config() {
+        let resolved = ConnectionConfig::layered_from(vars(&[
+            ("CITA_PUBSUB_AMQP_URL", "amqp://prefixed"),
+            (AMQP_URL, "amqp://localhost/dev"),
+        ]))
+        .build()
+        .unwrap();
+        assert_eq!(
+            resolved.config(),
+            &ConnectionConfig {
+                amqp_url: "amqp://localhost/dev".to_owned(),
+                prefetch: 10,
+                prefetch_size: 0,
+                high_watermark: 0,
+                low_watermark: 0,
+                strict_namespace: false,
+            }
+        );
+        assert_eq!(resolved.config().qos(), Qos::default());
+        assert_eq!(
+            resolved.source("amqp_url"),
+            Some(&Source::Environment(AMQP_URL.to_owned()))
+        );
+
+        let resolved = ConnectionConfig::layered_from(vars(&[
+            ("CITA_PUBSUB_AMQP_URL", "amqp://prefixed"),
+            ("CITA_PUBSUB_PREFETCH", "100"),
+        ]))
+        .set("prefetch", "20")
+        .build()
+        .unwrap();
+        assert_eq!(resolved.config().amqp_url, "amqp://prefixed");
+        assert_eq!(resolved.config().prefetch, 20);
+        assert_eq!(resolved.source("prefetch"), Some(&Source::Override));
+
+        let resolved = ConnectionConfig::layered_from(vars(&[
+            ("CITA_PUBSUB_AMQP_URL", "amqp://prefixed"),
+            ("CITA_PUBSUB_PREFETCH_SIZE", "65536"),
+            ("CITA_PUBSUB_HIGH_WATERMARK", "1000"),
+            ("CITA_PUBSUB_LOW_WATERMARK", "100"),
+        ]))
+        .build()
+        .unwrap();
+        assert_eq!(
+            resolved.config().qos(),
+            Qos {
+                prefetch_count: 10,
+                prefetch_size: 65536,
+                watermarks: Some(Watermarks::new(1000, 100)),
+            }
+        );
+
+        let missing = ConnectionConfig::layered_from(Vec::new()).build();
+        assert_eq!(
+            config_error_message(&missing.err().unwrap()),
+            "AMQP_URL or CITA_PUBSUB_AMQP_URL must be set"
+        );
+        let invalid = ConnectionConfig::layered_from(vars(&[
+            (AMQP_URL, "amqp://localhost/dev"),
+            ("CITA_PUBSUB_PREFETCH", "lots"),
+        ]))
+        .build();
+        assert_eq!(
+            config_error_message(&invalid.err().unwrap()),
+            "invalid pubsub config: prefetch from env CITA_PUBSUB_PREFETCH: \
+             expected integer, found \"lots\""
+        );
+    }
 }
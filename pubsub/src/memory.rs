@@ -0,0 +1,1257 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! An in-memory broker behaving like the rabbitmq backend, for tests.
+//!
+//! Queues are bound to topic keys on one exchange. Deliveries follow the
+//! queue's `AckMode`: unacked ones are kept until acked, nacked with
+//! requeue go back to the front of the queue, and `crash` puts every
+//! unacked delivery back as if the consumer had died.
+//!
+//! `forward` consumes like the rabbitmq `Handler`, with the queue's
+//! watermark as the prefetch count.
+//!
+//! The broker also stands for the service's connection to it, which
+//! `disconnect` and `reconnect` drop and restore, reporting it like the
+//! other backends do to `start_with_events`.
+//!
+//! With `set_chaos` the messages published are perturbed as the
+//! `ChaosPolicy` says, delayed ones are queued once the clock passed
+//! their time.
+//!
+//! `in_namespace` gives the view of a broker one chain of several sharing
+//! it has, see `namespace`.
+//!
+//! `deliver_schemas` exchanges the schema announcements, see `schema`.
+//!
+//! `wait_for_peers` waits for the peers to declare their queues, see
+//! `readiness`.
+//!
+//! A reply's idempotency key goes with its deliveries, see
+//! `idempotency`.
+
+use crate::ack::{AckCommand, AckKind, AckMode, Delivery, Reply};
+use crate::capture::{Clock, Filter};
+use crate::channel::{self, Receiver, Sender};
+use crate::chaos::{Chaos, ChaosPolicy, Perturbation};
+use crate::events::{self, Event, EventKind, Events};
+use crate::namespace::Namespace;
+use crate::qos::FlowControl;
+use crate::readiness::{Barrier, ReadinessError};
+use crate::schema::SchemaRegistry;
+use cita_types::H256;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::mem;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Replies kept while disconnected, unless `start_with_events` says.
+pub const DEFAULT_PUBLISH_BUFFER: usize = 1024;
+
+#[derive(Debug, Clone)]
+struct Message {
+    routing_key: String,
+    body: Vec<u8>,
+    redelivered: bool,
+    idempotency_key: Option<H256>,
+}
+
+struct Queue {
+    keys: Vec<String>,
+    /// None without bindings, an empty `Filter` would match every key.
+    bindings: Option<Filter>,
+    mode: AckMode,
+    ready: VecDeque<Message>,
+    /// Delayed by chaos, by due time, publish and copy.
+    delayed: BTreeMap<(Duration, u64, usize), Message>,
+    unacked: BTreeMap<u64, Message>,
+    next_tag: u64,
+    acks_tx: Sender<AckCommand>,
+    acks_rx: Receiver<AckCommand>,
+}
+
+impl Message {
+    fn new(routing_key: &str, body: &[u8], idempotency_key: Option<H256>) -> Self {
+        Message {
+            routing_key: routing_key.to_owned(),
+            body: body.to_vec(),
+            redelivered: false,
+            idempotency_key,
+        }
+    }
+}
+
+impl Queue {
+    fn new(keys: Vec<String>, mode: AckMode) -> Self {
+        let (acks_tx, acks_rx) = channel::unbounded();
+        Queue {
+            bindings: if keys.is_empty() {
+                None
+            } else {
+                Some(Filter::new(keys.clone()))
+            },
+            keys,
+            mode,
+            ready: VecDeque::new(),
+            delayed: BTreeMap::new(),
+            unacked: BTreeMap::new(),
+            next_tag: 1,
+            acks_tx,
+            acks_rx,
+        }
+    }
+
+    fn is_bound(&self, routing_key: &str) -> bool {
+        match self.bindings {
+            Some(ref bindings) => bindings.matches(routing_key),
+            None => false,
+        }
+    }
+
+    /// Queue `message`, of the publish `id`, as far ahead of those queued
+    /// already as chaos says.
+    fn enqueue(&mut self, chaos: &mut Chaos, name: &str, id: u64, message: Message) {
+        let ahead = chaos.ahead(id, name, &message.routing_key, self.ready.len());
+        let at = self.ready.len() - ahead;
+        self.ready.insert(at, message);
+    }
+
+    fn settle(&mut self) {
+        while let Ok(command) = self.acks_rx.try_recv() {
+            // Tags of deliveries made before a crash are gone.
+            if let Some(mut message) = self.unacked.remove(&command.delivery_tag) {
+                if command.kind == (AckKind::Nack { requeue: true }) {
+                    message.redelivered = true;
+                    self.ready.push_front(message);
+                }
+            }
+        }
+    }
+
+    fn deliver(&mut self) -> Option<Delivery> {
+        self.settle();
+        if let Some(watermark) = self.mode.watermark() {
+            if self.unacked.len() >= watermark {
+                return None;
+            }
+        }
+        let message = self.ready.pop_front()?;
+        let tag = self.next_tag;
+        self.next_tag += 1;
+        let acks = match self.mode {
+            AckMode::AutoAck => None,
+            _ => {
+                self.unacked.insert(tag, message.clone());
+                Some(self.acks_tx.clone())
+            }
+        };
+        let delivery = Delivery::new(
+            message.routing_key,
+            message.body,
+            message.redelivered,
+            tag,
+            acks,
+        );
+        Some(match message.idempotency_key {
+            Some(key) => delivery.with_idempotency_key(key),
+            None => delivery,
+        })
+    }
+
+    fn crash(&mut self) {
+        // A new channel, so acks from the dead consumer don't arrive.
+        let (acks_tx, acks_rx) = channel::unbounded();
+        self.acks_tx = acks_tx;
+        self.acks_rx = acks_rx;
+        let unacked = mem::replace(&mut self.unacked, Default::default());
+        for (_, mut message) in unacked.into_iter().rev() {
+            message.redelivered = true;
+            self.ready.push_front(message);
+        }
+    }
+}
+
+struct Connection {
+    events: Events,
+    endpoint: String,
+    connected: bool,
+    /// Replies published while disconnected.
+    outbox: VecDeque<Reply>,
+    capacity: usize,
+}
+
+impl Default for Connection {
+    fn default() -> Self {
+        Connection {
+            events: Events::none(),
+            endpoint: String::new(),
+            connected: true,
+            outbox: VecDeque::new(),
+            capacity: DEFAULT_PUBLISH_BUFFER,
+        }
+    }
+}
+
+impl Connection {
+    /// Keep `reply` for after the reconnect, dropping the oldest one kept
+    /// when the buffer is full.
+    fn buffer(&mut self, reply: Reply) {
+        let dropped = if self.capacity == 0 {
+            Some(reply)
+        } else {
+            let oldest = if self.outbox.len() >= self.capacity {
+                self.outbox.pop_front()
+            } else {
+                None
+            };
+            self.outbox.push_back(reply);
+            oldest
+        };
+        if let Some(dropped) = dropped {
+            dropped.published(false);
+            self.events
+                .emit(EventKind::PublishBufferOverflow { dropped: 1 });
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryBroker {
+    /// Locked before `chaos`, and that before `queues`.
+    connection: Mutex<Connection>,
+    chaos: Mutex<Option<Chaos>>,
+    /// By name, so chaos goes through them in the same order every time.
+    queues: Mutex<BTreeMap<String, Queue>>,
+}
+
+impl MemoryBroker {
+    pub fn new() -> Self {
+        MemoryBroker::default()
+    }
+
+    /// Declare `name` bound to `keys`. Declaring it again replaces it.
+    pub fn declare<K>(&self, name: &str, keys: Vec<K>, mode: AckMode)
+    where
+        K: Into<String>,
+    {
+        let keys: Vec<String> = keys.into_iter().map(Into::into).collect();
+        let queue = Queue::new(keys, mode);
+        self.queues.lock().unwrap().insert(name.to_owned(), queue);
+    }
+
+    pub fn publish(&self, routing_key: &str, body: &[u8]) {
+        self.publish_keyed(routing_key, body, None);
+    }
+
+    /// Like `publish`, the deliveries with `idempotency_key`.
+    fn publish_keyed(&self, routing_key: &str, body: &[u8], idempotency_key: Option<H256>) {
+        let mut chaos = self.chaos.lock().unwrap();
+        let mut queues = self.queues.lock().unwrap();
+        let chaos = match *chaos {
+            Some(ref mut chaos) => chaos,
+            None => {
+                for queue in queues.values_mut() {
+                    if queue.is_bound(routing_key) {
+                        let message = Message::new(routing_key, body, idempotency_key);
+                        queue.ready.push_back(message);
+                    }
+                }
+                return;
+            }
+        };
+
+        let id = chaos.next_message();
+        let now = chaos.now();
+        for (name, queue) in queues.iter_mut() {
+            if !queue.is_bound(routing_key) {
+                continue;
+            }
+            for (copy, delay) in chaos.copies(id, name, routing_key).into_iter().enumerate() {
+                let message = Message::new(routing_key, body, idempotency_key);
+                if delay > Duration::from_millis(0) {
+                    queue.delayed.insert((now + delay, id, copy), message);
+                } else {
+                    queue.enqueue(chaos, name, id, message);
+                }
+            }
+        }
+    }
+
+    /// Queue a message in `queue` whatever its bindings, as a binding
+    /// made by hand or a publisher which got the exchange wrong would.
+    pub fn inject(&self, queue: &str, routing_key: &str, body: &[u8]) {
+        if let Some(queue) = self.queues.lock().unwrap().get_mut(queue) {
+            queue.ready.push_back(Message::new(routing_key, body, None));
+        }
+    }
+
+    /// The broker as the services of `namespace` see it.
+    pub fn in_namespace(&self, namespace: Namespace) -> NamespacedBroker<'_> {
+        NamespacedBroker {
+            broker: self,
+            namespace,
+        }
+    }
+
+    /// Perturb what is published from now on as `policy` says, with
+    /// `clock` for the delays.
+    pub fn set_chaos<C>(&self, policy: ChaosPolicy, clock: C)
+    where
+        C: Clock + Send + 'static,
+    {
+        *self.chaos.lock().unwrap() = Some(Chaos::new(policy, clock));
+    }
+
+    /// What the chaos policy did so far.
+    pub fn perturbations(&self) -> Vec<Perturbation> {
+        match *self.chaos.lock().unwrap() {
+            Some(ref chaos) => chaos.log().to_vec(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Queue the delayed messages whose time has come.
+    fn release_delayed(&self) {
+        let mut chaos = self.chaos.lock().unwrap();
+        let chaos = match *chaos {
+            Some(ref mut chaos) => chaos,
+            None => return,
+        };
+        let now = chaos.now();
+        let mut queues = self.queues.lock().unwrap();
+        for (name, queue) in queues.iter_mut() {
+            let later = queue
+                .delayed
+                .split_off(&(now + Duration::from_nanos(1), 0, 0));
+            for ((_, id, _), message) in mem::replace(&mut queue.delayed, later) {
+                queue.enqueue(chaos, name, id, message);
+            }
+        }
+    }
+
+    /// Publish like the rabbitmq publisher thread, settling the request
+    /// the reply answers. While disconnected the reply waits for the
+    /// reconnect.
+    pub fn publish_reply(&self, reply: Reply) {
+        let mut connection = self.connection.lock().unwrap();
+        if connection.connected {
+            self.publish_keyed(&reply.routing_key, &reply.body, reply.idempotency_key);
+            reply.published(true);
+        } else {
+            connection.buffer(reply);
+        }
+    }
+
+    /// The next delivery of `queue`, none when it is empty, consumption
+    /// is paused at the watermark, or the connection is down.
+    pub fn next_delivery(&self, queue: &str) -> Option<Delivery> {
+        if !self.connection.lock().unwrap().connected {
+            return None;
+        }
+        self.release_delayed();
+        self.queues.lock().unwrap().get_mut(queue)?.deliver()
+    }
+
+    /// Send the connection's events to the returned channel, starting
+    /// with `Connected`. At most `capacity` replies are kept while
+    /// disconnected.
+    pub fn start_with_events(&self, endpoint: &str, capacity: usize) -> Receiver<Event> {
+        let (events, rx) = Events::channel();
+        let mut connection = self.connection.lock().unwrap();
+        *connection = Connection {
+            events,
+            endpoint: endpoint.to_owned(),
+            capacity,
+            ..Connection::default()
+        };
+        connection.events.emit(EventKind::Connected {
+            endpoint: endpoint.to_owned(),
+        });
+        rx
+    }
+
+    /// Lose the connection: nothing is delivered, every unacked delivery
+    /// is requeued as after a crash, and replies are buffered.
+    pub fn disconnect(&self, error: &str) {
+        let mut connection = self.connection.lock().unwrap();
+        if !connection.connected {
+            return;
+        }
+        connection.connected = false;
+        for queue in self.queues.lock().unwrap().values_mut() {
+            queue.crash();
+        }
+        connection.events.emit(EventKind::Disconnected {
+            error: error.to_owned(),
+        });
+    }
+
+    /// Get the connection back after `failed_attempts` attempts which
+    /// didn't, restoring the bindings and publishing the buffered
+    /// replies.
+    pub fn reconnect(&self, failed_attempts: u32) {
+        let mut connection = self.connection.lock().unwrap();
+        if connection.connected {
+            return;
+        }
+        for attempt in 1..=failed_attempts + 1 {
+            connection.events.emit(EventKind::Reconnecting {
+                attempt,
+                backoff: events::backoff(attempt),
+            });
+        }
+        connection.connected = true;
+        let endpoint = connection.endpoint.clone();
+        connection.events.emit(EventKind::Connected { endpoint });
+
+        let keys: BTreeSet<String> = self
+            .queues
+            .lock()
+            .unwrap()
+            .values()
+            .flat_map(|queue| queue.keys.iter().cloned())
+            .collect();
+        connection.events.emit(EventKind::SubscriptionRestored {
+            keys: keys.into_iter().collect(),
+        });
+        for reply in mem::replace(&mut connection.outbox, Default::default()) {
+            self.publish_keyed(&reply.routing_key, &reply.body, reply.idempotency_key);
+            reply.published(true);
+        }
+    }
+
+    /// Forward what is in `queue` to `tx`, acking each delivery once
+    /// `flow` lets consuming go on. Returns how many were forwarded.
+    pub fn forward(
+        &self,
+        queue: &str,
+        tx: &Sender<(String, Vec<u8>)>,
+        flow: &mut FlowControl,
+    ) -> usize {
+        forward(|| self.next_delivery(queue), tx, flow)
+    }
+
+    /// Hand the announcements in `queue` to `registry`, publishing the
+    /// answers it has, like the rabbitmq schema consumer. Returns how
+    /// many were handed.
+    pub fn deliver_schemas(&self, queue: &str, registry: &SchemaRegistry) -> usize {
+        let mut delivered = 0;
+        while let Some(delivery) = self.next_delivery(queue) {
+            if let Some((routing_key, body)) = registry.receive(&delivery.body) {
+                self.publish(&routing_key, &body);
+            }
+            delivery.ack();
+            delivered += 1;
+        }
+        delivered
+    }
+
+    /// Wait as `barrier` says for the queues of the peers to be
+    /// declared, telling it to `start_with_events`. Returns the peers
+    /// missing when proceeding without them.
+    pub fn wait_for_peers<C>(
+        &self,
+        barrier: &Barrier,
+        clock: &C,
+    ) -> Result<Vec<String>, ReadinessError>
+    where
+        C: Clock,
+    {
+        let events = self.connection.lock().unwrap().events.clone();
+        barrier.wait(clock, &events, |peer| {
+            self.queues.lock().unwrap().contains_key(peer)
+        })
+    }
+
+    /// The consumer of `queue` died, every unacked delivery is requeued.
+    pub fn crash(&self, queue: &str) {
+        if let Some(queue) = self.queues.lock().unwrap().get_mut(queue) {
+            queue.crash();
+        }
+    }
+
+    /// Messages waiting in `queue`.
+    pub fn ready(&self, queue: &str) -> usize {
+        self.release_delayed();
+        self.with_queue(queue, |queue| queue.ready.len())
+    }
+
+    /// Messages of `queue` delayed by chaos, and not due yet.
+    pub fn delayed(&self, queue: &str) -> usize {
+        self.release_delayed();
+        self.with_queue(queue, |queue| queue.delayed.len())
+    }
+
+    /// Deliveries of `queue` neither acked nor nacked yet.
+    pub fn unacked(&self, queue: &str) -> usize {
+        self.with_queue(queue, |queue| queue.unacked.len())
+    }
+
+    fn with_queue<F: FnOnce(&mut Queue) -> usize>(&self, queue: &str, f: F) -> usize {
+        let mut queues = self.queues.lock().unwrap();
+        match queues.get_mut(queue) {
+            Some(queue) => {
+                queue.settle();
+                f(queue)
+            }
+            None => 0,
+        }
+    }
+}
+
+/// What `forward` of both brokers does, with `next` their `next_delivery`.
+fn forward<F>(mut next: F, tx: &Sender<(String, Vec<u8>)>, flow: &mut FlowControl) -> usize
+where
+    F: FnMut() -> Option<Delivery>,
+{
+    let mut forwarded = 0;
+    while let Some(delivery) = next() {
+        if tx
+            .send((delivery.routing_key.clone(), delivery.body.clone()))
+            .is_err()
+        {
+            delivery.nack(true);
+            break;
+        }
+        flow.wait(|| tx.len());
+        delivery.ack();
+        forwarded += 1;
+    }
+    forwarded
+}
+
+/// Names and keys in a namespace, added on publish and stripped on
+/// consume like the rabbitmq backend does.
+pub struct NamespacedBroker<'a> {
+    broker: &'a MemoryBroker,
+    namespace: Namespace,
+}
+
+impl<'a> NamespacedBroker<'a> {
+    pub fn namespace(&self) -> &Namespace {
+        &self.namespace
+    }
+
+    pub fn declare<K>(&self, name: &str, keys: Vec<K>, mode: AckMode)
+    where
+        K: Into<String>,
+    {
+        let keys: Vec<String> = keys
+            .into_iter()
+            .map(|key| self.namespace.wrap(&key.into()))
+            .collect();
+        self.broker.declare(&self.namespace.wrap(name), keys, mode);
+    }
+
+    pub fn publish(&self, routing_key: &str, body: &[u8]) {
+        self.broker.publish(&self.namespace.wrap(routing_key), body);
+    }
+
+    pub fn publish_reply(&self, mut reply: Reply) {
+        reply.routing_key = self.namespace.wrap(&reply.routing_key);
+        self.broker.publish_reply(reply);
+    }
+
+    /// Like `MemoryBroker::next_delivery`, acking and skipping those
+    /// from outside the namespace in strict mode.
+    pub fn next_delivery(&self, queue: &str) -> Option<Delivery> {
+        let queue = self.namespace.wrap(queue);
+        loop {
+            let mut delivery = self.broker.next_delivery(&queue)?;
+            let key = mem::replace(&mut delivery.routing_key, String::new());
+            match self.namespace.consume(key) {
+                Some(key) => {
+                    delivery.routing_key = key;
+                    return Some(delivery);
+                }
+                None => delivery.ack(),
+            }
+        }
+    }
+
+    /// Like `MemoryBroker::forward`.
+    pub fn forward(
+        &self,
+        queue: &str,
+        tx: &Sender<(String, Vec<u8>)>,
+        flow: &mut FlowControl,
+    ) -> usize {
+        forward(|| self.next_delivery(queue), tx, flow)
+    }
+
+    /// Like `MemoryBroker::wait_for_peers`, for the peers in the
+    /// namespace.
+    pub fn wait_for_peers<C>(
+        &self,
+        barrier: &Barrier,
+        clock: &C,
+    ) -> Result<Vec<String>, ReadinessError>
+    where
+        C: Clock,
+    {
+        let events = self.broker.connection.lock().unwrap().events.clone();
+        barrier.wait(clock, &events, |peer| {
+            let peer = self.namespace.wrap(peer);
+            self.broker.queues.lock().unwrap().contains_key(&peer)
+        })
+    }
+
+    pub fn ready(&self, queue: &str) -> usize {
+        self.broker.ready(&self.namespace.wrap(queue))
+    }
+
+    pub fn unacked(&self, queue: &str) -> usize {
+        self.broker.unacked(&self.namespace.wrap(queue))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MemoryBroker;
+    use crate::ack::{AckMode, Delivery, Reply};
+    use crate::capture::{MockClock, SystemClock};
+    use crate::channel;
+    use crate::chaos::{ChaosPolicy, ChaosRule, Latency, Perturbation, PerturbationKind};
+    use crate::events::{backoff, EventKind, Status};
+    use crate::namespace::Namespace;
+    use crate::qos::{FlowControl, Watermarks};
+    use crate::readiness::{Barrier, OnTimeout, ReadinessError};
+    use crate::schema::{schema_queue, SchemaAnnounce, SchemaError, SchemaRegistry, SCHEMA_KEY};
+    use std::collections::{HashMap, HashSet};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    fn body(delivery: &Delivery) -> &str {
+        ::std::str::from_utf8(&delivery.body).unwrap()
+    }
+
+    fn publish(broker: &MemoryBroker, bodies: &[&str]) {
+        for b in bodies {
+            broker.publish("chain.tx", b.as_bytes());
+        }
+    }
+
+    #[test]
+    fn auto_ack_loses_on_crash() {
+        let broker = MemoryBroker::new();
+        broker.declare("auth", vec!["chain.*"], AckMode::AutoAck);
+        broker.publish("net.tx", b"not bound");
+        publish(&broker, &["a", "b"]);
+
+        let first = broker.next_delivery("auth").unwrap();
+        assert_eq!(body(&first), "a");
+        assert_eq!(broker.unacked("auth"), 0);
+        broker.crash("auth");
+        // Received but not handled, and gone.
+        assert_eq!(body(&broker.next_delivery("auth").unwrap()), "b");
+        assert!(broker.next_delivery("auth").is_none());
+    }
+
+    #[test]
+    fn crash_before_ack_redelivers() {
+        let broker = MemoryBroker::new();
+        broker.declare(
+            "auth",
+            vec!["chain.#"],
+            AckMode::AckOnHandled { watermark: 10 },
+        );
+        publish(&broker, &["a", "b", "c"]);
+
+        let a = broker.next_delivery("auth").unwrap();
+        let b = broker.next_delivery("auth").unwrap();
+        assert!(!a.redelivered);
+        a.ack();
+        assert_eq!(broker.unacked("auth"), 1);
+
+        broker.crash("auth");
+        // Acking after the crash does nothing.
+        b.ack();
+        let again = broker.next_delivery("auth").unwrap();
+        assert_eq!(body(&again), "b");
+        assert!(again.redelivered);
+        let c = broker.next_delivery("auth").unwrap();
+        assert_eq!(body(&c), "c");
+        assert!(!c.redelivered);
+        again.ack();
+        c.ack();
+        assert_eq!(broker.unacked("auth"), 0);
+        assert_eq!(broker.ready("auth"), 0);
+    }
+
+    #[test]
+    fn watermark_pauses() {
+        let broker = MemoryBroker::new();
+        broker.declare(
+            "auth",
+            vec!["chain.tx"],
+            AckMode::AckOnHandled { watermark: 2 },
+        );
+        publish(&broker, &["a", "b", "c"]);
+
+        let a = broker.next_delivery("auth").unwrap();
+        let _b = broker.next_delivery("auth").unwrap();
+        assert!(broker.next_delivery("auth").is_none());
+        assert_eq!(broker.ready("auth"), 1);
+
+        a.ack();
+        assert_eq!(body(&broker.next_delivery("auth").unwrap()), "c");
+    }
+
+    #[test]
+    fn nack_requeue_order() {
+        let broker = MemoryBroker::new();
+        broker.declare(
+            "auth",
+            vec!["chain.tx"],
+            AckMode::AckOnHandled { watermark: 10 },
+        );
+        publish(&broker, &["a", "b", "c"]);
+
+        let a = broker.next_delivery("auth").unwrap();
+        let b = broker.next_delivery("auth").unwrap();
+        // Requeued ones come back first, ahead of what is still waiting.
+        b.nack(true);
+        let b = broker.next_delivery("auth").unwrap();
+        assert_eq!(body(&b), "b");
+        assert!(b.redelivered);
+        assert_eq!(body(&broker.next_delivery("auth").unwrap()), "c");
+
+        // Without requeue the message is dropped.
+        a.nack(false);
+        b.ack();
+        assert_eq!(broker.unacked("auth"), 1);
+        assert_eq!(broker.ready("auth"), 0);
+    }
+
+    #[test]
+    fn ack_on_publish_of_reply() {
+        let broker = MemoryBroker::new();
+        broker.declare(
+            "executor",
+            vec!["chain.tx"],
+            AckMode::AckOnPublishOfReply { watermark: 1 },
+        );
+        broker.declare("chain", vec!["executor.result"], AckMode::AutoAck);
+        publish(&broker, &["a", "b"]);
+
+        let request = broker.next_delivery("executor").unwrap();
+        // Not acked until the reply is out.
+        assert!(broker.next_delivery("executor").is_none());
+        let reply = Reply::to(request, "executor.result".to_owned(), b"done".to_vec());
+        assert_eq!(broker.unacked("executor"), 1);
+        broker.publish_reply(reply);
+        assert_eq!(broker.unacked("executor"), 0);
+        assert_eq!(body(&broker.next_delivery("chain").unwrap()), "done");
+
+        // A failed publish requeues the request.
+        let request = broker.next_delivery("executor").unwrap();
+        Reply::to(request, "executor.result".to_owned(), Vec::new()).published(false);
+        let request = broker.next_delivery("executor").unwrap();
+        assert_eq!(body(&request), "b");
+        assert!(request.redelivered);
+    }
+
+    #[test]
+    fn slow_consumer_bounded_depth() {
+        let broker = MemoryBroker::new();
+        broker.declare(
+            "sync",
+            vec!["net.block"],
+            AckMode::AckOnHandled { watermark: 4 },
+        );
+        let total = 200u32;
+        for i in 0..total {
+            broker.publish("net.block", &i.to_be_bytes());
+        }
+
+        let (tx, rx) = channel::unbounded::<(String, Vec<u8>)>();
+        let mut flow = FlowControl::new("sync", Some(Watermarks::new(16, 4)));
+        let counters = flow.counters();
+        let drained = flow.counters();
+        let slow = thread::spawn(move || {
+            let mut max_depth = 0;
+            let mut bodies = Vec::new();
+            for (_, body) in rx.iter() {
+                max_depth = max_depth.max(rx.len());
+                thread::sleep(Duration::from_micros(200));
+                bodies.push(body);
+                drained.notify_drained();
+            }
+            (max_depth, bodies)
+        });
+
+        assert_eq!(broker.forward("sync", &tx, &mut flow), total as usize);
+        drop(tx);
+        let (max_depth, bodies) = slow.join().unwrap();
+
+        // The one taken out plus what is left, and no more was forwarded
+        // while paused.
+        assert!(max_depth <= 16, "depth {}", max_depth);
+        assert!(counters.pauses() > 0);
+        assert_eq!(counters.pauses(), counters.resumes());
+        let expected: Vec<Vec<u8>> = (0..total).map(|i| i.to_be_bytes().to_vec()).collect();
+        assert_eq!(bodies, expected);
+        assert_eq!(broker.ready("sync"), 0);
+        assert_eq!(broker.unacked("sync"), 0);
+    }
+
+    #[test]
+    fn lifecycle_events() {
+        let broker = MemoryBroker::new();
+        broker.declare(
+            "auth",
+            vec!["net.*", "chain.tx"],
+            AckMode::AckOnHandled { watermark: 10 },
+        );
+        broker.declare("chain", vec!["auth.result"], AckMode::AutoAck);
+        let events = broker.start_with_events("amqp://localhost", 2);
+        publish(&broker, &["a"]);
+        let a = broker.next_delivery("auth").unwrap();
+
+        broker.disconnect("connection reset");
+        broker.disconnect("already down");
+        assert!(broker.next_delivery("auth").is_none());
+        for reply in &["r1", "r2", "r3"] {
+            let reply = ("auth.result".to_owned(), reply.as_bytes().to_vec());
+            broker.publish_reply(reply.into());
+        }
+        broker.reconnect(2);
+        broker.reconnect(0);
+
+        let events: Vec<_> = events.try_iter().collect();
+        assert!(events.windows(2).all(|pair| pair[0].at <= pair[1].at));
+        let connected = EventKind::Connected {
+            endpoint: "amqp://localhost".to_owned(),
+        };
+        let reconnecting = |attempt| EventKind::Reconnecting {
+            attempt,
+            backoff: backoff(attempt),
+        };
+        assert_eq!(
+            events
+                .into_iter()
+                .map(|event| event.kind)
+                .collect::<Vec<_>>(),
+            vec![
+                connected.clone(),
+                EventKind::Disconnected {
+                    error: "connection reset".to_owned(),
+                },
+                EventKind::PublishBufferOverflow { dropped: 1 },
+                reconnecting(1),
+                reconnecting(2),
+                reconnecting(3),
+                connected,
+                EventKind::SubscriptionRestored {
+                    keys: vec![
+                        "auth.result".to_owned(),
+                        "chain.tx".to_owned(),
+                        "net.*".to_owned(),
+                    ],
+                },
+            ]
+        );
+
+        // The delivery unacked when the connection dropped comes again,
+        // and the replies which fit the buffer went out.
+        a.ack();
+        let again = broker.next_delivery("auth").unwrap();
+        assert_eq!(body(&again), "a");
+        assert!(again.redelivered);
+        assert_eq!(body(&broker.next_delivery("chain").unwrap()), "r2");
+        assert_eq!(body(&broker.next_delivery("chain").unwrap()), "r3");
+        assert!(broker.next_delivery("chain").is_none());
+    }
+
+    fn drain(broker: &MemoryBroker, queue: &str) -> Vec<String> {
+        let mut bodies = Vec::new();
+        while let Some(delivery) = broker.next_delivery(queue) {
+            bodies.push(body(&delivery).to_owned());
+        }
+        bodies
+    }
+
+    /// Votes under chaos and transactions without, interleaved.
+    fn chaos_run(seed: u64) -> (Vec<Perturbation>, Vec<String>, Vec<String>) {
+        let clock = MockClock::default();
+        let broker = MemoryBroker::new();
+        broker.declare("consensus", vec!["consensus.#"], AckMode::AutoAck);
+        broker.declare("auth", vec!["auth.*"], AckMode::AutoAck);
+        let rule = ChaosRule::new()
+            .drop(0.1)
+            .duplicate(0.1)
+            .reorder(3)
+            .latency(Latency::Uniform {
+                min: Duration::from_millis(0),
+                max: Duration::from_millis(30),
+            });
+        broker.set_chaos(
+            ChaosPolicy::from_seed(seed).rule(vec!["consensus.#"], rule),
+            clock.clone(),
+        );
+
+        let mut votes = Vec::new();
+        let mut txs = Vec::new();
+        for i in 0..100 {
+            broker.publish("consensus.vote", format!("v{}", i).as_bytes());
+            broker.publish("auth.tx", format!("t{}", i).as_bytes());
+            clock.advance(Duration::from_millis(5));
+            votes.extend(drain(&broker, "consensus"));
+            txs.extend(drain(&broker, "auth"));
+        }
+        clock.advance(Duration::from_millis(30));
+        votes.extend(drain(&broker, "consensus"));
+        assert_eq!(broker.delayed("consensus"), 0);
+        (broker.perturbations(), votes, txs)
+    }
+
+    #[test]
+    fn chaos_replays_from_seed() {
+        let run = chaos_run(7);
+        assert_eq!(run, chaos_run(7));
+        assert_ne!(run.0, chaos_run(8).0);
+
+        let (log, _, txs) = run;
+        assert!(log.iter().all(|p| p.queue == "consensus"));
+        let expected: Vec<_> = (0..100).map(|i| format!("t{}", i)).collect();
+        assert_eq!(txs, expected);
+    }
+
+    #[test]
+    fn chaos_deliveries_match_log() {
+        let (log, votes, _) = chaos_run(7);
+        let kinds = |kind: fn(&PerturbationKind) -> bool| {
+            log.iter()
+                .filter(|p| kind(&p.kind))
+                // Votes are every other publish.
+                .map(|p| format!("v{}", p.message / 2))
+                .collect::<HashSet<_>>()
+        };
+        let dropped = kinds(|kind| *kind == PerturbationKind::Dropped);
+        let duplicated = kinds(|kind| *kind == PerturbationKind::Duplicated);
+        assert!(!dropped.is_empty() && !duplicated.is_empty());
+        let delayed = kinds(|kind| match *kind {
+            PerturbationKind::Delayed(_) => true,
+            _ => false,
+        });
+        let reordered = kinds(|kind| match *kind {
+            PerturbationKind::Reordered { .. } => true,
+            _ => false,
+        });
+        assert!(!delayed.is_empty() && !reordered.is_empty());
+
+        let mut counts = HashMap::new();
+        for vote in &votes {
+            *counts.entry(vote.clone()).or_insert(0) += 1;
+        }
+        for i in 0..100 {
+            let vote = format!("v{}", i);
+            let expected = if dropped.contains(&vote) {
+                0
+            } else if duplicated.contains(&vote) {
+                2
+            } else {
+                1
+            };
+            assert_eq!(
+                counts.get(&vote).cloned().unwrap_or(0),
+                expected,
+                "{}",
+                vote
+            );
+        }
+
+        // A subscriber dropping what it saw gets every vote not dropped
+        // once, in another order.
+        let mut seen = HashSet::new();
+        let unique: Vec<_> = votes.iter().filter(|vote| seen.insert(*vote)).collect();
+        assert_eq!(unique.len(), 100 - dropped.len());
+        let mut sorted = unique.clone();
+        sorted.sort_by_key(|vote| vote[1..].parse::<u32>().unwrap());
+        assert_ne!(unique, sorted);
+    }
+
+    #[test]
+    fn chaos_reorders_within_window() {
+        let broker = MemoryBroker::new();
+        broker.declare("consensus", vec!["consensus.#"], AckMode::AutoAck);
+        broker.set_chaos(
+            ChaosPolicy::from_seed(11).rule(vec!["consensus.#"], ChaosRule::new().reorder(2)),
+            MockClock::default(),
+        );
+        for i in 0..50 {
+            broker.publish("consensus.vote", i.to_string().as_bytes());
+        }
+        let order: Vec<usize> = drain(&broker, "consensus")
+            .iter()
+            .map(|vote| vote.parse().unwrap())
+            .collect();
+        assert_eq!(order.len(), 50);
+        assert!(order.windows(2).any(|pair| pair[0] > pair[1]));
+        // Never more than two places ahead of where it was published.
+        for (position, &vote) in order.iter().enumerate() {
+            assert!(position + 2 >= vote, "{} at {}", vote, position);
+        }
+    }
+
+    #[test]
+    fn chaos_latency_follows_clock() {
+        let clock = MockClock::default();
+        let broker = MemoryBroker::new();
+        broker.declare("consensus", vec!["consensus.#"], AckMode::AutoAck);
+        let rule = ChaosRule::new().latency(Latency::Fixed(Duration::from_millis(100)));
+        broker.set_chaos(
+            ChaosPolicy::from_seed(0).rule(vec!["consensus.#"], rule),
+            clock.clone(),
+        );
+        broker.publish("consensus.vote", b"a");
+        assert!(broker.next_delivery("consensus").is_none());
+        assert_eq!(broker.delayed("consensus"), 1);
+        clock.advance(Duration::from_millis(99));
+        assert!(broker.next_delivery("consensus").is_none());
+        clock.advance(Duration::from_millis(1));
+        assert_eq!(body(&broker.next_delivery("consensus").unwrap()), "a");
+        assert_eq!(
+            broker.perturbations(),
+            vec![Perturbation {
+                message: 0,
+                queue: "consensus".to_owned(),
+                routing_key: "consensus.vote".to_owned(),
+                kind: PerturbationKind::Delayed(Duration::from_millis(100)),
+            }]
+        );
+    }
+
+    #[test]
+    fn namespaces_are_isolated() {
+        let broker = Arc::new(MemoryBroker::new());
+        let chains: Vec<_> = (1..=2)
+            .map(|chain_id| {
+                let broker = broker.clone();
+                thread::spawn(move || {
+                    let chain =
+                        broker.in_namespace(Namespace::from_chain_id(chain_id).strict(true));
+                    // The same names and keys on both chains.
+                    chain.declare("auth", vec!["net.*"], AckMode::AutoAck);
+                    chain.declare("chain", vec!["#"], AckMode::AutoAck);
+                    for i in 0..100 {
+                        chain.publish("net.tx", format!("{}-{}", chain_id, i).as_bytes());
+                    }
+                    let mut received = Vec::new();
+                    while received.len() < 100 {
+                        match chain.next_delivery("auth") {
+                            Some(delivery) => {
+                                assert_eq!(delivery.routing_key, "net.tx");
+                                received.push(body(&delivery).to_owned());
+                            }
+                            None => thread::yield_now(),
+                        }
+                    }
+                    let expected: Vec<_> =
+                        (0..100).map(|i| format!("{}-{}", chain_id, i)).collect();
+                    assert_eq!(received, expected);
+                    chain.namespace().counters()
+                })
+            })
+            .collect();
+        for chain in chains {
+            assert_eq!(chain.join().unwrap().mismatched(), 0);
+        }
+
+        // Nothing of one chain reached the other, `#` included.
+        for chain_id in 1..=2 {
+            let chain = broker.in_namespace(Namespace::from_chain_id(chain_id));
+            assert_eq!(chain.ready("auth"), 0);
+            let all = drain(&broker, &format!("chain{}.chain", chain_id));
+            assert_eq!(all.len(), 100);
+            let prefix = format!("{}-", chain_id);
+            assert!(all.iter().all(|b| b.starts_with(&prefix)));
+        }
+    }
+
+    #[test]
+    fn strict_namespace_drops_mismatched() {
+        let broker = MemoryBroker::new();
+        let chain = broker.in_namespace(Namespace::new("side").unwrap().strict(true));
+        chain.declare(
+            "auth",
+            vec!["net.tx"],
+            AckMode::AckOnHandled { watermark: 10 },
+        );
+        broker.inject("side.auth", "main.net.tx", b"foreign");
+        broker.inject("side.auth", "net.tx", b"bare");
+        chain.publish("net.tx", b"own");
+
+        let delivery = chain.next_delivery("auth").unwrap();
+        assert_eq!(delivery.routing_key, "net.tx");
+        assert_eq!(body(&delivery), "own");
+        assert!(chain.next_delivery("auth").is_none());
+        assert_eq!(chain.namespace().counters().mismatched(), 2);
+        // The dropped ones were acked, not left for redelivery.
+        assert_eq!(chain.unacked("auth"), 1);
+        delivery.ack();
+        assert_eq!(chain.unacked("auth"), 0);
+
+        let lenient = broker.in_namespace(Namespace::new("side").unwrap());
+        broker.inject("side.auth", "main.net.tx", b"foreign");
+        let delivery = lenient.next_delivery("auth").unwrap();
+        assert_eq!(delivery.routing_key, "main.net.tx");
+        assert_eq!(lenient.namespace().counters().mismatched(), 1);
+    }
+
+    /// `name` on `broker`, announcing itself as a started service does.
+    fn join(broker: &MemoryBroker, name: &str, schema: &str) -> SchemaRegistry {
+        let own = SchemaAnnounce::new(name, schema, "0.6.0", vec![1]);
+        let registry = SchemaRegistry::new(own).critical(vec!["auth", "chain"]);
+        broker.declare(&schema_queue(name), vec![SCHEMA_KEY], AckMode::AutoAck);
+        let (routing_key, body) = registry.own().message();
+        broker.publish(&routing_key, &body);
+        registry
+    }
+
+    /// Until nobody has anything more to answer.
+    fn exchange(broker: &MemoryBroker, registries: &[(&str, &SchemaRegistry)]) {
+        while registries
+            .iter()
+            .map(|(name, registry)| broker.deliver_schemas(&schema_queue(name), registry))
+            .sum::<usize>()
+            > 0
+        {}
+    }
+
+    #[test]
+    fn compatible_peers() {
+        let broker = MemoryBroker::new();
+        let auth = join(&broker, "auth", "aaaa");
+        let chain = join(&broker, "chain", "aaaa");
+        exchange(&broker, &[("auth", &auth), ("chain", &chain)]);
+        // Joining late, it hears of the others from their answers.
+        let jsonrpc = join(&broker, "jsonrpc", "aaaa").strict(true);
+        exchange(
+            &broker,
+            &[("auth", &auth), ("chain", &chain), ("jsonrpc", &jsonrpc)],
+        );
+
+        let report = jsonrpc.compatibility_report();
+        assert_eq!(report.peers.len(), 2);
+        assert!(report.is_compatible());
+        assert_eq!(report.to_string(), "2 peers on schema aaaa");
+        assert_eq!(auth.compatibility_report().peers.len(), 2);
+        assert_eq!(jsonrpc.check(), Ok(()));
+        for name in &["auth", "chain", "jsonrpc"] {
+            assert_eq!(broker.ready(&schema_queue(name)), 0);
+        }
+    }
+
+    #[test]
+    fn incompatible_critical_peer_refuses_strict_start() {
+        let broker = MemoryBroker::new();
+        let auth = join(&broker, "auth", "aaaa");
+        let network = join(&broker, "network", "bbbb");
+        let jsonrpc = join(&broker, "jsonrpc", "aaaa").strict(true);
+        let all = [
+            ("auth", &auth),
+            ("network", &network),
+            ("jsonrpc", &jsonrpc),
+        ];
+        exchange(&broker, &all);
+        // Not critical, so reported only.
+        assert_eq!(jsonrpc.compatibility_report().incompatible().len(), 1);
+        assert_eq!(jsonrpc.check(), Ok(()));
+
+        // An older chain.
+        let chain = join(&broker, "chain", "0ld0");
+        exchange(
+            &broker,
+            &[
+                ("auth", &auth),
+                ("network", &network),
+                ("jsonrpc", &jsonrpc),
+                ("chain", &chain),
+            ],
+        );
+        let err = jsonrpc.check().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "incompatible critical peers chain: \
+             chain 0.6.0 (critical) has schema 0ld0 instead of aaaa, \
+             network 0.6.0 has schema bbbb instead of aaaa"
+        );
+        match err {
+            SchemaError::IncompatiblePeers(services, _) => assert_eq!(services, vec!["chain"]),
+            other => panic!("{:?}", other),
+        }
+        // Lenient ones start anyway.
+        assert_eq!(auth.check(), Ok(()));
+        assert_eq!(auth.compatibility_report().incompatible().len(), 2);
+    }
+
+    #[test]
+    fn publishes_after_readiness_reach_peers_declared_late() {
+        let broker = Arc::new(MemoryBroker::new());
+        let events = broker.start_with_events("memory://", 16);
+        broker.declare("consensus", vec!["auth.*"], AckMode::AutoAck);
+
+        // Published before auth is there, this one is lost.
+        broker.publish("consensus.block", b"early");
+
+        let late = Arc::clone(&broker);
+        let auth = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            late.declare("auth", vec!["consensus.*"], AckMode::AutoAck);
+        });
+        let barrier = Barrier::new(vec!["auth"], Duration::from_secs(10))
+            .poll_every(Duration::from_millis(5));
+        let missing = broker
+            .wait_for_peers(&barrier, &SystemClock::default())
+            .unwrap();
+        assert!(missing.is_empty());
+        auth.join().unwrap();
+
+        for b in &["one", "two", "three"] {
+            broker.publish("consensus.block", b.as_bytes());
+        }
+        let bodies: Vec<String> = (0..3)
+            .map(|_| body(&broker.next_delivery("auth").unwrap()).to_owned())
+            .collect();
+        assert_eq!(bodies, vec!["one", "two", "three"]);
+        assert!(broker.next_delivery("auth").is_none());
+
+        let mut status = Status::default();
+        status.update(&events);
+        assert!(status.connected);
+        assert!(status.ready);
+
+        // A namespace only sees its own peers.
+        let chain = broker.in_namespace(Namespace::new("chain-1").unwrap());
+        let barrier = Barrier::new(vec!["auth"], Duration::from_millis(20))
+            .on_timeout(OnTimeout::Fail)
+            .poll_every(Duration::from_millis(5));
+        let err = chain
+            .wait_for_peers(&barrier, &MockClock::default())
+            .unwrap_err();
+        assert_eq!(
+            err,
+            ReadinessError::MissingPeers(vec!["auth".to_owned()], Duration::from_millis(20))
+        );
+        chain.declare("auth", vec!["consensus.*"], AckMode::AutoAck);
+        assert!(chain
+            .wait_for_peers(&barrier, &MockClock::default())
+            .is_ok());
+        status.update(&events);
+        assert!(status.ready);
+        assert!(status.missing_peers.is_empty());
+    }
+}
@@ -0,0 +1,213 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Chains sharing one broker.
+//!
+//! A namespace is one word put in front of the exchange, the queue names
+//! and the routing keys, `chain1.cita`, `chain1.auth` and
+//! `chain1.net.tx`. It is added on publish and stripped on consume, so
+//! the services see the keys they always did. A topic backend would use
+//! it as the topic prefix the same way.
+//!
+//! A consumed message outside the namespace can only come from a binding
+//! made by hand, or a publisher which got the exchange wrong. It is
+//! counted either way, and in strict mode dropped rather than handed to
+//! the service with its foreign key. The first one is logged, then the
+//! 2nd, the 4th, the 8th and so on, so a stray binding doesn't flood the
+//! log.
+//!
+//! The empty namespace leaves every name as it is.
+
+use std::error::Error;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+const SEPARATOR: char = '.';
+
+/// Messages consumed from outside the namespace, readable from any
+/// thread.
+#[derive(Debug, Clone, Default)]
+pub struct NamespaceCounters {
+    mismatched: Arc<AtomicUsize>,
+}
+
+impl NamespaceCounters {
+    pub fn mismatched(&self) -> usize {
+        self.mismatched.load(Ordering::Relaxed)
+    }
+}
+
+/// A namespace name which isn't a single word.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NamespaceError(pub String);
+
+impl fmt::Display for NamespaceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "namespace {:?} isn't a single word", self.0)
+    }
+}
+
+impl Error for NamespaceError {}
+
+#[derive(Debug, Clone, Default)]
+pub struct Namespace {
+    name: String,
+    strict: bool,
+    counters: NamespaceCounters,
+}
+
+impl Namespace {
+    /// An error unless `name` is a single word a topic pattern would
+    /// match with `*`.
+    pub fn new(name: &str) -> Result<Self, NamespaceError> {
+        if name.contains(&[SEPARATOR, '*', '#'][..]) {
+            return Err(NamespaceError(name.to_owned()));
+        }
+        Ok(Namespace {
+            name: name.to_owned(),
+            ..Namespace::default()
+        })
+    }
+
+    /// The namespace of the chain `chain_id`.
+    pub fn from_chain_id(chain_id: u32) -> Self {
+        Namespace {
+            name: format!("chain{}", chain_id),
+            ..Namespace::default()
+        }
+    }
+
+    /// Drop the messages consumed from outside the namespace.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn is_strict(&self) -> bool {
+        self.strict
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.name.is_empty()
+    }
+
+    pub fn counters(&self) -> NamespaceCounters {
+        self.counters.clone()
+    }
+
+    /// `name` in the namespace, for exchanges, queues, routing keys and
+    /// binding patterns alike.
+    pub fn wrap(&self, name: &str) -> String {
+        if self.is_empty() {
+            name.to_owned()
+        } else {
+            format!("{}{}{}", self.name, SEPARATOR, name)
+        }
+    }
+
+    /// `key` without the namespace, none when it is in another one.
+    pub fn strip<'a>(&self, key: &'a str) -> Option<&'a str> {
+        if self.is_empty() {
+            return Some(key);
+        }
+        let len = self.name.len();
+        if key.starts_with(self.name.as_str()) && key[len..].starts_with(SEPARATOR) {
+            Some(&key[len + SEPARATOR.len_utf8()..])
+        } else {
+            None
+        }
+    }
+
+    /// The key to hand a consumed message to the service with. One from
+    /// outside the namespace is counted, and none in strict mode.
+    pub fn consume(&self, key: String) -> Option<String> {
+        if let Some(stripped) = self.strip(&key) {
+            return Some(stripped.to_owned());
+        }
+        let mismatched = self.counters.mismatched.fetch_add(1, Ordering::Relaxed) + 1;
+        if mismatched.is_power_of_two() {
+            warn!(
+                "{} {} from outside namespace {}, {} so far",
+                if self.strict { "dropped" } else { "consumed" },
+                key,
+                self.name,
+                mismatched
+            );
+        }
+        if self.strict {
+            None
+        } else {
+            Some(key)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Namespace, NamespaceError};
+    use crate::capture::Filter;
+
+    #[test]
+    fn wrap_and_strip() {
+        let ns = Namespace::from_chain_id(1);
+        assert_eq!(ns.name(), "chain1");
+        assert_eq!(ns.wrap("cita"), "chain1.cita");
+        assert_eq!(ns.wrap("net.tx"), "chain1.net.tx");
+        assert_eq!(ns.strip("chain1.net.tx"), Some("net.tx"));
+        assert_eq!(ns.strip("chain12.net.tx"), None);
+        assert_eq!(ns.strip("chain1"), None);
+        assert_eq!(ns.strip("net.tx"), None);
+
+        // Wildcards still match within the namespace only.
+        let filter = Filter::new(vec![ns.wrap("#"), ns.wrap("*.tx")]);
+        assert!(filter.matches("chain1.net.blk"));
+        assert!(!filter.matches("chain2.net.tx"));
+
+        let none = Namespace::default();
+        assert_eq!(none.wrap("net.tx"), "net.tx");
+        assert_eq!(none.strip("chain1.net.tx"), Some("chain1.net.tx"));
+    }
+
+    #[test]
+    fn mismatched_are_counted() {
+        let lenient = Namespace::new("side").unwrap();
+        let counters = lenient.counters();
+        assert_eq!(lenient.consume("side.net.tx".to_owned()).unwrap(), "net.tx");
+        assert_eq!(
+            lenient.consume("main.net.tx".to_owned()).unwrap(),
+            "main.net.tx"
+        );
+        assert_eq!(counters.mismatched(), 1);
+
+        let strict = Namespace::new("side").unwrap().strict(true);
+        assert!(strict.consume("main.net.tx".to_owned()).is_none());
+        assert_eq!(strict.counters().mismatched(), 1);
+    }
+
+    #[test]
+    fn namespace_is_one_word() {
+        for name in &["side.chain", "side*", "#"] {
+            assert_eq!(
+                Namespace::new(name).unwrap_err(),
+                NamespaceError((*name).to_owned())
+            );
+        }
+        assert!(Namespace::new("side-chain").is_ok());
+    }
+}
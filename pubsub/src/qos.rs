@@ -0,0 +1,235 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! How much a consumer takes from the broker before the service keeps up.
+//!
+//! The prefetch limits the messages, or bytes, the broker sends without
+//! an ack. It only bounds memory if acks wait for the service, so with
+//! watermarks the consumer stops acking and reading while the forwarding
+//! channel holds more than `high`, and goes on once it is down to `low`.
+//! Meanwhile messages wait in the broker.
+//!
+//! A paused consumer sleeps until the receiver of the forwarding channel
+//! calls `FlowCounters::notify_drained`, after taking messages out.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+/// How long a paused consumer sleeps at most without a notification, for
+/// receivers which don't notify.
+const RECHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watermarks {
+    pub high: usize,
+    pub low: usize,
+}
+
+impl Watermarks {
+    /// `low` is at most `high`.
+    pub fn new(high: usize, low: usize) -> Self {
+        Watermarks {
+            high,
+            low: low.min(high),
+        }
+    }
+}
+
+/// The default is the one of `ConnectionConfig`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Qos {
+    /// Unacked messages the broker sends at most, 0 for no limit.
+    pub prefetch_count: u16,
+    /// Unacked bytes the broker sends at most, 0 for no limit.
+    pub prefetch_size: u32,
+    /// Pause consuming between these depths of the forwarding channel,
+    /// never paused without.
+    pub watermarks: Option<Watermarks>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transition {
+    Paused,
+    Resumed,
+}
+
+/// How often a consumer paused and resumed, readable from any thread,
+/// and the wakeup of the paused consumer.
+#[derive(Debug, Clone, Default)]
+pub struct FlowCounters {
+    pauses: Arc<AtomicUsize>,
+    resumes: Arc<AtomicUsize>,
+    /// Bumped by every `notify_drained`.
+    drained: Arc<(Mutex<u64>, Condvar)>,
+}
+
+impl FlowCounters {
+    pub fn pauses(&self) -> usize {
+        self.pauses.load(Ordering::Relaxed)
+    }
+
+    pub fn resumes(&self) -> usize {
+        self.resumes.load(Ordering::Relaxed)
+    }
+
+    /// Wake the consumer if paused, so it looks at the depth of the
+    /// forwarding channel again.
+    pub fn notify_drained(&self) {
+        let (ref drained, ref condvar) = *self.drained;
+        let mut drained = drained.lock().unwrap_or_else(|e| e.into_inner());
+        *drained = drained.wrapping_add(1);
+        condvar.notify_all();
+    }
+
+    fn drained(&self) -> u64 {
+        *self.drained.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Sleep until a `notify_drained` after `seen` was bumped to it, or
+    /// for `RECHECK_INTERVAL`.
+    fn wait_drained(&self, seen: u64) {
+        let (ref drained, ref condvar) = *self.drained;
+        let drained = drained.lock().unwrap_or_else(|e| e.into_inner());
+        if *drained == seen {
+            let _ = condvar.wait_timeout(drained, RECHECK_INTERVAL);
+        }
+    }
+}
+
+/// The pausing of one consumer.
+#[derive(Debug)]
+pub struct FlowControl {
+    name: String,
+    watermarks: Option<Watermarks>,
+    paused: bool,
+    counters: FlowCounters,
+}
+
+impl FlowControl {
+    /// `name` is the consumer's, for the log.
+    pub fn new(name: &str, watermarks: Option<Watermarks>) -> Self {
+        FlowControl {
+            name: name.to_owned(),
+            watermarks,
+            paused: false,
+            counters: FlowCounters::default(),
+        }
+    }
+
+    pub fn counters(&self) -> FlowCounters {
+        self.counters.clone()
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Take the depth of the forwarding channel into account.
+    pub fn observe(&mut self, depth: usize) -> Option<Transition> {
+        let watermarks = self.watermarks?;
+        if !self.paused && depth > watermarks.high {
+            self.paused = true;
+            self.counters.pauses.fetch_add(1, Ordering::Relaxed);
+            info!(
+                "consumer {} paused, {} messages not handled yet",
+                self.name, depth
+            );
+            Some(Transition::Paused)
+        } else if self.paused && depth <= watermarks.low {
+            self.paused = false;
+            self.counters.resumes.fetch_add(1, Ordering::Relaxed);
+            info!("consumer {} resumed, {} messages left", self.name, depth);
+            Some(Transition::Resumed)
+        } else {
+            None
+        }
+    }
+
+    /// Return once consuming may go on, looking at `depth` again on every
+    /// `FlowCounters::notify_drained` while paused.
+    pub fn wait<F: Fn() -> usize>(&mut self, depth: F) {
+        loop {
+            // Read before the depth, so a notification in between isn't
+            // slept through.
+            let seen = self.counters.drained();
+            self.observe(depth());
+            if !self.paused {
+                return;
+            }
+            self.counters.wait_drained(seen);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FlowControl, Transition, Watermarks, RECHECK_INTERVAL};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn watermarks_hysteresis() {
+        let mut flow = FlowControl::new("test", Some(Watermarks::new(4, 2)));
+        assert_eq!(flow.observe(4), None);
+        assert_eq!(flow.observe(5), Some(Transition::Paused));
+        assert_eq!(flow.observe(9), None);
+        // Between the watermarks it stays paused.
+        assert_eq!(flow.observe(3), None);
+        assert!(flow.is_paused());
+        assert_eq!(flow.observe(2), Some(Transition::Resumed));
+        assert_eq!(flow.observe(3), None);
+        assert_eq!(flow.observe(5), Some(Transition::Paused));
+
+        let counters = flow.counters();
+        assert_eq!(counters.pauses(), 2);
+        assert_eq!(counters.resumes(), 1);
+
+        assert_eq!(Watermarks::new(4, 8), Watermarks::new(4, 4));
+    }
+
+    #[test]
+    fn no_watermarks_never_pause() {
+        let mut flow = FlowControl::new("test", None);
+        assert_eq!(flow.observe(usize::max_value()), None);
+        flow.wait(|| 1_000_000);
+        assert_eq!(flow.counters().pauses(), 0);
+    }
+
+    #[test]
+    fn paused_wait_wakes_on_drain() {
+        let mut flow = FlowControl::new("test", Some(Watermarks::new(4, 2)));
+        let counters = flow.counters();
+        let depth = Arc::new(AtomicUsize::new(8));
+        let receiver = {
+            let depth = Arc::clone(&depth);
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(10));
+                depth.store(0, Ordering::SeqCst);
+                counters.notify_drained();
+            })
+        };
+
+        let start = Instant::now();
+        flow.wait(|| depth.load(Ordering::SeqCst));
+        // Woken by the receiver, not by the recheck.
+        assert!(start.elapsed() < RECHECK_INTERVAL);
+        assert!(!flow.is_paused());
+        assert_eq!(flow.counters().pauses(), 1);
+        assert_eq!(flow.counters().resumes(), 1);
+        receiver.join().unwrap();
+    }
+}
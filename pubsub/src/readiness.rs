@@ -0,0 +1,235 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Waiting for the peers' queues before publishing.
+//!
+//! A message published to a key no queue is bound to yet is dropped by
+//! the broker, so a service publishing right away at startup may lose
+//! its first messages to a peer still starting. A `Barrier` names the
+//! peers a service must not start publishing without, and polls for
+//! their queues until all are there or the timeout passed, see
+//! `start_pubsub_with_required` and `MemoryBroker::wait_for_peers`.
+//!
+//! The own queue is declared and consumed before waiting, so two
+//! services requiring each other don't wait forever. What is published
+//! meanwhile waits in the channel.
+//!
+//! The events tell each peer found, and then whether the service is
+//! ready, which `events::Status` keeps.
+
+use crate::capture::Clock;
+use crate::events::{EventKind, Events};
+use crate::namespace::NamespaceError;
+use std::error::Error;
+use std::fmt;
+use std::time::Duration;
+
+/// Wait between two looks for the peers missing.
+pub const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// What to do when the timeout passed with peers still missing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnTimeout {
+    /// Don't publish, the error names the peers missing.
+    Fail,
+    /// Publish anyway, with a warning.
+    Proceed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Barrier {
+    required: Vec<String>,
+    timeout: Duration,
+    on_timeout: OnTimeout,
+    poll: Duration,
+}
+
+impl Barrier {
+    /// Waiting at most `timeout` for the queues of `required`, by the
+    /// names they are started with, failing after.
+    pub fn new<K>(required: Vec<K>, timeout: Duration) -> Self
+    where
+        K: Into<String>,
+    {
+        Barrier {
+            required: required.into_iter().map(Into::into).collect(),
+            timeout,
+            on_timeout: OnTimeout::Fail,
+            poll: POLL_INTERVAL,
+        }
+    }
+
+    pub fn on_timeout(mut self, on_timeout: OnTimeout) -> Self {
+        self.on_timeout = on_timeout;
+        self
+    }
+
+    pub fn poll_every(mut self, poll: Duration) -> Self {
+        self.poll = poll;
+        self
+    }
+
+    pub fn required(&self) -> &[String] {
+        &self.required
+    }
+
+    /// Look for the peers with `exists` until all are there or the
+    /// timeout passed. Returns the peers missing, none unless proceeding
+    /// without them.
+    pub fn wait<C, F>(
+        &self,
+        clock: &C,
+        events: &Events,
+        mut exists: F,
+    ) -> Result<Vec<String>, ReadinessError>
+    where
+        C: Clock,
+        F: FnMut(&str) -> bool,
+    {
+        let start = clock.now();
+        let mut missing = self.required.clone();
+        let waited = loop {
+            missing.retain(|peer| {
+                let found = exists(peer);
+                if found {
+                    events.emit(EventKind::PeerReady { peer: peer.clone() });
+                }
+                !found
+            });
+            let waited = clock.now() - start;
+            if missing.is_empty() || waited >= self.timeout {
+                break waited;
+            }
+            clock.sleep(self.poll.min(self.timeout - waited));
+        };
+
+        if missing.is_empty() || self.on_timeout == OnTimeout::Proceed {
+            if !missing.is_empty() {
+                warn!(
+                    "proceeding without {} after {:?}",
+                    missing.join(", "),
+                    waited
+                );
+            }
+            events.emit(EventKind::Ready {
+                missing: missing.clone(),
+            });
+            Ok(missing)
+        } else {
+            events.emit(EventKind::NotReady {
+                missing: missing.clone(),
+            });
+            Err(ReadinessError::MissingPeers(missing, waited))
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReadinessError {
+    /// The peers whose queues weren't there after waiting so long.
+    MissingPeers(Vec<String>, Duration),
+    InvalidNamespace(NamespaceError),
+}
+
+impl fmt::Display for ReadinessError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ReadinessError::MissingPeers(ref peers, waited) => {
+                write!(f, "peers {} missing after {:?}", peers.join(", "), waited)
+            }
+            ReadinessError::InvalidNamespace(ref err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl Error for ReadinessError {}
+
+impl From<NamespaceError> for ReadinessError {
+    fn from(err: NamespaceError) -> Self {
+        ReadinessError::InvalidNamespace(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Barrier, OnTimeout, ReadinessError};
+    use crate::capture::{Clock, MockClock};
+    use crate::events::{EventKind, Events, Status};
+    use std::time::Duration;
+
+    #[test]
+    fn peers_found_late_release_the_barrier() {
+        let clock = MockClock::default();
+        let (events, rx) = Events::channel();
+        let barrier = Barrier::new(vec!["auth", "chain"], Duration::from_secs(5));
+        let missing = barrier
+            .wait(&clock, &events, |peer| {
+                peer == "chain" || clock.now() >= Duration::from_millis(300)
+            })
+            .unwrap();
+        assert!(missing.is_empty());
+        assert_eq!(clock.now(), Duration::from_millis(300));
+
+        let kinds: Vec<EventKind> = rx.try_iter().map(|event| event.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                EventKind::PeerReady {
+                    peer: "chain".to_owned()
+                },
+                EventKind::PeerReady {
+                    peer: "auth".to_owned()
+                },
+                EventKind::Ready { missing: vec![] },
+            ]
+        );
+
+        // Nobody required, nothing to wait for.
+        let missing = Barrier::new(Vec::<String>::new(), Duration::from_secs(5))
+            .wait(&clock, &Events::none(), |_| false)
+            .unwrap();
+        assert!(missing.is_empty());
+        assert_eq!(clock.now(), Duration::from_millis(300));
+    }
+
+    #[test]
+    fn timeout_fails_or_proceeds() {
+        let clock = MockClock::default();
+        let (events, rx) = Events::channel();
+        let mut status = Status::default();
+        let barrier = Barrier::new(vec!["auth", "chain"], Duration::from_millis(250));
+
+        let err = barrier
+            .wait(&clock, &events, |peer| peer == "chain")
+            .unwrap_err();
+        assert_eq!(
+            err,
+            ReadinessError::MissingPeers(vec!["auth".to_owned()], Duration::from_millis(250))
+        );
+        assert_eq!(err.to_string(), "peers auth missing after 250ms");
+        status.update(&rx);
+        assert!(!status.ready);
+        assert_eq!(status.missing_peers, vec!["auth".to_owned()]);
+
+        let barrier = barrier
+            .on_timeout(OnTimeout::Proceed)
+            .poll_every(Duration::from_millis(40));
+        let missing = barrier.wait(&clock, &events, |_| false).unwrap();
+        assert_eq!(missing, vec!["auth".to_owned(), "chain".to_owned()]);
+        assert_eq!(clock.now(), Duration::from_millis(500));
+        status.update(&rx);
+        assert!(status.ready);
+        assert_eq!(status.missing_peers, missing);
+    }
+}
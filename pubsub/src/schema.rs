@@ -0,0 +1,469 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Which schema the services were built with.
+//!
+//! A service built against another libproto decodes the messages of the
+//! others wrong, or fails deep in the decoder. So each one announces its
+//! schema, `libproto::compat::fingerprint()` of its build, on `SCHEMA_KEY`:
+//! when it starts, every `ANNOUNCE_INTERVAL`, and again when it hears from
+//! a peer for the first time, so one joining late learns of everybody at
+//! once. The announcements go to a queue of their own, `schema_queue`, and
+//! never reach the service.
+//!
+//! A `SchemaRegistry` keeps the latest announcement of each peer. Its
+//! `compatibility_report` lists the peers with another schema, or without
+//! an envelope version in common. In strict mode `check` fails while one
+//! of them is critical, see `start_pubsub_with_schema`.
+//!
+//! Only the last announcement of a service counts, so of several
+//! instances the one announcing last is reported.
+
+use crate::channel::Sender;
+use crate::namespace::NamespaceError;
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+pub const SCHEMA_KEY: &str = "schema.announce";
+pub const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// First line of an announcement, which a later format changes.
+const HEADER: &str = "schema-announce 1";
+
+/// The queue of the announcements `name` receives.
+pub fn schema_queue(name: &str) -> String {
+    format!("{}_schema", name)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchemaAnnounce {
+    pub service: String,
+    /// The fingerprint of the schema.
+    pub schema: String,
+    /// Of the crate, to tell the builds apart in reports.
+    pub version: String,
+    /// Of the messages the service reads.
+    pub envelope_versions: Vec<u32>,
+}
+
+impl SchemaAnnounce {
+    pub fn new(service: &str, schema: &str, version: &str, envelope_versions: Vec<u32>) -> Self {
+        SchemaAnnounce {
+            service: service.to_owned(),
+            schema: schema.to_owned(),
+            version: version.to_owned(),
+            envelope_versions,
+        }
+    }
+
+    /// A line per field, after the header.
+    pub fn encode(&self) -> Vec<u8> {
+        let envelopes: Vec<String> = self
+            .envelope_versions
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        format!(
+            "{}\nservice {}\nschema {}\nversion {}\nenvelopes {}\n",
+            HEADER,
+            self.service,
+            self.schema,
+            self.version,
+            envelopes.join(" ")
+        )
+        .into_bytes()
+    }
+
+    pub fn decode(body: &[u8]) -> Option<Self> {
+        let text = ::std::str::from_utf8(body).ok()?;
+        let mut lines = text.lines();
+        if lines.next()? != HEADER {
+            return None;
+        }
+        let mut field = |name: &str| {
+            let line = lines.next()?;
+            if line == name {
+                Some("")
+            } else if line.starts_with(name) && line[name.len()..].starts_with(' ') {
+                Some(&line[name.len() + 1..])
+            } else {
+                None
+            }
+        };
+        let service = field("service")?;
+        let schema = field("schema")?;
+        let version = field("version")?;
+        let envelope_versions = field("envelopes")?
+            .split_whitespace()
+            .map(str::parse)
+            .collect::<Result<_, _>>()
+            .ok()?;
+        if service.is_empty() || schema.is_empty() {
+            return None;
+        }
+        Some(SchemaAnnounce::new(
+            service,
+            schema,
+            version,
+            envelope_versions,
+        ))
+    }
+
+    /// The routing key and body to publish.
+    pub fn message(&self) -> (String, Vec<u8>) {
+        (SCHEMA_KEY.to_owned(), self.encode())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerStatus {
+    pub announce: SchemaAnnounce,
+    pub critical: bool,
+    pub schema_matches: bool,
+    pub common_envelopes: Vec<u32>,
+}
+
+impl PeerStatus {
+    pub fn is_compatible(&self) -> bool {
+        self.schema_matches && !self.common_envelopes.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompatibilityReport {
+    pub own: SchemaAnnounce,
+    /// By service.
+    pub peers: Vec<PeerStatus>,
+}
+
+impl CompatibilityReport {
+    pub fn incompatible(&self) -> Vec<&PeerStatus> {
+        self.peers
+            .iter()
+            .filter(|peer| !peer.is_compatible())
+            .collect()
+    }
+
+    pub fn is_compatible(&self) -> bool {
+        self.peers.iter().all(PeerStatus::is_compatible)
+    }
+}
+
+impl fmt::Display for CompatibilityReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let incompatible = self.incompatible();
+        if incompatible.is_empty() {
+            return write!(
+                f,
+                "{} peers on schema {}",
+                self.peers.len(),
+                self.own.schema
+            );
+        }
+        for (i, peer) in incompatible.into_iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            let announce = &peer.announce;
+            write!(f, "{} {}", announce.service, announce.version)?;
+            if peer.critical {
+                write!(f, " (critical)")?;
+            }
+            if !peer.schema_matches {
+                write!(
+                    f,
+                    " has schema {} instead of {}",
+                    announce.schema, self.own.schema
+                )?;
+            } else {
+                write!(f, " reads no envelope version this build does")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SchemaError {
+    /// The critical peers built differently, and the report.
+    IncompatiblePeers(Vec<String>, Box<CompatibilityReport>),
+    InvalidNamespace(NamespaceError),
+}
+
+impl fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SchemaError::IncompatiblePeers(ref services, ref report) => write!(
+                f,
+                "incompatible critical peers {}: {}",
+                services.join(", "),
+                report
+            ),
+            SchemaError::InvalidNamespace(ref err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl Error for SchemaError {}
+
+impl From<NamespaceError> for SchemaError {
+    fn from(err: NamespaceError) -> Self {
+        SchemaError::InvalidNamespace(err)
+    }
+}
+
+#[derive(Debug)]
+struct Registry {
+    own: SchemaAnnounce,
+    critical: BTreeSet<String>,
+    strict: bool,
+    peers: BTreeMap<String, SchemaAnnounce>,
+    malformed: usize,
+}
+
+/// The announcements heard of, shared with the consumer which receives
+/// them.
+#[derive(Debug, Clone)]
+pub struct SchemaRegistry {
+    inner: Arc<Mutex<Registry>>,
+}
+
+impl SchemaRegistry {
+    pub fn new(own: SchemaAnnounce) -> Self {
+        SchemaRegistry {
+            inner: Arc::new(Mutex::new(Registry {
+                own,
+                critical: BTreeSet::new(),
+                strict: false,
+                peers: BTreeMap::new(),
+                malformed: 0,
+            })),
+        }
+    }
+
+    /// The services `check` fails for in strict mode, `auth` or `chain`
+    /// say.
+    pub fn critical<I, S>(self, services: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.inner
+            .lock()
+            .unwrap()
+            .critical
+            .extend(services.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn strict(self, strict: bool) -> Self {
+        self.inner.lock().unwrap().strict = strict;
+        self
+    }
+
+    pub fn is_strict(&self) -> bool {
+        self.inner.lock().unwrap().strict
+    }
+
+    pub fn own(&self) -> SchemaAnnounce {
+        self.inner.lock().unwrap().own.clone()
+    }
+
+    /// Take note of an announcement. For a peer heard of the first time,
+    /// or built anew, the announcement to answer with.
+    pub fn receive(&self, body: &[u8]) -> Option<(String, Vec<u8>)> {
+        let mut inner = self.inner.lock().unwrap();
+        let announce = match SchemaAnnounce::decode(body) {
+            Some(announce) => announce,
+            None => {
+                inner.malformed += 1;
+                warn!("malformed schema announcement of {} bytes", body.len());
+                return None;
+            }
+        };
+        if announce.service == inner.own.service {
+            return None;
+        }
+        let before = inner
+            .peers
+            .insert(announce.service.clone(), announce.clone());
+        if before.as_ref() == Some(&announce) {
+            return None;
+        }
+        if announce.schema != inner.own.schema {
+            warn!(
+                "{} {} has schema {}, this build {}",
+                announce.service, announce.version, announce.schema, inner.own.schema
+            );
+        }
+        Some(inner.own.message())
+    }
+
+    /// Announcements which didn't decode.
+    pub fn malformed(&self) -> usize {
+        self.inner.lock().unwrap().malformed
+    }
+
+    pub fn compatibility_report(&self) -> CompatibilityReport {
+        let inner = self.inner.lock().unwrap();
+        let peers = inner
+            .peers
+            .values()
+            .map(|announce| PeerStatus {
+                announce: announce.clone(),
+                critical: inner.critical.contains(&announce.service),
+                schema_matches: announce.schema == inner.own.schema,
+                common_envelopes: announce
+                    .envelope_versions
+                    .iter()
+                    .filter(|v| inner.own.envelope_versions.contains(v))
+                    .cloned()
+                    .collect(),
+            })
+            .collect();
+        CompatibilityReport {
+            own: inner.own.clone(),
+            peers,
+        }
+    }
+
+    /// Fails in strict mode while a critical peer is incompatible.
+    pub fn check(&self) -> Result<(), SchemaError> {
+        if !self.inner.lock().unwrap().strict {
+            return Ok(());
+        }
+        let report = self.compatibility_report();
+        let services: Vec<String> = report
+            .incompatible()
+            .into_iter()
+            .filter(|peer| peer.critical)
+            .map(|peer| peer.announce.service.clone())
+            .collect();
+        if services.is_empty() {
+            Ok(())
+        } else {
+            Err(SchemaError::IncompatiblePeers(services, Box::new(report)))
+        }
+    }
+}
+
+/// Send the announcement of `registry` to `tx` now and every `interval`,
+/// until the publisher is gone.
+pub fn spawn_announcer(
+    registry: SchemaRegistry,
+    tx: Sender<(String, Vec<u8>)>,
+    interval: Duration,
+) {
+    let _ = thread::Builder::new()
+        .name("schema announcer".to_string())
+        .spawn(move || {
+            let announce = registry.own();
+            while tx.send(announce.message()).is_ok() {
+                thread::sleep(interval);
+            }
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SchemaAnnounce, SchemaError, SchemaRegistry, SCHEMA_KEY};
+
+    fn announce(service: &str, schema: &str, envelopes: Vec<u32>) -> SchemaAnnounce {
+        SchemaAnnounce::new(service, schema, "0.6.0", envelopes)
+    }
+
+    #[test]
+    fn announce_round_trip() {
+        let announce = announce("auth", "a1b2c3d4e5f60718", vec![1, 2]);
+        let (key, body) = announce.message();
+        assert_eq!(key, SCHEMA_KEY);
+        assert_eq!(
+            String::from_utf8(body.clone()).unwrap(),
+            "schema-announce 1\nservice auth\nschema a1b2c3d4e5f60718\nversion 0.6.0\nenvelopes 1 2\n"
+        );
+        assert_eq!(SchemaAnnounce::decode(&body), Some(announce));
+
+        let none = SchemaAnnounce::new("chain", "ff", "", vec![]);
+        assert_eq!(SchemaAnnounce::decode(&none.encode()), Some(none));
+
+        for malformed in &[
+            &b""[..],
+            b"schema-announce 2\nservice auth\nschema ff\nversion 1\nenvelopes 1\n",
+            b"schema-announce 1\nservice auth\nschema ff\nversion 1\n",
+            b"schema-announce 1\nservice auth\nschema ff\nversion 1\nenvelopes x\n",
+            b"schema-announce 1\nservice \nschema ff\nversion 1\nenvelopes 1\n",
+            b"schema-announce 1\nschema ff\nservice auth\nversion 1\nenvelopes 1\n",
+            b"\xff",
+        ] {
+            assert_eq!(SchemaAnnounce::decode(malformed), None, "{:?}", malformed);
+        }
+    }
+
+    #[test]
+    fn report() {
+        let registry = SchemaRegistry::new(announce("jsonrpc", "aaaa", vec![1, 2]))
+            .critical(vec!["auth", "chain"])
+            .strict(true);
+        assert!(registry.compatibility_report().is_compatible());
+        assert_eq!(registry.check(), Ok(()));
+
+        // Answered the first time only.
+        let auth = announce("auth", "aaaa", vec![2, 3]).encode();
+        assert_eq!(registry.receive(&auth), Some(registry.own().message()));
+        assert_eq!(registry.receive(&auth), None);
+        // Its own, echoed by the broker.
+        assert_eq!(registry.receive(&registry.own().encode()), None);
+        assert_eq!(registry.receive(b"garbage"), None);
+        assert_eq!(registry.malformed(), 1);
+
+        registry.receive(&announce("network", "bbbb", vec![1]).encode());
+        registry.receive(&announce("executor", "aaaa", vec![3]).encode());
+        let report = registry.compatibility_report();
+        let services: Vec<&str> = report
+            .peers
+            .iter()
+            .map(|peer| peer.announce.service.as_str())
+            .collect();
+        assert_eq!(services, vec!["auth", "executor", "network"]);
+        assert_eq!(report.peers[0].common_envelopes, vec![2]);
+        assert!(report.peers[0].critical);
+        assert_eq!(
+            report.to_string(),
+            "executor 0.6.0 reads no envelope version this build does, \
+             network 0.6.0 has schema bbbb instead of aaaa"
+        );
+        // Neither is critical.
+        assert_eq!(registry.check(), Ok(()));
+
+        assert!(registry
+            .receive(&announce("chain", "cccc", vec![1]).encode())
+            .is_some());
+        match registry.check() {
+            Err(SchemaError::IncompatiblePeers(services, report)) => {
+                assert_eq!(services, vec!["chain".to_owned()]);
+                assert_eq!(report.incompatible().len(), 3);
+            }
+            other => panic!("{:?}", other),
+        }
+
+        // Only reported when lenient.
+        let registry = registry.strict(false);
+        assert_eq!(registry.check(), Ok(()));
+        assert!(!registry.compatibility_report().is_compatible());
+    }
+}
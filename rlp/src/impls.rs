@@ -10,6 +10,7 @@ use byteorder::{BigEndian, ByteOrder};
 use std::{cmp, mem, str};
 use stream::RlpStream;
 use traits::{Decodable, Encodable};
+use types::{BlockHeight, HeightDiff, Quota, TxIndex};
 use types::{Bloom, H128, H160, H256, H512, H520, H64, U128, U256};
 use {DecoderError, UntrustedRlp};
 
@@ -178,6 +179,28 @@ impl Decodable for usize {
     }
 }
 
+/// The quantities are encoded exactly like the integers they wrap.
+macro_rules! impl_rlp_for_quantity {
+    ($name: ident, $inner: ident) => {
+        impl Encodable for $name {
+            fn rlp_append(&self, s: &mut RlpStream) {
+                self.0.rlp_append(s);
+            }
+        }
+
+        impl Decodable for $name {
+            fn decode(rlp: &UntrustedRlp) -> Result<Self, DecoderError> {
+                $inner::decode(rlp).map($name)
+            }
+        }
+    };
+}
+
+impl_rlp_for_quantity!(BlockHeight, u64);
+impl_rlp_for_quantity!(HeightDiff, u64);
+impl_rlp_for_quantity!(TxIndex, u32);
+impl_rlp_for_quantity!(Quota, u64);
+
 macro_rules! impl_encodable_for_hash {
     ($name: ident) => {
         impl Encodable for $name {
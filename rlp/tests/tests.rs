@@ -11,7 +11,7 @@ extern crate rlp;
 
 use rlp::{Decodable, DecoderError, Encodable, RlpStream, UntrustedRlp};
 use std::{cmp, fmt};
-use types::U256;
+use types::{BlockHeight, HeightDiff, Quota, TxIndex, U256};
 
 #[test]
 fn rlp_at() {
@@ -469,3 +469,34 @@ fn test_rlp_stream_unbounded_list() {
     stream.complete_unbounded_list();
     assert!(stream.is_finished());
 }
+
+#[test]
+fn quantities_encoded_like_integers() {
+    for &value in &[0u64, 1, 0x7f, 0x80, 0x1000000, u64::max_value()] {
+        let bytes = rlp::encode(&value).into_vec();
+        assert_eq!(rlp::encode(&BlockHeight(value)).into_vec(), bytes);
+        assert_eq!(rlp::encode(&HeightDiff(value)).into_vec(), bytes);
+        assert_eq!(rlp::encode(&Quota(value)).into_vec(), bytes);
+        assert_eq!(
+            UntrustedRlp::new(&bytes).as_val::<BlockHeight>(),
+            Ok(BlockHeight(value))
+        );
+        assert_eq!(
+            UntrustedRlp::new(&bytes).as_val::<Quota>(),
+            Ok(Quota(value))
+        );
+    }
+    for &value in &[0u32, 0x80, u32::max_value()] {
+        let bytes = rlp::encode(&value).into_vec();
+        assert_eq!(rlp::encode(&TxIndex(value)).into_vec(), bytes);
+        assert_eq!(
+            UntrustedRlp::new(&bytes).as_val::<TxIndex>(),
+            Ok(TxIndex(value))
+        );
+    }
+    let too_big = rlp::encode(&(u64::from(u32::max_value()) + 1)).into_vec();
+    assert_eq!(
+        UntrustedRlp::new(&too_big).as_val::<TxIndex>(),
+        Err(DecoderError::RlpIsTooBig)
+    );
+}
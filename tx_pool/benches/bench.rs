@@ -61,7 +61,7 @@ fn bench_enqueue(b: &mut Bencher) {
         tx.set_valid_until_block(99);
         // 2000*10000 <= account_quota_limit <= block_quota_limit
         tx.set_quota(2000);
-        p.enqueue(tx.sign(*pv));
+        p.enqueue(tx.sign(pv));
     }
     let sys_time = SystemTime::now();
     let diff = sys_time
@@ -91,7 +91,7 @@ fn bench_package(b: &mut Bencher) {
         tx.set_valid_until_block(99);
         // 6000*10000 <= account_quota_limit <= block_quota_limit
         tx.set_quota(6000);
-        p.enqueue(tx.sign(*pv));
+        p.enqueue(tx.sign(pv));
     }
     let mut account_quota_limit = AccountGasLimit::new();
     // set block_quota_limit default
@@ -132,7 +132,7 @@ fn bench_update(b: &mut Bencher) {
         tx.set_valid_until_block(99);
         // 6000*10000 <= account_quota_limit <= block_quota_limit
         tx.set_quota(6000);
-        p.enqueue(tx.sign(*pv));
+        p.enqueue(tx.sign(pv));
     }
     let mut account_quota_limit = AccountGasLimit::new();
     // set block_quota_limit default
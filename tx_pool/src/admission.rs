@@ -0,0 +1,613 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Verifying transactions on their way into the pool, so every service
+//! feeding it checks them the same way.
+//!
+//! `Admission::submit_unverified` hands a transaction to worker threads,
+//! which run the stages in turn:
+//!
+//! - verify: the `TxPolicy`, then recover the sender from the signature
+//! - dedup: not in the pool, nor in the caller's `RecentHistory`
+//! - insert: `Pool::admit`, which replaces the transaction of the same
+//!   sender with the same nonce
+//!
+//! A worker takes whatever was submitted meanwhile as one batch, verified
+//! together and deduplicated and inserted under one lock of the pool.
+//!
+//! A transaction is submitted with where it came from, which the pool
+//! keeps, and the metrics count by.
+
+use libproto::policy::{verify_batch, PolicyViolation, TxPolicy, TxRejection};
+use libproto::tx_origin::TxOrigin;
+use libproto::UnverifiedTransaction;
+use pool::{AdmissionOutcome, Pool};
+use std::collections::HashSet;
+use std::error;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use types::H256;
+
+pub const DEFAULT_PARALLELISM: usize = 4;
+pub const DEFAULT_BATCH_SIZE: usize = 64;
+
+/// Transactions of the recent blocks, which must not be pooled again.
+pub trait RecentHistory: Send + Sync {
+    fn contains(&self, tx_hash: &H256) -> bool;
+}
+
+impl RecentHistory for HashSet<H256> {
+    fn contains(&self, tx_hash: &H256) -> bool {
+        HashSet::contains(self, tx_hash)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SubmitError {
+    Policy(PolicyViolation),
+    Signature(String),
+    InPool,
+    InRecentBlock,
+    /// The admission stopped before the transaction was handled.
+    Stopped,
+}
+
+impl fmt::Display for SubmitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SubmitError::Policy(ref violation) => write!(f, "policy violated: {}", violation),
+            SubmitError::Signature(ref reason) => write!(f, "invalid signature: {}", reason),
+            SubmitError::InPool => write!(f, "already in the pool"),
+            SubmitError::InRecentBlock => write!(f, "already in a recent block"),
+            SubmitError::Stopped => write!(f, "admission stopped"),
+        }
+    }
+}
+
+impl error::Error for SubmitError {}
+
+pub type SubmitResult = Result<H256, SubmitError>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AdmissionConfig {
+    /// Worker threads.
+    pub parallelism: usize,
+    /// Most submissions a worker takes at once.
+    pub batch_size: usize,
+}
+
+impl Default for AdmissionConfig {
+    fn default() -> Self {
+        AdmissionConfig {
+            parallelism: DEFAULT_PARALLELISM,
+            batch_size: DEFAULT_BATCH_SIZE,
+        }
+    }
+}
+
+/// What one stage did. A transaction rejected by it doesn't reach the
+/// next one.
+#[derive(Debug, Default)]
+pub struct StageMetrics {
+    passed: AtomicUsize,
+    rejected: AtomicUsize,
+    nanos: AtomicU64,
+}
+
+impl StageMetrics {
+    pub fn passed(&self) -> usize {
+        self.passed.load(Ordering::SeqCst)
+    }
+
+    pub fn rejected(&self) -> usize {
+        self.rejected.load(Ordering::SeqCst)
+    }
+
+    /// Time spent in the stage, by all workers together.
+    pub fn time(&self) -> Duration {
+        Duration::from_nanos(self.nanos.load(Ordering::SeqCst))
+    }
+
+    fn record(&self, passed: usize, rejected: usize, since: Instant) {
+        self.passed.fetch_add(passed, Ordering::SeqCst);
+        self.rejected.fetch_add(rejected, Ordering::SeqCst);
+        let nanos = since.elapsed().as_nanos().min(u128::from(::std::u64::MAX)) as u64;
+        self.nanos.fetch_add(nanos, Ordering::SeqCst);
+    }
+}
+
+/// Submissions from one kind of origin, and how many of them got into
+/// the pool.
+#[derive(Debug, Default)]
+pub struct OriginMetrics {
+    submitted: AtomicUsize,
+    admitted: AtomicUsize,
+}
+
+impl OriginMetrics {
+    pub fn submitted(&self) -> usize {
+        self.submitted.load(Ordering::SeqCst)
+    }
+
+    pub fn admitted(&self) -> usize {
+        self.admitted.load(Ordering::SeqCst)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct AdmissionMetrics {
+    pub verify: StageMetrics,
+    pub dedup: StageMetrics,
+    pub insert: StageMetrics,
+    pub rpc: OriginMetrics,
+    /// From any peer.
+    pub peer: OriginMetrics,
+    pub resubmit: OriginMetrics,
+    batches: AtomicUsize,
+    replaced: AtomicUsize,
+}
+
+impl AdmissionMetrics {
+    pub fn origin(&self, origin: TxOrigin) -> &OriginMetrics {
+        match origin {
+            TxOrigin::Rpc => &self.rpc,
+            TxOrigin::Peer { .. } => &self.peer,
+            TxOrigin::Resubmit => &self.resubmit,
+        }
+    }
+
+    pub fn batches(&self) -> usize {
+        self.batches.load(Ordering::SeqCst)
+    }
+
+    /// Inserted transactions which replaced one of the same nonce.
+    pub fn replaced(&self) -> usize {
+        self.replaced.load(Ordering::SeqCst)
+    }
+}
+
+struct Job {
+    tx: UnverifiedTransaction,
+    origin: TxOrigin,
+    reply: Sender<SubmitResult>,
+}
+
+struct Worker<H> {
+    jobs: Arc<Mutex<Receiver<Job>>>,
+    pool: Arc<Mutex<Pool>>,
+    policy: TxPolicy,
+    history: Arc<H>,
+    metrics: Arc<AdmissionMetrics>,
+    batch_size: usize,
+}
+
+impl<H: RecentHistory> Worker<H> {
+    fn run(&self) {
+        while let Some(batch) = self.next_batch() {
+            self.handle(batch);
+        }
+    }
+
+    /// Wait for a submission and take those queued behind it, none once
+    /// the admission is dropped.
+    fn next_batch(&self) -> Option<Vec<Job>> {
+        let jobs = self.jobs.lock().unwrap();
+        let mut batch = vec![jobs.recv().ok()?];
+        while batch.len() < self.batch_size {
+            match jobs.try_recv() {
+                Ok(job) => batch.push(job),
+                Err(_) => break,
+            }
+        }
+        Some(batch)
+    }
+
+    fn handle(&self, batch: Vec<Job>) {
+        self.metrics.batches.fetch_add(1, Ordering::SeqCst);
+        let mut txs = Vec::with_capacity(batch.len());
+        let mut origins = Vec::with_capacity(batch.len());
+        let mut replies = Vec::with_capacity(batch.len());
+        for job in batch {
+            self.metrics
+                .origin(job.origin)
+                .submitted
+                .fetch_add(1, Ordering::SeqCst);
+            txs.push(job.tx);
+            origins.push(job.origin);
+            replies.push(job.reply);
+        }
+
+        let since = Instant::now();
+        let verified = verify_batch(&self.policy, txs);
+        let mut results: Vec<Option<SubmitResult>> = vec![None; verified.len()];
+        let mut signed = Vec::with_capacity(verified.len());
+        for (i, result) in verified.into_iter().enumerate() {
+            match result {
+                Ok(tx) => signed.push((i, tx)),
+                Err(TxRejection::Policy(_, violation)) => {
+                    results[i] = Some(Err(SubmitError::Policy(violation)));
+                }
+                Err(TxRejection::Signature(_, reason)) => {
+                    results[i] = Some(Err(SubmitError::Signature(reason)));
+                }
+            }
+        }
+        self.metrics
+            .verify
+            .record(signed.len(), results.len() - signed.len(), since);
+
+        let mut pool = self.pool.lock().unwrap();
+        let since = Instant::now();
+        let checked = signed.len();
+        let mut seen = HashSet::new();
+        let mut fresh = Vec::with_capacity(checked);
+        for (i, tx) in signed {
+            let hash = H256::from_slice(tx.get_tx_hash());
+            // A batch may have the same transaction twice.
+            results[i] = Some(if pool.get(&hash).is_some() || !seen.insert(hash) {
+                Err(SubmitError::InPool)
+            } else if self.history.contains(&hash) {
+                Err(SubmitError::InRecentBlock)
+            } else {
+                fresh.push((i, tx));
+                Ok(hash)
+            });
+        }
+        self.metrics
+            .dedup
+            .record(fresh.len(), checked - fresh.len(), since);
+
+        let since = Instant::now();
+        let inserted = fresh.len();
+        for (i, tx) in fresh {
+            if let AdmissionOutcome::Replaced { .. } = pool.admit_from(tx, origins[i]) {
+                self.metrics.replaced.fetch_add(1, Ordering::SeqCst);
+            }
+            self.metrics
+                .origin(origins[i])
+                .admitted
+                .fetch_add(1, Ordering::SeqCst);
+        }
+        self.metrics.insert.record(inserted, 0, since);
+        drop(pool);
+
+        for (reply, result) in replies.into_iter().zip(results) {
+            // The submitter doesn't wait for the result.
+            let _ = reply.send(result.expect("a result for every submission"));
+        }
+    }
+}
+
+/// The workers verifying and admitting submitted transactions. Dropping
+/// it waits for those already submitted.
+pub struct Admission {
+    jobs: Option<Sender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+    metrics: Arc<AdmissionMetrics>,
+}
+
+impl Admission {
+    pub fn new<H>(
+        pool: Arc<Mutex<Pool>>,
+        policy: TxPolicy,
+        history: Arc<H>,
+        config: AdmissionConfig,
+    ) -> Self
+    where
+        H: RecentHistory + 'static,
+    {
+        let (jobs, rx) = mpsc::channel();
+        let rx = Arc::new(Mutex::new(rx));
+        let metrics = Arc::new(AdmissionMetrics::default());
+        let workers = (0..config.parallelism.max(1))
+            .map(|i| {
+                let worker = Worker {
+                    jobs: Arc::clone(&rx),
+                    pool: Arc::clone(&pool),
+                    policy: policy.clone(),
+                    history: Arc::clone(&history),
+                    metrics: Arc::clone(&metrics),
+                    batch_size: config.batch_size.max(1),
+                };
+                thread::Builder::new()
+                    .name(format!("admission-{}", i))
+                    .spawn(move || worker.run())
+                    .expect("spawn an admission worker")
+            })
+            .collect();
+        Admission {
+            jobs: Some(jobs),
+            workers,
+            metrics,
+        }
+    }
+
+    /// Verify `tx` and put it into the pool. The result comes on the
+    /// returned channel, with the hash the pool knows it by.
+    pub fn submit_unverified(&self, tx: UnverifiedTransaction) -> Receiver<SubmitResult> {
+        self.submit_unverified_from(tx, TxOrigin::default())
+    }
+
+    /// `submit_unverified` a transaction which came from `origin`.
+    pub fn submit_unverified_from(
+        &self,
+        tx: UnverifiedTransaction,
+        origin: TxOrigin,
+    ) -> Receiver<SubmitResult> {
+        let (reply, rx) = mpsc::channel();
+        let job = Job { tx, origin, reply };
+        if let Some(ref jobs) = self.jobs {
+            if let Err(mpsc::SendError(job)) = jobs.send(job) {
+                let _ = job.reply.send(Err(SubmitError::Stopped));
+            }
+        }
+        rx
+    }
+
+    pub fn metrics(&self) -> &AdmissionMetrics {
+        &self.metrics
+    }
+}
+
+impl Drop for Admission {
+    fn drop(&mut self) {
+        self.jobs.take();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "secp256k1")]
+mod tests {
+    use super::{Admission, AdmissionConfig, OriginMetrics, SubmitError, SubmitResult};
+    use crypto::{CreateKey, KeyPair, PrivKey};
+    use libproto::blockchain::Transaction;
+    use libproto::policy::{TxPolicy, Violation};
+    use libproto::tx_origin::TxOrigin;
+    use libproto::UnverifiedTransaction;
+    use pool::Pool;
+    use std::collections::HashSet;
+    use std::mem;
+    use std::sync::{Arc, Mutex};
+    use types::H256;
+
+    fn unverified(
+        privkey: &PrivKey,
+        nonce: usize,
+        data: u8,
+        chain_id: u32,
+    ) -> UnverifiedTransaction {
+        let mut tx = Transaction::new();
+        tx.set_to("0x0000000000000000000000000000000000001234".to_owned());
+        tx.set_nonce(nonce.to_string());
+        tx.set_data(vec![data]);
+        tx.set_valid_until_block(100);
+        tx.set_quota(100);
+        tx.set_chain_id(chain_id);
+        tx.build_unverified(privkey)
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    enum Expected {
+        Admitted,
+        /// Submitted twice, one of them is admitted.
+        Twice,
+        /// Shares the sender and nonce with another, both are admitted
+        /// and one stays in the pool.
+        Replacing(usize),
+        Policy,
+        Signature,
+        InRecentBlock,
+    }
+
+    /// Where the case `i` is submitted from.
+    fn origin(i: usize) -> TxOrigin {
+        match i % 3 {
+            0 => TxOrigin::Rpc,
+            1 => TxOrigin::Peer {
+                node_short_id: i as u32,
+            },
+            _ => TxOrigin::Resubmit,
+        }
+    }
+
+    fn flood(config: AdmissionConfig, seed: u64) {
+        let keys: Vec<KeyPair> = (0..8).map(|_| KeyPair::gen_keypair()).collect();
+        let mut cases = Vec::new();
+        for (k, key) in keys.iter().enumerate() {
+            for nonce in 0..10 {
+                let expected = if nonce < 3 && k < 7 {
+                    Expected::Twice
+                } else {
+                    Expected::Admitted
+                };
+                cases.push((unverified(key.privkey(), nonce, 0, 1), expected));
+            }
+        }
+        let mut history = HashSet::new();
+        for (k, key) in keys.iter().enumerate() {
+            cases.push((unverified(key.privkey(), 100, 0, 0), Expected::Policy));
+            let mut bad = unverified(key.privkey(), 101, 0, 1);
+            bad.set_signature(vec![1; 10]);
+            cases.push((bad, Expected::Signature));
+            let old = unverified(key.privkey(), 102, 0, 1);
+            history.insert(old.crypt_hash());
+            cases.push((old, Expected::InRecentBlock));
+            if k < 5 {
+                let first = cases.len();
+                cases.push((
+                    unverified(key.privkey(), 200, 1, 1),
+                    Expected::Replacing(first + 1),
+                ));
+                cases.push((
+                    unverified(key.privkey(), 200, 2, 1),
+                    Expected::Replacing(first),
+                ));
+            }
+        }
+
+        let mut submissions: Vec<usize> = (0..cases.len()).collect();
+        for (i, &(_, expected)) in cases.iter().enumerate() {
+            if expected == Expected::Twice {
+                submissions.push(i);
+            }
+        }
+        // Shuffle, so the batches differ from run to run.
+        let mut state = seed;
+        for i in (1..submissions.len()).rev() {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            submissions.swap(i, (state % (i as u64 + 1)) as usize);
+        }
+
+        let pool = Arc::new(Mutex::new(Pool::new(1000)));
+        let admission = Admission::new(
+            Arc::clone(&pool),
+            TxPolicy::default(),
+            Arc::new(history),
+            config,
+        );
+        let receivers: Vec<_> = submissions
+            .iter()
+            .map(|&i| {
+                let rx = admission.submit_unverified_from(cases[i].0.clone(), origin(i));
+                (i, rx)
+            })
+            .collect();
+        let mut results: Vec<Vec<SubmitResult>> = vec![Vec::new(); cases.len()];
+        for (i, rx) in receivers {
+            results[i].push(rx.recv().unwrap());
+        }
+
+        let pool = pool.lock().unwrap();
+        for (i, (tx, expected)) in cases.iter().enumerate() {
+            let hash: H256 = tx.crypt_hash();
+            let results = &results[i];
+            match *expected {
+                Expected::Admitted => {
+                    assert_eq!(results, &vec![Ok(hash)]);
+                    assert!(pool.get(&hash).is_some());
+                }
+                Expected::Twice => {
+                    assert!(results.contains(&Ok(hash)), "{:?}", results);
+                    assert!(results.contains(&Err(SubmitError::InPool)), "{:?}", results);
+                    assert!(pool.get(&hash).is_some());
+                }
+                Expected::Replacing(other) => {
+                    assert_eq!(results, &vec![Ok(hash)]);
+                    let other = cases[other].0.crypt_hash();
+                    assert!(pool.get(&hash).is_some() != pool.get(&other).is_some());
+                }
+                Expected::Policy => match results[..] {
+                    [Err(SubmitError::Policy(ref violation))] => {
+                        assert_eq!(violation.violations(), &[Violation::MissingChainId])
+                    }
+                    _ => panic!("{:?}", results),
+                },
+                Expected::Signature => assert_eq!(
+                    results,
+                    &vec![Err(SubmitError::Signature(
+                        "Invalid signature length".to_owned()
+                    ))]
+                ),
+                Expected::InRecentBlock => {
+                    assert_eq!(results, &vec![Err(SubmitError::InRecentBlock)]);
+                    assert!(pool.get(&hash).is_none());
+                }
+            }
+        }
+        // 80 distinct valid transactions, and one of each replacing pair.
+        assert_eq!(pool.len(), 85);
+        for (i, (tx, _)) in cases.iter().enumerate() {
+            if let Some(kept) = pool.origin(&tx.crypt_hash()) {
+                assert_eq!(kept, origin(i));
+            }
+        }
+        let status = pool.status();
+        assert_eq!(status.rpc + status.peer + status.resubmit, status.len);
+
+        let metrics = admission.metrics();
+        assert_eq!(metrics.verify.passed(), 80 + 21 + 8 + 10);
+        assert_eq!(metrics.verify.rejected(), 16);
+        assert_eq!(metrics.dedup.passed(), 90);
+        assert_eq!(metrics.dedup.rejected(), 21 + 8);
+        assert_eq!(metrics.insert.passed(), 90);
+        assert_eq!(metrics.replaced(), 5);
+        assert!(metrics.batches() >= submissions.len() / config.batch_size);
+
+        let kinds = [TxOrigin::Rpc, TxOrigin::default(), TxOrigin::Resubmit];
+        for kind in &kinds {
+            let of_kind = (0..cases.len())
+                .filter(|&i| mem::discriminant(&origin(i)) == mem::discriminant(kind));
+            let (submitted, admitted) = of_kind.fold((0, 0), |(submitted, admitted), i| {
+                let ok = results[i].iter().filter(|result| result.is_ok()).count();
+                (submitted + results[i].len(), admitted + ok)
+            });
+            assert_eq!(metrics.origin(*kind).submitted(), submitted);
+            assert_eq!(metrics.origin(*kind).admitted(), admitted);
+        }
+        let total = |count: fn(&OriginMetrics) -> usize| {
+            kinds
+                .iter()
+                .map(|kind| count(metrics.origin(*kind)))
+                .sum::<usize>()
+        };
+        assert_eq!(total(OriginMetrics::submitted), submissions.len());
+        assert_eq!(total(OriginMetrics::admitted), metrics.insert.passed());
+    }
+
+    #[test]
+    fn flood_of_mixed_submissions() {
+        flood(
+            AdmissionConfig {
+                parallelism: 1,
+                batch_size: 1,
+            },
+            1,
+        );
+        flood(
+            AdmissionConfig {
+                parallelism: 4,
+                batch_size: 16,
+            },
+            0x2545_f491_4f6c_dd1d,
+        );
+    }
+
+    #[test]
+    fn stops_after_submitted_are_handled() {
+        let pool = Arc::new(Mutex::new(Pool::new(10)));
+        let key = KeyPair::gen_keypair();
+        let tx = unverified(key.privkey(), 0, 0, 1);
+        let hash = tx.crypt_hash();
+        let rx = {
+            let admission = Admission::new(
+                Arc::clone(&pool),
+                TxPolicy::default(),
+                Arc::new(HashSet::new()),
+                AdmissionConfig::default(),
+            );
+            admission.submit_unverified(tx)
+        };
+        assert_eq!(rx.recv().unwrap(), Ok(hash));
+        assert_eq!(pool.lock().unwrap().len(), 1);
+    }
+}
@@ -17,6 +17,7 @@ extern crate cita_types as types;
 extern crate libproto;
 extern crate util;
 
+pub mod admission;
 pub mod pool;
 
 pub use pool::*;
@@ -14,11 +14,13 @@
 
 use crypto::{pubkey_to_address, PubKey};
 use libproto::blockchain::{AccountGasLimit, SignedTransaction};
+use libproto::tx_origin::TxOrigin;
 use std::cmp::Ordering;
-use std::collections::{BTreeSet, HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use types::traits::LowerHex;
-use types::{Address, H256};
-use util::BLOCKLIMIT;
+use types::tx_validity::ValidityPolicy;
+use types::{Address, BlockHeight, H256};
+use util::timer::{TimerHandle, TimerWheel};
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum Strategy {
@@ -58,13 +60,107 @@ impl Ord for TxOrder {
     }
 }
 
+/// Result of inserting a transaction into the pool through `Pool::admit`.
+/// An admitted one keeps the origin it was admitted from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AdmissionOutcome {
+    /// Ready to be packaged, `position` is its place in the package order.
+    Pending { position: usize, origin: TxOrigin },
+    /// Held back by the pool until the gap before its nonce is filled, see
+    /// `Pool::set_sequential_nonces`. `position` is its place among the
+    /// held back transactions of its sender.
+    Queued { position: usize, origin: TxOrigin },
+    /// Took the place of `replaced`, sent earlier by the same signer with the same nonce.
+    /// `position` is as for `Pending`, or as for `Queued` if it is held back in turn.
+    Replaced {
+        replaced: H256,
+        position: usize,
+        origin: TxOrigin,
+    },
+    /// The transaction is already in the pool.
+    Duplicate,
+}
+
+impl AdmissionOutcome {
+    pub fn is_admitted(&self) -> bool {
+        *self != AdmissionOutcome::Duplicate
+    }
+
+    pub fn position(&self) -> Option<usize> {
+        match *self {
+            AdmissionOutcome::Pending { position, .. }
+            | AdmissionOutcome::Queued { position, .. }
+            | AdmissionOutcome::Replaced { position, .. } => Some(position),
+            AdmissionOutcome::Duplicate => None,
+        }
+    }
+
+    pub fn origin(&self) -> Option<TxOrigin> {
+        match *self {
+            AdmissionOutcome::Pending { origin, .. }
+            | AdmissionOutcome::Queued { origin, .. }
+            | AdmissionOutcome::Replaced { origin, .. } => Some(origin),
+            AdmissionOutcome::Duplicate => None,
+        }
+    }
+
+    pub fn replaced_hash(&self) -> Option<H256> {
+        match *self {
+            AdmissionOutcome::Replaced { replaced, .. } => Some(replaced),
+            _ => None,
+        }
+    }
+}
+
+/// What the pool holds, by where it came from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PoolStatus {
+    pub len: usize,
+    pub rpc: usize,
+    pub peer: usize,
+    pub resubmit: usize,
+}
+
+type SenderNonce = (Vec<u8>, String);
+
+fn sender_nonce(tx: &SignedTransaction) -> SenderNonce {
+    (
+        tx.get_signer().to_vec(),
+        tx.get_transaction_with_sig()
+            .get_transaction()
+            .get_nonce()
+            .to_string(),
+    )
+}
+
+/// The nonce, if it is a decimal number.
+fn sequential_nonce(tx: &SignedTransaction) -> Option<u64> {
+    tx.get_transaction_with_sig()
+        .get_transaction()
+        .get_nonce()
+        .parse()
+        .ok()
+}
+
 #[derive(Debug)]
 pub struct Pool {
     package_limit: usize,
     order_set: BTreeSet<TxOrder>,
     txs: HashMap<H256, SignedTransaction>,
+    orders: HashMap<H256, u64>,
+    nonces: HashMap<SenderNonce, H256>,
+    origins: HashMap<H256, TxOrigin>,
+    /// Drops each transaction at its `valid_until_block`.
+    expiry: TimerWheel<Vec<H256>>,
+    expiry_handles: HashMap<H256, TimerHandle>,
+    validity: ValidityPolicy,
     strategy: Strategy,
     order: u64,
+    sequential_nonces: bool,
+    /// The decimal nonces of the transactions in the order, by signer.
+    pending_nonces: HashMap<Vec<u8>, BTreeSet<u64>>,
+    /// The transactions held back on a nonce gap, by signer and nonce.
+    held: HashMap<Vec<u8>, BTreeMap<u64, H256>>,
 }
 
 impl Pool {
@@ -73,8 +169,17 @@ impl Pool {
             package_limit,
             order_set: BTreeSet::new(),
             txs: HashMap::new(),
+            orders: HashMap::new(),
+            nonces: HashMap::new(),
+            origins: HashMap::new(),
+            expiry: TimerWheel::new(),
+            expiry_handles: HashMap::new(),
+            validity: ValidityPolicy::default(),
             strategy: Strategy::FIFO,
             order: 0,
+            sequential_nonces: false,
+            pending_nonces: HashMap::new(),
+            held: HashMap::new(),
         }
     }
 
@@ -83,11 +188,37 @@ impl Pool {
             package_limit,
             order_set: BTreeSet::new(),
             txs: HashMap::new(),
+            orders: HashMap::new(),
+            nonces: HashMap::new(),
+            origins: HashMap::new(),
+            expiry: TimerWheel::new(),
+            expiry_handles: HashMap::new(),
+            validity: ValidityPolicy::default(),
             strategy,
             order: 0,
+            sequential_nonces: false,
+            pending_nonces: HashMap::new(),
+            held: HashMap::new(),
         }
     }
 
+    /// Which transactions `package` takes, by their `valid_until_block`.
+    /// Set it before enqueueing any.
+    pub fn set_validity_policy(&mut self, validity: ValidityPolicy) {
+        self.validity = validity;
+    }
+
+    /// Hold back, in `admit`, a transaction with a decimal nonce more than
+    /// one past the highest of its signer in the order, until the nonces
+    /// in between are admitted. The pool only knows the nonces it has, so
+    /// a signer without any in the order is never held back, and when the
+    /// last one leaves the order the lowest held back goes in. Nonces are
+    /// free-form unless the chain says otherwise, so it is off by default.
+    /// Set it before enqueueing any.
+    pub fn set_sequential_nonces(&mut self, sequential: bool) {
+        self.sequential_nonces = sequential;
+    }
+
     fn get_order(&mut self) -> u64 {
         let order = self.order;
         let (new_order, _) = order.overflowing_add(1);
@@ -103,23 +234,216 @@ impl Pool {
         self.get_order()
     }
 
+    fn next_order(&mut self, tx: &SignedTransaction) -> u64 {
+        match self.strategy {
+            Strategy::FIFO => self.get_order(),
+            Strategy::PRIORITY => self.get_order_by_priority(tx),
+            Strategy::VIP => self.get_order_by_vip(tx),
+        }
+    }
+
+    fn insert_with_order(
+        &mut self,
+        hash: H256,
+        order: u64,
+        tx: SignedTransaction,
+        origin: TxOrigin,
+    ) {
+        self.order_set.insert(TxOrder::new(hash, order));
+        self.orders.insert(hash, order);
+        if let Some(nonce) = sequential_nonce(&tx) {
+            self.pending_nonces
+                .entry(tx.get_signer().to_vec())
+                .or_insert_with(BTreeSet::new)
+                .insert(nonce);
+        }
+        self.insert_tx(hash, tx, origin);
+    }
+
+    /// Into the pool, not into the order.
+    fn insert_tx(&mut self, hash: H256, tx: SignedTransaction, origin: TxOrigin) {
+        self.nonces.insert(sender_nonce(&tx), hash);
+        self.origins.insert(hash, origin);
+        let valid_until_block = tx
+            .get_transaction_with_sig()
+            .get_transaction()
+            .valid_until_block;
+        if !self.validity.never_expires(valid_until_block) {
+            let handle = self
+                .expiry
+                .schedule_at_height(valid_until_block, move |expired: &mut Vec<H256>| {
+                    expired.push(hash)
+                });
+            self.expiry_handles.insert(hash, handle);
+        }
+        self.txs.insert(hash, tx);
+    }
+
+    fn remove_tx(&mut self, hash: &H256) {
+        let in_order = self.orders.remove(hash).is_some();
+        if let Some(tx) = self.txs.remove(hash) {
+            let key = sender_nonce(&tx);
+            if self.nonces.get(&key) == Some(hash) {
+                self.nonces.remove(&key);
+            }
+            if let Some(nonce) = sequential_nonce(&tx) {
+                let signer = tx.get_signer();
+                if in_order {
+                    let emptied = self.pending_nonces.get_mut(signer).map_or(false, |nonces| {
+                        nonces.remove(&nonce);
+                        nonces.is_empty()
+                    });
+                    if emptied {
+                        self.pending_nonces.remove(signer);
+                    }
+                } else {
+                    self.unhold(signer, nonce);
+                }
+            }
+        }
+        self.origins.remove(hash);
+        if let Some(handle) = self.expiry_handles.remove(hash) {
+            self.expiry.cancel(handle);
+        }
+    }
+
+    /// Whether a transaction of `signer` with `nonce` waits on a gap.
+    fn is_gapped(&self, signer: &[u8], nonce: u64) -> bool {
+        self.sequential_nonces
+            && self
+                .pending_nonces
+                .get(signer)
+                .and_then(|nonces| nonces.iter().next_back())
+                .and_then(|highest| highest.checked_add(1))
+                .map_or(false, |next| nonce > next)
+    }
+
+    fn unhold(&mut self, signer: &[u8], nonce: u64) {
+        let emptied = self.held.get_mut(signer).map_or(false, |held| {
+            held.remove(&nonce);
+            held.is_empty()
+        });
+        if emptied {
+            self.held.remove(signer);
+        }
+    }
+
+    /// `release_held` for every signer, after transactions left the order.
+    fn release_all_held(&mut self) {
+        let signers: Vec<Vec<u8>> = self.held.keys().cloned().collect();
+        for signer in signers {
+            self.release_held(&signer);
+        }
+    }
+
+    /// Put the transactions of `signer` no longer waiting on a gap into
+    /// the order, in the order of their nonces.
+    fn release_held(&mut self, signer: &[u8]) {
+        loop {
+            let first = self
+                .held
+                .get(signer)
+                .and_then(|held| held.iter().next())
+                .map(|(nonce, hash)| (*nonce, *hash));
+            let (nonce, hash) = match first {
+                Some((nonce, hash)) if !self.is_gapped(signer, nonce) => (nonce, hash),
+                _ => break,
+            };
+            self.unhold(signer, nonce);
+            let order = match self.txs.get(&hash).cloned() {
+                Some(tx) => self.next_order(&tx),
+                None => continue,
+            };
+            self.order_set.insert(TxOrder::new(hash, order));
+            self.orders.insert(hash, order);
+            self.pending_nonces
+                .entry(signer.to_vec())
+                .or_insert_with(BTreeSet::new)
+                .insert(nonce);
+        }
+    }
+
+    fn position_of(&self, hash: H256, order: u64) -> usize {
+        self.order_set.range(..TxOrder::new(hash, order)).count()
+    }
+
     pub fn enqueue(&mut self, tx: SignedTransaction) -> bool {
         let hash = H256::from_slice(tx.get_tx_hash());
 
         let is_ok = !self.txs.contains_key(&hash);
         if is_ok {
-            let order = match self.strategy {
-                Strategy::FIFO => self.get_order(),
-                Strategy::PRIORITY => self.get_order_by_priority(&tx),
-                Strategy::VIP => self.get_order_by_vip(&tx),
-            };
-            let tx_order = TxOrder::new(hash, order);
-            self.order_set.insert(tx_order);
-            self.txs.insert(hash, tx);
+            let order = self.next_order(&tx);
+            self.insert_with_order(hash, order, tx, TxOrigin::default());
         }
         is_ok
     }
 
+    /// Insert a transaction and report how it was admitted.
+    ///
+    /// Unlike `enqueue`, a transaction with the same signer and nonce as one
+    /// already in the pool replaces it and takes over its place in the order.
+    pub fn admit(&mut self, tx: SignedTransaction) -> AdmissionOutcome {
+        self.admit_from(tx, TxOrigin::default())
+    }
+
+    /// `admit` a transaction which came from `origin`.
+    pub fn admit_from(&mut self, tx: SignedTransaction, origin: TxOrigin) -> AdmissionOutcome {
+        let hash = H256::from_slice(tx.get_tx_hash());
+        if self.txs.contains_key(&hash) {
+            return AdmissionOutcome::Duplicate;
+        }
+
+        let replaced = self.nonces.get(&sender_nonce(&tx)).cloned();
+        match replaced.and_then(|old| self.orders.get(&old).map(|order| (old, *order))) {
+            Some((old, order)) => {
+                self.order_set.remove(&TxOrder::new(old, order));
+                self.remove_tx(&old);
+                self.insert_with_order(hash, order, tx, origin);
+                AdmissionOutcome::Replaced {
+                    replaced: old,
+                    position: self.position_of(hash, order),
+                    origin,
+                }
+            }
+            None => {
+                // One held back is replaced by the other, held back or let
+                // in if it fills the gap.
+                if let Some(old) = replaced {
+                    self.remove_tx(&old);
+                }
+                let signer = tx.get_signer().to_vec();
+                let outcome = match sequential_nonce(&tx) {
+                    Some(nonce) if self.is_gapped(&signer, nonce) => {
+                        self.insert_tx(hash, tx, origin);
+                        let held = self.held.entry(signer).or_insert_with(BTreeMap::new);
+                        held.insert(nonce, hash);
+                        AdmissionOutcome::Queued {
+                            position: held.range(..nonce).count(),
+                            origin,
+                        }
+                    }
+                    _ => {
+                        let order = self.next_order(&tx);
+                        self.insert_with_order(hash, order, tx, origin);
+                        self.release_held(&signer);
+                        AdmissionOutcome::Pending {
+                            position: self.position_of(hash, order),
+                            origin,
+                        }
+                    }
+                };
+                match (replaced, outcome.position()) {
+                    (Some(old), Some(position)) => AdmissionOutcome::Replaced {
+                        replaced: old,
+                        position,
+                        origin,
+                    },
+                    _ => outcome,
+                }
+            }
+        }
+    }
+
     fn update_order_set(&mut self, hash_list: &HashSet<H256>) {
         self.order_set = self
             .order_set
@@ -133,32 +457,41 @@ impl Pool {
         let mut hash_list = HashSet::with_capacity(txs.len());
         for tx in txs {
             let hash = tx.crypt_hash();
-            self.txs.remove(&hash);
+            self.remove_tx(&hash);
             hash_list.insert(hash);
         }
         self.update_order_set(&hash_list);
+        self.release_all_held();
     }
 
     pub fn update_with_hash(&mut self, txs: &HashSet<H256>) {
         for tx in txs {
-            self.txs.remove(&tx);
+            self.remove_tx(&tx);
         }
         self.update_order_set(txs);
+        self.release_all_held();
     }
 
     pub fn get(&self, hash: &H256) -> Option<&SignedTransaction> {
         self.txs.get(hash)
     }
 
+    /// Where the transaction came from, those enqueued rather than
+    /// admitted from an unknown peer.
+    pub fn origin(&self, hash: &H256) -> Option<TxOrigin> {
+        self.origins.get(hash).cloned()
+    }
+
     pub fn package(
         &mut self,
-        height: u64,
+        height: impl Into<BlockHeight>,
         block_quota_limit: u64,
         account_quota_limit: AccountGasLimit,
         check_quota: bool,
         admin_address: Option<Address>,
         version: u32,
     ) -> Vec<SignedTransaction> {
+        let height = height.into();
         let mut tx_list = Vec::new();
         let mut invalid_tx_list = Vec::new();
         let mut n = block_quota_limit;
@@ -174,16 +507,19 @@ impl Pool {
                 }
                 let hash = order.unwrap().hash;
                 let tx = self.txs.get(&hash);
-                let tx_is_valid =
-                    |signed_tx: &SignedTransaction, height: u64, address: Address, version: u32| {
-                        let valid_until_block = signed_tx.get_transaction().get_valid_until_block();
-                        let tx_version = signed_tx.get_transaction().get_version();
-                        (height < valid_until_block && valid_until_block <= (height + BLOCKLIMIT))
-                            && admin_address
-                                .map(|admin| address == admin)
-                                .unwrap_or_else(|| true)
-                            && (tx_version == version)
-                    };
+                let validity = self.validity;
+                let tx_is_valid = |signed_tx: &SignedTransaction,
+                                   height: BlockHeight,
+                                   address: Address,
+                                   version: u32| {
+                    let valid_until_block = signed_tx.get_transaction().get_valid_until_block();
+                    let tx_version = signed_tx.get_transaction().get_version();
+                    validity.check(valid_until_block, height.0).is_ok()
+                        && admin_address
+                            .map(|admin| address == admin)
+                            .unwrap_or_else(|| true)
+                        && (tx_version == version)
+                };
                 if let Some(tx) = tx {
                     let address = pubkey_to_address(&PubKey::from(tx.get_signer()));
                     if tx_is_valid(tx, height, address, version) {
@@ -239,7 +575,13 @@ impl Pool {
         acc
     }
 
-    pub fn package_backword_compatible(&mut self, height: u64) -> Vec<SignedTransaction> {
+    pub fn package_backword_compatible(
+        &mut self,
+        height: impl Into<BlockHeight>,
+    ) -> Vec<SignedTransaction> {
+        let height = height.into();
+        // The window these chains always had, whatever `set_validity_policy`.
+        let validity = ValidityPolicy::legacy();
         let mut tx_list = Vec::new();
         let mut invalid_tx_list = Vec::new();
         let mut n = self.package_limit;
@@ -253,17 +595,11 @@ impl Pool {
                 let hash = order.unwrap().hash;
                 let tx = self.txs.get(&hash);
                 if let Some(tx) = tx {
-                    if tx
+                    let valid_until_block = tx
                         .get_transaction_with_sig()
                         .get_transaction()
-                        .valid_until_block
-                        >= height
-                        && tx
-                            .get_transaction_with_sig()
-                            .get_transaction()
-                            .valid_until_block
-                            < (height + BLOCKLIMIT)
-                    {
+                        .valid_until_block;
+                    if validity.check(valid_until_block, height.0).is_ok() {
                         tx_list.push(tx.clone());
                         n -= 1;
                         if n == 0 {
@@ -282,6 +618,16 @@ impl Pool {
         tx_list
     }
 
+    /// Drop the transactions which can't be packaged at `height` any more,
+    /// those valid until it or an earlier block. Returns how many.
+    pub fn on_new_height(&mut self, height: u64) -> usize {
+        let mut expired = Vec::new();
+        self.expiry.on_new_height(height, &mut expired);
+        let expired: HashSet<H256> = expired.into_iter().collect();
+        self.update_with_hash(&expired);
+        expired.len()
+    }
+
     pub fn len(&self) -> usize {
         self.txs.len()
     }
@@ -289,6 +635,21 @@ impl Pool {
     pub fn is_empty(&self) -> bool {
         self.len() == 0
     }
+
+    pub fn status(&self) -> PoolStatus {
+        let mut status = PoolStatus {
+            len: self.len(),
+            ..PoolStatus::default()
+        };
+        for origin in self.origins.values() {
+            match *origin {
+                TxOrigin::Rpc => status.rpc += 1,
+                TxOrigin::Peer { .. } => status.peer += 1,
+                TxOrigin::Resubmit => status.resubmit += 1,
+            }
+        }
+        status
+    }
 }
 
 #[cfg(test)]
@@ -297,22 +658,33 @@ mod tests {
     use super::*;
     use crypto::{CreateKey, KeyPair, PrivKey};
     use libproto::blockchain::{AccountGasLimit, SignedTransaction, Transaction};
+    use util::BLOCKLIMIT;
 
     pub fn generate_tx(
         data: Vec<u8>,
         valid_until_block: u64,
         privkey: &PrivKey,
         version: u32,
+    ) -> SignedTransaction {
+        generate_tx_with_nonce(data, valid_until_block, privkey, version, "0")
+    }
+
+    pub fn generate_tx_with_nonce(
+        data: Vec<u8>,
+        valid_until_block: u64,
+        privkey: &PrivKey,
+        version: u32,
+        nonce: &str,
     ) -> SignedTransaction {
         let mut tx = Transaction::new();
         tx.set_data(data);
         tx.set_to("1234567".to_string());
-        tx.set_nonce("0".to_string());
+        tx.set_nonce(nonce.to_string());
         tx.set_valid_until_block(valid_until_block);
         tx.set_quota(184467440737095);
         tx.set_version(version);
 
-        tx.sign(*privkey)
+        tx.sign(privkey)
     }
 
     #[test]
@@ -383,6 +755,58 @@ mod tests {
         assert_eq!(p.len(), 0);
     }
 
+    #[test]
+    fn package_by_validity_window() {
+        let keypair = KeyPair::gen_keypair();
+        let privkey = keypair.privkey();
+        let mut account_quota_limit = AccountGasLimit::new();
+        account_quota_limit.set_common_quota_limit(10000);
+        account_quota_limit.set_specific_quota_limit(HashMap::new());
+
+        let expired = generate_tx(vec![1], 10, privkey, 0);
+        let next = generate_tx(vec![2], 11, privkey, 0);
+        let last = generate_tx(vec![3], 110, privkey, 0);
+        let beyond = generate_tx(vec![4], 111, privkey, 0);
+        let txs = vec![expired, next.clone(), last.clone(), beyond];
+
+        let mut p = Pool::new(10);
+        txs.iter().for_each(|tx| assert!(p.enqueue(tx.clone())));
+        assert_eq!(
+            p.package(
+                10,
+                ::std::u64::MAX,
+                account_quota_limit.clone(),
+                false,
+                None,
+                0
+            ),
+            vec![next, last]
+        );
+        assert_eq!(p.len(), 2);
+
+        // Chains packaging the old way keep theirs, a block earlier.
+        let legacy = vec![
+            generate_tx(vec![5], 9, privkey, 0),
+            generate_tx(vec![6], 10, privkey, 0),
+            generate_tx(vec![7], 109, privkey, 0),
+            generate_tx(vec![8], 110, privkey, 0),
+        ];
+        let mut p = Pool::new(10);
+        legacy.iter().for_each(|tx| assert!(p.enqueue(tx.clone())));
+        assert_eq!(p.package_backword_compatible(10), legacy[1..3].to_vec());
+        assert_eq!(p.len(), 2);
+
+        let mut p = Pool::new(10);
+        p.set_validity_policy(ValidityPolicy::new(BLOCKLIMIT, true));
+        let forever = generate_tx(vec![5], 0, privkey, 0);
+        assert!(p.enqueue(forever.clone()));
+        assert_eq!(p.on_new_height(1000), 0);
+        assert_eq!(
+            p.package(1000, ::std::u64::MAX, account_quota_limit, false, None, 0),
+            vec![forever]
+        );
+    }
+
     #[test]
     fn test_cull() {
         let mut p = Pool::new(1);
@@ -407,4 +831,275 @@ mod tests {
 
         assert_eq!(txs, vec![tx1, tx3, tx4]);
     }
+
+    #[test]
+    fn admit_pending_and_duplicate() {
+        let mut p = Pool::new(1);
+        let keypair = KeyPair::gen_keypair();
+        let privkey = keypair.privkey();
+
+        let tx1 = generate_tx_with_nonce(vec![1], 99, privkey, 0, "1");
+        let tx2 = generate_tx_with_nonce(vec![2], 99, privkey, 0, "2");
+
+        assert_eq!(
+            p.admit(tx1.clone()),
+            AdmissionOutcome::Pending {
+                position: 0,
+                origin: TxOrigin::default(),
+            }
+        );
+        assert_eq!(
+            p.admit(tx2),
+            AdmissionOutcome::Pending {
+                position: 1,
+                origin: TxOrigin::default(),
+            }
+        );
+        assert_eq!(p.admit(tx1.clone()), AdmissionOutcome::Duplicate);
+        assert!(!p.admit(tx1).is_admitted());
+        assert_eq!(p.len(), 2);
+    }
+
+    #[test]
+    fn admit_replaces_same_signer_and_nonce() {
+        let mut p = Pool::new(1);
+        let keypair = KeyPair::gen_keypair();
+        let privkey = keypair.privkey();
+        let other = KeyPair::gen_keypair();
+
+        let tx1 = generate_tx_with_nonce(vec![1], 99, privkey, 0, "1");
+        let tx2 = generate_tx_with_nonce(vec![2], 99, privkey, 0, "2");
+        let tx3 = generate_tx_with_nonce(vec![3], 99, privkey, 0, "1");
+        let tx4 = generate_tx_with_nonce(vec![4], 99, other.privkey(), 0, "1");
+
+        p.admit(tx1.clone());
+        p.admit(tx2);
+        let outcome = p.admit(tx3.clone());
+        assert_eq!(
+            outcome,
+            AdmissionOutcome::Replaced {
+                replaced: tx1.crypt_hash(),
+                position: 0,
+                origin: TxOrigin::default(),
+            }
+        );
+        assert_eq!(outcome.replaced_hash(), Some(tx1.crypt_hash()));
+        assert_eq!(outcome.position(), Some(0));
+        assert!(p.get(&tx1.crypt_hash()).is_none());
+        assert_eq!(p.len(), 2);
+
+        // Same nonce from another signer is not a replacement.
+        assert_eq!(p.admit(tx4).position(), Some(2));
+
+        let mut account_quota_limit = AccountGasLimit::new();
+        account_quota_limit.set_common_quota_limit(10000);
+        account_quota_limit.set_specific_quota_limit(HashMap::new());
+        assert_eq!(
+            p.package(5, 30, account_quota_limit, false, None, 0),
+            vec![tx3.clone()]
+        );
+
+        // Once packaged, the nonce can be used again without replacing anything.
+        p.update(&[tx3]);
+        let tx5 = generate_tx_with_nonce(vec![5], 99, privkey, 0, "1");
+        assert_eq!(p.admit(tx5).position(), Some(2));
+    }
+
+    #[test]
+    fn origin_is_kept_and_counted() {
+        let mut p = Pool::new(1);
+        let keypair = KeyPair::gen_keypair();
+        let privkey = keypair.privkey();
+        let peer = TxOrigin::Peer { node_short_id: 3 };
+
+        let tx1 = generate_tx_with_nonce(vec![1], 99, privkey, 0, "1");
+        let tx2 = generate_tx_with_nonce(vec![2], 99, privkey, 0, "2");
+        let tx3 = generate_tx_with_nonce(vec![3], 99, privkey, 0, "3");
+        let tx4 = generate_tx_with_nonce(vec![4], 99, privkey, 0, "1");
+        let tx5 = generate_tx_with_nonce(vec![5], 99, privkey, 0, "5");
+
+        assert_eq!(
+            p.admit_from(tx1.clone(), TxOrigin::Rpc).origin(),
+            Some(TxOrigin::Rpc)
+        );
+        p.admit_from(tx2.clone(), peer);
+        p.admit_from(tx3, TxOrigin::Resubmit);
+        assert!(p.enqueue(tx5.clone()));
+        assert_eq!(p.admit_from(tx2.clone(), TxOrigin::Rpc).origin(), None);
+        assert_eq!(p.origin(&tx2.crypt_hash()), Some(peer));
+        assert_eq!(p.origin(&tx5.crypt_hash()), Some(TxOrigin::default()));
+        assert_eq!(
+            p.status(),
+            PoolStatus {
+                len: 4,
+                rpc: 1,
+                peer: 2,
+                resubmit: 1,
+            }
+        );
+
+        // The replacement counts as where it came from.
+        assert_eq!(p.admit_from(tx4, peer).origin(), Some(peer));
+        assert_eq!(p.origin(&tx1.crypt_hash()), None);
+        p.update(&[tx2]);
+        assert_eq!(
+            p.status(),
+            PoolStatus {
+                len: 3,
+                rpc: 0,
+                peer: 2,
+                resubmit: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn admit_holds_back_nonce_gaps() {
+        let mut p = Pool::new(10);
+        p.set_sequential_nonces(true);
+        let keypair = KeyPair::gen_keypair();
+        let privkey = keypair.privkey();
+        let tx = |nonce: &str| generate_tx_with_nonce(vec![1], 99, privkey, 0, nonce);
+        let origin = TxOrigin::default();
+
+        let (tx1, tx2, tx3, tx4, tx6) = (tx("1"), tx("2"), tx("3"), tx("4"), tx("6"));
+        assert_eq!(
+            p.admit(tx1.clone()),
+            AdmissionOutcome::Pending {
+                position: 0,
+                origin,
+            }
+        );
+        assert_eq!(
+            p.admit(tx4.clone()),
+            AdmissionOutcome::Queued {
+                position: 0,
+                origin,
+            }
+        );
+        assert_eq!(
+            p.admit(tx3.clone()),
+            AdmissionOutcome::Queued {
+                position: 0,
+                origin,
+            }
+        );
+        assert_eq!(
+            p.admit(tx6.clone()),
+            AdmissionOutcome::Queued {
+                position: 2,
+                origin,
+            }
+        );
+        assert_eq!(p.len(), 4);
+        assert_eq!(p.package_backword_compatible(5), vec![tx1.clone()]);
+
+        // Filling the gap lets the held back in, up to the next gap.
+        assert_eq!(
+            p.admit(tx2.clone()),
+            AdmissionOutcome::Pending {
+                position: 1,
+                origin,
+            }
+        );
+        assert_eq!(
+            p.package_backword_compatible(5),
+            vec![tx1.clone(), tx2.clone(), tx3.clone(), tx4.clone()]
+        );
+
+        // Once the order has none of the signer, the lowest held back goes in.
+        p.update(&[tx1, tx2, tx3, tx4]);
+        assert_eq!(p.package_backword_compatible(5), vec![tx6]);
+
+        // Other nonces, and other signers, are never held back.
+        let other = KeyPair::gen_keypair();
+        let free_form = generate_tx_with_nonce(vec![2], 99, privkey, 0, "a nonce");
+        let first = generate_tx_with_nonce(vec![3], 99, other.privkey(), 0, "9");
+        assert_eq!(
+            p.admit(free_form),
+            AdmissionOutcome::Pending {
+                position: 1,
+                origin,
+            }
+        );
+        assert_eq!(
+            p.admit(first),
+            AdmissionOutcome::Pending {
+                position: 2,
+                origin,
+            }
+        );
+
+        // Off by default.
+        let mut p = Pool::new(10);
+        p.admit(tx("1"));
+        assert_eq!(p.admit(tx("3")).position(), Some(1));
+        assert_eq!(p.package_backword_compatible(5).len(), 2);
+    }
+
+    #[test]
+    fn admit_replaces_held_back() {
+        let mut p = Pool::new(10);
+        p.set_sequential_nonces(true);
+        let keypair = KeyPair::gen_keypair();
+        let privkey = keypair.privkey();
+        let tx = |data: u8, nonce: &str| generate_tx_with_nonce(vec![data], 99, privkey, 0, nonce);
+        let origin = TxOrigin::default();
+
+        let (tx1, tx2, tx3, tx4) = (tx(1, "1"), tx(2, "2"), tx(3, "3"), tx(4, "4"));
+        let tx3b = tx(5, "3");
+        p.admit(tx1.clone());
+        p.admit(tx3.clone());
+        p.admit(tx4.clone());
+
+        // Still held back, in the place of the one it replaced.
+        let outcome = p.admit(tx3b.clone());
+        assert_eq!(
+            outcome,
+            AdmissionOutcome::Replaced {
+                replaced: tx3.crypt_hash(),
+                position: 0,
+                origin,
+            }
+        );
+        assert_eq!(outcome.replaced_hash(), Some(tx3.crypt_hash()));
+        assert!(p.get(&tx3.crypt_hash()).is_none());
+        assert_eq!(p.len(), 3);
+
+        // Filling the gap lets the replacement in.
+        assert_eq!(
+            p.admit(tx2.clone()),
+            AdmissionOutcome::Pending {
+                position: 1,
+                origin,
+            }
+        );
+        assert_eq!(p.package_backword_compatible(5), vec![tx1, tx2, tx3b, tx4]);
+    }
+
+    #[test]
+    fn expire_on_new_height() {
+        let mut p = Pool::new(1);
+        let keypair = KeyPair::gen_keypair();
+        let privkey = keypair.privkey();
+
+        let tx1 = generate_tx(vec![1], 5, privkey, 0);
+        let tx2 = generate_tx(vec![2], 10, privkey, 0);
+        let tx3 = generate_tx(vec![3], 5, privkey, 0);
+        p.enqueue(tx1.clone());
+        p.enqueue(tx2.clone());
+        p.enqueue(tx3.clone());
+        p.update(&[tx3]);
+
+        assert_eq!(p.on_new_height(4), 0);
+        assert_eq!(p.on_new_height(5), 1);
+        assert!(p.get(&tx1.crypt_hash()).is_none());
+        assert_eq!(p.len(), 1);
+
+        // An earlier height drops nothing more.
+        assert_eq!(p.on_new_height(3), 0);
+        assert_eq!(p.on_new_height(10), 1);
+        assert!(p.get(&tx2.crypt_hash()).is_none());
+        assert!(p.is_empty());
+    }
 }
@@ -23,6 +23,9 @@ extern crate serde;
 extern crate toml;
 
 extern crate backtrace;
+#[cfg(feature = "deadlock-detection")]
+#[macro_use]
+extern crate lazy_static;
 #[macro_use]
 extern crate cita_logger as logger;
 
@@ -31,14 +34,17 @@ pub mod instrument;
 #[macro_use]
 pub mod init;
 pub mod panic_hook;
+pub mod sync;
+pub mod timer;
 
 pub use crate::init::*;
 pub use crate::instrument::*;
 pub use ansi_term::{Colour, Style};
+pub use cita_config as config;
 pub use panic_hook::set_panic_handler;
 pub use parking_lot::{Condvar, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
 
-pub const BLOCKLIMIT: u64 = 100;
+pub const BLOCKLIMIT: u64 = types::tx_validity::DEFAULT_MAX_WINDOW;
 
 /// Boolean type for clean/dirty status.
 #[derive(PartialEq, Eq, Clone, Copy, Debug)]
@@ -0,0 +1,530 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Locks which find lock-order inversions before they hang a node.
+//!
+//! `TracedMutex` and `TracedRwLock` are the parking_lot locks, with the
+//! same API, unless the `deadlock-detection` feature is on. Then every
+//! thread records the traced locks it holds, and acquiring one while
+//! holding another adds an edge to a global lock-order graph. An edge
+//! closing a cycle means two threads can each wait for a lock the other
+//! holds; it is reported when the lock is acquired, with the backtraces of
+//! the acquisitions making up the cycle, whether or not the threads ever
+//! happen to hang. By default that panics, `set_cycle_action` can have it
+//! logged instead.
+//!
+//! With the feature, `warn_when_held_longer_than` also has a warning
+//! logged when a lock is released after being held longer than a
+//! threshold, by a `Clock`.
+
+#[cfg(not(feature = "deadlock-detection"))]
+pub type TracedMutex<T> = parking_lot::Mutex<T>;
+#[cfg(not(feature = "deadlock-detection"))]
+pub type TracedMutexGuard<'a, T> = parking_lot::MutexGuard<'a, T>;
+#[cfg(not(feature = "deadlock-detection"))]
+pub type TracedRwLock<T> = parking_lot::RwLock<T>;
+#[cfg(not(feature = "deadlock-detection"))]
+pub type TracedRwLockReadGuard<'a, T> = parking_lot::RwLockReadGuard<'a, T>;
+#[cfg(not(feature = "deadlock-detection"))]
+pub type TracedRwLockWriteGuard<'a, T> = parking_lot::RwLockWriteGuard<'a, T>;
+
+#[cfg(feature = "deadlock-detection")]
+pub use self::traced::{
+    long_holds, set_cycle_action, warn_when_held_longer_than, CycleAction, TracedMutex,
+    TracedMutexGuard, TracedRwLock, TracedRwLockReadGuard, TracedRwLockWriteGuard,
+};
+
+#[cfg(feature = "deadlock-detection")]
+mod traced {
+    use std::cell::RefCell;
+    use std::collections::{HashMap, HashSet, VecDeque};
+    use std::fmt;
+    use std::ops::{Deref, DerefMut};
+    use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex as StdMutex, MutexGuard as StdMutexGuard};
+    use std::time::Duration;
+
+    use backtrace::Backtrace;
+    use parking_lot::{Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+    use crate::timer::Clock;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum CycleAction {
+        Panic,
+        /// Log the cycle and acquire the lock anyway.
+        Log,
+    }
+
+    const PANIC: usize = 0;
+    const LOG: usize = 1;
+
+    static CYCLE_ACTION: AtomicUsize = AtomicUsize::new(PANIC);
+    static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+    static LONG_HOLDS: AtomicU64 = AtomicU64::new(0);
+
+    lazy_static! {
+        static ref GRAPH: StdMutex<Graph> = StdMutex::new(Graph::default());
+        static ref HOLD_LIMIT: StdMutex<Option<HoldLimit>> = StdMutex::new(None);
+    }
+
+    thread_local! {
+        /// Ids of the traced locks this thread holds, in acquisition order.
+        static HELD: RefCell<Vec<usize>> = RefCell::new(Vec::new());
+    }
+
+    pub fn set_cycle_action(action: CycleAction) {
+        let action = match action {
+            CycleAction::Panic => PANIC,
+            CycleAction::Log => LOG,
+        };
+        CYCLE_ACTION.store(action, Ordering::SeqCst);
+    }
+
+    /// Warn about locks held longer than `threshold` by `clock`, from the
+    /// next acquisition on.
+    pub fn warn_when_held_longer_than<C>(threshold: Duration, clock: C)
+    where
+        C: Clock + Send + Sync + 'static,
+    {
+        *lock(&HOLD_LIMIT) = Some(HoldLimit {
+            threshold,
+            clock: Arc::new(clock),
+        });
+    }
+
+    /// How many warnings about locks held too long were logged.
+    pub fn long_holds() -> u64 {
+        LONG_HOLDS.load(Ordering::SeqCst)
+    }
+
+    /// The bookkeeping must go on after a panic reporting a cycle.
+    fn lock<T>(mutex: &StdMutex<T>) -> StdMutexGuard<'_, T> {
+        mutex.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    #[derive(Clone)]
+    struct HoldLimit {
+        threshold: Duration,
+        clock: Arc<dyn Clock + Send + Sync>,
+    }
+
+    /// An edge `a -> b` is a thread acquiring `b` while holding `a`, with
+    /// where it first did.
+    #[derive(Default)]
+    struct Graph {
+        edges: HashMap<usize, HashMap<usize, Backtrace>>,
+    }
+
+    impl Graph {
+        /// The locks from `from` to `to` along the edges, both included.
+        fn path(&self, from: usize, to: usize) -> Option<Vec<usize>> {
+            let mut previous = HashMap::new();
+            let mut seen = HashSet::new();
+            let mut queue = VecDeque::new();
+            seen.insert(from);
+            queue.push_back(from);
+            while let Some(id) = queue.pop_front() {
+                if id == to {
+                    let mut path = vec![to];
+                    let mut id = to;
+                    while let Some(&before) = previous.get(&id) {
+                        path.push(before);
+                        id = before;
+                    }
+                    path.reverse();
+                    return Some(path);
+                }
+                for &next in self.edges.get(&id).into_iter().flat_map(HashMap::keys) {
+                    if seen.insert(next) {
+                        previous.insert(next, id);
+                        queue.push_back(next);
+                    }
+                }
+            }
+            None
+        }
+
+        /// Record acquiring `id` while holding `held`. A cycle this would
+        /// close is returned instead, and the edge left out.
+        fn acquire(&mut self, held: &[usize], id: usize) -> Option<String> {
+            for &before in held {
+                if before == id || self.has_edge(before, id) {
+                    continue;
+                }
+                if let Some(path) = self.path(id, before) {
+                    return Some(self.report(&path, id));
+                }
+                self.edges
+                    .entry(before)
+                    .or_default()
+                    .insert(id, Backtrace::new());
+            }
+            None
+        }
+
+        fn has_edge(&self, from: usize, to: usize) -> bool {
+            self.edges
+                .get(&from)
+                .into_iter()
+                .any(|edges| edges.contains_key(&to))
+        }
+
+        /// `path` leads from `id` to a lock held while acquiring `id`.
+        fn report(&self, path: &[usize], id: usize) -> String {
+            let mut report = format!(
+                "lock order cycle: acquiring lock #{} while holding lock #{}, \
+                 which was acquired after it before\n\nnow:\n{:?}",
+                id,
+                path[path.len() - 1],
+                Backtrace::new()
+            );
+            for pair in path.windows(2) {
+                report.push_str(&format!(
+                    "\nlock #{} acquired while holding lock #{}:\n{:?}",
+                    pair[1], pair[0], self.edges[&pair[0]][&pair[1]]
+                ));
+            }
+            report
+        }
+    }
+
+    /// Check the order before blocking on lock `id`.
+    fn before_acquire(id: usize) {
+        let held = HELD.with(|held| held.borrow().clone());
+        if held.is_empty() {
+            return;
+        }
+        let cycle = lock(&GRAPH).acquire(&held, id);
+        if let Some(report) = cycle {
+            if CYCLE_ACTION.load(Ordering::SeqCst) == LOG {
+                error!("{}", report);
+            } else {
+                panic!("{}", report);
+            }
+        }
+    }
+
+    /// Lock `id` held by this thread, until dropped.
+    struct Held {
+        id: usize,
+        since: Option<(Duration, HoldLimit)>,
+    }
+
+    impl Held {
+        fn new(id: usize) -> Self {
+            HELD.with(|held| held.borrow_mut().push(id));
+            let since = lock(&HOLD_LIMIT)
+                .clone()
+                .map(|limit| (limit.clock.now(), limit));
+            Held { id, since }
+        }
+    }
+
+    impl Drop for Held {
+        fn drop(&mut self) {
+            let id = self.id;
+            // Guards need not be dropped in the order they were taken.
+            let _ = HELD.try_with(|held| {
+                let mut held = held.borrow_mut();
+                if let Some(position) = held.iter().rposition(|&h| h == id) {
+                    held.remove(position);
+                }
+            });
+            if let Some((since, ref limit)) = self.since {
+                let held_for = limit.clock.now().checked_sub(since).unwrap_or_default();
+                if held_for > limit.threshold {
+                    LONG_HOLDS.fetch_add(1, Ordering::SeqCst);
+                    warn!(
+                        "lock #{} held for {:?}, longer than {:?}\n{:?}",
+                        id,
+                        held_for,
+                        limit.threshold,
+                        Backtrace::new()
+                    );
+                }
+            }
+        }
+    }
+
+    fn next_id() -> usize {
+        NEXT_ID.fetch_add(1, Ordering::SeqCst)
+    }
+
+    pub struct TracedMutex<T: ?Sized> {
+        id: usize,
+        inner: Mutex<T>,
+    }
+
+    impl<T> TracedMutex<T> {
+        pub fn new(value: T) -> Self {
+            TracedMutex {
+                id: next_id(),
+                inner: Mutex::new(value),
+            }
+        }
+
+        pub fn into_inner(self) -> T {
+            self.inner.into_inner()
+        }
+    }
+
+    impl<T: ?Sized> TracedMutex<T> {
+        pub fn lock(&self) -> TracedMutexGuard<'_, T> {
+            before_acquire(self.id);
+            let guard = self.inner.lock();
+            TracedMutexGuard {
+                guard,
+                _held: Held::new(self.id),
+            }
+        }
+
+        /// Doesn't block, so can't deadlock, and isn't checked.
+        pub fn try_lock(&self) -> Option<TracedMutexGuard<'_, T>> {
+            self.inner.try_lock().map(|guard| TracedMutexGuard {
+                guard,
+                _held: Held::new(self.id),
+            })
+        }
+
+        pub fn get_mut(&mut self) -> &mut T {
+            self.inner.get_mut()
+        }
+    }
+
+    impl<T: Default> Default for TracedMutex<T> {
+        fn default() -> Self {
+            TracedMutex::new(T::default())
+        }
+    }
+
+    impl<T: ?Sized + fmt::Debug> fmt::Debug for TracedMutex<T> {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            self.inner.fmt(f)
+        }
+    }
+
+    pub struct TracedMutexGuard<'a, T: ?Sized + 'a> {
+        guard: MutexGuard<'a, T>,
+        _held: Held,
+    }
+
+    impl<'a, T: ?Sized + 'a> Deref for TracedMutexGuard<'a, T> {
+        type Target = T;
+
+        fn deref(&self) -> &T {
+            &self.guard
+        }
+    }
+
+    impl<'a, T: ?Sized + 'a> DerefMut for TracedMutexGuard<'a, T> {
+        fn deref_mut(&mut self) -> &mut T {
+            &mut self.guard
+        }
+    }
+
+    /// Reads are traced like writes: with a writer waiting, a reader
+    /// blocks too.
+    pub struct TracedRwLock<T: ?Sized> {
+        id: usize,
+        inner: RwLock<T>,
+    }
+
+    impl<T> TracedRwLock<T> {
+        pub fn new(value: T) -> Self {
+            TracedRwLock {
+                id: next_id(),
+                inner: RwLock::new(value),
+            }
+        }
+
+        pub fn into_inner(self) -> T {
+            self.inner.into_inner()
+        }
+    }
+
+    impl<T: ?Sized> TracedRwLock<T> {
+        pub fn read(&self) -> TracedRwLockReadGuard<'_, T> {
+            before_acquire(self.id);
+            let guard = self.inner.read();
+            TracedRwLockReadGuard {
+                guard,
+                _held: Held::new(self.id),
+            }
+        }
+
+        pub fn write(&self) -> TracedRwLockWriteGuard<'_, T> {
+            before_acquire(self.id);
+            let guard = self.inner.write();
+            TracedRwLockWriteGuard {
+                guard,
+                _held: Held::new(self.id),
+            }
+        }
+
+        pub fn try_read(&self) -> Option<TracedRwLockReadGuard<'_, T>> {
+            self.inner.try_read().map(|guard| TracedRwLockReadGuard {
+                guard,
+                _held: Held::new(self.id),
+            })
+        }
+
+        pub fn try_write(&self) -> Option<TracedRwLockWriteGuard<'_, T>> {
+            self.inner.try_write().map(|guard| TracedRwLockWriteGuard {
+                guard,
+                _held: Held::new(self.id),
+            })
+        }
+
+        pub fn get_mut(&mut self) -> &mut T {
+            self.inner.get_mut()
+        }
+    }
+
+    impl<T: Default> Default for TracedRwLock<T> {
+        fn default() -> Self {
+            TracedRwLock::new(T::default())
+        }
+    }
+
+    impl<T: ?Sized + fmt::Debug> fmt::Debug for TracedRwLock<T> {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            self.inner.fmt(f)
+        }
+    }
+
+    pub struct TracedRwLockReadGuard<'a, T: ?Sized + 'a> {
+        guard: RwLockReadGuard<'a, T>,
+        _held: Held,
+    }
+
+    impl<'a, T: ?Sized + 'a> Deref for TracedRwLockReadGuard<'a, T> {
+        type Target = T;
+
+        fn deref(&self) -> &T {
+            &self.guard
+        }
+    }
+
+    pub struct TracedRwLockWriteGuard<'a, T: ?Sized + 'a> {
+        guard: RwLockWriteGuard<'a, T>,
+        _held: Held,
+    }
+
+    impl<'a, T: ?Sized + 'a> Deref for TracedRwLockWriteGuard<'a, T> {
+        type Target = T;
+
+        fn deref(&self) -> &T {
+            &self.guard
+        }
+    }
+
+    impl<'a, T: ?Sized + 'a> DerefMut for TracedRwLockWriteGuard<'a, T> {
+        fn deref_mut(&mut self) -> &mut T {
+            &mut self.guard
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::{long_holds, warn_when_held_longer_than, TracedMutex, TracedRwLock};
+        use crate::timer::MockClock;
+        use std::sync::Arc;
+        use std::thread;
+        use std::time::Duration;
+
+        #[test]
+        fn detects_an_order_cycle() {
+            let a = Arc::new(TracedMutex::new(0));
+            let b = Arc::new(TracedRwLock::new(0));
+
+            let (a1, b1) = (a.clone(), b.clone());
+            thread::spawn(move || {
+                let _a = a1.lock();
+                *b1.write() += 1;
+            })
+            .join()
+            .unwrap();
+
+            // Run after the first thread, so they can't actually hang.
+            let (a2, b2) = (a.clone(), b.clone());
+            let result = thread::spawn(move || {
+                let _b = b2.read();
+                *a2.lock() += 1;
+            })
+            .join();
+            let message = result.unwrap_err();
+            let message = message.downcast_ref::<String>().unwrap();
+            assert!(message.starts_with("lock order cycle"), "{}", message);
+
+            // Neither lock was left locked by the panic.
+            assert_eq!(*a.lock(), 0);
+            assert_eq!(*b.read(), 1);
+        }
+
+        #[test]
+        fn consistent_order_is_fine() {
+            let a = Arc::new(TracedMutex::new(0));
+            let b = Arc::new(TracedRwLock::new(0));
+            let c = Arc::new(TracedMutex::new(0));
+            let threads: Vec<_> = (0..4)
+                .map(|i| {
+                    let (a, b, c) = (a.clone(), b.clone(), c.clone());
+                    thread::spawn(move || {
+                        for _ in 0..100 {
+                            let mut a = a.lock();
+                            *a += 1;
+                            // Skipping one in between is the same order.
+                            if i % 2 == 0 {
+                                let _b = b.read();
+                                *c.lock() += 1;
+                            } else {
+                                *c.lock() += 1;
+                            }
+                            drop(a);
+                            *b.write() += 1;
+                        }
+                    })
+                })
+                .collect();
+            for thread in threads {
+                thread.join().unwrap();
+            }
+            assert_eq!(*a.lock(), 400);
+            assert_eq!(*c.lock(), 400);
+
+            // Released in another order than acquired.
+            let a_guard = a.lock();
+            let c_guard = c.lock();
+            drop(a_guard);
+            drop(c_guard);
+            let _a = a.lock();
+            let _c = c.lock();
+        }
+
+        #[test]
+        fn warns_about_long_holds() {
+            let clock = MockClock::default();
+            warn_when_held_longer_than(Duration::from_millis(100), clock.clone());
+            let lock = TracedMutex::new(());
+            let before = long_holds();
+
+            drop(lock.lock());
+            let guard = lock.lock();
+            clock.advance(Duration::from_millis(101));
+            drop(guard);
+            assert!(long_holds() > before);
+        }
+    }
+}
@@ -0,0 +1,413 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Callbacks scheduled for a block height or after a delay.
+//!
+//! A `TimerWheel` has no thread of its own. Its owner calls `on_new_height`
+//! when a block is added, which runs what was scheduled up to that height,
+//! and `tick` now and then, which runs what is due by the `Clock`. The
+//! callbacks get a `&mut T` the owner passes in, so they can change its
+//! state without sharing it.
+//!
+//! Entries are kept in levels of 64 buckets, each bucket of a level as wide
+//! as the whole level below, and entries further away than the levels
+//! reach wait in a binary heap. An entry is only looked at when its bucket
+//! comes up, and moved down a level at a time, so advancing costs little
+//! however many entries are waiting.
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::fmt;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::time::Duration;
+
+pub use crate::types::clock::{Clock, MockClock, SystemClock};
+
+const SLOT_BITS: u32 = 6;
+const SLOTS: usize = 1 << SLOT_BITS;
+const LEVELS: usize = 4;
+
+/// Deadlines by bucket, for one kind of tick: heights or milliseconds.
+struct Wheel {
+    elapsed: u64,
+    /// Bit `i` of `occupied[level]` is set when `slots[level][i]` isn't empty.
+    occupied: [u64; LEVELS],
+    /// Deadline and id of each entry.
+    slots: Vec<Vec<Vec<(u64, u64)>>>,
+    /// Entries beyond the levels, earliest first.
+    far: BinaryHeap<Reverse<(u64, u64)>>,
+    due: Vec<(u64, u64)>,
+}
+
+impl Wheel {
+    fn new() -> Self {
+        Wheel {
+            elapsed: 0,
+            occupied: [0; LEVELS],
+            slots: (0..LEVELS).map(|_| vec![Vec::new(); SLOTS]).collect(),
+            far: BinaryHeap::new(),
+            due: Vec::new(),
+        }
+    }
+
+    fn insert(&mut self, deadline: u64, id: u64) {
+        if deadline <= self.elapsed {
+            self.due.push((deadline, id));
+            return;
+        }
+        // The level is the highest group of bits where the deadline and
+        // `elapsed` differ.
+        let level = ((63 - (deadline ^ self.elapsed).leading_zeros()) / SLOT_BITS) as usize;
+        if level >= LEVELS {
+            self.far.push(Reverse((deadline, id)));
+            return;
+        }
+        let slot = ((deadline >> (level as u32 * SLOT_BITS)) as usize) & (SLOTS - 1);
+        self.slots[level][slot].push((deadline, id));
+        self.occupied[level] |= 1 << slot;
+    }
+
+    /// Move the entries of the heap which the levels reach now.
+    fn pull_near(&mut self) {
+        let reach = SLOT_BITS * LEVELS as u32;
+        while let Some(&Reverse((deadline, id))) = self.far.peek() {
+            if (deadline ^ self.elapsed) >> reach != 0 {
+                break;
+            }
+            self.far.pop();
+            self.insert(deadline, id);
+        }
+    }
+
+    /// Start of the earliest occupied bucket, and where it is. Buckets of a
+    /// lower level always come before those of a higher one.
+    fn next_bucket(&self) -> Option<(u64, usize, usize)> {
+        for level in 0..LEVELS {
+            let shift = level as u32 * SLOT_BITS;
+            let position = (self.elapsed >> shift) as usize & (SLOTS - 1);
+            let ahead = self.occupied[level] & (!0u64 << position);
+            if ahead != 0 {
+                let slot = ahead.trailing_zeros() as usize;
+                let level_span = (1u64 << (shift + SLOT_BITS)) - 1;
+                let start = (self.elapsed & !level_span) | ((slot as u64) << shift);
+                return Some((start, level, slot));
+            }
+        }
+        None
+    }
+
+    /// Move to `to`, or stay if it's behind, and take the entries due by
+    /// then, by deadline and then in the order they were inserted.
+    fn advance(&mut self, to: u64) -> Vec<(u64, u64)> {
+        loop {
+            self.pull_near();
+            let far = self.far.peek().map(|&Reverse((deadline, _))| deadline);
+            match self.next_bucket() {
+                Some((start, level, slot))
+                    if start <= to && far.map_or(true, |far| start <= far) =>
+                {
+                    // Run what's due, and move the rest a level down.
+                    self.elapsed = self.elapsed.max(start);
+                    self.occupied[level] &= !(1 << slot);
+                    let entries = mem::replace(&mut self.slots[level][slot], Vec::new());
+                    for (deadline, id) in entries {
+                        self.insert(deadline, id);
+                    }
+                }
+                // Nothing in the levels before the heap's first entry,
+                // `pull_near` takes it once there.
+                _ => match far {
+                    Some(far) if far <= to => self.elapsed = self.elapsed.max(far),
+                    _ => break,
+                },
+            }
+        }
+        self.elapsed = self.elapsed.max(to);
+        self.pull_near();
+        let mut due = mem::replace(&mut self.due, Vec::new());
+        // Ids grow in the order entries are scheduled.
+        due.sort();
+        due
+    }
+}
+
+/// Cancels the entry it was returned for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TimerHandle(u64);
+
+pub type Callback<T> = Box<dyn FnOnce(&mut T) + Send>;
+
+pub struct TimerWheel<T = (), C = SystemClock> {
+    clock: C,
+    heights: Wheel,
+    times: Wheel,
+    next_id: u64,
+    callbacks: HashMap<u64, Callback<T>>,
+}
+
+impl<T> TimerWheel<T, SystemClock> {
+    pub fn new() -> Self {
+        Self::with_clock(SystemClock::default())
+    }
+}
+
+impl<T> Default for TimerWheel<T, SystemClock> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, C> fmt::Debug for TimerWheel<T, C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TimerWheel")
+            .field("height", &self.heights.elapsed)
+            .field("millis", &self.times.elapsed)
+            .field("scheduled", &self.callbacks.len())
+            .finish()
+    }
+}
+
+impl<T, C> TimerWheel<T, C>
+where
+    C: Clock,
+{
+    pub fn with_clock(clock: C) -> Self {
+        TimerWheel {
+            clock,
+            heights: Wheel::new(),
+            times: Wheel::new(),
+            next_id: 0,
+            callbacks: HashMap::new(),
+        }
+    }
+
+    /// Entries scheduled and neither run nor cancelled.
+    pub fn len(&self) -> usize {
+        self.callbacks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.callbacks.is_empty()
+    }
+
+    /// Run `callback` once `on_new_height` is told of `height` or a later
+    /// one. For a height already passed, that's the next notification.
+    pub fn schedule_at_height<F>(&mut self, height: u64, callback: F) -> TimerHandle
+    where
+        F: FnOnce(&mut T) + Send + 'static,
+    {
+        let id = self.add(Box::new(callback));
+        self.heights.insert(height, id);
+        TimerHandle(id)
+    }
+
+    /// Run `callback` on the first `tick` after at least `delay` passed.
+    pub fn schedule_after<F>(&mut self, delay: Duration, callback: F) -> TimerHandle
+    where
+        F: FnOnce(&mut T) + Send + 'static,
+    {
+        let id = self.add(Box::new(callback));
+        // Round up, never run early.
+        let delay = ((delay.as_nanos() + 999_999) / 1_000_000) as u64;
+        let deadline = self.clock.now().as_millis() as u64 + delay;
+        self.times.insert(deadline, id);
+        TimerHandle(id)
+    }
+
+    /// Returns whether the entry was still waiting.
+    pub fn cancel(&mut self, handle: TimerHandle) -> bool {
+        // It's skipped when its bucket comes up.
+        self.callbacks.remove(&handle.0).is_some()
+    }
+
+    /// Run the entries scheduled up to `height`, returns how many. A height
+    /// lower than one seen before runs nothing new.
+    pub fn on_new_height(&mut self, height: u64, context: &mut T) -> usize {
+        let due = self.heights.advance(height);
+        self.run(due, context)
+    }
+
+    /// Run the entries whose delay passed by the clock, returns how many.
+    pub fn tick(&mut self, context: &mut T) -> usize {
+        let now = self.clock.now().as_millis() as u64;
+        let due = self.times.advance(now);
+        self.run(due, context)
+    }
+
+    fn add(&mut self, callback: Callback<T>) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.callbacks.insert(id, callback);
+        id
+    }
+
+    /// A callback which panics is logged and the rest still run. This
+    /// needs the default panic hook, `set_panic_handler` exits instead.
+    fn run(&mut self, due: Vec<(u64, u64)>, context: &mut T) -> usize {
+        let mut ran = 0;
+        for (_, id) in due {
+            if let Some(callback) = self.callbacks.remove(&id) {
+                ran += 1;
+                if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| callback(context))) {
+                    let message = payload
+                        .downcast_ref::<&str>()
+                        .map(|message| (*message).to_owned())
+                        .or_else(|| payload.downcast_ref::<String>().cloned())
+                        .unwrap_or_default();
+                    warn!("timer callback panicked: {}", message);
+                }
+            }
+        }
+        ran
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Clock, MockClock, TimerWheel};
+    use std::time::Duration;
+
+    fn record(label: u64) -> impl FnOnce(&mut Vec<u64>) + Send + 'static {
+        move |fired: &mut Vec<u64>| fired.push(label)
+    }
+
+    #[test]
+    fn firing_order() {
+        let mut wheel = TimerWheel::new();
+        let mut fired = Vec::new();
+        for &height in &[70u64, 3, 5000, 3, 64, 1 << 30] {
+            wheel.schedule_at_height(height, record(height));
+        }
+        assert_eq!(wheel.on_new_height(2, &mut fired), 0);
+        assert_eq!(wheel.on_new_height(100, &mut fired), 4);
+        assert_eq!(fired, vec![3, 3, 64, 70]);
+        assert_eq!(wheel.on_new_height(::std::u64::MAX, &mut fired), 2);
+        assert_eq!(fired, vec![3, 3, 64, 70, 5000, 1 << 30]);
+        assert!(wheel.is_empty());
+
+        let clock = MockClock::default();
+        let mut wheel = TimerWheel::with_clock(clock.clone());
+        let mut fired = Vec::new();
+        wheel.schedule_after(Duration::from_millis(2500), record(2));
+        wheel.schedule_after(Duration::from_millis(500), record(1));
+        wheel.schedule_after(Duration::from_secs(3600 * 24), record(3));
+        clock.advance(Duration::from_millis(499));
+        assert_eq!(wheel.tick(&mut fired), 0);
+        clock.advance(Duration::from_millis(1));
+        assert_eq!(wheel.tick(&mut fired), 1);
+        clock.advance(Duration::from_secs(3600 * 24));
+        assert_eq!(wheel.tick(&mut fired), 2);
+        assert_eq!(fired, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn cancellation() {
+        let mut wheel = TimerWheel::new();
+        let mut fired = Vec::new();
+        let first = wheel.schedule_at_height(10, record(1));
+        let far = wheel.schedule_at_height(1 << 40, record(2));
+        wheel.schedule_at_height(10, record(3));
+        assert!(wheel.cancel(first));
+        assert!(!wheel.cancel(first));
+        assert!(wheel.cancel(far));
+        assert_eq!(wheel.len(), 1);
+
+        assert_eq!(wheel.on_new_height(::std::u64::MAX, &mut fired), 1);
+        assert_eq!(fired, vec![3]);
+        assert!(!wheel.cancel(far));
+    }
+
+    #[test]
+    fn panics_are_isolated() {
+        let mut wheel = TimerWheel::new();
+        let mut fired = Vec::new();
+        wheel.schedule_at_height(1, record(1));
+        wheel.schedule_at_height(1, |_: &mut Vec<u64>| panic!("callback"));
+        wheel.schedule_at_height(1, record(3));
+        assert_eq!(wheel.on_new_height(1, &mut fired), 3);
+        assert_eq!(fired, vec![1, 3]);
+    }
+
+    #[test]
+    fn mass_expiry_in_one_tick() {
+        let clock = MockClock::default();
+        let mut wheel = TimerWheel::with_clock(clock.clone());
+        let mut fired = Vec::new();
+        for i in 0..10_000 {
+            wheel.schedule_after(Duration::from_secs(300), record(i));
+        }
+        clock.advance(Duration::from_secs(299));
+        assert_eq!(wheel.tick(&mut fired), 0);
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(wheel.tick(&mut fired), 10_000);
+        assert_eq!(fired, (0..10_000).collect::<Vec<u64>>());
+        assert!(wheel.is_empty());
+    }
+
+    /// Random heights, notified in steps which sometimes go back, against
+    /// checking every entry.
+    #[test]
+    fn out_of_order_heights() {
+        let clock = MockClock::default();
+        let mut wheel = TimerWheel::with_clock(clock.clone());
+        let mut seed = 0x2545_f491_4f6c_dd1du64;
+        let mut random = move |bound: u64| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed % bound
+        };
+
+        let mut waiting = Vec::new();
+        let mut top = 0;
+        for round in 0..2_000 {
+            for _ in 0..5 {
+                let range = [64, 5_000, 1 << 20, 1 << 30][random(4) as usize];
+                let height = top + random(range);
+                let handle = wheel.schedule_at_height(height, record(height));
+                waiting.push((height, round, handle));
+            }
+            if random(10) == 0 {
+                let (_, _, handle) = waiting.swap_remove(random(waiting.len() as u64) as usize);
+                assert!(wheel.cancel(handle));
+            }
+
+            let height = if random(4) == 0 {
+                top.saturating_sub(random(100))
+            } else {
+                let step = 1 << (random(4) * 8);
+                top + random(step)
+            };
+            top = top.max(height);
+            let mut fired = Vec::new();
+            wheel.on_new_height(height, &mut fired);
+
+            let mut expected: Vec<u64> = waiting
+                .iter()
+                .filter(|&&(at, _, _)| at <= top)
+                .map(|&(at, _, _)| at)
+                .collect();
+            expected.sort();
+            waiting.retain(|&(at, _, _)| at > top);
+            assert_eq!(fired, expected, "round {}", round);
+            assert_eq!(wheel.len(), waiting.len());
+        }
+
+        // The clock has nothing to do with heights.
+        clock.advance(Duration::from_secs(1 << 20));
+        assert_eq!(wheel.tick(&mut Vec::new()), 0);
+        assert_eq!(clock.now(), Duration::from_secs(1 << 20));
+    }
+}